@@ -0,0 +1,126 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+// Counts heap allocations made while selecting and canonicalizing the signed
+// headers of the DKIM test corpus, and while verifying it end to end.
+// Run with `cargo bench --features bench,test --bench allocations`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use mail_auth::{
+    common::{parse::TxtRecordParser, verify::DomainKey},
+    AuthenticatedMessage, Resolver,
+};
+
+const CORPUS: [&str; 6] = [
+    include_str!("../resources/dkim/001.txt"),
+    include_str!("../resources/dkim/002.txt"),
+    include_str!("../resources/dkim/003.txt"),
+    include_str!("../resources/dkim/004.txt"),
+    include_str!("../resources/dkim/005.txt"),
+    include_str!("../resources/dkim/006.txt"),
+];
+
+const ITERATIONS: usize = 1000;
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn main() {
+    let resolver = Resolver::new_system_conf().unwrap();
+    let valid_until = Instant::now() + Duration::from_secs(86400 * 365);
+    let corpus = CORPUS
+        .iter()
+        .map(|test| {
+            let (dns_records, message) = test.split_once("\n\n").unwrap();
+            for (name, record) in dns_records.lines().filter_map(|r| r.split_once(' ')) {
+                resolver.txt_add(
+                    format!("{name}."),
+                    DomainKey::parse(record.as_bytes()).unwrap(),
+                    valid_until,
+                );
+            }
+            message.replace('\n', "\r\n")
+        })
+        .collect::<Vec<_>>();
+    let messages = corpus
+        .iter()
+        .map(|message| AuthenticatedMessage::parse(message.as_bytes()).unwrap())
+        .collect::<Vec<_>>();
+    let signatures = messages
+        .iter()
+        .map(|message| message.dkim_headers.len())
+        .sum::<usize>();
+
+    let mut buf = Vec::with_capacity(4096);
+    report("signed headers", signatures, || {
+        for message in &messages {
+            for header in &message.dkim_headers {
+                let signature = header.header.as_ref().unwrap();
+                buf.clear();
+                signature.ch.canonicalize_headers(
+                    message.signed_headers(&signature.h, header.name, header.value),
+                    &mut buf,
+                );
+                black_box(&buf);
+            }
+        }
+    });
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    report("verify_dkim", signatures, || {
+        for message in &messages {
+            black_box(rt.block_on(resolver.verify_dkim(message)));
+        }
+    });
+}
+
+fn report(name: &str, signatures: usize, mut f: impl FnMut()) {
+    f();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!(
+        "{name:<16} {:>8.2} allocs/signature {:>10.2?}/signature",
+        allocations as f64 / (ITERATIONS * signatures) as f64,
+        elapsed / (ITERATIONS * signatures) as u32
+    );
+}
@@ -0,0 +1,96 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+// Measures DKIM signing throughput of a single signer shared by a growing number
+// of threads. Run with `cargo bench --bench dkim_sign`.
+
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mail_auth::{
+    common::crypto::{RsaKey, Sha256},
+    dkim::DkimSigner,
+};
+
+const RSA_PRIVATE_KEY: &str = include_str!("../resources/rsa-private.pem");
+
+const TEST_MESSAGE: &str = concat!(
+    "From: bill@example.com\r\n",
+    "To: jdoe@example.com\r\n",
+    "Subject: TPS Report\r\n",
+    "\r\n",
+    "I'm going to need those TPS reports ASAP. ",
+    "So, if you could do that, that'd be great.\r\n"
+);
+
+fn dkim_sign(c: &mut Criterion) {
+    #[cfg(feature = "rust-crypto")]
+    let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+    #[cfg(all(
+        feature = "ring",
+        feature = "rustls-pemfile",
+        not(feature = "rust-crypto")
+    ))]
+    let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+
+    let signer = Arc::new(
+        DkimSigner::from_key(pk_rsa)
+            .domain("example.com")
+            .selector("default")
+            .headers(["From", "To", "Subject"]),
+    );
+
+    let max_threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let mut num_threads = 1;
+    let mut group = c.benchmark_group("dkim_sign");
+
+    loop {
+        // Each thread signs `iters` messages, so throughput scales with the thread count
+        group.throughput(Throughput::Elements(num_threads as u64));
+        group.bench_function(BenchmarkId::new("threads", num_threads), |b| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                let threads = (0..num_threads)
+                    .map(|_| {
+                        let signer = signer.clone();
+                        thread::spawn(move || {
+                            for _ in 0..iters {
+                                signer.sign(TEST_MESSAGE.as_bytes()).unwrap();
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                for thread in threads {
+                    thread.join().unwrap();
+                }
+                start.elapsed()
+            })
+        });
+
+        if num_threads >= max_threads {
+            break;
+        }
+        num_threads = std::cmp::min(num_threads * 2, max_threads);
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().measurement_time(Duration::from_secs(10));
+    targets = dkim_sign
+}
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+// Runs the built-in benchmark suite, optionally filtered by name.
+// Run with `cargo bench --features bench --bench suite [-- <filter>]`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use mail_auth::bench::{Benchmark, BenchmarkFixture};
+
+fn suite(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let fixture = BenchmarkFixture::new();
+    for benchmark in Benchmark::ALL {
+        c.bench_function(benchmark.name(), |b| {
+            b.iter(|| runtime.block_on(fixture.run(benchmark)))
+        });
+    }
+}
+
+criterion_group!(benches, suite);
+criterion_main!(benches);
@@ -25,14 +25,25 @@ use mail_auth::{
 static RFC822_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz:=- \r\n";
 static XML_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz</>";
 static TXT_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz1=;:";
+static BASE64_ALPHABET: &[u8] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/= \r\n\t;";
 
 fuzz_target!(|data: &[u8]| {
     let data_rfc822 = into_alphabet(data, RFC822_ALPHABET);
     let data_txt = into_alphabet(data, TXT_ALPHABET);
+    let data_base64 = into_alphabet(data, BASE64_ALPHABET);
 
     dkim::Signature::parse(data).ok();
     dkim::Signature::parse(&data_txt).ok();
 
+    for tag in [&b"b="[..], b"bh=", b"p="] {
+        let value = [tag, &data_base64].concat();
+        dkim::Signature::parse(&value).ok();
+        arc::Signature::parse(&value).ok();
+        arc::Seal::parse(&value).ok();
+        DomainKey::parse(&value).ok();
+    }
+
     arc::Signature::parse(data).ok();
     arc::Signature::parse(&data_txt).ok();
 
@@ -9,8 +9,11 @@
  */
 
 use crate::{
-    common::crypto::{Sha256, SigningKey},
-    dkim::{Canonicalization, Done, NeedDomain, NeedHeaders, NeedSelector},
+    common::{
+        crypto::{Sha256, SigningKey},
+        domain::to_ascii,
+    },
+    dkim::{builder::oversign, Canonicalization, Done, NeedDomain, NeedHeaders, NeedSelector},
 };
 
 use super::{ArcSealer, Seal, Signature};
@@ -28,26 +31,29 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T> {
                 ..Default::default()
             },
             key,
+            copy_headers: false,
         }
     }
 }
 
 impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedDomain> {
-    /// Sets the domain to use for signing.
+    /// Sets the domain to use for signing, a string or a validated [`crate::Domain`].
     pub fn domain(mut self, domain: impl Into<String> + Clone) -> ArcSealer<T, NeedSelector> {
-        self.signature.d = domain.clone().into();
-        self.seal.d = domain.into();
+        let domain = to_ascii(&domain.into()).into_owned();
+        self.signature.d = domain.clone();
+        self.seal.d = domain;
         ArcSealer {
             _state: Default::default(),
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            copy_headers: self.copy_headers,
         }
     }
 }
 
 impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedSelector> {
-    /// Sets the selector to use for signing.
+    /// Sets the selector to use for signing, a string or a validated [`crate::Selector`].
     pub fn selector(mut self, selector: impl Into<String> + Clone) -> ArcSealer<T, NeedHeaders> {
         self.signature.s = selector.clone().into();
         self.seal.s = selector.into();
@@ -56,6 +62,7 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedSelector> {
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            copy_headers: self.copy_headers,
         }
     }
 }
@@ -72,6 +79,7 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, NeedHeaders> {
             key: self.key,
             signature: self.signature,
             seal: self.seal,
+            copy_headers: self.copy_headers,
         }
     }
 }
@@ -100,4 +108,20 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
         self.signature.cb = cb;
         self
     }
+
+    /// Over-signs headers by listing them once more than they occur in the message,
+    /// preventing additional instances from being added after sealing.
+    pub fn oversign_headers(
+        mut self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        oversign(&mut self.signature.h, headers);
+        self
+    }
+
+    /// Includes a copy of the signed headers in the `z=` tag for diagnostic purposes.
+    pub fn copy_headers(mut self, copy_headers: bool) -> Self {
+        self.copy_headers = copy_headers;
+        self
+    }
 }
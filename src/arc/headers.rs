@@ -13,7 +13,7 @@ use crate::{
         crypto::Algorithm,
         headers::{HeaderWriter, Writer},
     },
-    dkim::Canonicalization,
+    dkim::{headers::write_copied_headers, Canonicalization},
     AuthenticationResults,
 };
 
@@ -80,6 +80,8 @@ impl Signature {
             }
         }
 
+        write_copied_headers(&self.z, writer, new_line, &mut bw);
+
         for (tag, value) in [(&b"; bh="[..], &self.bh), (&b"; b="[..], &self.b)] {
             writer.write_len(tag, &mut bw);
             for &byte in value {
@@ -163,7 +165,9 @@ impl<'x> AuthenticationResults<'x> {
         writer.write(i.to_string().as_bytes());
         writer.write(b"; ");
         writer.write(self.hostname.as_bytes());
-        if !as_header {
+        if self.auth_results.is_empty() {
+            writer.write(b"; none");
+        } else if !as_header {
             let mut last_is_space = false;
             for &ch in self.auth_results.as_bytes() {
                 if !ch.is_ascii_whitespace() {
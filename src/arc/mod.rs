@@ -11,6 +11,7 @@
 pub mod builder;
 pub mod headers;
 pub mod parse;
+pub mod score;
 pub mod seal;
 pub mod verify;
 
@@ -21,15 +22,18 @@ use crate::{
         verify::VerifySignature,
     },
     dkim::{Canonicalization, NeedDomain},
-    ArcOutput, AuthenticationResults, DkimResult,
+    ArcOutput, AuthenticationResults, DkimResult, Error,
 };
 
+/// ARC message sealer, which can be shared across threads as described in
+/// [Sharing signers and sealers](crate#sharing-signers-and-sealers).
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct ArcSealer<T: SigningKey<Hasher = Sha256>, State = NeedDomain> {
     _state: std::marker::PhantomData<State>,
     pub(crate) key: T,
     pub(crate) signature: Signature,
     pub(crate) seal: Seal,
+    pub(crate) copy_headers: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
@@ -79,6 +83,27 @@ pub struct Set<'x> {
     pub(crate) results: Header<'x, &'x Results>,
 }
 
+/// Requirement of RFC 8617 section 5.2 violated by an ARC chain, reported
+/// when strict validation is enabled.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ArcViolation {
+    /// The chain has more ARC sets than allowed.
+    ChainTooLong { sets: u32 },
+    /// An ARC header could not be parsed.
+    Malformed { name: String, error: Error },
+    /// The instance has no ARC-Authentication-Results header.
+    MissingResults { instance: u32 },
+    /// The instance has no ARC-Message-Signature header.
+    MissingSignature { instance: u32 },
+    /// The instance has no ARC-Seal header.
+    MissingSeal { instance: u32 },
+    /// The instance has more than one header of the same type.
+    DuplicateInstance { instance: u32 },
+    /// The seal's `cv=` tag is not `none` for the first instance, or not
+    /// `pass` for the others.
+    InvalidCv { instance: u32 },
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub(crate) enum ChainValidation {
     #[default]
@@ -129,16 +154,39 @@ impl<'x> ArcOutput<'x> {
         self
     }
 
+    /// Returns the RFC 8617 requirements violated by the chain, if strict
+    /// validation is enabled.
+    pub fn violations(&self) -> &[ArcViolation] {
+        &self.violations
+    }
+
     pub fn can_be_sealed(&self) -> bool {
         self.set.is_empty() || self.set.last().unwrap().seal.header.cv != ChainValidation::Fail
     }
 }
 
+impl ArcViolation {
+    /// Returns the error reported in the ARC result for this violation.
+    pub fn error(&self) -> Error {
+        match self {
+            ArcViolation::ChainTooLong { .. } => Error::ArcChainTooLong,
+            ArcViolation::Malformed { error, .. } => error.clone(),
+            ArcViolation::MissingResults { .. }
+            | ArcViolation::MissingSignature { .. }
+            | ArcViolation::MissingSeal { .. } => Error::ArcBrokenChain,
+            ArcViolation::DuplicateInstance { instance } => Error::ArcInvalidInstance(*instance),
+            ArcViolation::InvalidCv { .. } => Error::ArcInvalidCV,
+        }
+    }
+}
+
 impl<'x> Default for ArcOutput<'x> {
     fn default() -> Self {
         Self {
             result: DkimResult::None,
             set: Vec::new(),
+            violations: Vec::new(),
+            score: None,
         }
     }
 }
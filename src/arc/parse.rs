@@ -8,10 +8,12 @@
  * except according to those terms.
  */
 
-use mail_parser::decoders::base64::base64_decode_stream;
-
 use crate::{
-    common::{crypto::Algorithm, parse::TagParser},
+    common::{
+        base64::{MAX_BODY_HASH_LEN, MAX_SIGNATURE_LEN},
+        crypto::Algorithm,
+        parse::TagParser,
+    },
     dkim::{parse::SignatureParser, Canonicalization},
     Error,
 };
@@ -40,7 +42,6 @@ impl Signature {
             ch: Canonicalization::Simple,
             cb: Canonicalization::Simple,
         };
-        let header_len = header.len();
         let mut header = header.iter();
 
         while let Some(key) = header.key() {
@@ -54,14 +55,8 @@ impl Signature {
                 A => {
                     signature.a = header.algorithm()?;
                 }
-                B => {
-                    signature.b =
-                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
-                }
-                BH => {
-                    signature.bh =
-                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
-                }
+                B => signature.b = header.base64(MAX_SIGNATURE_LEN)?,
+                BH => signature.bh = header.base64(MAX_BODY_HASH_LEN)?,
                 C => {
                     let (ch, cb) = header.canonicalization(Canonicalization::Simple)?;
                     signature.ch = ch;
@@ -89,6 +84,14 @@ impl Signature {
             Err(Error::MissingParameters)
         }
     }
+
+    /// Parses an ARC-Message-Signature header, also returning any tag-list syntax warnings.
+    pub fn parse_with_strictness(
+        header: &'_ [u8],
+        strictness: TagStrictness,
+    ) -> crate::Result<(Self, Vec<TagWarning>)> {
+        parse_tag_list(header, strictness, Self::parse)
+    }
 }
 
 impl Seal {
@@ -103,7 +106,6 @@ impl Seal {
             i: 0,
             cv: ChainValidation::None,
         };
-        let header_len = header.len();
         let mut header = header.iter();
         let mut cv = None;
 
@@ -115,10 +117,7 @@ impl Seal {
                 A => {
                     seal.a = header.algorithm()?;
                 }
-                B => {
-                    seal.b =
-                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
-                }
+                B => seal.b = header.base64(MAX_SIGNATURE_LEN)?,
                 D => seal.d = header.text(true),
                 S => seal.s = header.text(true),
                 T => seal.t = header.number().unwrap_or(0),
@@ -155,6 +154,14 @@ impl Seal {
             Err(Error::MissingParameters)
         }
     }
+
+    /// Parses an ARC-Seal header, also returning any tag-list syntax warnings.
+    pub fn parse_with_strictness(
+        header: &'_ [u8],
+        strictness: TagStrictness,
+    ) -> crate::Result<(Self, Vec<TagWarning>)> {
+        parse_tag_list(header, strictness, Self::parse)
+    }
 }
 
 impl Results {
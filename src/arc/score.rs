@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::{common::crypto::Algorithm, ArcOutput, DkimResult, Domain, Resolver};
+
+/// Scores the trust placed in a verified ARC chain, from 0 to 100, so that
+/// receivers can make graded decisions instead of relying on a list of
+/// trusted sealers.
+pub trait ArcScorer: Send + Sync {
+    fn score(&self, arc: &ArcOutput<'_>) -> u8;
+}
+
+impl<F: Fn(&ArcOutput<'_>) -> u8 + Send + Sync> ArcScorer for F {
+    fn score(&self, arc: &ArcOutput<'_>) -> u8 {
+        self(arc)
+    }
+}
+
+/// Default `ArcScorer`, combining the reputation of each sealer, the strength
+/// of the algorithms used and the length of the chain.
+///
+/// Each hop scores the reputation of its sealer weighted by the strength of
+/// the seal algorithm. The chain scores as its weakest hop, minus a penalty
+/// for each hop after the first. Chains that did not pass score 0.
+#[derive(Clone)]
+pub struct ArcTrustScorer {
+    reputation: Option<Reputation>,
+    default_reputation: u8,
+    hop_penalty: u8,
+    strength: [(Algorithm, u8); 3],
+}
+
+type Reputation = Arc<dyn Fn(&str) -> u8 + Send + Sync>;
+
+impl ArcTrustScorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the callback returning the reputation, from 0 to 100, of a sealer
+    /// domain. The domain is lowercase and in A-label form.
+    pub fn with_reputation(
+        mut self,
+        reputation: impl Fn(&str) -> u8 + Send + Sync + 'static,
+    ) -> Self {
+        self.reputation = Some(Arc::new(reputation));
+        self
+    }
+
+    /// Sets the reputation of sealers when no callback is set, 50 by default.
+    pub fn with_default_reputation(mut self, reputation: u8) -> Self {
+        self.default_reputation = reputation.min(100);
+        self
+    }
+
+    /// Sets the penalty applied for each hop after the first, 5 by default.
+    pub fn with_hop_penalty(mut self, penalty: u8) -> Self {
+        self.hop_penalty = penalty;
+        self
+    }
+
+    /// Sets the strength of an algorithm, from 0 to 100. Ed25519-SHA256 and
+    /// RSA-SHA256 default to 100 and RSA-SHA1 to 50.
+    pub fn with_algorithm_strength(mut self, algorithm: Algorithm, strength: u8) -> Self {
+        for (a, s) in self.strength.iter_mut() {
+            if *a == algorithm {
+                *s = strength.min(100);
+            }
+        }
+        self
+    }
+
+    fn hop_score(&self, domain: &str, algorithm: Algorithm) -> u8 {
+        let reputation = match &self.reputation {
+            Some(reputation) => reputation(&Domain::normalize(domain)).min(100),
+            None => self.default_reputation,
+        };
+        let strength = self
+            .strength
+            .iter()
+            .find_map(|(a, s)| (*a == algorithm).then_some(*s))
+            .unwrap_or(0);
+        (u16::from(reputation) * u16::from(strength) / 100) as u8
+    }
+}
+
+impl ArcScorer for ArcTrustScorer {
+    fn score(&self, arc: &ArcOutput<'_>) -> u8 {
+        if arc.result != DkimResult::Pass {
+            return 0;
+        }
+        let weakest = arc
+            .set
+            .iter()
+            .map(|set| self.hop_score(&set.seal.header.d, set.seal.header.a))
+            .min()
+            .unwrap_or(0);
+        let penalty = arc.set.len().saturating_sub(1) * usize::from(self.hop_penalty);
+        weakest.saturating_sub(penalty.min(u8::MAX as usize) as u8)
+    }
+}
+
+impl Default for ArcTrustScorer {
+    fn default() -> Self {
+        ArcTrustScorer {
+            reputation: None,
+            default_reputation: 50,
+            hop_penalty: 5,
+            strength: [
+                (Algorithm::Ed25519Sha256, 100),
+                (Algorithm::RsaSha256, 100),
+                (Algorithm::RsaSha1, 50),
+            ],
+        }
+    }
+}
+
+impl std::fmt::Debug for ArcTrustScorer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArcTrustScorer")
+            .field("reputation", &self.reputation.is_some())
+            .field("default_reputation", &self.default_reputation)
+            .field("hop_penalty", &self.hop_penalty)
+            .field("strength", &self.strength)
+            .finish()
+    }
+}
+
+impl Resolver {
+    /// Scores every verified ARC chain, see `ArcOutput::trust_score`.
+    pub fn with_arc_scorer(mut self, scorer: impl ArcScorer + 'static) -> Self {
+        self.arc_scorer = Some(Arc::new(scorer));
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        arc::ArcSealer,
+        common::{
+            crypto::Algorithm,
+            headers::HeaderWriter,
+            test_key::{ed25519_domain_key, ed25519_key},
+        },
+        ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult, Resolver,
+    };
+
+    use super::ArcTrustScorer;
+
+    #[tokio::test]
+    async fn arc_trust_score() {
+        let key = || ed25519_key().unwrap();
+        let resolver = Resolver::new_system_conf().unwrap();
+        for domain in ["lists.example.org", "Forwarder.Example"] {
+            resolver.txt_add(
+                format!("ed._domainkey.{}.", domain.to_lowercase()),
+                ed25519_domain_key(),
+                Instant::now() + Duration::from_secs(3600),
+            );
+        }
+
+        // Seal the message twice
+        let mut raw_message = concat!(
+            "From: queso@manchego.org\r\n",
+            "To: affumicata@scamorza.org\r\n",
+            "Subject: Say cheese\r\n",
+            "\r\n",
+            "Scored.\r\n"
+        )
+        .to_string();
+        for domain in ["lists.example.org", "Forwarder.Example"] {
+            let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+            let arc = resolver.verify_arc(&message).await;
+            let auth_results = AuthenticationResults::new(domain);
+            raw_message = format!(
+                "{}{}{}",
+                ArcSealer::from_key(key())
+                    .domain(domain)
+                    .selector("ed")
+                    .headers(["From", "To", "Subject"])
+                    .seal(&message, &auth_results, &arc)
+                    .unwrap()
+                    .to_header(),
+                auth_results.to_header(),
+                raw_message
+            );
+        }
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc = resolver.verify_arc(&message).await;
+        assert_eq!(arc.result(), &DkimResult::Pass);
+        assert_eq!(arc.trust_score(), None);
+
+        // Weakest hop minus the penalty for the second hop
+        let scorer = ArcTrustScorer::new().with_reputation(|domain| match domain {
+            "forwarder.example" => 90,
+            "lists.example.org" => 60,
+            _ => 0,
+        });
+        let scored = resolver.clone().with_arc_scorer(scorer.clone());
+        assert_eq!(scored.verify_arc(&message).await.trust_score(), Some(55));
+        let scored = resolver.clone().with_arc_scorer(
+            scorer
+                .clone()
+                .with_hop_penalty(0)
+                .with_algorithm_strength(Algorithm::Ed25519Sha256, 50),
+        );
+        assert_eq!(scored.verify_arc(&message).await.trust_score(), Some(30));
+        let scored = resolver.clone().with_arc_scorer(ArcTrustScorer::new());
+        assert_eq!(scored.verify_arc(&message).await.trust_score(), Some(45));
+
+        // Failed chains score 0
+        let tampered = raw_message.replace("Scored.", "Tampered.");
+        let tampered = AuthenticatedMessage::parse(tampered.as_bytes()).unwrap();
+        let scored = resolver.clone().with_arc_scorer(scorer);
+        let arc = scored.verify_arc(&tampered).await;
+        assert_ne!(arc.result(), &DkimResult::Pass);
+        assert_eq!(arc.trust_score(), Some(0));
+
+        // Custom scorers
+        let scored = resolver
+            .clone()
+            .with_arc_scorer(|arc: &ArcOutput<'_>| arc.sets().len() as u8 * 10);
+        assert_eq!(scored.verify_arc(&message).await.trust_score(), Some(20));
+    }
+}
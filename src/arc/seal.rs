@@ -8,7 +8,7 @@
  * except according to those terms.
  */
 
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use mail_builder::encoders::base64::base64_encode;
 
@@ -17,13 +17,31 @@ use crate::{
         crypto::{HashAlgorithm, Sha256, SigningKey},
         headers::{Writable, Writer},
     },
-    dkim::{canonicalize::CanonicalHeaders, Canonicalization, Done},
-    ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult, Error,
+    dkim::{
+        canonicalize::{add_missing_headers, CanonicalHeaders},
+        Canonicalization, Done,
+    },
+    ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimResult, Error, Resolver,
 };
 
 use super::{ArcSealer, ArcSet, ChainValidation, Signature};
 
 impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
+    /// Polls the propagation resolvers until all of them return `public_key`
+    /// under the sealing selector, see [`Resolver::wait_for_dkim_key`]. When
+    /// rotating keys, switch to a new sealer only once this returns `true`,
+    /// otherwise downstream verifiers will find a broken chain.
+    pub async fn wait_until_published(
+        &self,
+        resolver: &Resolver,
+        public_key: &[u8],
+        timeout: Duration,
+    ) -> bool {
+        resolver
+            .wait_for_dkim_key(&self.seal.d, &self.seal.s, public_key, timeout)
+            .await
+    }
+
     pub fn seal<'x>(
         &self,
         message: &'x AuthenticatedMessage<'x>,
@@ -100,6 +118,9 @@ impl<T: SigningKey<Hasher = Sha256>> ArcSealer<T, Done> {
             0
         };
         set.signature.h = signed_headers;
+        if self.copy_headers {
+            set.signature.z = canonical_headers.copied_headers();
+        }
 
         // Sign
         let b = self.key.sign(SignableSet {
@@ -183,11 +204,7 @@ impl Signature {
 
         // Add any missing headers
         signed_headers.reverse();
-        for (header, found) in self.h.iter().zip(found_headers) {
-            if !found {
-                signed_headers.push(header.to_string());
-            }
-        }
+        add_missing_headers(&mut signed_headers, &self.h, found_headers);
 
         Ok((canonical_headers, signed_headers))
     }
@@ -196,19 +213,21 @@ impl Signature {
 #[cfg(test)]
 #[allow(unused)]
 mod test {
-    use std::time::{Duration, Instant};
-
-    use mail_parser::decoders::base64::base64_decode;
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     use crate::{
         arc::ArcSealer,
         common::{
-            crypto::{Ed25519Key, RsaKey, Sha256, SigningKey},
+            crypto::{usage::KeyUsageStore, RsaKey, Sha256, SigningKey},
             headers::HeaderWriter,
             parse::TxtRecordParser,
+            test_key::{ed25519_domain_key, ed25519_key, ed25519_public_key, ED25519_RECORD},
             verify::DomainKey,
         },
-        dkim::DkimSigner,
+        dkim::{propagation::test::serve, DkimSigner},
         AuthenticatedMessage, AuthenticationResults, DkimResult, Resolver,
     };
 
@@ -226,10 +245,6 @@ mod test {
         "WheQOTA19k5r2BmlRw/W9CrgCBo0Sdj+KQIDAQAB",
     );
 
-    const ED25519_PRIVATE_KEY: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
-    const ED25519_PUBLIC_KEY: &str =
-        "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
-
     #[cfg(any(
         feature = "rust-crypto",
         all(feature = "ring", feature = "rustls-pemfile")
@@ -256,16 +271,11 @@ mod test {
             );
             resolver.txt_add(
                 "ed._domainkey.scamorza.org.".to_string(),
-                DomainKey::parse(ED25519_PUBLIC_KEY.as_bytes()).unwrap(),
+                ed25519_domain_key(),
                 Instant::now() + Duration::new(3600, 0),
             );
         }
 
-        // Create private keys
-        let pk_ed_public =
-            base64_decode(ED25519_PUBLIC_KEY.rsplit_once("p=").unwrap().1.as_bytes()).unwrap();
-        let pk_ed_private = base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap();
-
         // Create DKIM-signed message
         #[cfg(feature = "rust-crypto")]
         let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
@@ -292,10 +302,7 @@ mod test {
                 &raw_message,
                 "scamorza.org",
                 "ed",
-                #[cfg(feature = "rust-crypto")]
-                Ed25519Key::from_bytes(&pk_ed_private).unwrap(),
-                #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
-                Ed25519Key::from_seed_and_public_key(&pk_ed_private, &pk_ed_public).unwrap(),
+                ed25519_key().unwrap(),
             )
             .await;
             raw_message =
@@ -305,6 +312,69 @@ mod test {
         //println!("{}", raw_message);
     }
 
+    #[tokio::test]
+    async fn arc_key_rotation() {
+        let message = concat!(
+            "From: queso@manchego.org\r\n",
+            "To: affumicata@scamorza.org\r\n",
+            "Subject: Say cheese\r\n",
+            "\r\n",
+            "Rotating keys.\r\n"
+        );
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        let public_key = ed25519_public_key();
+        let key = || ed25519_key().unwrap();
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_propagation_resolvers([
+                serve(vec![None, Some(ED25519_RECORD)], Arc::default()).await
+            ]);
+
+        let store = KeyUsageStore::new();
+        let _old = store.track_sealer("scamorza.org", "old", key());
+        let new = store
+            .track_sealer("scamorza.org", "new", key())
+            .headers(["From", "To", "Subject"]);
+
+        // The new selector is not published yet
+        assert!(
+            !new.wait_until_published(&resolver, &public_key, Duration::ZERO)
+                .await
+        );
+        resolver.txt_add(
+            "new._domainkey.scamorza.org.",
+            ed25519_domain_key(),
+            valid_until,
+        );
+        assert!(
+            new.wait_until_published(&resolver, &public_key, Duration::from_secs(10))
+                .await
+        );
+
+        // Seal with the new selector and retire the old one
+        let auth_message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let arc_result = resolver.verify_arc(&auth_message).await;
+        let auth_results = AuthenticationResults::new("scamorza.org");
+        let raw_message = format!(
+            "{}{}{}",
+            new.seal(&auth_message, &auth_results, &arc_result)
+                .unwrap()
+                .to_header(),
+            auth_results.to_header(),
+            message
+        );
+        let auth_message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        assert_eq!(
+            resolver.verify_arc(&auth_message).await.result(),
+            &DkimResult::Pass
+        );
+        assert_eq!(store.usage("scamorza.org", "new").unwrap().signatures(), 2);
+        assert_eq!(
+            store.unused_since(1),
+            vec![("scamorza.org".to_string(), "old".to_string())]
+        );
+    }
+
     async fn arc_verify_and_seal(
         resolver: &Resolver,
         raw_message: &str,
@@ -12,7 +12,8 @@ use std::time::SystemTime;
 
 use crate::{
     common::{
-        crypto::HashAlgorithm,
+        config::Config,
+        crypto::{Algorithm, HashAlgorithm},
         headers::Header,
         verify::{DomainKey, VerifySignature},
     },
@@ -20,15 +21,67 @@ use crate::{
     ArcOutput, AuthenticatedMessage, DkimResult, Error, Resolver,
 };
 
-use super::{ChainValidation, Set};
+use super::{ArcViolation, ChainValidation, Set};
 
 impl Resolver {
     /// Verifies ARC headers of an RFC5322 message.
+    #[inline(always)]
     pub async fn verify_arc<'x>(&self, message: &'x AuthenticatedMessage<'x>) -> ArcOutput<'x> {
+        self.verify_arc_at(
+            message,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+        .await
+    }
+
+    /// Verifies ARC headers of an RFC5322 message as of a UNIX timestamp.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(sets = message.ams_headers.len(), now)
+        )
+    )]
+    pub async fn verify_arc_at<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        now: u64,
+    ) -> ArcOutput<'x> {
+        let mut output = self.verify_arc_(message, now).await;
+        if let Some(scorer) = &self.arc_scorer {
+            output.score = Some(scorer.score(&output));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(result = %output.result, "arc chain verified");
+        output
+    }
+
+    async fn verify_arc_<'x>(
+        &self,
+        message: &'x AuthenticatedMessage<'x>,
+        now: u64,
+    ) -> ArcOutput<'x> {
+        let config = self.config();
+        if config.arc_strict() {
+            let violations = strict_violations(message, config.limits().arc_headers());
+            if let Some(violation) = violations.first() {
+                return ArcOutput {
+                    result: DkimResult::Fail(violation.error()),
+                    set: Vec::new(),
+                    violations,
+                    score: None,
+                };
+            }
+        }
+
         let arc_headers = message.ams_headers.len();
         if arc_headers == 0 {
             return ArcOutput::default();
-        } else if arc_headers > 50 {
+        } else if arc_headers > config.limits().arc_headers() {
             return ArcOutput::default().with_result(DkimResult::Fail(Error::ArcChainTooLong));
         } else if (arc_headers != message.as_headers.len())
             || (arc_headers != message.aar_headers.len())
@@ -36,14 +89,11 @@ impl Resolver {
             return ArcOutput::default().with_result(DkimResult::Fail(Error::ArcBrokenChain));
         }
 
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-
         let mut output = ArcOutput {
             result: DkimResult::None,
             set: Vec::with_capacity(message.aar_headers.len() / 3),
+            violations: Vec::new(),
+            score: None,
         };
 
         // Group ARC headers in sets
@@ -79,7 +129,13 @@ impl Resolver {
                     output.result = DkimResult::Fail(Error::ArcInvalidCV);
                 } else if pos == arc_headers - 1 {
                     // Validate last signature in the chain
-                    if signature.x == 0 || (signature.x > signature.t && signature.x > now) {
+                    if config
+                        .verification_policy(&signature.d)
+                        .body_length_decision(signature.l, message.body_len)
+                        .is_some()
+                    {
+                        output.result = DkimResult::Fail(Error::SignatureLength);
+                    } else if signature.x == 0 || (signature.x > signature.t && signature.x > now) {
                         // Validate body hash
                         let ha = HashAlgorithm::from(signature.a);
                         let bh = &message
@@ -120,7 +176,11 @@ impl Resolver {
         let mut headers = message.signed_headers(&signature.h, header.name, &dkim_hdr_value);
 
         // Obtain record
-        let record = match self.txt_lookup::<DomainKey>(signature.domain_key()).await {
+        let _permit = self.fairness_permit(&signature.d).await;
+        let record = match self
+            .txt_lookup_quorum::<DomainKey>(signature.domain_key())
+            .await
+        {
             Ok(record) => record,
             Err(err) => {
                 return output.with_result(err.into());
@@ -131,13 +191,16 @@ impl Resolver {
         if let Err(err) = record.verify(&mut headers, *signature, signature.ch) {
             return output.with_result(DkimResult::Fail(err));
         }
+        if let Some(err) = rsa_key_error(&config, &signature.d, signature.a, &signature.b) {
+            return output.with_result(DkimResult::PermError(err));
+        }
 
         // Validate ARC Seals
         for (pos, set) in output.set.iter().enumerate().rev() {
             // Obtain record
             let header = &set.seal;
             let seal = &header.header;
-            let record = match self.txt_lookup::<DomainKey>(seal.domain_key()).await {
+            let record = match self.txt_lookup_quorum::<DomainKey>(seal.domain_key()).await {
                 Ok(record) => record,
                 Err(err) => {
                     return output.with_result(err.into());
@@ -167,6 +230,9 @@ impl Resolver {
             if let Err(err) = record.verify(&mut headers, *seal, Canonicalization::Relaxed) {
                 return output.with_result(DkimResult::Fail(err));
             }
+            if let Some(err) = rsa_key_error(&config, &seal.d, seal.a, &seal.b) {
+                return output.with_result(DkimResult::PermError(err));
+            }
         }
 
         // ARC Validation successful
@@ -174,6 +240,89 @@ impl Resolver {
     }
 }
 
+/// Enforces the minimum RSA key size of the verification policy.
+fn rsa_key_error(config: &Config, domain: &str, algorithm: Algorithm, b: &[u8]) -> Option<Error> {
+    let policy = config.verification_policy(domain);
+    policy
+        .rsa_key_decision(algorithm, b)
+        .map(|_| policy.rsa_key_error())
+}
+
+/// Checks the structure of the ARC chain against every requirement of
+/// RFC 8617 section 5.2, reporting each violation found.
+fn strict_violations(message: &AuthenticatedMessage<'_>, max_sets: usize) -> Vec<ArcViolation> {
+    let mut violations = Vec::new();
+
+    fn collect<'x, T>(
+        headers: &'x [Header<'x, crate::Result<T>>],
+        instance: impl Fn(&T) -> u32,
+        violations: &mut Vec<ArcViolation>,
+    ) -> Vec<u32> {
+        headers
+            .iter()
+            .filter_map(|header| match &header.header {
+                Ok(value) => Some(instance(value)),
+                Err(error) => {
+                    violations.push(ArcViolation::Malformed {
+                        name: String::from_utf8_lossy(header.name).into_owned(),
+                        error: error.clone(),
+                    });
+                    None
+                }
+            })
+            .collect()
+    }
+    let instances = [
+        collect(&message.aar_headers, |r| r.i, &mut violations),
+        collect(&message.ams_headers, |s| s.i, &mut violations),
+        collect(&message.as_headers, |s| s.i, &mut violations),
+    ];
+
+    // Instances have to run from 1 to N, with exactly one header of each type
+    let num_sets = instances.iter().flatten().copied().max().unwrap_or(0);
+    if num_sets as usize > max_sets {
+        violations.push(ArcViolation::ChainTooLong { sets: num_sets });
+        return violations;
+    }
+    for instance in 1..=num_sets {
+        for (pos, found) in instances.iter().enumerate() {
+            match found.iter().filter(|i| **i == instance).count() {
+                0 => violations.push(match pos {
+                    0 => ArcViolation::MissingResults { instance },
+                    1 => ArcViolation::MissingSignature { instance },
+                    _ => ArcViolation::MissingSeal { instance },
+                }),
+                1 => (),
+                _ => {
+                    if !violations.contains(&ArcViolation::DuplicateInstance { instance }) {
+                        violations.push(ArcViolation::DuplicateInstance { instance });
+                    }
+                }
+            }
+        }
+    }
+
+    // The first seal has cv=none and the following ones cv=pass
+    let mut seals = message
+        .as_headers
+        .iter()
+        .filter_map(|header| header.header.as_ref().ok())
+        .collect::<Vec<_>>();
+    seals.sort_unstable_by_key(|seal| seal.i);
+    for seal in seals {
+        let expected = if seal.i == 1 {
+            ChainValidation::None
+        } else {
+            ChainValidation::Pass
+        };
+        if seal.cv != expected {
+            violations.push(ArcViolation::InvalidCv { instance: seal.i });
+        }
+    }
+
+    violations
+}
+
 #[cfg(test)]
 #[allow(unused)]
 mod test {
@@ -184,8 +333,9 @@ mod test {
     };
 
     use crate::{
-        common::{parse::TxtRecordParser, verify::DomainKey},
-        AuthenticatedMessage, DkimResult, Resolver,
+        arc::ArcViolation,
+        common::{config::Config, parse::TxtRecordParser, verify::DomainKey},
+        AuthenticatedMessage, DkimResult, Error, Resolver,
     };
 
     #[tokio::test]
@@ -215,6 +365,40 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn arc_verify_strict() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("arc");
+        test_file.push("001.txt");
+
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = new_resolver(dns_records);
+        resolver.set_config(Config::new().with_arc_strict(true));
+        let raw_message = raw_message.replace('\n', "\r\n");
+
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc = resolver.verify_arc(&message).await;
+        assert_eq!(arc.result(), &DkimResult::Pass);
+        assert!(arc.violations().is_empty());
+
+        // Drop the AAR and claim a passing chain on the first seal
+        let raw_message = raw_message
+            .replace("ARC-Authentication-Results: i=1", "X-Removed: i=1")
+            .replace("cv=none", "cv=pass");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let arc = resolver.verify_arc(&message).await;
+        assert_eq!(arc.result(), &DkimResult::Fail(Error::ArcBrokenChain));
+        assert_eq!(
+            arc.violations(),
+            &[
+                ArcViolation::MissingResults { instance: 1 },
+                ArcViolation::InvalidCv { instance: 1 }
+            ]
+        );
+    }
+
     fn new_resolver(dns_records: &str) -> Resolver {
         let resolver = Resolver::new_system_conf().unwrap();
         for (key, value) in dns_records
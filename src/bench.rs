@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Benchmarks of message parsing, DKIM canonicalization, signing and verification
+//! and SPF evaluation against bundled test corpora. Timing is left to the caller,
+//! `benches/suite.rs` drives them with criterion.
+
+use std::{
+    hint::black_box,
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    common::{
+        crypto::{Ed25519Key, RsaKey, Sha256, SigningKey},
+        headers::{HeaderWriter, Writable},
+        lru::DnsCache,
+        parse::TxtRecordParser,
+        test_key::{ed25519_key, ED25519_PUBLIC_KEY},
+        verify::DomainKey,
+    },
+    dkim::{Canonicalization, DkimSigner, Done},
+    spf::Spf,
+    AuthenticatedMessage, DkimResult, Resolver, SpfResult,
+};
+
+const CORPUS: [&str; 6] = [
+    include_str!("../resources/dkim/001.txt"),
+    include_str!("../resources/dkim/002.txt"),
+    include_str!("../resources/dkim/003.txt"),
+    include_str!("../resources/dkim/004.txt"),
+    include_str!("../resources/dkim/005.txt"),
+    include_str!("../resources/dkim/006.txt"),
+];
+
+const RSA_PRIVATE_KEY: &str = include_str!("../resources/rsa-private.pem");
+const RSA_PUBLIC_KEY: &str = concat!(
+    "v=DKIM1; t=s; p=MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ",
+    "8AMIIBCgKCAQEAv9XYXG3uK95115mB4nJ37nGeNe2CrARm",
+    "1agrbcnSk5oIaEfMZLUR/X8gPzoiNHZcfMZEVR6bAytxUh",
+    "c5EvZIZrjSuEEeny+fFd/cTvcm3cOUUbIaUmSACj0dL2/K",
+    "wW0LyUaza9z9zor7I5XdIl1M53qVd5GI62XBB76FH+Q0bW",
+    "PZNkT4NclzTLspD/MTpNCCPhySM4Kdg5CuDczTH4aNzyS0",
+    "TqgXdtw6A4Sdsp97VXT9fkPW9rso3lrkpsl/9EQ1mR/DWK",
+    "6PBmRfIuSFuqnLKY6v/z2hXHxF7IoojfZLa2kZr9Aed4l9",
+    "WheQOTA19k5r2BmlRw/W9CrgCBo0Sdj+KQIDAQAB",
+);
+
+const SPF_RECORDS: [(&str, &str); 4] = [
+    (
+        "example.org.",
+        "v=spf1 ip4:198.51.100.0/24 include:_spf.example.org include:_spf.example.net -all",
+    ),
+    (
+        "_spf.example.org.",
+        "v=spf1 ip4:203.0.113.0/24 ip6:2001:db8::/32 ~all",
+    ),
+    ("_spf.example.net.", "v=spf1 include:_ip4.example.net ?all"),
+    ("_ip4.example.net.", "v=spf1 ip4:192.0.2.0/24 -all"),
+];
+
+/// Operation measured by a benchmark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Benchmark {
+    Parse,
+    Canonicalize,
+    SignRsa,
+    SignEd25519,
+    VerifyRsa,
+    VerifyEd25519,
+    Spf,
+}
+
+type Headers = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Inputs shared by the benchmarks, prepared once so that each run performs
+/// a single iteration of the measured operation.
+pub struct BenchmarkFixture {
+    corpus: Vec<String>,
+    canonicalize: Vec<(Headers, Vec<u8>)>,
+    rsa_signer: DkimSigner<RsaKey<Sha256>, Done>,
+    ed25519_signer: DkimSigner<Ed25519Key, Done>,
+    rsa_signed: String,
+    ed25519_signed: String,
+    resolver: Resolver,
+}
+
+impl Benchmark {
+    pub const ALL: [Benchmark; 7] = [
+        Benchmark::Parse,
+        Benchmark::Canonicalize,
+        Benchmark::SignRsa,
+        Benchmark::SignEd25519,
+        Benchmark::VerifyRsa,
+        Benchmark::VerifyEd25519,
+        Benchmark::Spf,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Benchmark::Parse => "parse",
+            Benchmark::Canonicalize => "canonicalize",
+            Benchmark::SignRsa => "sign/rsa",
+            Benchmark::SignEd25519 => "sign/ed25519",
+            Benchmark::VerifyRsa => "verify/rsa",
+            Benchmark::VerifyEd25519 => "verify/ed25519",
+            Benchmark::Spf => "spf",
+        }
+    }
+}
+
+impl Default for BenchmarkFixture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BenchmarkFixture {
+    pub fn new() -> Self {
+        let corpus = CORPUS
+            .iter()
+            .map(|corpus| {
+                corpus
+                    .split_once("\n\n")
+                    .map_or(*corpus, |(_, message)| message)
+                    .replace('\n', "\r\n")
+            })
+            .collect::<Vec<_>>();
+        let canonicalize = corpus
+            .iter()
+            .filter_map(|message| AuthenticatedMessage::parse(message.as_bytes()))
+            .map(|message| {
+                (
+                    message
+                        .headers
+                        .iter()
+                        .map(|(name, value)| (name.to_vec(), value.to_vec()))
+                        .collect(),
+                    message.raw_body().to_vec(),
+                )
+            })
+            .collect();
+        let rsa_signer = signer(rsa_key(), "rsa");
+        let ed25519_signer = signer(ed25519_key().unwrap(), "ed");
+        let rsa_signed = rsa_signer.sign(message().as_bytes()).unwrap().to_header() + message();
+        let ed25519_signed = ed25519_signer
+            .sign(message().as_bytes())
+            .unwrap()
+            .to_header()
+            + message();
+
+        BenchmarkFixture {
+            corpus,
+            canonicalize,
+            rsa_signer,
+            ed25519_signer,
+            rsa_signed,
+            ed25519_signed,
+            resolver: resolver(),
+        }
+    }
+
+    /// Runs one iteration of a benchmark. DNS answers are served from the
+    /// resolver cache, so the future completes without performing any I/O.
+    pub async fn run(&self, benchmark: Benchmark) {
+        match benchmark {
+            Benchmark::Parse => {
+                for message in &self.corpus {
+                    black_box(AuthenticatedMessage::parse(message.as_bytes()));
+                }
+            }
+            Benchmark::Canonicalize => {
+                let mut buf = Vec::with_capacity(1024);
+                for (headers, body) in &self.canonicalize {
+                    for canonicalization in [Canonicalization::Relaxed, Canonicalization::Simple] {
+                        buf.clear();
+                        canonicalization.canonicalize_headers(
+                            headers
+                                .iter()
+                                .map(|(name, value)| (name.as_slice(), value.as_slice())),
+                            &mut buf,
+                        );
+                        canonicalization.canonical_body(body, 0).write(&mut buf);
+                        black_box(&buf);
+                    }
+                }
+            }
+            Benchmark::SignRsa => {
+                black_box(self.rsa_signer.sign(message().as_bytes()).unwrap());
+            }
+            Benchmark::SignEd25519 => {
+                black_box(self.ed25519_signer.sign(message().as_bytes()).unwrap());
+            }
+            Benchmark::VerifyRsa | Benchmark::VerifyEd25519 => {
+                let signed = if benchmark == Benchmark::VerifyRsa {
+                    &self.rsa_signed
+                } else {
+                    &self.ed25519_signed
+                };
+                let message = AuthenticatedMessage::parse(signed.as_bytes()).unwrap();
+                let output = self.resolver.verify_dkim(&message).await;
+                assert_eq!(output[0].result(), &DkimResult::Pass);
+            }
+            Benchmark::Spf => {
+                let output = self
+                    .resolver
+                    .verify_spf_sender(
+                        IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                        "mx.example.org",
+                        "mx.example.com",
+                        "sender@example.org",
+                    )
+                    .await;
+                assert_eq!(output.result(), SpfResult::Pass);
+            }
+        }
+    }
+}
+
+fn message() -> &'static str {
+    concat!(
+        "From: bill@example.com\r\n",
+        "To: jdoe@example.com\r\n",
+        "Subject: TPS Report\r\n",
+        "\r\n",
+        "I'm going to need those TPS reports ASAP. ",
+        "So, if you could do that, that'd be great.\r\n"
+    )
+}
+
+fn signer<T: SigningKey>(key: T, selector: &str) -> DkimSigner<T, Done> {
+    DkimSigner::from_key(key)
+        .domain("example.com")
+        .selector(selector)
+        .headers(["From", "To", "Subject"])
+}
+
+fn rsa_key() -> RsaKey<Sha256> {
+    #[cfg(feature = "rust-crypto")]
+    let key = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY);
+    #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+    let key = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY);
+    key.unwrap()
+}
+
+fn resolver() -> Resolver {
+    let resolver = Resolver::new_offline();
+    let valid_until = Instant::now() + Duration::from_secs(86400 * 365);
+    resolver.cache_txt.insert(
+        "rsa._domainkey.example.com.".to_string(),
+        DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap().into(),
+        valid_until,
+    );
+    resolver.cache_txt.insert(
+        "ed._domainkey.example.com.".to_string(),
+        DomainKey::parse(format!("v=DKIM1; k=ed25519; p={ED25519_PUBLIC_KEY}").as_bytes())
+            .unwrap()
+            .into(),
+        valid_until,
+    );
+    for (name, record) in SPF_RECORDS {
+        resolver.cache_txt.insert(
+            name.to_string(),
+            Spf::parse(record.as_bytes()).unwrap().into(),
+            valid_until,
+        );
+    }
+    resolver
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Benchmark, BenchmarkFixture};
+
+    #[tokio::test]
+    async fn run_benchmarks() {
+        let fixture = BenchmarkFixture::new();
+        for benchmark in Benchmark::ALL {
+            fixture.run(benchmark).await;
+        }
+    }
+}
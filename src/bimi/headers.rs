@@ -0,0 +1,85 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use mail_builder::encoders::base64::base64_encode;
+
+use crate::{
+    common::headers::{HeaderWriter, Writer},
+    BimiOutput, BimiResult,
+};
+
+use super::BimiIndicator;
+
+impl HeaderWriter for BimiOutput {
+    fn write_header(&self, writer: &mut impl Writer) {
+        if let (BimiResult::Pass, Some(record)) = (&self.result, &self.record) {
+            writer.write(b"BIMI-Location: v=BIMI1");
+            if let Some(l) = &record.l {
+                writer.write(b";\r\n\tl=");
+                writer.write(l.as_bytes());
+            }
+            if let Some(a) = &record.a {
+                writer.write(b";\r\n\ta=");
+                writer.write(a.as_bytes());
+            }
+            writer.write(b"\r\n");
+        }
+    }
+}
+
+impl<'x> HeaderWriter for BimiIndicator<'x> {
+    fn write_header(&self, writer: &mut impl Writer) {
+        writer.write(b"BIMI-Indicator: ");
+        let encoded = base64_encode(self.svg).unwrap_or_default();
+        for (pos, chunk) in encoded.chunks(76).enumerate() {
+            if pos > 0 {
+                writer.write(b"\r\n\t");
+            }
+            writer.write(chunk);
+        }
+        writer.write(b"\r\n");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        bimi::{Bimi, BimiIndicator},
+        common::headers::HeaderWriter,
+        BimiOutput, BimiResult, Version,
+    };
+
+    #[test]
+    fn bimi_headers() {
+        let output = BimiOutput::new("example.org", "default")
+            .with_result(BimiResult::Pass)
+            .with_record(
+                Bimi {
+                    v: Version::V1,
+                    l: Some("https://example.org/logo.svg".to_string()),
+                    a: Some("https://example.org/vmc.pem".to_string()),
+                }
+                .into(),
+            );
+        assert_eq!(
+            output.to_header(),
+            concat!(
+                "BIMI-Location: v=BIMI1;\r\n\tl=https://example.org/logo.svg;",
+                "\r\n\ta=https://example.org/vmc.pem\r\n"
+            )
+        );
+        assert_eq!(output.with_result(BimiResult::Skipped).to_header(), "");
+
+        let svg = vec![b'a'; 100];
+        let header = BimiIndicator::new(&svg).to_header();
+        assert!(header.starts_with("BIMI-Indicator: YWFh"));
+        assert_eq!(header.split("\r\n\t").count(), 2);
+    }
+}
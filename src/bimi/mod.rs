@@ -0,0 +1,104 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::{BimiOutput, BimiResult, Error, Version};
+
+pub mod headers;
+pub mod parse;
+pub mod verify;
+
+#[derive(Debug, Hash, Clone, PartialEq, Eq)]
+pub struct Bimi {
+    pub v: Version,
+    pub l: Option<String>,
+    pub a: Option<String>,
+}
+
+/// BIMI-Indicator header containing the validated SVG logo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BimiIndicator<'x> {
+    pub svg: &'x [u8],
+}
+
+impl Bimi {
+    /// Returns the logo location URI.
+    pub fn location(&self) -> Option<&str> {
+        self.l.as_deref()
+    }
+
+    /// Returns the authority evidence (VMC) URI.
+    pub fn authority(&self) -> Option<&str> {
+        self.a.as_deref()
+    }
+
+    /// Returns `true` if the domain declined to participate in BIMI.
+    pub fn is_declination(&self) -> bool {
+        self.l.is_none() && self.a.is_none()
+    }
+}
+
+impl<'x> BimiIndicator<'x> {
+    pub fn new(svg: &'x [u8]) -> Self {
+        BimiIndicator { svg }
+    }
+}
+
+impl From<Error> for BimiResult {
+    fn from(err: Error) -> Self {
+        if matches!(&err, Error::DnsError(_) | Error::Io(_)) {
+            BimiResult::TempError(err)
+        } else {
+            BimiResult::Fail(err)
+        }
+    }
+}
+
+impl BimiOutput {
+    pub(crate) fn new(domain: &str, selector: &str) -> Self {
+        BimiOutput {
+            result: BimiResult::None,
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            record: None,
+        }
+    }
+
+    pub(crate) fn with_result(mut self, result: BimiResult) -> Self {
+        self.result = result;
+        self
+    }
+
+    pub(crate) fn with_record(mut self, record: Arc<Bimi>) -> Self {
+        self.record = record.into();
+        self
+    }
+
+    pub fn result(&self) -> &BimiResult {
+        &self.result
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    pub fn bimi_record(&self) -> Option<&Bimi> {
+        self.record.as_deref()
+    }
+
+    pub fn bimi_record_cloned(&self) -> Option<Arc<Bimi>> {
+        self.record.clone()
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::slice::Iter;
+
+use crate::{
+    common::parse::{TagParser, TxtRecordParser, A, L, S, V},
+    Error, Version,
+};
+
+use super::Bimi;
+
+impl TxtRecordParser for Bimi {
+    #[allow(clippy::while_let_on_iterator)]
+    fn parse(bytes: &[u8]) -> crate::Result<Self> {
+        let mut record = bytes.iter();
+        if !record.bimi_version() {
+            return Err(Error::InvalidRecordType);
+        }
+
+        let mut bimi = Bimi {
+            v: Version::V1,
+            l: None,
+            a: None,
+        };
+
+        while let Some(key) = record.key() {
+            match key {
+                L => {
+                    bimi.l = record.uri()?;
+                }
+                A => {
+                    bimi.a = record.uri()?;
+                }
+                _ => {
+                    record.ignore();
+                }
+            }
+        }
+
+        Ok(bimi)
+    }
+}
+
+impl Bimi {
+    /// Parses the selector from a BIMI-Selector header value.
+    pub fn parse_selector(header: &[u8]) -> Option<String> {
+        let mut header = header.iter();
+        if !header.bimi_version() {
+            return None;
+        }
+
+        while let Some(key) = header.key() {
+            if key == S {
+                let selector = header.text(true);
+                return if !selector.is_empty() {
+                    Some(selector)
+                } else {
+                    None
+                };
+            } else {
+                header.ignore();
+            }
+        }
+
+        None
+    }
+}
+
+trait BimiParser {
+    fn bimi_version(&mut self) -> bool;
+    fn uri(&mut self) -> crate::Result<Option<String>>;
+}
+
+impl BimiParser for Iter<'_, u8> {
+    fn bimi_version(&mut self) -> bool {
+        self.key().unwrap_or(0) == V && self.match_bytes(b"BIMI1") && self.seek_tag_end()
+    }
+
+    fn uri(&mut self) -> crate::Result<Option<String>> {
+        let uri = self.text(false);
+        if uri.is_empty() {
+            Ok(None)
+        } else if uri
+            .get(..8)
+            .is_some_and(|p| p.eq_ignore_ascii_case("https://"))
+        {
+            Ok(Some(uri))
+        } else {
+            Err(Error::ParseError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{bimi::Bimi, common::parse::TxtRecordParser, Error, Version};
+
+    #[test]
+    fn parse_bimi() {
+        for (record, expected_result) in [
+            (
+                "v=BIMI1; l=https://example.com/logo.svg; a=https://example.com/vmc.pem",
+                Ok(Bimi {
+                    v: Version::V1,
+                    l: Some("https://example.com/logo.svg".to_string()),
+                    a: Some("https://example.com/vmc.pem".to_string()),
+                }),
+            ),
+            (
+                "v=BIMI1;l=https://example.com/logo.svg",
+                Ok(Bimi {
+                    v: Version::V1,
+                    l: Some("https://example.com/logo.svg".to_string()),
+                    a: None,
+                }),
+            ),
+            (
+                "v=BIMI1; l=; a=;",
+                Ok(Bimi {
+                    v: Version::V1,
+                    l: None,
+                    a: None,
+                }),
+            ),
+            (
+                "v=BIMI1; l=http://example.com/logo.svg",
+                Err(Error::ParseError),
+            ),
+            ("v=DMARC1; p=reject", Err(Error::InvalidRecordType)),
+        ] {
+            assert_eq!(Bimi::parse(record.as_bytes()), expected_result, "{record}");
+        }
+
+        assert_eq!(
+            Bimi::parse_selector(b" v=BIMI1; s=Brand2024;"),
+            Some("brand2024".to_string())
+        );
+        assert_eq!(Bimi::parse_selector(b"v=BIMI2; s=brand"), None);
+    }
+}
@@ -0,0 +1,282 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{future::Future, sync::Arc};
+
+use crate::{
+    dmarc::Policy, AuthenticatedMessage, BimiOutput, BimiResult, DmarcOutput, DmarcResult, Error,
+    Resolver,
+};
+
+use super::Bimi;
+
+impl Resolver {
+    /// Verifies the BIMI record of an RFC5322.From domain
+    pub async fn verify_bimi(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        dmarc_output: &DmarcOutput,
+        selector: Option<&str>,
+    ) -> BimiOutput {
+        self.verify_bimi_with_evidence(message, dmarc_output, selector, |_| async { Ok(()) })
+            .await
+    }
+
+    /// Verifies the BIMI record of an RFC5322.From domain, validating the authority
+    /// evidence (VMC) of passing records with the provided function.
+    pub async fn verify_bimi_with_evidence<F, Fut>(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        dmarc_output: &DmarcOutput,
+        selector: Option<&str>,
+        validate_evidence: F,
+    ) -> BimiOutput
+    where
+        F: FnOnce(Arc<Bimi>) -> Fut,
+        Fut: Future<Output = crate::Result<()>>,
+    {
+        // Use the requested selector, otherwise the one from the BIMI-Selector header
+        let selector = selector.map(|s| s.to_lowercase()).unwrap_or_else(|| {
+            message
+                .headers
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case(b"BIMI-Selector"))
+                .and_then(|(_, value)| Bimi::parse_selector(value))
+                .unwrap_or_else(|| "default".to_string())
+        });
+        let domain = dmarc_output.domain();
+        let output = BimiOutput::new(domain, &selector);
+        if domain.is_empty() {
+            return output;
+        }
+
+        // The message must pass DMARC under a policy at enforcement
+        if !dmarc_output.is_enforced() {
+            return output.with_result(BimiResult::Skipped);
+        }
+
+        // Obtain BIMI record, falling back to the DMARC organizational domain
+        let mut bimi = self
+            .txt_lookup::<Bimi>(format!("{selector}._bimi.{domain}."))
+            .await;
+        if matches!(
+            bimi,
+            Err(Error::DnsRecordNotFound(_)) | Err(Error::InvalidRecordType)
+        ) {
+            match self.dmarc_organizational_domain(domain).await {
+                Ok(org_domain) if !org_domain.eq_ignore_ascii_case(domain) => {
+                    bimi = self
+                        .txt_lookup::<Bimi>(format!("{selector}._bimi.{org_domain}."))
+                        .await;
+                }
+                _ => (),
+            }
+        }
+
+        match bimi {
+            Ok(bimi) if bimi.is_declination() => {
+                output.with_result(BimiResult::Declined).with_record(bimi)
+            }
+            Ok(bimi) => {
+                let result = if bimi.a.is_some() {
+                    match validate_evidence(bimi.clone()).await {
+                        Ok(_) => BimiResult::Pass,
+                        Err(err) => err.into(),
+                    }
+                } else {
+                    BimiResult::Pass
+                };
+                output.with_result(result).with_record(bimi)
+            }
+            Err(Error::DnsRecordNotFound(_)) | Err(Error::InvalidRecordType) => output,
+            Err(err) => output.with_result(err.into()),
+        }
+    }
+}
+
+impl DmarcOutput {
+    /// Returns `true` if the message passed DMARC under a policy at enforcement.
+    pub(crate) fn is_enforced(&self) -> bool {
+        (self.spf_result == DmarcResult::Pass || self.dkim_result == DmarcResult::Pass)
+            && match &self.record {
+                Some(record) => {
+                    matches!(record.p, Policy::Quarantine | Policy::Reject)
+                        && record.sp != Policy::None
+                        && record.pct == 100
+                        && matches!(self.policy, Policy::Quarantine | Policy::Reject)
+                }
+                None => false,
+            }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        bimi::Bimi,
+        common::parse::TxtRecordParser,
+        dmarc::{Dmarc, Policy},
+        AuthenticatedMessage, BimiResult, DmarcOutput, DmarcResult, Error, Resolver,
+    };
+
+    #[tokio::test]
+    async fn bimi_verify() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        for (name, record) in [
+            (
+                "default._bimi.example.org.",
+                "v=BIMI1; l=https://example.org/logo.svg; a=https://example.org/vmc.pem",
+            ),
+            ("brand._bimi.example.org.", "v=BIMI1; l=; a=;"),
+            ("default._bimi.bad.example.net.", "v=BIMI1; l=ftp://logo"),
+            ("default._bimi.co.uk.", "v=BIMI1; l=https://co.uk/logo.svg"),
+            (
+                "default._bimi.example.net.",
+                "v=BIMI1; l=https://example.net/logo.svg",
+            ),
+        ] {
+            resolver.txt_add(name, Bimi::parse(record.as_bytes()), valid_until);
+        }
+        for (name, record) in [
+            ("_dmarc.example.org.", "v=DMARC1; p=reject"),
+            ("_dmarc.example.co.uk.", "v=DMARC1; p=reject"),
+            ("_dmarc.mail.example.net.", "v=DMARC1; p=reject; psd=n"),
+        ] {
+            resolver.txt_add(name, Dmarc::parse(record.as_bytes()), valid_until);
+        }
+        let message = AuthenticatedMessage::parse(
+            b"From: hello@example.org\r\nBIMI-Selector: v=BIMI1; s=brand\r\n\r\nHi!\r\n",
+        )
+        .unwrap();
+
+        for (domain, record, selector, evidence, expected_result) in [
+            // Enforced policy, record found
+            (
+                "example.org",
+                "v=DMARC1; p=reject",
+                Some("default"),
+                Ok(()),
+                BimiResult::Pass,
+            ),
+            // Subdomain falls back to organizational domain
+            (
+                "news.example.org",
+                "v=DMARC1; p=quarantine",
+                Some("default"),
+                Ok(()),
+                BimiResult::Pass,
+            ),
+            // The organizational domain is not the last two labels
+            (
+                "news.example.co.uk",
+                "v=DMARC1; p=reject",
+                Some("default"),
+                Ok(()),
+                BimiResult::None,
+            ),
+            // No fallback when the domain is its own organizational domain
+            (
+                "mail.example.net",
+                "v=DMARC1; p=reject; psd=n",
+                Some("default"),
+                Ok(()),
+                BimiResult::None,
+            ),
+            // Invalid evidence
+            (
+                "example.org",
+                "v=DMARC1; p=reject",
+                Some("default"),
+                Err(Error::FailedVerification),
+                BimiResult::Fail(Error::FailedVerification),
+            ),
+            // Selector from BIMI-Selector header, declination record
+            (
+                "example.org",
+                "v=DMARC1; p=reject",
+                None,
+                Ok(()),
+                BimiResult::Declined,
+            ),
+            // Policy not at enforcement
+            (
+                "example.org",
+                "v=DMARC1; p=none",
+                Some("default"),
+                Ok(()),
+                BimiResult::Skipped,
+            ),
+            (
+                "example.org",
+                "v=DMARC1; p=quarantine; pct=50",
+                Some("default"),
+                Ok(()),
+                BimiResult::Skipped,
+            ),
+            (
+                "example.org",
+                "v=DMARC1; p=reject; pct=50",
+                Some("default"),
+                Ok(()),
+                BimiResult::Skipped,
+            ),
+            // Invalid record
+            (
+                "bad.example.net",
+                "v=DMARC1; p=reject",
+                Some("default"),
+                Ok(()),
+                BimiResult::Fail(Error::ParseError),
+            ),
+            // No record
+            (
+                "example.com",
+                "v=DMARC1; p=reject",
+                Some("default"),
+                Ok(()),
+                BimiResult::None,
+            ),
+        ] {
+            let dmarc = Dmarc::parse(record.as_bytes()).unwrap();
+            let dmarc_output = DmarcOutput {
+                spf_result: DmarcResult::None,
+                dkim_result: DmarcResult::Pass,
+                domain: domain.to_string(),
+                policy: dmarc.p,
+                record: Some(dmarc.into()),
+                arc_override: None,
+                local_override: None,
+            };
+
+            let output = resolver
+                .verify_bimi_with_evidence(&message, &dmarc_output, selector, move |_| async move {
+                    evidence
+                })
+                .await;
+            assert_eq!(output.result(), &expected_result, "{domain} {record}");
+        }
+
+        // DMARC failure
+        let output = resolver
+            .verify_bimi(
+                &message,
+                &DmarcOutput {
+                    policy: Policy::Reject,
+                    ..DmarcOutput::default().with_domain("example.org")
+                },
+                Some("default"),
+            )
+            .await;
+        assert_eq!(output.result(), &BimiResult::Skipped);
+    }
+}
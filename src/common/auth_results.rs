@@ -12,16 +12,149 @@ use std::{
     borrow::Cow,
     fmt::{Display, Write},
     net::IpAddr,
+    str::FromStr,
 };
 
 use mail_builder::encoders::base64::base64_encode;
 
 use crate::{
-    ArcOutput, AuthenticationResults, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
-    IprevOutput, IprevResult, ReceivedSpf, SpfOutput, SpfResult,
+    ArcOutput, AuthenticationResults, BimiOutput, BimiResult, DkimOutput, DkimResult, DmarcOutput,
+    DmarcResult, Error, IprevOutput, IprevResult, ReceivedSpf, SpfOutput, SpfResult, VbrOutput,
+    VbrResult,
 };
 
-use super::headers::{HeaderWriter, Writer};
+use super::{
+    domain::{address_to_ascii, to_ascii},
+    headers::{HeaderWriter, Writer},
+};
+
+/// Authentication methods registered for Authentication-Results headers
+/// (RFC 8601 section 6.3 and the IANA Email Authentication Methods registry).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AuthMethod {
+    Auth,
+    Dkim,
+    DkimAdsp,
+    DkimAtps,
+    DomainKeys,
+    Iprev,
+    SenderId,
+    Spf,
+    Vbr,
+    Rrvs,
+    Smime,
+    Dmarc,
+    Arc,
+    Dnswl,
+    Bimi,
+    /// A method missing from the registry, in lowercase.
+    Other(String),
+}
+
+/// Property types of Authentication-Results method properties (RFC 8601
+/// section 2.3), such as `header` in `header.d=example.org`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PType {
+    Smtp,
+    Header,
+    Body,
+    Policy,
+}
+
+impl AuthMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            AuthMethod::Auth => "auth",
+            AuthMethod::Dkim => "dkim",
+            AuthMethod::DkimAdsp => "dkim-adsp",
+            AuthMethod::DkimAtps => "dkim-atps",
+            AuthMethod::DomainKeys => "domainkeys",
+            AuthMethod::Iprev => "iprev",
+            AuthMethod::SenderId => "sender-id",
+            AuthMethod::Spf => "spf",
+            AuthMethod::Vbr => "vbr",
+            AuthMethod::Rrvs => "rrvs",
+            AuthMethod::Smime => "smime",
+            AuthMethod::Dmarc => "dmarc",
+            AuthMethod::Arc => "arc",
+            AuthMethod::Dnswl => "dnswl",
+            AuthMethod::Bimi => "bimi",
+            AuthMethod::Other(method) => method,
+        }
+    }
+
+    /// Returns `false` for methods missing from the registry.
+    pub fn is_registered(&self) -> bool {
+        !matches!(self, AuthMethod::Other(_))
+    }
+}
+
+impl PType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PType::Smtp => "smtp",
+            PType::Header => "header",
+            PType::Body => "body",
+            PType::Policy => "policy",
+        }
+    }
+}
+
+impl From<&str> for AuthMethod {
+    fn from(value: &str) -> Self {
+        let value = value.trim().to_ascii_lowercase();
+        match value.as_str() {
+            "auth" => AuthMethod::Auth,
+            "dkim" => AuthMethod::Dkim,
+            "dkim-adsp" => AuthMethod::DkimAdsp,
+            "dkim-atps" => AuthMethod::DkimAtps,
+            "domainkeys" => AuthMethod::DomainKeys,
+            "iprev" => AuthMethod::Iprev,
+            "sender-id" => AuthMethod::SenderId,
+            "spf" => AuthMethod::Spf,
+            "vbr" => AuthMethod::Vbr,
+            "rrvs" => AuthMethod::Rrvs,
+            "smime" => AuthMethod::Smime,
+            "dmarc" => AuthMethod::Dmarc,
+            "arc" => AuthMethod::Arc,
+            "dnswl" => AuthMethod::Dnswl,
+            "bimi" => AuthMethod::Bimi,
+            _ => AuthMethod::Other(value),
+        }
+    }
+}
+
+impl FromStr for PType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "smtp" => Ok(PType::Smtp),
+            "header" => Ok(PType::Header),
+            "body" => Ok(PType::Body),
+            "policy" => Ok(PType::Policy),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AsRef<str> for AuthMethod {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Display for AuthMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Display for PType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 impl<'x> AuthenticationResults<'x> {
     pub fn new(hostname: &'x str) -> Self {
@@ -44,33 +177,31 @@ impl<'x> AuthenticationResults<'x> {
     }
 
     pub fn set_dkim_result(&mut self, dkim: &DkimOutput, header_from: &str) {
-        if !dkim.is_atps {
-            self.auth_results.push_str(";\r\n\tdkim=");
+        self.push_method(if !dkim.is_atps {
+            AuthMethod::Dkim
         } else {
-            self.auth_results.push_str(";\r\n\tdkim-atps=");
-        }
+            AuthMethod::DkimAtps
+        });
         dkim.result.as_auth_result(&mut self.auth_results);
         if let Some(signature) = &dkim.signature {
             if !signature.i.is_empty() {
-                self.auth_results.push_str(" header.i=");
-                self.auth_results.push_str(&signature.i);
+                self.push_property(PType::Header, "i", address_to_ascii(&signature.i));
             } else {
-                self.auth_results.push_str(" header.d=");
-                self.auth_results.push_str(&signature.d);
+                self.push_property(PType::Header, "d", to_ascii(&signature.d));
             }
-            self.auth_results.push_str(" header.s=");
-            self.auth_results.push_str(&signature.s);
+            self.push_property(PType::Header, "s", &signature.s);
             if signature.b.len() >= 6 {
-                self.auth_results.push_str(" header.b=");
-                self.auth_results.push_str(
-                    &String::from_utf8(base64_encode(&signature.b[..6]).unwrap_or_default())
+                self.push_property(
+                    PType::Header,
+                    "b",
+                    String::from_utf8(base64_encode(&signature.b[..6]).unwrap_or_default())
                         .unwrap_or_default(),
                 );
             }
         }
 
         if dkim.is_atps {
-            write!(self.auth_results, " header.from={header_from}").ok();
+            self.push_property(PType::Header, "from", header_from);
         }
     }
 
@@ -80,14 +211,14 @@ impl<'x> AuthenticationResults<'x> {
         ip_addr: IpAddr,
         ehlo_domain: &str,
     ) -> Self {
-        self.auth_results.push_str(";\r\n\tspf=");
+        self.push_method(AuthMethod::Spf);
         spf.result.as_spf_result(
             &mut self.auth_results,
             self.hostname,
             &format!("postmaster@{ehlo_domain}"),
             ip_addr,
         );
-        write!(self.auth_results, " smtp.helo={ehlo_domain}").ok();
+        self.push_property(PType::Smtp, "helo", ehlo_domain);
         self
     }
 
@@ -103,26 +234,26 @@ impl<'x> AuthenticationResults<'x> {
         } else {
             (format!("postmaster@{ehlo_domain}").into(), "<>")
         };
-        self.auth_results.push_str(";\r\n\tspf=");
+        self.push_method(AuthMethod::Spf);
         spf.result.as_spf_result(
             &mut self.auth_results,
             self.hostname,
             mail_from.as_ref(),
             ip_addr,
         );
-        write!(self.auth_results, " smtp.mailfrom={addr}").ok();
+        self.push_property(PType::Smtp, "mailfrom", addr);
         self
     }
 
     pub fn with_arc_result(mut self, arc: &ArcOutput, remote_ip: IpAddr) -> Self {
-        self.auth_results.push_str(";\r\n\tarc=");
+        self.push_method(AuthMethod::Arc);
         arc.result.as_auth_result(&mut self.auth_results);
-        write!(self.auth_results, " smtp.remote-ip={remote_ip}").ok();
+        self.push_property(PType::Smtp, "remote-ip", remote_ip);
         self
     }
 
     pub fn with_dmarc_result(mut self, dmarc: &DmarcOutput) -> Self {
-        self.auth_results.push_str(";\r\n\tdmarc=");
+        self.push_method(AuthMethod::Dmarc);
         if dmarc.spf_result == DmarcResult::Pass || dmarc.dkim_result == DmarcResult::Pass {
             DmarcResult::Pass.as_auth_result(&mut self.auth_results);
         } else if dmarc.spf_result != DmarcResult::None {
@@ -132,21 +263,60 @@ impl<'x> AuthenticationResults<'x> {
         } else {
             DmarcResult::None.as_auth_result(&mut self.auth_results);
         }
-        write!(
-            self.auth_results,
-            " header.from={} policy.dmarc={}",
-            dmarc.domain, dmarc.policy
-        )
-        .ok();
+        self.push_property(PType::Header, "from", &dmarc.domain);
+        self.push_property(PType::Policy, "dmarc", dmarc.policy);
         self
     }
 
+    pub fn with_bimi_result(mut self, bimi: &BimiOutput) -> Self {
+        self.push_method(AuthMethod::Bimi);
+        bimi.result.as_auth_result(&mut self.auth_results);
+        if !bimi.domain.is_empty() {
+            self.push_property(PType::Header, "d", &bimi.domain);
+            self.push_property(PType::Header, "selector", &bimi.selector);
+        }
+        self
+    }
+
+    pub fn with_vbr_result(mut self, vbr: &VbrOutput) -> Self {
+        self.push_method(AuthMethod::Vbr);
+        vbr.result.as_auth_result(&mut self.auth_results);
+        if !vbr.domain.is_empty() {
+            self.push_property(PType::Header, "md", &vbr.domain);
+        }
+        if let Some(service) = &vbr.service {
+            self.push_property(PType::Header, "mv", service);
+        }
+        self
+    }
+
+    /// Adds an explicit `none` result for a method that was not evaluated.
+    pub fn with_none_result(mut self, method: impl AsRef<str>) -> Self {
+        self.set_none_result(method);
+        self
+    }
+
+    pub fn set_none_result(&mut self, method: impl AsRef<str>) {
+        write!(self.auth_results, ";\r\n\t{}=none", method.as_ref()).ok();
+    }
+
     pub fn with_iprev_result(mut self, iprev: &IprevOutput, remote_ip: IpAddr) -> Self {
-        self.auth_results.push_str(";\r\n\tiprev=");
+        self.push_method(AuthMethod::Iprev);
         iprev.result.as_auth_result(&mut self.auth_results);
-        write!(self.auth_results, " policy.iprev={remote_ip}").ok();
+        self.push_property(PType::Policy, "iprev", remote_ip);
+        if let Some(confirmed) = &iprev.confirmed {
+            write!(self.auth_results, " ({confirmed})").ok();
+        }
         self
     }
+
+    fn push_method(&mut self, method: AuthMethod) {
+        write!(self.auth_results, ";\r\n\t{method}=").ok();
+    }
+
+    fn push_property(&mut self, ptype: PType, property: &str, value: impl Display) {
+        write!(self.auth_results, " {ptype}.{property}={value}").ok();
+    }
 }
 
 impl<'x> Display for AuthenticationResults<'x> {
@@ -287,6 +457,46 @@ impl AsAuthResult for IprevResult {
     }
 }
 
+impl AsAuthResult for BimiResult {
+    fn as_auth_result(&self, header: &mut String) {
+        match &self {
+            BimiResult::Pass => header.push_str("pass"),
+            BimiResult::Declined => header.push_str("declined"),
+            BimiResult::Skipped => header.push_str("skipped"),
+            BimiResult::Fail(err) => {
+                header.push_str("fail");
+                err.as_auth_result(header);
+            }
+            BimiResult::TempError(err) => {
+                header.push_str("temperror");
+                err.as_auth_result(header);
+            }
+            BimiResult::None => header.push_str("none"),
+        }
+    }
+}
+
+impl AsAuthResult for VbrResult {
+    fn as_auth_result(&self, header: &mut String) {
+        match &self {
+            VbrResult::Pass => header.push_str("pass"),
+            VbrResult::Fail(err) => {
+                header.push_str("fail");
+                err.as_auth_result(header);
+            }
+            VbrResult::TempError(err) => {
+                header.push_str("temperror");
+                err.as_auth_result(header);
+            }
+            VbrResult::PermError(err) => {
+                header.push_str("permerror");
+                err.as_auth_result(header);
+            }
+            VbrResult::None => header.push_str("none"),
+        }
+    }
+}
+
 impl AsAuthResult for DkimResult {
     fn as_auth_result(&self, header: &mut String) {
         match &self {
@@ -322,6 +532,7 @@ impl AsAuthResult for Error {
             Error::CryptoError(_) => "verification failed",
             Error::Io(_) => "i/o error",
             Error::Base64 => "base64 error",
+            Error::Base64TooLong => "base64 value too long",
             Error::UnsupportedVersion => "unsupported version",
             Error::UnsupportedAlgorithm => "unsupported algorithm",
             Error::UnsupportedCanonicalization => "unsupported canonicalization",
@@ -339,12 +550,17 @@ impl AsAuthResult for Error {
                 return;
             }
             Error::ArcInvalidCV => "invalid ARC cv",
+            Error::TooManySignatures => "too many signatures",
             Error::ArcChainTooLong => "too many ARC headers",
             Error::ArcHasHeaderTag => "ARC has header tag",
             Error::ArcBrokenChain => "broken ARC chain",
             Error::NotAligned => "policy not aligned",
             Error::InvalidRecordType => "invalid dns record type",
+            Error::RecordTooLong => "record too long",
+            Error::MultipleRecords => "multiple dns records",
+            Error::DeprecatedRecordType => "deprecated dns record type",
             Error::SignatureLength => "signature length ignored due to security risk",
+            Error::LocalPolicy => "local policy",
         });
         header.push(')');
     }
@@ -358,6 +574,8 @@ mod test {
         SpfResult,
     };
 
+    use super::{AuthMethod, PType};
+
     #[test]
     fn authentication_results() {
         let mut auth_results = AuthenticationResults::new("mydomain.org");
@@ -375,6 +593,11 @@ mod test {
                         .into(),
                     report: None,
                     is_atps: false,
+                    compat_shims: Vec::new(),
+                    body_normalization: None,
+                    body_bytes_hashed: None,
+                    resent_unsigned: false,
+                    policy: None,
                 },
             ),
             (
@@ -393,6 +616,11 @@ mod test {
                         .into(),
                     report: None,
                     is_atps: false,
+                    compat_shims: Vec::new(),
+                    body_normalization: None,
+                    body_bytes_hashed: None,
+                    resent_unsigned: false,
+                    policy: None,
                 },
             ),
             (
@@ -411,6 +639,11 @@ mod test {
                         .into(),
                     report: None,
                     is_atps: true,
+                    compat_shims: Vec::new(),
+                    body_normalization: None,
+                    body_bytes_hashed: None,
+                    resent_unsigned: false,
+                    policy: None,
                 },
             ),
         ] {
@@ -487,6 +720,9 @@ mod test {
                     domain: "".to_string(),
                     report: None,
                     explanation: None,
+                    code: None,
+                    trace: None,
+                    helo: None,
                 },
                 ip_addr,
                 mail_from,
@@ -498,6 +734,9 @@ mod test {
                     domain: "".to_string(),
                     report: None,
                     explanation: None,
+                    code: None,
+                    trace: None,
+                    helo: None,
                 },
                 ip_addr,
                 helo,
@@ -520,6 +759,8 @@ mod test {
                     domain: "example.org".to_string(),
                     policy: Policy::None,
                     record: None,
+                    arc_override: None,
+                    local_override: None,
                 },
             ),
             (
@@ -530,6 +771,8 @@ mod test {
                     domain: "example.com".to_string(),
                     policy: Policy::Quarantine,
                     record: None,
+                    arc_override: None,
+                    local_override: None,
                 },
             ),
         ] {
@@ -556,6 +799,8 @@ mod test {
                 &ArcOutput {
                     result: arc,
                     set: vec![],
+                    violations: vec![],
+                    score: None,
                 },
                 remote_ip,
             );
@@ -571,14 +816,25 @@ mod test {
                 IprevOutput {
                     result: IprevResult::Pass,
                     ptr: None,
+                    confirmed: None,
                 },
                 "192.127.9.2".parse().unwrap(),
             ),
+            (
+                "iprev=pass policy.iprev=192.0.2.1 (mail.example.org)",
+                IprevOutput {
+                    result: IprevResult::Pass,
+                    ptr: None,
+                    confirmed: Some("mail.example.org".to_string()),
+                },
+                "192.0.2.1".parse().unwrap(),
+            ),
             (
                 "iprev=fail (policy not aligned) policy.iprev=1:2:3::a",
                 IprevOutput {
                     result: IprevResult::Fail(Error::NotAligned),
                     ptr: None,
+                    confirmed: None,
                 },
                 "1:2:3::a".parse().unwrap(),
             ),
@@ -589,5 +845,48 @@ mod test {
                 expected_auth_results
             );
         }
+
+        auth_results = auth_results.with_none_result("iprev");
+        assert_eq!(
+            auth_results.auth_results.rsplit_once(';').unwrap().1.trim(),
+            "iprev=none"
+        );
+
+        let mut aar = Vec::new();
+        AuthenticationResults::new("mydomain.org").write(&mut aar, 1, true);
+        assert_eq!(
+            aar,
+            b"ARC-Authentication-Results: i=1; mydomain.org; none\r\n"
+        );
+    }
+
+    #[test]
+    fn auth_methods() {
+        for (name, method) in [
+            ("dkim", AuthMethod::Dkim),
+            ("DKIM-ATPS", AuthMethod::DkimAtps),
+            (" dnswl", AuthMethod::Dnswl),
+            ("x-spam", AuthMethod::Other("x-spam".to_string())),
+        ] {
+            let parsed = AuthMethod::from(name);
+            assert_eq!(parsed, method);
+            assert_eq!(parsed.as_str(), name.trim().to_ascii_lowercase());
+            assert_eq!(parsed.is_registered(), name != "x-spam");
+        }
+        for (name, ptype) in [
+            ("smtp", Ok(PType::Smtp)),
+            ("Header", Ok(PType::Header)),
+            ("body", Ok(PType::Body)),
+            ("policy", Ok(PType::Policy)),
+            ("foo", Err(())),
+        ] {
+            assert_eq!(name.parse::<PType>(), ptype);
+        }
+        assert_eq!(
+            AuthenticationResults::new("mydomain.org")
+                .with_none_result(AuthMethod::Dnswl)
+                .to_string(),
+            "mydomain.org;\r\n\tdnswl=none"
+        );
     }
 }
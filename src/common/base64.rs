@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::slice::Iter;
+
+use crate::Error;
+
+/// Maximum decoded length of a `b=` tag, enough for an RSA-8192 signature.
+pub const MAX_SIGNATURE_LEN: usize = 1024;
+
+/// Maximum decoded length of a `bh=` tag, enough for a SHA-512 digest.
+pub const MAX_BODY_HASH_LEN: usize = 64;
+
+/// Maximum decoded length of a `p=` tag, enough for an RSA-16384 public key.
+pub const MAX_PUBLIC_KEY_LEN: usize = 2112;
+
+/// Decodes a base64 tag value up to the next `;`, skipping folding whitespace.
+///
+/// Characters are decoded without table lookups or branches on their value,
+/// and invalid characters are only reported once the whole value has been
+/// read. The output is allocated once and never grows beyond `max_len`; longer
+/// values fail with `Error::Base64TooLong`.
+pub(crate) fn decode_tag(stream: &mut Iter<'_, u8>, max_len: usize) -> crate::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity((stream.len() / 4 * 3).min(max_len));
+    let mut chunk: u32 = 0;
+    let mut count = 0;
+    let mut padding = 0;
+    let mut invalid = 0;
+
+    for &ch in stream.by_ref() {
+        match ch {
+            b';' => break,
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            _ => (),
+        }
+        let val = decode_char(ch);
+        invalid |= (val >> 8) | i16::from(padding > 0);
+        chunk = (chunk << 6) | (val as u32 & 0x3f);
+        count += 1;
+        if count == 4 {
+            if buf.len() + 3 > max_len {
+                return Err(Error::Base64TooLong);
+            }
+            buf.extend_from_slice(&chunk.to_be_bytes()[1..]);
+            chunk = 0;
+            count = 0;
+        }
+    }
+
+    // Trailing partial group, with or without padding. A dangling character
+    // is ignored as it does not complete a byte.
+    let remainder = match count {
+        0 | 1 if padding == 0 => 0,
+        2 if padding <= 2 => 1,
+        3 if padding <= 1 => 2,
+        _ => return Err(Error::Base64),
+    };
+    if invalid != 0 {
+        Err(Error::Base64)
+    } else if buf.len() + remainder > max_len {
+        Err(Error::Base64TooLong)
+    } else {
+        let chunk = chunk << (6 * (4 - count));
+        buf.extend_from_slice(&chunk.to_be_bytes()[1..1 + remainder]);
+        Ok(buf)
+    }
+}
+
+/// Returns the value of a base64 character, or -1 if it is not part of the
+/// alphabet, in constant time.
+#[inline(always)]
+fn decode_char(ch: u8) -> i16 {
+    let ch = i16::from(ch);
+    let mut val = -1;
+    val += (((0x40 - ch) & (ch - 0x5b)) >> 8) & (ch - 64); // A-Z
+    val += (((0x60 - ch) & (ch - 0x7b)) >> 8) & (ch - 70); // a-z
+    val += (((0x2f - ch) & (ch - 0x3a)) >> 8) & (ch + 5); // 0-9
+    val += (((0x2a - ch) & (ch - 0x2c)) >> 8) & 63; // +
+    val += (((0x2e - ch) & (ch - 0x30)) >> 8) & 64; // /
+    val
+}
+
+#[cfg(test)]
+mod test {
+    use mail_builder::encoders::base64::base64_encode;
+
+    use crate::{
+        arc,
+        common::{parse::TxtRecordParser, verify::DomainKey},
+        dkim::Signature,
+        Error,
+    };
+
+    use super::{decode_char, decode_tag, MAX_SIGNATURE_LEN};
+
+    #[test]
+    fn base64_tags() {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        for ch in 0..=u8::MAX {
+            let expected = ALPHABET
+                .iter()
+                .position(|&c| c == ch)
+                .map_or(-1, |v| v as i16);
+            assert_eq!(decode_char(ch), expected, "{:?}", char::from(ch));
+        }
+
+        // Round trip of every length, with and without padding and folding
+        let mut seed = 0x2545f491u32;
+        for len in 0..=70 {
+            let bytes = (0..len)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 17;
+                    seed ^= seed << 5;
+                    seed as u8
+                })
+                .collect::<Vec<_>>();
+            let encoded = String::from_utf8(base64_encode(&bytes).unwrap()).unwrap();
+            for value in [
+                format!("{encoded}; x=1"),
+                format!(" {} ", encoded.trim_end_matches('=')),
+                encoded
+                    .as_bytes()
+                    .chunks(7)
+                    .map(|c| std::str::from_utf8(c).unwrap())
+                    .collect::<Vec<_>>()
+                    .join("\r\n\t"),
+            ] {
+                let mut stream = value.as_bytes().iter();
+                assert_eq!(decode_tag(&mut stream, len), Ok(bytes.clone()), "{value:?}");
+                if value.contains(';') {
+                    assert_eq!(stream.as_slice(), b" x=1");
+                }
+            }
+            if len > 0 {
+                assert_eq!(
+                    decode_tag(&mut encoded.as_bytes().iter(), len - 1),
+                    Err(Error::Base64TooLong)
+                );
+            }
+        }
+
+        // Malformed values
+        for value in [
+            "YWJj*GVm",
+            "YWJjZGVm=YQ",
+            "YQ===",
+            "YWI==x",
+            "YWJ\0",
+            "=",
+            "é",
+        ] {
+            assert_eq!(
+                decode_tag(&mut value.as_bytes().iter(), 64),
+                Err(Error::Base64),
+                "{value:?}"
+            );
+        }
+
+        // Oversized values are rejected before being fully decoded
+        let oversized = "QUFB".repeat(MAX_SIGNATURE_LEN);
+        let header =
+            format!("v=1; a=rsa-sha256; d=example.com; s=sel; h=from; bh=YWJj; b={oversized}");
+        assert_eq!(
+            Signature::parse(header.as_bytes()).err(),
+            Some(Error::Base64TooLong)
+        );
+        assert_eq!(
+            arc::Seal::parse(format!("i=1; a=rsa-sha256; cv=none; b={oversized}").as_bytes()).err(),
+            Some(Error::Base64TooLong)
+        );
+        let header = format!(
+            "v=1; a=rsa-sha256; d=example.com; s=sel; h=from; bh={}; b=YWJj",
+            "QUFB".repeat(22)
+        );
+        assert_eq!(
+            Signature::parse(header.as_bytes()).err(),
+            Some(Error::Base64TooLong)
+        );
+        assert_eq!(
+            DomainKey::parse(format!("v=DKIM1; p={oversized}").as_bytes()).err(),
+            Some(Error::Base64TooLong)
+        );
+        assert_eq!(
+            Signature::parse(b"v=1; a=rsa-sha256; d=example.com; s=sel; h=from; bh=Y*Jj; b=YWJj")
+                .err(),
+            Some(Error::Base64)
+        );
+    }
+}
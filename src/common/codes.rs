@@ -0,0 +1,430 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::fmt::Display;
+
+use crate::{
+    ArcOutput, BimiOutput, BimiResult, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
+    IprevOutput, IprevResult, SpfOutput, SpfResult,
+};
+
+/// Stable machine-readable codes describing why an authentication check did not pass.
+///
+/// Numeric values and names never change between versions, new codes are only appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u16)]
+pub enum ResultCode {
+    // DKIM
+    DkimNoSignature = 1000,
+    DkimBodyHashMismatch = 1001,
+    DkimSignatureMismatch = 1002,
+    DkimAuidMismatch = 1003,
+    DkimSignatureExpired = 1004,
+    DkimLengthExceeded = 1005,
+    DkimKeyRevoked = 1006,
+    DkimKeyNotFound = 1007,
+    DkimKeyInvalid = 1008,
+    DkimAlgorithmMismatch = 1009,
+    DkimUnsupportedVersion = 1010,
+    DkimUnsupportedAlgorithm = 1011,
+    DkimUnsupportedCanonicalization = 1012,
+    DkimUnsupportedKeyType = 1013,
+    DkimMalformedSignature = 1014,
+    DkimDnsError = 1015,
+    DkimCryptoError = 1016,
+    DkimTooManySignatures = 1017,
+    DkimOther = 1099,
+    // ARC
+    ArcNoChain = 2000,
+    ArcChainTooLong = 2001,
+    ArcInvalidInstance = 2002,
+    ArcInvalidCv = 2003,
+    ArcHasHeaderTag = 2004,
+    ArcBrokenChain = 2005,
+    ArcBodyHashMismatch = 2006,
+    ArcSignatureMismatch = 2007,
+    ArcKeyNotFound = 2008,
+    ArcMalformedHeader = 2009,
+    ArcDnsError = 2010,
+    ArcOther = 2099,
+    // SPF
+    SpfNoRecord = 3000,
+    SpfInvalidDomain = 3001,
+    SpfFail = 3002,
+    SpfSoftFail = 3003,
+    SpfNeutral = 3004,
+    SpfLookupLimit = 3005,
+    SpfMxLimit = 3006,
+    SpfIncludeNotFound = 3007,
+    SpfRedirectNotFound = 3008,
+    SpfRecordInvalid = 3009,
+    SpfDnsError = 3010,
+    SpfVoidLookupLimit = 3011,
+    SpfMultipleRecords = 3012,
+    SpfDeprecatedRecordType = 3013,
+    // DMARC
+    DmarcNoPolicy = 4000,
+    DmarcNotAligned = 4001,
+    DmarcNoAuthentication = 4002,
+    DmarcRecordInvalid = 4003,
+    DmarcDnsError = 4004,
+    DmarcMultipleRecords = 4005,
+    // IPREV
+    IprevNone = 5000,
+    IprevNotMatched = 5001,
+    IprevNoPtr = 5002,
+    IprevDnsError = 5003,
+    IprevOther = 5099,
+    // BIMI
+    BimiNoRecord = 6000,
+    BimiDeclined = 6001,
+    BimiSkipped = 6002,
+    BimiRecordInvalid = 6003,
+    BimiDnsError = 6004,
+}
+
+impl ResultCode {
+    /// All registered codes, sorted by numeric value.
+    pub const ALL: &'static [ResultCode] = &[
+        ResultCode::DkimNoSignature,
+        ResultCode::DkimBodyHashMismatch,
+        ResultCode::DkimSignatureMismatch,
+        ResultCode::DkimAuidMismatch,
+        ResultCode::DkimSignatureExpired,
+        ResultCode::DkimLengthExceeded,
+        ResultCode::DkimKeyRevoked,
+        ResultCode::DkimKeyNotFound,
+        ResultCode::DkimKeyInvalid,
+        ResultCode::DkimAlgorithmMismatch,
+        ResultCode::DkimUnsupportedVersion,
+        ResultCode::DkimUnsupportedAlgorithm,
+        ResultCode::DkimUnsupportedCanonicalization,
+        ResultCode::DkimUnsupportedKeyType,
+        ResultCode::DkimMalformedSignature,
+        ResultCode::DkimDnsError,
+        ResultCode::DkimCryptoError,
+        ResultCode::DkimTooManySignatures,
+        ResultCode::DkimOther,
+        ResultCode::ArcNoChain,
+        ResultCode::ArcChainTooLong,
+        ResultCode::ArcInvalidInstance,
+        ResultCode::ArcInvalidCv,
+        ResultCode::ArcHasHeaderTag,
+        ResultCode::ArcBrokenChain,
+        ResultCode::ArcBodyHashMismatch,
+        ResultCode::ArcSignatureMismatch,
+        ResultCode::ArcKeyNotFound,
+        ResultCode::ArcMalformedHeader,
+        ResultCode::ArcDnsError,
+        ResultCode::ArcOther,
+        ResultCode::SpfNoRecord,
+        ResultCode::SpfInvalidDomain,
+        ResultCode::SpfFail,
+        ResultCode::SpfSoftFail,
+        ResultCode::SpfNeutral,
+        ResultCode::SpfLookupLimit,
+        ResultCode::SpfMxLimit,
+        ResultCode::SpfIncludeNotFound,
+        ResultCode::SpfRedirectNotFound,
+        ResultCode::SpfRecordInvalid,
+        ResultCode::SpfDnsError,
+        ResultCode::SpfVoidLookupLimit,
+        ResultCode::SpfMultipleRecords,
+        ResultCode::SpfDeprecatedRecordType,
+        ResultCode::DmarcNoPolicy,
+        ResultCode::DmarcNotAligned,
+        ResultCode::DmarcNoAuthentication,
+        ResultCode::DmarcRecordInvalid,
+        ResultCode::DmarcDnsError,
+        ResultCode::DmarcMultipleRecords,
+        ResultCode::IprevNone,
+        ResultCode::IprevNotMatched,
+        ResultCode::IprevNoPtr,
+        ResultCode::IprevDnsError,
+        ResultCode::IprevOther,
+        ResultCode::BimiNoRecord,
+        ResultCode::BimiDeclined,
+        ResultCode::BimiSkipped,
+        ResultCode::BimiRecordInvalid,
+        ResultCode::BimiDnsError,
+    ];
+
+    /// Returns the numeric code.
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Returns the string code.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResultCode::DkimNoSignature => "DKIM_NO_SIGNATURE",
+            ResultCode::DkimBodyHashMismatch => "DKIM_BODYHASH_MISMATCH",
+            ResultCode::DkimSignatureMismatch => "DKIM_SIGNATURE_MISMATCH",
+            ResultCode::DkimAuidMismatch => "DKIM_AUID_MISMATCH",
+            ResultCode::DkimSignatureExpired => "DKIM_SIGNATURE_EXPIRED",
+            ResultCode::DkimLengthExceeded => "DKIM_LENGTH_EXCEEDED",
+            ResultCode::DkimKeyRevoked => "DKIM_KEY_REVOKED",
+            ResultCode::DkimKeyNotFound => "DKIM_KEY_NOT_FOUND",
+            ResultCode::DkimKeyInvalid => "DKIM_KEY_INVALID",
+            ResultCode::DkimAlgorithmMismatch => "DKIM_ALGORITHM_MISMATCH",
+            ResultCode::DkimUnsupportedVersion => "DKIM_UNSUPPORTED_VERSION",
+            ResultCode::DkimUnsupportedAlgorithm => "DKIM_UNSUPPORTED_ALGORITHM",
+            ResultCode::DkimUnsupportedCanonicalization => "DKIM_UNSUPPORTED_CANONICALIZATION",
+            ResultCode::DkimUnsupportedKeyType => "DKIM_UNSUPPORTED_KEY_TYPE",
+            ResultCode::DkimMalformedSignature => "DKIM_MALFORMED_SIGNATURE",
+            ResultCode::DkimDnsError => "DKIM_DNS_ERROR",
+            ResultCode::DkimCryptoError => "DKIM_CRYPTO_ERROR",
+            ResultCode::DkimTooManySignatures => "DKIM_TOO_MANY_SIGNATURES",
+            ResultCode::DkimOther => "DKIM_OTHER",
+            ResultCode::ArcNoChain => "ARC_NO_CHAIN",
+            ResultCode::ArcChainTooLong => "ARC_CHAIN_TOO_LONG",
+            ResultCode::ArcInvalidInstance => "ARC_INVALID_INSTANCE",
+            ResultCode::ArcInvalidCv => "ARC_INVALID_CV",
+            ResultCode::ArcHasHeaderTag => "ARC_HAS_HEADER_TAG",
+            ResultCode::ArcBrokenChain => "ARC_BROKEN_CHAIN",
+            ResultCode::ArcBodyHashMismatch => "ARC_BODYHASH_MISMATCH",
+            ResultCode::ArcSignatureMismatch => "ARC_SIGNATURE_MISMATCH",
+            ResultCode::ArcKeyNotFound => "ARC_KEY_NOT_FOUND",
+            ResultCode::ArcMalformedHeader => "ARC_MALFORMED_HEADER",
+            ResultCode::ArcDnsError => "ARC_DNS_ERROR",
+            ResultCode::ArcOther => "ARC_OTHER",
+            ResultCode::SpfNoRecord => "SPF_NO_RECORD",
+            ResultCode::SpfInvalidDomain => "SPF_INVALID_DOMAIN",
+            ResultCode::SpfFail => "SPF_FAIL",
+            ResultCode::SpfSoftFail => "SPF_SOFTFAIL",
+            ResultCode::SpfNeutral => "SPF_NEUTRAL",
+            ResultCode::SpfLookupLimit => "SPF_LOOKUP_LIMIT",
+            ResultCode::SpfMxLimit => "SPF_MX_LIMIT",
+            ResultCode::SpfIncludeNotFound => "SPF_INCLUDE_NOT_FOUND",
+            ResultCode::SpfRedirectNotFound => "SPF_REDIRECT_NOT_FOUND",
+            ResultCode::SpfRecordInvalid => "SPF_RECORD_INVALID",
+            ResultCode::SpfDnsError => "SPF_DNS_ERROR",
+            ResultCode::SpfVoidLookupLimit => "SPF_VOID_LOOKUP_LIMIT",
+            ResultCode::SpfMultipleRecords => "SPF_MULTIPLE_RECORDS",
+            ResultCode::SpfDeprecatedRecordType => "SPF_DEPRECATED_RECORD_TYPE",
+            ResultCode::DmarcNoPolicy => "DMARC_NO_POLICY",
+            ResultCode::DmarcNotAligned => "DMARC_NOT_ALIGNED",
+            ResultCode::DmarcNoAuthentication => "DMARC_NO_AUTHENTICATION",
+            ResultCode::DmarcRecordInvalid => "DMARC_RECORD_INVALID",
+            ResultCode::DmarcDnsError => "DMARC_DNS_ERROR",
+            ResultCode::DmarcMultipleRecords => "DMARC_MULTIPLE_RECORDS",
+            ResultCode::IprevNone => "IPREV_NONE",
+            ResultCode::IprevNotMatched => "IPREV_NOT_MATCHED",
+            ResultCode::IprevNoPtr => "IPREV_NO_PTR",
+            ResultCode::IprevDnsError => "IPREV_DNS_ERROR",
+            ResultCode::IprevOther => "IPREV_OTHER",
+            ResultCode::BimiNoRecord => "BIMI_NO_RECORD",
+            ResultCode::BimiDeclined => "BIMI_DECLINED",
+            ResultCode::BimiSkipped => "BIMI_SKIPPED",
+            ResultCode::BimiRecordInvalid => "BIMI_RECORD_INVALID",
+            ResultCode::BimiDnsError => "BIMI_DNS_ERROR",
+        }
+    }
+
+    /// Looks up a code by its numeric value.
+    pub fn from_code(code: u16) -> Option<Self> {
+        ResultCode::ALL
+            .binary_search_by_key(&code, |c| c.code())
+            .ok()
+            .map(|idx| ResultCode::ALL[idx])
+    }
+
+    /// Looks up a code by its string value.
+    pub fn from_str_code(code: &str) -> Option<Self> {
+        ResultCode::ALL.iter().copied().find(|c| c.as_str() == code)
+    }
+}
+
+impl Display for ResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<'x> DkimOutput<'x> {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        match &self.result {
+            DkimResult::Pass => None,
+            DkimResult::None => Some(ResultCode::DkimNoSignature),
+            DkimResult::Neutral(err)
+            | DkimResult::Fail(err)
+            | DkimResult::PermError(err)
+            | DkimResult::TempError(err) => Some(match err {
+                Error::FailedBodyHashMatch => ResultCode::DkimBodyHashMismatch,
+                Error::FailedVerification => ResultCode::DkimSignatureMismatch,
+                Error::FailedAuidMatch => ResultCode::DkimAuidMismatch,
+                Error::SignatureExpired => ResultCode::DkimSignatureExpired,
+                Error::SignatureLength => ResultCode::DkimLengthExceeded,
+                Error::RevokedPublicKey => ResultCode::DkimKeyRevoked,
+                Error::DnsRecordNotFound(_) => ResultCode::DkimKeyNotFound,
+                Error::InvalidRecordType => ResultCode::DkimKeyInvalid,
+                Error::IncompatibleAlgorithms => ResultCode::DkimAlgorithmMismatch,
+                Error::UnsupportedVersion => ResultCode::DkimUnsupportedVersion,
+                Error::UnsupportedAlgorithm => ResultCode::DkimUnsupportedAlgorithm,
+                Error::UnsupportedCanonicalization => ResultCode::DkimUnsupportedCanonicalization,
+                Error::UnsupportedKeyType => ResultCode::DkimUnsupportedKeyType,
+                Error::ParseError
+                | Error::MissingParameters
+                | Error::NoHeadersFound
+                | Error::Base64
+                | Error::Base64TooLong => ResultCode::DkimMalformedSignature,
+                Error::DnsError(_) => ResultCode::DkimDnsError,
+                Error::CryptoError(_) | Error::Io(_) => ResultCode::DkimCryptoError,
+                Error::TooManySignatures => ResultCode::DkimTooManySignatures,
+                _ => ResultCode::DkimOther,
+            }),
+        }
+    }
+}
+
+impl<'x> ArcOutput<'x> {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        match &self.result {
+            DkimResult::Pass => None,
+            DkimResult::None => Some(ResultCode::ArcNoChain),
+            DkimResult::Neutral(err)
+            | DkimResult::Fail(err)
+            | DkimResult::PermError(err)
+            | DkimResult::TempError(err) => Some(match err {
+                Error::ArcChainTooLong => ResultCode::ArcChainTooLong,
+                Error::ArcInvalidInstance(_) => ResultCode::ArcInvalidInstance,
+                Error::ArcInvalidCV => ResultCode::ArcInvalidCv,
+                Error::ArcHasHeaderTag => ResultCode::ArcHasHeaderTag,
+                Error::ArcBrokenChain => ResultCode::ArcBrokenChain,
+                Error::FailedBodyHashMatch => ResultCode::ArcBodyHashMismatch,
+                Error::FailedVerification => ResultCode::ArcSignatureMismatch,
+                Error::DnsRecordNotFound(_) => ResultCode::ArcKeyNotFound,
+                Error::ParseError
+                | Error::MissingParameters
+                | Error::NoHeadersFound
+                | Error::Base64
+                | Error::Base64TooLong => ResultCode::ArcMalformedHeader,
+                Error::DnsError(_) => ResultCode::ArcDnsError,
+                _ => ResultCode::ArcOther,
+            }),
+        }
+    }
+}
+
+impl SpfOutput {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        match self.result {
+            SpfResult::Pass => None,
+            _ if self.code.is_some() => self.code,
+            SpfResult::Fail => Some(ResultCode::SpfFail),
+            SpfResult::SoftFail => Some(ResultCode::SpfSoftFail),
+            SpfResult::Neutral => Some(ResultCode::SpfNeutral),
+            SpfResult::TempError => Some(ResultCode::SpfDnsError),
+            SpfResult::PermError => Some(ResultCode::SpfRecordInvalid),
+            SpfResult::None => Some(ResultCode::SpfNoRecord),
+        }
+    }
+}
+
+impl DmarcOutput {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        let results = [&self.spf_result, &self.dkim_result];
+        if results.contains(&&DmarcResult::Pass) {
+            None
+        } else if results
+            .iter()
+            .any(|r| matches!(r, DmarcResult::TempError(_)))
+        {
+            Some(ResultCode::DmarcDnsError)
+        } else if results.contains(&&DmarcResult::PermError(Error::MultipleRecords)) {
+            Some(ResultCode::DmarcMultipleRecords)
+        } else if results
+            .iter()
+            .any(|r| matches!(r, DmarcResult::PermError(_)))
+        {
+            Some(ResultCode::DmarcRecordInvalid)
+        } else if self.record.is_none() {
+            Some(ResultCode::DmarcNoPolicy)
+        } else if results.iter().any(|r| matches!(r, DmarcResult::Fail(_))) {
+            Some(ResultCode::DmarcNotAligned)
+        } else {
+            Some(ResultCode::DmarcNoAuthentication)
+        }
+    }
+}
+
+impl IprevOutput {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        match &self.result {
+            IprevResult::Pass => None,
+            IprevResult::None => Some(ResultCode::IprevNone),
+            IprevResult::Fail(_) => Some(ResultCode::IprevNotMatched),
+            IprevResult::TempError(_) => Some(ResultCode::IprevDnsError),
+            IprevResult::PermError(Error::DnsRecordNotFound(_)) => Some(ResultCode::IprevNoPtr),
+            IprevResult::PermError(_) => Some(ResultCode::IprevOther),
+        }
+    }
+}
+
+impl BimiOutput {
+    /// Returns the code describing why verification did not pass.
+    pub fn result_code(&self) -> Option<ResultCode> {
+        match &self.result {
+            BimiResult::Pass => None,
+            BimiResult::None => Some(ResultCode::BimiNoRecord),
+            BimiResult::Declined => Some(ResultCode::BimiDeclined),
+            BimiResult::Skipped => Some(ResultCode::BimiSkipped),
+            BimiResult::Fail(_) => Some(ResultCode::BimiRecordInvalid),
+            BimiResult::TempError(_) => Some(ResultCode::BimiDnsError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::{DkimOutput, Error, SpfOutput, SpfResult};
+
+    use super::ResultCode;
+
+    #[test]
+    fn result_codes() {
+        let mut names = HashSet::new();
+        for (pos, code) in ResultCode::ALL.iter().enumerate() {
+            assert!(names.insert(code.as_str()), "duplicate code {code}");
+            assert!(pos == 0 || ResultCode::ALL[pos - 1].code() < code.code());
+            assert_eq!(ResultCode::from_code(code.code()), Some(*code));
+            assert_eq!(ResultCode::from_str_code(code.as_str()), Some(*code));
+        }
+
+        assert_eq!(ResultCode::DkimBodyHashMismatch.code(), 1001);
+        assert_eq!(ResultCode::SpfLookupLimit.as_str(), "SPF_LOOKUP_LIMIT");
+        assert_eq!(
+            DkimOutput::fail(Error::FailedBodyHashMatch).result_code(),
+            Some(ResultCode::DkimBodyHashMismatch)
+        );
+        assert_eq!(DkimOutput::pass().result_code(), None);
+        assert_eq!(
+            SpfOutput::new("example.org".to_string())
+                .with_result(SpfResult::PermError)
+                .with_code(ResultCode::SpfLookupLimit)
+                .result_code(),
+            Some(ResultCode::SpfLookupLimit)
+        );
+        assert_eq!(
+            SpfOutput::new("example.org".to_string())
+                .with_result(SpfResult::SoftFail)
+                .result_code(),
+            Some(ResultCode::SpfSoftFail)
+        );
+    }
+}
@@ -0,0 +1,716 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::{
+    common::{crypto::Algorithm, lookalike::LookalikeDetector},
+    dkim::{CompatShim, PolicyDecision},
+    dmarc::PolicyVersion,
+    ArcOutput, DkimResult, Domain, Error, Resolver, SpfResult,
+};
+
+/// Verification settings of a `Resolver`, which can be replaced at runtime
+/// without discarding the DNS caches.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Config {
+    crypto: CryptoPolicy,
+    verification: VerificationPolicy,
+    limits: Limits,
+    dmarc_version: PolicyVersion,
+    trusted_sealers: Vec<String>,
+    compat_shims: Vec<CompatShim>,
+    body_hash_diagnostics: bool,
+    spf_trace: bool,
+    arc_strict: bool,
+    legacy_record_types: bool,
+    iprev: IprevPolicy,
+    lookalike: Option<LookalikeDetector>,
+    overrides: HashMap<String, DomainOverride>,
+    tenants: HashMap<String, TenantSettings>,
+}
+
+/// Signature algorithms accepted during verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoPolicy {
+    allow_rsa_sha1: bool,
+}
+
+/// Checks applied to DKIM signatures, and to ARC signatures and seals where
+/// relevant, on top of the ones required by RFC 6376.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerificationPolicy {
+    min_rsa_bits: usize,
+    body_length: BodyLengthPolicy,
+    max_age: Option<Duration>,
+    expiration_grace: Duration,
+}
+
+/// How signatures with an `l=` tag are handled. Messages parsed in strict mode,
+/// the default of `AuthenticatedMessage::parse`, reject `l=` tags when parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BodyLengthPolicy {
+    /// Signatures covering part of the body are accepted.
+    #[default]
+    Accept,
+    /// Signatures are accepted only if `l=` covers the whole body.
+    FullBody,
+    /// Signatures with an `l=` tag fail.
+    Reject,
+}
+
+/// Resource limits applied during verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    spf_lookups: u32,
+    spf_mx_lookups: usize,
+    spf_ptr_lookups: usize,
+    spf_void_lookups: u32,
+    spf_timeout: Duration,
+    spf_limit_result: SpfResult,
+    arc_headers: usize,
+    dkim_signatures: usize,
+}
+
+/// Forward-confirmed reverse DNS settings used by `verify_iprev`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IprevPolicy {
+    max_ptr: usize,
+    require_all: bool,
+    lookup_timeout: Option<Duration>,
+    negative_ttl: Option<Duration>,
+}
+
+/// Settings applied to a single domain instead of the global ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DomainOverride {
+    crypto: Option<CryptoPolicy>,
+    verification: Option<VerificationPolicy>,
+    dmarc_version: Option<PolicyVersion>,
+}
+
+/// Settings of a tenant, applied on top of the global ones by
+/// `Resolver::for_tenant`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TenantSettings {
+    trusted_sealers: Vec<String>,
+    overrides: HashMap<String, DomainOverride>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the signature algorithms accepted during verification.
+    pub fn with_crypto_policy(mut self, crypto: CryptoPolicy) -> Self {
+        self.crypto = crypto;
+        self
+    }
+
+    /// Sets the checks applied to DKIM and ARC signatures.
+    pub fn with_verification_policy(mut self, verification: VerificationPolicy) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Sets the verification resource limits.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the DMARC policy discovery and alignment rules used by `verify_dmarc`.
+    pub fn with_dmarc_version(mut self, version: PolicyVersion) -> Self {
+        self.dmarc_version = version;
+        self
+    }
+
+    /// Trusts the ARC seals added by a domain.
+    pub fn with_trusted_sealer(mut self, domain: impl AsRef<str>) -> Self {
+        self.trusted_sealers
+            .push(Domain::normalize(domain.as_ref()));
+        self
+    }
+
+    /// Enables a DKIM compatibility shim for known-broken signers.
+    pub fn with_compat_shim(mut self, shim: CompatShim) -> Self {
+        if !self.compat_shims.contains(&shim) {
+            self.compat_shims.push(shim);
+        }
+        self
+    }
+
+    /// Retries failed DKIM body hashes with the message normalized, reporting the
+    /// normalization that would have matched without changing the verdict.
+    pub fn with_body_hash_diagnostics(mut self, enable: bool) -> Self {
+        self.body_hash_diagnostics = enable;
+        self
+    }
+
+    /// Records a step-by-step trace of every SPF evaluation in its output.
+    /// The SPF result cache is bypassed while tracing is enabled.
+    pub fn with_spf_trace(mut self, enable: bool) -> Self {
+        self.spf_trace = enable;
+        self
+    }
+
+    /// Validates ARC chains against every requirement of RFC 8617 section 5.2,
+    /// reporting each violation in the output instead of stopping at the first.
+    pub fn with_arc_strict(mut self, enable: bool) -> Self {
+        self.arc_strict = enable;
+        self
+    }
+
+    /// Looks up the deprecated type 99 SPF record when no valid SPF TXT record
+    /// is found, reporting remnants as `Error::DeprecatedRecordType`. This
+    /// costs an extra query for every domain without a valid record.
+    pub fn with_legacy_record_types(mut self, enable: bool) -> Self {
+        self.legacy_record_types = enable;
+        self
+    }
+
+    /// Checks the RFC5322.From and DKIM domains of messages verified with
+    /// `verify_message` for lookalikes of protected domains.
+    pub fn with_lookalike_detector(mut self, detector: LookalikeDetector) -> Self {
+        self.lookalike = detector.into();
+        self
+    }
+
+    /// Sets the forward-confirmed reverse DNS settings.
+    pub fn with_iprev_policy(mut self, iprev: IprevPolicy) -> Self {
+        self.iprev = iprev;
+        self
+    }
+
+    /// Overrides the settings of a domain.
+    pub fn with_override(mut self, domain: impl AsRef<str>, settings: DomainOverride) -> Self {
+        self.overrides
+            .insert(Domain::normalize(domain.as_ref()), settings);
+        self
+    }
+
+    /// Adds the settings of a tenant, replacing any previous ones.
+    pub fn with_tenant(mut self, tenant: impl Into<String>, settings: TenantSettings) -> Self {
+        self.tenants.insert(tenant.into(), settings);
+        self
+    }
+
+    /// Returns the settings seen by a tenant: the global settings with the
+    /// trusted sealers and overrides of the tenant added, and without the
+    /// settings of any other tenant.
+    pub fn for_tenant(&self, tenant: &str) -> Config {
+        let mut config = Config {
+            tenants: HashMap::new(),
+            ..self.clone()
+        };
+        if let Some(settings) = self.tenants.get(tenant) {
+            for sealer in &settings.trusted_sealers {
+                if !config.trusted_sealers.contains(sealer) {
+                    config.trusted_sealers.push(sealer.clone());
+                }
+            }
+            config.overrides.extend(
+                settings
+                    .overrides
+                    .iter()
+                    .map(|(domain, settings)| (domain.clone(), *settings)),
+            );
+        }
+        config
+    }
+
+    /// Returns the crypto policy for a signing domain.
+    pub fn crypto_policy(&self, domain: &str) -> CryptoPolicy {
+        self.domain_override(domain)
+            .and_then(|o| o.crypto)
+            .unwrap_or(self.crypto)
+    }
+
+    /// Returns the verification policy for a signing domain.
+    pub fn verification_policy(&self, domain: &str) -> VerificationPolicy {
+        self.domain_override(domain)
+            .and_then(|o| o.verification)
+            .unwrap_or(self.verification)
+    }
+
+    /// Returns the verification resource limits.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Returns the DMARC policy version for an RFC5322.From domain.
+    pub fn dmarc_version(&self, domain: &str) -> PolicyVersion {
+        self.domain_override(domain)
+            .and_then(|o| o.dmarc_version)
+            .unwrap_or(self.dmarc_version)
+    }
+
+    /// Returns the enabled DKIM compatibility shims.
+    pub fn compat_shims(&self) -> &[CompatShim] {
+        &self.compat_shims
+    }
+
+    /// Returns `true` if body hash diagnostics are enabled.
+    pub fn body_hash_diagnostics(&self) -> bool {
+        self.body_hash_diagnostics
+    }
+
+    /// Returns `true` if SPF evaluations are traced.
+    pub fn spf_trace(&self) -> bool {
+        self.spf_trace
+    }
+
+    /// Returns `true` if ARC chains are validated in strict mode.
+    pub fn arc_strict(&self) -> bool {
+        self.arc_strict
+    }
+
+    /// Returns `true` if deprecated record types are looked up.
+    pub fn legacy_record_types(&self) -> bool {
+        self.legacy_record_types
+    }
+
+    /// Returns the forward-confirmed reverse DNS settings.
+    pub fn iprev_policy(&self) -> &IprevPolicy {
+        &self.iprev
+    }
+
+    pub fn lookalike_detector(&self) -> Option<&LookalikeDetector> {
+        self.lookalike.as_ref()
+    }
+
+    /// Returns `true` if ARC seals added by the domain are trusted.
+    pub fn is_trusted_sealer(&self, domain: &str) -> bool {
+        self.trusted_sealers.contains(&Domain::normalize(domain))
+    }
+
+    /// Returns `true` if the ARC chain passed and was last sealed by a trusted domain.
+    pub fn is_trusted_arc(&self, arc: &ArcOutput<'_>) -> bool {
+        arc.result == DkimResult::Pass
+            && arc
+                .set
+                .last()
+                .is_some_and(|set| self.is_trusted_sealer(&set.seal.header.d))
+    }
+
+    fn domain_override(&self, domain: &str) -> Option<&DomainOverride> {
+        if self.overrides.is_empty() {
+            None
+        } else {
+            self.overrides.get(&Domain::normalize(domain))
+        }
+    }
+}
+
+impl CryptoPolicy {
+    /// Sets whether RSA-SHA1 signatures are accepted.
+    pub fn with_rsa_sha1(mut self, allow: bool) -> Self {
+        self.allow_rsa_sha1 = allow;
+        self
+    }
+
+    pub fn allows_rsa_sha1(&self) -> bool {
+        self.allow_rsa_sha1
+    }
+}
+
+impl Default for CryptoPolicy {
+    fn default() -> Self {
+        CryptoPolicy {
+            allow_rsa_sha1: true,
+        }
+    }
+}
+
+impl VerificationPolicy {
+    /// Sets the minimum RSA key size in bits, 1024 by default as required by
+    /// RFC 8301. RFC 8301 recommends signers use at least 2048 bits.
+    pub fn with_min_rsa_bits(mut self, bits: usize) -> Self {
+        self.min_rsa_bits = bits;
+        self
+    }
+
+    /// Sets how signatures with an `l=` tag are handled.
+    pub fn with_body_length(mut self, body_length: BodyLengthPolicy) -> Self {
+        self.body_length = body_length;
+        self
+    }
+
+    /// Treats signatures with a `t=` timestamp older than `max_age` as expired.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age.into();
+        self
+    }
+
+    /// Accepts signatures up to `grace` after their `x=` expiration.
+    pub fn with_expiration_grace(mut self, grace: Duration) -> Self {
+        self.expiration_grace = grace;
+        self
+    }
+
+    pub fn min_rsa_bits(&self) -> usize {
+        self.min_rsa_bits
+    }
+
+    pub fn body_length(&self) -> BodyLengthPolicy {
+        self.body_length
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age
+    }
+
+    pub fn expiration_grace(&self) -> Duration {
+        self.expiration_grace
+    }
+
+    pub(crate) fn body_length_decision(
+        &self,
+        length: u64,
+        body_len: usize,
+    ) -> Option<PolicyDecision> {
+        match self.body_length {
+            BodyLengthPolicy::Reject if length != 0 => Some(PolicyDecision::BodyLengthRejected),
+            BodyLengthPolicy::FullBody if length != 0 && length < body_len as u64 => {
+                Some(PolicyDecision::BodyLengthExceeded)
+            }
+            _ => None,
+        }
+    }
+
+    /// RSA signatures are as long as the key modulus, so the key size of a
+    /// verified signature is known without decoding the public key.
+    pub(crate) fn rsa_key_decision(
+        &self,
+        algorithm: Algorithm,
+        signature: &[u8],
+    ) -> Option<PolicyDecision> {
+        let bits = signature.len() * 8;
+        (matches!(algorithm, Algorithm::RsaSha1 | Algorithm::RsaSha256) && bits < self.min_rsa_bits)
+            .then_some(PolicyDecision::RsaKeyTooShort(bits))
+    }
+
+    pub(crate) fn rsa_key_error(&self) -> Error {
+        Error::CryptoError(format!("RSA key shorter than {} bits", self.min_rsa_bits))
+    }
+}
+
+impl Default for VerificationPolicy {
+    fn default() -> Self {
+        VerificationPolicy {
+            min_rsa_bits: 1024,
+            body_length: BodyLengthPolicy::Accept,
+            max_age: None,
+            expiration_grace: Duration::ZERO,
+        }
+    }
+}
+
+impl Limits {
+    /// Sets the maximum number of DNS lookups per SPF evaluation.
+    pub fn with_spf_lookups(mut self, lookups: u32) -> Self {
+        self.spf_lookups = lookups;
+        self
+    }
+
+    /// Sets the maximum number of MX records evaluated by an SPF `mx` mechanism.
+    pub fn with_spf_mx_lookups(mut self, lookups: usize) -> Self {
+        self.spf_mx_lookups = lookups;
+        self
+    }
+
+    /// Sets the maximum number of names evaluated by an SPF `ptr` mechanism,
+    /// the remaining names are ignored.
+    pub fn with_spf_ptr_lookups(mut self, lookups: usize) -> Self {
+        self.spf_ptr_lookups = lookups;
+        self
+    }
+
+    /// Sets the maximum number of SPF lookups returning no records. Unlimited by
+    /// default, RFC 7208 recommends a limit of 2.
+    pub fn with_spf_void_lookups(mut self, lookups: u32) -> Self {
+        self.spf_void_lookups = lookups;
+        self
+    }
+
+    /// Sets the maximum duration of an SPF evaluation.
+    pub fn with_spf_timeout(mut self, timeout: Duration) -> Self {
+        self.spf_timeout = timeout;
+        self
+    }
+
+    /// Sets the SPF result returned when an evaluation exceeds a limit,
+    /// `PermError` by default.
+    pub fn with_spf_limit_result(mut self, result: SpfResult) -> Self {
+        self.spf_limit_result = result;
+        self
+    }
+
+    /// Sets the maximum number of ARC sets in a chain.
+    pub fn with_arc_headers(mut self, headers: usize) -> Self {
+        self.arc_headers = headers;
+        self
+    }
+
+    /// Sets the maximum number of DKIM signatures verified per message, the
+    /// remaining signatures are reported as neutral without looking up their keys.
+    pub fn with_dkim_signatures(mut self, signatures: usize) -> Self {
+        self.dkim_signatures = signatures;
+        self
+    }
+
+    pub fn spf_lookups(&self) -> u32 {
+        self.spf_lookups
+    }
+
+    pub fn spf_mx_lookups(&self) -> usize {
+        self.spf_mx_lookups
+    }
+
+    pub fn spf_ptr_lookups(&self) -> usize {
+        self.spf_ptr_lookups
+    }
+
+    pub fn spf_void_lookups(&self) -> u32 {
+        self.spf_void_lookups
+    }
+
+    pub fn spf_timeout(&self) -> Duration {
+        self.spf_timeout
+    }
+
+    pub fn spf_limit_result(&self) -> SpfResult {
+        self.spf_limit_result
+    }
+
+    pub fn arc_headers(&self) -> usize {
+        self.arc_headers
+    }
+
+    pub fn dkim_signatures(&self) -> usize {
+        self.dkim_signatures
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            spf_lookups: 10,
+            spf_mx_lookups: 10,
+            spf_ptr_lookups: 10,
+            spf_void_lookups: u32::MAX,
+            spf_timeout: Duration::from_secs(20),
+            spf_limit_result: SpfResult::PermError,
+            arc_headers: 50,
+            dkim_signatures: 10,
+        }
+    }
+}
+
+impl IprevPolicy {
+    /// Sets the maximum number of PTR names forward-confirmed, the remaining
+    /// names are ignored.
+    pub fn with_max_ptr(mut self, max_ptr: usize) -> Self {
+        self.max_ptr = max_ptr;
+        self
+    }
+
+    /// Requires every PTR name evaluated to forward-confirm, instead of any.
+    pub fn with_require_all(mut self, require_all: bool) -> Self {
+        self.require_all = require_all;
+        self
+    }
+
+    /// Sets the maximum duration of each PTR and address lookup, lookups
+    /// exceeding it return `TempError`.
+    pub fn with_lookup_timeout(mut self, timeout: Duration) -> Self {
+        self.lookup_timeout = timeout.into();
+        self
+    }
+
+    /// Caches `Fail` and `PermError` results per address for `ttl`.
+    pub fn with_negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl.into();
+        self
+    }
+
+    pub fn max_ptr(&self) -> usize {
+        self.max_ptr
+    }
+
+    pub fn require_all(&self) -> bool {
+        self.require_all
+    }
+
+    pub fn lookup_timeout(&self) -> Option<Duration> {
+        self.lookup_timeout
+    }
+
+    pub fn negative_ttl(&self) -> Option<Duration> {
+        self.negative_ttl
+    }
+}
+
+impl Default for IprevPolicy {
+    fn default() -> Self {
+        IprevPolicy {
+            max_ptr: 2,
+            require_all: false,
+            lookup_timeout: None,
+            negative_ttl: None,
+        }
+    }
+}
+
+impl DomainOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the crypto policy of the domain.
+    pub fn with_crypto_policy(mut self, crypto: CryptoPolicy) -> Self {
+        self.crypto = crypto.into();
+        self
+    }
+
+    /// Overrides the verification policy of the domain.
+    pub fn with_verification_policy(mut self, verification: VerificationPolicy) -> Self {
+        self.verification = verification.into();
+        self
+    }
+
+    /// Overrides the DMARC policy version of the domain.
+    pub fn with_dmarc_version(mut self, version: PolicyVersion) -> Self {
+        self.dmarc_version = version.into();
+        self
+    }
+}
+
+impl TenantSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the ARC seals added by a domain for this tenant only.
+    pub fn with_trusted_sealer(mut self, domain: impl AsRef<str>) -> Self {
+        self.trusted_sealers
+            .push(Domain::normalize(domain.as_ref()));
+        self
+    }
+
+    /// Overrides the settings of a domain for this tenant only, taking
+    /// precedence over the global override of the domain.
+    pub fn with_override(mut self, domain: impl AsRef<str>, settings: DomainOverride) -> Self {
+        self.overrides
+            .insert(Domain::normalize(domain.as_ref()), settings);
+        self
+    }
+}
+
+impl Resolver {
+    /// Sets the verification settings.
+    pub fn with_config(self, config: Config) -> Self {
+        self.set_config(config);
+        self
+    }
+
+    /// Atomically replaces the verification settings. Verifications already
+    /// in progress complete using the previous settings. The settings are
+    /// shared by a resolver and its tenant resolvers.
+    pub fn set_config(&self, config: Config) {
+        self.config.store(Arc::new(config));
+    }
+
+    /// Returns a snapshot of the current verification settings, as seen by the
+    /// tenant of the resolver if any.
+    pub fn config(&self) -> Arc<Config> {
+        let config = self.config.load_full();
+        match &self.tenant {
+            Some(tenant) => tenant.config(config),
+            None => config,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::parse::TxtRecordParser, dmarc::PolicyVersion, spf::Spf, Resolver, SpfResult,
+    };
+
+    use super::{Config, CryptoPolicy, DomainOverride, Limits};
+
+    #[tokio::test]
+    async fn config_reload() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip = "192.0.2.1".parse().unwrap();
+
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 include:a.example.org include:b.example.org -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "a.example.org.",
+            Spf::parse(b"v=spf1 ip4:198.51.100.1 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "b.example.org.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.1 -all"),
+            valid_until,
+        );
+        assert_eq!(
+            resolver
+                .verify_spf_sender(ip, "mx.example.org", "example.org", "a@example.org")
+                .await
+                .result(),
+            SpfResult::Pass
+        );
+
+        // Reloading the settings keeps cached records
+        let snapshot = resolver.config();
+        resolver.set_config(
+            Config::new()
+                .with_limits(Limits::default().with_spf_lookups(2))
+                .with_dmarc_version(PolicyVersion::DmarcBis)
+                .with_trusted_sealer("Sealer.Example")
+                .with_override(
+                    "legacy.example",
+                    DomainOverride::new()
+                        .with_crypto_policy(CryptoPolicy::default().with_rsa_sha1(true))
+                        .with_dmarc_version(PolicyVersion::Rfc7489),
+                )
+                .with_crypto_policy(CryptoPolicy::default().with_rsa_sha1(false)),
+        );
+        assert_eq!(snapshot.limits().spf_lookups(), 10);
+        assert_eq!(
+            resolver
+                .verify_spf_sender(ip, "mx.example.org", "example.org", "a@example.org")
+                .await
+                .result(),
+            SpfResult::PermError
+        );
+
+        let config = resolver.config();
+        assert!(config.is_trusted_sealer("sealer.example"));
+        assert!(!config.crypto_policy("example.org").allows_rsa_sha1());
+        assert!(config.crypto_policy("Legacy.Example").allows_rsa_sha1());
+        assert_eq!(config.dmarc_version("example.org"), PolicyVersion::DmarcBis);
+        assert_eq!(
+            config.dmarc_version("legacy.example"),
+            PolicyVersion::Rfc7489
+        );
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::ops::Range;
+
+use crate::{ArcOutput, AuthenticatedMessage, DkimOutput, DkimResult};
+
+/// Parts of a message covered by a DKIM or ARC-Message-Signature, so that
+/// tools rewriting messages after verification (banner inserters, disclaimer
+/// systems) know what they can modify without invalidating it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureCoverage {
+    domain: String,
+    selector: String,
+    signature_header: Option<usize>,
+    headers: Vec<usize>,
+    oversigned: Vec<String>,
+    body: Range<usize>,
+    body_length: bool,
+}
+
+impl SignatureCoverage {
+    /// Signing domain (`d=`).
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Selector (`s=`).
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    /// Index of the signature header in `AuthenticatedMessage::raw_parsed_headers`.
+    pub fn signature_header(&self) -> Option<usize> {
+        self.signature_header
+    }
+
+    /// Indexes of the header instances listed in `h=`, in signing order, in
+    /// `AuthenticatedMessage::raw_parsed_headers`.
+    pub fn headers(&self) -> &[usize] {
+        &self.headers
+    }
+
+    /// Names listed in `h=` without a matching header instance. Adding a
+    /// header with one of these names invalidates the signature.
+    pub fn oversigned_headers(&self) -> &[String] {
+        &self.oversigned
+    }
+
+    /// Byte range of the raw message covered by the body hash.
+    pub fn body(&self) -> Range<usize> {
+        self.body.clone()
+    }
+
+    /// Returns `true` if the header instance at `index` is covered, including
+    /// the signature header itself.
+    pub fn covers_header(&self, index: usize) -> bool {
+        self.signature_header == Some(index) || self.headers.contains(&index)
+    }
+
+    /// Returns `true` if a new header named `name` can be prepended to the
+    /// message without invalidating the signature.
+    pub fn allows_new_header(&self, name: &str) -> bool {
+        !self.oversigned.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Returns `true` if content can be appended to the body, which is only
+    /// the case for signatures with a body length (`l=`) tag.
+    pub fn allows_body_append(&self) -> bool {
+        self.body_length
+    }
+}
+
+impl<'x> AuthenticatedMessage<'x> {
+    /// Returns the parts of the message covered by a DKIM signature, or `None`
+    /// if the output has no signature.
+    pub fn dkim_coverage(&self, output: &DkimOutput<'_>) -> Option<SignatureCoverage> {
+        let signature = output.signature()?;
+        let name = self
+            .dkim_headers
+            .iter()
+            .find_map(|header| match &header.header {
+                Ok(s) if std::ptr::eq(s, signature) => Some(header.name),
+                _ => None,
+            });
+        Some(self.coverage(&signature.d, &signature.s, name, &signature.h, signature.l))
+    }
+
+    /// Returns the parts of the message covered by each ARC-Message-Signature
+    /// of a chain, from the first instance to the last.
+    pub fn arc_coverage(&self, output: &ArcOutput<'_>) -> Vec<SignatureCoverage> {
+        output
+            .sets()
+            .iter()
+            .map(|set| {
+                let signature = set.signature.header;
+                self.coverage(
+                    &signature.d,
+                    &signature.s,
+                    Some(set.signature.name),
+                    &signature.h,
+                    signature.l,
+                )
+            })
+            .collect()
+    }
+
+    /// Returns the coverage of the passing DKIM signatures and, if the chain
+    /// passed, of the ARC-Message-Signatures. Content outside all of them can
+    /// be modified without invalidating any passing signature.
+    pub fn verified_coverage(
+        &self,
+        dkim: &[DkimOutput<'_>],
+        arc: Option<&ArcOutput<'_>>,
+    ) -> Vec<SignatureCoverage> {
+        let mut coverage = dkim
+            .iter()
+            .filter(|output| output.result() == &DkimResult::Pass)
+            .filter_map(|output| self.dkim_coverage(output))
+            .collect::<Vec<_>>();
+        if let Some(arc) = arc.filter(|arc| arc.result() == &DkimResult::Pass) {
+            coverage.extend(self.arc_coverage(arc));
+        }
+        coverage
+    }
+
+    fn coverage(
+        &self,
+        domain: &str,
+        selector: &str,
+        signature_name: Option<&[u8]>,
+        h: &[String],
+        l: u64,
+    ) -> SignatureCoverage {
+        // Repeated names select instances from the bottom of the message up,
+        // as in `SignedHeaders`
+        let mut headers = Vec::with_capacity(h.len());
+        let mut oversigned = Vec::new();
+        for (pos, name) in h.iter().enumerate() {
+            let occurrence = h[..pos]
+                .iter()
+                .filter(|h| h.eq_ignore_ascii_case(name))
+                .count();
+            match self
+                .headers
+                .iter()
+                .enumerate()
+                .rev()
+                .filter(|(_, (mh, _))| mh.eq_ignore_ascii_case(name.as_bytes()))
+                .nth(occurrence)
+            {
+                Some((index, _)) => headers.push(index),
+                None => oversigned.push(name.clone()),
+            }
+        }
+
+        let body_len = self.raw_message.len().saturating_sub(self.body_offset);
+        let hashed_len = if l == 0 {
+            body_len
+        } else {
+            std::cmp::min(l, body_len as u64) as usize
+        };
+
+        SignatureCoverage {
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            signature_header: signature_name.and_then(|name| {
+                self.headers
+                    .iter()
+                    .position(|(n, _)| std::ptr::eq(n.as_ptr(), name.as_ptr()))
+            }),
+            headers,
+            oversigned,
+            body: self.body_offset..self.body_offset + hashed_len,
+            body_length: l != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{AuthenticatedMessage, DkimOutput};
+
+    #[test]
+    fn signature_coverage() {
+        let raw_message = concat!(
+            "Received: from mx.example.org by mail.example.com\r\n",
+            "DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=sel;\r\n",
+            "\th=From:Subject:Subject:Subject:Date; l=6; bh=YWJj; b=YWJj\r\n",
+            "Subject: First\r\n",
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Second\r\n",
+            "\r\n",
+            "Hello world.\r\n"
+        );
+        let message = AuthenticatedMessage::parse_with_opts(raw_message.as_bytes(), false).unwrap();
+        let signature = message.dkim_headers[0].header.as_ref().unwrap();
+        let coverage = message
+            .dkim_coverage(&DkimOutput::pass().with_signature(signature))
+            .unwrap();
+        assert_eq!(coverage.domain(), "example.com");
+        assert_eq!(coverage.selector(), "sel");
+        assert_eq!(coverage.signature_header(), Some(1));
+        assert_eq!(coverage.headers(), &[3, 5, 2]);
+        assert_eq!(coverage.oversigned_headers(), &["Subject", "Date"]);
+        assert!(coverage.covers_header(1));
+        assert!(!coverage.covers_header(0));
+        assert!(!coverage.covers_header(4));
+        assert!(coverage.allows_new_header("X-Disclaimer"));
+        assert!(!coverage.allows_new_header("date"));
+        assert_eq!(&raw_message[coverage.body()], "Hello ");
+        assert!(coverage.allows_body_append());
+
+        // Only passing signatures are reported
+        assert_eq!(
+            message.verified_coverage(
+                &[
+                    DkimOutput::pass().with_signature(signature),
+                    DkimOutput::fail(crate::Error::FailedVerification).with_signature(signature)
+                ],
+                None
+            ),
+            vec![coverage]
+        );
+    }
+}
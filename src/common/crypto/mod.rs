@@ -1,10 +1,14 @@
 #[cfg(feature = "sha1")]
 use sha1::{digest::Output, Digest};
 
-use crate::{dkim::Canonicalization, Result};
+use mail_parser::decoders::base64::base64_decode;
+
+use crate::{dkim::Canonicalization, Error, Result};
 
 use super::headers::{Writable, Writer};
 
+pub mod usage;
+
 #[cfg(feature = "rust-crypto")]
 mod rust_crypto;
 #[cfg(feature = "rust-crypto")]
@@ -41,6 +45,14 @@ pub trait VerifyingKey {
         canonicalication: Canonicalization,
         algorithm: Algorithm,
     ) -> Result<()>;
+
+    /// Returns the algorithm and the encoded public key, if it can be exported.
+    /// RSA keys are encoded as a PKCS#1 `RSAPublicKey` DER, the same as
+    /// `RsaKey::public_key`, whether they were published as a PKCS#1 key or a
+    /// `SubjectPublicKeyInfo`. Ed25519 keys are the raw 32 byte key.
+    fn public_key(&self) -> Option<(Algorithm, Vec<u8>)> {
+        None
+    }
 }
 
 pub(crate) enum VerifyingKeyType {
@@ -83,6 +95,7 @@ pub struct Sha1;
 pub struct Sha256;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u64)]
 pub enum HashAlgorithm {
     Sha1 = R_HASH_SHA1,
@@ -145,6 +158,7 @@ impl AsRef<[u8]> for HashOutput {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Algorithm {
     RsaSha1,
     #[default]
@@ -152,5 +166,174 @@ pub enum Algorithm {
     Ed25519Sha256,
 }
 
+/// Private key container found in a PEM document.
+pub(crate) enum PrivateKeyDer {
+    Pkcs1(Vec<u8>),
+    Pkcs8(Vec<u8>),
+    #[cfg_attr(not(feature = "pkcs8-encryption"), allow(dead_code))]
+    EncryptedPkcs8(Vec<u8>),
+}
+
+impl PrivateKeyDer {
+    /// Decodes the first private key found in a PEM document.
+    pub(crate) fn from_pem(pem: &str) -> Result<Self> {
+        let (label, rest) = pem
+            .split_once("-----BEGIN ")
+            .and_then(|(_, rest)| rest.split_once("-----"))
+            .ok_or_else(|| Error::CryptoError("No private key found in PEM".to_string()))?;
+        let body = rest
+            .split_once(&format!("-----END {label}-----"))
+            .map(|(body, _)| body)
+            .ok_or_else(|| Error::CryptoError("Truncated PEM document".to_string()))?;
+        if body.contains(':') {
+            return Err(Error::CryptoError(
+                "Legacy encrypted PEM keys are not supported".to_string(),
+            ));
+        }
+        let der = base64_decode(
+            &body
+                .bytes()
+                .filter(|ch| !ch.is_ascii_whitespace())
+                .collect::<Vec<_>>(),
+        )
+        .ok_or(Error::Base64)?;
+
+        match label {
+            "RSA PRIVATE KEY" => Ok(PrivateKeyDer::Pkcs1(der)),
+            "PRIVATE KEY" => Ok(PrivateKeyDer::Pkcs8(der)),
+            "ENCRYPTED PRIVATE KEY" => Ok(PrivateKeyDer::EncryptedPkcs8(der)),
+            _ => Err(Error::CryptoError(format!(
+                "Unsupported PEM label {label:?}"
+            ))),
+        }
+    }
+
+    pub(crate) fn pkcs8(self) -> Result<Vec<u8>> {
+        match self {
+            PrivateKeyDer::Pkcs8(der) => Ok(der),
+            _ => Err(Error::CryptoError("No PKCS8 key found in PEM".to_string())),
+        }
+    }
+
+    #[cfg(feature = "pkcs8-encryption")]
+    pub(crate) fn encrypted_pkcs8(self) -> Result<Vec<u8>> {
+        match self {
+            PrivateKeyDer::EncryptedPkcs8(der) => Ok(der),
+            _ => Err(Error::CryptoError(
+                "No encrypted PKCS8 key found in PEM".to_string(),
+            )),
+        }
+    }
+}
+
+/// Decrypts an encrypted PKCS8 DER document.
+#[cfg(feature = "pkcs8-encryption")]
+pub(crate) fn decrypt_pkcs8(der: &[u8], password: &[u8]) -> Result<pkcs8::SecretDocument> {
+    pkcs8::EncryptedPrivateKeyInfo::try_from(der)
+        .map_err(|err| Error::CryptoError(err.to_string()))?
+        .decrypt(password)
+        .map_err(|err| Error::CryptoError(err.to_string()))
+}
+
 pub(crate) const R_HASH_SHA1: u64 = 0x01;
 pub(crate) const R_HASH_SHA256: u64 = 0x02;
+
+#[cfg(test)]
+mod test {
+    use mail_parser::decoders::base64::base64_decode;
+
+    use crate::common::test_key::ed25519_public_key;
+
+    use super::{Algorithm, Ed25519Key, RsaKey, Sha256, VerifyingKeyType};
+
+    const RSA_PKCS1_PEM: &str = include_str!("../../../resources/rsa-private.pem");
+    const RSA_PKCS8_PEM: &str = include_str!("../../../resources/keys/rsa-pkcs8.pem");
+    const ED25519_PKCS8_PEM: &str = include_str!("../../../resources/keys/ed25519-pkcs8.pem");
+    const RSA_SPKI: &str = concat!(
+        "MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ",
+        "8AMIIBCgKCAQEAv9XYXG3uK95115mB4nJ37nGeNe2CrARm",
+        "1agrbcnSk5oIaEfMZLUR/X8gPzoiNHZcfMZEVR6bAytxUh",
+        "c5EvZIZrjSuEEeny+fFd/cTvcm3cOUUbIaUmSACj0dL2/K",
+        "wW0LyUaza9z9zor7I5XdIl1M53qVd5GI62XBB76FH+Q0bW",
+        "PZNkT4NclzTLspD/MTpNCCPhySM4Kdg5CuDczTH4aNzyS0",
+        "TqgXdtw6A4Sdsp97VXT9fkPW9rso3lrkpsl/9EQ1mR/DWK",
+        "6PBmRfIuSFuqnLKY6v/z2hXHxF7IoojfZLa2kZr9Aed4l9",
+        "WheQOTA19k5r2BmlRw/W9CrgCBo0Sdj+KQIDAQAB",
+    );
+
+    #[test]
+    fn verifying_key_export() {
+        // Published RSA keys are exported as PKCS#1 by every backend
+        let rsa_public_key = RsaKey::<Sha256>::from_any_pem(RSA_PKCS1_PEM)
+            .unwrap()
+            .public_key();
+        let spki = base64_decode(RSA_SPKI.as_bytes()).unwrap();
+        assert_ne!(spki, rsa_public_key);
+        for bytes in [&spki, &rsa_public_key] {
+            assert_eq!(
+                VerifyingKeyType::Rsa
+                    .verifying_key(bytes)
+                    .unwrap()
+                    .public_key(),
+                Some((Algorithm::RsaSha256, rsa_public_key.clone()))
+            );
+        }
+
+        let ed_public_key = ed25519_public_key();
+        assert_eq!(
+            VerifyingKeyType::Ed25519
+                .verifying_key(&ed_public_key)
+                .unwrap()
+                .public_key(),
+            Some((Algorithm::Ed25519Sha256, ed_public_key))
+        );
+    }
+
+    #[test]
+    fn private_key_pkcs8() {
+        let rsa_public_key = RsaKey::<Sha256>::from_any_pem(RSA_PKCS1_PEM)
+            .unwrap()
+            .public_key();
+        for key in [
+            RsaKey::<Sha256>::from_pkcs8_pem(RSA_PKCS8_PEM),
+            RsaKey::<Sha256>::from_any_pem(RSA_PKCS8_PEM),
+        ] {
+            assert_eq!(key.unwrap().public_key(), rsa_public_key);
+        }
+        assert!(RsaKey::<Sha256>::from_pkcs8_pem(RSA_PKCS1_PEM).is_err());
+
+        let ed_public_key = ed25519_public_key();
+        for key in [
+            Ed25519Key::from_pkcs8_pem(ED25519_PKCS8_PEM),
+            Ed25519Key::from_any_pem(ED25519_PKCS8_PEM),
+        ] {
+            assert_eq!(key.unwrap().public_key(), ed_public_key);
+        }
+        assert!(Ed25519Key::from_any_pem(RSA_PKCS1_PEM).is_err());
+
+        #[cfg(feature = "pkcs8-encryption")]
+        {
+            const RSA_ENCRYPTED_PEM: &str =
+                include_str!("../../../resources/keys/rsa-pkcs8-encrypted.pem");
+            const ED25519_ENCRYPTED_PEM: &str =
+                include_str!("../../../resources/keys/ed25519-pkcs8-encrypted.pem");
+
+            assert_eq!(
+                RsaKey::<Sha256>::from_encrypted_pkcs8_pem(RSA_ENCRYPTED_PEM, "secret")
+                    .unwrap()
+                    .public_key(),
+                rsa_public_key
+            );
+            assert_eq!(
+                Ed25519Key::from_encrypted_pkcs8_pem(ED25519_ENCRYPTED_PEM, "secret")
+                    .unwrap()
+                    .public_key(),
+                ed_public_key
+            );
+            assert!(
+                RsaKey::<Sha256>::from_encrypted_pkcs8_pem(RSA_ENCRYPTED_PEM, "wrong").is_err()
+            );
+            assert!(RsaKey::<Sha256>::from_any_pem(RSA_ENCRYPTED_PEM).is_err());
+        }
+    }
+}
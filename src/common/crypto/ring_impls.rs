@@ -14,7 +14,10 @@ use crate::{
     Error, Result,
 };
 
-use super::{Algorithm, HashContext, HashImpl, HashOutput, Sha1, Sha256, SigningKey, VerifyingKey};
+use super::{
+    Algorithm, HashContext, HashImpl, HashOutput, PrivateKeyDer, Sha1, Sha256, SigningKey,
+    VerifyingKey,
+};
 
 #[derive(Debug)]
 pub struct RsaKey<T> {
@@ -69,6 +72,32 @@ impl<T: HashImpl> RsaKey<T> {
         })
     }
 
+    /// Creates a new RSA private key from an encrypted PKCS8 PEM string.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_pem(pkcs8_pem: &str, password: impl AsRef<[u8]>) -> Result<Self> {
+        Self::from_encrypted_pkcs8_der(
+            &PrivateKeyDer::from_pem(pkcs8_pem)?.encrypted_pkcs8()?,
+            password,
+        )
+    }
+
+    /// Creates a new RSA private key from encrypted PKCS8 DER-encoded bytes.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_der(pkcs8_der: &[u8], password: impl AsRef<[u8]>) -> Result<Self> {
+        Self::from_pkcs8_der(super::decrypt_pkcs8(pkcs8_der, password.as_ref())?.as_bytes())
+    }
+
+    /// Creates a new RSA private key from a PKCS1 or PKCS8 PEM string.
+    pub fn from_any_pem(pem: &str) -> Result<Self> {
+        match PrivateKeyDer::from_pem(pem)? {
+            PrivateKeyDer::Pkcs1(der) => Self::from_der(&der),
+            PrivateKeyDer::Pkcs8(der) => Self::from_pkcs8_der(&der),
+            PrivateKeyDer::EncryptedPkcs8(_) => Err(Error::CryptoError(
+                "Encrypted PKCS8 key requires a password".to_string(),
+            )),
+        }
+    }
+
     /// Returns the public key of the RSA key pair.
     pub fn public_key(&self) -> Vec<u8> {
         self.inner.public().as_ref().to_vec()
@@ -120,6 +149,41 @@ impl Ed25519Key {
         })
     }
 
+    /// Creates a new Ed25519 private key from a PKCS8 v1 or v2 PEM string.
+    pub fn from_pkcs8_pem(pkcs8_pem: &str) -> Result<Self> {
+        Self::from_pkcs8_maybe_unchecked_der(&PrivateKeyDer::from_pem(pkcs8_pem)?.pkcs8()?)
+    }
+
+    /// Creates a new Ed25519 private key from an encrypted PKCS8 PEM string.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_pem(pkcs8_pem: &str, password: impl AsRef<[u8]>) -> Result<Self> {
+        Self::from_encrypted_pkcs8_der(
+            &PrivateKeyDer::from_pem(pkcs8_pem)?.encrypted_pkcs8()?,
+            password,
+        )
+    }
+
+    /// Creates a new Ed25519 private key from encrypted PKCS8 DER-encoded bytes.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_der(pkcs8_der: &[u8], password: impl AsRef<[u8]>) -> Result<Self> {
+        Self::from_pkcs8_maybe_unchecked_der(
+            super::decrypt_pkcs8(pkcs8_der, password.as_ref())?.as_bytes(),
+        )
+    }
+
+    /// Creates a new Ed25519 private key from a PKCS8 PEM string.
+    pub fn from_any_pem(pem: &str) -> Result<Self> {
+        match PrivateKeyDer::from_pem(pem)? {
+            PrivateKeyDer::Pkcs8(der) => Self::from_pkcs8_maybe_unchecked_der(&der),
+            PrivateKeyDer::EncryptedPkcs8(_) => Err(Error::CryptoError(
+                "Encrypted PKCS8 key requires a password".to_string(),
+            )),
+            PrivateKeyDer::Pkcs1(_) => Err(Error::CryptoError(
+                "PKCS1 PEM does not contain an Ed25519 key".to_string(),
+            )),
+        }
+    }
+
     pub fn from_seed_and_public_key(seed: &[u8], public_key: &[u8]) -> Result<Self> {
         Ok(Self {
             inner: Ed25519KeyPair::from_seed_and_public_key(seed, public_key)
@@ -241,6 +305,10 @@ impl VerifyingKey for RsaPublicKey {
             Algorithm::Ed25519Sha256 => Err(Error::IncompatibleAlgorithms),
         }
     }
+
+    fn public_key(&self) -> Option<(Algorithm, Vec<u8>)> {
+        Some((Algorithm::RsaSha256, self.sha2.as_ref().to_vec()))
+    }
 }
 
 pub(crate) struct Ed25519PublicKey {
@@ -275,6 +343,10 @@ impl VerifyingKey for Ed25519PublicKey {
             .verify(hasher.complete().as_ref(), signature)
             .map_err(|err| Error::CryptoError(err.to_string()))
     }
+
+    fn public_key(&self) -> Option<(Algorithm, Vec<u8>)> {
+        Some((Algorithm::Ed25519Sha256, self.inner.as_ref().to_vec()))
+    }
 }
 
 impl HashImpl for Sha1 {
@@ -2,7 +2,7 @@ use std::array::TryFromSliceError;
 use std::marker::PhantomData;
 
 use ed25519_dalek::Signer;
-use rsa::{pkcs1::DecodeRsaPrivateKey, Pkcs1v15Sign, RsaPrivateKey};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, Pkcs1v15Sign, RsaPrivateKey};
 use sha2::digest::Digest;
 
 use crate::{
@@ -11,7 +11,10 @@ use crate::{
     Error, Result,
 };
 
-use super::{Algorithm, HashContext, HashImpl, HashOutput, Sha1, Sha256, SigningKey, VerifyingKey};
+use super::{
+    Algorithm, HashContext, HashImpl, HashOutput, PrivateKeyDer, Sha1, Sha256, SigningKey,
+    VerifyingKey,
+};
 
 #[derive(Debug)]
 pub struct RsaKey<T> {
@@ -41,6 +44,61 @@ impl<T: HashImpl> RsaKey<T> {
             padding: PhantomData,
         })
     }
+
+    /// Creates a new RSA private key from a PKCS8 PEM string.
+    pub fn from_pkcs8_pem(private_key_pem: &str) -> Result<Self> {
+        Self::from_pkcs8_der(&PrivateKeyDer::from_pem(private_key_pem)?.pkcs8()?)
+    }
+
+    /// Creates a new RSA private key from PKCS8 DER-encoded bytes.
+    pub fn from_pkcs8_der(private_key_bytes: &[u8]) -> Result<Self> {
+        let inner = RsaPrivateKey::from_pkcs8_der(private_key_bytes)
+            .map_err(|err| Error::CryptoError(err.to_string()))?;
+
+        Ok(RsaKey {
+            inner,
+            padding: PhantomData,
+        })
+    }
+
+    /// Creates a new RSA private key from an encrypted PKCS8 PEM string.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_pem(
+        private_key_pem: &str,
+        password: impl AsRef<[u8]>,
+    ) -> Result<Self> {
+        Self::from_encrypted_pkcs8_der(
+            &PrivateKeyDer::from_pem(private_key_pem)?.encrypted_pkcs8()?,
+            password,
+        )
+    }
+
+    /// Creates a new RSA private key from encrypted PKCS8 DER-encoded bytes.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_der(
+        private_key_bytes: &[u8],
+        password: impl AsRef<[u8]>,
+    ) -> Result<Self> {
+        Self::from_pkcs8_der(super::decrypt_pkcs8(private_key_bytes, password.as_ref())?.as_bytes())
+    }
+
+    /// Creates a new RSA private key from a PKCS1 or PKCS8 PEM string.
+    pub fn from_any_pem(private_key_pem: &str) -> Result<Self> {
+        match PrivateKeyDer::from_pem(private_key_pem)? {
+            PrivateKeyDer::Pkcs1(der) => Self::from_pkcs1_der(&der),
+            PrivateKeyDer::Pkcs8(der) => Self::from_pkcs8_der(&der),
+            PrivateKeyDer::EncryptedPkcs8(_) => Err(Error::CryptoError(
+                "Encrypted PKCS8 key requires a password".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the PKCS1 DER encoded public key.
+    pub fn public_key(&self) -> Vec<u8> {
+        rsa::pkcs1::EncodeRsaPublicKey::to_pkcs1_der(&self.inner.to_public_key())
+            .map(|der| der.as_bytes().to_vec())
+            .unwrap_or_default()
+    }
 }
 
 impl SigningKey for RsaKey<Sha1> {
@@ -94,6 +152,58 @@ impl Ed25519Key {
             ),
         })
     }
+
+    /// Creates an Ed25519 private key from a PKCS8 PEM string.
+    pub fn from_pkcs8_pem(private_key_pem: &str) -> crate::Result<Self> {
+        Self::from_pkcs8_der(&PrivateKeyDer::from_pem(private_key_pem)?.pkcs8()?)
+    }
+
+    /// Creates an Ed25519 private key from PKCS8 DER-encoded bytes.
+    pub fn from_pkcs8_der(private_key_bytes: &[u8]) -> crate::Result<Self> {
+        Ok(Self {
+            inner: ed25519_dalek::SigningKey::from_pkcs8_der(private_key_bytes)
+                .map_err(|err| Error::CryptoError(err.to_string()))?,
+        })
+    }
+
+    /// Creates an Ed25519 private key from an encrypted PKCS8 PEM string.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_pem(
+        private_key_pem: &str,
+        password: impl AsRef<[u8]>,
+    ) -> crate::Result<Self> {
+        Self::from_encrypted_pkcs8_der(
+            &PrivateKeyDer::from_pem(private_key_pem)?.encrypted_pkcs8()?,
+            password,
+        )
+    }
+
+    /// Creates an Ed25519 private key from encrypted PKCS8 DER-encoded bytes.
+    #[cfg(feature = "pkcs8-encryption")]
+    pub fn from_encrypted_pkcs8_der(
+        private_key_bytes: &[u8],
+        password: impl AsRef<[u8]>,
+    ) -> crate::Result<Self> {
+        Self::from_pkcs8_der(super::decrypt_pkcs8(private_key_bytes, password.as_ref())?.as_bytes())
+    }
+
+    /// Creates an Ed25519 private key from a PKCS8 PEM string.
+    pub fn from_any_pem(private_key_pem: &str) -> crate::Result<Self> {
+        match PrivateKeyDer::from_pem(private_key_pem)? {
+            PrivateKeyDer::Pkcs8(der) => Self::from_pkcs8_der(&der),
+            PrivateKeyDer::EncryptedPkcs8(_) => Err(Error::CryptoError(
+                "Encrypted PKCS8 key requires a password".to_string(),
+            )),
+            PrivateKeyDer::Pkcs1(_) => Err(Error::CryptoError(
+                "PKCS1 PEM does not contain an Ed25519 key".to_string(),
+            )),
+        }
+    }
+
+    /// Returns the public key of the Ed25519 key pair.
+    pub fn public_key(&self) -> Vec<u8> {
+        self.inner.verifying_key().to_bytes().to_vec()
+    }
 }
 
 impl SigningKey for Ed25519Key {
@@ -159,6 +269,12 @@ impl VerifyingKey for RsaPublicKey {
             Algorithm::Ed25519Sha256 => Err(Error::IncompatibleAlgorithms),
         }
     }
+
+    fn public_key(&self) -> Option<(Algorithm, Vec<u8>)> {
+        rsa::pkcs1::EncodeRsaPublicKey::to_pkcs1_der(&self.inner)
+            .ok()
+            .map(|der| (Algorithm::RsaSha256, der.as_bytes().to_vec()))
+    }
 }
 
 pub(crate) struct Ed25519PublicKey {
@@ -207,6 +323,10 @@ impl VerifyingKey for Ed25519PublicKey {
             )
             .map_err(|_| Error::FailedVerification)
     }
+
+    fn public_key(&self) -> Option<(Algorithm, Vec<u8>)> {
+        Some((Algorithm::Ed25519Sha256, self.inner.to_bytes().to_vec()))
+    }
 }
 
 impl Writer for sha1::Sha1 {
@@ -0,0 +1,231 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use parking_lot::Mutex;
+
+use crate::{arc::ArcSealer, common::headers::Writable, dkim::NeedHeaders, Result};
+
+use super::{Algorithm, Sha256, SigningKey};
+
+type KeyUsageMap = HashMap<(String, String), Arc<KeyUsage>, ahash::RandomState>;
+
+/// Signing statistics of a single key.
+#[derive(Debug, Default)]
+pub struct KeyUsage {
+    signatures: AtomicU64,
+    last_used: AtomicU64,
+}
+
+/// Registry of signing keys indexed by domain and selector.
+#[derive(Debug, Default, Clone)]
+pub struct KeyUsageStore {
+    keys: Arc<Mutex<KeyUsageMap>>,
+}
+
+/// Signing key wrapper that records every signature produced.
+#[derive(Debug)]
+pub struct TrackedKey<T: SigningKey> {
+    key: T,
+    usage: Arc<KeyUsage>,
+}
+
+impl KeyUsage {
+    /// Returns the number of signatures produced with this key.
+    pub fn signatures(&self) -> u64 {
+        self.signatures.load(Ordering::Relaxed)
+    }
+
+    /// Returns the UNIX timestamp of the last signature, if any.
+    pub fn last_used(&self) -> Option<u64> {
+        match self.last_used.load(Ordering::Relaxed) {
+            0 => None,
+            last_used => Some(last_used),
+        }
+    }
+
+    pub(crate) fn record(&self) {
+        self.signatures.fetch_add(1, Ordering::Relaxed);
+        self.last_used.fetch_max(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl KeyUsageStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a key under a domain and selector and returns a tracked signing key.
+    pub fn track<T: SigningKey>(
+        &self,
+        domain: impl AsRef<str>,
+        selector: impl AsRef<str>,
+        key: T,
+    ) -> TrackedKey<T> {
+        let usage = self
+            .keys
+            .lock()
+            .entry(Self::key(domain.as_ref(), selector.as_ref()))
+            .or_default()
+            .clone();
+
+        TrackedKey { key, usage }
+    }
+
+    /// Registers an ARC sealing key and returns a sealer for its domain and
+    /// selector. During a rotation, the previous sealer can be retired once
+    /// [`KeyUsageStore::unused_since`] reports it.
+    pub fn track_sealer<T: SigningKey<Hasher = Sha256>>(
+        &self,
+        domain: impl AsRef<str>,
+        selector: impl AsRef<str>,
+        key: T,
+    ) -> ArcSealer<TrackedKey<T>, NeedHeaders> {
+        let (domain, selector) = (domain.as_ref(), selector.as_ref());
+        ArcSealer::from_key(self.track(domain, selector, key))
+            .domain(domain)
+            .selector(selector)
+    }
+
+    /// Returns the usage statistics of a domain and selector.
+    pub fn usage(&self, domain: &str, selector: &str) -> Option<Arc<KeyUsage>> {
+        self.keys.lock().get(&Self::key(domain, selector)).cloned()
+    }
+
+    /// Returns the domain and selector pairs that have not signed since the given UNIX timestamp.
+    pub fn unused_since(&self, timestamp: u64) -> Vec<(String, String)> {
+        let mut unused = self
+            .keys
+            .lock()
+            .iter()
+            .filter(|(_, usage)| !matches!(usage.last_used(), Some(t) if t >= timestamp))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        unused.sort_unstable();
+        unused
+    }
+
+    /// Returns the statistics of all registered keys, sorted by domain and selector.
+    pub fn snapshot(&self) -> Vec<(String, String, u64, Option<u64>)> {
+        let mut snapshot = self
+            .keys
+            .lock()
+            .iter()
+            .map(|((domain, selector), usage)| {
+                (
+                    domain.clone(),
+                    selector.clone(),
+                    usage.signatures(),
+                    usage.last_used(),
+                )
+            })
+            .collect::<Vec<_>>();
+        snapshot.sort_unstable_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        snapshot
+    }
+
+    fn key(domain: &str, selector: &str) -> (String, String) {
+        (domain.to_lowercase(), selector.to_lowercase())
+    }
+}
+
+impl<T: SigningKey> TrackedKey<T> {
+    /// Returns the usage statistics of this key.
+    pub fn usage(&self) -> &KeyUsage {
+        &self.usage
+    }
+
+    /// Returns the wrapped signing key.
+    pub fn into_inner(self) -> T {
+        self.key
+    }
+}
+
+impl<T: SigningKey> SigningKey for TrackedKey<T> {
+    type Hasher = T::Hasher;
+
+    fn sign(&self, input: impl Writable) -> Result<Vec<u8>> {
+        let signature = self.key.sign(input)?;
+        self.usage.record();
+        Ok(signature)
+    }
+
+    fn algorithm(&self) -> Algorithm {
+        self.key.algorithm()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            crypto::{Algorithm, Sha256, SigningKey},
+            headers::Writable,
+        },
+        dkim::DkimSigner,
+    };
+
+    use super::KeyUsageStore;
+
+    struct DummyKey;
+
+    impl SigningKey for DummyKey {
+        type Hasher = Sha256;
+
+        fn sign(&self, input: impl Writable) -> crate::Result<Vec<u8>> {
+            Ok(self.hash(input).as_ref().to_vec())
+        }
+
+        fn algorithm(&self) -> Algorithm {
+            Algorithm::RsaSha256
+        }
+    }
+
+    #[test]
+    fn key_usage() {
+        let store = KeyUsageStore::new();
+        let signer = DkimSigner::from_key(store.track("Example.org", "new", DummyKey))
+            .domain("example.org")
+            .selector("new")
+            .headers(["From", "To", "Subject"]);
+        let _old = store.track("example.org", "old", DummyKey);
+
+        for _ in 0..3 {
+            signer
+                .sign(b"From: hello@example.org\r\nTo: bye@example.org\r\n\r\nHi!\r\n")
+                .unwrap();
+        }
+
+        let usage = store.usage("example.org", "new").unwrap();
+        assert_eq!(usage.signatures(), 3);
+        assert!(usage.last_used().is_some());
+        assert_eq!(signer.key.usage().signatures(), 3);
+        assert_eq!(store.usage("example.org", "old").unwrap().signatures(), 0);
+        assert_eq!(
+            store.unused_since(1),
+            vec![("example.org".to_string(), "old".to_string())]
+        );
+        assert_eq!(store.snapshot().len(), 2);
+    }
+}
@@ -0,0 +1,395 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, fmt::Display, str::FromStr};
+
+use crate::Error;
+
+use super::resolver::IntoFqdn;
+
+/// A validated domain name, lowercased, IDNA-encoded and without a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Domain(String);
+
+/// A validated DKIM selector, normalized like a [`Domain`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String", into = "String")
+)]
+pub struct Selector(String);
+
+impl Domain {
+    pub fn new(name: impl AsRef<str>) -> crate::Result<Self> {
+        normalize(name.as_ref()).map(Domain)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the domain with its A-labels converted to U-labels, for display.
+    pub fn to_unicode(&self) -> String {
+        to_unicode(&self.0).into_owned()
+    }
+
+    /// Returns `true` if this domain is `parent` or one of its subdomains.
+    pub fn is_subdomain_of(&self, parent: &Domain) -> bool {
+        self.0 == parent.0
+            || self
+                .0
+                .strip_suffix(parent.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    }
+
+    /// Normalizes a domain name, falling back to lowercasing names that fail validation.
+    pub(crate) fn normalize(name: &str) -> String {
+        normalize(name).unwrap_or_else(|_| name.trim_end_matches('.').to_lowercase())
+    }
+}
+
+impl Selector {
+    pub fn new(name: impl AsRef<str>) -> crate::Result<Self> {
+        normalize(name.as_ref()).map(Selector)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn normalize(name: &str) -> crate::Result<String> {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let name = if name.is_ascii() {
+        name.to_ascii_lowercase()
+    } else {
+        idna::domain_to_ascii(name).map_err(|_| Error::ParseError)?
+    };
+
+    if !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == b'-' || ch == b'_')
+        })
+    {
+        Ok(name)
+    } else {
+        Err(Error::ParseError)
+    }
+}
+
+/// Converts the U-labels of an internationalized domain name to A-labels
+/// (RFC 8616), leaving ASCII names untouched. Labels that cannot be converted
+/// are lowercased instead.
+pub(crate) fn to_ascii(name: &str) -> Cow<'_, str> {
+    if name.is_ascii() {
+        return name.into();
+    }
+
+    name.split('.')
+        .map(|label| {
+            if label.is_ascii() {
+                label.into()
+            } else {
+                idna::domain_to_ascii(label)
+                    .map(Cow::Owned)
+                    .unwrap_or_else(|_| label.to_lowercase().into())
+            }
+        })
+        .collect::<Vec<Cow<'_, str>>>()
+        .join(".")
+        .into()
+}
+
+/// Converts the A-labels of a domain name to U-labels, leaving other labels untouched.
+pub(crate) fn to_unicode(name: &str) -> Cow<'_, str> {
+    let is_a_label = |label: &str| {
+        label
+            .get(..4)
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case("xn--"))
+    };
+    if !name.split('.').any(is_a_label) {
+        return name.into();
+    }
+
+    name.split('.')
+        .map(|label| match idna::domain_to_unicode(label) {
+            (unicode, Ok(())) if is_a_label(label) => unicode.into(),
+            _ => Cow::Borrowed(label),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+        .into()
+}
+
+/// Converts the domain part of an e-mail address to A-labels, keeping the
+/// local part as is.
+pub(crate) fn address_to_ascii(address: &str) -> Cow<'_, str> {
+    match address.rsplit_once('@') {
+        Some((local, domain)) if !domain.is_ascii() => {
+            format!("{local}@{}", to_ascii(domain)).into()
+        }
+        _ => address.into(),
+    }
+}
+
+macro_rules! impl_name {
+    ($name:ident) => {
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $name::new(s)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = Error;
+
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                $name::new(value)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = Error;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                $name::new(value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+    };
+}
+
+impl_name!(Domain);
+impl_name!(Selector);
+
+impl<'x> IntoFqdn<'x> for &Domain {
+    fn into_fqdn(self) -> Cow<'x, str> {
+        format!("{}.", self.0).into()
+    }
+}
+
+impl<'x> IntoFqdn<'x> for Domain {
+    fn into_fqdn(self) -> Cow<'x, str> {
+        let mut fqdn = self.0;
+        fqdn.push('.');
+        fqdn.into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::{
+            headers::HeaderWriter,
+            parse::TxtRecordParser,
+            resolver::IntoFqdn,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::DkimSigner,
+        dmarc::Dmarc,
+        spf::{cache::SpfCache, Spf},
+        AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, SpfOutput, SpfResult,
+    };
+
+    use super::{address_to_ascii, to_ascii, to_unicode, Domain, Selector};
+
+    #[test]
+    fn domain_names() {
+        for (name, expected) in [
+            ("example.org", "example.org"),
+            ("Example.ORG.", "example.org"),
+            ("_dmarc.Sub.example.org", "_dmarc.sub.example.org"),
+            ("bücher.example", "xn--bcher-kva.example"),
+            ("BÜCHER.example.", "xn--bcher-kva.example"),
+        ] {
+            let domain = Domain::new(name).unwrap();
+            assert_eq!(domain, expected, "{name}");
+            assert_eq!(domain.into_fqdn(), format!("{expected}."));
+        }
+
+        for name in [
+            "",
+            ".",
+            "example..org",
+            "exa mple.org",
+            "user@example.org",
+            &format!("{}.org", "a".repeat(64)),
+        ] {
+            assert!(Domain::new(name).is_err(), "{name}");
+        }
+
+        let domain = Domain::new("mail.Example.org").unwrap();
+        assert!(domain.is_subdomain_of(&"example.org".parse().unwrap()));
+        assert!(domain.is_subdomain_of(&domain));
+        assert!(!domain.is_subdomain_of(&"ample.org".parse().unwrap()));
+        assert_eq!(Domain::normalize("Not A Domain."), "not a domain");
+
+        assert_eq!(Selector::new("Default").unwrap(), "default");
+        assert!(Selector::new("sel ector").is_err());
+
+        // Differently written domains share SPF cache entries
+        let cache = SpfCache::new(10);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        cache.insert(
+            "Example.ORG.",
+            ip,
+            &SpfOutput::new("example.org".to_string()).with_result(SpfResult::Pass),
+        );
+        assert_eq!(
+            cache.get("example.org", ip).map(|output| output.result()),
+            Some(SpfResult::Pass)
+        );
+    }
+
+    #[tokio::test]
+    async fn internationalized_domains() {
+        assert_eq!(
+            to_ascii("mail.Bücher.example"),
+            "mail.xn--bcher-kva.example"
+        );
+        assert_eq!(to_ascii("_dmarc.example.org"), "_dmarc.example.org");
+        assert_eq!(
+            to_unicode("mail.xn--bcher-kva.example"),
+            "mail.bücher.example"
+        );
+        assert_eq!(to_unicode("_dmarc.example.org"), "_dmarc.example.org");
+        assert_eq!(
+            address_to_ascii("jürgen@bücher.example"),
+            "jürgen@xn--bcher-kva.example"
+        );
+        assert_eq!(
+            "sel._domainkey.bücher.example".into_fqdn(),
+            "sel._domainkey.xn--bcher-kva.example."
+        );
+        assert_eq!(
+            Domain::new("bücher.example").unwrap().to_unicode(),
+            "bücher.example"
+        );
+
+        // Sign with U-labels, then verify DKIM, SPF and DMARC
+        let resolver = ed25519_resolver("ed._domainkey.bücher.example");
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        resolver.txt_add(
+            "bücher.example",
+            Spf::parse(b"v=spf1 ip4:192.0.2.0/24 -all").unwrap(),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.bücher.example",
+            Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+            valid_until,
+        );
+
+        let key = ed25519_key().unwrap();
+        let message = concat!(
+            "From: Jürgen <jürgen@bücher.example>\r\n",
+            "To: jdoe@example.org\r\n",
+            "Subject: Grüße\r\n",
+            "\r\n",
+            "Hallo!\r\n"
+        );
+        let signature = DkimSigner::from_key(key)
+            .domain("Bücher.example")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .agent_user_identifier("jürgen@bücher.example")
+            .sign(message.as_bytes())
+            .unwrap();
+        let signed = signature.to_header() + message;
+        assert!(signed.contains("d=xn--bcher-kva.example;"), "{signed}");
+        assert!(
+            signed.contains("i=jürgen@xn--bcher-kva.example;"),
+            "{signed}"
+        );
+
+        let message = AuthenticatedMessage::parse(signed.as_bytes()).unwrap();
+        let dkim = resolver.verify_dkim(&message).await;
+        assert_eq!(dkim[0].result(), &DkimResult::Pass);
+
+        let spf = resolver
+            .verify_spf_sender(
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                "mx.bücher.example",
+                "mx.example.org",
+                "jürgen@bücher.example",
+            )
+            .await;
+        assert_eq!(spf.result(), SpfResult::Pass);
+        assert_eq!(spf.domain(), "xn--bcher-kva.example");
+        assert_eq!(spf.domain_unicode(), "bücher.example");
+
+        let dmarc = resolver
+            .verify_dmarc(&message, &dkim, "bücher.example", &spf)
+            .await;
+        assert_eq!(dmarc.dkim_result(), &DmarcResult::Pass);
+        assert_eq!(dmarc.spf_result(), &DmarcResult::Pass);
+        assert_eq!(dmarc.domain(), "xn--bcher-kva.example");
+        assert_eq!(dmarc.domain_unicode(), "bücher.example");
+
+        let auth_results = AuthenticationResults::new("mx.example.org")
+            .with_dkim_results(&dkim, "bücher.example")
+            .with_dmarc_result(&dmarc)
+            .to_string();
+        assert!(
+            auth_results.contains("header.i=jürgen@xn--bcher-kva.example"),
+            "{auth_results}"
+        );
+        assert!(
+            auth_results.contains("header.from=xn--bcher-kva.example"),
+            "{auth_results}"
+        );
+    }
+}
@@ -0,0 +1,370 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::{
+    common::codes::ResultCode, dmarc::Policy, DkimOutput, DkimResult, DmarcOutput, DmarcResult,
+    Error, SpfOutput, SpfResult,
+};
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+const MESSAGES: &[(&str, &str)] = &[
+    (
+        "dkim.pass",
+        "The message was signed by {domain} and has not been altered.",
+    ),
+    (
+        "dkim.pass.partial",
+        "The message was signed by {domain}, but the signature only covers the beginning of its text, so content may have been added.",
+    ),
+    (
+        "dkim.fail",
+        "The message claims to be signed by {domain}, but the signature does not match its contents.",
+    ),
+    (
+        "dkim.neutral",
+        "The message signature from {domain} could not be checked.",
+    ),
+    (
+        "dkim.temperror",
+        "The signature from {domain} could not be checked because of a temporary problem. It may succeed later.",
+    ),
+    (
+        "dkim.permerror",
+        "The signature from {domain} is damaged or uses a key that is no longer valid.",
+    ),
+    ("dkim.none", "The message is not signed."),
+    (
+        "spf.pass",
+        "The sending server is allowed to send mail for {domain}.",
+    ),
+    (
+        "spf.fail",
+        "The sending server is not allowed to send mail for {domain}.",
+    ),
+    (
+        "spf.softfail",
+        "The sending server is probably not allowed to send mail for {domain}.",
+    ),
+    (
+        "spf.neutral",
+        "{domain} does not say whether the sending server may send its mail.",
+    ),
+    (
+        "spf.temperror",
+        "The sending servers of {domain} could not be checked because of a temporary problem. It may succeed later.",
+    ),
+    (
+        "spf.permerror",
+        "{domain} publishes an invalid list of sending servers.",
+    ),
+    (
+        "spf.multiple",
+        "{domain} publishes more than one list of sending servers, so none of them apply.",
+    ),
+    (
+        "spf.deprecated",
+        "{domain} publishes its list of sending servers in an obsolete format that is no longer checked.",
+    ),
+    (
+        "spf.none",
+        "{domain} does not publish a list of its sending servers.",
+    ),
+    (
+        "dmarc.pass",
+        "The message passed the checks required by {domain}.",
+    ),
+    (
+        "dmarc.fail.reject",
+        "The message failed the checks required by {domain}, which asks for such messages to be rejected.",
+    ),
+    (
+        "dmarc.fail.quarantine",
+        "The message failed the checks required by {domain}, which asks for such messages to be treated as suspicious.",
+    ),
+    (
+        "dmarc.fail",
+        "The message failed the checks required by {domain}, which asks for no action to be taken.",
+    ),
+    (
+        "dmarc.temperror",
+        "The policy of {domain} could not be checked because of a temporary problem. It may succeed later.",
+    ),
+    (
+        "dmarc.permerror",
+        "{domain} publishes an invalid authentication policy.",
+    ),
+    (
+        "dmarc.multiple",
+        "{domain} publishes more than one authentication policy, so none of them apply.",
+    ),
+    (
+        "dmarc.none",
+        "{domain} does not publish an authentication policy.",
+    ),
+];
+
+/// Localized messages describing authentication results, indexed by locale and key.
+///
+/// Messages may include a `{domain}` placeholder. Missing translations fall back to
+/// the base language (`pt` for `pt-BR`) and then to English.
+#[derive(Debug, Clone)]
+pub struct MessageCatalog {
+    messages: HashMap<(String, String), String, ahash::RandomState>,
+}
+
+impl Default for MessageCatalog {
+    fn default() -> Self {
+        let mut catalog = Self {
+            messages: HashMap::default(),
+        };
+        for (key, message) in MESSAGES {
+            catalog.add_message(DEFAULT_LOCALE, *key, *message);
+        }
+        catalog
+    }
+}
+
+impl MessageCatalog {
+    /// Creates a catalog with the built-in English messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a message.
+    pub fn with_message(
+        mut self,
+        locale: impl AsRef<str>,
+        key: impl Into<String>,
+        message: impl Into<String>,
+    ) -> Self {
+        self.add_message(locale, key, message);
+        self
+    }
+
+    /// Adds or replaces a message.
+    pub fn add_message(
+        &mut self,
+        locale: impl AsRef<str>,
+        key: impl Into<String>,
+        message: impl Into<String>,
+    ) {
+        self.messages
+            .insert((normalize(locale.as_ref()), key.into()), message.into());
+    }
+
+    /// Returns a message, falling back to the base language and then to English.
+    pub fn message(&self, locale: &str, key: &str) -> Option<&str> {
+        let locale = normalize(locale);
+        let language = locale.split('-').next().unwrap_or_default();
+
+        for locale in [locale.as_str(), language, DEFAULT_LOCALE] {
+            if let Some(message) = self.messages.get(&(locale.to_string(), key.to_string())) {
+                return Some(message.as_str());
+            }
+        }
+
+        None
+    }
+
+    /// Returns a message with its `{domain}` placeholder replaced.
+    pub fn explain(&self, locale: &str, key: &str, domain: &str) -> String {
+        self.message(locale, key)
+            .map(|message| message.replace("{domain}", domain))
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    pub(crate) fn builtin() -> &'static MessageCatalog {
+        static CATALOG: OnceLock<MessageCatalog> = OnceLock::new();
+        CATALOG.get_or_init(MessageCatalog::default)
+    }
+}
+
+fn normalize(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_lowercase()
+}
+
+impl<'x> DkimOutput<'x> {
+    /// Returns a non-technical explanation of the result.
+    pub fn human_explanation(&self, locale: &str) -> String {
+        self.human_explanation_with(MessageCatalog::builtin(), locale)
+    }
+
+    /// Returns a non-technical explanation of the result using a custom catalog.
+    pub fn human_explanation_with(&self, catalog: &MessageCatalog, locale: &str) -> String {
+        catalog.explain(
+            locale,
+            self.explanation_key(),
+            self.signature.map_or("", |s| s.d.as_str()),
+        )
+    }
+
+    /// Returns the catalog key describing this result.
+    pub fn explanation_key(&self) -> &'static str {
+        match self.result {
+            // The body length tag (l=) leaves any appended content unsigned
+            DkimResult::Pass if self.signature.is_some_and(|s| s.l > 0) => "dkim.pass.partial",
+            DkimResult::Pass => "dkim.pass",
+            DkimResult::Neutral(_) => "dkim.neutral",
+            DkimResult::Fail(_) => "dkim.fail",
+            DkimResult::PermError(_) => "dkim.permerror",
+            DkimResult::TempError(_) => "dkim.temperror",
+            DkimResult::None => "dkim.none",
+        }
+    }
+}
+
+impl SpfOutput {
+    /// Returns a non-technical explanation of the result.
+    pub fn human_explanation(&self, locale: &str) -> String {
+        self.human_explanation_with(MessageCatalog::builtin(), locale)
+    }
+
+    /// Returns a non-technical explanation of the result using a custom catalog.
+    pub fn human_explanation_with(&self, catalog: &MessageCatalog, locale: &str) -> String {
+        catalog.explain(locale, self.explanation_key(), &self.domain)
+    }
+
+    /// Returns the catalog key describing this result.
+    pub fn explanation_key(&self) -> &'static str {
+        match self.result {
+            _ if self.code == Some(ResultCode::SpfMultipleRecords) => "spf.multiple",
+            _ if self.code == Some(ResultCode::SpfDeprecatedRecordType) => "spf.deprecated",
+            SpfResult::Pass => "spf.pass",
+            SpfResult::Fail => "spf.fail",
+            SpfResult::SoftFail => "spf.softfail",
+            SpfResult::Neutral => "spf.neutral",
+            SpfResult::TempError => "spf.temperror",
+            SpfResult::PermError => "spf.permerror",
+            SpfResult::None => "spf.none",
+        }
+    }
+}
+
+impl DmarcOutput {
+    /// Returns a non-technical explanation of the result.
+    pub fn human_explanation(&self, locale: &str) -> String {
+        self.human_explanation_with(MessageCatalog::builtin(), locale)
+    }
+
+    /// Returns a non-technical explanation of the result using a custom catalog.
+    pub fn human_explanation_with(&self, catalog: &MessageCatalog, locale: &str) -> String {
+        catalog.explain(locale, self.explanation_key(), &self.domain)
+    }
+
+    /// Returns the catalog key describing this result.
+    pub fn explanation_key(&self) -> &'static str {
+        let results = [&self.spf_result, &self.dkim_result];
+        if results.contains(&&DmarcResult::Pass) {
+            "dmarc.pass"
+        } else if results
+            .iter()
+            .any(|r| matches!(r, DmarcResult::TempError(_)))
+        {
+            "dmarc.temperror"
+        } else if results.contains(&&DmarcResult::PermError(Error::MultipleRecords)) {
+            "dmarc.multiple"
+        } else if results
+            .iter()
+            .any(|r| matches!(r, DmarcResult::PermError(_)))
+        {
+            "dmarc.permerror"
+        } else if results.iter().all(|r| **r == DmarcResult::None) {
+            "dmarc.none"
+        } else {
+            match self.policy {
+                Policy::Reject => "dmarc.fail.reject",
+                Policy::Quarantine => "dmarc.fail.quarantine",
+                Policy::None | Policy::Unspecified => "dmarc.fail",
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dkim::Signature, dmarc::Policy, DkimOutput, DmarcOutput, DmarcResult, Error, SpfOutput,
+        SpfResult,
+    };
+
+    use super::MessageCatalog;
+
+    #[test]
+    fn human_explanation() {
+        let spf = SpfOutput::new("example.org".to_string()).with_result(SpfResult::Fail);
+        assert_eq!(
+            spf.human_explanation("en-US"),
+            "The sending server is not allowed to send mail for example.org."
+        );
+        assert_eq!(spf.human_explanation("fr"), spf.human_explanation("en"));
+
+        let catalog = MessageCatalog::new()
+            .with_message(
+                "es",
+                "spf.fail",
+                "El servidor no puede enviar correo de {domain}.",
+            )
+            .with_message(
+                "es-MX",
+                "spf.fail",
+                "El servidor no puede mandar correo de {domain}.",
+            );
+        assert_eq!(
+            spf.human_explanation_with(&catalog, "es_AR"),
+            "El servidor no puede enviar correo de example.org."
+        );
+        assert_eq!(
+            spf.human_explanation_with(&catalog, "es-MX"),
+            "El servidor no puede mandar correo de example.org."
+        );
+
+        let mut dmarc = DmarcOutput {
+            spf_result: DmarcResult::Fail(Error::NotAligned),
+            dkim_result: DmarcResult::None,
+            domain: "example.org".to_string(),
+            policy: Policy::Reject,
+            record: None,
+            arc_override: None,
+            local_override: None,
+        };
+        assert_eq!(dmarc.explanation_key(), "dmarc.fail.reject");
+        dmarc.dkim_result = DmarcResult::Pass;
+        assert_eq!(
+            dmarc.human_explanation("en"),
+            "The message passed the checks required by example.org."
+        );
+        dmarc.dkim_result = DmarcResult::None;
+        dmarc.spf_result = DmarcResult::None;
+        assert_eq!(dmarc.explanation_key(), "dmarc.none");
+        dmarc.spf_result = DmarcResult::PermError(Error::MultipleRecords);
+        assert_eq!(dmarc.explanation_key(), "dmarc.multiple");
+
+        let mut signature = Signature {
+            d: "example.org".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            DkimOutput::pass()
+                .with_signature(&signature)
+                .human_explanation("en"),
+            "The message was signed by example.org and has not been altered."
+        );
+        signature.l = 42;
+        assert_eq!(
+            DkimOutput::pass()
+                .with_signature(&signature)
+                .explanation_key(),
+            "dkim.pass.partial"
+        );
+    }
+}
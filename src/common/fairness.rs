@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+use crate::{Domain, Resolver};
+
+/// Limits on concurrent verifications, applied per sender domain so that a
+/// single domain cannot take up every slot of the shared DNS lookup pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FairnessLimits {
+    total: usize,
+    per_domain: usize,
+}
+
+/// Snapshot of the verification queues of a `Resolver`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FairnessStats {
+    /// Verifications currently holding a slot.
+    pub in_flight: usize,
+    /// Verifications waiting for a slot.
+    pub queued: usize,
+    /// Verifications that had to wait for a slot.
+    pub throttled: u64,
+    /// Total time spent waiting for slots.
+    pub wait_time: Duration,
+    /// Domains with queued verifications and the length of their queues, longest first.
+    pub queued_domains: Vec<(String, usize)>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Fairness {
+    limits: FairnessLimits,
+    slots: Semaphore,
+    domains: Mutex<HashMap<String, DomainQueue>>,
+    queued: AtomicUsize,
+    throttled: AtomicU64,
+    wait_time: AtomicU64,
+}
+
+#[derive(Debug)]
+struct DomainQueue {
+    slots: Arc<Semaphore>,
+    users: usize,
+    queued: usize,
+}
+
+/// Slot held for the duration of a verification.
+pub(crate) struct FairnessPermit<'x> {
+    fairness: &'x Fairness,
+    domain: String,
+    domain_slot: Option<OwnedSemaphorePermit>,
+    slot: Option<SemaphorePermit<'x>>,
+}
+
+struct QueueWait<'x> {
+    fairness: &'x Fairness,
+    domain: Option<&'x str>,
+    started: Instant,
+}
+
+impl FairnessLimits {
+    /// Allows `total` concurrent verifications, at most `per_domain` of them
+    /// for the same sender domain.
+    pub fn new(total: usize, per_domain: usize) -> Self {
+        let total = std::cmp::max(total, 1);
+        FairnessLimits {
+            total,
+            per_domain: per_domain.clamp(1, total),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn per_domain(&self) -> usize {
+        self.per_domain
+    }
+}
+
+impl Fairness {
+    fn new(limits: FairnessLimits) -> Self {
+        Fairness {
+            limits,
+            slots: Semaphore::new(limits.total),
+            domains: Mutex::new(HashMap::new()),
+            queued: AtomicUsize::new(0),
+            throttled: AtomicU64::new(0),
+            wait_time: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits for a slot of the domain's queue and then for a slot of the shared pool.
+    pub(crate) async fn acquire(&self, domain: &str) -> FairnessPermit<'_> {
+        let domain = Domain::normalize(domain);
+        let domain_slots = {
+            let mut domains = self.domains.lock();
+            let queue = domains
+                .entry(domain.clone())
+                .or_insert_with(|| DomainQueue {
+                    slots: Arc::new(Semaphore::new(self.limits.per_domain)),
+                    users: 0,
+                    queued: 0,
+                });
+            queue.users += 1;
+            queue.slots.clone()
+        };
+        let mut permit = FairnessPermit {
+            fairness: self,
+            domain,
+            domain_slot: None,
+            slot: None,
+        };
+
+        permit.domain_slot = match domain_slots.clone().try_acquire_owned() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                let _wait = self.wait(Some(&permit.domain));
+                domain_slots.acquire_owned().await.ok()
+            }
+        };
+        permit.slot = match self.slots.try_acquire() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                let _wait = self.wait(None);
+                self.slots.acquire().await.ok()
+            }
+        };
+        permit
+    }
+
+    fn wait<'x>(&'x self, domain: Option<&'x str>) -> QueueWait<'x> {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        if let Some(domain) = domain {
+            if let Some(queue) = self.domains.lock().get_mut(domain) {
+                queue.queued += 1;
+            }
+        }
+        QueueWait {
+            fairness: self,
+            domain,
+            started: Instant::now(),
+        }
+    }
+
+    fn stats(&self) -> FairnessStats {
+        let mut queued_domains = self
+            .domains
+            .lock()
+            .iter()
+            .filter(|(_, queue)| queue.queued > 0)
+            .map(|(domain, queue)| (domain.clone(), queue.queued))
+            .collect::<Vec<_>>();
+        queued_domains.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        FairnessStats {
+            in_flight: self.limits.total - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::Relaxed),
+            throttled: self.throttled.load(Ordering::Relaxed),
+            wait_time: Duration::from_micros(self.wait_time.load(Ordering::Relaxed)),
+            queued_domains,
+        }
+    }
+}
+
+impl Drop for FairnessPermit<'_> {
+    fn drop(&mut self) {
+        self.slot = None;
+        self.domain_slot = None;
+        let mut domains = self.fairness.domains.lock();
+        if let Some(queue) = domains.get_mut(&self.domain) {
+            queue.users -= 1;
+            if queue.users == 0 {
+                domains.remove(&self.domain);
+            }
+        }
+    }
+}
+
+impl Drop for QueueWait<'_> {
+    fn drop(&mut self) {
+        let fairness = self.fairness;
+        fairness.queued.fetch_sub(1, Ordering::Relaxed);
+        fairness
+            .wait_time
+            .fetch_add(self.started.elapsed().as_micros() as u64, Ordering::Relaxed);
+        if let Some(domain) = self.domain {
+            if let Some(queue) = fairness.domains.lock().get_mut(domain) {
+                queue.queued -= 1;
+            }
+        }
+    }
+}
+
+impl Resolver {
+    /// Limits the number of concurrent DKIM, ARC, SPF and DMARC verifications,
+    /// overall and per sender domain. Verifications exceeding the limits wait
+    /// for a slot. Clones of this resolver share the same limits.
+    pub fn with_fairness_limits(mut self, limits: FairnessLimits) -> Self {
+        self.fairness = Some(Arc::new(Fairness::new(limits)));
+        self
+    }
+
+    /// Returns the current verification queues, if fairness limits are set.
+    pub fn fairness_stats(&self) -> Option<FairnessStats> {
+        self.fairness.as_ref().map(|fairness| fairness.stats())
+    }
+
+    pub(crate) async fn fairness_permit(&self, domain: &str) -> Option<FairnessPermit<'_>> {
+        match &self.fairness {
+            Some(fairness) => Some(fairness.acquire(domain).await),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use crate::{common::parse::TxtRecordParser, spf::Spf, Resolver, SpfResult};
+
+    use super::{FairnessLimits, FairnessStats};
+
+    #[tokio::test]
+    async fn fairness_limits() {
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_fairness_limits(FairnessLimits::new(2, 1));
+        let stats = || resolver.fairness_stats().unwrap();
+
+        // A domain cannot use more than its share of the slots
+        let spam = resolver.fairness_permit("spam.example").await;
+        let mut spam_queued = Box::pin(resolver.fairness_permit("Spam.Example."));
+        assert!(futures_util::poll!(&mut spam_queued).is_pending());
+        let other = resolver.fairness_permit("example.org").await;
+        let mut pool_queued = Box::pin(resolver.fairness_permit("example.net"));
+        assert!(futures_util::poll!(&mut pool_queued).is_pending());
+        let current = stats();
+        assert_eq!(current.in_flight, 2);
+        assert_eq!(current.queued, 2);
+        assert_eq!(current.throttled, 2);
+        assert_eq!(
+            current.queued_domains,
+            vec![("spam.example".to_string(), 1)]
+        );
+
+        // Released slots are handed to the queued verifications
+        drop(other);
+        let pool_permit = pool_queued.await;
+        drop(spam);
+        let spam_permit = spam_queued.await;
+        assert_eq!(stats().queued, 0);
+        drop((pool_permit, spam_permit));
+
+        // Cancelled waits leave the queues
+        let permit = resolver.fairness_permit("spam.example").await;
+        let mut cancelled = Box::pin(resolver.fairness_permit("spam.example"));
+        assert!(futures_util::poll!(&mut cancelled).is_pending());
+        drop(cancelled);
+        drop(permit);
+        let current = stats();
+        assert_eq!(
+            (current.in_flight, current.queued, current.throttled),
+            (0, 0, 3)
+        );
+        assert!(resolver
+            .fairness
+            .as_ref()
+            .unwrap()
+            .domains
+            .lock()
+            .is_empty());
+
+        // Verifications run within the limits
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.1 -all").unwrap(),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        let output = resolver
+            .verify_spf_sender(
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                "mx.example.org",
+                "mx.example.net",
+                "jdoe@example.org",
+            )
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert_eq!(stats().in_flight, 0);
+        assert_eq!(
+            Resolver::new_system_conf().unwrap().fairness_stats(),
+            None::<FairnessStats>
+        );
+    }
+}
@@ -0,0 +1,475 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{AuthenticatedMessage, Error};
+
+const PROXY_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+const PP2_CLIENT_SSL: u8 = 0x01;
+
+/// Trusted relays used to locate the originating client of a message
+/// when verifying behind an inbound proxy or another MX.
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    trusted: Vec<(IpAddr, u8)>,
+    max_hops: usize,
+}
+
+/// Originating client as recorded by the last trusted relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OriginalClient {
+    ip: IpAddr,
+    helo: Option<String>,
+    hops: usize,
+}
+
+/// Client connection details relayed by a trusted proxy or load balancer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxiedClient {
+    ip: IpAddr,
+    port: Option<u16>,
+    tls: bool,
+    tls_version: Option<String>,
+    tls_cipher: Option<String>,
+}
+
+impl GatewayConfig {
+    pub fn new() -> Self {
+        GatewayConfig {
+            trusted: Vec::new(),
+            max_hops: 10,
+        }
+    }
+
+    /// Trusts a relay address.
+    pub fn with_trusted_ip(self, ip: IpAddr) -> Self {
+        let prefix = if ip.is_ipv4() { 32 } else { 128 };
+        self.with_trusted_network(ip, prefix)
+    }
+
+    /// Trusts all relays within a network.
+    pub fn with_trusted_network(mut self, network: IpAddr, prefix: u8) -> Self {
+        self.trusted.push((network, prefix));
+        self
+    }
+
+    /// Sets the maximum number of trusted Received headers to walk.
+    pub fn with_max_hops(mut self, max_hops: usize) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    /// Returns `true` if the address belongs to a trusted relay.
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted
+            .iter()
+            .any(|(network, prefix)| in_network(ip, *network, *prefix))
+    }
+
+    /// Returns the originating client of a message received from `peer_ip`.
+    ///
+    /// When the peer is not trusted it is the originating client. Otherwise the
+    /// Received headers are walked from the top while the relaying host is trusted,
+    /// and the first untrusted host is returned. `None` is returned when a trusted
+    /// relay did not record the address of its client or `max_hops` is exceeded.
+    pub fn original_client(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        peer_ip: IpAddr,
+    ) -> Option<OriginalClient> {
+        if !self.is_trusted(peer_ip) {
+            return Some(OriginalClient {
+                ip: peer_ip,
+                helo: None,
+                hops: 0,
+            });
+        }
+
+        for (hops, hop) in message.received_hops().take(self.max_hops).enumerate() {
+            let hop = hop?;
+            let ip = hop.from_ip()?;
+            if !self.is_trusted(ip) {
+                return Some(OriginalClient {
+                    ip,
+                    helo: hop.from().map(|from| from.to_string()),
+                    hops: hops + 1,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+impl GatewayConfig {
+    /// Parses a PROXY protocol v1 or v2 header sent by `peer_ip`, returning the
+    /// client and the number of bytes consumed.
+    ///
+    /// Headers are only honored when the peer is trusted, otherwise the peer is
+    /// returned as the client and no bytes are consumed.
+    pub fn proxy_protocol(
+        &self,
+        peer_ip: IpAddr,
+        data: &[u8],
+    ) -> crate::Result<(ProxiedClient, usize)> {
+        if !self.is_trusted(peer_ip) {
+            Ok((ProxiedClient::direct(peer_ip), 0))
+        } else if data.starts_with(PROXY_V2_SIGNATURE) {
+            parse_proxy_v2(peer_ip, data)
+        } else if data.starts_with(b"PROXY ") {
+            parse_proxy_v1(peer_ip, data)
+        } else {
+            Err(Error::ParseError)
+        }
+    }
+
+    /// Returns the client listed in an X-Forwarded-For header received from `peer_ip`.
+    ///
+    /// Addresses are walked from the right while they belong to trusted proxies and the
+    /// first untrusted address is returned. Headers from untrusted peers are ignored.
+    /// `None` is returned when an address in the walked portion cannot be parsed.
+    pub fn forwarded_for(&self, peer_ip: IpAddr, value: &str) -> Option<ProxiedClient> {
+        if !self.is_trusted(peer_ip) {
+            return Some(ProxiedClient::direct(peer_ip));
+        }
+
+        let mut client = peer_ip;
+        for addr in value.rsplit(',').map(str::trim).filter(|a| !a.is_empty()) {
+            client = parse_forwarded_addr(addr)?;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+
+        Some(ProxiedClient::direct(client))
+    }
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginalClient {
+    /// Address of the originating client.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// HELO/EHLO hostname announced by the originating client, if recorded.
+    pub fn helo(&self) -> Option<&str> {
+        self.helo.as_deref()
+    }
+
+    /// Number of trusted Received headers walked.
+    pub fn hops(&self) -> usize {
+        self.hops
+    }
+}
+
+impl ProxiedClient {
+    /// Client connected directly, without a proxy.
+    pub fn direct(ip: IpAddr) -> Self {
+        ProxiedClient {
+            ip,
+            port: None,
+            tls: false,
+            tls_version: None,
+            tls_cipher: None,
+        }
+    }
+
+    /// Address of the client.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Source port of the client, if relayed.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Returns `true` if the client connected to the proxy over TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// TLS version negotiated by the proxy, if relayed.
+    pub fn tls_version(&self) -> Option<&str> {
+        self.tls_version.as_deref()
+    }
+
+    /// TLS cipher negotiated by the proxy, if relayed.
+    pub fn tls_cipher(&self) -> Option<&str> {
+        self.tls_cipher.as_deref()
+    }
+}
+
+fn parse_proxy_v1(peer_ip: IpAddr, data: &[u8]) -> crate::Result<(ProxiedClient, usize)> {
+    let end = data
+        .iter()
+        .take(107)
+        .position(|&ch| ch == b'\n')
+        .filter(|&pos| pos > 0 && data[pos - 1] == b'\r')
+        .ok_or(Error::ParseError)?;
+    let line = std::str::from_utf8(&data[6..end - 1]).map_err(|_| Error::ParseError)?;
+    let mut parts = line.split(' ');
+
+    let client = match parts.next() {
+        Some("TCP4" | "TCP6") => {
+            let (Some(src), Some(_), Some(port), Some(_), None) = (
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+                parts.next(),
+            ) else {
+                return Err(Error::ParseError);
+            };
+            ProxiedClient {
+                port: Some(port.parse().map_err(|_| Error::ParseError)?),
+                ..ProxiedClient::direct(src.parse().map_err(|_| Error::ParseError)?)
+            }
+        }
+        Some("UNKNOWN") => ProxiedClient::direct(peer_ip),
+        _ => return Err(Error::ParseError),
+    };
+
+    Ok((client, end + 1))
+}
+
+fn parse_proxy_v2(peer_ip: IpAddr, data: &[u8]) -> crate::Result<(ProxiedClient, usize)> {
+    let header = data.get(..16).ok_or(Error::ParseError)?;
+    let len = 16 + u16::from_be_bytes([header[14], header[15]]) as usize;
+    let payload = data.get(16..len).ok_or(Error::ParseError)?;
+
+    let (mut client, tlvs) = match (header[12], header[13]) {
+        (0x20, _) => return Ok((ProxiedClient::direct(peer_ip), len)),
+        (0x21, 0x00) => (ProxiedClient::direct(peer_ip), &[][..]),
+        (0x21, 0x11 | 0x12) if payload.len() >= 12 => {
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[..4]).unwrap());
+            (
+                ProxiedClient {
+                    port: Some(u16::from_be_bytes([payload[8], payload[9]])),
+                    ..ProxiedClient::direct(ip.into())
+                },
+                &payload[12..],
+            )
+        }
+        (0x21, 0x21 | 0x22) if payload.len() >= 36 => {
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[..16]).unwrap());
+            (
+                ProxiedClient {
+                    port: Some(u16::from_be_bytes([payload[32], payload[33]])),
+                    ..ProxiedClient::direct(ip.into())
+                },
+                &payload[36..],
+            )
+        }
+        _ => return Err(Error::ParseError),
+    };
+
+    for (tlv_type, value) in proxy_v2_tlvs(tlvs) {
+        if tlv_type == PP2_TYPE_SSL && value.len() >= 5 {
+            client.tls = value[0] & PP2_CLIENT_SSL != 0;
+            for (sub_type, value) in proxy_v2_tlvs(&value[5..]) {
+                match sub_type {
+                    PP2_SUBTYPE_SSL_VERSION => {
+                        client.tls_version = String::from_utf8(value.to_vec()).ok();
+                    }
+                    PP2_SUBTYPE_SSL_CIPHER => {
+                        client.tls_cipher = String::from_utf8(value.to_vec()).ok();
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    Ok((client, len))
+}
+
+fn proxy_v2_tlvs(mut data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let len = u16::from_be_bytes([*data.get(1)?, *data.get(2)?]) as usize;
+        let value = data.get(3..3 + len)?;
+        let tlv_type = data[0];
+        data = &data[3 + len..];
+        Some((tlv_type, value))
+    })
+}
+
+fn parse_forwarded_addr(addr: &str) -> Option<IpAddr> {
+    addr.parse()
+        .ok()
+        .or_else(|| addr.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+        .or_else(|| {
+            addr.strip_prefix('[')
+                .and_then(|a| a.strip_suffix(']'))
+                .and_then(|a| a.parse().ok())
+        })
+}
+
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - std::cmp::min(prefix, 32) as u32)
+                .unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - std::cmp::min(prefix, 128) as u32)
+                .unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::AuthenticatedMessage;
+
+    use super::{GatewayConfig, ProxiedClient};
+
+    #[test]
+    fn gateway_original_client() {
+        let message = concat!(
+            "Received: from mx.gateway.org (mx.gateway.org [10.0.0.5])\r\n",
+            "\tby mail.example.org (Postfix) with ESMTPS id 1234\r\n",
+            "\tfor <jdoe@example.org>; Mon, 1 Jan 2024 10:00:02 +0000\r\n",
+            "Received: from mail.sender.org (mail.sender.org [192.0.2.25])\r\n",
+            "\tby mx.gateway.org (Postfix) with ESMTPS id 5678\r\n",
+            "\tfor <jdoe@example.org>; Mon, 1 Jan 2024 10:00:01 +0000\r\n",
+            "Received: from [198.51.100.1] by mail.sender.org with ESMTPSA id 9abc;\r\n",
+            "\tMon, 1 Jan 2024 10:00:00 +0000\r\n",
+            "From: hello@sender.org\r\n",
+            "To: jdoe@example.org\r\n",
+            "Subject: Hi\r\n",
+            "\r\n",
+            "Hi!\r\n"
+        );
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let config = GatewayConfig::new()
+            .with_trusted_network("10.0.0.0".parse().unwrap(), 8)
+            .with_trusted_ip("10.0.0.1".parse().unwrap());
+
+        // Untrusted peer
+        let peer: IpAddr = "203.0.113.1".parse().unwrap();
+        let client = config.original_client(&message, peer).unwrap();
+        assert_eq!(client.ip(), peer);
+        assert_eq!(client.helo(), None);
+        assert_eq!(client.hops(), 0);
+
+        // Trusted peer
+        let client = config
+            .original_client(&message, "10.0.0.1".parse().unwrap())
+            .unwrap();
+        assert_eq!(client.ip(), "192.0.2.25".parse::<IpAddr>().unwrap());
+        assert_eq!(client.helo(), Some("mail.sender.org"));
+        assert_eq!(client.hops(), 2);
+
+        // Trusted sender relay
+        let client = config
+            .clone()
+            .with_trusted_ip("192.0.2.25".parse().unwrap())
+            .original_client(&message, "10.0.0.1".parse().unwrap())
+            .unwrap();
+        assert_eq!(client.ip(), "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert_eq!(client.helo(), None);
+        assert_eq!(client.hops(), 3);
+
+        // Hop limit exceeded
+        assert_eq!(
+            config
+                .with_max_hops(1)
+                .original_client(&message, "10.0.0.1".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn gateway_proxied_client() {
+        let config = GatewayConfig::new().with_trusted_network("10.0.0.0".parse().unwrap(), 8);
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let peer: IpAddr = "203.0.113.1".parse().unwrap();
+
+        // PROXY v1
+        let data = b"PROXY TCP4 192.0.2.1 10.0.0.2 56324 25\r\nEHLO mx.example.org\r\n";
+        let (client, len) = config.proxy_protocol(proxy, data).unwrap();
+        assert_eq!(client.ip(), "192.0.2.1".parse::<IpAddr>().unwrap());
+        assert_eq!(client.port(), Some(56324));
+        assert!(!client.is_tls());
+        assert_eq!(&data[len..], b"EHLO mx.example.org\r\n");
+        assert_eq!(
+            config.proxy_protocol(peer, data).unwrap(),
+            (ProxiedClient::direct(peer), 0)
+        );
+        assert!(config.proxy_protocol(proxy, b"PROXY TCP4 x\r\n").is_err());
+        assert_eq!(
+            config.proxy_protocol(proxy, b"PROXY UNKNOWN\r\n").unwrap(),
+            (ProxiedClient::direct(proxy), 15)
+        );
+
+        // PROXY v2 with TLS
+        let mut data = b"\r\n\r\n\0\r\nQUIT\n\x21\x21".to_vec();
+        let mut payload = Vec::new();
+        payload.extend_from_slice(
+            &"2001:db8::1"
+                .parse::<std::net::Ipv6Addr>()
+                .unwrap()
+                .octets(),
+        );
+        payload.extend_from_slice(&[0u8; 16]);
+        payload.extend_from_slice(&[0x01, 0xbb, 0x00, 0x19]);
+        payload.extend_from_slice(&[0x20, 0x00, 0x0f, 0x01, 0, 0, 0, 0]);
+        payload.extend_from_slice(&[0x21, 0x00, 0x07]);
+        payload.extend_from_slice(b"TLSv1.3");
+        payload.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        data.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        data.extend_from_slice(&payload);
+        data.extend_from_slice(b"EHLO");
+        let (client, len) = config.proxy_protocol(proxy, &data).unwrap();
+        assert_eq!(client.ip(), "2001:db8::1".parse::<IpAddr>().unwrap());
+        assert_eq!(client.port(), Some(443));
+        assert!(client.is_tls());
+        assert_eq!(client.tls_version(), Some("TLSv1.3"));
+        assert_eq!(client.tls_cipher(), None);
+        assert_eq!(&data[len..], b"EHLO");
+        assert!(config.proxy_protocol(proxy, &data[..20]).is_err());
+
+        // X-Forwarded-For
+        for (value, expected) in [
+            ("192.0.2.1", Some("192.0.2.1")),
+            ("198.51.100.1, 192.0.2.1, 10.0.0.3", Some("192.0.2.1")),
+            ("10.0.0.4, 10.0.0.3", Some("10.0.0.4")),
+            ("[2001:db8::1]:4711", Some("2001:db8::1")),
+            ("192.0.2.1:4711, 10.0.0.3", Some("192.0.2.1")),
+            ("garbage, 192.0.2.1", Some("192.0.2.1")),
+            ("192.0.2.1, garbage", None),
+        ] {
+            assert_eq!(
+                config.forwarded_for(proxy, value).map(|c| c.ip()),
+                expected.map(|ip| ip.parse::<IpAddr>().unwrap()),
+                "{value}"
+            );
+        }
+        assert_eq!(config.forwarded_for(peer, "192.0.2.1").unwrap().ip(), peer);
+    }
+}
@@ -0,0 +1,448 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::{op::ResponseCode, rr::RecordType},
+    AsyncResolver, Name, TokioAsyncResolver,
+};
+use parking_lot::Mutex;
+
+use crate::{common::resolver::DnsConfig, Resolver};
+
+type Listener = Arc<dyn Fn(&UpstreamEvent) + Send + Sync>;
+
+/// Health checking settings for failover between upstream DNS servers.
+#[derive(Clone)]
+pub struct HealthCheck {
+    probe: String,
+    failure_threshold: u32,
+    listener: Option<Listener>,
+}
+
+/// Change in the health of the upstream DNS servers of a resolver.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamEvent {
+    /// An upstream stopped answering, with the error of the last query.
+    Down { upstream: String, error: String },
+    /// An upstream answered again.
+    Up { upstream: String },
+    /// Queries moved from one upstream to another.
+    Failover { from: String, to: String },
+}
+
+/// Health of an upstream DNS server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStatus {
+    name: String,
+    healthy: bool,
+    active: bool,
+    failures: u32,
+}
+
+pub(crate) struct Upstreams {
+    servers: Vec<Upstream>,
+    active: AtomicUsize,
+    failover: Mutex<()>,
+    check: HealthCheck,
+}
+
+struct Upstream {
+    name: String,
+    resolver: TokioAsyncResolver,
+    dns_config: DnsConfig,
+    // Uncached, so that probes always reach the server
+    resolver_probe: TokioAsyncResolver,
+    healthy: AtomicBool,
+    failures: AtomicU32,
+}
+
+impl Resolver {
+    /// Sends queries to the first healthy upstream, in order of preference.
+    /// An upstream is marked down after `HealthCheck::with_failure_threshold`
+    /// consecutive timeouts or connection errors, and the failed query is
+    /// retried on the next healthy upstream. Upstreams marked down are only
+    /// used again once a health check succeeds, see `check_upstreams`.
+    pub fn with_upstreams<N: Into<String>>(
+        mut self,
+        upstreams: impl IntoIterator<Item = (N, ResolverConfig)>,
+        options: ResolverOpts,
+        check: HealthCheck,
+    ) -> Self {
+        let servers = upstreams
+            .into_iter()
+            .map(|(name, config)| {
+                let mut probe_options = options.clone();
+                probe_options.cache_size = 0;
+                Upstream {
+                    name: name.into(),
+                    resolver: AsyncResolver::tokio(config.clone(), options.clone()),
+                    dns_config: DnsConfig::new(config.clone(), options.clone()),
+                    resolver_probe: AsyncResolver::tokio(config, probe_options),
+                    healthy: AtomicBool::new(true),
+                    failures: AtomicU32::new(0),
+                }
+            })
+            .collect::<Vec<_>>();
+        self.upstreams = (!servers.is_empty()).then(|| {
+            Arc::new(Upstreams {
+                servers,
+                active: AtomicUsize::new(0),
+                failover: Mutex::new(()),
+                check,
+            })
+        });
+        self
+    }
+
+    /// Probes every upstream, marking them up or down, and moves queries back
+    /// to the most preferred healthy upstream.
+    pub async fn check_upstreams(&self) -> Vec<UpstreamStatus> {
+        if let Some(upstreams) = &self.upstreams {
+            let probe = Name::from_str_relaxed(&upstreams.check.probe);
+            let results = futures_util::future::join_all(upstreams.servers.iter().map(|server| {
+                let probe = probe.clone();
+                async move {
+                    server
+                        .resolver_probe
+                        .lookup(probe?, RecordType::NS)
+                        .await
+                        .map(|_| ())
+                }
+            }))
+            .await;
+            for (server, result) in upstreams.servers.iter().zip(results) {
+                match result {
+                    Err(err) if is_upstream_failure(&err) => {
+                        server.failures.fetch_add(1, Ordering::Relaxed);
+                        if server.healthy.swap(false, Ordering::Relaxed) {
+                            upstreams.emit(UpstreamEvent::Down {
+                                upstream: server.name.clone(),
+                                error: err.to_string(),
+                            });
+                        }
+                    }
+                    _ => upstreams.mark_up(server),
+                }
+            }
+            upstreams.select();
+        }
+        self.upstream_status()
+    }
+
+    /// Checks the upstreams every `period`. The returned future never
+    /// completes and is meant to be spawned as a background task.
+    pub async fn check_upstreams_every(&self, period: Duration) {
+        loop {
+            tokio::time::sleep(period).await;
+            self.check_upstreams().await;
+        }
+    }
+
+    /// Returns the health of the upstreams, empty if none were configured.
+    pub fn upstream_status(&self) -> Vec<UpstreamStatus> {
+        self.upstreams
+            .as_ref()
+            .map(|upstreams| {
+                let active = upstreams.active.load(Ordering::Relaxed);
+                upstreams
+                    .servers
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, server)| UpstreamStatus {
+                        name: server.name.clone(),
+                        healthy: server.healthy.load(Ordering::Relaxed),
+                        active: idx == active,
+                        failures: server.failures.load(Ordering::Relaxed),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Sends a query to the active upstream, failing over to the next healthy
+    /// upstream on timeouts and connection errors. Offline resolvers answer
+    /// every query with `NXDOMAIN`.
+    pub(crate) async fn query<'x, T, F>(
+        &'x self,
+        dnssec: bool,
+        query: impl Fn(&'x TokioAsyncResolver) -> F,
+    ) -> Result<T, ResolveError>
+    where
+        F: Future<Output = Result<T, ResolveError>>,
+    {
+        if self.offline {
+            return Err(ResolveErrorKind::NoRecordsFound {
+                query: Box::default(),
+                soa: None,
+                negative_ttl: None,
+                response_code: ResponseCode::NXDomain,
+                trusted: true,
+            }
+            .into());
+        }
+
+        let upstreams = match &self.upstreams {
+            Some(upstreams) => upstreams,
+            None if dnssec => return query(self.dns_config.dnssec_resolver()).await,
+            None => return query(&self.resolver).await,
+        };
+
+        let mut idx = upstreams.active.load(Ordering::Relaxed);
+        let mut attempts = upstreams.servers.len();
+        loop {
+            let server = &upstreams.servers[idx];
+            let result = query(if dnssec {
+                server.dns_config.dnssec_resolver()
+            } else {
+                &server.resolver
+            })
+            .await;
+            attempts -= 1;
+            match upstreams.record(idx, result.as_ref().err()) {
+                Some(next) if attempts > 0 => idx = next,
+                _ => return result,
+            }
+        }
+    }
+}
+
+impl Upstreams {
+    /// Records the outcome of a query, returning the upstream to retry it on
+    /// if it failed and queries moved to another upstream.
+    fn record(&self, idx: usize, error: Option<&ResolveError>) -> Option<usize> {
+        let server = &self.servers[idx];
+        match error.filter(|err| is_upstream_failure(err)) {
+            None => {
+                self.mark_up(server);
+                None
+            }
+            Some(err) => {
+                let failures = server.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= self.check.failure_threshold
+                    && server.healthy.swap(false, Ordering::Relaxed)
+                {
+                    self.emit(UpstreamEvent::Down {
+                        upstream: server.name.clone(),
+                        error: err.to_string(),
+                    });
+                    self.select();
+                }
+                let active = self.active.load(Ordering::Relaxed);
+                (active != idx).then_some(active)
+            }
+        }
+    }
+
+    fn mark_up(&self, server: &Upstream) {
+        server.failures.store(0, Ordering::Relaxed);
+        if !server.healthy.swap(true, Ordering::Relaxed) {
+            self.emit(UpstreamEvent::Up {
+                upstream: server.name.clone(),
+            });
+        }
+    }
+
+    /// Moves queries to the most preferred healthy upstream. If all upstreams
+    /// are down, queries stay on the current one.
+    fn select(&self) {
+        let guard = self.failover.lock();
+        let from = self.active.load(Ordering::Relaxed);
+        let to = match self
+            .servers
+            .iter()
+            .position(|server| server.healthy.load(Ordering::Relaxed))
+        {
+            Some(to) if to != from => to,
+            _ => return,
+        };
+        self.active.store(to, Ordering::Relaxed);
+        drop(guard);
+        self.emit(UpstreamEvent::Failover {
+            from: self.servers[from].name.clone(),
+            to: self.servers[to].name.clone(),
+        });
+    }
+
+    fn emit(&self, event: UpstreamEvent) {
+        #[cfg(feature = "tracing")]
+        tracing::warn!(event = ?event, "dns upstream health changed");
+        if let Some(listener) = &self.check.listener {
+            listener(&event);
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn new() -> Self {
+        HealthCheck {
+            probe: ".".to_string(),
+            failure_threshold: 3,
+            listener: None,
+        }
+    }
+
+    /// Sets the name whose NS records are queried by health checks, the root
+    /// zone by default.
+    pub fn with_probe(mut self, name: impl Into<String>) -> Self {
+        self.probe = name.into();
+        self
+    }
+
+    /// Sets the number of consecutive failed queries after which an upstream
+    /// is marked down, 3 by default.
+    pub fn with_failure_threshold(mut self, failures: u32) -> Self {
+        self.failure_threshold = failures.max(1);
+        self
+    }
+
+    /// Sets a callback invoked when an upstream goes down or up, and on failover.
+    pub fn with_listener(
+        mut self,
+        listener: impl Fn(&UpstreamEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UpstreamStatus {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy
+    }
+
+    /// Returns `true` if queries are sent to this upstream.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Number of consecutive failed queries or health checks.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+}
+
+fn is_upstream_failure(err: &ResolveError) -> bool {
+    matches!(
+        err.kind(),
+        ResolveErrorKind::Timeout
+            | ResolveErrorKind::NoConnections
+            | ResolveErrorKind::Io(_)
+            | ResolveErrorKind::Proto(_)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use hickory_resolver::config::ResolverOpts;
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        common::test_dns::{empty_response, resolver_config, serve},
+        Error, Resolver,
+    };
+
+    use super::{HealthCheck, UpstreamEvent};
+
+    #[tokio::test]
+    async fn upstream_failover() {
+        let primary = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let backup = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (primary_addr, backup_addr) =
+            (primary.local_addr().unwrap(), backup.local_addr().unwrap());
+        serve(backup, empty_response);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut options = ResolverOpts::default();
+        options.timeout = Duration::from_millis(200);
+        options.attempts = 1;
+        let resolver = Resolver::new_system_conf().unwrap().with_upstreams(
+            [
+                ("primary", resolver_config(primary_addr)),
+                ("backup", resolver_config(backup_addr)),
+            ],
+            options,
+            HealthCheck::new().with_failure_threshold(1).with_listener({
+                let events = events.clone();
+                move |event| events.lock().unwrap().push(event.clone())
+            }),
+        );
+        assert!(resolver.upstream_status()[0].is_active());
+
+        // The query times out on the primary and is retried on the backup
+        assert!(matches!(
+            resolver.txt_raw_lookup("example.org").await,
+            Err(Error::DnsRecordNotFound(_))
+        ));
+        let status = resolver.upstream_status();
+        assert!(!status[0].is_healthy() && !status[0].is_active());
+        assert_eq!(status[0].failures(), 1);
+        assert!(status[1].is_healthy() && status[1].is_active());
+        assert!(matches!(
+            &events.lock().unwrap()[..],
+            [
+                UpstreamEvent::Down { upstream, .. },
+                UpstreamEvent::Failover { from, to }
+            ] if upstream == "primary" && from == "primary" && to == "backup"
+        ));
+
+        // Without a successful health check the primary stays down
+        let status = resolver.check_upstreams().await;
+        assert!(!status[0].is_healthy() && status[1].is_active());
+        assert_eq!(events.lock().unwrap().len(), 2);
+
+        // Once the primary answers again, queries move back to it
+        serve(primary, empty_response);
+        let status = resolver.check_upstreams().await;
+        assert!(status[0].is_healthy() && status[0].is_active());
+        assert_eq!(status[0].failures(), 0);
+        assert_eq!(
+            events.lock().unwrap()[2..],
+            [
+                UpstreamEvent::Up {
+                    upstream: "primary".to_string()
+                },
+                UpstreamEvent::Failover {
+                    from: "backup".to_string(),
+                    to: "primary".to_string()
+                }
+            ]
+        );
+        assert!(matches!(
+            resolver.txt_raw_lookup("example.org").await,
+            Err(Error::DnsRecordNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,367 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{fmt::Display, future::Future};
+
+use crate::{
+    report::{PolicyOverride, PolicyOverrideReason},
+    DkimOutput, DkimResult, DmarcOutput, Error, SpfIdentitiesOutput, SpfResult,
+};
+
+use super::pipeline::MessageAuthParams;
+
+/// Stage of `Resolver::verify_message_with_hooks` after which a hook runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PolicyStage {
+    Spf,
+    Dkim,
+    Dmarc,
+}
+
+/// Result forced by a `PolicyHooks` callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LocalOverride {
+    stage: PolicyStage,
+    pass: bool,
+    domain: String,
+    comment: Option<String>,
+}
+
+/// Local policy callbacks invoked by `Resolver::verify_message_with_hooks`
+/// after each evaluation stage. An override returned by a callback replaces
+/// the result of its stage before the next stage runs, so that a forced SPF or
+/// DKIM result is also used to evaluate DMARC.
+pub trait PolicyHooks: Sync {
+    /// Called once SPF has been evaluated, the override applies to the
+    /// identity used by DMARC.
+    fn after_spf(
+        &self,
+        _params: &MessageAuthParams<'_>,
+        _spf: &SpfIdentitiesOutput,
+    ) -> impl Future<Output = Option<LocalOverride>> + Send {
+        async { None }
+    }
+
+    /// Called once for each verified DKIM signature.
+    fn after_dkim(
+        &self,
+        _params: &MessageAuthParams<'_>,
+        _dkim: &DkimOutput<'_>,
+    ) -> impl Future<Output = Option<LocalOverride>> + Send {
+        async { None }
+    }
+
+    /// Called once DMARC has been evaluated, the override applies to the
+    /// disposition of the message.
+    fn after_dmarc(
+        &self,
+        _params: &MessageAuthParams<'_>,
+        _dmarc: &DmarcOutput,
+    ) -> impl Future<Output = Option<LocalOverride>> + Send {
+        async { None }
+    }
+}
+
+/// No local policy.
+impl PolicyHooks for () {}
+
+impl LocalOverride {
+    /// Forces the result of the stage to pass.
+    pub fn pass() -> Self {
+        LocalOverride {
+            stage: PolicyStage::Dmarc,
+            pass: true,
+            domain: String::new(),
+            comment: None,
+        }
+    }
+
+    /// Forces the result of the stage to fail.
+    pub fn fail() -> Self {
+        LocalOverride {
+            pass: false,
+            ..Self::pass()
+        }
+    }
+
+    /// Describes the local policy, such as `internal relay`.
+    pub fn with_comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub(crate) fn with_stage(mut self, stage: PolicyStage, domain: &str) -> Self {
+        self.stage = stage;
+        self.domain = domain.to_string();
+        self
+    }
+
+    /// Stage the override was returned for.
+    pub fn stage(&self) -> PolicyStage {
+        self.stage
+    }
+
+    pub fn is_pass(&self) -> bool {
+        self.pass
+    }
+
+    /// SPF domain, DKIM signing domain or RFC5322.From domain the override
+    /// applies to.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Reason to record in DMARC reports, such as
+    /// `local_policy (dkim=fail d=example.org: blocked selector)`.
+    pub fn reason(&self) -> PolicyOverrideReason {
+        PolicyOverrideReason::new(PolicyOverride::LocalPolicy).with_comment(self.to_string())
+    }
+
+    pub(crate) fn apply_spf(&self, spf: &mut SpfIdentitiesOutput) {
+        let output = if spf.is_null_sender {
+            &mut spf.helo
+        } else {
+            &mut spf.mail_from
+        };
+        output.result = if self.pass {
+            SpfResult::Pass
+        } else {
+            SpfResult::Fail
+        };
+    }
+
+    pub(crate) fn apply_dkim(&self, dkim: &mut DkimOutput<'_>) {
+        dkim.result = if self.pass {
+            DkimResult::Pass
+        } else {
+            DkimResult::Fail(Error::LocalPolicy)
+        };
+    }
+}
+
+impl Display for PolicyStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PolicyStage::Spf => "spf",
+            PolicyStage::Dkim => "dkim",
+            PolicyStage::Dmarc => "dmarc",
+        })
+    }
+}
+
+impl Display for LocalOverride {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}={} {}={}",
+            self.stage,
+            if self.pass { "pass" } else { "fail" },
+            if self.stage == PolicyStage::Dmarc {
+                "header.from"
+            } else {
+                "d"
+            },
+            self.domain
+        )?;
+        if let Some(comment) = &self.comment {
+            write!(f, ": {comment}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::{
+            headers::HeaderWriter,
+            parse::TxtRecordParser,
+            pipeline::MessageAuthParams,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::DkimSigner,
+        dmarc::Dmarc,
+        report::{ActionDisposition, PolicyOverride, Record},
+        spf::Spf,
+        AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
+        SpfIdentitiesOutput, SpfResult,
+    };
+
+    use super::{LocalOverride, PolicyHooks, PolicyStage};
+
+    struct LocalPolicy {
+        internal_relay: IpAddr,
+        blocked_selector: &'static str,
+        quarantine_all: bool,
+    }
+
+    impl PolicyHooks for LocalPolicy {
+        async fn after_spf(
+            &self,
+            params: &MessageAuthParams<'_>,
+            _spf: &SpfIdentitiesOutput,
+        ) -> Option<LocalOverride> {
+            (params.remote_ip() == self.internal_relay)
+                .then(|| LocalOverride::pass().with_comment("internal relay"))
+        }
+
+        async fn after_dkim(
+            &self,
+            _params: &MessageAuthParams<'_>,
+            dkim: &DkimOutput<'_>,
+        ) -> Option<LocalOverride> {
+            dkim.signature()
+                .filter(|signature| signature.s == self.blocked_selector)
+                .map(|_| LocalOverride::fail().with_comment("blocked selector"))
+        }
+
+        async fn after_dmarc(
+            &self,
+            _params: &MessageAuthParams<'_>,
+            _dmarc: &DmarcOutput,
+        ) -> Option<LocalOverride> {
+            self.quarantine_all.then(LocalOverride::fail)
+        }
+    }
+
+    #[tokio::test]
+    async fn policy_hooks() {
+        let resolver = ed25519_resolver("ed._domainkey.example.org.");
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:192.168.1.1 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::parse(b"v=DMARC1; p=quarantine").unwrap(),
+            valid_until,
+        );
+        resolver.ptr_add(
+            "10.0.0.1".parse().unwrap(),
+            vec!["relay.example.org.".to_string()],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "relay.example.org.",
+            vec!["10.0.0.1".parse().unwrap()],
+            valid_until,
+        );
+
+        let message = concat!(
+            "From: jdoe@example.org\r\n",
+            "To: bill@example.net\r\n",
+            "Subject: Hooks\r\n\r\n",
+            "Hi!\r\n"
+        );
+        let message = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .sign(message.as_bytes())
+            .unwrap()
+            .to_header()
+            + message;
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let params = MessageAuthParams::new(&message, "10.0.0.1".parse().unwrap())
+            .with_mail_from("jdoe@example.org");
+
+        // Without hooks, DMARC passes through DKIM only
+        let output = resolver.verify_message(params.clone()).await;
+        assert_eq!(output.spf().unwrap().mail_from().result(), SpfResult::Fail);
+        assert_eq!(output.dkim()[0].result(), &DkimResult::Pass);
+        assert!(output.local_overrides().is_empty());
+
+        // SPF is forced to pass for the internal relay, while the blocked
+        // selector fails DKIM
+        let mut hooks = LocalPolicy {
+            internal_relay: "10.0.0.1".parse().unwrap(),
+            blocked_selector: "ed",
+            quarantine_all: false,
+        };
+        let output = resolver
+            .verify_message_with_hooks(params.clone(), &hooks)
+            .await;
+        assert_eq!(output.spf().unwrap().mail_from().result(), SpfResult::Pass);
+        assert_eq!(
+            output.dkim()[0].result(),
+            &DkimResult::Fail(Error::LocalPolicy)
+        );
+        let dmarc = output.dmarc().unwrap();
+        assert_eq!(dmarc.spf_result(), &DmarcResult::Pass);
+        assert_eq!(dmarc.dkim_result(), &DmarcResult::None);
+        assert_eq!(
+            output
+                .local_overrides()
+                .iter()
+                .map(|local_override| (local_override.stage(), local_override.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    PolicyStage::Spf,
+                    "spf=pass d=example.org: internal relay".to_string()
+                ),
+                (
+                    PolicyStage::Dkim,
+                    "dkim=fail d=example.org: blocked selector".to_string()
+                )
+            ]
+        );
+        assert!(output
+            .authentication_results()
+            .to_string()
+            .contains("dkim=fail (local policy) header.d=example.org"));
+        let disposition = output.dmarc_disposition().unwrap();
+        assert_eq!(disposition.disposition(), ActionDisposition::Pass);
+        assert_eq!(disposition.reasons().len(), 2);
+        let record = Record::new().with_dmarc_disposition(&disposition);
+        assert_eq!(
+            record.policy_override_reason()[0].policy_override(),
+            PolicyOverride::LocalPolicy
+        );
+        assert_eq!(
+            record.policy_override_reason()[1].comment(),
+            Some("dkim=fail d=example.org: blocked selector")
+        );
+
+        // DMARC overrides apply the published policy
+        hooks.blocked_selector = "";
+        hooks.quarantine_all = true;
+        let output = resolver.verify_message_with_hooks(params, &hooks).await;
+        assert_eq!(output.dmarc().unwrap().dkim_result(), &DmarcResult::Pass);
+        let disposition = output.dmarc_disposition().unwrap();
+        assert!(disposition.is_quarantine());
+        assert_eq!(
+            disposition.comment(),
+            "dmarc-policy=quarantine disposition=quarantine reason=local_policy reason=local_policy"
+        );
+        assert_eq!(
+            output
+                .dmarc()
+                .unwrap()
+                .local_override()
+                .unwrap()
+                .to_string(),
+            "dmarc=fail header.from=example.org"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{common::domain::to_unicode, Domain};
+
+/// Flags domains that mix scripts or that look like a protected domain once
+/// confusable characters are replaced (UTS #39), such as `pаypal.com` written
+/// with a Cyrillic `а`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LookalikeDetector {
+    protected: Vec<(String, String)>,
+}
+
+/// Warning raised by a `LookalikeDetector`. Domains are reported as A-labels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LookalikeWarning {
+    /// A label of the domain mixes scripts that are not normally used together.
+    MixedScript(String),
+    /// The domain is visually confusable with a protected domain.
+    Confusable { domain: String, protected: String },
+}
+
+const LATIN: u16 = 1 << 0;
+const GREEK: u16 = 1 << 1;
+const CYRILLIC: u16 = 1 << 2;
+const ARMENIAN: u16 = 1 << 3;
+const HAN: u16 = 1 << 4;
+const HIRAGANA: u16 = 1 << 5;
+const KATAKANA: u16 = 1 << 6;
+const HANGUL: u16 = 1 << 7;
+const BOPOMOFO: u16 = 1 << 8;
+const OTHER: u16 = 1 << 9;
+
+// Script combinations allowed by the UTS #39 highly restrictive profile
+const ALLOWED_SCRIPTS: [u16; 3] = [
+    LATIN | HAN | HIRAGANA | KATAKANA,
+    LATIN | HAN | HANGUL,
+    LATIN | HAN | BOPOMOFO,
+];
+
+impl LookalikeDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a domain to protect, such as the domain of a brand or of the
+    /// organization itself. Its subdomains are not flagged.
+    pub fn with_protected_domain(mut self, domain: impl AsRef<str>) -> Self {
+        let domain = Domain::normalize(domain.as_ref());
+        let skeleton = skeleton(&domain);
+        self.protected.push((domain, skeleton));
+        self
+    }
+
+    pub fn protected_domains(&self) -> impl Iterator<Item = &str> {
+        self.protected.iter().map(|(domain, _)| domain.as_str())
+    }
+
+    /// Checks a domain name, given either as U-labels or A-labels.
+    pub fn check(&self, domain: &str) -> Vec<LookalikeWarning> {
+        let domain = Domain::normalize(domain);
+        let mut warnings = Vec::new();
+        if domain.is_empty() {
+            return warnings;
+        }
+
+        if to_unicode(&domain).split('.').any(is_mixed_script) {
+            warnings.push(LookalikeWarning::MixedScript(domain.clone()));
+        }
+
+        let domain_skeleton = skeleton(&domain);
+        for (protected, protected_skeleton) in &self.protected {
+            if !is_subdomain(&domain, protected)
+                && is_subdomain(&domain_skeleton, protected_skeleton)
+            {
+                warnings.push(LookalikeWarning::Confusable {
+                    domain: domain.clone(),
+                    protected: protected.clone(),
+                });
+            }
+        }
+
+        warnings
+    }
+}
+
+fn is_subdomain(domain: &str, parent: &str) -> bool {
+    domain == parent
+        || domain
+            .strip_suffix(parent)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn is_mixed_script(label: &str) -> bool {
+    let scripts = label.chars().fold(0, |scripts, ch| scripts | script(ch));
+    scripts.count_ones() > 1
+        && !ALLOWED_SCRIPTS
+            .iter()
+            .any(|allowed| scripts & !allowed == 0)
+}
+
+fn script(ch: char) -> u16 {
+    match ch {
+        '0'..='9' | '-' | '_' | '\u{0300}'..='\u{036F}' => 0,
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => LATIN,
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => GREEK,
+        '\u{0400}'..='\u{052F}' => CYRILLIC,
+        '\u{0530}'..='\u{058F}' => ARMENIAN,
+        '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' => HAN,
+        '\u{3040}'..='\u{309F}' => HIRAGANA,
+        '\u{30A0}'..='\u{30FF}' => KATAKANA,
+        '\u{1100}'..='\u{11FF}' | '\u{AC00}'..='\u{D7AF}' => HANGUL,
+        '\u{3100}'..='\u{312F}' => BOPOMOFO,
+        _ => OTHER,
+    }
+}
+
+/// Replaces confusable characters of a domain with their ASCII prototypes.
+fn skeleton(domain: &str) -> String {
+    let mut skeleton = String::with_capacity(domain.len());
+    for ch in to_unicode(domain).chars() {
+        match prototype(ch) {
+            Some(prototype) => skeleton.push(prototype),
+            None => skeleton.push(ch),
+        }
+    }
+    skeleton.replace("rn", "m").replace("vv", "w")
+}
+
+fn prototype(ch: char) -> Option<char> {
+    Some(match ch {
+        '0' | 'о' | 'ο' | 'օ' | 'ò'..='ö' | 'ø' => 'o',
+        '1' | 'ӏ' | 'ι' => 'l',
+        'а' | 'ɑ' | 'α' | 'à'..='å' => 'a',
+        'с' | 'ç' => 'c',
+        'ԁ' => 'd',
+        'е' | 'è'..='ë' => 'e',
+        'ɡ' => 'g',
+        'һ' | 'հ' => 'h',
+        'і' | 'ı' | 'ì'..='ï' => 'i',
+        'ј' => 'j',
+        'κ' => 'k',
+        'ո' | 'ñ' => 'n',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'υ' | 'ս' | 'ù'..='ü' => 'u',
+        'ν' => 'v',
+        'ԝ' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'γ' | 'ý' | 'ÿ' => 'y',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LookalikeDetector, LookalikeWarning};
+
+    #[test]
+    fn lookalike_domains() {
+        let detector = LookalikeDetector::new()
+            .with_protected_domain("PayPal.com.")
+            .with_protected_domain("example.org");
+        assert_eq!(
+            detector.protected_domains().collect::<Vec<_>>(),
+            ["paypal.com", "example.org"]
+        );
+
+        for (domain, expected) in [
+            ("paypal.com", vec![]),
+            ("mail.paypal.com", vec![]),
+            ("example.net", vec![]),
+            ("bücher.example", vec![]),
+            ("日本語テスト.jp", vec![]),
+            (
+                "pаypal.com",
+                vec![
+                    LookalikeWarning::MixedScript("xn--pypal-4ve.com".to_string()),
+                    LookalikeWarning::Confusable {
+                        domain: "xn--pypal-4ve.com".to_string(),
+                        protected: "paypal.com".to_string(),
+                    },
+                ],
+            ),
+            (
+                "xn--pypal-4ve.com",
+                vec![
+                    LookalikeWarning::MixedScript("xn--pypal-4ve.com".to_string()),
+                    LookalikeWarning::Confusable {
+                        domain: "xn--pypal-4ve.com".to_string(),
+                        protected: "paypal.com".to_string(),
+                    },
+                ],
+            ),
+            (
+                "secure.paypa1.com",
+                vec![LookalikeWarning::Confusable {
+                    domain: "secure.paypa1.com".to_string(),
+                    protected: "paypal.com".to_string(),
+                }],
+            ),
+            (
+                "exarnple.org",
+                vec![LookalikeWarning::Confusable {
+                    domain: "exarnple.org".to_string(),
+                    protected: "example.org".to_string(),
+                }],
+            ),
+            (
+                "ехample.net",
+                vec![LookalikeWarning::MixedScript(
+                    "xn--ample-ywe6i.net".to_string(),
+                )],
+            ),
+        ] {
+            assert_eq!(detector.check(domain), expected, "{domain}");
+        }
+    }
+}
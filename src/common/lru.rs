@@ -20,6 +20,17 @@ pub struct LruItem<V> {
     valid_until: Instant,
 }
 
+#[cfg(feature = "serde")]
+impl<V> LruItem<V> {
+    pub(crate) fn item(&self) -> &V {
+        &self.item
+    }
+
+    pub(crate) fn valid_until(&self) -> Instant {
+        self.valid_until
+    }
+}
+
 pub trait DnsCache<K, V>: Sized {
     fn with_capacity(capacity: usize) -> Self;
     fn get<Q>(&self, name: &Q) -> Option<V>
@@ -8,11 +8,94 @@
  * except according to those terms.
  */
 
+use std::borrow::Cow;
+
 use mail_parser::{parsers::MessageStream, Address, HeaderValue};
 
-use crate::{arc, common::crypto::HashAlgorithm, dkim, AuthenticatedMessage};
+use crate::{
+    arc,
+    common::crypto::HashAlgorithm,
+    dkim::{self, compat},
+    AuthenticatedMessage,
+};
+
+use super::{
+    headers::{AuthenticatedHeader, Header, HeaderParser},
+    parse::{TagStrictness, TagWarning},
+};
+
+/// Options for `AuthenticatedMessage::parse_with_options`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    strict: bool,
+    tag_strictness: TagStrictness,
+    undo_dot_stuffing: bool,
+    undo_mbox_quoting: bool,
+    detect_storage_artifacts: bool,
+}
 
-use super::headers::{AuthenticatedHeader, Header, HeaderParser};
+/// Transformation applied to a message by the way it was stored, which breaks
+/// DKIM body hashes unless undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageArtifact {
+    /// SMTP dot-stuffing left in place on lines starting with a dot (RFC 5321 section 4.5.2).
+    DotStuffing,
+    /// mbox `>From ` quoting of body lines starting with `From `.
+    MboxFromQuoting,
+    /// mbox `From ` separator line preceding the headers.
+    MboxFromLine,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rejects DKIM and ARC signatures with an `l=` tag, enabled by default.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_tag_strictness(mut self, tag_strictness: TagStrictness) -> Self {
+        self.tag_strictness = tag_strictness;
+        self
+    }
+
+    /// Removes the dot added by SMTP dot-stuffing to body lines starting with
+    /// a dot before computing body hashes, for raw SMTP captures.
+    pub fn with_undo_dot_stuffing(mut self, undo: bool) -> Self {
+        self.undo_dot_stuffing = undo;
+        self
+    }
+
+    /// Skips a leading mbox `From ` line and removes one `>` from body lines
+    /// matching `>From `, `>>From ` and so on before computing body hashes, for
+    /// messages extracted from mbox archives.
+    pub fn with_undo_mbox_quoting(mut self, undo: bool) -> Self {
+        self.undo_mbox_quoting = undo;
+        self
+    }
+
+    /// Reports storage artifacts found in the message without undoing them,
+    /// see `AuthenticatedMessage::storage_artifacts`.
+    pub fn with_detect_storage_artifacts(mut self, detect: bool) -> Self {
+        self.detect_storage_artifacts = detect;
+        self
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            strict: true,
+            tag_strictness: TagStrictness::Forgiving,
+            undo_dot_stuffing: false,
+            undo_mbox_quoting: false,
+            detect_storage_artifacts: false,
+        }
+    }
+}
 
 impl<'x> AuthenticatedMessage<'x> {
     pub fn parse(raw_message: &'x [u8]) -> Option<Self> {
@@ -20,11 +103,51 @@ impl<'x> AuthenticatedMessage<'x> {
     }
 
     pub fn parse_with_opts(raw_message: &'x [u8], strict: bool) -> Option<Self> {
+        Self::parse_with_strictness(raw_message, strict, TagStrictness::Forgiving)
+    }
+
+    /// Parses a message, rejecting DKIM and ARC headers with tag-list syntax
+    /// deviations when `tag_strictness` is strict.
+    pub fn parse_with_strictness(
+        raw_message: &'x [u8],
+        strict: bool,
+        tag_strictness: TagStrictness,
+    ) -> Option<Self> {
+        Self::parse_with_options(
+            raw_message,
+            &ParseOptions::new()
+                .with_strict(strict)
+                .with_tag_strictness(tag_strictness),
+        )
+    }
+
+    /// Parses a message, optionally undoing or detecting the dot-stuffing and
+    /// mbox quoting of messages stored in raw SMTP captures or mbox archives.
+    pub fn parse_with_options(raw_message: &'x [u8], options: &ParseOptions) -> Option<Self> {
+        let ParseOptions {
+            strict,
+            tag_strictness,
+            ..
+        } = *options;
+        let mut storage_artifacts = Vec::new();
+        let raw_message = match raw_message.strip_prefix(b"From ") {
+            Some(line) if options.undo_mbox_quoting || options.detect_storage_artifacts => {
+                storage_artifacts.push(StorageArtifact::MboxFromLine);
+                if options.undo_mbox_quoting {
+                    let pos = line.iter().position(|&ch| ch == b'\n')?;
+                    &line[pos + 1..]
+                } else {
+                    raw_message
+                }
+            }
+            _ => raw_message,
+        };
         let mut message = AuthenticatedMessage {
             headers: Vec::new(),
             from: Vec::new(),
             raw_message,
             body_offset: 0,
+            body_len: 0,
             body_hashes: Vec::new(),
             dkim_headers: Vec::new(),
             ams_headers: Vec::new(),
@@ -33,6 +156,8 @@ impl<'x> AuthenticatedMessage<'x> {
             received_headers_count: 0,
             date_header_present: false,
             message_id_header_present: false,
+            tag_warnings: Vec::new(),
+            storage_artifacts,
         };
 
         let mut headers = HeaderParser::new(raw_message);
@@ -42,24 +167,26 @@ impl<'x> AuthenticatedMessage<'x> {
             let name =
                 match header {
                     AuthenticatedHeader::Ds(name) => {
-                        let signature = match dkim::Signature::parse(value) {
-                            Ok(signature) if signature.l == 0 || !strict => {
-                                let ha = HashAlgorithm::from(signature.a);
-                                if !message.body_hashes.iter().any(|(c, h, l, _)| {
-                                    c == &signature.cb && h == &ha && l == &signature.l
-                                }) {
-                                    message.body_hashes.push((
-                                        signature.cb,
-                                        ha,
-                                        signature.l,
-                                        Vec::new(),
-                                    ));
+                        let signature =
+                            match dkim::Signature::parse_with_strictness(value, tag_strictness) {
+                                Ok((signature, warnings)) if signature.l == 0 || !strict => {
+                                    message.add_tag_warnings(name, value, warnings);
+                                    let ha = HashAlgorithm::from(signature.a);
+                                    if !message.body_hashes.iter().any(|(c, h, l, _)| {
+                                        c == &signature.cb && h == &ha && l == &signature.l
+                                    }) {
+                                        message.body_hashes.push((
+                                            signature.cb,
+                                            ha,
+                                            signature.l,
+                                            Vec::new(),
+                                        ));
+                                    }
+                                    Ok(signature)
                                 }
-                                Ok(signature)
-                            }
-                            Ok(_) => Err(crate::Error::SignatureLength),
-                            Err(err) => Err(err),
-                        };
+                                Ok(_) => Err(crate::Error::SignatureLength),
+                                Err(err) => Err(err),
+                            };
 
                         message
                             .dkim_headers
@@ -75,30 +202,32 @@ impl<'x> AuthenticatedMessage<'x> {
                         name
                     }
                     AuthenticatedHeader::Ams(name) => {
-                        let signature = match arc::Signature::parse(value) {
-                            Ok(signature) if signature.l == 0 || !strict => {
-                                let ha = HashAlgorithm::from(signature.a);
-                                if !message.body_hashes.iter().any(|(c, h, l, _)| {
-                                    c == &signature.cb && h == &ha && l == &signature.l
-                                }) {
-                                    message.body_hashes.push((
-                                        signature.cb,
-                                        ha,
-                                        signature.l,
-                                        Vec::new(),
-                                    ));
+                        let signature =
+                            match arc::Signature::parse_with_strictness(value, tag_strictness) {
+                                Ok((signature, warnings)) if signature.l == 0 || !strict => {
+                                    message.add_tag_warnings(name, value, warnings);
+                                    let ha = HashAlgorithm::from(signature.a);
+                                    if !message.body_hashes.iter().any(|(c, h, l, _)| {
+                                        c == &signature.cb && h == &ha && l == &signature.l
+                                    }) {
+                                        message.body_hashes.push((
+                                            signature.cb,
+                                            ha,
+                                            signature.l,
+                                            Vec::new(),
+                                        ));
+                                    }
+                                    Ok(signature)
                                 }
-                                Ok(signature)
-                            }
-                            Ok(_) => {
-                                has_arc_errors = true;
-                                Err(crate::Error::SignatureLength)
-                            }
-                            Err(err) => {
-                                has_arc_errors = true;
-                                Err(err)
-                            }
-                        };
+                                Ok(_) => {
+                                    has_arc_errors = true;
+                                    Err(crate::Error::SignatureLength)
+                                }
+                                Err(err) => {
+                                    has_arc_errors = true;
+                                    Err(err)
+                                }
+                            };
 
                         message
                             .ams_headers
@@ -106,7 +235,12 @@ impl<'x> AuthenticatedMessage<'x> {
                         name
                     }
                     AuthenticatedHeader::As(name) => {
-                        let seal = arc::Seal::parse(value);
+                        let seal = arc::Seal::parse_with_strictness(value, tag_strictness).map(
+                            |(seal, warnings)| {
+                                message.add_tag_warnings(name, value, warnings);
+                                seal
+                            },
+                        );
                         if !has_arc_errors {
                             has_arc_errors = seal.is_err();
                         }
@@ -155,11 +289,35 @@ impl<'x> AuthenticatedMessage<'x> {
         } else {
             message.body_offset = raw_message.len();
         }
-        let body = raw_message.get(message.body_offset..).unwrap_or_default();
+        let mut body = Cow::from(raw_message.get(message.body_offset..).unwrap_or_default());
+        for (artifact, undo, detect) in [
+            (
+                StorageArtifact::DotStuffing,
+                options.undo_dot_stuffing,
+                compat::has_dot_stuffing as fn(&[u8]) -> bool,
+            ),
+            (
+                StorageArtifact::MboxFromQuoting,
+                options.undo_mbox_quoting,
+                compat::has_mbox_quoting,
+            ),
+        ] {
+            if (undo || options.detect_storage_artifacts) && detect(&body) {
+                message.storage_artifacts.push(artifact);
+                if undo {
+                    body = match artifact {
+                        StorageArtifact::DotStuffing => compat::remove_dot_stuffing(&body),
+                        _ => compat::remove_mbox_quoting(&body),
+                    }
+                    .map_or(body, Cow::Owned);
+                }
+            }
+        }
+        message.body_len = body.len();
 
         // Calculate body hashes
         for (cb, ha, l, bh) in &mut message.body_hashes {
-            *bh = ha.hash(cb.canonical_body(body, *l)).as_ref().to_vec();
+            *bh = ha.hash(cb.canonical_body(&body, *l)).as_ref().to_vec();
         }
 
         // Sort ARC headers
@@ -190,6 +348,24 @@ impl<'x> AuthenticatedMessage<'x> {
         message.into()
     }
 
+    fn add_tag_warnings(&mut self, name: &'x [u8], value: &'x [u8], warnings: Vec<TagWarning>) {
+        self.tag_warnings.extend(
+            warnings
+                .into_iter()
+                .map(|warning| Header::new(name, value, warning)),
+        );
+    }
+
+    /// Returns the tag-list syntax deviations found in DKIM and ARC headers.
+    pub fn tag_warnings(&self) -> &[Header<'x, TagWarning>] {
+        &self.tag_warnings
+    }
+
+    /// Returns the storage artifacts detected, or undone, when parsing the message.
+    pub fn storage_artifacts(&self) -> &[StorageArtifact] {
+        &self.storage_artifacts
+    }
+
     pub fn received_headers_count(&self) -> usize {
         self.received_headers_count
     }
@@ -230,3 +406,72 @@ impl<'x> AuthenticatedMessage<'x> {
         self.from.first().map_or("", |f| f.as_str())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::AuthenticatedMessage;
+
+    use super::{ParseOptions, StorageArtifact};
+
+    #[test]
+    fn parse_storage_artifacts() {
+        let headers = concat!(
+            "DKIM-Signature: v=1; a=rsa-sha256; c=simple/simple; d=example.com;\r\n",
+            "\ts=sel; h=From:Subject; bh=YWJj; b=YWJj\r\n",
+            "From: bill@example.com\r\n",
+            "Subject: Minutes\r\n",
+            "\r\n"
+        );
+        let body = concat!(
+            "From the minutes:\r\n",
+            ".\r\n",
+            "..signature\r\n",
+            ">From here on\r\n",
+            "Done.\r\n"
+        );
+        let original = format!("{headers}{body}");
+        let stored = format!(
+            "From bill@example.com Tue Aug  1 10:00:00 2023\n{headers}{}",
+            body.replace("\r\n.", "\r\n..")
+                .replace("\r\n>From", "\r\n>>From")
+                .replacen("From the", ">From the", 1)
+        );
+        let original = AuthenticatedMessage::parse(original.as_bytes()).unwrap();
+        assert_eq!(original.storage_artifacts(), &[]);
+
+        // Artifacts are ignored by default
+        let message = AuthenticatedMessage::parse(stored.as_bytes()).unwrap();
+        assert_ne!(message.body_hashes, original.body_hashes);
+        assert_eq!(message.storage_artifacts(), &[]);
+
+        // Detected artifacts are not undone
+        let message = AuthenticatedMessage::parse_with_options(
+            stored.as_bytes(),
+            &ParseOptions::new().with_detect_storage_artifacts(true),
+        )
+        .unwrap();
+        assert_ne!(message.body_hashes, original.body_hashes);
+        assert_eq!(
+            message.storage_artifacts(),
+            &[
+                StorageArtifact::MboxFromLine,
+                StorageArtifact::DotStuffing,
+                StorageArtifact::MboxFromQuoting
+            ]
+        );
+
+        // Undone artifacts
+        let message = AuthenticatedMessage::parse_with_options(
+            stored.as_bytes(),
+            &ParseOptions::new()
+                .with_undo_dot_stuffing(true)
+                .with_undo_mbox_quoting(true),
+        )
+        .unwrap();
+        assert_eq!(message.body_hashes, original.body_hashes);
+        assert_eq!(message.body_len, original.body_len);
+        assert_eq!(message.from(), "bill@example.com");
+        assert_eq!(message.raw_headers(), original.raw_headers());
+        assert_eq!(message.storage_artifacts().len(), 3);
+    }
+}
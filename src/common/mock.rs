@@ -0,0 +1,337 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use hickory_resolver::proto::{op::ResponseCode, rr::RecordType};
+use parking_lot::Mutex;
+
+use crate::{dane::Tlsa, Error, Resolver, Txt, MX};
+
+use super::resolver::IntoFqdn;
+
+/// Scripted response of the test resolver, for exercising the retry, timeout
+/// and temporary error handling of callers.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MockResponse {
+    record_type: Option<RecordType>,
+    delay: Duration,
+    fault: Option<MockFault>,
+    limit: Option<u32>,
+}
+
+/// DNS misbehavior simulated by the test resolver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFault {
+    /// The server answers `SERVFAIL`.
+    ServFail,
+    /// The answer is truncated and the retry over TCP fails, leaving the
+    /// records that fit in the UDP response: the first record of address,
+    /// MX, PTR and TLSA answers, and no TXT records.
+    Truncated,
+    /// The answer echoes the query name in a different letter case than
+    /// sent with DNS 0x20 randomization, and is discarded as spoofed so that
+    /// the query times out.
+    WrongCase,
+}
+
+#[derive(Default)]
+pub(crate) struct MockDns {
+    scripts: Mutex<Vec<(String, MockResponse)>>,
+    record_queries: AtomicBool,
+    queries: Mutex<Vec<(RecordType, String)>>,
+}
+
+impl Resolver {
+    /// Scripts the responses to queries for `name`. Scripts are matched in the
+    /// order they were added, and a script with a limit is removed once it
+    /// was applied to that many queries.
+    pub fn mock_add<'x>(&self, name: impl IntoFqdn<'x>, response: MockResponse) {
+        self.mock
+            .scripts
+            .lock()
+            .push((normalize(name.into_fqdn().as_ref()), response));
+    }
+
+    /// Starts or stops recording the queries received by the test resolver.
+    /// Queries are not recorded by default, and stopping clears the log.
+    pub fn mock_record_queries(&self, enable: bool) {
+        self.mock.record_queries.store(enable, Ordering::Relaxed);
+        if !enable {
+            self.mock.queries.lock().clear();
+        }
+    }
+
+    /// Returns the queries recorded since `mock_record_queries(true)`, in order,
+    /// including those answered with records added by `txt_add` and similar methods.
+    pub fn mock_queries(&self) -> Vec<(RecordType, String)> {
+        self.mock.queries.lock().clone()
+    }
+
+    /// Applies the scripted response for a query, returning `true` if the
+    /// answer is truncated.
+    pub(crate) async fn mock_query(
+        &self,
+        record_type: RecordType,
+        name: &str,
+    ) -> crate::Result<bool> {
+        let name = normalize(name);
+        if self.mock.record_queries.load(Ordering::Relaxed) {
+            self.mock.queries.lock().push((record_type, name.clone()));
+        }
+        let response = {
+            let mut scripts = self.mock.scripts.lock();
+            let pos = scripts.iter().position(|(script_name, response)| {
+                script_name == &name
+                    && response
+                        .record_type
+                        .is_none_or(|script_type| script_type == record_type)
+            });
+            match pos {
+                Some(pos) => {
+                    let response = scripts[pos].1.clone();
+                    if let Some(limit) = &mut scripts[pos].1.limit {
+                        *limit -= 1;
+                        if *limit == 0 {
+                            scripts.remove(pos);
+                        }
+                    }
+                    response
+                }
+                None => return Ok(false),
+            }
+        };
+
+        if !response.delay.is_zero() {
+            tokio::time::sleep(response.delay).await;
+        }
+        match response.fault {
+            Some(MockFault::ServFail) => Err(Error::DnsRecordNotFound(ResponseCode::ServFail)),
+            Some(MockFault::WrongCase) => Err(Error::DnsError("request timed out".to_string())),
+            Some(MockFault::Truncated) => Ok(true),
+            None => Ok(false),
+        }
+    }
+}
+
+impl MockResponse {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only applies to queries of this record type.
+    pub fn with_record_type(mut self, record_type: RecordType) -> Self {
+        self.record_type = Some(record_type);
+        self
+    }
+
+    /// Waits before answering.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn with_fault(mut self, fault: MockFault) -> Self {
+        self.fault = Some(fault);
+        self
+    }
+
+    /// Only applies to the next `queries` matching queries, for example to
+    /// fail the first attempt of a lookup.
+    pub fn with_limit(mut self, queries: u32) -> Self {
+        self.limit = Some(queries.max(1));
+        self
+    }
+}
+
+pub(crate) trait MockTruncate: Sized {
+    fn truncate(self) -> crate::Result<Self>;
+
+    fn truncate_if(self, truncated: bool) -> crate::Result<Self> {
+        if truncated {
+            self.truncate()
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl MockTruncate for Txt {
+    fn truncate(self) -> crate::Result<Self> {
+        Err(Error::DnsRecordNotFound(ResponseCode::NoError))
+    }
+}
+
+impl MockTruncate for Arc<Vec<MX>> {
+    fn truncate(self) -> crate::Result<Self> {
+        Ok(Arc::new(
+            self.first()
+                .map(|mx| MX {
+                    exchanges: mx.exchanges.iter().take(1).cloned().collect(),
+                    preference: mx.preference,
+                })
+                .into_iter()
+                .collect(),
+        ))
+    }
+}
+
+impl MockTruncate for Arc<Tlsa> {
+    fn truncate(self) -> crate::Result<Self> {
+        Ok(Arc::new(Tlsa {
+            entries: self.entries.iter().take(1).cloned().collect(),
+        }))
+    }
+}
+
+macro_rules! impl_truncate {
+    ($($t:ty),+) => {
+        $(impl MockTruncate for Arc<Vec<$t>> {
+            fn truncate(self) -> crate::Result<Self> {
+                Ok(Arc::new(self.iter().take(1).cloned().collect()))
+            }
+        })+
+    };
+}
+
+impl_truncate!(Ipv4Addr, Ipv6Addr, String);
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use hickory_resolver::proto::{op::ResponseCode, rr::RecordType};
+
+    use crate::{
+        common::config::{Config, IprevPolicy},
+        Error, IprevResult, Resolver, MX,
+    };
+
+    use super::{MockFault, MockResponse};
+
+    #[tokio::test]
+    async fn mock_scripts() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        resolver.ipv4_add(
+            "mail.example.org.",
+            vec!["192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap()],
+            valid_until,
+        );
+        resolver.ptr_add(ip, vec!["mail.example.org.".to_string()], valid_until);
+        resolver.mx_add(
+            "example.org.",
+            vec![MX {
+                exchanges: vec!["mx1.example.org".to_string(), "mx2.example.org".to_string()],
+                preference: 10,
+            }],
+            valid_until,
+        );
+
+        // Queries are only recorded when enabled
+        resolver.ipv4_lookup("mail.example.org.").await.unwrap();
+        assert!(resolver.mock_queries().is_empty());
+        resolver.mock_record_queries(true);
+
+        // SERVFAIL on the first attempt only
+        resolver.mock_add(
+            "Mail.Example.org",
+            MockResponse::new()
+                .with_record_type(RecordType::A)
+                .with_fault(MockFault::ServFail)
+                .with_limit(1),
+        );
+        assert_eq!(
+            resolver.ipv4_lookup("mail.example.org.").await.unwrap_err(),
+            Error::DnsRecordNotFound(ResponseCode::ServFail)
+        );
+        assert_eq!(
+            resolver
+                .ipv4_lookup("mail.example.org.")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Truncated answers keep the first record
+        resolver.mock_add(
+            "example.org",
+            MockResponse::new().with_fault(MockFault::Truncated),
+        );
+        assert_eq!(
+            resolver.mx_lookup("example.org.").await.unwrap()[0].exchanges,
+            vec!["mx1.example.org".to_string()]
+        );
+        assert_eq!(
+            resolver
+                .ipv4_lookup("mail.example.org.")
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+
+        // Wrong-case answers are discarded
+        resolver.mock_add(
+            "mail.example.org",
+            MockResponse::new().with_fault(MockFault::WrongCase),
+        );
+        assert!(matches!(
+            resolver.ipv4_lookup("mail.example.org.").await,
+            Err(Error::DnsError(_))
+        ));
+
+        // Slow PTR answers exceed the iprev lookup timeout
+        resolver.set_config(Config::new().with_iprev_policy(
+            IprevPolicy::default().with_lookup_timeout(Duration::from_millis(50)),
+        ));
+        resolver.mock_add(
+            ip.to_string(),
+            MockResponse::new()
+                .with_delay(Duration::from_millis(500))
+                .with_limit(1),
+        );
+        assert!(matches!(
+            resolver.verify_iprev(ip).await.result(),
+            IprevResult::TempError(_)
+        ));
+
+        assert_eq!(
+            resolver.mock_queries(),
+            vec![
+                (RecordType::A, "mail.example.org".to_string()),
+                (RecordType::A, "mail.example.org".to_string()),
+                (RecordType::MX, "example.org".to_string()),
+                (RecordType::A, "mail.example.org".to_string()),
+                (RecordType::A, "mail.example.org".to_string()),
+                (RecordType::PTR, "192.0.2.1".to_string()),
+            ]
+        );
+        resolver.mock_record_queries(false);
+        resolver.mx_lookup("example.org.").await.unwrap();
+        assert!(resolver.mock_queries().is_empty());
+    }
+}
@@ -12,12 +12,39 @@ use crate::{Error, IprevResult};
 
 pub mod auth_results;
 pub mod base32;
+pub mod base64;
+pub mod codes;
+pub mod config;
+pub mod coverage;
 pub mod crypto;
+pub mod domain;
+pub mod explain;
+pub mod fairness;
+pub mod gateway;
 pub mod headers;
+pub mod health;
+pub mod hooks;
+pub mod lookalike;
 pub mod lru;
 pub mod message;
+#[cfg(any(test, feature = "test"))]
+pub mod mock;
+pub mod mx;
 pub mod parse;
+#[cfg(feature = "serde")]
+pub mod persist;
+pub mod pipeline;
+pub mod quorum;
+pub mod received;
 pub mod resolver;
+#[cfg(feature = "serde")]
+pub(crate) mod serde;
+pub mod stream;
+pub mod tenant;
+#[cfg(test)]
+pub(crate) mod test_dns;
+#[cfg(any(feature = "ring", feature = "rust-crypto"))]
+pub(crate) mod test_key;
 pub mod verify;
 
 impl From<Error> for IprevResult {
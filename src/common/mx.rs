@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::{Error, Resolver, MX};
+
+use super::resolver::IntoFqdn;
+
+/// How a domain receives mail, according to its MX records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MxPolicy {
+    /// Mail is delivered to these exchanges, ordered by preference.
+    Exchanges(Arc<Vec<MX>>),
+    /// The domain has no MX records, so mail is delivered to its own address
+    /// records (RFC 5321 section 5.1).
+    Implicit,
+    /// The domain publishes a Null MX record and does not accept mail (RFC 7505).
+    NullMx,
+    /// The domain has neither MX nor address records.
+    NoMail,
+}
+
+impl MxPolicy {
+    /// Returns `true` if mail can be delivered to the domain.
+    pub fn accepts_mail(&self) -> bool {
+        matches!(self, MxPolicy::Exchanges(_) | MxPolicy::Implicit)
+    }
+}
+
+impl MX {
+    /// Returns `true` if this is a Null MX record (`0 .`).
+    pub fn is_null(&self) -> bool {
+        self.preference == 0 && self.exchanges.iter().all(|e| is_null_exchange(e))
+    }
+}
+
+impl Resolver {
+    /// Classifies the MX records of a domain. DNS errors are returned as such,
+    /// so they can be told apart from domains that do not accept mail.
+    pub async fn mx_policy<'x>(&self, domain: impl IntoFqdn<'x>) -> crate::Result<MxPolicy> {
+        let domain = domain.into_fqdn();
+        match self.mx_lookup(domain.as_ref()).await {
+            Ok(records) if !records.is_empty() => return Ok(mx_records_policy(records)),
+            Ok(_) | Err(Error::DnsRecordNotFound(_)) => (),
+            Err(err) => return Err(err),
+        }
+
+        if self.exists(domain.as_ref()).await? {
+            Ok(MxPolicy::Implicit)
+        } else {
+            Ok(MxPolicy::NoMail)
+        }
+    }
+}
+
+/// Classifies a non-empty set of MX records. Null MX records published next
+/// to regular exchanges, which RFC 7505 forbids, are ignored.
+pub(crate) fn mx_records_policy(records: Arc<Vec<MX>>) -> MxPolicy {
+    if !records
+        .iter()
+        .any(|mx| mx.exchanges.iter().any(|e| is_null_exchange(e)))
+    {
+        MxPolicy::Exchanges(records)
+    } else {
+        let records = records
+            .iter()
+            .filter_map(|mx| {
+                let exchanges = mx
+                    .exchanges
+                    .iter()
+                    .filter(|e| !is_null_exchange(e))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (!exchanges.is_empty()).then_some(MX {
+                    exchanges,
+                    preference: mx.preference,
+                })
+            })
+            .collect::<Vec<_>>();
+        if records.is_empty() {
+            MxPolicy::NullMx
+        } else {
+            MxPolicy::Exchanges(Arc::new(records))
+        }
+    }
+}
+
+fn is_null_exchange(exchange: &str) -> bool {
+    exchange.is_empty() || exchange == "."
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{Error, Resolver, MX};
+
+    use super::MxPolicy;
+
+    #[tokio::test]
+    async fn mx_policy() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        let mx = |preference, exchanges: &[&str]| MX {
+            exchanges: exchanges.iter().map(|e| e.to_string()).collect(),
+            preference,
+        };
+        resolver.mx_add("null.example.org.", vec![mx(0, &["."])], valid_until);
+        resolver.mx_add(
+            "mixed.example.org.",
+            vec![mx(0, &["."]), mx(10, &["mx.example.org."])],
+            valid_until,
+        );
+        resolver.mx_add(
+            "mail.example.org.",
+            vec![mx(10, &["mx1.example.org.", "mx2.example.org."])],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "implicit.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+
+        assert!(MX {
+            exchanges: vec![".".to_string()],
+            preference: 0
+        }
+        .is_null());
+        assert_eq!(
+            resolver.mx_policy("null.example.org").await.unwrap(),
+            MxPolicy::NullMx
+        );
+        assert_eq!(
+            resolver.mx_policy("mixed.example.org").await.unwrap(),
+            MxPolicy::Exchanges(Arc::new(vec![mx(10, &["mx.example.org."])]))
+        );
+        assert_eq!(
+            resolver.mx_policy("mail.example.org.").await.unwrap(),
+            MxPolicy::Exchanges(Arc::new(vec![mx(
+                10,
+                &["mx1.example.org.", "mx2.example.org."]
+            )]))
+        );
+        assert_eq!(
+            resolver.mx_policy("implicit.example.org").await.unwrap(),
+            MxPolicy::Implicit
+        );
+        assert_eq!(
+            resolver.mx_policy("nxdomain.example.org").await.unwrap(),
+            MxPolicy::NoMail
+        );
+        assert!(matches!(
+            resolver.mx_policy("_dns_error.example.org").await,
+            Err(Error::DnsError(_))
+        ));
+        assert!(MxPolicy::Implicit.accepts_mail());
+        assert!(!MxPolicy::NullMx.accepts_mail());
+    }
+}
@@ -8,7 +8,7 @@
  * except according to those terms.
  */
 
-use std::{borrow::Cow, slice::Iter};
+use std::{borrow::Cow, fmt::Display, slice::Iter};
 
 use mail_parser::decoders::quoted_printable::quoted_printable_decode_char;
 
@@ -22,6 +22,9 @@ pub(crate) const H: u64 = b'h' as u64;
 pub(crate) const I: u64 = b'i' as u64;
 pub(crate) const K: u64 = b'k' as u64;
 pub(crate) const L: u64 = b'l' as u64;
+pub(crate) const MC: u64 = (b'm' as u64) | ((b'c' as u64) << 8);
+pub(crate) const MD: u64 = (b'm' as u64) | ((b'd' as u64) << 8);
+pub(crate) const MV: u64 = (b'm' as u64) | ((b'v' as u64) << 8);
 pub(crate) const N: u64 = b'n' as u64;
 pub(crate) const O: u64 = b'o' as u64;
 pub(crate) const P: u64 = b'p' as u64;
@@ -33,7 +36,32 @@ pub(crate) const X: u64 = b'x' as u64;
 pub(crate) const Y: u64 = b'y' as u64;
 pub(crate) const Z: u64 = b'z' as u64;
 
+/// Tag-list parsing mode for DKIM-Signature and ARC headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TagStrictness {
+    /// Reject tag-lists with duplicate tags or misplaced whitespace.
+    Strict,
+    /// Accept common real-world sloppiness, reporting it as warnings.
+    #[default]
+    Forgiving,
+}
+
+/// Deviation from the RFC 6376 tag-list syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TagWarning {
+    DuplicateTag(String),
+    InvalidTagName(String),
+    MissingValue(String),
+    WhitespaceInValue(String),
+}
+
 pub trait TxtRecordParser: Sized {
+    /// Whether publishing more than one valid record at a name is an error.
+    const UNIQUE: bool = false;
+
+    /// Deprecated DNS record type formerly used to publish these records.
+    const LEGACY_RECORD_TYPE: Option<u16> = None;
+
     fn parse(record: &[u8]) -> crate::Result<Self>;
 }
 
@@ -51,6 +79,7 @@ pub(crate) trait TagParser: Sized {
     fn ignore(&mut self);
     fn seek_tag_end(&mut self) -> bool;
     fn next_skip_whitespaces(&mut self) -> Option<u8>;
+    fn base64(&mut self, max_len: usize) -> crate::Result<Vec<u8>>;
 }
 
 pub(crate) trait ItemParser: Sized {
@@ -334,6 +363,11 @@ impl TagParser for Iter<'_, u8> {
         None
     }
 
+    #[inline(always)]
+    fn base64(&mut self, max_len: usize) -> crate::Result<Vec<u8>> {
+        super::base64::decode_tag(self, max_len)
+    }
+
     fn items<T: ItemParser>(&mut self) -> Vec<T> {
         let mut buf = Vec::with_capacity(10);
         let mut items = Vec::new();
@@ -407,3 +441,121 @@ impl ItemParser for Cow<'_, str> {
         )
     }
 }
+
+/// Returns the deviations from the RFC 6376 tag-list syntax found in a header.
+pub fn tag_list_warnings(header: &[u8]) -> Vec<TagWarning> {
+    let mut warnings = Vec::new();
+    let mut names: Vec<&[u8]> = Vec::new();
+
+    for tag_spec in header.split(|&ch| ch == b';') {
+        if tag_spec.iter().all(|ch| ch.is_ascii_whitespace()) {
+            continue;
+        }
+        let (name, value) = match tag_spec.iter().position(|&ch| ch == b'=') {
+            Some(pos) => (trim(&tag_spec[..pos]), trim(&tag_spec[pos + 1..])),
+            None => {
+                warnings.push(TagWarning::MissingValue(lossy(trim(tag_spec))));
+                continue;
+            }
+        };
+
+        if !name.first().is_some_and(|ch| ch.is_ascii_alphabetic())
+            || !name
+                .iter()
+                .all(|&ch| ch.is_ascii_alphanumeric() || ch == b'_')
+        {
+            warnings.push(TagWarning::InvalidTagName(lossy(name)));
+        } else if names.contains(&name) {
+            warnings.push(TagWarning::DuplicateTag(lossy(name)));
+        } else {
+            names.push(name);
+        }
+
+        // Only signatures, header lists, query methods and copied headers
+        // allow FWS within their value (RFC 6376 section 3.5)
+        if !matches!(name, b"b" | b"bh" | b"h" | b"q" | b"z")
+            && value.iter().any(|ch| ch.is_ascii_whitespace())
+        {
+            warnings.push(TagWarning::WhitespaceInValue(lossy(name)));
+        }
+    }
+
+    warnings
+}
+
+/// Parses a tag-list header, rejecting it on syntax deviations when strict and
+/// reporting them otherwise.
+pub(crate) fn parse_tag_list<T>(
+    header: &[u8],
+    strictness: TagStrictness,
+    parse: impl FnOnce(&[u8]) -> crate::Result<T>,
+) -> crate::Result<(T, Vec<TagWarning>)> {
+    let warnings = tag_list_warnings(header);
+    if strictness == TagStrictness::Strict && !warnings.is_empty() {
+        Err(crate::Error::ParseError)
+    } else {
+        parse(header).map(|value| (value, warnings))
+    }
+}
+
+fn trim(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|ch| !ch.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let end = bytes
+        .iter()
+        .rposition(|ch| !ch.is_ascii_whitespace())
+        .map_or(start, |pos| pos + 1);
+    &bytes[start..end]
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+impl Display for TagWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagWarning::DuplicateTag(tag) => write!(f, "Duplicate tag {tag:?}"),
+            TagWarning::InvalidTagName(tag) => write!(f, "Invalid tag name {tag:?}"),
+            TagWarning::MissingValue(tag) => write!(f, "Tag {tag:?} has no value"),
+            TagWarning::WhitespaceInValue(tag) => {
+                write!(f, "Unexpected whitespace in value of tag {tag:?}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tag_list_warnings, TagWarning};
+
+    #[test]
+    fn tag_list_lint() {
+        for (header, expected_warnings) in [
+            (
+                concat!(
+                    "v=1; a=rsa-sha256; d=example.net; s=brisbane;\r\n",
+                    " c=relaxed/simple; h=from : to;\r\n b=dzdVyOfAKCdLXdJOc9G2q8LoXSlEniSb\r\n",
+                    "  av+yuU4zGeeruD00lszZVoG4ZHRNiYzR;"
+                ),
+                vec![],
+            ),
+            (
+                "v=1; d=example.net; d=example.org; s=sel",
+                vec![TagWarning::DuplicateTag("d".to_string())],
+            ),
+            (
+                "v=1; d=exa mple.net; b h=abcd; s",
+                vec![
+                    TagWarning::WhitespaceInValue("d".to_string()),
+                    TagWarning::InvalidTagName("b h".to_string()),
+                    TagWarning::MissingValue("s".to_string()),
+                ],
+            ),
+        ] {
+            assert_eq!(tag_list_warnings(header.as_bytes()), expected_warnings);
+        }
+    }
+}
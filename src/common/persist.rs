@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    fs,
+    future::Future,
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Resolver, Txt};
+
+use super::{lru::DnsCache, verify::DomainKey};
+
+/// A DKIM public key cached by a resolver, along with the UNIX time it expires at.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CachedKey {
+    pub name: String,
+    pub key: Arc<DomainKey>,
+    pub expires: u64,
+}
+
+/// Persistent storage for the DKIM keys cached by a resolver.
+pub trait CacheStore: Sync {
+    /// Replaces the stored keys.
+    fn save(&self, keys: Vec<CachedKey>) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Returns the stored keys.
+    fn load(&self) -> impl Future<Output = io::Result<Vec<CachedKey>>> + Send;
+}
+
+/// Stores cached keys as JSON in a file, replaced atomically on each save.
+#[derive(Debug, Clone)]
+pub struct FileCacheStore {
+    path: PathBuf,
+}
+
+impl Resolver {
+    /// Returns the unexpired DKIM keys in the cache, from the least to the most
+    /// recently used.
+    pub fn cached_keys(&self) -> Vec<CachedKey> {
+        let now = Instant::now();
+        let unix_now = unix_time();
+        self.cache_txt
+            .lock()
+            .iter()
+            .filter_map(|(name, entry)| match entry.item() {
+                Txt::DomainKey(key)
+                    if entry.valid_until() > now && key.p.public_key().is_some() =>
+                {
+                    Some(CachedKey {
+                        name: name.clone(),
+                        key: key.clone(),
+                        expires: unix_now + (entry.valid_until() - now).as_secs(),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Adds previously cached DKIM keys to the cache, skipping expired keys.
+    /// Returns the number of keys added.
+    pub fn restore_keys(&self, keys: impl IntoIterator<Item = CachedKey>) -> usize {
+        let now = Instant::now();
+        let unix_now = unix_time();
+        let mut restored = 0;
+        for key in keys {
+            if key.expires > unix_now {
+                self.cache_txt.insert(
+                    key.name,
+                    Txt::DomainKey(key.key),
+                    now + Duration::from_secs(key.expires - unix_now),
+                );
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    /// Saves the cached DKIM keys to a store, returning the number of keys saved.
+    pub async fn flush_to(&self, store: &impl CacheStore) -> io::Result<usize> {
+        let keys = self.cached_keys();
+        let num_keys = keys.len();
+        store.save(keys).await.map(|_| num_keys)
+    }
+
+    /// Loads the DKIM keys of a store into the cache, returning the number of keys restored.
+    pub async fn restore_from(&self, store: &impl CacheStore) -> io::Result<usize> {
+        store.load().await.map(|keys| self.restore_keys(keys))
+    }
+
+    /// Saves the cached DKIM keys to a store every `period`. The returned
+    /// future only completes if saving fails and is meant to be spawned
+    /// as a background task.
+    pub async fn persist_every(&self, store: &impl CacheStore, period: Duration) -> io::Result<()> {
+        loop {
+            tokio::time::sleep(period).await;
+            self.flush_to(store).await?;
+        }
+    }
+}
+
+impl FileCacheStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileCacheStore { path: path.into() }
+    }
+}
+
+impl CacheStore for FileCacheStore {
+    async fn save(&self, keys: Vec<CachedKey>) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&keys)?)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    async fn load(&self) -> io::Result<Vec<CachedKey>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(Into::into),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::{test_key::ed25519_domain_key, verify::DomainKey},
+        Resolver,
+    };
+
+    use super::FileCacheStore;
+
+    #[tokio::test]
+    async fn persist_cache() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "sel._domainkey.example.org.",
+            ed25519_domain_key(),
+            Instant::now() + Duration::from_secs(3600),
+        );
+        resolver.txt_add(
+            "expired._domainkey.example.org.",
+            ed25519_domain_key(),
+            Instant::now(),
+        );
+
+        let path = std::env::temp_dir().join(format!("mail-auth-keys-{}.json", std::process::id()));
+        let store = FileCacheStore::new(&path);
+        assert_eq!(resolver.flush_to(&store).await.unwrap(), 1);
+
+        // Keys are restored into an empty cache with their remaining lifetime
+        let restored = Resolver::new_system_conf().unwrap();
+        assert_eq!(restored.restore_from(&store).await.unwrap(), 1);
+        std::fs::remove_file(&path).unwrap();
+        let keys = restored.cached_keys();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].name, "sel._domainkey.example.org.");
+        assert!(restored
+            .txt_lookup::<DomainKey>("sel._domainkey.example.org.")
+            .await
+            .is_ok());
+
+        // Missing stores are empty
+        assert_eq!(restored.restore_from(&store).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,966 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{future::Future, net::IpAddr, time::SystemTime};
+
+use crate::{
+    arc::ArcSealer,
+    common::{
+        crypto::{Sha256, SigningKey},
+        gateway::{GatewayConfig, OriginalClient},
+        hooks::{LocalOverride, PolicyHooks, PolicyStage},
+        lookalike::{LookalikeDetector, LookalikeWarning},
+    },
+    dkim::Done,
+    dmarc::disposition::DmarcDisposition,
+    report::{AuthFailureType, Feedback, FeedbackType, IdentityAlignment},
+    ArcOutput, AuthenticatedMessage, AuthenticationResults, DkimOutput, DkimResult, DmarcOutput,
+    DmarcResult, Domain, Error, IprevOutput, MessageAuthOutput, ReceivedSpf, Resolver,
+    SpfIdentitiesOutput, SpfResult,
+};
+
+use super::headers::{HeaderWriter, Writer};
+
+/// SMTP session details and message to authenticate with `Resolver::verify_message`.
+#[derive(Debug, Clone)]
+pub struct MessageAuthParams<'x> {
+    message: &'x AuthenticatedMessage<'x>,
+    remote_ip: IpAddr,
+    helo: &'x str,
+    mail_from: &'x str,
+    hostname: &'x str,
+    timestamp: Option<u64>,
+    client: Option<OriginalClient>,
+}
+
+/// User-supplied record of when domains were first seen, such as a passive DNS
+/// or registration date database, consulted to flag newly observed domains.
+pub trait FirstSeenStore: Sync {
+    /// Returns the UNIX time the domain was first seen, or `None` if it is unknown.
+    fn first_seen(&self, domain: &str) -> impl Future<Output = Option<u64>> + Send;
+}
+
+impl<'x> MessageAuthParams<'x> {
+    pub fn new(message: &'x AuthenticatedMessage<'x>, remote_ip: IpAddr) -> Self {
+        MessageAuthParams {
+            message,
+            remote_ip,
+            helo: "",
+            mail_from: "",
+            hostname: "localhost",
+            timestamp: None,
+            client: None,
+        }
+    }
+
+    pub fn with_helo(mut self, helo: &'x str) -> Self {
+        self.helo = helo;
+        self
+    }
+
+    /// Sets the MAIL FROM address, an empty address denotes a null reverse-path.
+    pub fn with_mail_from(mut self, mail_from: &'x str) -> Self {
+        self.mail_from = mail_from;
+        self
+    }
+
+    /// Sets the hostname of the receiving server used in the generated headers.
+    pub fn with_hostname(mut self, hostname: &'x str) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Verifies DKIM and ARC signature expiration as of a UNIX timestamp,
+    /// such as the time an archived message was received, instead of now.
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp.into();
+        self
+    }
+
+    /// Evaluates SPF and iprev against the original client when the message
+    /// was relayed by trusted gateways, as recorded in its `Received` headers.
+    /// The SMTP peer is used when it is not trusted or the client cannot be located.
+    pub fn with_gateway(mut self, gateway: &GatewayConfig) -> Self {
+        self.client = gateway
+            .original_client(self.message, self.remote_ip)
+            .filter(|client| client.hops() > 0);
+        self
+    }
+
+    pub fn message(&self) -> &'x AuthenticatedMessage<'x> {
+        self.message
+    }
+
+    pub fn remote_ip(&self) -> IpAddr {
+        self.remote_ip
+    }
+
+    pub fn helo(&self) -> &'x str {
+        self.helo
+    }
+
+    pub fn mail_from(&self) -> &'x str {
+        self.mail_from
+    }
+
+    /// Returns the client located behind the trusted gateways, if any.
+    pub fn original_client(&self) -> Option<&OriginalClient> {
+        self.client.as_ref()
+    }
+
+    fn client_ip(&self) -> IpAddr {
+        self.client
+            .as_ref()
+            .map_or(self.remote_ip, |client| client.ip())
+    }
+
+    fn client_helo(&self) -> &str {
+        match &self.client {
+            Some(client) => client.helo().unwrap_or_default(),
+            None => self.helo,
+        }
+    }
+}
+
+impl Resolver {
+    /// Verifies the DKIM signatures, ARC chain, SPF identities, reverse IP and
+    /// DMARC policy of a message. Checks that do not depend on each other are
+    /// run concurrently.
+    pub async fn verify_message<'x>(&self, params: MessageAuthParams<'x>) -> MessageAuthOutput<'x> {
+        self.verify_message_with_hooks(params, &()).await
+    }
+
+    /// Verifies a message like `verify_message`, invoking the `PolicyHooks`
+    /// callbacks after the SPF, DKIM and DMARC stages. The overrides they
+    /// return are applied to the results and recorded in the output.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                remote_ip = %params.client_ip(),
+                helo = params.client_helo(),
+                mail_from = params.mail_from
+            )
+        )
+    )]
+    pub async fn verify_message_with_hooks<'x>(
+        &self,
+        params: MessageAuthParams<'x>,
+        hooks: &impl PolicyHooks,
+    ) -> MessageAuthOutput<'x> {
+        let message = params.message;
+        let now = params.timestamp.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+        let (mut dkim, arc, mut spf, iprev) = futures_util::future::join4(
+            self.verify_dkim_at(message, now),
+            self.verify_arc_at(message, now),
+            self.verify_spf_identities(
+                params.client_ip(),
+                params.client_helo(),
+                params.hostname,
+                params.mail_from,
+            ),
+            self.verify_iprev(params.client_ip()),
+        )
+        .await;
+
+        let mut local_overrides = Vec::new();
+        if let Some(local_override) = hooks.after_spf(&params, &spf).await {
+            let local_override =
+                local_override.with_stage(PolicyStage::Spf, spf.combined().domain());
+            local_override.apply_spf(&mut spf);
+            local_overrides.push(local_override);
+        }
+        let dkim_overrides = futures_util::future::join_all(
+            dkim.iter().map(|output| hooks.after_dkim(&params, output)),
+        )
+        .await;
+        for (output, local_override) in dkim.iter_mut().zip(dkim_overrides) {
+            if let Some(local_override) = local_override {
+                let local_override = local_override.with_stage(
+                    PolicyStage::Dkim,
+                    output
+                        .signature()
+                        .map_or("", |signature| signature.d.as_str()),
+                );
+                local_override.apply_dkim(output);
+                local_overrides.push(local_override);
+            }
+        }
+
+        let mail_from_domain = params
+            .mail_from
+            .rsplit_once('@')
+            .map_or(params.client_helo(), |(_, domain)| domain);
+        let dmarc = self
+            .verify_dmarc(message, &dkim, mail_from_domain, spf.combined())
+            .await;
+        let mut dmarc = self.verify_dmarc_arc_override(dmarc, &arc);
+        if let Some(local_override) = hooks.after_dmarc(&params, &dmarc).await {
+            let local_override = local_override.with_stage(PolicyStage::Dmarc, dmarc.domain());
+            dmarc = dmarc.with_local_override(local_override);
+        }
+
+        let output = MessageAuthOutput::new(
+            params.hostname,
+            params.client_ip(),
+            params.client_helo(),
+            params.mail_from,
+        )
+        .with_dkim_results(dkim, message.from.first().cloned().unwrap_or_default())
+        .with_spf_results(spf)
+        .with_arc_result(arc)
+        .with_iprev_result(iprev)
+        .with_dmarc_result(dmarc)
+        .with_local_overrides(local_overrides);
+        match self.config().lookalike_detector() {
+            Some(detector) => output.with_lookalike_check(detector),
+            None => output,
+        }
+    }
+
+    /// Verifies a message like `verify_message` and also looks up when its
+    /// RFC5322.From and DKIM domains were first seen.
+    pub async fn verify_message_with_first_seen<'x>(
+        &self,
+        params: MessageAuthParams<'x>,
+        store: &impl FirstSeenStore,
+    ) -> MessageAuthOutput<'x> {
+        self.verify_message(params)
+            .await
+            .with_first_seen(store)
+            .await
+    }
+}
+
+impl<'x> MessageAuthOutput<'x> {
+    /// Creates an empty output for a message received from `remote_ip`. The
+    /// headers returned by this output are always rendered from the results it
+    /// holds, so that the logged verdict and the added headers never diverge.
+    pub fn new(
+        hostname: impl Into<String>,
+        remote_ip: IpAddr,
+        helo: impl Into<String>,
+        mail_from: impl Into<String>,
+    ) -> Self {
+        MessageAuthOutput {
+            hostname: hostname.into(),
+            remote_ip,
+            helo: helo.into(),
+            mail_from: mail_from.into(),
+            header_from: String::new(),
+            dkim: Vec::new(),
+            spf: None,
+            arc: None,
+            iprev: None,
+            dmarc: None,
+            arc_set: None,
+            first_seen: Vec::new(),
+            lookalike: Vec::new(),
+            local_overrides: Vec::new(),
+        }
+    }
+
+    pub fn with_dkim_results(
+        mut self,
+        dkim: Vec<DkimOutput<'x>>,
+        header_from: impl Into<String>,
+    ) -> Self {
+        self.dkim = dkim;
+        self.header_from = header_from.into();
+        self
+    }
+
+    pub fn with_spf_results(mut self, spf: SpfIdentitiesOutput) -> Self {
+        self.spf = spf.into();
+        self
+    }
+
+    pub fn with_arc_result(mut self, arc: ArcOutput<'x>) -> Self {
+        self.arc = arc.into();
+        self.arc_set = None;
+        self
+    }
+
+    pub fn with_iprev_result(mut self, iprev: IprevOutput) -> Self {
+        self.iprev = iprev.into();
+        self
+    }
+
+    pub fn with_dmarc_result(mut self, dmarc: DmarcOutput) -> Self {
+        self.dmarc = dmarc.into();
+        self
+    }
+
+    /// Records the SPF and DKIM results forced by `PolicyHooks` callbacks.
+    pub fn with_local_overrides(mut self, local_overrides: Vec<LocalOverride>) -> Self {
+        self.local_overrides = local_overrides;
+        self
+    }
+
+    /// Looks up in `store` when the RFC5322.From domain and the domains of
+    /// passing DKIM signatures were first seen.
+    pub async fn with_first_seen(mut self, store: &impl FirstSeenStore) -> Self {
+        let mut domains: Vec<String> = Vec::new();
+        let from_domain = self.header_from.rsplit_once('@').map(|(_, domain)| domain);
+        let dkim_domains = self
+            .dkim
+            .iter()
+            .filter(|dkim| dkim.result() == &DkimResult::Pass)
+            .filter_map(|dkim| dkim.signature().map(|signature| signature.d.as_str()));
+        for domain in from_domain.into_iter().chain(dkim_domains) {
+            let domain = Domain::normalize(domain);
+            if !domain.is_empty() && !domains.contains(&domain) {
+                domains.push(domain);
+            }
+        }
+
+        let first_seen =
+            futures_util::future::join_all(domains.iter().map(|domain| store.first_seen(domain)))
+                .await;
+        self.first_seen = domains.into_iter().zip(first_seen).collect();
+        self
+    }
+
+    /// Checks the RFC5322.From domain and the domains of all DKIM signatures
+    /// for mixed scripts and lookalikes of protected domains.
+    pub fn with_lookalike_check(mut self, detector: &LookalikeDetector) -> Self {
+        let mut domains: Vec<&str> = Vec::new();
+        let from_domain = self.header_from.rsplit_once('@').map(|(_, domain)| domain);
+        let dkim_domains = self
+            .dkim
+            .iter()
+            .filter_map(|dkim| dkim.signature().map(|signature| signature.d.as_str()));
+        for domain in from_domain.into_iter().chain(dkim_domains) {
+            if !domains.contains(&domain) {
+                domains.push(domain);
+            }
+        }
+
+        let mut warnings = Vec::new();
+        for warning in domains
+            .into_iter()
+            .flat_map(|domain| detector.check(domain))
+        {
+            if !warnings.contains(&warning) {
+                warnings.push(warning);
+            }
+        }
+        self.lookalike = warnings;
+        self
+    }
+
+    /// Seals the message using the Authentication-Results rendered from this output.
+    pub fn seal<T: SigningKey<Hasher = Sha256>>(
+        mut self,
+        sealer: &ArcSealer<T, Done>,
+        message: &AuthenticatedMessage<'_>,
+    ) -> crate::Result<Self> {
+        let arc = self.arc.as_ref().ok_or(Error::MissingParameters)?;
+        let auth_results = self.authentication_results();
+        let arc_set = sealer.seal(message, &auth_results, arc)?.to_header();
+        self.arc_set = arc_set.into();
+        Ok(self)
+    }
+
+    pub fn dkim(&self) -> &[DkimOutput<'x>] {
+        &self.dkim
+    }
+
+    pub fn spf(&self) -> Option<&SpfIdentitiesOutput> {
+        self.spf.as_ref()
+    }
+
+    pub fn arc(&self) -> Option<&ArcOutput<'x>> {
+        self.arc.as_ref()
+    }
+
+    pub fn iprev(&self) -> Option<&IprevOutput> {
+        self.iprev.as_ref()
+    }
+
+    pub fn dmarc(&self) -> Option<&DmarcOutput> {
+        self.dmarc.as_ref()
+    }
+
+    /// Returns the disposition to apply to the message, see `DmarcOutput::disposition`.
+    /// SPF and DKIM results forced by local policy are listed as override reasons.
+    pub fn dmarc_disposition(&self) -> Option<DmarcDisposition> {
+        self.dmarc.as_ref().map(|dmarc| {
+            self.local_overrides
+                .iter()
+                .fold(dmarc.disposition(), |disposition, local_override| {
+                    disposition.with_reason(local_override.reason())
+                })
+        })
+    }
+
+    /// Returns the SPF and DKIM results forced by `PolicyHooks` callbacks, the
+    /// DMARC override is available from `DmarcOutput::local_override`.
+    pub fn local_overrides(&self) -> &[LocalOverride] {
+        &self.local_overrides
+    }
+
+    /// Returns the RFC5322.From and DKIM domains along with the UNIX time they
+    /// were first seen, if looked up with `with_first_seen`.
+    pub fn first_seen(&self) -> &[(String, Option<u64>)] {
+        &self.first_seen
+    }
+
+    /// Returns the lookalike domain warnings, if checked with a `LookalikeDetector`.
+    pub fn lookalike_warnings(&self) -> &[LookalikeWarning] {
+        &self.lookalike
+    }
+
+    /// Returns the Authentication-Results header for the results in this output.
+    pub fn authentication_results(&self) -> AuthenticationResults<'_> {
+        let mut auth_results = AuthenticationResults::new(&self.hostname)
+            .with_dkim_results(&self.dkim, &self.header_from);
+        if let Some(spf) = &self.spf {
+            auth_results = auth_results
+                .with_spf_ehlo_result(spf.helo(), self.remote_ip, &self.helo)
+                .with_spf_mailfrom_result(
+                    spf.mail_from(),
+                    self.remote_ip,
+                    if spf.is_null_sender() {
+                        ""
+                    } else {
+                        &self.mail_from
+                    },
+                    &self.helo,
+                );
+        }
+        if let Some(iprev) = &self.iprev {
+            auth_results = auth_results.with_iprev_result(iprev, self.remote_ip);
+        }
+        if let Some(arc) = &self.arc {
+            auth_results = auth_results.with_arc_result(arc, self.remote_ip);
+        }
+        if let Some(dmarc) = &self.dmarc {
+            auth_results = auth_results.with_dmarc_result(dmarc);
+        }
+        auth_results
+    }
+
+    /// Returns the Received-SPF header, if SPF was evaluated.
+    pub fn received_spf(&self) -> Option<ReceivedSpf> {
+        self.spf.as_ref().map(|spf| {
+            ReceivedSpf::new(
+                spf.combined(),
+                self.remote_ip,
+                &self.helo,
+                if spf.is_null_sender() {
+                    ""
+                } else {
+                    &self.mail_from
+                },
+                &self.hostname,
+            )
+        })
+    }
+
+    /// Returns the ARC set headers, if the message was sealed.
+    pub fn arc_set(&self) -> Option<&str> {
+        self.arc_set.as_deref()
+    }
+
+    /// Returns an authentication failure report (RFC 6591) describing the
+    /// results in this output, so that failed messages can be archived in
+    /// the same format used for DMARC forensic reports.
+    pub fn auth_failure_report<'y>(
+        &'y self,
+        message: &'y AuthenticatedMessage<'_>,
+    ) -> Feedback<'y> {
+        let failed_dkim = self
+            .dkim
+            .iter()
+            .find(|dkim| !matches!(dkim.result(), DkimResult::Pass | DkimResult::None));
+        let dmarc_results = self.dmarc.as_ref().and_then(|dmarc| {
+            dmarc
+                .dmarc_record()
+                .map(|_| (dmarc.spf_result(), dmarc.dkim_result()))
+        });
+
+        let auth_failure = match (dmarc_results, failed_dkim, &self.spf) {
+            (Some((spf, dkim)), _, _)
+                if spf != &DmarcResult::Pass && dkim != &DmarcResult::Pass =>
+            {
+                AuthFailureType::Dmarc
+            }
+            (_, Some(dkim), _) => AuthFailureType::from(dkim.result()),
+            (_, _, Some(spf))
+                if matches!(
+                    spf.mail_from().result(),
+                    SpfResult::Fail | SpfResult::SoftFail
+                ) =>
+            {
+                AuthFailureType::Spf
+            }
+            _ => AuthFailureType::Unspecified,
+        };
+        let identity_alignment = match dmarc_results {
+            Some((spf, dkim)) => match (spf == &DmarcResult::Pass, dkim == &DmarcResult::Pass) {
+                (true, true) => IdentityAlignment::DkimSpf,
+                (true, false) => IdentityAlignment::Spf,
+                (false, true) => IdentityAlignment::Dkim,
+                (false, false) => IdentityAlignment::None,
+            },
+            None => IdentityAlignment::Unspecified,
+        };
+
+        let mut report = Feedback::new(FeedbackType::AuthFailure)
+            .with_auth_failure(auth_failure)
+            .with_identity_alignment(identity_alignment)
+            .with_authentication_results(self.authentication_results().to_string())
+            .with_reporting_mta(self.hostname.as_str())
+            .with_source_ip(self.remote_ip)
+            .with_message(String::from_utf8_lossy(message.raw_message));
+        if !self.mail_from.is_empty() {
+            report = report.with_original_mail_from(self.mail_from.as_str());
+        }
+        if let Some(dmarc) = self.dmarc.as_ref().filter(|d| !d.domain().is_empty()) {
+            report = report.with_reported_domain(dmarc.domain());
+        }
+        if let Some(signature) = failed_dkim.and_then(|dkim| dkim.signature()) {
+            report = report
+                .with_dkim_domain(signature.d.as_str())
+                .with_dkim_selector(signature.s.as_str());
+            if !signature.i.is_empty() {
+                report = report.with_dkim_identity(signature.i.as_str());
+            }
+        }
+        report
+    }
+}
+
+impl<'x> HeaderWriter for MessageAuthOutput<'x> {
+    fn write_header(&self, writer: &mut impl Writer) {
+        if let Some(arc_set) = &self.arc_set {
+            writer.write(arc_set.as_bytes());
+        }
+        self.authentication_results().write_header(writer);
+        if let Some(received_spf) = self.received_spf() {
+            received_spf.write_header(writer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        arc::ArcSealer,
+        common::{
+            config::Config,
+            gateway::GatewayConfig,
+            headers::HeaderWriter,
+            lookalike::{LookalikeDetector, LookalikeWarning},
+            parse::TxtRecordParser,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::{DkimSigner, Signature},
+        dmarc::Dmarc,
+        report::{AuthFailureType, FeedbackType, IdentityAlignment},
+        spf::Spf,
+        ArcOutput, AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
+        IprevOutput, IprevResult, MessageAuthOutput, SpfIdentitiesOutput, SpfOutput, SpfResult,
+    };
+
+    use super::{FirstSeenStore, MessageAuthParams};
+
+    struct FirstSeen(Vec<(&'static str, u64)>);
+
+    impl FirstSeenStore for FirstSeen {
+        async fn first_seen(&self, domain: &str) -> Option<u64> {
+            self.0
+                .iter()
+                .find(|(d, _)| *d == domain)
+                .map(|(_, first_seen)| *first_seen)
+        }
+    }
+
+    /// Collects the events emitted while verifying as `field=value` lines.
+    #[cfg(feature = "tracing")]
+    #[derive(Default, Clone)]
+    struct EventCollector(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for EventCollector {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Fields(String);
+            impl tracing::field::Visit for Fields {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0.push_str(&format!("{}={:?} ", field.name(), value));
+                }
+            }
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0.trim_end().to_string());
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn verify_message() {
+        #[cfg(feature = "tracing")]
+        let events = EventCollector::default();
+        #[cfg(feature = "tracing")]
+        let _guard = tracing::subscriber::set_default(events.clone());
+        let resolver = ed25519_resolver("ed._domainkey.example.org.");
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:192.168.1.1 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "mail.example.org.",
+            Spf::parse(b"v=spf1 a -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "mail.example.org.",
+            vec!["192.168.1.1".parse().unwrap()],
+            valid_until,
+        );
+        resolver.ptr_add(
+            "192.168.1.1".parse().unwrap(),
+            vec!["mail.example.org.".to_string()],
+            valid_until,
+        );
+
+        let message = concat!(
+            "From: jdoe@example.org\r\n",
+            "To: bill@example.net\r\n",
+            "Subject: Pipeline\r\n\r\n",
+            "Hi!\r\n"
+        );
+        let signature = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .sign(message.as_bytes())
+            .unwrap();
+        let signed = signature.to_header() + message;
+        let message = AuthenticatedMessage::parse(signed.as_bytes()).unwrap();
+
+        let output = resolver
+            .verify_message(
+                MessageAuthParams::new(&message, "192.168.1.1".parse().unwrap())
+                    .with_helo("mail.example.org")
+                    .with_mail_from("jdoe@example.org")
+                    .with_hostname("mx.example.net"),
+            )
+            .await;
+        assert_eq!(output.dkim().len(), 1);
+        assert_eq!(output.dkim()[0].result(), &DkimResult::Pass);
+        assert_eq!(output.spf().unwrap().helo().result(), SpfResult::Pass);
+        assert_eq!(output.spf().unwrap().mail_from().result(), SpfResult::Pass);
+        assert_eq!(output.iprev().unwrap().result, IprevResult::Pass);
+        assert_eq!(output.arc().unwrap().result(), &DkimResult::None);
+        assert_eq!(output.dmarc().unwrap().dkim_result(), &DmarcResult::Pass);
+        assert_eq!(output.dmarc().unwrap().spf_result(), &DmarcResult::Pass);
+
+        let headers = output.to_header();
+        for result in [
+            "\tdkim=pass header.d=example.org header.s=ed",
+            "smtp.helo=mail.example.org;",
+            "smtp.mailfrom=jdoe@example.org;",
+            "\tiprev=pass policy.iprev=192.168.1.1 (mail.example.org);",
+            "\tarc=none smtp.remote-ip=192.168.1.1;",
+            "\tdmarc=pass header.from=example.org policy.dmarc=reject\r\n",
+            "Received-SPF: pass (mx.example.net: domain of jdoe@example.org designates",
+        ] {
+            assert!(
+                headers.contains(result),
+                "{result:?} not found in {headers}"
+            );
+        }
+
+        // Message relayed by a trusted gateway
+        let relayed = concat!(
+            "Received: from mail.example.org (mail.example.org [192.168.1.1])\r\n",
+            "\tby mx.gateway.net (Postfix) with ESMTPS id 1234;\r\n",
+            "\tMon, 1 Jan 2024 10:00:00 +0000\r\n"
+        )
+        .to_string()
+            + &signed;
+        let relayed = AuthenticatedMessage::parse(relayed.as_bytes()).unwrap();
+        let gateway = GatewayConfig::new().with_trusted_ip("10.0.0.1".parse().unwrap());
+        let params = MessageAuthParams::new(&relayed, "10.0.0.1".parse().unwrap())
+            .with_helo("mx.gateway.net")
+            .with_mail_from("jdoe@example.org");
+        let output = resolver.verify_message(params.clone()).await;
+        assert_eq!(output.spf().unwrap().mail_from().result(), SpfResult::Fail);
+        let output = resolver.verify_message(params.with_gateway(&gateway)).await;
+        assert_eq!(output.dkim()[0].result(), &DkimResult::Pass);
+        assert_eq!(output.spf().unwrap().helo().result(), SpfResult::Pass);
+        assert_eq!(output.spf().unwrap().mail_from().result(), SpfResult::Pass);
+        assert_eq!(output.iprev().unwrap().result, IprevResult::Pass);
+        assert_eq!(output.dmarc().unwrap().spf_result(), &DmarcResult::Pass);
+
+        // First seen lookups
+        assert!(output.first_seen().is_empty());
+        let from_message = concat!(
+            "From: jdoe@New-Example.com\r\n",
+            "To: bill@example.net\r\n",
+            "Subject: Pipeline\r\n\r\n",
+            "Hi!\r\n"
+        );
+        let message = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .sign(from_message.as_bytes())
+            .unwrap()
+            .to_header()
+            + from_message;
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let output = resolver
+            .verify_message_with_first_seen(
+                MessageAuthParams::new(&message, "192.168.1.1".parse().unwrap())
+                    .with_mail_from("jdoe@example.org"),
+                &FirstSeen(vec![("example.org", 946684800)]),
+            )
+            .await;
+        assert_eq!(
+            output.first_seen(),
+            [
+                ("new-example.com".to_string(), None),
+                ("example.org".to_string(), Some(946684800))
+            ]
+        );
+        assert!(output.lookalike_warnings().is_empty());
+
+        // Lookalike domains
+        resolver.set_config(Config::new().with_lookalike_detector(
+            LookalikeDetector::new().with_protected_domain("example.org"),
+        ));
+        let from_message = from_message.replace("New-Example.com", "examp1e.org");
+        let message = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .sign(from_message.as_bytes())
+            .unwrap()
+            .to_header()
+            + &from_message;
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let output = resolver
+            .verify_message(MessageAuthParams::new(
+                &message,
+                "192.168.1.1".parse().unwrap(),
+            ))
+            .await;
+        assert_eq!(
+            output.lookalike_warnings(),
+            [LookalikeWarning::Confusable {
+                domain: "examp1e.org".to_string(),
+                protected: "example.org".to_string()
+            }]
+        );
+
+        #[cfg(feature = "tracing")]
+        {
+            let events = events.0.lock().unwrap();
+            for event in [
+                "message=dkim signature verified domain=\"example.org\" selector=\"ed\" result=pass",
+                "message=spf directive matched domain=example.org directive=ip4:192.168.1.1",
+                "message=spf directive matched domain=mail.example.org directive=a",
+                "message=spf evaluated result=Pass",
+                "message=arc chain verified result=none",
+                "message=dmarc evaluated domain=example.org policy=Reject spf=pass dkim=pass",
+            ] {
+                assert!(
+                    events.iter().any(|e| e == event),
+                    "{event:?} not found in {events:#?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn message_auth_headers() {
+        let signature = Signature {
+            d: "example.org".into(),
+            s: "default".into(),
+            ..Default::default()
+        };
+        let output = MessageAuthOutput::new(
+            "mx.example.net",
+            "192.168.1.1".parse().unwrap(),
+            "mail.example.org",
+            "jdoe@example.org",
+        )
+        .with_dkim_results(
+            vec![DkimOutput::pass().with_signature(&signature)],
+            "jdoe@example.org",
+        )
+        .with_spf_results(SpfIdentitiesOutput {
+            helo: SpfOutput::new("mail.example.org".into()).with_result(SpfResult::None),
+            mail_from: SpfOutput::new("example.org".into()).with_result(SpfResult::Pass),
+            is_null_sender: false,
+        })
+        .with_iprev_result(IprevOutput {
+            result: IprevResult::Pass,
+            ptr: None,
+            confirmed: None,
+        })
+        .with_arc_result(ArcOutput::default())
+        .with_dmarc_result(DmarcOutput::default().with_domain("example.org"));
+
+        assert!(output.arc_set().is_none());
+        assert_eq!(
+            output.to_header(),
+            concat!(
+                "Authentication-Results: mx.example.net;\r\n",
+                "\tdkim=pass header.d=example.org header.s=default;\r\n",
+                "\tspf=none (mx.example.net: no SPF records found for ",
+                "postmaster@mail.example.org) smtp.helo=mail.example.org;\r\n",
+                "\tspf=pass (mx.example.net: domain of jdoe@example.org designates ",
+                "192.168.1.1 as permitted sender) smtp.mailfrom=jdoe@example.org;\r\n",
+                "\tiprev=pass policy.iprev=192.168.1.1;\r\n",
+                "\tarc=none smtp.remote-ip=192.168.1.1;\r\n",
+                "\tdmarc=none header.from=example.org policy.dmarc=none\r\n",
+                "Received-SPF: pass (mx.example.net: domain of jdoe@example.org ",
+                "designates 192.168.1.1 as permitted sender)\r\n",
+                "\treceiver=mx.example.net; client-ip=192.168.1.1; ",
+                "envelope-from=\"jdoe@example.org\"; helo=mail.example.org;\r\n",
+            )
+        );
+
+        // Seal the message using the rendered Authentication-Results
+        let message = concat!(
+            "From: jdoe@example.org\r\n",
+            "To: bill@example.net\r\n",
+            "Subject: Sealed\r\n\r\n",
+            "Hi!\r\n"
+        );
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let sealer = ArcSealer::from_key(ed25519_key().unwrap())
+            .domain("example.net")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+        let auth_results = output.authentication_results().to_string();
+        let sealed = output.clone().seal(&sealer, &message).unwrap();
+        let arc_set = sealed.arc_set().unwrap();
+        assert!(
+            arc_set.starts_with("ARC-Seal: i=1; a=ed25519-sha256; s=ed; d=example.net; cv=none;")
+        );
+        assert!(arc_set.replace("\r\n\t", " ").contains(&format!(
+            "ARC-Authentication-Results: i=1; {}",
+            auth_results.replace("\r\n\t", " ")
+        )));
+        assert!(sealed.to_header().starts_with(arc_set));
+        assert!(sealed.to_header().ends_with(&output.to_header()));
+        assert_eq!(
+            MessageAuthOutput::new("mx.example.net", "::1".parse().unwrap(), "", "")
+                .seal(&sealer, &message)
+                .unwrap_err(),
+            Error::MissingParameters
+        );
+    }
+
+    #[test]
+    fn auth_failure_report() {
+        let message = concat!(
+            "From: jdoe@example.org\r\n",
+            "To: bill@example.net\r\n",
+            "Subject: Forged\r\n\r\n",
+            "Hi!\r\n"
+        );
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let signature = Signature {
+            d: "example.org".into(),
+            s: "default".into(),
+            i: "jdoe@example.org".into(),
+            ..Default::default()
+        };
+        let output = MessageAuthOutput::new(
+            "mx.example.net",
+            "192.168.1.1".parse().unwrap(),
+            "mail.example.org",
+            "jdoe@example.org",
+        )
+        .with_dkim_results(
+            vec![DkimOutput::neutral(Error::FailedBodyHashMatch).with_signature(&signature)],
+            "jdoe@example.org",
+        )
+        .with_spf_results(SpfIdentitiesOutput {
+            helo: SpfOutput::new("mail.example.org".into()).with_result(SpfResult::None),
+            mail_from: SpfOutput::new("example.org".into()).with_result(SpfResult::Fail),
+            is_null_sender: false,
+        })
+        .with_dmarc_result(
+            DmarcOutput::default()
+                .with_domain("example.org")
+                .with_spf_result(DmarcResult::Fail(Error::NotAligned))
+                .with_dkim_result(DmarcResult::Fail(Error::NotAligned))
+                .with_record(Arc::new(Dmarc::parse(b"v=DMARC1; p=reject").unwrap())),
+        );
+
+        let report = output.auth_failure_report(&message);
+        assert_eq!(report.feedback_type(), FeedbackType::AuthFailure);
+        assert_eq!(report.auth_failure(), AuthFailureType::Dmarc);
+        assert_eq!(report.identity_alignment(), IdentityAlignment::None);
+        assert_eq!(report.source_ip(), Some("192.168.1.1".parse().unwrap()));
+        assert_eq!(report.reporting_mta(), Some("mx.example.net"));
+        assert_eq!(report.original_mail_from(), Some("jdoe@example.org"));
+        assert_eq!(report.reported_domain(), ["example.org"]);
+        assert_eq!(report.dkim_domain(), Some("example.org"));
+        assert_eq!(report.dkim_selector(), Some("default"));
+        assert_eq!(report.dkim_identity(), Some("jdoe@example.org"));
+        assert_eq!(
+            report.authentication_results(),
+            [output.authentication_results().to_string()]
+        );
+        assert_eq!(report.message().unwrap().as_bytes(), message.raw_message());
+
+        // Without a DMARC verdict the failing DKIM signature is reported
+        let output = output.with_dmarc_result(DmarcOutput::default());
+        let report = output.auth_failure_report(&message);
+        assert_eq!(report.auth_failure(), AuthFailureType::BodyHash);
+        assert_eq!(report.identity_alignment(), IdentityAlignment::Unspecified);
+        assert!(report.to_arf().contains("Auth-Failure: bodyhash\r\n"));
+    }
+}
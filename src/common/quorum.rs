@@ -0,0 +1,249 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::{
+    dmarc::{Dmarc, Policy},
+    Error, Resolver, Txt,
+};
+
+use super::{
+    parse::TxtRecordParser,
+    resolver::{IntoFqdn, UnwrapTxtRecord},
+    verify::DomainKey,
+};
+
+/// How the answers of the quorum resolvers are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuorumMode {
+    /// All resolvers must return the same answer, disagreements are
+    /// reported as temporary DNS errors.
+    Agreement,
+    /// Disagreements resolve to the most conservative answer: missing or
+    /// revoked DKIM keys and the strictest DMARC policy.
+    Conservative,
+}
+
+#[derive(Clone)]
+pub(crate) struct Quorum {
+    resolvers: Vec<Resolver>,
+    mode: QuorumMode,
+}
+
+pub(crate) trait QuorumRecord: Sized {
+    fn agrees(&self, other: &Self) -> bool;
+
+    fn most_conservative(results: Vec<crate::Result<Arc<Self>>>) -> crate::Result<Arc<Self>>;
+}
+
+impl Resolver {
+    /// Looks up DKIM keys and DMARC records on this resolver and on every
+    /// quorum resolver, combining the answers according to `mode`.
+    pub fn with_quorum_resolvers(
+        mut self,
+        resolvers: impl IntoIterator<Item = Resolver>,
+        mode: QuorumMode,
+    ) -> Self {
+        self.quorum = Some(Quorum {
+            resolvers: resolvers.into_iter().collect(),
+            mode,
+        });
+        self
+    }
+
+    pub(crate) async fn txt_lookup_quorum<'x, T>(
+        &self,
+        key: impl IntoFqdn<'x>,
+    ) -> crate::Result<Arc<T>>
+    where
+        T: TxtRecordParser + Into<Txt> + UnwrapTxtRecord + QuorumRecord,
+    {
+        let quorum = match &self.quorum {
+            Some(quorum) if !quorum.resolvers.is_empty() => quorum,
+            _ => return self.txt_lookup::<T>(key).await,
+        };
+
+        let key = key.into_fqdn();
+        let mut results = futures_util::future::join_all(
+            std::iter::once(self)
+                .chain(quorum.resolvers.iter())
+                .map(|resolver| resolver.txt_lookup::<T>(key.as_ref())),
+        )
+        .await;
+
+        match quorum.mode {
+            QuorumMode::Agreement => {
+                let first = results.swap_remove(0);
+                if results.iter().all(|result| match (&first, result) {
+                    (Ok(a), Ok(b)) => a.agrees(b),
+                    (Err(a), Err(b)) => a == b,
+                    _ => false,
+                }) {
+                    first
+                } else {
+                    Err(Error::DnsError("Quorum resolvers disagree".to_string()))
+                }
+            }
+            QuorumMode::Conservative => T::most_conservative(results),
+        }
+    }
+}
+
+impl QuorumRecord for DomainKey {
+    fn agrees(&self, other: &Self) -> bool {
+        self.f == other.f
+            && self.p.public_key().is_some()
+            && self.p.public_key() == other.p.public_key()
+    }
+
+    fn most_conservative(results: Vec<crate::Result<Arc<Self>>>) -> crate::Result<Arc<Self>> {
+        let mut key: Option<Arc<Self>> = None;
+        for result in results {
+            match (result, &key) {
+                (Err(err), _) => return Err(err),
+                (Ok(record), Some(key)) if !key.agrees(&record) => {
+                    return Err(Error::DnsError("Quorum resolvers disagree".to_string()))
+                }
+                (Ok(record), _) => key = Some(record),
+            }
+        }
+        key.ok_or(Error::MissingParameters)
+    }
+}
+
+impl QuorumRecord for Dmarc {
+    fn agrees(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn most_conservative(results: Vec<crate::Result<Arc<Self>>>) -> crate::Result<Arc<Self>> {
+        let mut strictest: Option<crate::Result<Arc<Self>>> = None;
+        for result in results {
+            strictest = Some(match (strictest, result) {
+                (None, result) | (Some(Err(_)), result @ Ok(_)) => result,
+                (Some(Ok(current)), Ok(record))
+                    if policy_rank(record.p) > policy_rank(current.p) =>
+                {
+                    Ok(record)
+                }
+                (Some(current), _) => current,
+            });
+        }
+        strictest.unwrap_or(Err(Error::MissingParameters))
+    }
+}
+
+fn policy_rank(policy: Policy) -> u8 {
+    match policy {
+        Policy::Reject => 3,
+        Policy::Quarantine => 2,
+        Policy::None => 1,
+        Policy::Unspecified => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::{parse::TxtRecordParser, test_key::ED25519_RECORD as ED_KEY, verify::DomainKey},
+        dmarc::{Dmarc, Policy},
+        Error, Resolver,
+    };
+
+    use super::QuorumMode;
+
+    const RSA_KEY: &str = concat!(
+        "v=DKIM1; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQCYtb/9Sh8nGKV7exhUFS",
+        "+cBNXlHgO1CxD9zIfQd5ztlq1LO7g38dfmFpQafh9lKgqPBTolFhZxhF1yUNT",
+        "hpV673NdAtaCVGNyx/fTYtvyyFe9DH2tmm/ijLlygDRboSkIJ4NHZjK++48hk",
+        "NP8/htqWHS+CvwWT4Qgs0NtB7Re9bQIDAQAB"
+    );
+
+    fn resolver(key: Option<&str>, dmarc: Option<&str>) -> Resolver {
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let resolver = Resolver::new_system_conf().unwrap();
+        if let Some(key) = key {
+            resolver.txt_add(
+                "default._domainkey.example.org.",
+                DomainKey::parse(key.as_bytes()).unwrap(),
+                valid_until,
+            );
+        }
+        if let Some(dmarc) = dmarc {
+            resolver.txt_add(
+                "_dmarc.example.org.",
+                Dmarc::parse(dmarc.as_bytes()).unwrap(),
+                valid_until,
+            );
+        }
+        resolver
+    }
+
+    #[tokio::test]
+    async fn quorum_lookups() {
+        let key = "default._domainkey.example.org.";
+        let dmarc = "_dmarc.example.org.";
+        let disagree = Error::DnsError("Quorum resolvers disagree".to_string());
+
+        // Matching answers are returned in both modes
+        for mode in [QuorumMode::Agreement, QuorumMode::Conservative] {
+            let resolver = resolver(Some(ED_KEY), Some("v=DMARC1; p=none"))
+                .with_quorum_resolvers([resolver(Some(ED_KEY), Some("v=DMARC1; p=none"))], mode);
+            assert!(resolver.txt_lookup_quorum::<DomainKey>(key).await.is_ok());
+            assert_eq!(
+                resolver.txt_lookup_quorum::<Dmarc>(dmarc).await.unwrap().p,
+                Policy::None
+            );
+        }
+
+        // Diverging answers
+        let quorum = || {
+            [
+                resolver(Some(RSA_KEY), Some("v=DMARC1; p=reject")),
+                resolver(None, None),
+            ]
+        };
+        let resolver_agreement = resolver(Some(ED_KEY), Some("v=DMARC1; p=none"))
+            .with_quorum_resolvers(quorum(), QuorumMode::Agreement);
+        assert!(matches!(
+            resolver_agreement
+                .txt_lookup_quorum::<DomainKey>(key)
+                .await,
+            Err(err) if err == disagree
+        ));
+        assert_eq!(
+            resolver_agreement
+                .txt_lookup_quorum::<Dmarc>(dmarc)
+                .await
+                .unwrap_err(),
+            disagree
+        );
+
+        let resolver_conservative = resolver(Some(ED_KEY), Some("v=DMARC1; p=none"))
+            .with_quorum_resolvers(quorum(), QuorumMode::Conservative);
+        assert!(matches!(
+            resolver_conservative
+                .txt_lookup_quorum::<DomainKey>(key)
+                .await,
+            Err(Error::DnsError(_))
+        ));
+        assert_eq!(
+            resolver_conservative
+                .txt_lookup_quorum::<Dmarc>(dmarc)
+                .await
+                .unwrap()
+                .p,
+            Policy::Reject
+        );
+    }
+}
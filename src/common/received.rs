@@ -0,0 +1,205 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::net::IpAddr;
+
+use mail_parser::{parsers::MessageStream, HeaderValue, Host};
+
+use crate::AuthenticatedMessage;
+
+/// A relay hop recorded in a Received header (RFC 5321 section 4.4).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReceivedHop {
+    from: Option<String>,
+    from_ip: Option<IpAddr>,
+    helo: Option<String>,
+    by: Option<String>,
+    for_: Option<String>,
+    with: Option<String>,
+    id: Option<String>,
+    timestamp: Option<i64>,
+    tls_version: Option<String>,
+    tls_cipher: Option<String>,
+}
+
+impl ReceivedHop {
+    /// Parses the value of a Received header.
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let received = match MessageStream::new(value).parse_received() {
+            HeaderValue::Received(received) => received,
+            _ => return None,
+        };
+        let host = |host: Option<Host<'_>>| match host {
+            Some(Host::Name(name)) => Some(name.into_owned()),
+            Some(Host::IpAddr(ip)) => Some(ip.to_string()),
+            None => None,
+        };
+
+        Some(ReceivedHop {
+            from_ip: match (received.from_ip, &received.from) {
+                (Some(ip), _) => Some(ip),
+                (None, Some(Host::IpAddr(ip))) => Some(*ip),
+                _ => None,
+            },
+            from: match received.from {
+                Some(Host::Name(name)) => Some(name.into_owned()),
+                _ => None,
+            },
+            helo: host(received.helo),
+            by: host(received.by),
+            for_: received.for_.map(|f| f.into_owned()),
+            with: received.with.map(|with| with.to_string()),
+            id: received.id.map(|id| id.into_owned()),
+            timestamp: received.date.map(|date| date.to_timestamp()),
+            tls_version: received.tls_version.map(|version| version.to_string()),
+            tls_cipher: received.tls_cipher.map(|cipher| cipher.into_owned()),
+        })
+    }
+
+    /// Hostname of the client, as resolved or announced in the `from` clause.
+    pub fn from(&self) -> Option<&str> {
+        self.from.as_deref()
+    }
+
+    /// Address of the client.
+    pub fn from_ip(&self) -> Option<IpAddr> {
+        self.from_ip
+    }
+
+    /// HELO/EHLO hostname announced by the client, if recorded separately.
+    pub fn helo(&self) -> Option<&str> {
+        self.helo.as_deref()
+    }
+
+    /// Host that received the message.
+    pub fn by(&self) -> Option<&str> {
+        self.by.as_deref()
+    }
+
+    /// Recipient the message was received for.
+    pub fn for_(&self) -> Option<&str> {
+        self.for_.as_deref()
+    }
+
+    /// Transmission protocol, such as `ESMTPS`.
+    pub fn with(&self) -> Option<&str> {
+        self.with.as_deref()
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// UNIX timestamp at which the hop received the message.
+    pub fn timestamp(&self) -> Option<i64> {
+        self.timestamp
+    }
+
+    pub fn tls_version(&self) -> Option<&str> {
+        self.tls_version.as_deref()
+    }
+
+    pub fn tls_cipher(&self) -> Option<&str> {
+        self.tls_cipher.as_deref()
+    }
+
+    /// Returns `true` if the message was received over TLS, according to the
+    /// recorded TLS details or the transmission protocol (RFC 3848).
+    pub fn is_tls(&self) -> bool {
+        self.tls_version.is_some()
+            || self.tls_cipher.is_some()
+            || self.with.as_deref().is_some_and(|with| {
+                let with = with.to_ascii_uppercase();
+                with.ends_with('S') || with.ends_with("SA")
+            })
+    }
+
+    /// Seconds elapsed between an earlier hop and this one, if both are timestamped.
+    pub fn delay_since(&self, earlier: &ReceivedHop) -> Option<i64> {
+        Some(self.timestamp? - earlier.timestamp?)
+    }
+}
+
+impl<'x> AuthenticatedMessage<'x> {
+    /// Parses the Received headers of the message from the most recent hop to
+    /// the oldest. Headers that cannot be parsed are returned as `None`.
+    pub fn received_hops(&self) -> impl Iterator<Item = Option<ReceivedHop>> + '_ {
+        self.headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(b"Received"))
+            .map(|(_, value)| ReceivedHop::parse(value))
+    }
+
+    /// Returns the number of hops received by a host, which exceeds one when
+    /// a message loops through it.
+    pub fn received_by_count(&self, host: &str) -> usize {
+        self.received_hops()
+            .filter(|hop| {
+                hop.as_ref()
+                    .and_then(|hop| hop.by())
+                    .is_some_and(|by| by.eq_ignore_ascii_case(host))
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::AuthenticatedMessage;
+
+    #[test]
+    fn received_hops() {
+        let message = concat!(
+            "Received: from mx.example.org (mx.example.org [192.0.2.1])\r\n",
+            "\tby mail.example.com (Postfix) with ESMTPS id 4A1B2C3D\r\n",
+            "\t(version=TLS1_3 cipher=TLS_AES_256_GCM_SHA384)\r\n",
+            "\tfor <jdoe@example.com>; Tue, 1 Aug 2023 10:00:30 +0000\r\n",
+            "Received: from [198.51.100.7] (helo=laptop)\r\n",
+            "\tby mx.example.org with ESMTPSA id 77;\r\n",
+            "\tTue, 1 Aug 2023 11:00:00 +0100\r\n",
+            "Received: garbage\r\n",
+            "Received: from mx.example.org by MAIL.example.com with SMTP;\r\n",
+            "\tTue, 1 Aug 2023 09:59:00 +0000\r\n",
+            "From: bill@example.org\r\n",
+            "\r\n",
+            "Hi.\r\n"
+        );
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        assert_eq!(message.received_headers_count(), 4);
+        let hops = message.received_hops().collect::<Vec<_>>();
+        assert_eq!(hops.len(), 4);
+        assert!(hops[2].is_none());
+
+        let hop = hops[0].as_ref().unwrap();
+        assert_eq!(hop.from(), Some("mx.example.org"));
+        assert_eq!(hop.from_ip(), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(hop.by(), Some("mail.example.com"));
+        assert_eq!(hop.with(), Some("ESMTPS"));
+        assert_eq!(hop.id(), Some("4A1B2C3D"));
+        assert_eq!(hop.for_(), Some("jdoe@example.com"));
+        assert_eq!(hop.timestamp(), Some(1690884030));
+        assert!(hop.tls_version().is_some());
+        assert_eq!(hop.tls_cipher(), Some("TLS_AES_256_GCM_SHA384"));
+        assert!(hop.is_tls());
+
+        let submission = hops[1].as_ref().unwrap();
+        assert_eq!(submission.from(), None);
+        assert_eq!(submission.from_ip(), Some("198.51.100.7".parse().unwrap()));
+        assert_eq!(submission.by(), Some("mx.example.org"));
+        assert!(submission.is_tls());
+        assert_eq!(hop.delay_since(submission), Some(30));
+
+        let looped = hops[3].as_ref().unwrap();
+        assert!(!looped.is_tls());
+        assert_eq!(looped.delay_since(hop), Some(-90));
+        assert_eq!(message.received_by_count("mail.example.com"), 2);
+        assert_eq!(message.received_by_count("mx.example.org"), 1);
+    }
+}
@@ -10,32 +10,178 @@
 
 use std::{
     borrow::Cow,
+    fmt::Display,
+    future::Future,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::Duration,
 };
 
 use hickory_resolver::{
     config::{ResolverConfig, ResolverOpts},
     error::{ResolveError, ResolveErrorKind},
-    proto::rr::RecordType,
+    proto::rr::{RData, RecordType},
     system_conf::read_system_conf,
-    AsyncResolver, Name,
+    AsyncResolver, Name, TokioAsyncResolver,
 };
 
 use crate::{
+    bimi::Bimi,
+    dane::{Tlsa, TlsaEntry},
     dkim::{Atps, DomainKeyReport},
     dmarc::Dmarc,
     mta_sts::{MtaSts, TlsRpt},
     spf::{Macro, Spf},
+    vbr::Vouch,
     Error, IpLookupStrategy, Resolver, Txt, MX,
 };
 
+#[cfg(any(test, feature = "test"))]
+use super::mock::MockTruncate;
 use super::{
+    domain::to_ascii,
     lru::{DnsCache, LruCache},
     parse::TxtRecordParser,
     verify::DomainKey,
 };
 
+/// DNS query and cache settings used by [`Resolver::with_options`].
+#[derive(Debug, Clone)]
+pub struct ResolverOptions {
+    options: ResolverOpts,
+    txt_capacity: usize,
+    mx_capacity: usize,
+    ipv4_capacity: usize,
+    ipv6_capacity: usize,
+    ptr_capacity: usize,
+}
+
+impl Default for ResolverOptions {
+    fn default() -> Self {
+        ResolverOpts::default().into()
+    }
+}
+
+impl From<ResolverOpts> for ResolverOptions {
+    fn from(options: ResolverOpts) -> Self {
+        ResolverOptions {
+            options,
+            txt_capacity: 128,
+            mx_capacity: 128,
+            ipv4_capacity: 128,
+            ipv6_capacity: 128,
+            ptr_capacity: 128,
+        }
+    }
+}
+
+impl ResolverOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how long to wait for a response before retrying a query.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = timeout;
+        self
+    }
+
+    /// Sets the number of times a query is sent before giving up.
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.options.attempts = attempts;
+        self
+    }
+
+    /// Sets the number of name servers queried concurrently for each lookup.
+    pub fn with_concurrent_queries(mut self, queries: usize) -> Self {
+        self.options.num_concurrent_reqs = queries;
+        self
+    }
+
+    /// Enables EDNS(0), advertising a UDP payload size of 1232 bytes.
+    pub fn with_edns0(mut self, edns0: bool) -> Self {
+        self.options.edns0 = edns0;
+        self
+    }
+
+    /// Sets the number of entries in the upstream resolver cache.
+    pub fn with_dns_cache_size(mut self, size: usize) -> Self {
+        self.options.cache_size = size;
+        self
+    }
+
+    /// Sets the capacity of all record caches.
+    pub fn with_cache_capacity(self, capacity: usize) -> Self {
+        self.with_txt_cache_capacity(capacity)
+            .with_mx_cache_capacity(capacity)
+            .with_ip_cache_capacity(capacity)
+            .with_ptr_cache_capacity(capacity)
+    }
+
+    /// Sets the capacity of the TXT record cache, which holds DKIM keys and policies.
+    pub fn with_txt_cache_capacity(mut self, capacity: usize) -> Self {
+        self.txt_capacity = capacity;
+        self
+    }
+
+    /// Sets the capacity of the MX record cache.
+    pub fn with_mx_cache_capacity(mut self, capacity: usize) -> Self {
+        self.mx_capacity = capacity;
+        self
+    }
+
+    /// Sets the capacity of the IPv4 and IPv6 address caches.
+    pub fn with_ip_cache_capacity(mut self, capacity: usize) -> Self {
+        self.ipv4_capacity = capacity;
+        self.ipv6_capacity = capacity;
+        self
+    }
+
+    /// Sets the capacity of the PTR record cache.
+    pub fn with_ptr_cache_capacity(mut self, capacity: usize) -> Self {
+        self.ptr_capacity = capacity;
+        self
+    }
+}
+
+/// Settings of the upstream resolver, from which the resolvers needed only by
+/// some lookups are created.
+pub(crate) struct DnsConfig {
+    config: ResolverConfig,
+    options: ResolverOpts,
+    dnssec: OnceLock<TokioAsyncResolver>,
+    uncached: OnceLock<TokioAsyncResolver>,
+}
+
+impl DnsConfig {
+    pub(crate) fn new(config: ResolverConfig, options: ResolverOpts) -> Self {
+        DnsConfig {
+            config,
+            options,
+            dnssec: OnceLock::new(),
+            uncached: OnceLock::new(),
+        }
+    }
+
+    /// Returns the DNSSEC validating resolver, created on first use.
+    pub(crate) fn dnssec_resolver(&self) -> &TokioAsyncResolver {
+        self.dnssec.get_or_init(|| {
+            let mut options = self.options.clone();
+            options.validate = true;
+            AsyncResolver::tokio(self.config.clone(), options)
+        })
+    }
+
+    /// Returns a resolver without a record cache, created on first use.
+    pub(crate) fn uncached_resolver(&self) -> &TokioAsyncResolver {
+        self.uncached.get_or_init(|| {
+            let mut options = self.options.clone();
+            options.cache_size = 0;
+            AsyncResolver::tokio(self.config.clone(), options)
+        })
+    }
+}
+
 impl Resolver {
     pub fn new_cloudflare_tls() -> Result<Self, ResolveError> {
         Self::with_capacity(
@@ -66,18 +212,83 @@ impl Resolver {
         Self::with_capacity(config, options, 128)
     }
 
+    /// Creates a resolver that never sends queries and only answers with the
+    /// records loaded into its cache.
+    #[cfg(any(feature = "bench", feature = "corpus"))]
+    pub(crate) fn new_offline() -> Self {
+        Self {
+            offline: true,
+            ..Self::with_capacity(ResolverConfig::new(), ResolverOpts::default(), 1024)
+                .expect("resolver construction does not fail")
+        }
+    }
+
+    /// Creates a resolver that sends queries over DNS-over-HTTPS to `url`,
+    /// such as `https://dns.example.org/dns-query`.
+    #[cfg(feature = "dns-over-https")]
+    pub fn new_doh(url: &str) -> Result<Self, ResolveError> {
+        Self::new_doh_with_options(url, ResolverOptions::default())
+    }
+
+    /// Creates a DNS-over-HTTPS resolver with custom options. Host names in
+    /// `url` are resolved once, using the system resolver.
+    #[cfg(feature = "dns-over-https")]
+    pub fn new_doh_with_options(url: &str, options: ResolverOptions) -> Result<Self, ResolveError> {
+        let (host, port, ips) = parse_doh_url(url)?;
+        Self::with_options(
+            ResolverConfig::from_parts(
+                None,
+                vec![],
+                hickory_resolver::config::NameServerConfigGroup::from_ips_https(
+                    &ips, port, host, true,
+                ),
+            ),
+            options,
+        )
+    }
+
+    pub fn with_options(
+        config: ResolverConfig,
+        options: ResolverOptions,
+    ) -> Result<Self, ResolveError> {
+        Self::with_capacities(
+            config,
+            options.options,
+            options.txt_capacity,
+            options.mx_capacity,
+            options.ipv4_capacity,
+            options.ipv6_capacity,
+            options.ptr_capacity,
+        )
+    }
+
     pub fn with_capacity(
         config: ResolverConfig,
         options: ResolverOpts,
         capacity: usize,
     ) -> Result<Self, ResolveError> {
         Ok(Self {
-            resolver: AsyncResolver::tokio(config, options),
+            resolver: AsyncResolver::tokio(config.clone(), options.clone()),
+            dns_config: Arc::new(DnsConfig::new(config, options)),
             cache_txt: LruCache::with_capacity(capacity),
             cache_mx: LruCache::with_capacity(capacity),
             cache_ipv4: LruCache::with_capacity(capacity),
             cache_ipv6: LruCache::with_capacity(capacity),
             cache_ptr: LruCache::with_capacity(capacity),
+            cache_mta_sts: LruCache::with_capacity(capacity),
+            cache_tlsa: LruCache::with_capacity(capacity),
+            cache_spf: None,
+            cache_iprev: LruCache::with_capacity(capacity),
+            config: Default::default(),
+            propagation_resolvers: Vec::new(),
+            quorum: None,
+            fairness: None,
+            tenant: None,
+            arc_scorer: None,
+            upstreams: None,
+            offline: false,
+            #[cfg(any(test, feature = "test"))]
+            mock: Default::default(),
         })
     }
 
@@ -91,20 +302,35 @@ impl Resolver {
         ptr_capacity: usize,
     ) -> Result<Self, ResolveError> {
         Ok(Self {
-            resolver: AsyncResolver::tokio(config, options),
+            resolver: AsyncResolver::tokio(config.clone(), options.clone()),
+            dns_config: Arc::new(DnsConfig::new(config, options)),
             cache_txt: LruCache::with_capacity(txt_capacity),
             cache_mx: LruCache::with_capacity(mx_capacity),
             cache_ipv4: LruCache::with_capacity(ipv4_capacity),
             cache_ipv6: LruCache::with_capacity(ipv6_capacity),
             cache_ptr: LruCache::with_capacity(ptr_capacity),
+            cache_mta_sts: LruCache::with_capacity(txt_capacity),
+            cache_tlsa: LruCache::with_capacity(mx_capacity),
+            cache_spf: None,
+            cache_iprev: LruCache::with_capacity(ptr_capacity),
+            config: Default::default(),
+            propagation_resolvers: Vec::new(),
+            quorum: None,
+            fairness: None,
+            tenant: None,
+            arc_scorer: None,
+            upstreams: None,
+            offline: false,
+            #[cfg(any(test, feature = "test"))]
+            mock: Default::default(),
         })
     }
 
     pub async fn txt_raw_lookup(&self, key: impl IntoFqdn<'_>) -> crate::Result<Vec<u8>> {
         let mut result = vec![];
+        let name = Name::from_str_relaxed(key.into_fqdn().as_ref())?;
         for record in self
-            .resolver
-            .txt_lookup(Name::from_str_relaxed(key.into_fqdn().as_ref())?)
+            .query(false, |r| r.txt_lookup(name.clone()))
             .await?
             .as_lookup()
             .record_iter()
@@ -124,7 +350,11 @@ impl Resolver {
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<T>> {
         let key = key.into_fqdn();
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::TXT, key.as_ref()).await?;
         if let Some(value) = self.cache_txt.get(key.as_ref()) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
             return T::unwrap_txt(value);
         }
 
@@ -133,11 +363,28 @@ impl Resolver {
             return mock_resolve(key.as_ref());
         }
 
-        let txt_lookup = self
-            .resolver
-            .txt_lookup(Name::from_str_relaxed(key.as_ref())?)
-            .await?;
-        let mut result = Err(Error::InvalidRecordType);
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        let txt_lookup = match timed(
+            "TXT",
+            &key,
+            self.query(false, |r| r.txt_lookup(name.clone())),
+        )
+        .await
+        {
+            Ok(txt_lookup) => txt_lookup,
+            Err(err) => {
+                let err = Error::from(err);
+                return Err(
+                    if matches!(err, Error::DnsRecordNotFound(_))
+                        && self.legacy_txt_lookup::<T>(name).await
+                    {
+                        Error::DeprecatedRecordType
+                    } else {
+                        err
+                    },
+                );
+            }
+        };
         let records = txt_lookup.as_lookup().record_iter().filter_map(|r| {
             let txt_data = r.data()?.as_txt()?.txt_data();
             match txt_data.len() {
@@ -153,11 +400,11 @@ impl Resolver {
             }
         });
 
-        for record in records {
-            result = T::parse(record.as_ref());
-            if result.is_ok() {
-                break;
-            }
+        let mut result = parse_txt_records::<T>(records);
+        if matches!(result, Err(Error::InvalidRecordType))
+            && self.legacy_txt_lookup::<T>(name).await
+        {
+            result = Err(Error::DeprecatedRecordType);
         }
         T::unwrap_txt(self.cache_txt.insert(
             key.into_owned(),
@@ -166,9 +413,34 @@ impl Resolver {
         ))
     }
 
+    /// Returns `true` if a valid record is published using the legacy record type of `T`,
+    /// when enabled with `Config::with_legacy_record_types`.
+    async fn legacy_txt_lookup<T: TxtRecordParser>(&self, name: Name) -> bool {
+        let record_type = match T::LEGACY_RECORD_TYPE {
+            Some(record_type) if self.config().legacy_record_types() => {
+                RecordType::from(record_type)
+            }
+            _ => return false,
+        };
+        self.query(false, |r| r.lookup(name.clone(), record_type))
+            .await
+            .is_ok_and(|lookup| {
+                lookup.record_iter().any(|record| match record.data() {
+                    Some(RData::Unknown { rdata, .. }) => {
+                        T::parse(&character_strings(rdata.anything())).is_ok()
+                    }
+                    _ => false,
+                })
+            })
+    }
+
     pub async fn mx_lookup<'x>(&self, key: impl IntoFqdn<'x>) -> crate::Result<Arc<Vec<MX>>> {
         let key = key.into_fqdn();
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::MX, key.as_ref()).await?;
         if let Some(value) = self.cache_mx.get(key.as_ref()) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
             return Ok(value);
         }
 
@@ -177,10 +449,8 @@ impl Resolver {
             return mock_resolve(key.as_ref());
         }
 
-        let mx_lookup = self
-            .resolver
-            .mx_lookup(Name::from_str_relaxed(key.as_ref())?)
-            .await?;
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        let mx_lookup = timed("MX", &key, self.query(false, |r| r.mx_lookup(name.clone()))).await?;
         let mx_records = mx_lookup.as_lookup().records();
         let mut records: Vec<MX> = Vec::with_capacity(mx_records.len());
         for mx_record in mx_records {
@@ -211,7 +481,11 @@ impl Resolver {
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<Vec<Ipv4Addr>>> {
         let key = key.into_fqdn();
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::A, key.as_ref()).await?;
         if let Some(value) = self.cache_ipv4.get(key.as_ref()) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
             return Ok(value);
         }
 
@@ -220,10 +494,13 @@ impl Resolver {
             return mock_resolve(key.as_ref());
         }
 
-        let ipv4_lookup = self
-            .resolver
-            .ipv4_lookup(Name::from_str_relaxed(key.as_ref())?)
-            .await?;
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        let ipv4_lookup = timed(
+            "A",
+            &key,
+            self.query(false, |r| r.ipv4_lookup(name.clone())),
+        )
+        .await?;
         let ips: Vec<Ipv4Addr> = ipv4_lookup
             .as_lookup()
             .record_iter()
@@ -240,7 +517,11 @@ impl Resolver {
         key: impl IntoFqdn<'x>,
     ) -> crate::Result<Arc<Vec<Ipv6Addr>>> {
         let key = key.into_fqdn();
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::AAAA, key.as_ref()).await?;
         if let Some(value) = self.cache_ipv6.get(key.as_ref()) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
             return Ok(value);
         }
 
@@ -249,10 +530,13 @@ impl Resolver {
             return mock_resolve(key.as_ref());
         }
 
-        let ipv6_lookup = self
-            .resolver
-            .ipv6_lookup(Name::from_str_relaxed(key.as_ref())?)
-            .await?;
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        let ipv6_lookup = timed(
+            "AAAA",
+            &key,
+            self.query(false, |r| r.ipv6_lookup(name.clone())),
+        )
+        .await?;
         let ips = ipv6_lookup
             .as_lookup()
             .record_iter()
@@ -309,7 +593,11 @@ impl Resolver {
     }
 
     pub async fn ptr_lookup<'x>(&self, addr: IpAddr) -> crate::Result<Arc<Vec<String>>> {
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::PTR, &addr.to_string()).await?;
         if let Some(value) = self.cache_ptr.get(&addr) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
             return Ok(value);
         }
 
@@ -318,7 +606,7 @@ impl Resolver {
             return mock_resolve(&addr.to_string());
         }
 
-        let ptr_lookup = self.resolver.reverse_lookup(addr).await?;
+        let ptr_lookup = timed("PTR", &addr, self.query(false, |r| r.reverse_lookup(addr))).await?;
         let ptr = ptr_lookup
             .as_lookup()
             .record_iter()
@@ -337,9 +625,50 @@ impl Resolver {
             .insert(addr, Arc::new(ptr), ptr_lookup.valid_until()))
     }
 
-    pub async fn exists<'x>(&self, key: impl IntoFqdn<'x>) -> crate::Result<bool> {
+    /// Looks up TLSA records using a DNSSEC validating resolver,
+    /// records with unsupported parameters are skipped.
+    pub async fn tlsa_lookup<'x>(&self, key: impl IntoFqdn<'x>) -> crate::Result<Arc<Tlsa>> {
+        let key = key.into_fqdn();
+        #[cfg(any(test, feature = "test"))]
+        let truncated = self.mock_query(RecordType::TLSA, key.as_ref()).await?;
+        if let Some(value) = self.cache_tlsa.get(key.as_ref()) {
+            #[cfg(any(test, feature = "test"))]
+            let value = value.truncate_if(truncated)?;
+            return Ok(value);
+        }
+
         #[cfg(any(test, feature = "test"))]
         if true {
+            return mock_resolve(key.as_ref());
+        }
+
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        let tlsa_lookup = self
+            .query(true, |r| r.lookup(name.clone(), RecordType::TLSA))
+            .await?;
+        let entries = tlsa_lookup
+            .record_iter()
+            .filter_map(|r| match r.data()? {
+                RData::TLSA(tlsa) => TlsaEntry {
+                    usage: u8::from(tlsa.cert_usage()).try_into().ok()?,
+                    selector: u8::from(tlsa.selector()).try_into().ok()?,
+                    matching: u8::from(tlsa.matching()).try_into().ok()?,
+                    data: tlsa.cert_data().to_vec(),
+                }
+                .into(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(self.cache_tlsa.insert(
+            key.into_owned(),
+            Arc::new(Tlsa { entries }),
+            tlsa_lookup.valid_until(),
+        ))
+    }
+
+    pub async fn exists<'x>(&self, key: impl IntoFqdn<'x>) -> crate::Result<bool> {
+        if cfg!(any(test, feature = "test")) || self.offline {
             let key = key.into_fqdn().into_owned();
             return match self.ipv4_lookup(key.as_str()).await {
                 Ok(_) => Ok(true),
@@ -353,11 +682,8 @@ impl Resolver {
         }
 
         let key = key.into_fqdn();
-        match self
-            .resolver
-            .lookup_ip(Name::from_str_relaxed(key.as_ref())?)
-            .await
-        {
+        let name = Name::from_str_relaxed(key.as_ref())?;
+        match self.query(false, |r| r.lookup_ip(name.clone())).await {
             Ok(result) => Ok(result.as_lookup().record_iter().any(|r| {
                 r.data().map_or(false, |d| {
                     matches!(d.record_type(), RecordType::A | RecordType::AAAA)
@@ -411,6 +737,17 @@ impl Resolver {
         self.cache_ptr.insert(name, Arc::new(value), valid_until);
     }
 
+    #[cfg(any(test, feature = "test"))]
+    pub fn tlsa_add<'x>(
+        &self,
+        name: impl IntoFqdn<'x>,
+        value: Tlsa,
+        valid_until: std::time::Instant,
+    ) {
+        self.cache_tlsa
+            .insert(name.into_fqdn().into_owned(), Arc::new(value), valid_until);
+    }
+
     #[cfg(any(test, feature = "test"))]
     pub fn mx_add<'x>(
         &self,
@@ -482,6 +819,18 @@ impl From<TlsRpt> for Txt {
     }
 }
 
+impl From<Bimi> for Txt {
+    fn from(v: Bimi) -> Self {
+        Txt::Bimi(v.into())
+    }
+}
+
+impl From<Vouch> for Txt {
+    fn from(v: Vouch) -> Self {
+        Txt::Vouch(v.into())
+    }
+}
+
 impl<T: Into<Txt>> From<crate::Result<T>> for Txt {
     fn from(v: crate::Result<T>) -> Self {
         match v {
@@ -575,37 +924,56 @@ impl UnwrapTxtRecord for TlsRpt {
     }
 }
 
+impl UnwrapTxtRecord for Bimi {
+    fn unwrap_txt(txt: Txt) -> crate::Result<Arc<Self>> {
+        match txt {
+            Txt::Bimi(a) => Ok(a),
+            Txt::Error(err) => Err(err),
+            _ => Err(Error::Io("Invalid record type".to_string())),
+        }
+    }
+}
+
+impl UnwrapTxtRecord for Vouch {
+    fn unwrap_txt(txt: Txt) -> crate::Result<Arc<Self>> {
+        match txt {
+            Txt::Vouch(a) => Ok(a),
+            Txt::Error(err) => Err(err),
+            _ => Err(Error::Io("Invalid record type".to_string())),
+        }
+    }
+}
+
 pub trait IntoFqdn<'x> {
     fn into_fqdn(self) -> Cow<'x, str>;
 }
 
 impl<'x> IntoFqdn<'x> for String {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
-        }
+        fqdn(&self).into()
     }
 }
 
 impl<'x> IntoFqdn<'x> for &'x str {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
-        }
+        fqdn(self).into()
     }
 }
 
 impl<'x> IntoFqdn<'x> for &String {
     fn into_fqdn(self) -> Cow<'x, str> {
-        if self.ends_with('.') {
-            self.to_lowercase().into()
-        } else {
-            format!("{}.", self.to_lowercase()).into()
-        }
+        fqdn(self).into()
+    }
+}
+
+/// Lowercases a domain name, converting U-labels to A-labels (RFC 8616) and
+/// appending the trailing dot.
+fn fqdn(name: &str) -> String {
+    let name = to_ascii(name).to_lowercase();
+    if name.ends_with('.') {
+        name
+    } else {
+        format!("{name}.")
     }
 }
 
@@ -644,6 +1012,62 @@ impl ToReverseName for IpAddr {
     }
 }
 
+/// Awaits a DNS query, emitting its duration and outcome when the `tracing`
+/// feature is enabled.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+async fn timed<T, E: Display>(
+    record_type: &'static str,
+    name: &impl Display,
+    query: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    #[cfg(feature = "tracing")]
+    let started = std::time::Instant::now();
+    let result = query.await;
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        record_type,
+        name = %name,
+        elapsed_us = started.elapsed().as_micros() as u64,
+        error = result.as_ref().err().map(tracing::field::display),
+        "dns lookup"
+    );
+    result
+}
+
+/// Concatenates the character-strings of a TXT-formatted record.
+fn character_strings(mut rdata: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(rdata.len());
+    while let Some((&len, rest)) = rdata.split_first() {
+        let (string, rest) = rest.split_at(std::cmp::min(len as usize, rest.len()));
+        record.extend_from_slice(string);
+        rdata = rest;
+    }
+    record
+}
+
+/// Returns the first valid record or, for record types that must be unique,
+/// `Error::MultipleRecords` if more than one valid record is published.
+fn parse_txt_records<'x, T: TxtRecordParser>(
+    records: impl Iterator<Item = Cow<'x, [u8]>>,
+) -> crate::Result<T> {
+    let mut result = Err(Error::InvalidRecordType);
+    for record in records {
+        match T::parse(record.as_ref()) {
+            Ok(_) if T::UNIQUE && result.is_ok() => return Err(Error::MultipleRecords),
+            Ok(record) if result.is_err() => {
+                result = Ok(record);
+                if !T::UNIQUE {
+                    break;
+                }
+            }
+            Ok(_) => (),
+            Err(err) if result.is_err() => result = Err(err),
+            Err(_) => (),
+        }
+    }
+    result
+}
+
 #[cfg(any(test, feature = "test"))]
 pub fn mock_resolve<T>(domain: &str) -> crate::Result<T> {
     Err(if domain.contains("_parse_error.") {
@@ -657,11 +1081,93 @@ pub fn mock_resolve<T>(domain: &str) -> crate::Result<T> {
     })
 }
 
+/// Splits a DNS-over-HTTPS URL into its host, port and addresses. Only the
+/// standard `/dns-query` path is supported.
+#[cfg(feature = "dns-over-https")]
+fn parse_doh_url(url: &str) -> Result<(String, u16, Vec<IpAddr>), ResolveError> {
+    use std::net::ToSocketAddrs;
+
+    let authority = url
+        .strip_prefix("https://")
+        .ok_or("DNS-over-HTTPS URLs must use the https scheme")?;
+    let (authority, path) = authority.split_once('/').unwrap_or((authority, ""));
+    if !matches!(path, "" | "dns-query") {
+        return Err(format!("Unsupported DNS-over-HTTPS path /{path}").into());
+    }
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(authority) => {
+            let (host, port) = authority
+                .split_once(']')
+                .ok_or("Invalid IPv6 address in DNS-over-HTTPS URL")?;
+            let port = match port {
+                "" => None,
+                port => Some(
+                    port.strip_prefix(':')
+                        .ok_or("Invalid IPv6 address in DNS-over-HTTPS URL")?,
+                ),
+            };
+            (host, port)
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        Some(port) => port
+            .parse::<u16>()
+            .map_err(|_| format!("Invalid port {port} in DNS-over-HTTPS URL"))?,
+        None => 443,
+    };
+    if host.is_empty() {
+        return Err("Missing host in DNS-over-HTTPS URL".into());
+    }
+
+    let ips = if let Ok(ip) = host.parse::<IpAddr>() {
+        vec![ip]
+    } else {
+        (host, port)
+            .to_socket_addrs()?
+            .map(|addr| addr.ip())
+            .collect::<Vec<_>>()
+    };
+    if ips.is_empty() {
+        return Err(format!("Could not resolve {host}").into());
+    }
+
+    Ok((host.to_string(), port, ips))
+}
+
 #[cfg(test)]
 mod test {
-    use std::net::IpAddr;
-
-    use crate::common::resolver::ToReverseName;
+    use std::{
+        borrow::Cow,
+        net::IpAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use hickory_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        Name,
+    };
+    use tokio::net::UdpSocket;
+
+    #[cfg(feature = "dns-over-https")]
+    use crate::common::resolver::parse_doh_url;
+    use crate::{
+        common::{
+            config::Config,
+            resolver::{character_strings, parse_txt_records, ResolverOptions, ToReverseName},
+            test_dns::{empty_response, resolver_config, serve},
+        },
+        dmarc::Dmarc,
+        spf::Spf,
+        Error, Resolver,
+    };
 
     #[test]
     fn reverse_lookup_addr() {
@@ -679,4 +1185,128 @@ mod test {
             assert_eq!(addr.parse::<IpAddr>().unwrap().to_reverse_name(), expected);
         }
     }
+
+    #[test]
+    fn txt_multiple_records() {
+        let records = |records: &'static [&'static str]| {
+            records
+                .iter()
+                .map(|record| Cow::Borrowed(record.as_bytes()))
+        };
+
+        // Only one DMARC record may be published
+        assert_eq!(
+            parse_txt_records::<Dmarc>(records(&[
+                "v=DMARC1; p=reject",
+                "google-site-verification=abc",
+                "v=DMARC1; p=none",
+            ])),
+            Err(Error::MultipleRecords)
+        );
+        assert_eq!(
+            parse_txt_records::<Dmarc>(records(&["v=spf1 -all", "v=DMARC1; p=reject"]))
+                .unwrap()
+                .p,
+            crate::dmarc::Policy::Reject
+        );
+        assert!(parse_txt_records::<Dmarc>(records(&["v=spf1 -all"])).is_err());
+
+        // Only one SPF record may be published
+        assert_eq!(
+            parse_txt_records::<Spf>(records(&["v=spf1 -all", "v=spf1 +all"])),
+            Err(Error::MultipleRecords)
+        );
+
+        // Type SPF records are decoded from their character-strings
+        assert_eq!(
+            character_strings(b"\x07v=spf1 \x04-all"),
+            b"v=spf1 -all".to_vec()
+        );
+    }
+
+    #[test]
+    fn resolver_options() {
+        let resolver = Resolver::with_options(
+            ResolverConfig::default(),
+            ResolverOptions::new()
+                .with_timeout(Duration::from_secs(1))
+                .with_attempts(1)
+                .with_concurrent_queries(1)
+                .with_edns0(true)
+                .with_cache_capacity(16)
+                .with_txt_cache_capacity(1024),
+        )
+        .unwrap();
+        assert_eq!(resolver.cache_txt.lock().capacity(), 1024);
+        assert_eq!(resolver.cache_mx.lock().capacity(), 16);
+        assert_eq!(resolver.cache_ipv6.lock().capacity(), 16);
+        assert_eq!(resolver.cache_ptr.lock().capacity(), 16);
+
+        // The DNSSEC validating resolver is created on first use and shared by clones
+        assert!(resolver.dns_config.dnssec.get().is_none());
+        resolver.clone().dns_config.dnssec_resolver();
+        assert!(resolver.dns_config.dnssec.get().is_some());
+    }
+
+    #[tokio::test]
+    async fn legacy_record_types() {
+        // Answers every query with an empty NOERROR response
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+        serve(socket, {
+            let queries = queries.clone();
+            move |query| {
+                queries.fetch_add(1, Ordering::Relaxed);
+                empty_response(query)
+            }
+        });
+        let resolver =
+            Resolver::with_capacity(resolver_config(addr), ResolverOpts::default(), 128).unwrap();
+        let name = Name::from_str_relaxed("example.org.").unwrap();
+
+        // Deprecated record types are only looked up when enabled
+        assert!(!resolver.legacy_txt_lookup::<Spf>(name.clone()).await);
+        assert_eq!(queries.load(Ordering::Relaxed), 0);
+        resolver.set_config(Config::new().with_legacy_record_types(true));
+        assert!(!resolver.legacy_txt_lookup::<Spf>(name.clone()).await);
+        assert_eq!(queries.load(Ordering::Relaxed), 1);
+        assert!(!resolver.legacy_txt_lookup::<Dmarc>(name).await);
+        assert_eq!(queries.load(Ordering::Relaxed), 1);
+    }
+
+    #[cfg(feature = "dns-over-https")]
+    #[test]
+    fn doh_url() {
+        for (url, expected) in [
+            (
+                "https://1.1.1.1/dns-query",
+                Some(("1.1.1.1", 443, "1.1.1.1")),
+            ),
+            (
+                "https://[2606:4700::1111]:8443",
+                Some(("2606:4700::1111", 8443, "2606:4700::1111")),
+            ),
+            ("https://localhost:853/", Some(("localhost", 853, ""))),
+            ("http://1.1.1.1/dns-query", None),
+            ("https://1.1.1.1/resolve", None),
+            ("https://1.1.1.1:dns/dns-query", None),
+            ("https://[2606:4700::1111]x", None),
+            ("https:///dns-query", None),
+        ] {
+            let result = parse_doh_url(url);
+            match expected {
+                Some((host, port, ip)) => {
+                    let (parsed_host, parsed_port, ips) = result.unwrap();
+                    assert_eq!((parsed_host.as_str(), parsed_port), (host, port), "{url}");
+                    assert!(!ips.is_empty());
+                    if !ip.is_empty() {
+                        assert_eq!(ips, vec![ip.parse::<IpAddr>().unwrap()], "{url}");
+                    }
+                }
+                None => assert!(result.is_err(), "{url}"),
+            }
+        }
+        assert!(Resolver::new_doh("https://1.1.1.1/dns-query").is_ok());
+    }
 }
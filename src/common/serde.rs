@@ -0,0 +1,145 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use ::serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use mail_builder::encoders::base64::base64_encode;
+use mail_parser::decoders::base64::base64_decode;
+
+use super::{
+    crypto::{Algorithm, VerifyingKeyType},
+    verify::DomainKey,
+};
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KeyType {
+    Rsa,
+    Ed25519,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DomainKeyRecord {
+    k: KeyType,
+    p: String,
+    f: u64,
+}
+
+impl Serialize for DomainKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let (algorithm, public_key) = self
+            .p
+            .public_key()
+            .ok_or_else(|| S::Error::custom("public key cannot be exported"))?;
+        DomainKeyRecord {
+            k: match algorithm {
+                Algorithm::RsaSha1 | Algorithm::RsaSha256 => KeyType::Rsa,
+                Algorithm::Ed25519Sha256 => KeyType::Ed25519,
+            },
+            p: String::from_utf8(base64_encode(&public_key).map_err(S::Error::custom)?)
+                .map_err(S::Error::custom)?,
+            f: self.f,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DomainKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let record = DomainKeyRecord::deserialize(deserializer)?;
+        let public_key = base64_decode(record.p.as_bytes())
+            .ok_or_else(|| D::Error::custom("invalid base64 public key"))?;
+        Ok(DomainKey {
+            p: match record.k {
+                KeyType::Rsa => VerifyingKeyType::Rsa,
+                KeyType::Ed25519 => VerifyingKeyType::Ed25519,
+            }
+            .verifying_key(&public_key)
+            .map_err(D::Error::custom)?,
+            f: record.f,
+        })
+    }
+}
+
+pub(crate) mod response_code {
+    use ::serde::{Deserialize, Deserializer, Serializer};
+    use hickory_resolver::proto::op::ResponseCode;
+
+    pub(crate) fn serialize<S: Serializer>(
+        code: &ResponseCode,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16((*code).into())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ResponseCode, D::Error> {
+        u16::deserialize(deserializer).map(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use hickory_resolver::proto::op::ResponseCode;
+
+    use crate::{
+        common::{parse::TxtRecordParser, verify::DomainKey},
+        dmarc::Dmarc,
+        spf::Spf,
+        DmarcOutput, DmarcResult, Error, SpfOutput, SpfResult,
+    };
+
+    #[test]
+    fn serde_round_trip() {
+        let spf = Spf::parse(b"v=spf1 ip4:192.0.2.0/24 include:example.org -all").unwrap();
+        assert_eq!(
+            serde_json::from_str::<Spf>(&serde_json::to_string(&spf).unwrap()).unwrap(),
+            spf
+        );
+
+        let dmarc = Arc::new(
+            Dmarc::parse(b"v=DMARC1; p=reject; rua=mailto:dmarc@example.org; pct=50").unwrap(),
+        );
+        let output = DmarcOutput::default()
+            .with_domain("example.org")
+            .with_spf_result(DmarcResult::Pass)
+            .with_dkim_result(DmarcResult::TempError(Error::DnsRecordNotFound(
+                ResponseCode::ServFail,
+            )))
+            .with_record(dmarc);
+        assert_eq!(
+            serde_json::from_str::<DmarcOutput>(&serde_json::to_string(&output).unwrap()).unwrap(),
+            output
+        );
+
+        let output = SpfOutput::new("example.org".to_string()).with_result(SpfResult::SoftFail);
+        assert_eq!(
+            serde_json::from_str::<SpfOutput>(&serde_json::to_string(&output).unwrap()).unwrap(),
+            output
+        );
+
+        for record in [
+            "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=",
+            concat!(
+                "v=DKIM1; t=y; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQCYtb/9Sh8nGKV7",
+                "exhUFS+cBNXlHgO1CxD9zIfQd5ztlq1LO7g38dfmFpQafh9lKgqPBTolFhZxhF1yUNThpV67",
+                "3NdAtaCVGNyx/fTYtvyyFe9DH2tmm/ijLlygDRboSkIJ4NHZjK++48hkNP8/htqWHS+CvwWT",
+                "4Qgs0NtB7Re9bQIDAQAB"
+            ),
+        ] {
+            let key = DomainKey::parse(record.as_bytes()).unwrap();
+            let json = serde_json::to_string(&key).unwrap();
+            let key = serde_json::from_str::<DomainKey>(&json).unwrap();
+            assert_eq!(serde_json::to_string(&key).unwrap(), json);
+        }
+    }
+}
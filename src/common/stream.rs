@@ -0,0 +1,371 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{
+    dkim::{canonicalize::BodyCanonicalizer, Canonicalization},
+    AuthenticatedMessage, Error,
+};
+
+use super::{
+    crypto::{HashAlgorithm, HashContext, HashImpl, Sha1, Sha256},
+    headers::Writer,
+    parse::TagStrictness,
+};
+
+/// Incremental message parser that hashes the body as it is received and only
+/// keeps the headers in memory.
+pub struct MessageStreamParser {
+    headers: Vec<u8>,
+    line_start: usize,
+    scan_pos: usize,
+    body_hashers: Option<Vec<BodyHasher>>,
+    requested_hashes: Vec<(Canonicalization, HashAlgorithm)>,
+    body_len: usize,
+    max_header_size: usize,
+    strict: bool,
+    tag_strictness: TagStrictness,
+}
+
+/// Message headers and body hashes produced by a `MessageStreamParser`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamedMessage {
+    headers: Vec<u8>,
+    body_hashes: Vec<(Canonicalization, HashAlgorithm, u64, Vec<u8>)>,
+    body_len: usize,
+    strict: bool,
+    tag_strictness: TagStrictness,
+}
+
+struct BodyHasher {
+    cb: Canonicalization,
+    ha: HashAlgorithm,
+    l: u64,
+    remaining: u64,
+    canonicalizer: BodyCanonicalizer,
+    context: BodyHashContext,
+}
+
+enum BodyHashContext {
+    Sha1(<Sha1 as HashImpl>::Context),
+    Sha256(<Sha256 as HashImpl>::Context),
+}
+
+impl MessageStreamParser {
+    pub fn new() -> Self {
+        Self::with_strictness(true, TagStrictness::Forgiving)
+    }
+
+    /// Creates a parser with the same options as `AuthenticatedMessage::parse_with_strictness`.
+    pub fn with_strictness(strict: bool, tag_strictness: TagStrictness) -> Self {
+        MessageStreamParser {
+            headers: Vec::new(),
+            line_start: 0,
+            scan_pos: 0,
+            body_hashers: None,
+            requested_hashes: Vec::new(),
+            body_len: 0,
+            max_header_size: MAX_HEADER_SIZE,
+            strict,
+            tag_strictness,
+        }
+    }
+
+    /// Requests an additional body hash, such as the one needed to sign or seal the message.
+    pub fn with_body_hash(mut self, cb: Canonicalization, ha: HashAlgorithm) -> Self {
+        self.requested_hashes.push((cb, ha));
+        self
+    }
+
+    /// Sets the maximum size of the header section, 1 MiB by default.
+    pub fn with_max_header_size(mut self, max_header_size: usize) -> Self {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    /// Feeds the next chunk of the message. Fails once the header section
+    /// exceeds the maximum size, after which the parser rejects further input.
+    pub fn write(&mut self, mut chunk: &[u8]) -> crate::Result<()> {
+        if self.body_hashers.is_none() {
+            if self.headers.len() > self.max_header_size {
+                return Err(Error::Io("Message headers are too large".to_string()));
+            }
+            self.headers.extend_from_slice(chunk);
+            let offset = self.find_body_offset();
+            if offset.unwrap_or(self.headers.len()) > self.max_header_size {
+                self.headers.truncate(self.max_header_size + 1);
+                return Err(Error::Io("Message headers are too large".to_string()));
+            }
+            match offset {
+                Some(offset) => {
+                    chunk = &chunk[chunk.len() - (self.headers.len() - offset)..];
+                    self.headers.truncate(offset);
+                    self.body_hashers = Some(self.create_hashers());
+                }
+                None => return Ok(()),
+            }
+        }
+
+        self.body_len += chunk.len();
+        for hasher in self.body_hashers.iter_mut().flatten() {
+            hasher.write(chunk);
+        }
+        Ok(())
+    }
+
+    /// Reads the remainder of the message from a reader.
+    pub fn read_from(&mut self, mut reader: impl std::io::Read) -> std::io::Result<()> {
+        let mut buf = vec![0u8; 8192];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => return Ok(()),
+                n => self.write(&buf[..n]).map_err(|err| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+                })?,
+            }
+        }
+    }
+
+    /// Completes parsing and returns the headers and body hashes.
+    pub fn finish(self) -> StreamedMessage {
+        let body_hashers = match self.body_hashers {
+            Some(body_hashers) => body_hashers,
+            None => {
+                let mut parser = self;
+                parser.body_hashers = Some(parser.create_hashers());
+                return parser.finish();
+            }
+        };
+
+        StreamedMessage {
+            body_hashes: body_hashers
+                .into_iter()
+                .map(|hasher| hasher.finish())
+                .collect(),
+            headers: self.headers,
+            body_len: self.body_len,
+            strict: self.strict,
+            tag_strictness: self.tag_strictness,
+        }
+    }
+
+    fn find_body_offset(&mut self) -> Option<usize> {
+        while self.scan_pos < self.headers.len() {
+            let pos = self.scan_pos;
+            self.scan_pos += 1;
+            if self.headers[pos] == b'\n' {
+                if matches!(&self.headers[self.line_start..pos], b"" | b"\r") {
+                    return Some(pos + 1);
+                }
+                self.line_start = pos + 1;
+            }
+        }
+        None
+    }
+
+    fn create_hashers(&self) -> Vec<BodyHasher> {
+        let mut hashes = AuthenticatedMessage::parse_with_strictness(
+            &self.headers,
+            self.strict,
+            self.tag_strictness,
+        )
+        .map(|message| {
+            message
+                .body_hashes
+                .into_iter()
+                .map(|(cb, ha, l, _)| (cb, ha, l))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+        for (cb, ha) in &self.requested_hashes {
+            if !hashes.contains(&(*cb, *ha, 0)) {
+                hashes.push((*cb, *ha, 0));
+            }
+        }
+
+        hashes
+            .into_iter()
+            .map(|(cb, ha, l)| BodyHasher {
+                cb,
+                ha,
+                l,
+                remaining: if l == 0 { u64::MAX } else { l },
+                canonicalizer: BodyCanonicalizer::new(cb),
+                context: match ha {
+                    HashAlgorithm::Sha1 => BodyHashContext::Sha1(Sha1::hasher()),
+                    HashAlgorithm::Sha256 => BodyHashContext::Sha256(Sha256::hasher()),
+                },
+            })
+            .collect()
+    }
+}
+
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+
+impl Default for MessageStreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::io::Write for MessageStreamParser {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        MessageStreamParser::write(self, buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StreamedMessage {
+    /// Returns the parsed message, with body hashes computed from the streamed body.
+    pub fn message(&self) -> Option<AuthenticatedMessage<'_>> {
+        let mut message = AuthenticatedMessage::parse_with_strictness(
+            &self.headers,
+            self.strict,
+            self.tag_strictness,
+        )?;
+        message.body_hashes = self.body_hashes.clone();
+        message.body_len = self.body_len;
+        Some(message)
+    }
+
+    /// Returns the body hash computed for a canonicalization and hash algorithm, without length limit.
+    pub fn body_hash(&self, cb: Canonicalization, ha: HashAlgorithm) -> Option<&[u8]> {
+        self.body_hashes
+            .iter()
+            .find(|(c, h, l, _)| *c == cb && *h == ha && *l == 0)
+            .map(|(_, _, _, bh)| bh.as_slice())
+    }
+
+    /// Returns the raw message headers, including the separating blank line.
+    pub fn raw_headers(&self) -> &[u8] {
+        &self.headers
+    }
+
+    /// Returns the length of the message body in bytes.
+    pub fn body_len(&self) -> usize {
+        self.body_len
+    }
+}
+
+impl BodyHasher {
+    fn write(&mut self, chunk: &[u8]) {
+        let chunk = &chunk[..std::cmp::min(self.remaining, chunk.len() as u64) as usize];
+        self.remaining -= chunk.len() as u64;
+        self.canonicalizer.write(chunk, &mut self.context);
+    }
+
+    fn finish(mut self) -> (Canonicalization, HashAlgorithm, u64, Vec<u8>) {
+        self.canonicalizer.finish(&mut self.context);
+        let hash = match self.context {
+            BodyHashContext::Sha1(context) => context.complete(),
+            BodyHashContext::Sha256(context) => context.complete(),
+        };
+        (self.cb, self.ha, self.l, hash.as_ref().to_vec())
+    }
+}
+
+impl Writer for BodyHashContext {
+    fn write(&mut self, buf: &[u8]) {
+        match self {
+            BodyHashContext::Sha1(context) => context.write(buf),
+            BodyHashContext::Sha256(context) => context.write(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            crypto::HashAlgorithm,
+            headers::HeaderWriter,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::{Canonicalization, DkimSigner},
+        AuthenticatedMessage, DkimResult,
+    };
+
+    use super::MessageStreamParser;
+
+    #[tokio::test]
+    async fn stream_parse() {
+        let message = concat!(
+            "From: hello@example.com\r\n",
+            "To: dkim@example.com\r\n",
+            "Subject: Streaming\r\n",
+            " DKIM\r\n\r\n",
+            "Line  one \r\n",
+            "Line two\t\r\n",
+            "\r\n\r\n",
+        );
+
+        // Sign from a streamed message
+        let mut parser = MessageStreamParser::new()
+            .with_body_hash(Canonicalization::Relaxed, HashAlgorithm::Sha256);
+        for chunk in message.as_bytes().chunks(7) {
+            parser.write(chunk).unwrap();
+        }
+        let streamed = parser.finish();
+        assert_eq!(streamed.body_len(), 27);
+        assert_eq!(streamed.message().unwrap().body_len, 27);
+        assert!(streamed.raw_headers().ends_with(b"DKIM\r\n\r\n"));
+
+        let signer = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.com")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+        let signature = signer.sign_streamed(&streamed).unwrap();
+        assert_eq!(signature.bh, signer.sign(message.as_bytes()).unwrap().bh);
+
+        let mut parser = MessageStreamParser::new();
+        parser.write(message.as_bytes()).unwrap();
+        assert_eq!(
+            signer.sign_streamed(&parser.finish()).unwrap_err(),
+            crate::Error::MissingParameters
+        );
+
+        // Stream the signed message and compare with a full parse
+        let signed_message = signature.to_header() + message;
+        for chunk_size in [1, 2, 3, 5, 64, signed_message.len()] {
+            let mut parser = MessageStreamParser::new();
+            for chunk in signed_message.as_bytes().chunks(chunk_size) {
+                std::io::Write::write_all(&mut parser, chunk).unwrap();
+            }
+            let streamed = parser.finish();
+            let streamed_message = streamed.message().unwrap();
+            let parsed_message = AuthenticatedMessage::parse(signed_message.as_bytes()).unwrap();
+            assert_eq!(streamed_message.body_hashes, parsed_message.body_hashes);
+            assert_eq!(streamed_message.dkim_headers.len(), 1);
+            assert_eq!(streamed.body_len(), 27);
+        }
+
+        // Verify the streamed message
+        let resolver = ed25519_resolver("ed._domainkey.example.com.");
+        let mut parser = MessageStreamParser::new();
+        parser.read_from(signed_message.as_bytes()).unwrap();
+        let streamed = parser.finish();
+        let streamed_message = streamed.message().unwrap();
+        let dkim = resolver.verify_dkim(&streamed_message).await;
+        assert_eq!(dkim.len(), 1);
+        assert_eq!(dkim[0].result(), &DkimResult::Pass);
+
+        // Header section over the maximum size
+        let mut parser = MessageStreamParser::new().with_max_header_size(64);
+        parser.write(&signed_message.as_bytes()[..32]).unwrap();
+        assert!(parser.write(signed_message.as_bytes()).is_err());
+        assert!(parser.write(b"\r\n").is_err());
+        let mut parser = MessageStreamParser::new().with_max_header_size(message.len());
+        assert!(parser.read_from(signed_message.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::{
+    common::{config::Config, lru::DnsCache},
+    Resolver,
+};
+
+/// Tenant of a resolver created with `Resolver::for_tenant`.
+pub(crate) struct Tenant {
+    name: String,
+    // Shared settings and the tenant settings derived from them
+    config: Mutex<Option<(Arc<Config>, Arc<Config>)>>,
+}
+
+impl Resolver {
+    /// Returns a resolver for a tenant, sharing the DNS clients and settings of
+    /// this resolver but with its own empty caches of the same capacities, so
+    /// that lookups and cached results of one tenant are never seen by another.
+    /// Caches start empty on every call, so a tenant resolver is meant to be
+    /// created once and kept for as long as the tenant is served.
+    ///
+    /// The resolver uses the settings returned by `Config::for_tenant`, derived
+    /// again whenever the shared settings are replaced with `set_config` on
+    /// this resolver or any of its tenant resolvers.
+    pub fn for_tenant(&self, tenant: impl Into<String>) -> Resolver {
+        Resolver {
+            resolver: self.resolver.clone(),
+            dns_config: self.dns_config.clone(),
+            cache_txt: DnsCache::with_capacity(self.cache_txt.lock().capacity()),
+            cache_mx: DnsCache::with_capacity(self.cache_mx.lock().capacity()),
+            cache_ipv4: DnsCache::with_capacity(self.cache_ipv4.lock().capacity()),
+            cache_ipv6: DnsCache::with_capacity(self.cache_ipv6.lock().capacity()),
+            cache_ptr: DnsCache::with_capacity(self.cache_ptr.lock().capacity()),
+            cache_mta_sts: DnsCache::with_capacity(self.cache_mta_sts.lock().capacity()),
+            cache_tlsa: DnsCache::with_capacity(self.cache_tlsa.lock().capacity()),
+            cache_spf: self.cache_spf.as_ref().map(|cache| cache.empty()),
+            cache_iprev: DnsCache::with_capacity(self.cache_iprev.lock().capacity()),
+            config: self.config.clone(),
+            propagation_resolvers: self.propagation_resolvers.clone(),
+            quorum: self.quorum.clone(),
+            fairness: self.fairness.clone(),
+            tenant: Some(Arc::new(Tenant {
+                name: tenant.into(),
+                config: Mutex::new(None),
+            })),
+            arc_scorer: self.arc_scorer.clone(),
+            upstreams: self.upstreams.clone(),
+            offline: self.offline,
+            #[cfg(any(test, feature = "test"))]
+            mock: self.mock.clone(),
+        }
+    }
+
+    /// Returns the tenant of a resolver created with `for_tenant`.
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_ref().map(|tenant| tenant.name.as_str())
+    }
+}
+
+impl Tenant {
+    /// Returns the settings of the tenant, deriving them only after the shared
+    /// settings were replaced.
+    pub(crate) fn config(&self, shared: Arc<Config>) -> Arc<Config> {
+        let mut cached = self.config.lock();
+        match cached.as_ref() {
+            Some((base, config)) if Arc::ptr_eq(base, &shared) => config.clone(),
+            _ => {
+                let config = Arc::new(shared.for_tenant(&self.name));
+                *cached = Some((shared, config.clone()));
+                config
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::{
+            config::{Config, CryptoPolicy, DomainOverride, TenantSettings},
+            parse::TxtRecordParser,
+        },
+        spf::{cache::SpfCache, Spf},
+        Resolver, SpfResult,
+    };
+
+    #[tokio::test]
+    async fn tenant_isolation() {
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_spf_cache(SpfCache::new(16))
+            .with_config(
+                Config::new()
+                    .with_trusted_sealer("arc.example")
+                    .with_override(
+                        "legacy.example",
+                        DomainOverride::new()
+                            .with_crypto_policy(CryptoPolicy::default().with_rsa_sha1(false)),
+                    )
+                    .with_tenant(
+                        "acme",
+                        TenantSettings::new()
+                            .with_trusted_sealer("Lists.Acme.Example")
+                            .with_override(
+                                "legacy.example",
+                                DomainOverride::new().with_crypto_policy(
+                                    CryptoPolicy::default().with_rsa_sha1(true),
+                                ),
+                            ),
+                    ),
+            );
+        let acme = resolver.for_tenant("acme");
+        let other = resolver.for_tenant("other");
+        assert_eq!(resolver.tenant(), None);
+        assert_eq!(acme.tenant(), Some("acme"));
+        assert_eq!(
+            acme.cache_txt.lock().capacity(),
+            resolver.cache_txt.lock().capacity()
+        );
+
+        // Settings of a tenant are not seen by other tenants
+        for (resolver, trusted, sha1) in [
+            (&resolver, false, false),
+            (&acme, true, true),
+            (&other, false, false),
+        ] {
+            let config = resolver.config();
+            assert!(config.is_trusted_sealer("arc.example"));
+            assert_eq!(config.is_trusted_sealer("lists.acme.example"), trusted);
+            assert_eq!(
+                config.crypto_policy("legacy.example").allows_rsa_sha1(),
+                sha1
+            );
+        }
+
+        // Settings replaced later apply to existing tenants
+        let snapshot = acme.config();
+        assert!(Arc::ptr_eq(&snapshot, &acme.config()));
+        resolver.set_config(
+            Config::new()
+                .with_trusted_sealer("arc2.example")
+                .with_tenant(
+                    "acme",
+                    TenantSettings::new().with_trusted_sealer("lists.acme.example"),
+                ),
+        );
+        for (resolver, trusted) in [(&resolver, false), (&acme, true), (&other, false)] {
+            let config = resolver.config();
+            assert!(config.is_trusted_sealer("arc2.example"));
+            assert!(!config.is_trusted_sealer("arc.example"));
+            assert_eq!(config.is_trusted_sealer("lists.acme.example"), trusted);
+        }
+        assert!(snapshot.is_trusted_sealer("arc.example"));
+
+        // Records cached by a tenant are not seen by other tenants
+        let ip = "192.0.2.1".parse().unwrap();
+        acme.txt_add(
+            "acme.example.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.1 -all"),
+            Instant::now() + Duration::from_secs(30),
+        );
+        for (resolver, result) in [
+            (&acme, SpfResult::Pass),
+            (&other, SpfResult::None),
+            (&resolver, SpfResult::None),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_spf_sender(ip, "mx.acme.example", "acme.example", "a@acme.example")
+                    .await
+                    .result(),
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,49 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Local UDP name server for the tests that exercise real DNS traffic.
+
+use std::net::SocketAddr;
+
+use hickory_resolver::config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverConfig};
+use tokio::net::UdpSocket;
+
+/// Answers each query received on `socket` with the response returned by
+/// `answer`, dropping the query when it returns `None`.
+pub(crate) fn serve(
+    socket: UdpSocket,
+    mut answer: impl FnMut(&[u8]) -> Option<Vec<u8>> + Send + 'static,
+) {
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        while let Ok((len, addr)) = socket.recv_from(&mut buf).await {
+            if let Some(response) = answer(&buf[..len]) {
+                let _ = socket.send_to(&response, addr).await;
+            }
+        }
+    });
+}
+
+/// Builds an empty NOERROR response to a query.
+pub(crate) fn empty_response(query: &[u8]) -> Option<Vec<u8>> {
+    (query.len() >= 12).then(|| {
+        let mut response = query.to_vec();
+        response[2] |= 0x80;
+        response[3] = 0x80;
+        response
+    })
+}
+
+/// Returns a configuration querying only the name server at `addr`.
+pub(crate) fn resolver_config(addr: SocketAddr) -> ResolverConfig {
+    let mut servers = NameServerConfigGroup::new();
+    servers.push(NameServerConfig::new(addr, Protocol::Udp));
+    ResolverConfig::from_parts(None, vec![], servers)
+}
@@ -0,0 +1,56 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Ed25519 key pair of the RFC 8463 test vectors, used by the self-test, the
+//! benchmarks and the unit tests.
+
+use mail_parser::decoders::base64::base64_decode;
+
+use super::crypto::Ed25519Key;
+
+pub(crate) const ED25519_PRIVATE_KEY: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
+pub(crate) const ED25519_PUBLIC_KEY: &str = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+#[cfg(test)]
+pub(crate) const ED25519_RECORD: &str =
+    "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+
+pub(crate) fn ed25519_key() -> crate::Result<Ed25519Key> {
+    let private_key = base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap_or_default();
+    #[cfg(feature = "rust-crypto")]
+    let key = Ed25519Key::from_bytes(&private_key);
+    #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+    let key = Ed25519Key::from_seed_and_public_key(
+        &private_key,
+        &base64_decode(ED25519_PUBLIC_KEY.as_bytes()).unwrap_or_default(),
+    );
+    key
+}
+
+#[cfg(test)]
+pub(crate) fn ed25519_public_key() -> Vec<u8> {
+    base64_decode(ED25519_PUBLIC_KEY.as_bytes()).unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) fn ed25519_domain_key() -> crate::common::verify::DomainKey {
+    crate::common::parse::TxtRecordParser::parse(ED25519_RECORD.as_bytes()).unwrap()
+}
+
+/// Returns a test resolver publishing the public key under `name`.
+#[cfg(test)]
+pub(crate) fn ed25519_resolver(name: &str) -> crate::Resolver {
+    let resolver = crate::Resolver::new_system_conf().unwrap();
+    resolver.txt_add(
+        name,
+        ed25519_domain_key(),
+        std::time::Instant::now() + std::time::Duration::new(3600, 0),
+    );
+    resolver
+}
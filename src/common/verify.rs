@@ -8,11 +8,19 @@
  * except according to those terms.
  */
 
-use std::net::IpAddr;
+use std::{
+    future::Future,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
 
-use crate::{dkim::Canonicalization, Error, IprevOutput, IprevResult, Resolver};
+use crate::{dkim::Canonicalization, Domain, Error, IprevOutput, IprevResult, Resolver};
 
-use super::crypto::{Algorithm, VerifyingKey};
+use super::{
+    config::IprevPolicy,
+    crypto::{Algorithm, VerifyingKey},
+    lru::DnsCache,
+};
 
 pub struct DomainKey {
     pub p: Box<dyn VerifyingKey + Send + Sync>,
@@ -20,62 +28,116 @@ pub struct DomainKey {
 }
 
 impl Resolver {
+    /// Verifies that the address has a PTR name resolving back to it,
+    /// as configured by the `IprevPolicy` of the resolver.
     pub async fn verify_iprev(&self, addr: IpAddr) -> IprevOutput {
-        match self.ptr_lookup(addr).await {
-            Ok(ptr) => {
-                let mut last_err = None;
-                for host in ptr.iter().take(2) {
-                    match &addr {
-                        IpAddr::V4(ip) => match self.ipv4_lookup(host).await {
-                            Ok(ips) => {
-                                if ips.iter().any(|cip| cip == ip) {
-                                    return IprevOutput {
-                                        result: IprevResult::Pass,
-                                        ptr: ptr.into(),
-                                    };
-                                }
-                            }
-                            Err(err) => {
-                                last_err = err.into();
-                            }
-                        },
-                        IpAddr::V6(ip) => match self.ipv6_lookup(host).await {
-                            Ok(ips) => {
-                                if ips.iter().any(|cip| cip == ip) {
-                                    return IprevOutput {
-                                        result: IprevResult::Pass,
-                                        ptr: ptr.into(),
-                                    };
-                                }
-                            }
-                            Err(err) => {
-                                last_err = err.into();
-                            }
-                        },
-                    }
+        let config = self.config();
+        let policy = config.iprev_policy();
+        let negative_ttl = policy.negative_ttl();
+        // Results are cached per policy, as another policy may not fail
+        if negative_ttl.is_some() {
+            if let Some(output) = self.cache_iprev.get(&(addr, *policy)) {
+                return output;
+            }
+        }
+
+        let output = self.verify_iprev_uncached(addr, policy).await;
+        if let Some(ttl) = negative_ttl {
+            if matches!(
+                output.result,
+                IprevResult::Fail(_) | IprevResult::PermError(_)
+            ) {
+                self.cache_iprev
+                    .insert((addr, *policy), output.clone(), Instant::now() + ttl);
+            }
+        }
+        output
+    }
+
+    async fn verify_iprev_uncached(&self, addr: IpAddr, policy: &IprevPolicy) -> IprevOutput {
+        let timeout = policy.lookup_timeout();
+        let ptr = match with_timeout(timeout, self.ptr_lookup(addr)).await {
+            Ok(ptr) => ptr,
+            Err(err) => {
+                return IprevOutput {
+                    result: err.into(),
+                    ptr: None,
+                    confirmed: None,
                 }
+            }
+        };
 
-                IprevOutput {
-                    result: if let Some(err) = last_err {
-                        err.into()
-                    } else {
-                        IprevResult::Fail(Error::NotAligned)
-                    },
-                    ptr: ptr.into(),
+        let mut confirmed = None;
+        let mut all_confirmed = true;
+        let mut last_err = None;
+        for host in ptr.iter().take(policy.max_ptr()) {
+            let matches = match &addr {
+                IpAddr::V4(ip) => with_timeout(timeout, self.ipv4_lookup(host))
+                    .await
+                    .map(|ips| ips.contains(ip)),
+                IpAddr::V6(ip) => with_timeout(timeout, self.ipv6_lookup(host))
+                    .await
+                    .map(|ips| ips.contains(ip)),
+            };
+            match matches {
+                Ok(true) => {
+                    confirmed.get_or_insert_with(|| host.trim_end_matches('.').to_string());
+                    if !policy.require_all() {
+                        break;
+                    }
+                }
+                Ok(false) => {
+                    all_confirmed = false;
+                }
+                Err(err) => {
+                    all_confirmed = false;
+                    last_err = err.into();
                 }
             }
-            Err(err) => IprevOutput {
-                result: err.into(),
-                ptr: None,
+        }
+
+        IprevOutput {
+            result: if confirmed.is_some() && (all_confirmed || !policy.require_all()) {
+                IprevResult::Pass
+            } else if let Some(err) = last_err {
+                err.into()
+            } else {
+                IprevResult::Fail(Error::NotAligned)
             },
+            ptr: ptr.into(),
+            confirmed: confirmed.filter(|_| all_confirmed || !policy.require_all()),
         }
     }
 }
 
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    lookup: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, lookup)
+            .await
+            .unwrap_or_else(|_| Err(Error::DnsError("lookup timed out".to_string()))),
+        None => lookup.await,
+    }
+}
+
 impl IprevOutput {
     pub fn result(&self) -> &IprevResult {
         &self.result
     }
+
+    /// Returns the PTR name that forward-confirmed the address, if any.
+    pub fn confirmed(&self) -> Option<&str> {
+        self.confirmed.as_deref()
+    }
+
+    /// Returns `true` if the forward-confirmed PTR name matches the HELO domain.
+    pub fn matches_helo(&self, helo: &str) -> bool {
+        self.confirmed
+            .as_deref()
+            .is_some_and(|confirmed| Domain::normalize(confirmed) == Domain::normalize(helo))
+    }
 }
 
 impl DomainKey {
@@ -114,3 +176,92 @@ pub trait VerifySignature {
         key
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::config::{Config, IprevPolicy},
+        Error, IprevResult, Resolver,
+    };
+
+    #[tokio::test]
+    async fn iprev_policy() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3600, 0);
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        resolver.ptr_add(
+            ip,
+            vec![
+                "unrelated.example.net.".to_string(),
+                "mail.example.org.".to_string(),
+                "mx.example.org.".to_string(),
+            ],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "unrelated.example.net.",
+            vec!["198.51.100.1".parse().unwrap()],
+            valid_until,
+        );
+        for host in ["mail.example.org.", "mx.example.org."] {
+            resolver.ipv4_add(host, vec!["192.0.2.1".parse().unwrap()], valid_until);
+        }
+
+        // Any of the first two names may forward-confirm
+        let output = resolver.verify_iprev(ip).await;
+        assert_eq!(output.result(), &IprevResult::Pass);
+        assert_eq!(output.confirmed(), Some("mail.example.org"));
+        assert!(output.matches_helo("Mail.Example.ORG"));
+        assert!(!output.matches_helo("mx.example.org"));
+
+        // Following a single name
+        resolver
+            .set_config(Config::new().with_iprev_policy(IprevPolicy::default().with_max_ptr(1)));
+        let output = resolver.verify_iprev(ip).await;
+        assert_eq!(output.result(), &IprevResult::Fail(Error::NotAligned));
+        assert_eq!(output.confirmed(), None);
+
+        // Every name has to forward-confirm
+        resolver.set_config(
+            Config::new().with_iprev_policy(
+                IprevPolicy::default()
+                    .with_max_ptr(3)
+                    .with_require_all(true)
+                    .with_negative_ttl(Duration::from_secs(60)),
+            ),
+        );
+        let output = resolver.verify_iprev(ip).await;
+        assert_eq!(output.result(), &IprevResult::Fail(Error::NotAligned));
+        assert_eq!(output.confirmed(), None);
+
+        // Negative results are cached for the policy that produced them
+        let require_all = resolver.config();
+        resolver.set_config(
+            Config::new().with_iprev_policy(
+                IprevPolicy::default()
+                    .with_max_ptr(3)
+                    .with_negative_ttl(Duration::from_secs(60)),
+            ),
+        );
+        assert_eq!(resolver.verify_iprev(ip).await.result(), &IprevResult::Pass);
+        resolver.set_config(require_all.as_ref().clone());
+        resolver.ipv4_add(
+            "unrelated.example.net.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+        assert_eq!(
+            resolver.verify_iprev(ip).await.result(),
+            &IprevResult::Fail(Error::NotAligned)
+        );
+        resolver.cache_iprev.lock().clear();
+        let output = resolver.verify_iprev(ip).await;
+        assert_eq!(output.result(), &IprevResult::Pass);
+        assert_eq!(output.confirmed(), Some("unrelated.example.net"));
+    }
+}
@@ -0,0 +1,453 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Regression runner that verifies a directory of `.eml` messages against a
+//! DNS snapshot and records the verdicts as a machine-readable matrix.
+//!
+//! The runner uses an offline resolver that only answers from the snapshot, so
+//! that runs never depend on live DNS data. Signature expiration is still
+//! evaluated against the current time.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{
+        config::Config, gateway::GatewayConfig, lru::DnsCache, parse::TxtRecordParser,
+        pipeline::MessageAuthParams, verify::DomainKey,
+    },
+    dkim::DomainKeyReport,
+    dmarc::Dmarc,
+    spf::Spf,
+    AuthenticatedMessage, Resolver, Txt, MX,
+};
+
+const SNAPSHOT_TTL: Duration = Duration::from_secs(10 * 365 * 86400);
+
+/// Verifies messages against a DNS snapshot loaded into the resolver cache.
+pub struct CorpusRunner {
+    resolver: Resolver,
+    gateway: GatewayConfig,
+    hostname: String,
+}
+
+/// Verification outcome of a single message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusOutcome {
+    pub file: String,
+    pub remote_ip: Option<IpAddr>,
+    pub helo: String,
+    pub mail_from: String,
+    pub dkim: Vec<String>,
+    pub arc: String,
+    pub spf_helo: String,
+    pub spf_mail_from: String,
+    pub iprev: String,
+    pub dmarc_spf: String,
+    pub dmarc_dkim: String,
+    pub dmarc_policy: String,
+    pub error: Option<String>,
+}
+
+/// Outcomes of a corpus run, sorted by file name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CorpusReport {
+    pub outcomes: Vec<CorpusOutcome>,
+}
+
+impl CorpusRunner {
+    pub fn new() -> Self {
+        CorpusRunner {
+            resolver: Resolver::new_offline(),
+            gateway: GatewayConfig::new().with_trusted_ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            hostname: "localhost".to_string(),
+        }
+    }
+
+    /// Sets the relays to skip when locating the originating client in the
+    /// Received headers. By default, the client of the topmost hop is used.
+    pub fn with_gateway(mut self, gateway: GatewayConfig) -> Self {
+        self.gateway = gateway.with_trusted_ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        self
+    }
+
+    /// Sets the verification settings of the resolver.
+    pub fn with_config(self, config: Config) -> Self {
+        self.resolver.set_config(config);
+        self
+    }
+
+    pub fn with_hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Loads a DNS snapshot into the resolver cache. Each line contains a
+    /// name, a record type (`TXT`, `A`, `AAAA`, `MX` or `PTR`) and a value,
+    /// for example `example.org. MX 10 mx.example.org.`. TXT values may be
+    /// quoted and empty lines and lines starting with `#` are ignored.
+    pub fn with_dns_snapshot(self, snapshot: &str) -> Result<Self, String> {
+        let valid_until = Instant::now() + SNAPSHOT_TTL;
+        let mut ipv4 = HashMap::new();
+        let mut ipv6 = HashMap::new();
+        let mut mx = HashMap::<String, Vec<MX>>::new();
+        let mut ptr = HashMap::new();
+
+        for (num, line) in snapshot.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (name, rr_type, value) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(rr_type), Some(value)) => (
+                    name.trim_end_matches('.').to_lowercase(),
+                    rr_type,
+                    value.trim(),
+                ),
+                _ => return Err(format!("line {}: expected name, type and value", num + 1)),
+            };
+            let invalid = || format!("line {}: invalid {rr_type} record {value:?}", num + 1);
+
+            match rr_type.to_ascii_uppercase().as_str() {
+                "TXT" => {
+                    let value = value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value)
+                        .as_bytes();
+                    let txt = if name.starts_with("_dmarc.") {
+                        Txt::from(Dmarc::parse(value))
+                    } else if name.starts_with("_report._domainkey.") {
+                        Txt::from(DomainKeyReport::parse(value))
+                    } else if name.contains("._domainkey.") {
+                        Txt::from(DomainKey::parse(value))
+                    } else {
+                        Txt::from(Spf::parse(value))
+                    };
+                    self.resolver
+                        .cache_txt
+                        .insert(format!("{name}."), txt, valid_until);
+                }
+                "A" => ipv4
+                    .entry(name)
+                    .or_insert_with(Vec::new)
+                    .push(value.parse().map_err(|_| invalid())?),
+                "AAAA" => ipv6
+                    .entry(name)
+                    .or_insert_with(Vec::new)
+                    .push(value.parse().map_err(|_| invalid())?),
+                "MX" => {
+                    let (preference, exchange) = value
+                        .split_once(char::is_whitespace)
+                        .and_then(|(preference, exchange)| {
+                            Some((preference.parse::<u16>().ok()?, exchange.trim()))
+                        })
+                        .ok_or_else(invalid)?;
+                    let exchange = exchange.trim_end_matches('.').to_lowercase();
+                    let exchanges = mx.entry(name).or_default();
+                    if let Some(mx) = exchanges.iter_mut().find(|mx| mx.preference == preference) {
+                        mx.exchanges.push(exchange);
+                    } else {
+                        exchanges.push(MX {
+                            exchanges: vec![exchange],
+                            preference,
+                        });
+                    }
+                }
+                "PTR" => ptr
+                    .entry(name.parse::<IpAddr>().map_err(|_| invalid())?)
+                    .or_insert_with(Vec::new)
+                    .push(format!("{}.", value.trim_end_matches('.').to_lowercase())),
+                _ => return Err(format!("line {}: unsupported type {rr_type}", num + 1)),
+            }
+        }
+
+        for (name, addrs) in ipv4 {
+            self.resolver
+                .cache_ipv4
+                .insert(format!("{name}."), Arc::new(addrs), valid_until);
+        }
+        for (name, addrs) in ipv6 {
+            self.resolver
+                .cache_ipv6
+                .insert(format!("{name}."), Arc::new(addrs), valid_until);
+        }
+        for (name, mut exchanges) in mx {
+            exchanges.sort_unstable_by_key(|mx| mx.preference);
+            self.resolver
+                .cache_mx
+                .insert(format!("{name}."), Arc::new(exchanges), valid_until);
+        }
+        for (ip, names) in ptr {
+            self.resolver
+                .cache_ptr
+                .insert(ip, Arc::new(names), valid_until);
+        }
+
+        Ok(self)
+    }
+
+    /// Verifies all `.eml` files in a directory. The SMTP envelope of each
+    /// message is recovered from its Return-Path and Received headers.
+    pub async fn run_dir(&self, path: impl AsRef<Path>) -> io::Result<CorpusReport> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let path = entry?.path();
+            if path.is_file()
+                && path
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+            {
+                files.push(path);
+            }
+        }
+        files.sort();
+
+        let mut outcomes = Vec::with_capacity(files.len());
+        for path in files {
+            let file = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            outcomes.push(self.run_message(file, &fs::read(&path)?).await);
+        }
+
+        Ok(CorpusReport { outcomes })
+    }
+
+    /// Verifies a single raw message.
+    pub async fn run_message(&self, file: impl Into<String>, raw_message: &[u8]) -> CorpusOutcome {
+        let mut outcome = CorpusOutcome {
+            file: file.into(),
+            ..Default::default()
+        };
+        let message = match AuthenticatedMessage::parse(raw_message) {
+            Some(message) => message,
+            None => {
+                outcome.error = Some("Failed to parse message".to_string());
+                return outcome;
+            }
+        };
+
+        let (remote_ip, helo) = match self
+            .gateway
+            .original_client(&message, IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+        {
+            Some(client) if !client.ip().is_unspecified() => {
+                (client.ip(), client.helo().unwrap_or_default().to_string())
+            }
+            _ => {
+                outcome.error = Some("Failed to locate the originating client".to_string());
+                return outcome;
+            }
+        };
+        let mail_from = message
+            .headers
+            .iter()
+            .rev()
+            .find(|(name, _)| name.eq_ignore_ascii_case(b"Return-Path"))
+            .map(|(_, value)| {
+                let value = std::str::from_utf8(value).unwrap_or_default().trim();
+                value
+                    .split_once('<')
+                    .and_then(|(_, addr)| addr.split_once('>'))
+                    .map_or(value, |(addr, _)| addr)
+                    .trim()
+                    .to_string()
+            })
+            .unwrap_or_default();
+
+        let output = self
+            .resolver
+            .verify_message(
+                MessageAuthParams::new(&message, remote_ip)
+                    .with_helo(&helo)
+                    .with_mail_from(&mail_from)
+                    .with_hostname(&self.hostname),
+            )
+            .await;
+
+        outcome.dkim = output
+            .dkim()
+            .iter()
+            .map(|dkim| dkim.result().to_string())
+            .collect();
+        if let Some(arc) = output.arc() {
+            outcome.arc = arc.result().to_string();
+        }
+        if let Some(spf) = output.spf() {
+            outcome.spf_helo = spf.helo().result().to_string();
+            outcome.spf_mail_from = spf.mail_from().result().to_string();
+        }
+        if let Some(iprev) = output.iprev() {
+            outcome.iprev = iprev.result().to_string();
+        }
+        if let Some(dmarc) = output.dmarc() {
+            outcome.dmarc_spf = dmarc.spf_result().to_string();
+            outcome.dmarc_dkim = dmarc.dkim_result().to_string();
+            outcome.dmarc_policy = dmarc.policy().to_string();
+        }
+        outcome.remote_ip = remote_ip.into();
+        outcome.helo = helo;
+        outcome.mail_from = mail_from;
+        outcome
+    }
+}
+
+impl Default for CorpusRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CorpusReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    /// Returns the files whose outcome differs from a baseline run, along with
+    /// their baseline and current outcomes. Files missing from either run are
+    /// returned with `None` on that side.
+    pub fn changes<'x>(
+        &'x self,
+        baseline: &'x CorpusReport,
+    ) -> Vec<(
+        &'x str,
+        Option<&'x CorpusOutcome>,
+        Option<&'x CorpusOutcome>,
+    )> {
+        let current = self
+            .outcomes
+            .iter()
+            .map(|outcome| (outcome.file.as_str(), outcome))
+            .collect::<HashMap<_, _>>();
+        let baseline = baseline
+            .outcomes
+            .iter()
+            .map(|outcome| (outcome.file.as_str(), outcome))
+            .collect::<HashMap<_, _>>();
+
+        let mut changes = baseline
+            .iter()
+            .filter(|(file, outcome)| current.get(*file) != Some(*outcome))
+            .map(|(file, outcome)| (*file, Some(*outcome), current.get(file).copied()))
+            .chain(
+                current
+                    .iter()
+                    .filter(|(file, _)| !baseline.contains_key(*file))
+                    .map(|(file, outcome)| (*file, None, Some(*outcome))),
+            )
+            .collect::<Vec<_>>();
+        changes.sort_unstable_by_key(|(file, _, _)| *file);
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, net::IpAddr};
+
+    use super::{CorpusReport, CorpusRunner};
+
+    const SNAPSHOT: &str = concat!(
+        "# Sender domain\n",
+        "example.org. TXT \"v=spf1 ip4:192.0.2.1 -all\"\n",
+        "_dmarc.example.org. TXT \"v=DMARC1; p=reject\"\n",
+        "example.org. MX 10 mx.example.org.\n",
+        "mx.example.org. A 192.0.2.1\n",
+        "192.0.2.1 PTR mx.example.org.\n",
+    );
+
+    fn message(ip: &str) -> String {
+        format!(
+            concat!(
+                "Return-Path: <jdoe@example.org>\r\n",
+                "Received: from mx.example.org (mx.example.org [{}])\r\n",
+                "\tby mx.example.net; Mon, 1 Jan 2024 00:00:00 +0000\r\n",
+                "From: jdoe@example.org\r\n",
+                "To: jane@example.net\r\n",
+                "Subject: Hello\r\n",
+                "\r\n",
+                "Hi!\r\n"
+            ),
+            ip
+        )
+    }
+
+    #[tokio::test]
+    async fn corpus_runner() {
+        let dir = std::env::temp_dir().join(format!("mail-auth-corpus-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("001.eml"), message("192.0.2.1")).unwrap();
+        fs::write(dir.join("002.eml"), message("192.0.2.2")).unwrap();
+        fs::write(dir.join("003.eml"), "").unwrap();
+        fs::write(dir.join("notes.txt"), message("192.0.2.1")).unwrap();
+
+        assert!(CorpusRunner::new()
+            .with_dns_snapshot("example.org. SRV 0 0 25 mx.example.org.")
+            .is_err());
+        let runner = CorpusRunner::new().with_dns_snapshot(SNAPSHOT).unwrap();
+        let report = runner.run_dir(&dir).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            report
+                .outcomes
+                .iter()
+                .map(|outcome| outcome.file.as_str())
+                .collect::<Vec<_>>(),
+            vec!["001.eml", "002.eml", "003.eml"]
+        );
+        let pass = &report.outcomes[0];
+        assert_eq!(pass.remote_ip, Some("192.0.2.1".parse::<IpAddr>().unwrap()));
+        assert_eq!(pass.helo, "mx.example.org");
+        assert_eq!(pass.mail_from, "jdoe@example.org");
+        assert_eq!(pass.spf_mail_from, "Pass");
+        assert_eq!(pass.iprev, "pass");
+        assert_eq!(pass.dmarc_spf, "pass");
+        assert_eq!(pass.dmarc_policy, "reject");
+        assert!(pass.dkim.is_empty());
+        assert_eq!(pass.error, None);
+        let fail = &report.outcomes[1];
+        assert_eq!(fail.spf_mail_from, "Fail");
+        assert_ne!(fail.dmarc_spf, "pass");
+        assert!(report.outcomes[2].error.is_some());
+
+        // Matrices round-trip through JSON and are compared by file
+        let baseline = CorpusReport::from_json(&report.to_json()).unwrap();
+        assert_eq!(baseline, report);
+        assert!(report.changes(&baseline).is_empty());
+
+        let mut current = baseline.clone();
+        current.outcomes[1].dmarc_spf = "pass".to_string();
+        current.outcomes.remove(2);
+        let changes = current.changes(&baseline);
+        assert_eq!(
+            changes
+                .iter()
+                .map(|(file, baseline, current)| (*file, baseline.is_some(), current.is_some()))
+                .collect::<Vec<_>>(),
+            vec![("002.eml", true, true), ("003.eml", true, false)]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::fmt::Display;
+
+use crate::{report::tlsrpt::ResultType, DaneOutput, DaneResult};
+
+pub mod verify;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Tlsa {
+    pub entries: Vec<TlsaEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TlsaEntry {
+    pub usage: CertUsage,
+    pub selector: Selector,
+    pub matching: Matching,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CertUsage {
+    PkixTa = 0,
+    PkixEe = 1,
+    DaneTa = 2,
+    DaneEe = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selector {
+    Full = 0,
+    Spki = 1,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Matching {
+    Raw = 0,
+    Sha256 = 1,
+    Sha512 = 2,
+}
+
+impl Tlsa {
+    /// Returns the entries usable by SMTP clients, PKIX-TA and PKIX-EE are ignored (RFC 7672).
+    pub fn usable_entries(&self) -> impl Iterator<Item = &TlsaEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| matches!(entry.usage, CertUsage::DaneTa | CertUsage::DaneEe))
+    }
+
+    /// Returns the TLSA records in presentation format, as used in TLS-RPT policy strings.
+    pub fn policy_strings(&self) -> Vec<String> {
+        self.entries.iter().map(|entry| entry.to_string()).collect()
+    }
+}
+
+impl Display for TlsaEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} ",
+            self.usage as u8, self.selector as u8, self.matching as u8
+        )?;
+        for byte in &self.data {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<u8> for CertUsage {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(CertUsage::PkixTa),
+            1 => Ok(CertUsage::PkixEe),
+            2 => Ok(CertUsage::DaneTa),
+            3 => Ok(CertUsage::DaneEe),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<u8> for Selector {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Selector::Full),
+            1 => Ok(Selector::Spki),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<u8> for Matching {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Matching::Raw),
+            1 => Ok(Matching::Sha256),
+            2 => Ok(Matching::Sha512),
+            _ => Err(()),
+        }
+    }
+}
+
+impl DaneOutput {
+    pub fn result(&self) -> DaneResult {
+        self.result
+    }
+
+    /// Returns the TLSA record that matched the presented certificate chain.
+    pub fn matched_entry(&self) -> Option<&TlsaEntry> {
+        self.entry.as_ref()
+    }
+
+    /// Returns the TLS-RPT failure result type, if any.
+    pub fn result_type(&self) -> Option<ResultType> {
+        match self.result {
+            DaneResult::Pass => None,
+            DaneResult::Fail => Some(ResultType::ValidationFailure),
+            DaneResult::NoUsableRecords => Some(ResultType::TlsaInvalid),
+        }
+    }
+}
+
+impl Display for DaneResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DaneResult::Pass => "pass",
+            DaneResult::Fail => "fail",
+            DaneResult::NoUsableRecords => "no-usable-records",
+        })
+    }
+}
@@ -0,0 +1,298 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{common::crypto::HashAlgorithm, DaneOutput, DaneResult, Resolver};
+
+use super::{CertUsage, Matching, Selector, Tlsa, TlsaEntry};
+
+impl Resolver {
+    /// Looks up the DNSSEC-validated TLSA records of an MX host and matches them
+    /// against the certificate chain it presented, leaf certificate first.
+    /// See [`Tlsa::verify`] for what `verify_path` has to check.
+    pub async fn verify_dane<C: AsRef<[u8]>>(
+        &self,
+        mx_host: &str,
+        certificates: &[C],
+        verify_path: impl Fn(&[C]) -> bool,
+    ) -> crate::Result<DaneOutput> {
+        self.tlsa_lookup(format!("_25._tcp.{}.", mx_host.trim_end_matches('.')))
+            .await
+            .map(|tlsa| tlsa.verify(certificates, verify_path))
+    }
+}
+
+impl Tlsa {
+    /// Matches the TLSA records against a DER encoded certificate chain, leaf certificate first.
+    ///
+    /// DANE-EE(3) records are matched against the leaf certificate alone. A DANE-TA(2) record
+    /// matching a certificate further up the chain only passes if `verify_path` returns `true`
+    /// for the certificates from the leaf up to and including the matched trust anchor. The
+    /// caller must verify that each of these certificates is signed by the next one, that
+    /// they are within their validity period and that the leaf certificate names the MX host
+    /// (RFC 7672 3.2). PKIX validation of the TLS layer does not cover this, as it never
+    /// anchors to a DANE trust anchor.
+    pub fn verify<C: AsRef<[u8]>>(
+        &self,
+        certificates: &[C],
+        verify_path: impl Fn(&[C]) -> bool,
+    ) -> DaneOutput {
+        let mut has_usable = false;
+
+        for entry in self.usable_entries() {
+            has_usable = true;
+            let is_match = match entry.usage {
+                CertUsage::DaneEe => certificates
+                    .first()
+                    .is_some_and(|cert| entry.matches(cert.as_ref())),
+                _ => certificates.iter().enumerate().skip(1).any(|(pos, cert)| {
+                    entry.matches(cert.as_ref()) && verify_path(&certificates[..=pos])
+                }),
+            };
+
+            if is_match {
+                return DaneOutput {
+                    result: DaneResult::Pass,
+                    entry: entry.clone().into(),
+                };
+            }
+        }
+
+        DaneOutput {
+            result: if has_usable {
+                DaneResult::Fail
+            } else {
+                DaneResult::NoUsableRecords
+            },
+            entry: None,
+        }
+    }
+}
+
+impl TlsaEntry {
+    /// Returns `true` if the DER encoded certificate matches this record.
+    pub fn matches(&self, certificate: &[u8]) -> bool {
+        let data = match self.selector {
+            Selector::Full => certificate,
+            Selector::Spki => match subject_public_key_info(certificate) {
+                Some(spki) => spki,
+                None => return false,
+            },
+        };
+
+        match self.matching {
+            Matching::Raw => data == self.data,
+            Matching::Sha256 => HashAlgorithm::Sha256.hash(data).as_ref() == self.data,
+            Matching::Sha512 => sha512(data) == self.data,
+        }
+    }
+}
+
+#[cfg(feature = "sha2")]
+fn sha512(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha512::digest(data).to_vec()
+}
+
+#[cfg(all(feature = "ring", not(feature = "sha2")))]
+fn sha512(data: &[u8]) -> Vec<u8> {
+    ring::digest::digest(&ring::digest::SHA512, data)
+        .as_ref()
+        .to_vec()
+}
+
+/// Returns the DER encoded SubjectPublicKeyInfo of an X.509 certificate.
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, certificate, _) = der_next(certificate, 0x30)?;
+    let (_, mut tbs, _) = der_next(certificate, 0x30)?;
+
+    // Optional version
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_next(tbs, 0xa0)?.2;
+    }
+
+    // Serial number, signature algorithm, issuer, validity and subject
+    for tag in [0x02, 0x30, 0x30, 0x30, 0x30] {
+        tbs = der_next(tbs, tag)?.2;
+    }
+
+    der_next(tbs, 0x30).map(|(spki, _, _)| spki)
+}
+
+/// Reads a DER element with the expected tag and returns the whole element,
+/// its contents and the remaining bytes.
+fn der_next(data: &[u8], tag: u8) -> Option<(&[u8], &[u8], &[u8])> {
+    if *data.first()? != tag {
+        return None;
+    }
+
+    let (len, header_len) = match *data.get(1)? {
+        len @ 0..=0x7f => (len as usize, 2),
+        len @ 0x81..=0x84 => {
+            let num_bytes = (len & 0x7f) as usize;
+            let len = data
+                .get(2..2 + num_bytes)?
+                .iter()
+                .fold(0usize, |acc, &byte| (acc << 8) | byte as usize);
+            (len, 2 + num_bytes)
+        }
+        _ => return None,
+    };
+    let end = header_len.checked_add(len)?;
+
+    Some((
+        data.get(..end)?,
+        data.get(header_len..end)?,
+        data.get(end..)?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        dane::{CertUsage, Matching, Selector, Tlsa, TlsaEntry},
+        report::tlsrpt::ResultType,
+        DaneResult, Resolver,
+    };
+
+    fn hex(data: &str) -> Vec<u8> {
+        (0..data.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&data[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn dane_verify() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources");
+        path.push("dane");
+        path.push("chain.pem");
+        let chain = rustls_pemfile::certs(&mut std::fs::read(path).unwrap().as_slice())
+            .map(|cert| cert.unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(chain.len(), 2);
+
+        let leaf_spki_sha256 = TlsaEntry {
+            usage: CertUsage::DaneEe,
+            selector: Selector::Spki,
+            matching: Matching::Sha256,
+            data: hex("277dbaa8e1cbc2e125bc96471f11656a6f93edb2d3a45bd0986a0fd76098af97"),
+        };
+        let leaf_full_sha256 = TlsaEntry {
+            usage: CertUsage::DaneEe,
+            selector: Selector::Full,
+            matching: Matching::Sha256,
+            data: hex("b61fb74b33a31ff579814e908d447160294bfb8529b0d88f777329aae39440c0"),
+        };
+        let ca_full_sha512 = TlsaEntry {
+            usage: CertUsage::DaneTa,
+            selector: Selector::Full,
+            matching: Matching::Sha512,
+            data: hex(concat!(
+                "f43b364116ff9b0670f5d7ef47d7e38486f07e15068ae41e3f755a1badc589769a",
+                "5e24538adec71608d2f8b851ce26e0a3e9d0ef2529b95fda1c0daf1704e277"
+            )),
+        };
+        let ca_raw = TlsaEntry {
+            usage: CertUsage::DaneTa,
+            selector: Selector::Full,
+            matching: Matching::Raw,
+            data: chain[1].clone(),
+        };
+        let pkix = TlsaEntry {
+            usage: CertUsage::PkixEe,
+            ..leaf_spki_sha256.clone()
+        };
+        let mut mismatch = leaf_spki_sha256.clone();
+        mismatch.data[0] ^= 0xff;
+
+        for entry in [
+            &leaf_spki_sha256,
+            &leaf_full_sha256,
+            &ca_full_sha512,
+            &ca_raw,
+        ] {
+            let output = Tlsa {
+                entries: vec![mismatch.clone(), entry.clone()],
+            }
+            .verify(&chain, |path| path.len() == 2);
+            assert_eq!(output.result(), DaneResult::Pass, "{entry}");
+            assert_eq!(output.matched_entry(), Some(entry));
+            assert_eq!(output.result_type(), None);
+        }
+
+        // Trust anchors are not accepted without a verified path from the leaf certificate
+        for entry in [&ca_full_sha512, &ca_raw] {
+            let output = Tlsa {
+                entries: vec![entry.clone()],
+            }
+            .verify(&chain, |_| false);
+            assert_eq!(output.result(), DaneResult::Fail, "{entry}");
+        }
+
+        // Trust anchors never match the leaf certificate
+        let output = Tlsa {
+            entries: vec![TlsaEntry {
+                usage: CertUsage::DaneTa,
+                ..leaf_spki_sha256.clone()
+            }],
+        }
+        .verify(&chain, |_| true);
+        assert_eq!(output.result(), DaneResult::Fail);
+        assert_eq!(output.result_type(), Some(ResultType::ValidationFailure));
+
+        // PKIX usages are not usable for SMTP
+        let output = Tlsa {
+            entries: vec![pkix],
+        }
+        .verify(&chain, |_| true);
+        assert_eq!(output.result(), DaneResult::NoUsableRecords);
+        assert_eq!(output.result_type(), Some(ResultType::TlsaInvalid));
+
+        // Lookup
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.tlsa_add(
+            "_25._tcp.mx.example.org.",
+            Tlsa {
+                entries: vec![leaf_spki_sha256.clone()],
+            },
+            Instant::now() + Duration::new(3200, 0),
+        );
+        assert_eq!(
+            resolver
+                .verify_dane("mx.example.org", &chain, |_| false)
+                .await
+                .unwrap()
+                .result(),
+            DaneResult::Pass
+        );
+        assert_eq!(
+            resolver
+                .verify_dane("mx.example.org", &chain[1..], |_| false)
+                .await
+                .unwrap()
+                .result(),
+            DaneResult::Fail
+        );
+        assert!(resolver
+            .verify_dane("mx.example.net", &chain, |_| false)
+            .await
+            .is_err());
+        assert_eq!(
+            leaf_spki_sha256.to_string(),
+            "3 1 1 277dbaa8e1cbc2e125bc96471f11656a6f93edb2d3a45bd0986a0fd76098af97"
+        );
+    }
+}
@@ -8,9 +8,17 @@
  * except according to those terms.
  */
 
-use crate::common::crypto::{HashAlgorithm, SigningKey};
+use mail_builder::encoders::base64::base64_encode;
 
-use super::{Canonicalization, DkimSigner, Done, NeedDomain, NeedHeaders, NeedSelector, Signature};
+use crate::common::{
+    crypto::{Algorithm, HashAlgorithm, SigningKey},
+    domain::{address_to_ascii, to_ascii},
+};
+
+use super::{
+    Canonicalization, DkimSigner, DomainKeyBuilder, Done, NeedDomain, NeedHeaders, NeedSelector,
+    Signature,
+};
 
 impl<T: SigningKey> DkimSigner<T> {
     pub fn from_key(key: T) -> DkimSigner<T, NeedDomain> {
@@ -22,30 +30,37 @@ impl<T: SigningKey> DkimSigner<T> {
                 ..Default::default()
             },
             key,
+            copy_headers: false,
+            sign_resent: false,
         }
     }
 }
 
 impl<T: SigningKey> DkimSigner<T, NeedDomain> {
-    /// Sets the domain to use for signing.
+    /// Sets the domain to use for signing, a string or a validated [`crate::Domain`].
+    /// Internationalized domains are converted to A-labels (RFC 8616).
     pub fn domain(mut self, domain: impl Into<String>) -> DkimSigner<T, NeedSelector> {
-        self.template.d = domain.into();
+        self.template.d = to_ascii(&domain.into()).into_owned();
         DkimSigner {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            copy_headers: self.copy_headers,
+            sign_resent: self.sign_resent,
         }
     }
 }
 
 impl<T: SigningKey> DkimSigner<T, NeedSelector> {
-    /// Sets the selector to use for signing.
+    /// Sets the selector to use for signing, a string or a validated [`crate::Selector`].
     pub fn selector(mut self, selector: impl Into<String>) -> DkimSigner<T, NeedHeaders> {
         self.template.s = selector.into();
         DkimSigner {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            copy_headers: self.copy_headers,
+            sign_resent: self.sign_resent,
         }
     }
 }
@@ -61,6 +76,8 @@ impl<T: SigningKey> DkimSigner<T, NeedHeaders> {
             _state: Default::default(),
             key: self.key,
             template: self.template,
+            copy_headers: self.copy_headers,
+            sign_resent: self.sign_resent,
         }
     }
 }
@@ -78,9 +95,9 @@ impl<T: SigningKey> DkimSigner<T, Done> {
         self
     }
 
-    /// Sets the selector to use for signing.
+    /// Sets the selector to use for signing, a string or a validated [`crate::Selector`].
     pub fn agent_user_identifier(mut self, auid: impl Into<String>) -> Self {
-        self.template.i = auid.into();
+        self.template.i = address_to_ascii(&auid.into()).into_owned();
         self
     }
 
@@ -113,4 +130,168 @@ impl<T: SigningKey> DkimSigner<T, Done> {
         self.template.cb = cb;
         self
     }
+
+    /// Over-signs headers by listing them once more than they occur in the message,
+    /// preventing additional instances from being added after signing (RFC 6376 section 8.15).
+    pub fn oversign_headers(
+        mut self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        oversign(&mut self.template.h, headers);
+        self
+    }
+
+    /// Includes a copy of the signed headers in the `z=` tag for diagnostic purposes.
+    pub fn copy_headers(mut self, copy_headers: bool) -> Self {
+        self.copy_headers = copy_headers;
+        self
+    }
+
+    /// Also signs the `Resent-*` header fields present in the message, which
+    /// includes the latest resent block. Absent `Resent-*` fields are not listed
+    /// in `h=`, so resending the message later does not break the signature.
+    pub fn resent_headers(mut self, sign_resent: bool) -> Self {
+        self.sign_resent = sign_resent;
+        self
+    }
+}
+
+pub(crate) fn oversign(
+    signed_headers: &mut Vec<String>,
+    headers: impl IntoIterator<Item = impl Into<String>>,
+) {
+    for header in headers {
+        let header = header.into();
+        if !signed_headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(&header))
+        {
+            signed_headers.push(header.clone());
+        }
+        signed_headers.push(header);
+    }
+}
+
+impl DomainKeyBuilder {
+    /// Creates a DKIM public key record for a key of the given algorithm.
+    pub fn new(algorithm: Algorithm, public_key: impl Into<Vec<u8>>) -> Self {
+        DomainKeyBuilder {
+            algorithm,
+            public_key: public_key.into(),
+            hashes: Vec::new(),
+            email_only: false,
+            testing: false,
+            strict: false,
+        }
+    }
+
+    /// Creates a record revoking all keys of the given algorithm.
+    pub fn revoked(algorithm: Algorithm) -> Self {
+        Self::new(algorithm, Vec::new())
+    }
+
+    /// Sets the acceptable hash algorithms (h=).
+    pub fn hash_algorithms(mut self, hashes: impl IntoIterator<Item = HashAlgorithm>) -> Self {
+        self.hashes = hashes.into_iter().collect();
+        self
+    }
+
+    /// Restricts the key to e-mail (s=email).
+    pub fn email_only(mut self, email_only: bool) -> Self {
+        self.email_only = email_only;
+        self
+    }
+
+    /// Flags the domain as testing DKIM (t=y).
+    pub fn testing(mut self, testing: bool) -> Self {
+        self.testing = testing;
+        self
+    }
+
+    /// Disallows subdomains in the i= tag of signatures (t=s).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns the TXT record value.
+    pub fn build(&self) -> String {
+        let mut record = String::with_capacity(self.public_key.len() * 2 + 32);
+        record.push_str("v=DKIM1; k=");
+        record.push_str(match self.algorithm {
+            Algorithm::RsaSha1 | Algorithm::RsaSha256 => "rsa",
+            Algorithm::Ed25519Sha256 => "ed25519",
+        });
+        if !self.hashes.is_empty() {
+            record.push_str("; h=");
+            for (pos, hash) in self.hashes.iter().enumerate() {
+                if pos > 0 {
+                    record.push(':');
+                }
+                record.push_str(match hash {
+                    HashAlgorithm::Sha1 => "sha1",
+                    HashAlgorithm::Sha256 => "sha256",
+                });
+            }
+        }
+        if self.email_only {
+            record.push_str("; s=email");
+        }
+        match (self.testing, self.strict) {
+            (true, true) => record.push_str("; t=y:s"),
+            (true, false) => record.push_str("; t=y"),
+            (false, true) => record.push_str("; t=s"),
+            (false, false) => (),
+        }
+        record.push_str("; p=");
+        record.push_str(
+            &String::from_utf8(base64_encode(&self.public_key).unwrap_or_default())
+                .unwrap_or_default(),
+        );
+        record
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            crypto::{Algorithm, HashAlgorithm},
+            parse::TxtRecordParser,
+            verify::DomainKey,
+        },
+        dkim::{DomainKeyBuilder, R_FLAG_MATCH_DOMAIN, R_FLAG_TESTING, R_SVC_EMAIL},
+    };
+
+    #[test]
+    fn domain_key_builder() {
+        let public_key = [
+            0xd7, 0x5a, 0x98, 0x01, 0x82, 0xb1, 0x0a, 0xb7, 0xd5, 0x4b, 0xfe, 0xd3, 0xc9, 0x64,
+            0x07, 0x3a, 0x0e, 0xe1, 0x72, 0xf3, 0xda, 0xa6, 0x23, 0x25, 0xaf, 0x02, 0x1a, 0x68,
+            0xf7, 0x07, 0x51, 0x1a,
+        ];
+        let record = DomainKeyBuilder::new(Algorithm::Ed25519Sha256, public_key)
+            .hash_algorithms([HashAlgorithm::Sha256])
+            .email_only(true)
+            .testing(true)
+            .strict(true)
+            .build();
+        assert_eq!(
+            record,
+            concat!(
+                "v=DKIM1; k=ed25519; h=sha256; s=email; t=y:s; ",
+                "p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+            )
+        );
+        let domain_key = DomainKey::parse(record.as_bytes()).unwrap();
+        assert_eq!(
+            domain_key.f & (R_SVC_EMAIL | R_FLAG_TESTING | R_FLAG_MATCH_DOMAIN),
+            R_SVC_EMAIL | R_FLAG_TESTING | R_FLAG_MATCH_DOMAIN
+        );
+
+        assert_eq!(
+            DomainKeyBuilder::revoked(Algorithm::RsaSha256).build(),
+            "v=DKIM1; k=rsa; p="
+        );
+    }
 }
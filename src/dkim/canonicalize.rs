@@ -19,69 +19,89 @@ pub struct CanonicalBody<'a> {
 
 impl Writable for CanonicalBody<'_> {
     fn write(self, hasher: &mut impl Writer) {
-        let mut crlf_seq = 0;
+        let mut canonicalizer = BodyCanonicalizer::new(self.canonicalization);
+        canonicalizer.write(self.body, hasher);
+        canonicalizer.finish(hasher);
+    }
+}
+
+/// Incremental body canonicalization, allowing a body to be hashed in chunks.
+pub(crate) struct BodyCanonicalizer {
+    canonicalization: Canonicalization,
+    crlf_seq: usize,
+    last_ch: u8,
+    is_empty: bool,
+}
+
+impl BodyCanonicalizer {
+    pub(crate) fn new(canonicalization: Canonicalization) -> Self {
+        BodyCanonicalizer {
+            canonicalization,
+            crlf_seq: 0,
+            last_ch: 0,
+            is_empty: true,
+        }
+    }
 
+    pub(crate) fn write(&mut self, body: &[u8], hasher: &mut impl Writer) {
         match self.canonicalization {
             Canonicalization::Relaxed => {
-                let mut last_ch = 0;
-                let mut is_empty = true;
-
-                for &ch in self.body {
+                for &ch in body {
                     match ch {
                         b' ' | b'\t' => {
-                            while crlf_seq > 0 {
+                            while self.crlf_seq > 0 {
                                 hasher.write(b"\r\n");
-                                crlf_seq -= 1;
+                                self.crlf_seq -= 1;
                             }
-                            is_empty = false;
+                            self.is_empty = false;
                         }
                         b'\n' => {
-                            crlf_seq += 1;
+                            self.crlf_seq += 1;
                         }
                         b'\r' => {}
                         _ => {
-                            while crlf_seq > 0 {
+                            while self.crlf_seq > 0 {
                                 hasher.write(b"\r\n");
-                                crlf_seq -= 1;
+                                self.crlf_seq -= 1;
                             }
 
-                            if last_ch == b' ' || last_ch == b'\t' {
+                            if self.last_ch == b' ' || self.last_ch == b'\t' {
                                 hasher.write(b" ");
                             }
 
                             hasher.write(&[ch]);
-                            is_empty = false;
+                            self.is_empty = false;
                         }
                     }
 
-                    last_ch = ch;
-                }
-
-                if !is_empty {
-                    hasher.write(b"\r\n");
+                    self.last_ch = ch;
                 }
             }
             Canonicalization::Simple => {
-                for &ch in self.body {
+                for &ch in body {
                     match ch {
                         b'\n' => {
-                            crlf_seq += 1;
+                            self.crlf_seq += 1;
                         }
                         b'\r' => {}
                         _ => {
-                            while crlf_seq > 0 {
+                            while self.crlf_seq > 0 {
                                 hasher.write(b"\r\n");
-                                crlf_seq -= 1;
+                                self.crlf_seq -= 1;
                             }
                             hasher.write(&[ch]);
                         }
                     }
                 }
-
-                hasher.write(b"\r\n");
             }
         }
     }
+
+    pub(crate) fn finish(self, hasher: &mut impl Writer) {
+        if self.canonicalization == Canonicalization::Simple || !self.is_empty {
+            hasher.write(b"\r\n");
+        }
+    }
 }
 
 impl Canonicalization {
@@ -161,6 +181,7 @@ impl Signature {
     pub fn canonicalize<'x>(
         &self,
         mut message: impl HeaderStream<'x>,
+        sign_resent: bool,
     ) -> (usize, CanonicalHeaders<'x>, Vec<String>, CanonicalBody<'x>) {
         let mut headers = Vec::with_capacity(self.h.len());
         let mut found_headers = vec![false; self.h.len()];
@@ -175,6 +196,9 @@ impl Signature {
                 headers.push((name, value));
                 found_headers[pos] = true;
                 signed_headers.push(std::str::from_utf8(name).unwrap().into());
+            } else if sign_resent && is_resent_header(name) {
+                headers.push((name, value));
+                signed_headers.push(String::from_utf8_lossy(name).into_owned());
             }
         }
 
@@ -185,21 +209,62 @@ impl Signature {
 
         // Add any missing headers
         signed_headers.reverse();
-        for (header, found) in self.h.iter().zip(found_headers) {
-            if !found {
-                signed_headers.push(header.to_string());
-            }
-        }
+        add_missing_headers(&mut signed_headers, &self.h, found_headers);
 
         (body_len, canonical_headers, signed_headers, canonical_body)
     }
 }
 
+/// Returns `true` for the header fields of a resent block (RFC 5322 section 3.6.6).
+pub(crate) fn is_resent_header(name: &[u8]) -> bool {
+    name.len() > 7 && name[..7].eq_ignore_ascii_case(b"Resent-")
+}
+
+pub(crate) fn add_missing_headers(
+    signed_headers: &mut Vec<String>,
+    headers: &[String],
+    found_headers: Vec<bool>,
+) {
+    let num_found = signed_headers.len();
+    for (header, found) in headers.iter().zip(found_headers) {
+        if !found
+            && !signed_headers[num_found..]
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(header))
+        {
+            signed_headers.push(header.to_string());
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct CanonicalHeaders<'a> {
     canonicalization: Canonicalization,
     headers: Vec<(&'a [u8], &'a [u8])>,
 }
 
+impl<'a> CanonicalHeaders<'a> {
+    /// Returns the signed headers in `z=` tag format.
+    pub(crate) fn copied_headers(&self) -> Vec<String> {
+        self.headers
+            .iter()
+            .rev()
+            .map(|(name, value)| {
+                let value = value
+                    .iter()
+                    .filter(|&&ch| ch != b'\r' && ch != b'\n')
+                    .copied()
+                    .collect::<Vec<_>>();
+                format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(name).trim(),
+                    String::from_utf8_lossy(&value).trim()
+                )
+            })
+            .collect()
+    }
+}
+
 impl<'a> Writable for CanonicalHeaders<'a> {
     fn write(self, writer: &mut impl Writer) {
         self.canonicalization
@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use super::{BodyNormalization, Canonicalization, CompatShim, HashAlgorithm, Signature};
+
+impl CompatShim {
+    /// Returns the body hash produced by a signer affected by this bug, if the
+    /// shim applies to the signature and body.
+    pub(crate) fn body_hash(&self, signature: &Signature, body: &[u8]) -> Option<Vec<u8>> {
+        let ha = HashAlgorithm::from(signature.a);
+        match (self, signature.cb) {
+            (CompatShim::SimpleBodyTrailingWhitespace, Canonicalization::Simple) => {
+                strip_trailing_whitespace(body).map(|body| {
+                    ha.hash(Canonicalization::Simple.canonical_body(&body, signature.l))
+                        .as_ref()
+                        .to_vec()
+                })
+            }
+            (CompatShim::EmptyBodyCrlf, Canonicalization::Relaxed)
+                if body.iter().all(|ch| ch.is_ascii_whitespace()) =>
+            {
+                Some(ha.hash(&b"\r\n"[..]).as_ref().to_vec())
+            }
+            (CompatShim::EmptyBodyCrlf, Canonicalization::Simple)
+                if body.iter().all(|ch| matches!(ch, b'\r' | b'\n')) =>
+            {
+                Some(ha.hash(&b""[..]).as_ref().to_vec())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl BodyNormalization {
+    /// Returns the body hash of the message after applying this normalization,
+    /// if it changes the body.
+    pub(crate) fn body_hash(&self, signature: &Signature, body: &[u8]) -> Option<Vec<u8>> {
+        let ha = HashAlgorithm::from(signature.a);
+        let (cb, body) = match self {
+            BodyNormalization::DotStuffing => (signature.cb, remove_dot_stuffing(body)?),
+            BodyNormalization::TrailingWhitespace => {
+                (signature.cb, strip_trailing_whitespace(body)?)
+            }
+            BodyNormalization::Canonicalization => (
+                match signature.cb {
+                    Canonicalization::Relaxed => Canonicalization::Simple,
+                    Canonicalization::Simple => Canonicalization::Relaxed,
+                },
+                body.to_vec(),
+            ),
+        };
+        Some(
+            ha.hash(cb.canonical_body(&body, signature.l))
+                .as_ref()
+                .to_vec(),
+        )
+    }
+}
+
+fn strip_trailing_whitespace(body: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = Vec::with_capacity(body.len());
+    for line in body.split_inclusive(|&ch| ch == b'\n') {
+        match line.strip_suffix(b"\r\n") {
+            Some(line) => {
+                stripped.extend_from_slice(line.trim_ascii_end());
+                stripped.extend_from_slice(b"\r\n");
+            }
+            None => stripped.extend_from_slice(line),
+        }
+    }
+    (stripped.len() != body.len()).then_some(stripped)
+}
+
+pub(crate) fn has_dot_stuffing(body: &[u8]) -> bool {
+    body.split(|&ch| ch == b'\n')
+        .any(|line| line.starts_with(b".."))
+}
+
+pub(crate) fn remove_dot_stuffing(body: &[u8]) -> Option<Vec<u8>> {
+    let mut unstuffed = Vec::with_capacity(body.len());
+    for line in body.split_inclusive(|&ch| ch == b'\n') {
+        unstuffed.extend_from_slice(
+            line.strip_prefix(b".")
+                .filter(|line| line.starts_with(b"."))
+                .unwrap_or(line),
+        );
+    }
+    (unstuffed.len() != body.len()).then_some(unstuffed)
+}
+
+pub(crate) fn has_mbox_quoting(body: &[u8]) -> bool {
+    body.split(|&ch| ch == b'\n').any(is_mbox_quoted)
+}
+
+/// Removes one level of mboxrd quoting, which also undoes mboxo quoting.
+pub(crate) fn remove_mbox_quoting(body: &[u8]) -> Option<Vec<u8>> {
+    let mut unquoted = Vec::with_capacity(body.len());
+    for line in body.split_inclusive(|&ch| ch == b'\n') {
+        unquoted.extend_from_slice(if is_mbox_quoted(line) {
+            &line[1..]
+        } else {
+            line
+        });
+    }
+    (unquoted.len() != body.len()).then_some(unquoted)
+}
+
+fn is_mbox_quoted(line: &[u8]) -> bool {
+    line.starts_with(b">")
+        && line
+            .iter()
+            .position(|&ch| ch != b'>')
+            .is_some_and(|pos| line[pos..].starts_with(b"From "))
+}
+
+#[cfg(test)]
+mod test {
+    use mail_builder::encoders::base64::base64_encode;
+
+    use crate::{
+        common::{
+            config::Config,
+            crypto::{Ed25519Key, SigningKey},
+            headers::HeaderWriter,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::{
+            verify::Verifier, BodyNormalization, Canonicalization, CompatShim, DkimSigner, Done,
+        },
+        AuthenticatedMessage, DkimResult, Error,
+    };
+
+    #[tokio::test]
+    async fn dkim_compat_shims() {
+        let signer = signer();
+        let resolver = ed25519_resolver("ed._domainkey.example.org.");
+
+        // Signer hashing relaxed headers while declaring c=simple
+        let received = "From: jdoe@example.org\r\nSubject:  Folded\r\n  subject\r\n\r\nHi!\r\n";
+        let mut signature = signer.sign(received.as_bytes()).unwrap();
+        let message = signature.to_header() + received;
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let header = &message.dkim_headers[0];
+        let mut data = Vec::new();
+        Canonicalization::Relaxed.canonicalize_headers(
+            message.signed_headers(&signature.h, header.name, &header.value.strip_signature()),
+            &mut data,
+        );
+        signature.b = base64_encode(&signer.key.sign(data.as_slice()).unwrap()).unwrap();
+        let relaxed_message = signature.to_header() + received;
+
+        // Signer stripping trailing whitespace before hashing a c=simple body
+        let signed = "From: jdoe@example.org\r\nSubject: Trailing\r\n\r\nHi!\r\nBye!\r\n";
+        let received = "From: jdoe@example.org\r\nSubject: Trailing\r\n\r\nHi! \t\r\nBye!  \r\n";
+        let trailing_message = signer.sign(signed.as_bytes()).unwrap().to_header() + received;
+
+        for (message, shim) in [
+            (relaxed_message, CompatShim::RelaxedHeaders),
+            (trailing_message, CompatShim::SimpleBodyTrailingWhitespace),
+        ] {
+            let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+
+            // Strict verification fails
+            resolver.set_config(Config::new());
+            let dkim = resolver.verify_dkim(&message).await;
+            assert_ne!(dkim[0].result(), &DkimResult::Pass);
+
+            // Shims for other bugs do not apply
+            resolver.set_config(Config::new().with_compat_shim(CompatShim::EmptyBodyCrlf));
+            let dkim = resolver.verify_dkim(&message).await;
+            assert_ne!(dkim[0].result(), &DkimResult::Pass);
+
+            // The matching shim recovers the signature and is reported
+            resolver.set_config(Config::new().with_compat_shim(shim));
+            let dkim = resolver.verify_dkim(&message).await;
+            assert_eq!(dkim[0].result(), &DkimResult::Pass);
+            assert_eq!(dkim[0].compat_shims(), &[shim]);
+        }
+
+        // Shims are not reported for signatures that pass strict verification
+        let message = "From: jdoe@example.org\r\nSubject: Hi\r\n\r\nHi!\r\n";
+        let message = signer.sign(message.as_bytes()).unwrap().to_header() + message;
+        let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+        let dkim = resolver.verify_dkim(&message).await;
+        assert_eq!(dkim[0].result(), &DkimResult::Pass);
+        assert!(dkim[0].compat_shims().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dkim_body_hash_diagnostics() {
+        let signer = signer();
+        let resolver = ed25519_resolver("ed._domainkey.example.org.");
+        let headers = "From: jdoe@example.org\r\nSubject: Diagnostics\r\n\r\n";
+
+        for (signed, received, expected) in [
+            (
+                "Hi!\r\n.\r\n.Bye!\r\n",
+                "Hi!\r\n..\r\n..Bye!\r\n",
+                Some(BodyNormalization::DotStuffing),
+            ),
+            (
+                "Hi!\r\nBye!\r\n",
+                "Hi! \r\nBye!\t\r\n",
+                Some(BodyNormalization::TrailingWhitespace),
+            ),
+            (
+                "Hi there!\r\n",
+                "Hi  there!\r\n",
+                Some(BodyNormalization::Canonicalization),
+            ),
+            ("Hi!\r\nBye!\r\n", "Hi!\r\nBuy!\r\n", None),
+        ] {
+            let signature = signer
+                .sign(format!("{headers}{signed}").as_bytes())
+                .unwrap();
+            let message = format!("{}{headers}{received}", signature.to_header());
+            let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+
+            for enable in [false, true] {
+                resolver.set_config(Config::new().with_body_hash_diagnostics(enable));
+                let dkim = resolver.verify_dkim(&message).await;
+                assert_eq!(
+                    dkim[0].result(),
+                    &DkimResult::Neutral(Error::FailedBodyHashMatch)
+                );
+                assert_eq!(
+                    dkim[0].body_normalization(),
+                    if enable { expected } else { None },
+                    "{received:?}"
+                );
+            }
+        }
+    }
+
+    fn signer() -> DkimSigner<Ed25519Key, Done> {
+        DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.org")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .header_canonicalization(Canonicalization::Simple)
+            .body_canonicalization(Canonicalization::Simple)
+    }
+}
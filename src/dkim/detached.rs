@@ -0,0 +1,156 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{common::crypto::SigningKey, AuthenticatedMessage, DkimResult, Error, Resolver};
+
+use super::{DkimSigner, Done, Signature};
+
+/// Header-like key-value fields and a body that are signed and verified
+/// using DKIM canonicalization without being an RFC 5322 message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetachedMessage {
+    raw: Vec<u8>,
+}
+
+impl DetachedMessage {
+    /// Creates a detached message from a list of fields and a body.
+    pub fn new<'x>(
+        fields: impl IntoIterator<Item = (&'x str, &'x str)>,
+        body: &[u8],
+    ) -> crate::Result<Self> {
+        let mut raw = Vec::with_capacity(body.len() + 256);
+        for (name, value) in fields {
+            if name.is_empty()
+                || !name.bytes().all(|ch| ch.is_ascii_graphic() && ch != b':')
+                || name.eq_ignore_ascii_case("DKIM-Signature")
+                || value.bytes().any(|ch| ch == b'\r' || ch == b'\n')
+            {
+                return Err(Error::ParseError);
+            }
+            raw.extend_from_slice(name.as_bytes());
+            raw.extend_from_slice(b": ");
+            raw.extend_from_slice(value.as_bytes());
+            raw.extend_from_slice(b"\r\n");
+        }
+        if raw.is_empty() {
+            return Err(Error::NoHeadersFound);
+        }
+        raw.extend_from_slice(b"\r\n");
+        raw.extend_from_slice(body);
+
+        Ok(DetachedMessage { raw })
+    }
+
+    /// Returns the RFC 5322-like serialization that is signed.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+}
+
+impl<T: SigningKey> DkimSigner<T, Done> {
+    /// Signs a detached message.
+    pub fn sign_detached(&self, message: &DetachedMessage) -> crate::Result<Signature> {
+        self.sign(&message.raw)
+    }
+}
+
+impl Resolver {
+    /// Verifies a DKIM signature over a detached message, where `signature` is the
+    /// DKIM-Signature header value as produced by [`Signature::to_header`](crate::common::headers::HeaderWriter::to_header).
+    pub async fn verify_detached(&self, message: &DetachedMessage, signature: &str) -> DkimResult {
+        let signature = signature.trim_end();
+        let mut raw = Vec::with_capacity(message.raw.len() + signature.len() + 20);
+        raw.extend_from_slice(b"DKIM-Signature:");
+        raw.extend_from_slice(signature.as_bytes());
+        raw.extend_from_slice(b"\r\n");
+        raw.extend_from_slice(&message.raw);
+
+        match AuthenticatedMessage::parse(&raw) {
+            Some(message) => self
+                .verify_dkim(&message)
+                .await
+                .into_iter()
+                .next()
+                .map_or(DkimResult::None, |output| output.result),
+            None => DkimResult::PermError(Error::ParseError),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            headers::HeaderWriter,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::DkimSigner,
+        DkimResult, Error,
+    };
+
+    use super::DetachedMessage;
+
+    #[tokio::test]
+    async fn detached_sign_verify() {
+        let pk_ed = ed25519_key().unwrap();
+        let resolver = ed25519_resolver("ed._domainkey.example.com.");
+
+        let fields = [
+            ("X-Webhook-Id", "1234"),
+            ("X-Webhook-Event", "order.created"),
+        ];
+        let message = DetachedMessage::new(fields, b"{\"order\": 1}").unwrap();
+        let signature = DkimSigner::from_key(pk_ed)
+            .domain("example.com")
+            .selector("ed")
+            .headers(["X-Webhook-Id", "X-Webhook-Event"])
+            .sign_detached(&message)
+            .unwrap();
+
+        // Transport the signature as a header value
+        let header = signature.to_header();
+        let signature = header.split_once(':').unwrap().1;
+        assert_eq!(
+            resolver.verify_detached(&message, signature).await,
+            DkimResult::Pass
+        );
+
+        // Tampered field
+        let tampered = DetachedMessage::new(
+            [
+                ("X-Webhook-Id", "1235"),
+                ("X-Webhook-Event", "order.created"),
+            ],
+            b"{\"order\": 1}",
+        )
+        .unwrap();
+        assert!(matches!(
+            resolver.verify_detached(&tampered, signature).await,
+            DkimResult::Fail(_)
+        ));
+
+        // Tampered body
+        let tampered = DetachedMessage::new(fields, b"{\"order\": 2}").unwrap();
+        assert_eq!(
+            resolver.verify_detached(&tampered, signature).await,
+            DkimResult::Neutral(Error::FailedBodyHashMatch)
+        );
+
+        // Invalid fields
+        assert_eq!(
+            DetachedMessage::new([("X-Id", "1\r\nBcc: a@b")], b""),
+            Err(Error::ParseError)
+        );
+        assert_eq!(
+            DetachedMessage::new([], b"body"),
+            Err(Error::NoHeadersFound)
+        );
+    }
+}
@@ -14,7 +14,10 @@ use rsa::{
     RsaPrivateKey, RsaPublicKey,
 };
 
-use crate::{common::crypto::Ed25519Key, Error};
+use crate::{
+    common::crypto::{Ed25519Key, HashImpl, RsaKey},
+    Error,
+};
 
 pub struct DkimKeyPair {
     private_key: Vec<u8>,
@@ -72,6 +75,40 @@ impl DkimKeyPair {
     }
 }
 
+impl<T: HashImpl> RsaKey<T> {
+    /// Generates a new RSA private key with the given number of bits
+    pub fn generate(bits: usize) -> crate::Result<Self> {
+        let key_pair = DkimKeyPair::generate_rsa(bits)?;
+
+        #[cfg(feature = "rust-crypto")]
+        {
+            Self::from_pkcs1_der(key_pair.private_key())
+        }
+
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        {
+            Self::from_der(key_pair.private_key())
+        }
+    }
+}
+
+impl Ed25519Key {
+    /// Generates a new Ed25519 private key
+    pub fn generate() -> crate::Result<Self> {
+        #[cfg(feature = "rust-crypto")]
+        {
+            let mut seed = [0u8; 32];
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut seed);
+            Self::from_bytes(&seed)
+        }
+
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        {
+            Self::from_pkcs8_der(&Self::generate_pkcs8()?)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::dkim::sign::test::verify;
@@ -79,14 +116,68 @@ mod test {
 
     use crate::{
         common::{
-            crypto::{Ed25519Key, RsaKey, Sha256},
+            crypto::{Ed25519Key, HashAlgorithm, RsaKey, Sha256, SigningKey},
             parse::TxtRecordParser,
             verify::DomainKey,
         },
-        dkim::{generate::DkimKeyPair, DkimSigner, DomainKeyReport},
+        dkim::{generate::DkimKeyPair, DkimSigner, DomainKeyBuilder, DomainKeyReport},
         Resolver,
     };
 
+    #[tokio::test]
+    async fn dkim_generate_keys() {
+        let rsa_key = RsaKey::<Sha256>::generate(2048).unwrap();
+        let ed_key = Ed25519Key::generate().unwrap();
+
+        let resolver = Resolver::new_system_conf().unwrap();
+        for (selector, record) in [
+            (
+                "rsa",
+                DomainKeyBuilder::new(rsa_key.algorithm(), rsa_key.public_key())
+                    .hash_algorithms([HashAlgorithm::Sha256])
+                    .build(),
+            ),
+            (
+                "ed",
+                DomainKeyBuilder::new(ed_key.algorithm(), ed_key.public_key())
+                    .email_only(true)
+                    .build(),
+            ),
+        ] {
+            resolver.txt_add(
+                format!("{selector}._domainkey.example.com."),
+                DomainKey::parse(record.as_bytes()).unwrap(),
+                Instant::now() + Duration::new(3600, 0),
+            );
+        }
+
+        let message = "From: bill@example.com\r\nSubject: TPS Report\r\n\r\nHi!\r\n";
+        verify(
+            &resolver,
+            DkimSigner::from_key(rsa_key)
+                .domain("example.com")
+                .selector("rsa")
+                .headers(["From", "Subject"])
+                .sign(message.as_bytes())
+                .unwrap(),
+            message,
+            Ok(()),
+        )
+        .await;
+        verify(
+            &resolver,
+            DkimSigner::from_key(ed_key)
+                .domain("example.com")
+                .selector("ed")
+                .headers(["From", "Subject"])
+                .sign(message.as_bytes())
+                .unwrap(),
+            message,
+            Ok(()),
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn dkim_generate_verify() {
         let rsa_pkcs = DkimKeyPair::generate_rsa(2048).unwrap();
@@ -77,13 +77,15 @@ impl Signature {
             }
             writer.write_len(b"i=", &mut bw);
 
-            for &ch in self.i.as_bytes().iter() {
+            // UTF-8 is written as is (RFC 8616 section 4)
+            let mut buf = [0u8; 4];
+            for ch in self.i.chars() {
                 match ch {
-                    0..=0x20 | b';' | 0x7f..=u8::MAX => {
-                        writer.write_len(format!("={ch:02X}").as_bytes(), &mut bw);
+                    '\0'..=' ' | ';' | '\x7f' => {
+                        writer.write_len(format!("={:02X}", ch as u8).as_bytes(), &mut bw);
                     }
                     _ => {
-                        writer.write_len(&[ch], &mut bw);
+                        writer.write_len(ch.encode_utf8(&mut buf).as_bytes(), &mut bw);
                     }
                 }
                 if bw >= 76 {
@@ -113,6 +115,8 @@ impl Signature {
             }
         }
 
+        write_copied_headers(&self.z, writer, new_line, &mut bw);
+
         for (tag, value) in [(&b"; bh="[..], &self.bh), (&b"; b="[..], &self.b)] {
             writer.write_len(tag, &mut bw);
             for &byte in value {
@@ -131,6 +135,36 @@ impl Signature {
     }
 }
 
+pub(crate) fn write_copied_headers(
+    headers: &[String],
+    writer: &mut impl Writer,
+    new_line: &[u8],
+    bw: &mut usize,
+) {
+    for (num, header) in headers.iter().enumerate() {
+        if num > 0 {
+            writer.write_len(b"|", bw);
+        } else {
+            writer.write_len(b"; z=", bw);
+        }
+
+        for &ch in header.as_bytes() {
+            match ch {
+                0..=0x20 | b';' | b'|' | b'=' | 0x7f..=u8::MAX => {
+                    writer.write_len(format!("={ch:02X}").as_bytes(), bw);
+                }
+                _ => {
+                    writer.write_len(&[ch], bw);
+                }
+            }
+            if *bw >= 76 {
+                writer.write(new_line);
+                *bw = 1;
+            }
+        }
+    }
+}
+
 impl HeaderWriter for Signature {
     fn write_header(&self, writer: &mut impl Writer) {
         self.write(writer, true);
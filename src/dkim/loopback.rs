@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{
+    common::{
+        crypto::{Algorithm, HashAlgorithm, SigningKey, VerifyingKeyType},
+        headers::HeaderWriter,
+        verify::DomainKey,
+    },
+    AuthenticatedMessage, Error,
+};
+
+use super::{verify::Verifier, DkimSigner, Done, Signature};
+
+impl<T: SigningKey> DkimSigner<T, Done> {
+    /// Signs a message and verifies the new signature against the public key
+    /// of the signer, without DNS lookups.
+    pub fn sign_verified(&self, message: &[u8], public_key: &[u8]) -> crate::Result<Signature> {
+        let signature = self.sign(message)?;
+        signature.verify_loopback(message, public_key)?;
+        Ok(signature)
+    }
+}
+
+impl Signature {
+    /// Verifies this signature over the message it was created for, using the
+    /// public key of the signer instead of the DNS record. Signing services can
+    /// use it to catch canonicalization bugs or mismatched keys before sending.
+    pub fn verify_loopback(&self, message: &[u8], public_key: &[u8]) -> crate::Result<()> {
+        let header = self.to_header();
+        let mut signed = Vec::with_capacity(header.len() + message.len());
+        signed.extend_from_slice(header.as_bytes());
+        signed.extend_from_slice(message);
+
+        // Messages signed with l= are rejected by the strict parser
+        let message =
+            AuthenticatedMessage::parse_with_opts(&signed, false).ok_or(Error::ParseError)?;
+        let header = message.dkim_headers.first().ok_or(Error::NoHeadersFound)?;
+        let signature = header.header.as_ref().map_err(|err| err.clone())?;
+
+        // Validate body hash
+        let ha = HashAlgorithm::from(signature.a);
+        if !message.body_hashes.iter().any(|(c, h, l, bh)| {
+            c == &signature.cb && h == &ha && l == &signature.l && bh == &signature.bh
+        }) {
+            return Err(Error::FailedBodyHashMatch);
+        }
+
+        // Verify signature
+        let record = DomainKey {
+            p: match signature.a {
+                Algorithm::Ed25519Sha256 => VerifyingKeyType::Ed25519,
+                Algorithm::RsaSha1 | Algorithm::RsaSha256 => VerifyingKeyType::Rsa,
+            }
+            .verifying_key(public_key)?,
+            f: 0,
+        };
+        let dkim_hdr_value = header.value.strip_signature();
+        record.verify(
+            &mut message.signed_headers(&signature.h, header.name, &dkim_hdr_value),
+            signature,
+            signature.ch,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::test_key::{ed25519_key, ed25519_public_key},
+        dkim::DkimSigner,
+        Error,
+    };
+
+    #[test]
+    fn dkim_verify_loopback() {
+        let message = concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP. ",
+            "So, if you could do that, that'd be great.\r\n"
+        );
+        let public_key = ed25519_public_key();
+        let key = || ed25519_key().unwrap();
+
+        for body_length in [false, true] {
+            let signature = DkimSigner::from_key(key())
+                .domain("example.com")
+                .selector("ed")
+                .headers(["From", "To", "Subject"])
+                .body_length(body_length)
+                .sign_verified(message.as_bytes(), &public_key)
+                .unwrap();
+
+            // Wrong key
+            let mut wrong_key = public_key.clone();
+            wrong_key[0] ^= 0xff;
+            assert!(signature
+                .verify_loopback(message.as_bytes(), &wrong_key)
+                .is_err());
+
+            // Message changed after signing
+            assert_eq!(
+                signature.verify_loopback(message.replace("ASAP", "today").as_bytes(), &public_key),
+                Err(Error::FailedBodyHashMatch)
+            );
+            assert!(signature
+                .verify_loopback(
+                    message.replace("TPS Report", "Reports").as_bytes(),
+                    &public_key
+                )
+                .is_err());
+        }
+    }
+}
@@ -19,25 +19,119 @@ use crate::{
 
 pub mod builder;
 pub mod canonicalize;
+pub mod compat;
+pub mod detached;
 #[cfg(feature = "generate")]
 pub mod generate;
 pub mod headers;
+pub mod loopback;
 pub mod parse;
+pub mod preview;
+pub mod profile;
+pub mod propagation;
 pub mod sign;
+pub mod submission;
 pub mod verify;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Canonicalization {
     #[default]
     Relaxed,
     Simple,
 }
 
+/// Opt-in workarounds for signers that deviate from RFC 6376. Shims are only
+/// tried after a signature fails standard verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CompatShim {
+    /// Verifies `c=simple` headers using relaxed canonicalization, for signers
+    /// that unfold and normalize header whitespace despite declaring `simple`.
+    RelaxedHeaders,
+    /// Ignores trailing whitespace on body lines when computing `c=simple`
+    /// body hashes, as done by signers that strip it before hashing.
+    SimpleBodyTrailingWhitespace,
+    /// Accepts empty bodies hashed as a single CRLF with `c=relaxed`, or as
+    /// no data with `c=simple`.
+    EmptyBodyCrlf,
+}
+
+/// Message normalizations tried after a body hash mismatch, to help tell
+/// transport mangling apart from forgery. Bare CR and LF line endings need no
+/// normalization as body canonicalization already treats them as CRLF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BodyNormalization {
+    /// SMTP dot-stuffing left in place on lines starting with a dot.
+    DotStuffing,
+    /// Trailing whitespace removed from body lines.
+    TrailingWhitespace,
+    /// Body hashed with the other canonicalization algorithm.
+    Canonicalization,
+}
+
+/// Verification policy rule that was applied to a signature, see
+/// [`crate::common::config::VerificationPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PolicyDecision {
+    /// RSA-SHA1 signatures are not accepted by the crypto policy.
+    RsaSha1Rejected,
+    /// The RSA key is shorter than the minimum size, in bits.
+    RsaKeyTooShort(usize),
+    /// Signatures with an `l=` tag are not accepted.
+    BodyLengthRejected,
+    /// The body has content beyond the `l=` length.
+    BodyLengthExceeded,
+    /// The `t=` timestamp is older than the maximum signature age.
+    SignatureTooOld,
+    /// The signature expired but is within the expiration grace period.
+    ExpirationGrace,
+}
+
+/// DKIM message signer, which can be shared across threads as described in
+/// [Sharing signers and sealers](crate#sharing-signers-and-sealers).
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct DkimSigner<T: SigningKey, State = NeedDomain> {
     _state: std::marker::PhantomData<State>,
     pub key: T,
     pub template: Signature,
+    pub(crate) copy_headers: bool,
+    pub(crate) sign_resent: bool,
+}
+
+/// Signs a message with several DKIM signers in a single pass, for example to
+/// dual-sign with an RSA and an Ed25519 key. Headers are parsed once and each
+/// distinct body hash is computed once.
+#[derive(Default)]
+pub struct DkimSigners {
+    signers: Vec<Box<dyn DkimSign + Send + Sync>>,
+}
+
+/// Key-independent interface of a [`DkimSigner`] that is ready to sign.
+pub trait DkimSign {
+    /// Returns the body canonicalization and hash algorithm used by the signer.
+    fn body_hash_params(&self) -> (Canonicalization, HashAlgorithm);
+
+    /// Signs already parsed headers using a precomputed body hash.
+    fn sign_parsed<'x>(
+        &self,
+        headers: &[(&'x [u8], &'x [u8])],
+        body: &'x [u8],
+        body_hash: &[u8],
+        now: u64,
+    ) -> crate::Result<Signature>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DomainKeyBuilder {
+    algorithm: Algorithm,
+    public_key: Vec<u8>,
+    hashes: Vec<HashAlgorithm>,
+    email_only: bool,
+    testing: bool,
+    strict: bool,
 }
 
 pub struct NeedDomain;
@@ -46,6 +140,7 @@ pub struct NeedHeaders;
 pub struct Done;
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Signature {
     pub v: u32,
     pub a: Algorithm,
@@ -67,6 +162,7 @@ pub struct Signature {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DomainKeyReport {
     pub(crate) ra: String,
     pub(crate) rp: u8,
@@ -75,11 +171,16 @@ pub struct DomainKeyReport {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Atps {
     pub(crate) v: Version,
     pub(crate) d: Option<String>,
 }
 
+/// Header fields commonly altered in replayed or spoofed messages, which a
+/// signature should cover to vouch for what the recipient reads.
+pub const CRITICAL_HEADERS: [&str; 5] = ["Subject", "To", "Date", "Reply-To", "Content-Type"];
+
 pub(crate) const R_SVC_ALL: u64 = 0x04;
 pub(crate) const R_SVC_EMAIL: u64 = 0x08;
 pub(crate) const R_FLAG_TESTING: u64 = 0x10;
@@ -153,9 +254,71 @@ impl VerifySignature for Signature {
 }
 
 impl Signature {
+    /// Agent or user identifier (`i=`), empty if not present.
     pub fn identity(&self) -> &str {
         &self.i
     }
+
+    /// Names of the signed header fields (`h=`), in signing order.
+    pub fn signed_headers(&self) -> &[String] {
+        &self.h
+    }
+
+    /// Returns `true` if the signature covers at least one instance of a header.
+    pub fn signs_header(&self, name: &str) -> bool {
+        self.h.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Decoded hash of the canonicalized body (`bh=`).
+    pub fn body_hash(&self) -> &[u8] {
+        &self.bh
+    }
+
+    /// Decoded signature data (`b=`).
+    pub fn signature_data(&self) -> &[u8] {
+        &self.b
+    }
+
+    /// Header canonicalization algorithm (`c=`).
+    pub fn header_canonicalization(&self) -> Canonicalization {
+        self.ch
+    }
+
+    /// Body canonicalization algorithm (`c=`).
+    pub fn body_canonicalization(&self) -> Canonicalization {
+        self.cb
+    }
+
+    /// Public key query method (`q=`). RFC 6376 only defines `dns/txt`, which
+    /// is also the default, so the tag is not stored when parsing.
+    pub fn query_method(&self) -> &'static str {
+        "dns/txt"
+    }
+
+    /// Signature timestamp in seconds since the Unix epoch (`t=`).
+    pub fn timestamp(&self) -> Option<u64> {
+        (self.t != 0).then_some(self.t)
+    }
+
+    /// Signature expiration in seconds since the Unix epoch (`x=`).
+    pub fn expiration(&self) -> Option<u64> {
+        (self.x != 0).then_some(self.x)
+    }
+
+    /// Number of canonicalized body octets covered by the signature (`l=`).
+    pub fn body_length(&self) -> Option<u64> {
+        (self.l != 0).then_some(self.l)
+    }
+
+    /// Copied header fields (`z=`) as decoded name and value pairs.
+    pub fn copied_headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.z.iter().filter_map(|header| header.split_once(':'))
+    }
+
+    /// Returns `true` if the signer requested failure reports (`r=y`, RFC 6651).
+    pub fn reporting_requested(&self) -> bool {
+        self.r
+    }
 }
 
 impl<'x> DkimOutput<'x> {
@@ -165,6 +328,11 @@ impl<'x> DkimOutput<'x> {
             signature: None,
             report: None,
             is_atps: false,
+            compat_shims: Vec::new(),
+            body_normalization: None,
+            body_bytes_hashed: None,
+            resent_unsigned: false,
+            policy: None,
         }
     }
 
@@ -174,6 +342,11 @@ impl<'x> DkimOutput<'x> {
             signature: None,
             report: None,
             is_atps: false,
+            compat_shims: Vec::new(),
+            body_normalization: None,
+            body_bytes_hashed: None,
+            resent_unsigned: false,
+            policy: None,
         }
     }
 
@@ -183,6 +356,11 @@ impl<'x> DkimOutput<'x> {
             signature: None,
             report: None,
             is_atps: false,
+            compat_shims: Vec::new(),
+            body_normalization: None,
+            body_bytes_hashed: None,
+            resent_unsigned: false,
+            policy: None,
         }
     }
 
@@ -192,6 +370,11 @@ impl<'x> DkimOutput<'x> {
             signature: None,
             report: None,
             is_atps: false,
+            compat_shims: Vec::new(),
+            body_normalization: None,
+            body_bytes_hashed: None,
+            resent_unsigned: false,
+            policy: None,
         }
     }
 
@@ -201,6 +384,11 @@ impl<'x> DkimOutput<'x> {
             signature: None,
             report: None,
             is_atps: false,
+            compat_shims: Vec::new(),
+            body_normalization: None,
+            body_bytes_hashed: None,
+            resent_unsigned: false,
+            policy: None,
         }
     }
 
@@ -222,6 +410,21 @@ impl<'x> DkimOutput<'x> {
         self
     }
 
+    pub(crate) fn with_compat_shims(mut self, compat_shims: Vec<CompatShim>) -> Self {
+        self.compat_shims = compat_shims;
+        self
+    }
+
+    pub(crate) fn with_body_normalization(mut self, normalization: BodyNormalization) -> Self {
+        self.body_normalization = normalization.into();
+        self
+    }
+
+    pub(crate) fn with_policy_decision(mut self, policy: Option<PolicyDecision>) -> Self {
+        self.policy = policy;
+        self
+    }
+
     pub fn result(&self) -> &DkimResult {
         &self.result
     }
@@ -230,9 +433,59 @@ impl<'x> DkimOutput<'x> {
         self.signature
     }
 
+    /// Returns the compatibility shims that were needed for the signature to verify.
+    pub fn compat_shims(&self) -> &[CompatShim] {
+        &self.compat_shims
+    }
+
+    /// Returns the normalization after which a mismatched body hash would have
+    /// matched, when body hash diagnostics are enabled.
+    pub fn body_normalization(&self) -> Option<BodyNormalization> {
+        self.body_normalization
+    }
+
     pub fn failure_report_addr(&self) -> Option<&str> {
         self.report.as_deref()
     }
+
+    /// Returns the number of body bytes covered by the signature's body hash,
+    /// which is less than the body length when the signature has an `l=` tag.
+    pub fn body_bytes_hashed(&self) -> Option<u64> {
+        self.body_bytes_hashed
+    }
+
+    /// Returns `true` if the message has `Resent-*` header fields not covered
+    /// by the signature, usually because it was resent after being signed.
+    pub fn resent_unsigned(&self) -> bool {
+        self.resent_unsigned
+    }
+
+    /// Returns the verification policy rule applied to the signature, if any.
+    pub fn policy_decision(&self) -> Option<PolicyDecision> {
+        self.policy
+    }
+
+    /// Returns the [`CRITICAL_HEADERS`] not covered by a passing signature,
+    /// or nothing if the signature did not pass.
+    pub fn unsigned_critical_headers(&self) -> Vec<&'static str> {
+        match self.signature {
+            Some(signature) if self.result == DkimResult::Pass => CRITICAL_HEADERS
+                .into_iter()
+                .filter(|name| !signature.signs_header(name))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns `true` if the signature passed and covers all [`CRITICAL_HEADERS`].
+    pub fn covers_critical_headers(&self) -> bool {
+        self.result == DkimResult::Pass
+            && self.signature.is_some_and(|signature| {
+                CRITICAL_HEADERS
+                    .iter()
+                    .all(|name| signature.signs_header(name))
+            })
+    }
 }
 
 impl<'x> ArcOutput<'x> {
@@ -243,6 +496,20 @@ impl<'x> ArcOutput<'x> {
     pub fn sets(&self) -> &[Set] {
         &self.set
     }
+
+    /// Returns the trust score of the chain, from 0 to 100, if the resolver
+    /// has an `ArcScorer`.
+    pub fn trust_score(&self) -> Option<u8> {
+        self.score
+    }
+
+    /// Returns the domains that sealed each ARC instance, in instance order.
+    pub fn sealers(&self) -> Vec<&str> {
+        self.set
+            .iter()
+            .map(|set| set.seal.header.d.as_str())
+            .collect()
+    }
 }
 
 impl From<Error> for DkimResult {
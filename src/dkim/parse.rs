@@ -10,10 +10,13 @@
 
 use std::slice::Iter;
 
-use mail_parser::decoders::base64::base64_decode_stream;
-
 use crate::{
-    common::{crypto::VerifyingKeyType, parse::*, verify::DomainKey},
+    common::{
+        base64::{MAX_BODY_HASH_LEN, MAX_PUBLIC_KEY_LEN, MAX_SIGNATURE_LEN},
+        crypto::VerifyingKeyType,
+        parse::*,
+        verify::DomainKey,
+    },
     dkim::{RR_EXPIRATION, RR_SIGNATURE, RR_UNKNOWN_TAG, RR_VERIFICATION},
     Error,
 };
@@ -65,7 +68,6 @@ impl Signature {
             atps: None,
             atpsh: None,
         };
-        let header_len = header.len();
         let mut header = header.iter();
 
         while let Some(key) = header.key() {
@@ -79,14 +81,8 @@ impl Signature {
                 A => {
                     signature.a = header.algorithm()?;
                 }
-                B => {
-                    signature.b =
-                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
-                }
-                BH => {
-                    signature.bh =
-                        base64_decode_stream(&mut header, header_len, b';').ok_or(Error::Base64)?
-                }
+                B => signature.b = header.base64(MAX_SIGNATURE_LEN)?,
+                BH => signature.bh = header.base64(MAX_BODY_HASH_LEN)?,
                 C => {
                     let (ch, cb) = header.canonicalization(Canonicalization::Simple)?;
                     signature.ch = ch;
@@ -132,6 +128,14 @@ impl Signature {
             Err(Error::MissingParameters)
         }
     }
+
+    /// Parses a DKIM-Signature header, also returning any tag-list syntax warnings.
+    pub fn parse_with_strictness(
+        header: &'_ [u8],
+        strictness: TagStrictness,
+    ) -> crate::Result<(Self, Vec<TagWarning>)> {
+        parse_tag_list(header, strictness, Self::parse)
+    }
 }
 
 pub(crate) trait SignatureParser: Sized {
@@ -243,7 +247,6 @@ impl SignatureParser for Iter<'_, u8> {
 impl TxtRecordParser for DomainKey {
     #[allow(clippy::while_let_on_iterator)]
     fn parse(header: &[u8]) -> crate::Result<Self> {
-        let header_len = header.len();
         let mut header = header.iter();
         let mut flags = 0;
         let mut key_type = VerifyingKeyType::Rsa;
@@ -257,11 +260,11 @@ impl TxtRecordParser for DomainKey {
                     }
                 }
                 H => flags |= header.flags::<HashAlgorithm>(),
-                P => {
-                    if let Some(bytes) = base64_decode_stream(&mut header, header_len, b';') {
-                        public_key = Some(bytes);
-                    }
-                }
+                P => match header.base64(MAX_PUBLIC_KEY_LEN) {
+                    Ok(bytes) => public_key = Some(bytes),
+                    Err(Error::Base64TooLong) => return Err(Error::Base64TooLong),
+                    Err(_) => (),
+                },
                 S => flags |= header.flags::<Service>(),
                 T => flags |= header.flags::<Flag>(),
                 K => {
@@ -462,7 +465,7 @@ mod test {
     use crate::{
         common::{
             crypto::{Algorithm, R_HASH_SHA1, R_HASH_SHA256},
-            parse::TxtRecordParser,
+            parse::{tag_list_warnings, TagStrictness, TagWarning, TxtRecordParser},
             verify::DomainKey,
         },
         dkim::{
@@ -470,6 +473,7 @@ mod test {
             RR_POLICY, RR_SIGNATURE, RR_UNKNOWN_TAG, RR_VERIFICATION, R_FLAG_MATCH_DOMAIN,
             R_FLAG_TESTING, R_SVC_ALL, R_SVC_EMAIL,
         },
+        AuthenticatedMessage, Error,
     };
 
     #[test]
@@ -706,4 +710,113 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn dkim_signature_strictness() {
+        let header = concat!(
+            "v=1; a=rsa-sha256; s=default; d=stalw.art; d=example.org; ",
+            "bh=QoiUNYyUV+1tZ/xUPRcE+gST2zAStvJx1OK078Ylm5s=; ",
+            "b=Du0rvdzNodI6b5bhlUaZZ+gpXJi0VwjY/3qL7lS0wzKutNVCbvdJuZObGdAcv; ",
+            "h=Subject:To:From"
+        );
+
+        let (signature, warnings) =
+            Signature::parse_with_strictness(header.as_bytes(), TagStrictness::Forgiving).unwrap();
+        assert_eq!(signature.d, "example.org");
+        assert_eq!(warnings, vec![TagWarning::DuplicateTag("d".to_string())]);
+        assert_eq!(
+            Signature::parse_with_strictness(header.as_bytes(), TagStrictness::Strict),
+            Err(Error::ParseError)
+        );
+
+        let message = format!("DKIM-Signature: {header}\r\nFrom: hello@stalw.art\r\n\r\nHi!\r\n");
+        let message = AuthenticatedMessage::parse_with_strictness(
+            message.as_bytes(),
+            true,
+            TagStrictness::Forgiving,
+        )
+        .unwrap();
+        assert!(message.dkim_headers[0].header.is_ok());
+        assert_eq!(message.tag_warnings().len(), 1);
+
+        // FWS is allowed within header lists and query methods
+        let header = concat!(
+            "v=1; a=rsa-sha256; s=default; d=stalw.art;\r\n q=dns/txt :\r\n\tdns/txt; ",
+            "bh=QoiUNYyUV+1tZ/xUPRcE+gST2zAStvJx1OK078Ylm5s=; ",
+            "b=Du0rvdzNodI6b5bhlUaZZ+gpXJi0VwjY/3qL7lS0wzKutNVCbvdJuZObGdAcv; ",
+            "h=Subject : To :\r\n From"
+        );
+        assert_eq!(
+            Signature::parse_with_strictness(header.as_bytes(), TagStrictness::Strict)
+                .unwrap()
+                .1,
+            vec![]
+        );
+        assert_eq!(
+            tag_list_warnings(b"v=1; d=stalw .art"),
+            vec![TagWarning::WhitespaceInValue("d".to_string())]
+        );
+    }
+
+    #[test]
+    fn dkim_signature_accessors() {
+        let signature = Signature::parse(
+            concat!(
+                "v=1; a=rsa-sha256; d=example.net; s=brisbane; c=simple/relaxed;\r\n",
+                " q=dns/txt; i=@eng.example.net; t=1117574938; x=1118006938; l=200;\r\n",
+                " h=from:to:subject:date:Subject; r=y;\r\n",
+                " z=From:foo@eng.example.net|Subject:demo=20run;\r\n",
+                " bh=MTIzNDU2Nzg5MDEyMzQ1Njc4OTAxMjM0NTY3ODkwMTI=;\r\n",
+                " b=dzdVyOfAKCdLXdJOc9G2q8LoXSlEniSbav+yuU4zGeeruD00lszZVoG4ZHRNiYzR",
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            signature.signed_headers(),
+            ["from", "to", "subject", "date", "Subject"]
+        );
+        assert!(signature.signs_header("Date"));
+        assert!(!signature.signs_header("Reply-To"));
+        assert_eq!(
+            signature.body_hash(),
+            b"12345678901234567890123456789012".as_slice()
+        );
+        assert_eq!(
+            signature.signature_data(),
+            base64_decode(b"dzdVyOfAKCdLXdJOc9G2q8LoXSlEniSbav+yuU4zGeeruD00lszZVoG4ZHRNiYzR")
+                .unwrap()
+        );
+        assert_eq!(
+            signature.header_canonicalization(),
+            Canonicalization::Simple
+        );
+        assert_eq!(signature.body_canonicalization(), Canonicalization::Relaxed);
+        assert_eq!(signature.query_method(), "dns/txt");
+        assert_eq!(signature.identity(), "@eng.example.net");
+        assert_eq!(signature.timestamp(), Some(1117574938));
+        assert_eq!(signature.expiration(), Some(1118006938));
+        assert_eq!(signature.body_length(), Some(200));
+        assert_eq!(
+            signature.copied_headers().collect::<Vec<_>>(),
+            [("From", "foo@eng.example.net"), ("Subject", "demo run")]
+        );
+        assert!(signature.reporting_requested());
+
+        let signature = Signature::parse(
+            b"v=1; a=ed25519-sha256; d=example.net; s=ed; h=from; bh=AA==; b=AA==",
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                signature.timestamp(),
+                signature.expiration(),
+                signature.body_length()
+            ),
+            (None, None, None)
+        );
+        assert_eq!(signature.copied_headers().count(), 0);
+        assert!(!signature.reporting_requested());
+    }
 }
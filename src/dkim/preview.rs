@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::time::SystemTime;
+
+use mail_builder::encoders::base64::base64_encode;
+
+use super::{sign::SignableMessage, DkimSigner, Done, HashAlgorithm, Signature};
+
+use crate::common::{
+    crypto::SigningKey,
+    headers::{HeaderIterator, Writable},
+};
+
+/// What a `DkimSigner` would sign, produced without the private key operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningPreview {
+    signature: Signature,
+    canonical_headers: Vec<u8>,
+    signed_data: Vec<u8>,
+}
+
+impl<T: SigningKey> DkimSigner<T, Done> {
+    /// Returns the canonicalized headers, body hash and tags of the signature
+    /// without signing, for audit logging or for reviewing the data before a
+    /// remote signing call. The signature is completed with
+    /// `SigningPreview::finish`.
+    pub fn preview(&self, message: &[u8]) -> crate::Result<SigningPreview> {
+        let (signature, canonical_headers) = self.prepare(
+            HeaderIterator::new(message),
+            None,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )?;
+        let mut headers = Vec::new();
+        canonical_headers.clone().write(&mut headers);
+        let mut signed_data = Vec::new();
+        SignableMessage {
+            headers: canonical_headers,
+            signature: &signature,
+        }
+        .write(&mut signed_data);
+
+        Ok(SigningPreview {
+            signature,
+            canonical_headers: headers,
+            signed_data,
+        })
+    }
+}
+
+impl SigningPreview {
+    /// Signature with all tags set except `b=`.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Canonicalized header block, without the DKIM-Signature header.
+    pub fn canonical_headers(&self) -> &[u8] {
+        &self.canonical_headers
+    }
+
+    /// Base64-encoded body hash (`bh=`).
+    pub fn body_hash(&self) -> &[u8] {
+        &self.signature.bh
+    }
+
+    /// Data passed to the signing algorithm: the canonicalized headers followed
+    /// by the canonicalized DKIM-Signature header with an empty `b=` tag and
+    /// without a trailing CRLF.
+    pub fn signed_data(&self) -> &[u8] {
+        &self.signed_data
+    }
+
+    /// Digest of the signed data, for signing services that take a
+    /// precomputed hash.
+    pub fn digest(&self) -> Vec<u8> {
+        HashAlgorithm::from(self.signature.a)
+            .hash(self.signed_data.as_slice())
+            .as_ref()
+            .to_vec()
+    }
+
+    /// Completes the signature with the output of the signing algorithm.
+    pub fn finish(self, b: &[u8]) -> crate::Result<Signature> {
+        let mut signature = self.signature;
+        signature.b = base64_encode(b)?;
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            crypto::SigningKey,
+            headers::HeaderWriter,
+            test_key::{ed25519_key, ed25519_resolver},
+        },
+        dkim::DkimSigner,
+        AuthenticatedMessage, DkimResult,
+    };
+
+    #[tokio::test]
+    async fn dkim_sign_preview() {
+        let message = concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject:  TPS   Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP.\r\n"
+        );
+        let signer = DkimSigner::from_key(ed25519_key().unwrap())
+            .domain("example.com")
+            .selector("ed")
+            .headers(["From", "To", "Subject"]);
+
+        let preview = signer.preview(message.as_bytes()).unwrap();
+        assert_eq!(
+            preview.canonical_headers(),
+            concat!(
+                "subject:TPS Report\r\n",
+                "to:jdoe@example.com\r\n",
+                "from:bill@example.com\r\n"
+            )
+            .as_bytes()
+        );
+        assert_eq!(
+            preview.body_hash(),
+            signer.sign(message.as_bytes()).unwrap().bh.as_slice()
+        );
+        assert!(preview.signature().b.is_empty());
+        assert!(preview
+            .signed_data()
+            .starts_with(preview.canonical_headers()));
+        assert!(preview.signed_data().ends_with(b" b=;"));
+        assert_eq!(preview.digest().len(), 32);
+
+        // Sign the previewed data separately
+        let b = signer.key.sign(preview.signed_data()).unwrap();
+        let signature = preview.finish(&b).unwrap();
+        let resolver = ed25519_resolver("ed._domainkey.example.com.");
+        let signed = format!("{}{}", signature.to_header(), message);
+        let signed = AuthenticatedMessage::parse(signed.as_bytes()).unwrap();
+        let result = resolver.verify_dkim(&signed).await;
+        assert_eq!(result[0].result(), &DkimResult::Pass);
+    }
+}
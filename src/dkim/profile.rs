@@ -0,0 +1,352 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::time::Duration;
+
+use crate::{common::crypto::SigningKey, Error};
+
+use super::{Canonicalization, DkimSigner, DkimSigners, Done, Signature};
+
+/// Kind of mail a message is sent as, used to select a `SigningProfile`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MailStream {
+    Transactional,
+    Marketing,
+    Forwarding,
+    Custom(String),
+}
+
+/// Declarative DKIM signing settings, turned into a signer for a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningProfile {
+    domain: String,
+    selector: String,
+    headers: Vec<String>,
+    oversign_headers: Vec<String>,
+    ch: Canonicalization,
+    cb: Canonicalization,
+    expiration: Option<Duration>,
+    body_length: bool,
+    reporting: bool,
+    copy_headers: bool,
+    sign_resent: bool,
+    auid: Option<String>,
+}
+
+/// Signing profiles of several mail streams, with a callback that selects the
+/// stream of a message from user-defined metadata `M`.
+pub struct SigningProfiles<M> {
+    streams: Vec<(MailStream, DkimSigners)>,
+    resolve: StreamResolver<M>,
+}
+
+type StreamResolver<M> = Box<dyn Fn(&M) -> Option<MailStream> + Send + Sync>;
+
+// Header fields recommended for signing by RFC 6376 section 5.4.1
+const DEFAULT_HEADERS: [&str; 14] = [
+    "From",
+    "Reply-To",
+    "Subject",
+    "Date",
+    "To",
+    "Cc",
+    "In-Reply-To",
+    "References",
+    "Message-ID",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+    "List-Id",
+    "List-Unsubscribe",
+];
+
+impl SigningProfile {
+    /// Creates a profile that signs the header fields recommended by RFC 6376
+    /// using relaxed canonicalization, over-signing `From`.
+    pub fn new(domain: impl Into<String>, selector: impl Into<String>) -> Self {
+        SigningProfile {
+            domain: domain.into(),
+            selector: selector.into(),
+            headers: DEFAULT_HEADERS.iter().map(|h| h.to_string()).collect(),
+            oversign_headers: vec!["From".to_string()],
+            ch: Canonicalization::Relaxed,
+            cb: Canonicalization::Relaxed,
+            expiration: None,
+            body_length: false,
+            reporting: false,
+            copy_headers: false,
+            sign_resent: false,
+            auid: None,
+        }
+    }
+
+    /// Sets the headers to sign.
+    pub fn with_headers(mut self, headers: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.headers = headers.into_iter().map(|h| h.into()).collect();
+        self
+    }
+
+    /// Sets the headers to over-sign, see `DkimSigner::oversign_headers`.
+    pub fn with_oversign_headers(
+        mut self,
+        headers: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.oversign_headers = headers.into_iter().map(|h| h.into()).collect();
+        self
+    }
+
+    pub fn with_header_canonicalization(mut self, ch: Canonicalization) -> Self {
+        self.ch = ch;
+        self
+    }
+
+    pub fn with_body_canonicalization(mut self, cb: Canonicalization) -> Self {
+        self.cb = cb;
+        self
+    }
+
+    /// Sets how long signatures remain valid after signing.
+    pub fn with_expiration(mut self, expiration: Duration) -> Self {
+        self.expiration = expiration.into();
+        self
+    }
+
+    pub fn with_body_length(mut self, body_length: bool) -> Self {
+        self.body_length = body_length;
+        self
+    }
+
+    pub fn with_reporting(mut self, reporting: bool) -> Self {
+        self.reporting = reporting;
+        self
+    }
+
+    pub fn with_copy_headers(mut self, copy_headers: bool) -> Self {
+        self.copy_headers = copy_headers;
+        self
+    }
+
+    pub fn with_resent_headers(mut self, sign_resent: bool) -> Self {
+        self.sign_resent = sign_resent;
+        self
+    }
+
+    pub fn with_agent_user_identifier(mut self, auid: impl Into<String>) -> Self {
+        self.auid = Some(auid.into());
+        self
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    pub fn selector(&self) -> &str {
+        &self.selector
+    }
+
+    /// Creates a signer for a key using the settings of this profile.
+    pub fn signer<T: SigningKey>(&self, key: T) -> DkimSigner<T, Done> {
+        let mut signer = DkimSigner::from_key(key)
+            .domain(self.domain.as_str())
+            .selector(self.selector.as_str())
+            .headers(&self.headers)
+            .oversign_headers(&self.oversign_headers)
+            .header_canonicalization(self.ch)
+            .body_canonicalization(self.cb)
+            .expiration(self.expiration.map_or(0, |e| e.as_secs()))
+            .body_length(self.body_length)
+            .reporting(self.reporting)
+            .copy_headers(self.copy_headers)
+            .resent_headers(self.sign_resent);
+        if let Some(auid) = &self.auid {
+            signer = signer.agent_user_identifier(auid.as_str());
+        }
+        signer
+    }
+}
+
+impl<M> SigningProfiles<M> {
+    /// Creates an empty set of profiles, selected by `resolve` from the
+    /// metadata of each message.
+    pub fn new(resolve: impl Fn(&M) -> Option<MailStream> + Send + Sync + 'static) -> Self {
+        SigningProfiles {
+            streams: Vec::new(),
+            resolve: Box::new(resolve),
+        }
+    }
+
+    /// Signs messages of a stream with a key using a profile. Adding several
+    /// profiles to the same stream signs its messages once with each of them.
+    pub fn with_profile<T: SigningKey + Send + Sync + 'static>(
+        mut self,
+        stream: MailStream,
+        profile: &SigningProfile,
+        key: T,
+    ) -> Self {
+        let signer = profile.signer(key);
+        match self.streams.iter_mut().find(|(s, _)| s == &stream) {
+            Some((_, signers)) => {
+                *signers = std::mem::take(signers).with_signer(signer);
+            }
+            None => {
+                self.streams
+                    .push((stream, DkimSigners::new().with_signer(signer)));
+            }
+        }
+        self
+    }
+
+    /// Returns the stream selected for a message.
+    pub fn stream(&self, metadata: &M) -> Option<MailStream> {
+        (self.resolve)(metadata)
+    }
+
+    /// Signs a message with the profiles of the stream selected from its metadata.
+    /// Returns `Error::MissingParameters` if no stream, or a stream without
+    /// profiles, is selected.
+    pub fn sign(&self, metadata: &M, message: &[u8]) -> crate::Result<Vec<Signature>> {
+        let stream = self.stream(metadata).ok_or(Error::MissingParameters)?;
+        self.streams
+            .iter()
+            .find(|(s, _)| s == &stream)
+            .ok_or(Error::MissingParameters)?
+            .1
+            .sign(message)
+    }
+}
+
+impl From<&str> for MailStream {
+    fn from(value: &str) -> Self {
+        match value {
+            "transactional" => MailStream::Transactional,
+            "marketing" => MailStream::Marketing,
+            "forwarding" => MailStream::Forwarding,
+            _ => MailStream::Custom(value.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{
+        common::test_key::{ed25519_key, ed25519_public_key},
+        dkim::{Canonicalization, Signature},
+        Error,
+    };
+
+    use super::{MailStream, SigningProfile, SigningProfiles};
+
+    struct Metadata {
+        tenant: &'static str,
+        precedence: &'static str,
+    }
+
+    #[test]
+    fn signing_profiles() {
+        let message = concat!(
+            "From: news@example.com\r\n",
+            "To: jdoe@example.net\r\n",
+            "Subject: Weekly offers\r\n",
+            "List-Unsubscribe: <mailto:leave@example.com>\r\n",
+            "\r\n",
+            "Offers.\r\n"
+        );
+        let public_key = ed25519_public_key();
+        let key = || ed25519_key().unwrap();
+
+        let transactional = SigningProfile::new("example.com", "tx")
+            .with_headers(["From", "To", "Subject"])
+            .with_header_canonicalization(Canonicalization::Simple)
+            .with_expiration(Duration::from_secs(86400));
+        let marketing = SigningProfile::new("example.com", "mkt");
+        let profiles = SigningProfiles::new(|metadata: &Metadata| match metadata {
+            Metadata {
+                tenant: "blocked", ..
+            } => None,
+            Metadata {
+                precedence: "bulk", ..
+            } => Some(MailStream::Marketing),
+            Metadata { precedence, .. } => Some(MailStream::from(*precedence)),
+        })
+        .with_profile(MailStream::Transactional, &transactional, key())
+        .with_profile(MailStream::Marketing, &marketing, key())
+        .with_profile(
+            MailStream::Marketing,
+            &SigningProfile::new("esp.example.org", "mkt"),
+            key(),
+        );
+
+        let verify = |signatures: Vec<Signature>| {
+            for signature in &signatures {
+                signature
+                    .verify_loopback(message.as_bytes(), &public_key)
+                    .unwrap();
+            }
+            signatures
+        };
+
+        let signatures = verify(
+            profiles
+                .sign(
+                    &Metadata {
+                        tenant: "acme",
+                        precedence: "transactional",
+                    },
+                    message.as_bytes(),
+                )
+                .unwrap(),
+        );
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].s, "tx");
+        assert_eq!(signatures[0].h, ["Subject", "To", "From", "From"]);
+        assert_eq!(signatures[0].ch, Canonicalization::Simple);
+        assert_eq!(signatures[0].x, signatures[0].t + 86400);
+
+        let signatures = verify(
+            profiles
+                .sign(
+                    &Metadata {
+                        tenant: "acme",
+                        precedence: "bulk",
+                    },
+                    message.as_bytes(),
+                )
+                .unwrap(),
+        );
+        assert_eq!(
+            signatures
+                .iter()
+                .map(|s| (s.d.as_str(), s.s.as_str()))
+                .collect::<Vec<_>>(),
+            [("example.com", "mkt"), ("esp.example.org", "mkt")]
+        );
+        assert!(signatures[0].h.iter().any(|h| h == "List-Unsubscribe"));
+        assert_eq!(signatures[0].ch, Canonicalization::Relaxed);
+        assert_eq!(signatures[0].x, 0);
+
+        for metadata in [
+            Metadata {
+                tenant: "blocked",
+                precedence: "bulk",
+            },
+            Metadata {
+                tenant: "acme",
+                precedence: "forwarding",
+            },
+        ] {
+            assert_eq!(
+                profiles.sign(&metadata, message.as_bytes()),
+                Err(Error::MissingParameters)
+            );
+        }
+    }
+}
@@ -0,0 +1,241 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::time::{Duration, Instant};
+
+use hickory_resolver::Name;
+
+use crate::{
+    common::{
+        crypto::{Algorithm, VerifyingKeyType},
+        parse::TxtRecordParser,
+        verify::DomainKey,
+    },
+    Resolver,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Resolver {
+    /// Sets the resolvers polled by `wait_for_dkim_key`, which defaults to the
+    /// Cloudflare, Google and Quad9 public resolvers.
+    pub fn with_propagation_resolvers(
+        mut self,
+        resolvers: impl IntoIterator<Item = Resolver>,
+    ) -> Self {
+        self.propagation_resolvers = resolvers.into_iter().collect();
+        self
+    }
+
+    /// Polls the propagation resolvers until all of them return the public key
+    /// published under `selector._domainkey.domain`. Returns `false` if the key
+    /// is still missing on any resolver once the timeout expires.
+    pub async fn wait_for_dkim_key(
+        &self,
+        domain: &str,
+        selector: &str,
+        expected_key: &[u8],
+        timeout: Duration,
+    ) -> bool {
+        let defaults;
+        let resolvers = if self.propagation_resolvers.is_empty() {
+            defaults = [
+                Resolver::new_cloudflare(),
+                Resolver::new_google(),
+                Resolver::new_quad9(),
+            ]
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect::<Vec<_>>();
+            &defaults
+        } else {
+            &self.propagation_resolvers
+        };
+        if resolvers.is_empty() {
+            return false;
+        }
+
+        let name = format!("{selector}._domainkey.{domain}.");
+        let deadline = Instant::now() + timeout;
+        let mut pending = resolvers.iter().collect::<Vec<_>>();
+        loop {
+            let published = futures_util::future::join_all(
+                pending
+                    .iter()
+                    .map(|resolver| resolver.has_dkim_key(&name, expected_key)),
+            )
+            .await;
+            pending = pending
+                .into_iter()
+                .zip(published)
+                .filter_map(|(resolver, published)| (!published).then_some(resolver))
+                .collect();
+            if pending.is_empty() {
+                return true;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            tokio::time::sleep((deadline - now).min(POLL_INTERVAL)).await;
+        }
+    }
+
+    async fn has_dkim_key(&self, name: &str, expected_key: &[u8]) -> bool {
+        // Polls bypass the record caches, which would otherwise keep returning
+        // a missing or previous key until its TTL expires.
+        let Ok(name) = Name::from_str_relaxed(name) else {
+            return false;
+        };
+        if self.offline {
+            return false;
+        }
+        let record = match self.dns_config.uncached_resolver().txt_lookup(name).await {
+            Ok(lookup) => lookup
+                .iter()
+                .find_map(|txt| DomainKey::parse(&txt.txt_data().concat()).ok()),
+            Err(_) => None,
+        };
+        let Some((algorithm, public_key)) =
+            record.as_ref().and_then(|record| record.p.public_key())
+        else {
+            return false;
+        };
+
+        match algorithm {
+            Algorithm::Ed25519Sha256 => VerifyingKeyType::Ed25519,
+            Algorithm::RsaSha1 | Algorithm::RsaSha256 => VerifyingKeyType::Rsa,
+        }
+        .verifying_key(expected_key)
+        .ok()
+        .and_then(|key| key.public_key())
+        .is_some_and(|(_, expected_key)| expected_key == public_key)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use hickory_resolver::config::ResolverOpts;
+    use tokio::net::UdpSocket;
+
+    use crate::{
+        common::{
+            test_dns::{self, resolver_config},
+            test_key::{ed25519_public_key, ED25519_RECORD as NEW_RECORD},
+        },
+        Resolver,
+    };
+
+    const OLD_RECORD: &str = concat!(
+        "v=DKIM1; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQCYtb/9Sh8nGKV7exhUFS",
+        "+cBNXlHgO1CxD9zIfQd5ztlq1LO7g38dfmFpQafh9lKgqPBTolFhZxhF1yUNT",
+        "hpV673NdAtaCVGNyx/fTYtvyyFe9DH2tmm/ijLlygDRboSkIJ4NHZjK++48hk",
+        "NP8/htqWHS+CvwWT4Qgs0NtB7Re9bQIDAQAB"
+    );
+
+    // Answers the n-th query for the "new" selector with the n-th record (or the
+    // last one) using a one hour TTL, and any other name or missing record with
+    // NXDOMAIN
+    pub(crate) async fn serve(
+        records: Vec<Option<&'static str>>,
+        queries: Arc<AtomicUsize>,
+    ) -> Resolver {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        test_dns::serve(socket, move |query| {
+            let len = query.len();
+            let mut pos = 12;
+            while pos < len && query[pos] != 0 {
+                pos += query[pos] as usize + 1;
+            }
+            if pos + 5 > len {
+                return None;
+            }
+            let mut response = query[..pos + 5].to_vec();
+            response[2] |= 0x80;
+            response[6..12].fill(0);
+            let record = if response[13..16].eq_ignore_ascii_case(b"new") {
+                records[queries
+                    .fetch_add(1, Ordering::Relaxed)
+                    .min(records.len() - 1)]
+            } else {
+                None
+            };
+            if let Some(record) = record {
+                let rdata = record
+                    .as_bytes()
+                    .chunks(255)
+                    .flat_map(|chunk| [&[chunk.len() as u8][..], chunk].concat())
+                    .collect::<Vec<_>>();
+                response[3] = 0x80;
+                response[7] = 1;
+                response.extend_from_slice(&[0xc0, 12, 0, 16, 0, 1, 0, 0, 0x0e, 0x10]);
+                response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+                response.extend_from_slice(&rdata);
+            } else {
+                response[3] = 0x83;
+            }
+            Some(response)
+        });
+
+        let mut options = ResolverOpts::default();
+        options.timeout = Duration::from_millis(500);
+        Resolver::with_capacity(resolver_config(addr), options, 128).unwrap()
+    }
+
+    #[tokio::test]
+    async fn wait_for_dkim_key() {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_propagation_resolvers([
+                serve(vec![Some(NEW_RECORD)], Arc::default()).await,
+                serve(
+                    vec![Some(OLD_RECORD), Some(OLD_RECORD), Some(NEW_RECORD)],
+                    queries.clone(),
+                )
+                .await,
+                serve(vec![Some(NEW_RECORD)], Arc::default()).await,
+            ]);
+        let new_key = ed25519_public_key();
+
+        // One resolver still returns the previous key
+        let started = Instant::now();
+        assert!(
+            !resolver
+                .wait_for_dkim_key("example.org", "new", &new_key, Duration::from_millis(100))
+                .await
+        );
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        // The record changed since the last poll, which is seen despite its TTL
+        assert!(
+            resolver
+                .wait_for_dkim_key("example.org", "new", &new_key, Duration::from_secs(10))
+                .await
+        );
+        assert_eq!(queries.load(Ordering::Relaxed), 3);
+        assert!(
+            !resolver
+                .wait_for_dkim_key("example.org", "other", &new_key, Duration::ZERO)
+                .await
+        );
+    }
+}
@@ -12,12 +12,16 @@ use std::time::SystemTime;
 
 use mail_builder::encoders::base64::base64_encode;
 
-use super::{canonicalize::CanonicalHeaders, DkimSigner, Done, Signature};
+use super::{
+    canonicalize::CanonicalHeaders, Canonicalization, DkimSign, DkimSigner, DkimSigners, Done,
+    HashAlgorithm, Signature,
+};
 
 use crate::{
     common::{
         crypto::SigningKey,
         headers::{ChainedHeaderIterator, HeaderIterator, HeaderStream, Writable, Writer},
+        stream::StreamedMessage,
     },
     Error,
 };
@@ -50,14 +54,61 @@ impl<T: SigningKey> DkimSigner<T, Done> {
         )
     }
 
+    /// Signs a message read with `MessageStreamParser`, which must have been
+    /// configured to compute the body hash required by this signer.
+    pub fn sign_streamed(&self, message: &StreamedMessage) -> crate::Result<Signature> {
+        let body_hash = message
+            .body_hash(self.template.cb, HashAlgorithm::from(self.template.a))
+            .ok_or(Error::MissingParameters)?;
+
+        self.sign_with_body_hash(
+            HeaderIterator::new(message.raw_headers()),
+            Some((body_hash, message.body_len())),
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+    }
+
     fn sign_stream<'x>(
         &self,
         message: impl HeaderStream<'x>,
         now: u64,
     ) -> crate::Result<Signature> {
+        self.sign_with_body_hash(message, None, now)
+    }
+
+    fn sign_with_body_hash<'x>(
+        &self,
+        message: impl HeaderStream<'x>,
+        body_hash: Option<(&[u8], usize)>,
+        now: u64,
+    ) -> crate::Result<Signature> {
+        let (mut signature, canonical_headers) = self.prepare(message, body_hash, now)?;
+
+        // Sign
+        let b = self.key.sign(SignableMessage {
+            headers: canonical_headers,
+            signature: &signature,
+        })?;
+
+        // Encode
+        signature.b = base64_encode(&b)?;
+
+        Ok(signature)
+    }
+
+    /// Returns the signature without `b=` along with the canonicalized headers it signs.
+    pub(super) fn prepare<'x>(
+        &self,
+        message: impl HeaderStream<'x>,
+        body_hash: Option<(&[u8], usize)>,
+        now: u64,
+    ) -> crate::Result<(Signature, CanonicalHeaders<'x>)> {
         // Canonicalize headers and body
         let (body_len, canonical_headers, signed_headers, canonical_body) =
-            self.template.canonicalize(message);
+            self.template.canonicalize(message, self.sign_resent);
 
         if signed_headers.is_empty() {
             return Err(Error::NoHeadersFound);
@@ -65,8 +116,17 @@ impl<T: SigningKey> DkimSigner<T, Done> {
 
         // Create Signature
         let mut signature = self.template.clone();
-        let body_hash = self.key.hash(canonical_body);
-        signature.bh = base64_encode(body_hash.as_ref())?;
+        let body_len = match body_hash {
+            Some((body_hash, body_len)) => {
+                signature.bh = base64_encode(body_hash)?;
+                body_len
+            }
+            None => {
+                let body_hash = self.key.hash(canonical_body);
+                signature.bh = base64_encode(body_hash.as_ref())?;
+                body_len
+            }
+        };
         signature.t = now;
         signature.x = if signature.x > 0 {
             now + signature.x
@@ -74,26 +134,110 @@ impl<T: SigningKey> DkimSigner<T, Done> {
             0
         };
         signature.h = signed_headers;
+        if self.copy_headers {
+            signature.z = canonical_headers.copied_headers();
+        }
         if signature.l > 0 {
             signature.l = body_len as u64;
         }
 
-        // Sign
-        let b = self.key.sign(SignableMessage {
-            headers: canonical_headers,
-            signature: &signature,
-        })?;
+        Ok((signature, canonical_headers))
+    }
+}
 
-        // Encode
-        signature.b = base64_encode(&b)?;
+impl<T: SigningKey> DkimSign for DkimSigner<T, Done> {
+    fn body_hash_params(&self) -> (Canonicalization, HashAlgorithm) {
+        (self.template.cb, HashAlgorithm::from(self.template.a))
+    }
 
-        Ok(signature)
+    fn sign_parsed<'x>(
+        &self,
+        headers: &[(&'x [u8], &'x [u8])],
+        body: &'x [u8],
+        body_hash: &[u8],
+        now: u64,
+    ) -> crate::Result<Signature> {
+        self.sign_with_body_hash(
+            ParsedHeaders {
+                headers: headers.iter(),
+                body,
+            },
+            Some((body_hash, body.len())),
+            now,
+        )
+    }
+}
+
+impl DkimSigners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a signer, signatures are returned in the order signers were added.
+    pub fn with_signer(mut self, signer: impl DkimSign + Send + Sync + 'static) -> Self {
+        self.signers.push(Box::new(signer));
+        self
+    }
+
+    /// Signs a message with every signer.
+    pub fn sign(&self, message: &[u8]) -> crate::Result<Vec<Signature>> {
+        self.sign_at(
+            message,
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        )
+    }
+
+    fn sign_at(&self, message: &[u8], now: u64) -> crate::Result<Vec<Signature>> {
+        // Parse headers once
+        let mut iter = HeaderIterator::new(message);
+        let headers = (&mut iter).collect::<Vec<_>>();
+        let body = iter.body();
+
+        // Hash the body once per canonicalization and hash algorithm
+        let mut body_hashes: Vec<(Canonicalization, HashAlgorithm, Vec<u8>)> = Vec::new();
+        for signer in &self.signers {
+            let (cb, ha) = signer.body_hash_params();
+            if !body_hashes.iter().any(|(c, h, _)| c == &cb && h == &ha) {
+                let bh = ha.hash(cb.canonical_body(body, u64::MAX)).as_ref().to_vec();
+                body_hashes.push((cb, ha, bh));
+            }
+        }
+
+        self.signers
+            .iter()
+            .map(|signer| {
+                let (cb, ha) = signer.body_hash_params();
+                let (_, _, bh) = body_hashes
+                    .iter()
+                    .find(|(c, h, _)| c == &cb && h == &ha)
+                    .unwrap();
+                signer.sign_parsed(&headers, body, bh, now)
+            })
+            .collect()
+    }
+}
+
+struct ParsedHeaders<'x, 'y> {
+    headers: std::slice::Iter<'y, (&'x [u8], &'x [u8])>,
+    body: &'x [u8],
+}
+
+impl<'x> HeaderStream<'x> for ParsedHeaders<'x, '_> {
+    fn next_header(&mut self) -> Option<(&'x [u8], &'x [u8])> {
+        self.headers.next().copied()
+    }
+
+    fn body(&mut self) -> &'x [u8] {
+        self.body
     }
 }
 
 pub(super) struct SignableMessage<'a> {
-    headers: CanonicalHeaders<'a>,
-    signature: &'a Signature,
+    pub(super) headers: CanonicalHeaders<'a>,
+    pub(super) signature: &'a Signature,
 }
 
 impl<'a> Writable for SignableMessage<'a> {
@@ -107,20 +251,27 @@ impl<'a> Writable for SignableMessage<'a> {
 #[allow(unused)]
 pub mod test {
     use core::str;
-    use std::time::{Duration, Instant};
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
 
     use hickory_resolver::proto::op::ResponseCode;
-    use mail_parser::decoders::base64::base64_decode;
 
     use crate::{
         common::{
-            crypto::{Ed25519Key, RsaKey, Sha256},
-            headers::HeaderIterator,
+            config::{BodyLengthPolicy, Config, DomainOverride, VerificationPolicy},
+            crypto::{RsaKey, Sha256},
+            headers::{HeaderIterator, HeaderWriter},
             parse::TxtRecordParser,
+            test_key::{ed25519_domain_key, ed25519_key},
             verify::DomainKey,
         },
-        dkim::{Atps, Canonicalization, DkimSigner, DomainKeyReport, HashAlgorithm, Signature},
-        AuthenticatedMessage, DkimOutput, DkimResult, Resolver,
+        dkim::{
+            Atps, Canonicalization, DkimSigner, DkimSigners, DomainKeyReport, HashAlgorithm,
+            PolicyDecision, Signature,
+        },
+        AuthenticatedMessage, DkimOutput, DkimResult, Error, Resolver,
     };
 
     const RSA_PRIVATE_KEY: &str = include_str!("../../resources/rsa-private.pem");
@@ -137,10 +288,6 @@ pub mod test {
         "WheQOTA19k5r2BmlRw/W9CrgCBo0Sdj+KQIDAQAB",
     );
 
-    const ED25519_PRIVATE_KEY: &str = "nWGxne/9WmC6hEr0kuwsxERJxWl7MmkZcDusAxyuf2A=";
-    const ED25519_PUBLIC_KEY: &str =
-        "v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
-
     #[cfg(any(
         feature = "rust-crypto",
         all(feature = "ring", feature = "rustls-pemfile")
@@ -186,6 +333,55 @@ pub mod test {
         );
     }
 
+    #[cfg(any(
+        feature = "rust-crypto",
+        all(feature = "ring", feature = "rustls-pemfile")
+    ))]
+    #[test]
+    fn dkim_sign_shared() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let pk = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        #[cfg(feature = "rust-crypto")]
+        let pk = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+        let signer = DkimSigner::from_key(pk)
+            .domain("stalw.art")
+            .selector("default")
+            .headers(["From", "To", "Subject"]);
+        assert_send_sync(&signer);
+        let message = concat!(
+            "From: hello@stalw.art\r\n",
+            "To: dkim@stalw.art\r\n",
+            "Subject: Testing  DKIM!\r\n\r\n",
+            "Here goes the test\r\n\r\n"
+        );
+        let expected = signer
+            .sign_stream(HeaderIterator::new(message.as_bytes()), 311923920)
+            .unwrap();
+
+        let signer = Arc::new(signer);
+        let threads = (0..4)
+            .map(|_| {
+                let signer = signer.clone();
+                std::thread::spawn(move || {
+                    (0..8)
+                        .map(|_| {
+                            signer
+                                .sign_stream(HeaderIterator::new(message.as_bytes()), 311923920)
+                                .unwrap()
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            for signature in thread.join().unwrap() {
+                assert_eq!(signature, expected);
+            }
+        }
+    }
+
     #[cfg(any(
         feature = "rust-crypto",
         all(feature = "ring", feature = "rustls-pemfile")
@@ -222,15 +418,7 @@ pub mod test {
         );
 
         // Create private keys
-        #[cfg(feature = "rust-crypto")]
-        let pk_ed = Ed25519Key::from_bytes(&base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap())
-            .unwrap();
-        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
-        let pk_ed = Ed25519Key::from_seed_and_public_key(
-            &base64_decode(ED25519_PRIVATE_KEY.as_bytes()).unwrap(),
-            &base64_decode(ED25519_PUBLIC_KEY.rsplit_once("p=").unwrap().1.as_bytes()).unwrap(),
-        )
-        .unwrap();
+        let pk_ed = ed25519_key().unwrap();
 
         // Create resolver
         let resolver = Resolver::new_system_conf().unwrap();
@@ -243,7 +431,7 @@ pub mod test {
             );
             resolver.txt_add(
                 "ed._domainkey.example.com.".to_string(),
-                DomainKey::parse(ED25519_PUBLIC_KEY.as_bytes()).unwrap(),
+                ed25519_domain_key(),
                 Instant::now() + Duration::new(3600, 0),
             );
             resolver.txt_add(
@@ -286,6 +474,43 @@ pub mod test {
         )
         .await;
 
+        dbg!("Test ED25519-SHA256 with over-signed and copied headers");
+        let pk_ed = ed25519_key().unwrap();
+        let signature = DkimSigner::from_key(pk_ed)
+            .domain("example.com")
+            .selector("ed")
+            .headers(["From", "To", "Subject"])
+            .oversign_headers(["From", "Reply-To"])
+            .copy_headers(true)
+            .sign(message.as_bytes())
+            .unwrap();
+        assert_eq!(signature.h, ["Subject", "To", "From", "From", "Reply-To"]);
+        assert_eq!(
+            signature.z,
+            [
+                "Subject:TPS Report",
+                "To:jdoe@example.com",
+                "From:bill@example.com"
+            ]
+        );
+        let header = signature.to_header();
+        assert_eq!(
+            Signature::parse(header.split_once(':').unwrap().1.as_bytes())
+                .unwrap()
+                .z,
+            signature.z
+        );
+        verify(&resolver, signature.clone(), message, Ok(())).await;
+        let injected_message = format!(
+            "{}From: mallory@example.org\r\n{message}",
+            signature.to_header()
+        );
+        let injected_message = AuthenticatedMessage::parse(injected_message.as_bytes()).unwrap();
+        assert!(matches!(
+            resolver.verify_dkim(&injected_message).await[0].result(),
+            DkimResult::Fail(_)
+        ));
+
         dbg!("Test RSA-SHA256 relaxed/relaxed with an empty message");
         #[cfg(feature = "rust-crypto")]
         let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
@@ -357,7 +582,8 @@ pub mod test {
         let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
         #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
         let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
-        verify_with_opts(
+        let mailing_list_message = message.to_string() + "\r\n----- Mailing list";
+        let dkim = verify_with_opts(
             &resolver,
             DkimSigner::from_key(pk_rsa)
                 .domain("example.com")
@@ -367,11 +593,19 @@ pub mod test {
                 .body_length(true)
                 .sign(message.as_bytes())
                 .unwrap(),
-            &(message.to_string() + "\r\n----- Mailing list"),
+            &mailing_list_message,
             Ok(()),
             false,
         )
         .await;
+        assert_eq!(
+            dkim.last().unwrap().body_bytes_hashed(),
+            Some(
+                AuthenticatedMessage::parse(message.as_bytes())
+                    .unwrap()
+                    .body_len as u64
+            )
+        );
 
         dbg!("Test RSA-SHA256 simple/relaxed with fixed body length (strict)");
         #[cfg(feature = "rust-crypto")]
@@ -507,6 +741,83 @@ pub mod test {
             Ok(()),
         )
         .await;
+
+        dbg!("Sign Resent-* headers");
+        #[cfg(feature = "rust-crypto")]
+        let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let resent_message = concat!(
+            "Resent-From: jane@example.com\r\n",
+            "Resent-To: list@example.com\r\n",
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP.\r\n"
+        );
+        let signature = DkimSigner::from_key(pk_rsa)
+            .domain("example.com")
+            .selector("default")
+            .headers(["From", "To", "Subject"])
+            .resent_headers(true)
+            .sign_stream(HeaderIterator::new(resent_message.as_bytes()), 12345)
+            .unwrap();
+        assert_eq!(
+            signature.h,
+            ["Subject", "To", "From", "Resent-To", "Resent-From"]
+        );
+        let dkim = verify(&resolver, signature.clone(), resent_message, Ok(())).await;
+        assert!(!dkim.last().unwrap().resent_unsigned());
+
+        // Resending the message after signing does not break the signature
+        let resent_again = format!(
+            "Resent-From: jdoe@example.com\r\nResent-To: bill@example.com\r\n{resent_message}"
+        );
+        let dkim = verify(&resolver, signature, &resent_again, Ok(())).await;
+        assert!(dkim.last().unwrap().resent_unsigned());
+
+        dbg!("Sign with RSA and ED25519 in a single pass");
+        let rsa_signer = || {
+            #[cfg(feature = "rust-crypto")]
+            let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+            #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+            let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+            DkimSigner::from_key(pk_rsa)
+                .domain("example.com")
+                .selector("default")
+                .headers(["From", "To", "Subject"])
+        };
+        let ed_signer = || {
+            DkimSigner::from_key(ed25519_key().unwrap())
+                .domain("example.com")
+                .selector("ed")
+                .headers(["From", "To", "Subject"])
+        };
+        let signatures = DkimSigners::new()
+            .with_signer(rsa_signer())
+            .with_signer(ed_signer())
+            .with_signer(rsa_signer().body_canonicalization(Canonicalization::Simple))
+            .sign_at(message.as_bytes(), 12345)
+            .unwrap();
+        assert_eq!(
+            signatures,
+            [
+                rsa_signer()
+                    .sign_stream(HeaderIterator::new(message.as_bytes()), 12345)
+                    .unwrap(),
+                ed_signer()
+                    .sign_stream(HeaderIterator::new(message.as_bytes()), 12345)
+                    .unwrap(),
+                rsa_signer()
+                    .body_canonicalization(Canonicalization::Simple)
+                    .sign_stream(HeaderIterator::new(message.as_bytes()), 12345)
+                    .unwrap(),
+            ]
+        );
+        for signature in signatures {
+            verify(&resolver, signature, message, Ok(())).await;
+        }
     }
 
     pub async fn verify_with_opts<'x>(
@@ -538,10 +849,115 @@ pub mod test {
                 signature: None,
                 report: d.report,
                 is_atps: d.is_atps,
+                compat_shims: d.compat_shims,
+                body_normalization: d.body_normalization,
+                body_bytes_hashed: d.body_bytes_hashed,
+                resent_unsigned: d.resent_unsigned,
+                policy: d.policy,
             })
             .collect()
     }
 
+    #[cfg(any(
+        feature = "rust-crypto",
+        all(feature = "ring", feature = "rustls-pemfile")
+    ))]
+    #[tokio::test]
+    async fn dkim_verification_policy() {
+        let message = concat!(
+            "From: bill@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "\r\n",
+            "I'm going to need those TPS reports ASAP.\r\n"
+        );
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "default._domainkey.example.com.".to_string(),
+            DomainKey::parse(RSA_PUBLIC_KEY.as_bytes()).unwrap(),
+            Instant::now() + Duration::new(3600, 0),
+        );
+
+        #[cfg(feature = "rust-crypto")]
+        let pk_rsa = RsaKey::<Sha256>::from_pkcs1_pem(RSA_PRIVATE_KEY).unwrap();
+        #[cfg(all(feature = "ring", not(feature = "rust-crypto")))]
+        let pk_rsa = RsaKey::<Sha256>::from_rsa_pem(RSA_PRIVATE_KEY).unwrap();
+        let signature = DkimSigner::from_key(pk_rsa)
+            .domain("example.com")
+            .selector("default")
+            .headers(["From", "To", "Subject"])
+            .body_length(true)
+            .expiration(60)
+            .sign(message.as_bytes())
+            .unwrap();
+        let signed_at = signature.t;
+        let raw_message = format!("{}{message}Appended text.\r\n", signature.to_header());
+        let message = AuthenticatedMessage::parse_with_opts(raw_message.as_bytes(), false).unwrap();
+
+        for (policy, now, expected_result, expected_decision) in [
+            (VerificationPolicy::default(), 0, DkimResult::Pass, None),
+            (
+                VerificationPolicy::default().with_body_length(BodyLengthPolicy::FullBody),
+                0,
+                DkimResult::Fail(Error::SignatureLength),
+                Some(PolicyDecision::BodyLengthExceeded),
+            ),
+            (
+                VerificationPolicy::default().with_body_length(BodyLengthPolicy::Reject),
+                0,
+                DkimResult::Fail(Error::SignatureLength),
+                Some(PolicyDecision::BodyLengthRejected),
+            ),
+            (
+                VerificationPolicy::default().with_min_rsa_bits(4096),
+                0,
+                DkimResult::PermError(Error::CryptoError(
+                    "RSA key shorter than 4096 bits".to_string(),
+                )),
+                Some(PolicyDecision::RsaKeyTooShort(2048)),
+            ),
+            (
+                VerificationPolicy::default(),
+                90,
+                DkimResult::Neutral(Error::SignatureExpired),
+                None,
+            ),
+            (
+                VerificationPolicy::default().with_expiration_grace(Duration::from_secs(60)),
+                90,
+                DkimResult::Pass,
+                Some(PolicyDecision::ExpirationGrace),
+            ),
+            (
+                VerificationPolicy::default().with_max_age(Duration::from_secs(30)),
+                40,
+                DkimResult::Neutral(Error::SignatureExpired),
+                Some(PolicyDecision::SignatureTooOld),
+            ),
+        ] {
+            resolver.set_config(Config::new().with_verification_policy(policy));
+            let output = resolver.verify_dkim_at(&message, signed_at + now).await;
+            assert_eq!(output[0].result(), &expected_result, "{policy:?}");
+            assert_eq!(output[0].policy_decision(), expected_decision, "{policy:?}");
+        }
+
+        // Domain overrides take precedence
+        resolver.set_config(
+            Config::new()
+                .with_verification_policy(
+                    VerificationPolicy::default().with_body_length(BodyLengthPolicy::Reject),
+                )
+                .with_override(
+                    "example.com",
+                    DomainOverride::new().with_verification_policy(VerificationPolicy::default()),
+                ),
+        );
+        assert_eq!(
+            resolver.verify_dkim_at(&message, signed_at).await[0].result(),
+            &DkimResult::Pass
+        );
+    }
+
     pub async fn verify<'x>(
         resolver: &Resolver,
         signature: Signature,
@@ -0,0 +1,230 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{AuthenticatedMessage, Domain};
+
+/// Identities an authenticated submission user may send as.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SubmissionPolicy {
+    allowed_domains: Vec<Domain>,
+    allow_subdomains: bool,
+    rewrite_domain: Option<Domain>,
+}
+
+/// Recommended handling of a submitted message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubmissionAction {
+    /// Sign with the key of `domain`, which is aligned with the RFC5322.From domain.
+    Sign {
+        domain: Domain,
+    },
+    /// The user may not send as the RFC5322.From domain. Rewrite the From
+    /// address to one within `domain` and sign with its key.
+    RewriteFrom {
+        domain: Domain,
+    },
+    Reject(SubmissionRejection),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmissionRejection {
+    /// The message has no RFC5322.From address.
+    MissingFrom,
+    /// The RFC5322.From header contains addresses in different domains.
+    MultipleFromDomains,
+    /// The user may not send as the RFC5322.From domain.
+    FromNotAllowed,
+    /// The user may not use the MAIL FROM domain.
+    MailFromNotAllowed,
+}
+
+impl SubmissionPolicy {
+    /// Allows the user to send as the given domains. Names that are not
+    /// valid domains are ignored.
+    pub fn new(allowed_domains: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        SubmissionPolicy {
+            allowed_domains: allowed_domains
+                .into_iter()
+                .filter_map(|domain| Domain::new(domain).ok())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Also allows subdomains of the allowed domains, which are signed with
+    /// the key of the parent domain.
+    pub fn with_subdomains(mut self, allow: bool) -> Self {
+        self.allow_subdomains = allow;
+        self
+    }
+
+    /// Recommends rewriting the From address to this domain instead of
+    /// rejecting messages sent as a domain the user may not use.
+    pub fn with_rewrite_domain(mut self, domain: impl AsRef<str>) -> Self {
+        self.rewrite_domain = Domain::new(domain).ok();
+        self
+    }
+
+    /// Returns the allowed domain a domain belongs to, if any.
+    pub fn allowed_domain(&self, domain: &str) -> Option<&Domain> {
+        let domain = Domain::new(domain).ok()?;
+        self.allowed_domains
+            .iter()
+            .filter(|allowed| {
+                domain == **allowed || (self.allow_subdomains && domain.is_subdomain_of(allowed))
+            })
+            .max_by_key(|allowed| allowed.as_str().len())
+    }
+
+    /// Checks the RFC5322.From addresses and MAIL FROM address of a submitted message.
+    pub fn check_message(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        mail_from: &str,
+    ) -> SubmissionAction {
+        self.check(message.from.iter().map(String::as_str), mail_from)
+    }
+
+    /// Checks the RFC5322.From addresses and MAIL FROM address of a submission,
+    /// an empty MAIL FROM denotes a null reverse-path.
+    pub fn check<'x>(
+        &self,
+        from: impl IntoIterator<Item = &'x str>,
+        mail_from: &str,
+    ) -> SubmissionAction {
+        // All RFC5322.From addresses have to share a domain
+        let mut from_domain: Option<&str> = None;
+        for address in from {
+            let domain = address.rsplit_once('@').map_or("", |(_, domain)| domain);
+            match from_domain {
+                None => from_domain = Some(domain),
+                Some(from_domain) if !from_domain.eq_ignore_ascii_case(domain) => {
+                    return SubmissionAction::Reject(SubmissionRejection::MultipleFromDomains);
+                }
+                _ => (),
+            }
+        }
+        let from_domain = match from_domain {
+            Some(from_domain) if !from_domain.is_empty() => from_domain,
+            _ => return SubmissionAction::Reject(SubmissionRejection::MissingFrom),
+        };
+
+        // The envelope sender cannot be rewritten by the signer
+        if let Some((_, mail_from_domain)) = mail_from.rsplit_once('@') {
+            if self.allowed_domain(mail_from_domain).is_none() {
+                return SubmissionAction::Reject(SubmissionRejection::MailFromNotAllowed);
+            }
+        }
+
+        match (self.allowed_domain(from_domain), &self.rewrite_domain) {
+            (Some(domain), _) => SubmissionAction::Sign {
+                domain: domain.clone(),
+            },
+            (None, Some(domain)) => SubmissionAction::RewriteFrom {
+                domain: domain.clone(),
+            },
+            (None, None) => SubmissionAction::Reject(SubmissionRejection::FromNotAllowed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{AuthenticatedMessage, Domain};
+
+    use super::{SubmissionAction, SubmissionPolicy, SubmissionRejection};
+
+    #[test]
+    fn submission_policy() {
+        let sign = |domain: &str| SubmissionAction::Sign {
+            domain: Domain::new(domain).unwrap(),
+        };
+        let reject = SubmissionAction::Reject;
+        let policy = SubmissionPolicy::new(["example.org", "Example.NET.", "not a domain"]);
+        let subdomains = policy.clone().with_subdomains(true);
+        let rewrite = policy.clone().with_rewrite_domain("users.example.com");
+
+        for (policy, from, mail_from, expected) in [
+            (
+                &policy,
+                &["jdoe@example.org"][..],
+                "jdoe@example.org",
+                sign("example.org"),
+            ),
+            (&policy, &["jdoe@EXAMPLE.net"], "", sign("example.net")),
+            (
+                &policy,
+                &["jdoe@example.org"],
+                "bounces@example.net",
+                sign("example.org"),
+            ),
+            (
+                &policy,
+                &["jdoe@mail.example.org"],
+                "",
+                reject(SubmissionRejection::FromNotAllowed),
+            ),
+            (
+                &subdomains,
+                &["jdoe@mail.example.org"],
+                "jdoe@mail.example.org",
+                sign("example.org"),
+            ),
+            (
+                &subdomains,
+                &["jdoe@badexample.org"],
+                "",
+                reject(SubmissionRejection::FromNotAllowed),
+            ),
+            (
+                &rewrite,
+                &["ceo@example.com"],
+                "jdoe@example.org",
+                SubmissionAction::RewriteFrom {
+                    domain: Domain::new("users.example.com").unwrap(),
+                },
+            ),
+            (
+                &rewrite,
+                &["jdoe@example.org"],
+                "jdoe@example.com",
+                reject(SubmissionRejection::MailFromNotAllowed),
+            ),
+            (
+                &policy,
+                &["jdoe@example.org", "jane@example.net"],
+                "",
+                reject(SubmissionRejection::MultipleFromDomains),
+            ),
+            (&policy, &[], "", reject(SubmissionRejection::MissingFrom)),
+            (
+                &policy,
+                &["jdoe"],
+                "",
+                reject(SubmissionRejection::MissingFrom),
+            ),
+        ] {
+            assert_eq!(
+                policy.check(from.iter().copied(), mail_from),
+                expected,
+                "{from:?} {mail_from}"
+            );
+        }
+
+        let message = AuthenticatedMessage::parse(
+            b"From: John Doe <jdoe@example.org>\r\nSubject: Hi\r\n\r\nHi!\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            policy.check_message(&message, "jdoe@example.org"),
+            sign("example.org")
+        );
+    }
+}
@@ -8,11 +8,13 @@
  * except according to those terms.
  */
 
-use std::time::SystemTime;
+use std::{collections::HashMap, time::SystemTime};
 
 use crate::{
     common::{
         base32::Base32Writer,
+        crypto::Algorithm,
+        domain::{address_to_ascii, to_ascii},
         headers::Writer,
         verify::{DomainKey, VerifySignature},
     },
@@ -20,8 +22,9 @@ use crate::{
 };
 
 use super::{
-    Atps, DomainKeyReport, Flag, HashAlgorithm, Signature, RR_DNS, RR_EXPIRATION, RR_OTHER,
-    RR_SIGNATURE, RR_VERIFICATION,
+    canonicalize::is_resent_header, Atps, BodyNormalization, Canonicalization, CompatShim,
+    DomainKeyReport, Flag, HashAlgorithm, PolicyDecision, Signature, RR_DNS, RR_EXPIRATION,
+    RR_OTHER, RR_SIGNATURE, RR_VERIFICATION,
 };
 
 impl Resolver {
@@ -31,7 +34,7 @@ impl Resolver {
         &self,
         message: &'x AuthenticatedMessage<'x>,
     ) -> Vec<DkimOutput<'x>> {
-        self.verify_dkim_(
+        self.verify_dkim_at(
             message,
             SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
@@ -41,38 +44,95 @@ impl Resolver {
         .await
     }
 
-    pub(crate) async fn verify_dkim_<'x>(
+    /// Verifies DKIM headers of an RFC5322 message as of a UNIX timestamp,
+    /// such as the time an archived message was received.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(signatures = message.dkim_headers.len(), now)
+        )
+    )]
+    pub async fn verify_dkim_at<'x>(
         &self,
         message: &'x AuthenticatedMessage<'x>,
         now: u64,
     ) -> Vec<DkimOutput<'x>> {
         let mut output = Vec::with_capacity(message.dkim_headers.len());
         let mut report_requested = false;
+        let config = self.config();
 
         // Validate DKIM headers
-        for header in &message.dkim_headers {
+        let mut checked = Vec::with_capacity(message.dkim_headers.len());
+        for (pos, header) in message.dkim_headers.iter().enumerate() {
             // Validate body hash
             let signature = match &header.header {
                 Ok(signature) => {
                     if signature.r {
                         report_requested = true;
                     }
-
-                    if signature.x == 0 || (signature.x > signature.t && signature.x > now) {
-                        signature
-                    } else {
-                        output.push(
-                            DkimOutput::neutral(Error::SignatureExpired).with_signature(signature),
-                        );
-                        continue;
-                    }
+                    signature
                 }
                 Err(err) => {
-                    output.push(DkimOutput::neutral(err.clone()));
+                    checked.push(Err(DkimOutput::neutral(err.clone())));
                     continue;
                 }
             };
 
+            // Limit the number of keys looked up per message
+            if pos >= config.limits().dkim_signatures() {
+                checked.push(Err(
+                    DkimOutput::neutral(Error::TooManySignatures).with_signature(signature)
+                ));
+                continue;
+            }
+            let policy = config.verification_policy(&signature.d);
+
+            // Enforce expiration and maximum age
+            let mut decision = None;
+            if signature.x != 0 && (signature.x <= signature.t || signature.x <= now) {
+                if signature.x > signature.t
+                    && signature
+                        .x
+                        .saturating_add(policy.expiration_grace().as_secs())
+                        > now
+                {
+                    decision = Some(PolicyDecision::ExpirationGrace);
+                } else {
+                    checked.push(Err(
+                        DkimOutput::neutral(Error::SignatureExpired).with_signature(signature)
+                    ));
+                    continue;
+                }
+            }
+            if policy.max_age().is_some_and(|max_age| {
+                signature.t != 0 && now.saturating_sub(signature.t) > max_age.as_secs()
+            }) {
+                checked.push(Err(DkimOutput::neutral(Error::SignatureExpired)
+                    .with_signature(signature)
+                    .with_policy_decision(Some(PolicyDecision::SignatureTooOld))));
+                continue;
+            }
+
+            // Enforce crypto policy
+            if signature.a == Algorithm::RsaSha1
+                && !config.crypto_policy(&signature.d).allows_rsa_sha1()
+            {
+                checked.push(Err(DkimOutput::neutral(Error::UnsupportedAlgorithm)
+                    .with_signature(signature)
+                    .with_policy_decision(Some(PolicyDecision::RsaSha1Rejected))));
+                continue;
+            }
+
+            // Enforce body length policy
+            if let Some(body_length) = policy.body_length_decision(signature.l, message.body_len) {
+                checked.push(Err(DkimOutput::fail(Error::SignatureLength)
+                    .with_signature(signature)
+                    .with_policy_decision(Some(body_length))));
+                continue;
+            }
+
             // Validate body hash
             let ha = HashAlgorithm::from(signature.a);
             let bh = &message
@@ -82,24 +142,79 @@ impl Resolver {
                 .unwrap()
                 .3;
 
+            let mut compat_shims = Vec::new();
             if bh != &signature.bh {
-                output.push(
-                    DkimOutput::neutral(Error::FailedBodyHashMatch).with_signature(signature),
-                );
-                continue;
+                let body = message
+                    .raw_message
+                    .get(message.body_offset..)
+                    .unwrap_or_default();
+                if let Some(shim) = config.compat_shims().iter().find(|shim| {
+                    shim.body_hash(signature, body)
+                        .is_some_and(|bh| bh == signature.bh)
+                }) {
+                    compat_shims.push(*shim);
+                } else {
+                    let mut dkim_output =
+                        DkimOutput::neutral(Error::FailedBodyHashMatch).with_signature(signature);
+                    if config.body_hash_diagnostics() {
+                        if let Some(normalization) = [
+                            BodyNormalization::DotStuffing,
+                            BodyNormalization::TrailingWhitespace,
+                            BodyNormalization::Canonicalization,
+                        ]
+                        .into_iter()
+                        .find(|n| {
+                            n.body_hash(signature, body)
+                                .is_some_and(|bh| bh == signature.bh)
+                        }) {
+                            dkim_output = dkim_output.with_body_normalization(normalization);
+                        }
+                    }
+                    checked.push(Err(dkim_output));
+                    continue;
+                }
             }
 
-            // Obtain ._domainkey TXT record
-            let record = match self.txt_lookup::<DomainKey>(signature.domain_key()).await {
+            checked.push(Ok((header, signature, compat_shims, policy, decision)));
+        }
+
+        // Obtain the ._domainkey TXT records, looking up each distinct record once
+        let mut domain_keys = Vec::new();
+        for (_, signature, ..) in checked.iter().flatten() {
+            let domain_key = signature.domain_key();
+            if !domain_keys.iter().any(|(name, _)| name == &domain_key) {
+                domain_keys.push((domain_key, signature.d.as_str()));
+            }
+        }
+        let records = futures_util::future::join_all(domain_keys.into_iter().map(
+            |(name, domain)| async move {
+                let _permit = self.fairness_permit(domain).await;
+                let record = self.txt_lookup_quorum::<DomainKey>(name.as_str()).await;
+                (name, record)
+            },
+        ))
+        .await
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        for check in checked {
+            let (header, signature, mut compat_shims, policy, decision) = match check {
+                Ok(check) => check,
+                Err(dkim_output) => {
+                    output.push(dkim_output);
+                    continue;
+                }
+            };
+            let record = match &records[&signature.domain_key()] {
                 Ok(record) => record,
                 Err(err) => {
-                    output.push(DkimOutput::dns_error(err).with_signature(signature));
+                    output.push(DkimOutput::dns_error(err.clone()).with_signature(signature));
                     continue;
                 }
             };
 
             // Enforce t=s flag
-            if !signature.validate_auid(&record) {
+            if !signature.validate_auid(record) {
                 output.push(DkimOutput::fail(Error::FailedAuidMatch).with_signature(signature));
                 continue;
             }
@@ -110,7 +225,30 @@ impl Resolver {
 
             // Verify signature
             if let Err(err) = record.verify(&mut headers, signature, signature.ch) {
-                output.push(DkimOutput::fail(err).with_signature(signature));
+                if signature.ch == Canonicalization::Simple
+                    && config.compat_shims().contains(&CompatShim::RelaxedHeaders)
+                    && record
+                        .verify(
+                            &mut message.signed_headers(&signature.h, header.name, &dkim_hdr_value),
+                            signature,
+                            Canonicalization::Relaxed,
+                        )
+                        .is_ok()
+                {
+                    compat_shims.push(CompatShim::RelaxedHeaders);
+                } else {
+                    output.push(DkimOutput::fail(err).with_signature(signature));
+                    continue;
+                }
+            }
+
+            // Enforce minimum key size
+            if let Some(key_size) = policy.rsa_key_decision(signature.a, &signature.b) {
+                output.push(
+                    DkimOutput::perm_err(policy.rsa_key_error())
+                        .with_signature(signature)
+                        .with_policy_decision(Some(key_size)),
+                );
                 continue;
             }
 
@@ -144,13 +282,21 @@ impl Resolver {
                     match self.txt_lookup::<Atps>(query_domain).await {
                         Ok(_) => {
                             // ATPS Verification successful
-                            output.push(DkimOutput::pass().with_atps().with_signature(signature));
+                            output.push(
+                                DkimOutput::pass()
+                                    .with_atps()
+                                    .with_signature(signature)
+                                    .with_compat_shims(compat_shims)
+                                    .with_policy_decision(decision),
+                            );
                         }
                         Err(err) => {
                             output.push(
                                 DkimOutput::dns_error(err)
                                     .with_atps()
-                                    .with_signature(signature),
+                                    .with_signature(signature)
+                                    .with_compat_shims(compat_shims)
+                                    .with_policy_decision(decision),
                             );
                         }
                     }
@@ -159,7 +305,27 @@ impl Resolver {
             }
 
             // Verification successful
-            output.push(DkimOutput::pass().with_signature(signature));
+            output.push(
+                DkimOutput::pass()
+                    .with_signature(signature)
+                    .with_compat_shims(compat_shims)
+                    .with_policy_decision(decision),
+            );
+        }
+
+        // Record how much of the body each signature covers
+        for dkim in &mut output {
+            if let Some(signature) = dkim.signature {
+                dkim.body_bytes_hashed = signature.hashed_body_len(message.body_len).into();
+                dkim.resent_unsigned = signature.has_unsigned_resent_headers(&message.headers);
+            }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                domain = dkim.signature.map(|s| s.d.as_str()),
+                selector = dkim.signature.map(|s| s.s.as_str()),
+                result = %dkim.result,
+                "dkim signature verified"
+            );
         }
 
         // Handle reports
@@ -203,6 +369,7 @@ impl Resolver {
                             | Error::FailedBodyHashMatch
                             | Error::FailedAuidMatch => (record.rr & RR_VERIFICATION) != 0,
                             Error::Base64
+                            | Error::Base64TooLong
                             | Error::UnsupportedVersion
                             | Error::UnsupportedAlgorithm
                             | Error::UnsupportedCanonicalization
@@ -216,13 +383,18 @@ impl Resolver {
                             | Error::RevokedPublicKey => (record.rr & RR_DNS) != 0,
                             Error::MissingParameters
                             | Error::NoHeadersFound
+                            | Error::TooManySignatures
                             | Error::ArcChainTooLong
                             | Error::ArcInvalidInstance(_)
                             | Error::ArcInvalidCV
                             | Error::ArcHasHeaderTag
                             | Error::ArcBrokenChain
                             | Error::SignatureLength
-                            | Error::NotAligned => (record.rr & RR_OTHER) != 0,
+                            | Error::NotAligned
+                            | Error::RecordTooLong
+                            | Error::MultipleRecords
+                            | Error::DeprecatedRecordType
+                            | Error::LocalPolicy => (record.rr & RR_OTHER) != 0,
                         };
 
                         if send_report {
@@ -242,7 +414,7 @@ impl Resolver {
 
 impl<'x> AuthenticatedMessage<'x> {
     pub async fn get_canonicalized_header(&self) -> Result<Vec<u8>, Error> {
-        // Based on verify_dkim_ function
+        // Based on verify_dkim_at function
         // Iterate through possible DKIM headers
         let mut data = Vec::with_capacity(256);
         for header in &self.dkim_headers {
@@ -271,51 +443,99 @@ impl<'x> AuthenticatedMessage<'x> {
         Err(Error::FailedBodyHashMatch)
     }
 
+    /// Returns the headers listed in `h=`, in signing order, followed by the
+    /// signature header itself. Headers are borrowed from the message without
+    /// copying, so the iterator can be fed directly to
+    /// [`Canonicalization::canonicalize_headers`].
     pub fn signed_headers<'z: 'x>(
         &'z self,
         headers: &'x [String],
         dkim_hdr_name: &'x [u8],
         dkim_hdr_value: &'x [u8],
-    ) -> impl Iterator<Item = (&'x [u8], &'x [u8])> {
-        let mut last_header_pos: Vec<(&[u8], usize)> = Vec::new();
+    ) -> SignedHeaders<'x> {
+        SignedHeaders {
+            message_headers: &self.headers,
+            names: headers,
+            pos: 0,
+            signature: Some((dkim_hdr_name, dkim_hdr_value)),
+        }
+    }
+}
+
+/// Iterator over the signed headers of a message, see
+/// [`AuthenticatedMessage::signed_headers`].
+#[derive(Debug, Clone)]
+pub struct SignedHeaders<'x> {
+    message_headers: &'x [(&'x [u8], &'x [u8])],
+    names: &'x [String],
+    pos: usize,
+    signature: Option<(&'x [u8], &'x [u8])>,
+}
+
+impl<'x> Iterator for SignedHeaders<'x> {
+    type Item = (&'x [u8], &'x [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(name) = self.names.get(self.pos) {
+            self.pos += 1;
+
+            // Repeated names select instances from the bottom of the message up,
+            // the n-th occurrence in h= selecting the n-th instance
+            let occurrence = self.names[..self.pos - 1]
+                .iter()
+                .filter(|n| n.eq_ignore_ascii_case(name))
+                .count();
+            if let Some(header) = self
+                .message_headers
+                .iter()
+                .rev()
+                .filter(|(mh, _)| mh.eq_ignore_ascii_case(name.as_bytes()))
+                .nth(occurrence)
+            {
+                return Some(*header);
+            }
+        }
+        self.signature.take()
+    }
+}
+
+impl Signature {
+    pub(crate) fn hashed_body_len(&self, body_len: usize) -> u64 {
+        if self.l == 0 {
+            body_len as u64
+        } else {
+            std::cmp::min(self.l, body_len as u64)
+        }
+    }
+
+    /// Returns `true` if any `Resent-*` header field appears more times in the
+    /// message than in `h=`, meaning at least one instance is not signed.
+    pub(crate) fn has_unsigned_resent_headers(&self, headers: &[(&[u8], &[u8])]) -> bool {
         headers
             .iter()
-            .filter_map(move |h| {
-                let header_pos = if let Some((_, header_pos)) = last_header_pos
-                    .iter_mut()
-                    .find(|(lh, _)| lh.eq_ignore_ascii_case(h.as_bytes()))
-                {
-                    header_pos
-                } else {
-                    last_header_pos.push((h.as_bytes(), 0));
-                    &mut last_header_pos.last_mut().unwrap().1
-                };
-                if let Some((last_pos, result)) = self
-                    .headers
+            .filter(|(name, _)| is_resent_header(name))
+            .any(|(name, _)| {
+                headers
                     .iter()
-                    .rev()
-                    .enumerate()
-                    .skip(*header_pos)
-                    .find(|(_, (mh, _))| h.as_bytes().eq_ignore_ascii_case(mh))
-                {
-                    *header_pos = last_pos + 1;
-                    Some(*result)
-                } else {
-                    *header_pos = self.headers.len();
-                    None
-                }
+                    .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .count()
+                    > self
+                        .h
+                        .iter()
+                        .filter(|h| h.as_bytes().eq_ignore_ascii_case(name))
+                        .count()
             })
-            .chain([(dkim_hdr_name, dkim_hdr_value)])
     }
-}
 
-impl Signature {
     #[allow(clippy::while_let_on_iterator)]
     pub(crate) fn validate_auid(&self, record: &DomainKey) -> bool {
         // Enforce t=s flag
         if !self.i.is_empty() && record.has_flag(Flag::MatchDomain) {
-            let mut auid = self.i.chars();
-            let mut domain = self.d.chars();
+            // Either tag may use U-labels (RFC 8616 section 4)
+            let auid = address_to_ascii(&self.i);
+            let domain = to_ascii(&self.d);
+            let mut auid = auid.chars();
+            let mut domain = domain.chars();
             while let Some(ch) = auid.next() {
                 if ch == '@' {
                     break;
@@ -393,9 +613,13 @@ mod test {
     };
 
     use crate::{
-        common::{parse::TxtRecordParser, verify::DomainKey},
-        dkim::verify::Verifier,
-        AuthenticatedMessage, DkimResult, Resolver,
+        common::{
+            config::{Config, Limits},
+            parse::TxtRecordParser,
+            verify::DomainKey,
+        },
+        dkim::{verify::Verifier, Signature},
+        AuthenticatedMessage, DkimOutput, DkimResult, Error, Resolver,
     };
 
     #[tokio::test]
@@ -417,12 +641,76 @@ mod test {
             let raw_message = raw_message.replace('\n', "\r\n");
             let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
 
-            let dkim = resolver.verify_dkim_(&message, 1667843664).await;
+            let dkim = resolver.verify_dkim_at(&message, 1667843664).await;
 
             assert_eq!(dkim.last().unwrap().result(), &DkimResult::Pass);
         }
     }
 
+    #[tokio::test]
+    async fn dkim_verify_at() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("dkim");
+        test_file.push("005.txt");
+
+        // Signed at t=1667843664 with x=1667930064
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = new_resolver(dns_records);
+        let raw_message = raw_message.replace('\n', "\r\n");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+
+        for (now, expected) in [
+            (1667843664, DkimResult::Pass),
+            (1667930063, DkimResult::Pass),
+            (1667930064, DkimResult::Neutral(Error::SignatureExpired)),
+        ] {
+            let dkim = resolver.verify_dkim_at(&message, now).await;
+            assert_eq!(dkim.last().unwrap().result(), &expected, "{now}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dkim_verify_multiple() {
+        let mut test_file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_file.push("resources");
+        test_file.push("dkim");
+        test_file.push("005.txt");
+
+        let test = String::from_utf8(fs::read(&test_file).unwrap()).unwrap();
+        let (dns_records, raw_message) = test.split_once("\n\n").unwrap();
+        let resolver = new_resolver(dns_records);
+        let (signature, raw_message) = raw_message.split_at(raw_message.find("From:").unwrap());
+
+        // Signatures sharing a key and signatures with missing keys are verified independently
+        let raw_message = format!(
+            "{signature}{}{signature}{raw_message}",
+            signature.replace("s=sysmsg-1", "s=sysmsg-2")
+        )
+        .replace('\n', "\r\n");
+        let message = AuthenticatedMessage::parse(raw_message.as_bytes()).unwrap();
+        let dkim = resolver.verify_dkim_at(&message, 1667843664).await;
+        assert_eq!(
+            dkim.iter()
+                .map(|output| (
+                    output.signature().unwrap().s.as_str(),
+                    output.result() == &DkimResult::Pass
+                ))
+                .collect::<Vec<_>>(),
+            vec![("sysmsg-1", true), ("sysmsg-2", false), ("sysmsg-1", true)]
+        );
+
+        // Signatures over the limit are not verified
+        resolver.set_config(Config::new().with_limits(Limits::default().with_dkim_signatures(2)));
+        let dkim = resolver.verify_dkim_at(&message, 1667843664).await;
+        assert_eq!(dkim[0].result(), &DkimResult::Pass);
+        assert_eq!(
+            dkim[2].result(),
+            &DkimResult::Neutral(Error::TooManySignatures)
+        );
+    }
+
     #[test]
     fn dkim_strip_signature() {
         for (value, stripped_value) in [
@@ -438,6 +726,80 @@ mod test {
         }
     }
 
+    #[test]
+    fn dkim_signed_headers() {
+        let message = AuthenticatedMessage::parse(
+            concat!(
+                "Received: 1\r\n",
+                "From: a\r\n",
+                "Received: 2\r\n",
+                "subject: b\r\n",
+                "Received: 3\r\n",
+                "\r\n",
+                "Hi!\r\n"
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let names = [
+            "Received", "From", "received", "To", "Subject", "From", "RECEIVED", "Received",
+            "Received",
+        ]
+        .iter()
+        .map(|h| h.to_string())
+        .collect::<Vec<_>>();
+        let headers = message.signed_headers(&names, b"DKIM-Signature", b"v=1");
+
+        assert_eq!(
+            headers
+                .clone()
+                .map(|(name, value)| format!(
+                    "{}:{}",
+                    std::str::from_utf8(name).unwrap(),
+                    std::str::from_utf8(value).unwrap().trim()
+                ))
+                .collect::<Vec<_>>(),
+            [
+                "Received:3",
+                "From:a",
+                "Received:2",
+                "subject:b",
+                "Received:1",
+                "DKIM-Signature:v=1"
+            ]
+        );
+        assert_eq!(headers.count(), 6);
+    }
+
+    #[test]
+    fn dkim_unsigned_critical_headers() {
+        let signature = |h: &str| {
+            Signature::parse(
+                format!("v=1; a=ed25519-sha256; d=example.org; s=ed; h={h}; bh=AA==; b=AA==")
+                    .as_bytes(),
+            )
+            .unwrap()
+        };
+        let full = signature("from:to:subject:date:reply-to:content-type:message-id");
+        let partial = signature("From:To:Date");
+
+        let output = DkimOutput::pass().with_signature(&full);
+        assert!(output.covers_critical_headers());
+        assert!(output.unsigned_critical_headers().is_empty());
+
+        let output = DkimOutput::pass().with_signature(&partial);
+        assert!(!output.covers_critical_headers());
+        assert_eq!(
+            output.unsigned_critical_headers(),
+            ["Subject", "Reply-To", "Content-Type"]
+        );
+
+        // Only passing signatures are analyzed
+        let output = DkimOutput::fail(Error::FailedVerification).with_signature(&partial);
+        assert!(!output.covers_critical_headers());
+        assert!(output.unsigned_critical_headers().is_empty());
+    }
+
     fn new_resolver(dns_records: &str) -> Resolver {
         let resolver = Resolver::new_system_conf().unwrap();
         for (key, value) in dns_records
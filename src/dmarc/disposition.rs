@@ -0,0 +1,340 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::fmt::Write;
+
+use crate::{
+    is_within_pct,
+    report::{ActionDisposition, PolicyOverride, PolicyOverrideReason, Record},
+    AuthenticationResults, DmarcOutput, DmarcResult,
+};
+
+use super::{Policy, Report, URI};
+
+/// Decision of the DMARC stage for a message, meant to be wired as a single
+/// object into the routing and quarantine logic of an MTA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DmarcDisposition {
+    domain: String,
+    policy: Policy,
+    disposition: ActionDisposition,
+    reasons: Vec<PolicyOverrideReason>,
+    rua: Vec<URI>,
+    ruf: Vec<URI>,
+    failure_report: Option<Report>,
+}
+
+impl DmarcOutput {
+    /// Returns the disposition to apply to the message, sampling failing
+    /// messages according to the `pct=` tag of the record.
+    pub fn disposition(&self) -> DmarcDisposition {
+        self.disposition_with_sample(
+            self.record
+                .as_ref()
+                .is_none_or(|record| is_within_pct(record.pct)),
+        )
+    }
+
+    /// Returns the disposition to apply to the message, using the outcome of
+    /// the caller's own `pct=` sampling. Messages outside the sample, or
+    /// published under a testing policy (`t=y`), receive the next less strict
+    /// disposition (RFC 7489 section 6.6.4). A local override forces the
+    /// message to pass or applies the published policy without sampling.
+    pub fn disposition_with_sample(&self, in_sample: bool) -> DmarcDisposition {
+        let mut reasons = Vec::new();
+        let passed = self.dkim_result == DmarcResult::Pass || self.spf_result == DmarcResult::Pass;
+        // Overrides agreeing with the evaluated outcome are not recorded
+        let disposition = if let Some(local_override) = self
+            .local_override
+            .as_ref()
+            .filter(|local_override| local_override.is_pass() != passed)
+        {
+            reasons.push(local_override.reason());
+            match (passed, self.policy) {
+                (true, Policy::Quarantine) => ActionDisposition::Quarantine,
+                (true, Policy::Reject) => ActionDisposition::Reject,
+                _ => ActionDisposition::None,
+            }
+        } else if passed {
+            ActionDisposition::Pass
+        } else if let (Some(sealer), Policy::Quarantine | Policy::Reject) =
+            (&self.arc_override, self.policy)
+        {
+            reasons.push(
+                PolicyOverrideReason::new(PolicyOverride::LocalPolicy)
+                    .with_comment(format!("arc-override as.d={sealer}")),
+            );
+            ActionDisposition::None
+        } else {
+            let disposition = match self.policy {
+                Policy::Quarantine => ActionDisposition::Quarantine,
+                Policy::Reject => ActionDisposition::Reject,
+                Policy::None | Policy::Unspecified => ActionDisposition::None,
+            };
+            let testing = self.record.as_ref().is_some_and(|record| record.t);
+            if disposition != ActionDisposition::None && (!in_sample || testing) {
+                reasons.push(
+                    PolicyOverrideReason::new(PolicyOverride::SampledOut).with_comment(
+                        if testing {
+                            "testing policy t=y"
+                        } else {
+                            "outside pct= sample"
+                        },
+                    ),
+                );
+                if disposition == ActionDisposition::Reject {
+                    ActionDisposition::Quarantine
+                } else {
+                    ActionDisposition::None
+                }
+            } else {
+                disposition
+            }
+        };
+
+        let failure_report = self.failure_report();
+        DmarcDisposition {
+            domain: self.domain.clone(),
+            policy: self.policy,
+            disposition,
+            reasons,
+            rua: self
+                .record
+                .as_ref()
+                .map(|record| record.rua.clone())
+                .unwrap_or_default(),
+            ruf: match (&self.record, &failure_report) {
+                (Some(record), Some(_)) => record.ruf.clone(),
+                _ => Vec::new(),
+            },
+            failure_report,
+        }
+    }
+}
+
+impl DmarcDisposition {
+    pub(crate) fn with_reason(mut self, reason: PolicyOverrideReason) -> Self {
+        self.reasons.push(reason);
+        self
+    }
+
+    /// RFC5322.From domain the policy was published for.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Published policy that applied to the message.
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    /// Disposition to apply to the message, after sampling and local overrides.
+    pub fn disposition(&self) -> ActionDisposition {
+        self.disposition
+    }
+
+    /// Reasons the disposition differs from the published policy.
+    pub fn reasons(&self) -> &[PolicyOverrideReason] {
+        &self.reasons
+    }
+
+    /// Returns `true` if the message should be quarantined.
+    pub fn is_quarantine(&self) -> bool {
+        self.disposition == ActionDisposition::Quarantine
+    }
+
+    /// Returns `true` if the message should be rejected.
+    pub fn is_reject(&self) -> bool {
+        self.disposition == ActionDisposition::Reject
+    }
+
+    /// Returns `true` if the message must be included in the aggregate reports
+    /// sent to the `rua=` addresses.
+    pub fn aggregate_report(&self) -> bool {
+        !self.rua.is_empty()
+    }
+
+    /// Aggregate report addresses of the policy.
+    pub fn rua(&self) -> &[URI] {
+        &self.rua
+    }
+
+    /// Failure reporting options, if a failure report should be sent.
+    pub fn failure_report(&self) -> Option<&Report> {
+        self.failure_report.as_ref()
+    }
+
+    /// Failure report addresses, empty unless a failure report should be sent.
+    pub fn ruf(&self) -> &[URI] {
+        &self.ruf
+    }
+
+    /// Human-readable description of the decision, suitable as quarantine
+    /// metadata.
+    pub fn reason(&self) -> String {
+        let mut reason = match self.disposition {
+            ActionDisposition::Pass => format!("DMARC passed for {}", self.domain),
+            _ => format!(
+                "DMARC failed for {} with policy {}, disposition {}",
+                self.domain, self.policy, self.disposition
+            ),
+        };
+        for override_reason in &self.reasons {
+            write!(reason, "; {}", override_reason.policy_override()).ok();
+            if let Some(comment) = override_reason.comment() {
+                write!(reason, " ({comment})").ok();
+            }
+        }
+        reason
+    }
+
+    /// Comment for the `dmarc` method of an Authentication-Results header,
+    /// such as `dmarc-policy=reject disposition=quarantine reason=sampled_out`.
+    pub fn comment(&self) -> String {
+        let mut comment = format!(
+            "dmarc-policy={} disposition={}",
+            self.policy, self.disposition
+        );
+        for reason in &self.reasons {
+            write!(comment, " reason={}", reason.policy_override()).ok();
+        }
+        comment
+    }
+}
+
+impl<'x> AuthenticationResults<'x> {
+    /// Adds the DMARC result followed by the comment of its disposition.
+    pub fn with_dmarc_disposition(
+        mut self,
+        dmarc: &DmarcOutput,
+        disposition: &DmarcDisposition,
+    ) -> Self {
+        self = self.with_dmarc_result(dmarc);
+        write!(self.auth_results, " ({})", disposition.comment()).ok();
+        self
+    }
+}
+
+impl Record {
+    /// Sets the evaluated disposition and override reasons of a record.
+    pub fn with_dmarc_disposition(mut self, disposition: &DmarcDisposition) -> Self {
+        self = self.with_action_disposition(disposition.disposition);
+        for reason in &disposition.reasons {
+            self = self.with_policy_override_reason(reason.clone());
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::parse::TxtRecordParser,
+        dmarc::{Dmarc, Policy, Report, URI},
+        report::{ActionDisposition, PolicyOverride, Record},
+        AuthenticationResults, DmarcOutput, DmarcResult, Error,
+    };
+
+    #[test]
+    fn dmarc_disposition() {
+        let output = |record: &str, policy: Policy, dkim_result: DmarcResult| {
+            DmarcOutput {
+                spf_result: DmarcResult::Fail(Error::NotAligned),
+                dkim_result,
+                policy,
+                ..DmarcOutput::default().with_domain("example.org")
+            }
+            .with_record(Dmarc::parse(record.as_bytes()).unwrap().into())
+        };
+        let record = "v=DMARC1; p=reject; rua=mailto:agg@example.org; ruf=mailto:ruf@example.org";
+        let fail = DmarcResult::Fail(Error::NotAligned);
+
+        // Passing messages
+        let disposition = output(record, Policy::Reject, DmarcResult::Pass).disposition();
+        assert_eq!(disposition.disposition(), ActionDisposition::Pass);
+        assert!(disposition.aggregate_report());
+        assert_eq!(disposition.failure_report(), None);
+        assert!(disposition.ruf().is_empty());
+        assert_eq!(disposition.reason(), "DMARC passed for example.org");
+
+        // Failing messages within the sample
+        let disposition = output(record, Policy::Reject, fail.clone()).disposition();
+        assert!(disposition.is_reject());
+        assert_eq!(disposition.reasons(), &[]);
+        assert_eq!(disposition.rua(), &[URI::new("agg@example.org", 0)]);
+        assert_eq!(disposition.ruf(), &[URI::new("ruf@example.org", 0)]);
+        assert_eq!(disposition.failure_report(), Some(&Report::All));
+        assert_eq!(
+            disposition.comment(),
+            "dmarc-policy=reject disposition=reject"
+        );
+
+        // Sampled out or testing policies use the next less strict disposition
+        let disposition =
+            output(&format!("{record}; pct=0"), Policy::Reject, fail.clone()).disposition();
+        assert!(disposition.is_quarantine());
+        assert_eq!(
+            disposition.reasons()[0].policy_override(),
+            PolicyOverride::SampledOut
+        );
+        assert_eq!(
+            disposition.reason(),
+            concat!(
+                "DMARC failed for example.org with policy reject, ",
+                "disposition quarantine; sampled_out (outside pct= sample)"
+            )
+        );
+        let disposition = output(
+            "v=DMARC1; p=quarantine; t=y",
+            Policy::Quarantine,
+            fail.clone(),
+        )
+        .disposition_with_sample(true);
+        assert_eq!(disposition.disposition(), ActionDisposition::None);
+        assert_eq!(
+            disposition.comment(),
+            "dmarc-policy=quarantine disposition=none reason=sampled_out"
+        );
+        assert!(!disposition.aggregate_report());
+        let disposition =
+            output("v=DMARC1; p=none", Policy::None, fail.clone()).disposition_with_sample(false);
+        assert_eq!(disposition.disposition(), ActionDisposition::None);
+        assert_eq!(disposition.reasons(), &[]);
+
+        // Local overrides from trusted ARC sealers
+        let dmarc = DmarcOutput {
+            arc_override: Some("lists.example.net".to_string()),
+            ..output(record, Policy::Reject, fail)
+        };
+        let disposition = dmarc.disposition();
+        assert_eq!(disposition.disposition(), ActionDisposition::None);
+        assert_eq!(
+            disposition.reasons()[0].comment(),
+            Some("arc-override as.d=lists.example.net")
+        );
+        let record = Record::new().with_dmarc_disposition(&disposition);
+        assert_eq!(record.action_disposition(), ActionDisposition::None);
+        assert_eq!(record.policy_override_reason(), disposition.reasons());
+        assert_eq!(
+            AuthenticationResults::new("mx.example.org")
+                .with_dmarc_disposition(&dmarc, &disposition)
+                .auth_results
+                .rsplit_once(';')
+                .unwrap()
+                .1
+                .trim(),
+            concat!(
+                "dmarc=fail (policy not aligned) header.from=example.org policy.dmarc=reject ",
+                "(dmarc-policy=reject disposition=none reason=local_policy)"
+            )
+        );
+    }
+}
@@ -0,0 +1,227 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use mail_parser::{parsers::MessageStream, HeaderValue};
+
+use crate::{
+    report::{PolicyOverride, PolicyOverrideReason},
+    AuthenticatedMessage,
+};
+
+/// Mailing list indicators found in the headers of a message, used to decide
+/// on local policy overrides for lists that break DMARC (RFC 7960).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MailingList {
+    list_id: Option<String>,
+    list_post: bool,
+    list_unsubscribe: bool,
+    precedence_list: bool,
+    subject_tag: Option<String>,
+}
+
+impl MailingList {
+    /// Identifier of the `List-Id` header (RFC 2919), without angle brackets.
+    pub fn list_id(&self) -> Option<&str> {
+        self.list_id.as_deref()
+    }
+
+    /// Returns `true` if the message has a `List-Post` header (RFC 2369).
+    pub fn has_list_post(&self) -> bool {
+        self.list_post
+    }
+
+    /// Returns `true` if the message has a `List-Unsubscribe` header (RFC 2369).
+    pub fn has_list_unsubscribe(&self) -> bool {
+        self.list_unsubscribe
+    }
+
+    /// Returns `true` if the message has a `Precedence: list` header.
+    pub fn is_precedence_list(&self) -> bool {
+        self.precedence_list
+    }
+
+    /// Bracketed tag prepended to the subject by the list, such as `[users]`.
+    pub fn subject_tag(&self) -> Option<&str> {
+        self.subject_tag.as_deref()
+    }
+
+    /// Returns the confidence, from 0 to 100, that the message was sent
+    /// through a mailing list.
+    pub fn confidence(&self) -> u8 {
+        let mut score = 0;
+        if self.list_id.is_some() {
+            score += 50;
+        }
+        if self.list_post {
+            score += 25;
+        }
+        if self.precedence_list {
+            score += 15;
+        }
+        if self.subject_tag.is_some() {
+            score += 10;
+        }
+        // Bulk senders use List-Unsubscribe too, so it only adds weight
+        // to other list indicators
+        if self.list_unsubscribe && score > 0 {
+            score += 10;
+        }
+        std::cmp::min(score, 100)
+    }
+
+    /// Returns `true` if the confidence is at least 50.
+    pub fn is_mailing_list(&self) -> bool {
+        self.confidence() >= 50
+    }
+
+    /// Returns a `mailing_list` policy override reason for aggregate reports,
+    /// if the message looks like it was sent through a mailing list.
+    pub fn override_reason(&self) -> Option<PolicyOverrideReason> {
+        if self.is_mailing_list() {
+            let reason = PolicyOverrideReason::new(PolicyOverride::MailingList);
+            Some(match &self.list_id {
+                Some(list_id) => reason.with_comment(format!("list-id={list_id}")),
+                None => reason,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'x> AuthenticatedMessage<'x> {
+    /// Looks for mailing list indicators in the message headers.
+    pub fn mailing_list(&self) -> MailingList {
+        let mut list = MailingList::default();
+
+        for (name, value) in &self.headers {
+            if name.eq_ignore_ascii_case(b"List-Id") {
+                list.list_id = parse_list_id(value);
+            } else if name.eq_ignore_ascii_case(b"List-Post") {
+                list.list_post = true;
+            } else if name.eq_ignore_ascii_case(b"List-Unsubscribe") {
+                list.list_unsubscribe = true;
+            } else if name.eq_ignore_ascii_case(b"Precedence") {
+                list.precedence_list = std::str::from_utf8(value)
+                    .is_ok_and(|value| value.trim().eq_ignore_ascii_case("list"));
+            } else if name.eq_ignore_ascii_case(b"Subject") {
+                if let HeaderValue::Text(subject) = MessageStream::new(value).parse_unstructured() {
+                    list.subject_tag = parse_subject_tag(&subject);
+                }
+            }
+        }
+
+        list
+    }
+}
+
+fn parse_list_id(value: &[u8]) -> Option<String> {
+    let value = std::str::from_utf8(value).ok()?;
+    let list_id = match value.rsplit_once('<') {
+        Some((_, list_id)) => list_id.split_once('>')?.0,
+        None => value,
+    }
+    .trim();
+
+    if !list_id.is_empty() && list_id.contains('.') && !list_id.contains(char::is_whitespace) {
+        Some(list_id.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+fn parse_subject_tag(subject: &str) -> Option<String> {
+    // Skip reply and forward prefixes added after the tag
+    let mut subject = subject.trim_start();
+    while let Some((prefix, rest)) = subject.split_once(':') {
+        if ["re", "fw", "fwd", "aw", "sv"]
+            .iter()
+            .any(|p| prefix.trim().eq_ignore_ascii_case(p))
+        {
+            subject = rest.trim_start();
+        } else {
+            break;
+        }
+    }
+
+    let (tag, _) = subject.strip_prefix('[')?.split_once(']')?;
+    let tag = tag.trim();
+    if !tag.is_empty() && tag.len() <= 64 {
+        Some(tag.to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        report::{PolicyOverride, PolicyOverrideReason},
+        AuthenticatedMessage,
+    };
+
+    #[test]
+    fn mailing_list_heuristics() {
+        for (headers, list_id, subject_tag, confidence) in [
+            (
+                concat!(
+                    "List-Id: Users of Example <users.lists.example.org>\r\n",
+                    "List-Post: <mailto:users@lists.example.org>\r\n",
+                    "List-Unsubscribe: <mailto:users-leave@lists.example.org>\r\n",
+                    "Precedence: list\r\n",
+                    "Subject: Re: [users] Release notes\r\n",
+                ),
+                Some("users.lists.example.org"),
+                Some("users"),
+                100,
+            ),
+            (
+                "List-Id: <Dev.Example.ORG>\r\nSubject: =?utf-8?q?=5Bdev=5D_Hi?=\r\n",
+                Some("dev.example.org"),
+                Some("dev"),
+                60,
+            ),
+            (
+                "Precedence: List\r\nSubject: [announce] New release\r\n",
+                None,
+                Some("announce"),
+                25,
+            ),
+            (
+                "List-Unsubscribe: <https://example.com/unsubscribe>\r\nPrecedence: bulk\r\n",
+                None,
+                None,
+                0,
+            ),
+            (
+                "List-Id: not a list id\r\nSubject: Re: []\r\n",
+                None,
+                None,
+                0,
+            ),
+        ] {
+            let message = format!("From: jdoe@example.org\r\n{headers}\r\nHi!\r\n");
+            let list = AuthenticatedMessage::parse(message.as_bytes())
+                .unwrap()
+                .mailing_list();
+            assert_eq!(list.list_id(), list_id, "{headers}");
+            assert_eq!(list.subject_tag(), subject_tag, "{headers}");
+            assert_eq!(list.confidence(), confidence, "{headers}");
+            assert_eq!(
+                list.override_reason(),
+                (confidence >= 50).then(|| {
+                    PolicyOverrideReason::new(PolicyOverride::MailingList)
+                        .with_comment(format!("list-id={}", list_id.unwrap()))
+                }),
+                "{headers}"
+            );
+        }
+    }
+}
@@ -8,16 +8,22 @@
  * except according to those terms.
  */
 
-use std::{fmt::Display, sync::Arc};
+use std::{borrow::Cow, fmt::Display, sync::Arc};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{DmarcOutput, DmarcResult, Error, Version};
+use crate::{
+    common::{domain::to_unicode, hooks::LocalOverride},
+    DmarcOutput, DmarcResult, Error, Version,
+};
 
+pub mod disposition;
+pub mod mailing_list;
 pub mod parse;
 pub mod verify;
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Dmarc {
     pub v: Version,
     pub adkim: Alignment,
@@ -43,12 +49,14 @@ pub struct URI {
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Alignment {
     Relaxed,
     Strict,
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Psd {
     Yes,
     No,
@@ -56,6 +64,7 @@ pub enum Psd {
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Report {
     All,
     Any,
@@ -65,6 +74,7 @@ pub enum Report {
 }
 
 #[derive(Debug, Hash, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Policy {
     None,
     Quarantine,
@@ -72,6 +82,16 @@ pub enum Policy {
     Unspecified,
 }
 
+#[derive(Debug, Hash, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PolicyVersion {
+    /// RFC 7489 policy discovery and alignment.
+    #[default]
+    Rfc7489,
+    /// DMARCbis tree walk, np= and psd= tags.
+    DmarcBis,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u8)]
 pub(crate) enum Format {
@@ -120,6 +140,8 @@ impl Default for DmarcOutput {
             record: None,
             spf_result: DmarcResult::None,
             dkim_result: DmarcResult::None,
+            arc_override: None,
+            local_override: None,
         }
     }
 }
@@ -145,10 +167,20 @@ impl DmarcOutput {
         self
     }
 
+    pub(crate) fn with_local_override(mut self, local_override: LocalOverride) -> Self {
+        self.local_override = local_override.into();
+        self
+    }
+
     pub fn domain(&self) -> &str {
         &self.domain
     }
 
+    /// Returns the domain with its A-labels converted to U-labels, for display.
+    pub fn domain_unicode(&self) -> Cow<'_, str> {
+        to_unicode(&self.domain)
+    }
+
     pub fn into_domain(self) -> String {
         self.domain
     }
@@ -165,6 +197,18 @@ impl DmarcOutput {
         &self.spf_result
     }
 
+    /// Returns the trusted ARC sealer whose chain overrides a failed DMARC
+    /// evaluation under local policy, if any.
+    pub fn arc_override(&self) -> Option<&str> {
+        self.arc_override.as_deref()
+    }
+
+    /// Returns the override of the disposition returned by a `PolicyHooks`
+    /// callback, if any.
+    pub fn local_override(&self) -> Option<&LocalOverride> {
+        self.local_override.as_ref()
+    }
+
     pub fn dmarc_record(&self) -> Option<&Dmarc> {
         self.record.as_deref()
     }
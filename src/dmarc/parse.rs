@@ -20,6 +20,8 @@ use crate::{
 use super::{Alignment, Dmarc, Format, Policy, Psd, Report, URI};
 
 impl TxtRecordParser for Dmarc {
+    const UNIQUE: bool = true;
+
     fn parse(bytes: &[u8]) -> crate::Result<Self> {
         let mut record = bytes.iter();
         if record.key().unwrap_or(0) != V
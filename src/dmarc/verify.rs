@@ -11,11 +11,11 @@
 use std::sync::Arc;
 
 use crate::{
-    AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error, Resolver,
-    SpfOutput, SpfResult,
+    ArcOutput, AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Domain,
+    Error, Resolver, SpfOutput, SpfResult,
 };
 
-use super::{Alignment, Dmarc, URI};
+use super::{Alignment, Dmarc, PolicyVersion, Psd, URI};
 
 impl Resolver {
     /// Verifies the DMARC policy of an RFC5322.From domain
@@ -25,6 +25,30 @@ impl Resolver {
         dkim_output: &[DkimOutput<'_>],
         mail_from_domain: &str,
         spf_output: &SpfOutput,
+    ) -> DmarcOutput {
+        let from_domain = message
+            .from
+            .first()
+            .and_then(|from| from.rsplit_once('@'))
+            .map_or("", |(_, domain)| domain);
+        let version = self.config().dmarc_version(from_domain);
+        self.verify_dmarc_with_version(message, dkim_output, mail_from_domain, spf_output, version)
+            .await
+    }
+
+    /// Verifies the DMARC policy of an RFC5322.From domain using the specified
+    /// policy discovery and alignment rules
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(?version))
+    )]
+    pub async fn verify_dmarc_with_version(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        dkim_output: &[DkimOutput<'_>],
+        mail_from_domain: &str,
+        spf_output: &SpfOutput,
+        version: PolicyVersion,
     ) -> DmarcOutput {
         // Extract RFC5322.From
         let mut from_domain = "";
@@ -32,7 +56,7 @@ impl Resolver {
             if let Some((_, domain)) = from.rsplit_once('@') {
                 if from_domain.is_empty() {
                     from_domain = domain;
-                } else if from_domain != domain {
+                } else if !from_domain.eq_ignore_ascii_case(domain) {
                     // Multi-valued RFC5322.From header fields with multiple
                     // domains MUST be exempt from DMARC checking.
                     return DmarcOutput::default();
@@ -42,7 +66,38 @@ impl Resolver {
         if from_domain.is_empty() {
             return DmarcOutput::default();
         }
+        let from_domain = Domain::normalize(from_domain);
+        let mail_from_domain = Domain::normalize(mail_from_domain);
+
+        let _permit = self.fairness_permit(&from_domain).await;
+        let output = match version {
+            PolicyVersion::Rfc7489 => {
+                self.verify_dmarc_rfc7489(&from_domain, dkim_output, &mail_from_domain, spf_output)
+                    .await
+            }
+            PolicyVersion::DmarcBis => {
+                self.verify_dmarc_bis(&from_domain, dkim_output, &mail_from_domain, spf_output)
+                    .await
+            }
+        };
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            domain = %output.domain,
+            policy = ?output.policy,
+            spf = %output.spf_result,
+            dkim = %output.dkim_result,
+            "dmarc evaluated"
+        );
+        output
+    }
 
+    async fn verify_dmarc_rfc7489(
+        &self,
+        from_domain: &str,
+        dkim_output: &[DkimOutput<'_>],
+        mail_from_domain: &str,
+        spf_output: &SpfOutput,
+    ) -> DmarcOutput {
         // Obtain DMARC policy
         let dmarc = match self.dmarc_tree_walk(from_domain).await {
             Ok(Some(dmarc)) => dmarc,
@@ -62,6 +117,8 @@ impl Resolver {
             domain: from_domain.to_string(),
             policy: dmarc.p,
             record: None,
+            arc_override: None,
+            local_override: None,
         };
 
         let has_dkim_pass = dkim_output.iter().any(|o| o.result == DkimResult::Pass);
@@ -84,30 +141,25 @@ impl Resolver {
 
             // Check DKIM alignment
             if has_dkim_pass {
-                output.dkim_result = if dkim_output.iter().any(|o| {
-                    o.result == DkimResult::Pass && o.signature.as_ref().unwrap().d.eq(from_domain)
-                }) {
-                    DmarcResult::Pass
-                } else if dmarc.adkim == Alignment::Relaxed
-                    && dkim_output.iter().any(|o| {
-                        o.result == DkimResult::Pass
-                            && (o.signature.as_ref().unwrap().d.ends_with(&from_subdomain)
-                                || from_domain
-                                    .ends_with(&format!(".{}", o.signature.as_ref().unwrap().d)))
-                    })
-                {
-                    output.policy = dmarc.sp;
+                let dkim_domains = dkim_output
+                    .iter()
+                    .filter(|o| o.result == DkimResult::Pass)
+                    .filter_map(|o| o.signature.map(|signature| Domain::normalize(&signature.d)))
+                    .collect::<Vec<_>>();
+                output.dkim_result = if dkim_domains.iter().any(|d| d == from_domain) {
                     DmarcResult::Pass
                 } else {
-                    if dkim_output.iter().any(|o| {
-                        o.result == DkimResult::Pass
-                            && (o.signature.as_ref().unwrap().d.ends_with(&from_subdomain)
-                                || from_domain
-                                    .ends_with(&format!(".{}", o.signature.as_ref().unwrap().d)))
-                    }) {
+                    let is_relaxed_aligned = dkim_domains.iter().any(|d| {
+                        d.ends_with(&from_subdomain) || from_domain.ends_with(&format!(".{d}"))
+                    });
+                    if is_relaxed_aligned {
                         output.policy = dmarc.sp;
                     }
-                    DmarcResult::Fail(Error::NotAligned)
+                    if is_relaxed_aligned && dmarc.adkim == Alignment::Relaxed {
+                        DmarcResult::Pass
+                    } else {
+                        DmarcResult::Fail(Error::NotAligned)
+                    }
                 };
             }
         }
@@ -115,6 +167,138 @@ impl Resolver {
         output.with_record(dmarc)
     }
 
+    async fn verify_dmarc_bis(
+        &self,
+        from_domain: &str,
+        dkim_output: &[DkimOutput<'_>],
+        mail_from_domain: &str,
+        spf_output: &SpfOutput,
+    ) -> DmarcOutput {
+        // Obtain DMARC policy and Organizational Domain
+        let records = match self.dmarc_tree_walk_all(from_domain).await {
+            Ok(records) => records,
+            Err(err) => {
+                let err = DmarcResult::from(err);
+                return DmarcOutput::default()
+                    .with_domain(from_domain)
+                    .with_dkim_result(err.clone())
+                    .with_spf_result(err);
+            }
+        };
+        let (policy_domain, dmarc) = match records.first() {
+            Some((policy_domain, dmarc)) => (policy_domain.as_str(), dmarc.clone()),
+            None => return DmarcOutput::default().with_domain(from_domain),
+        };
+        let from_org_domain = organizational_domain(from_domain, &records);
+
+        // The np= policy applies to non-existent subdomains
+        let policy = if policy_domain == from_domain {
+            dmarc.p
+        } else if self.dmarc_domain_exists(from_domain).await {
+            dmarc.sp
+        } else {
+            dmarc.np
+        };
+
+        let mut output = DmarcOutput {
+            spf_result: DmarcResult::None,
+            dkim_result: DmarcResult::None,
+            domain: from_domain.to_string(),
+            policy,
+            record: None,
+            arc_override: None,
+            local_override: None,
+        };
+
+        // Check SPF alignment
+        if spf_output.result == SpfResult::Pass {
+            output.spf_result = if self
+                .dmarc_is_aligned(mail_from_domain, from_domain, from_org_domain, &dmarc.aspf)
+                .await
+            {
+                DmarcResult::Pass
+            } else {
+                DmarcResult::Fail(Error::NotAligned)
+            };
+        }
+
+        // Check DKIM alignment
+        for o in dkim_output {
+            if o.result == DkimResult::Pass {
+                if let Some(signature) = o.signature {
+                    if self
+                        .dmarc_is_aligned(&signature.d, from_domain, from_org_domain, &dmarc.adkim)
+                        .await
+                    {
+                        output.dkim_result = DmarcResult::Pass;
+                        break;
+                    }
+                }
+                output.dkim_result = DmarcResult::Fail(Error::NotAligned);
+            }
+        }
+
+        output.with_record(dmarc)
+    }
+
+    /// Records a local policy override on a failed DMARC evaluation when the
+    /// ARC chain passed and was last sealed by a trusted sealer.
+    pub fn verify_dmarc_arc_override(
+        &self,
+        dmarc_output: DmarcOutput,
+        arc_output: &ArcOutput<'_>,
+    ) -> DmarcOutput {
+        if dmarc_output.record.is_some()
+            && dmarc_output.spf_result != DmarcResult::Pass
+            && dmarc_output.dkim_result != DmarcResult::Pass
+            && self.config().is_trusted_arc(arc_output)
+        {
+            DmarcOutput {
+                arc_override: arc_output.sealers().last().map(|d| d.to_string()),
+                ..dmarc_output
+            }
+        } else {
+            dmarc_output
+        }
+    }
+
+    /// Returns the Organizational Domain of a domain using the DMARCbis tree walk
+    pub async fn dmarc_organizational_domain(&self, domain: &str) -> crate::Result<String> {
+        self.dmarc_tree_walk_all(domain)
+            .await
+            .map(|records| organizational_domain(domain, &records).to_string())
+    }
+
+    /// Returns `false` if the domain has no A, AAAA or MX records (RFC 9091 section 2.1).
+    /// Lookup errors are treated as the domain existing.
+    async fn dmarc_domain_exists(&self, domain: &str) -> bool {
+        match self.exists(domain).await {
+            Ok(false) => !matches!(
+                self.mx_lookup(domain).await,
+                Err(Error::DnsRecordNotFound(_))
+            ),
+            _ => true,
+        }
+    }
+
+    async fn dmarc_is_aligned(
+        &self,
+        domain: &str,
+        from_domain: &str,
+        from_org_domain: &str,
+        alignment: &Alignment,
+    ) -> bool {
+        let domain = Domain::normalize(domain);
+        let domain = domain.as_str();
+        domain == from_domain
+            || (alignment == &Alignment::Relaxed
+                && (domain == from_org_domain || domain.ends_with(&format!(".{from_org_domain}")))
+                && self
+                    .dmarc_organizational_domain(domain)
+                    .await
+                    .is_ok_and(|org_domain| org_domain == from_org_domain))
+    }
+
     /// Validates the external report e-mail addresses of a DMARC record
     pub async fn verify_dmarc_report_address<'x>(
         &self,
@@ -149,55 +333,110 @@ impl Resolver {
     }
 
     async fn dmarc_tree_walk(&self, domain: &str) -> crate::Result<Option<Arc<Dmarc>>> {
-        let labels = domain.split('.').collect::<Vec<_>>();
-        let mut x = labels.len();
-        if x == 1 {
-            return Ok(None);
-        }
-        while x != 0 {
-            // Build query domain
-            let mut domain = String::with_capacity(domain.len() + 8);
-            domain.push_str("_dmarc");
-            for label in labels.iter().skip(labels.len() - x) {
-                domain.push('.');
-                domain.push_str(label);
-            }
-            domain.push('.');
-
-            // Query DMARC
-            match self.txt_lookup::<Dmarc>(domain).await {
+        for domain in tree_walk_domains(domain) {
+            match self
+                .txt_lookup_quorum::<Dmarc>(format!("_dmarc.{domain}."))
+                .await
+            {
                 Ok(dmarc) => {
                     return Ok(Some(dmarc));
                 }
                 Err(Error::DnsRecordNotFound(_)) | Err(Error::InvalidRecordType) => (),
+                // Any other error, including multiple published records, ends policy
+                // discovery without falling back to the parent domains (RFC 7489 6.6.3)
                 Err(err) => return Err(err),
             }
+        }
 
-            // If x < 5, remove the left-most (highest-numbered) label from the subject domain.
-            // If x >= 5, remove the left-most (highest-numbered) labels from the subject
-            // domain until 4 labels remain.
-            if x < 5 {
-                x -= 1;
-            } else {
-                x = 4;
+        Ok(None)
+    }
+
+    /// Returns all the DMARC records found during the tree walk, from the most to the fewest labels
+    async fn dmarc_tree_walk_all(&self, domain: &str) -> crate::Result<Vec<(String, Arc<Dmarc>)>> {
+        let mut records = Vec::new();
+        for domain in tree_walk_domains(domain) {
+            match self
+                .txt_lookup_quorum::<Dmarc>(format!("_dmarc.{domain}."))
+                .await
+            {
+                Ok(dmarc) => {
+                    records.push((domain.to_string(), dmarc));
+                }
+                Err(Error::DnsRecordNotFound(_)) | Err(Error::InvalidRecordType) => (),
+                Err(err) => return Err(err),
             }
         }
 
-        Ok(None)
+        Ok(records)
     }
 }
 
+/// Returns the domains queried during a DMARC tree walk
+fn tree_walk_domains(domain: &str) -> Vec<&str> {
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let mut x = labels.len();
+    let mut domains = Vec::with_capacity(std::cmp::min(x, 5));
+    if x == 1 {
+        return domains;
+    }
+    while x != 0 {
+        let skip = labels
+            .iter()
+            .take(labels.len() - x)
+            .map(|l| l.len() + 1)
+            .sum();
+        domains.push(&domain[skip..]);
+
+        // If x < 5, remove the left-most (highest-numbered) label from the subject domain.
+        // If x >= 5, remove the left-most (highest-numbered) labels from the subject
+        // domain until 4 labels remain.
+        if x < 5 {
+            x -= 1;
+        } else {
+            x = 4;
+        }
+    }
+
+    domains
+}
+
+/// Determines the Organizational Domain from the records found during a tree walk
+fn organizational_domain<'x>(domain: &'x str, records: &'x [(String, Arc<Dmarc>)]) -> &'x str {
+    let mut org_domain = domain;
+    for (record_domain, dmarc) in records {
+        match dmarc.psd {
+            Psd::No => return record_domain,
+            Psd::Yes => {
+                // The Organizational Domain is one label below the Public Suffix Domain
+                if record_domain.len() < domain.len() {
+                    let prefix = &domain[..domain.len() - record_domain.len() - 1];
+                    let label = prefix.rsplit('.').next().unwrap_or_default();
+                    return &domain[domain.len() - record_domain.len() - label.len() - 1..];
+                }
+                return domain;
+            }
+            Psd::Default => {
+                org_domain = record_domain;
+            }
+        }
+    }
+
+    org_domain
+}
+
 #[cfg(test)]
 #[allow(unused)]
 mod test {
     use std::time::{Duration, Instant};
 
     use crate::{
-        common::parse::TxtRecordParser,
+        arc::{Results, Seal, Set},
+        common::{config::Config, headers::Header, parse::TxtRecordParser},
         dkim::Signature,
-        dmarc::{Dmarc, Policy, URI},
-        AuthenticatedMessage, DkimOutput, DkimResult, DmarcResult, Error, Resolver, SpfOutput,
-        SpfResult,
+        dmarc::{Dmarc, Policy, PolicyVersion, URI},
+        report::{ActionDisposition, PolicyOverride, PolicyOverrideReason, Record},
+        ArcOutput, AuthenticatedMessage, DkimOutput, DkimResult, DmarcOutput, DmarcResult, Error,
+        Resolver, SpfOutput, SpfResult, MX,
     };
 
     #[tokio::test]
@@ -330,12 +569,20 @@ mod test {
                 signature: (&signature).into(),
                 report: None,
                 is_atps: false,
+                compat_shims: Vec::new(),
+                body_normalization: None,
+                body_bytes_hashed: None,
+                resent_unsigned: false,
+                policy: None,
             };
             let spf = SpfOutput {
                 result: spf,
                 domain: mail_from_domain.to_string(),
                 report: None,
                 explanation: None,
+                code: None,
+                trace: None,
+                helo: None,
             };
             let result = resolver
                 .verify_dmarc(&auth_message, &[dkim], mail_from_domain, &spf)
@@ -346,6 +593,137 @@ mod test {
         }
     }
 
+    #[tokio::test]
+    async fn dmarc_verify_bis() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        for (name, record) in [
+            (
+                "_dmarc.example.org.",
+                "v=DMARC1; p=none; sp=quarantine; np=reject; aspf=r; adkim=r",
+            ),
+            ("_dmarc.gov.test.", "v=DMARC1; p=reject; psd=y"),
+            ("_dmarc.example.net.", "v=DMARC1; p=reject; psd=n"),
+            ("_dmarc.sub.example.net.", "v=DMARC1; p=quarantine"),
+        ] {
+            resolver.txt_add(name, Dmarc::parse(record.as_bytes()).unwrap(), valid_until);
+        }
+        resolver.ipv4_add(
+            "existing.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+        resolver.mx_add(
+            "mx-only.example.org.",
+            vec![MX {
+                exchanges: vec!["mail.example.org".to_string()],
+                preference: 10,
+            }],
+            valid_until,
+        );
+
+        for (domain, expected) in [
+            ("a.b.agency.gov.test", "agency.gov.test"),
+            ("agency.gov.test", "agency.gov.test"),
+            ("gov.test", "gov.test"),
+            ("mail.example.org", "example.org"),
+            ("x.sub.example.net", "example.net"),
+            ("unknown.test", "unknown.test"),
+        ] {
+            assert_eq!(
+                resolver.dmarc_organizational_domain(domain).await.unwrap(),
+                expected
+            );
+        }
+
+        for (from, mail_from_domain, signature_domain, expect_dkim, expect_spf, policy) in [
+            // Relaxed alignment with existing subdomain
+            (
+                "existing.example.org",
+                "bounces.example.org",
+                "example.org",
+                DmarcResult::Pass,
+                DmarcResult::Pass,
+                Policy::Quarantine,
+            ),
+            // Subdomain with only MX records
+            (
+                "mx-only.example.org",
+                "example.com",
+                "example.com",
+                DmarcResult::Fail(Error::NotAligned),
+                DmarcResult::Fail(Error::NotAligned),
+                Policy::Quarantine,
+            ),
+            // Non-existent subdomain
+            (
+                "missing.example.org",
+                "example.com",
+                "example.com",
+                DmarcResult::Fail(Error::NotAligned),
+                DmarcResult::Fail(Error::NotAligned),
+                Policy::Reject,
+            ),
+            // Organizational Domain
+            (
+                "example.org",
+                "mail.example.org",
+                "example.org",
+                DmarcResult::Pass,
+                DmarcResult::Pass,
+                Policy::None,
+            ),
+            // Public Suffix Domain
+            (
+                "agency.gov.test",
+                "mail.agency.gov.test",
+                "other.gov.test",
+                DmarcResult::Fail(Error::NotAligned),
+                DmarcResult::Pass,
+                Policy::Reject,
+            ),
+        ] {
+            let message = format!("From: hello@{from}\r\n\r\n");
+            let auth_message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+            let signature = Signature {
+                d: signature_domain.into(),
+                ..Default::default()
+            };
+            let dkim = DkimOutput {
+                result: DkimResult::Pass,
+                signature: (&signature).into(),
+                report: None,
+                is_atps: false,
+                compat_shims: Vec::new(),
+                body_normalization: None,
+                body_bytes_hashed: None,
+                resent_unsigned: false,
+                policy: None,
+            };
+            let spf = SpfOutput {
+                result: SpfResult::Pass,
+                domain: mail_from_domain.to_string(),
+                report: None,
+                explanation: None,
+                code: None,
+                trace: None,
+                helo: None,
+            };
+            let result = resolver
+                .verify_dmarc_with_version(
+                    &auth_message,
+                    &[dkim],
+                    mail_from_domain,
+                    &spf,
+                    PolicyVersion::DmarcBis,
+                )
+                .await;
+            assert_eq!(result.dkim_result, expect_dkim, "{from}");
+            assert_eq!(result.spf_result, expect_spf, "{from}");
+            assert_eq!(result.policy, policy, "{from}");
+        }
+    }
+
     #[tokio::test]
     async fn dmarc_verify_report_address() {
         let resolver = Resolver::new_system_conf().unwrap();
@@ -372,4 +750,104 @@ mod test {
             ]
         );
     }
+
+    #[tokio::test]
+    async fn dmarc_multiple_records() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "_dmarc.multiple.example.org.",
+            Err::<Dmarc, _>(Error::MultipleRecords),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        resolver.txt_add(
+            "_dmarc.example.org.",
+            Dmarc::parse(b"v=DMARC1; p=reject").unwrap(),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        let message =
+            AuthenticatedMessage::parse(b"From: hello@multiple.example.org\r\n\r\n").unwrap();
+        let spf = SpfOutput::new("multiple.example.org".to_string()).with_result(SpfResult::Pass);
+
+        for version in [PolicyVersion::Rfc7489, PolicyVersion::DmarcBis] {
+            let output = resolver
+                .verify_dmarc_with_version(&message, &[], "multiple.example.org", &spf, version)
+                .await;
+            assert_eq!(
+                output.spf_result,
+                DmarcResult::PermError(Error::MultipleRecords)
+            );
+            assert_eq!(output.policy, Policy::None);
+            assert_eq!(
+                output.result_code(),
+                Some(crate::common::codes::ResultCode::DmarcMultipleRecords)
+            );
+        }
+    }
+
+    #[test]
+    fn dmarc_arc_override() {
+        let seal = Seal {
+            i: 1,
+            d: "lists.example.net".to_string(),
+            ..Default::default()
+        };
+        let signature = crate::arc::Signature::default();
+        let results = Results { i: 1 };
+        let arc = ArcOutput {
+            result: DkimResult::Pass,
+            set: vec![Set {
+                signature: Header::new(b"", b"", &signature),
+                seal: Header::new(b"", b"", &seal),
+                results: Header::new(b"", b"", &results),
+            }],
+            violations: vec![],
+            score: None,
+        };
+        assert_eq!(arc.sealers(), vec!["lists.example.net"]);
+
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_config(Config::new().with_trusted_sealer("Lists.Example.NET"));
+        let dmarc = DmarcOutput {
+            spf_result: DmarcResult::Fail(Error::NotAligned),
+            dkim_result: DmarcResult::Fail(Error::NotAligned),
+            policy: Policy::Reject,
+            ..DmarcOutput::default().with_domain("example.org")
+        }
+        .with_record(Dmarc::parse(b"v=DMARC1; p=reject").unwrap().into());
+
+        // Failed evaluations are overridden by chains from trusted sealers
+        let output = resolver.verify_dmarc_arc_override(dmarc.clone(), &arc);
+        assert_eq!(output.arc_override(), Some("lists.example.net"));
+        let record = Record::new().with_dmarc_output(&output);
+        assert_eq!(record.action_disposition(), ActionDisposition::None);
+        assert_eq!(
+            record.policy_override_reason(),
+            &[PolicyOverrideReason::new(PolicyOverride::LocalPolicy)
+                .with_comment("arc-override as.d=lists.example.net")]
+        );
+
+        // Untrusted or failed chains and passing evaluations are not overridden
+        let untrusted = Resolver::new_system_conf().unwrap();
+        let failed = ArcOutput {
+            result: DkimResult::Fail(Error::ArcBrokenChain),
+            ..arc.clone()
+        };
+        let passed = DmarcOutput {
+            dkim_result: DmarcResult::Pass,
+            ..dmarc.clone()
+        };
+        for (resolver, dmarc, arc) in [
+            (&untrusted, &dmarc, &arc),
+            (&resolver, &dmarc, &failed),
+            (&resolver, &passed, &arc),
+        ] {
+            let output = resolver.verify_dmarc_arc_override(dmarc.clone(), arc);
+            assert_eq!(output.arc_override(), None);
+        }
+        assert_eq!(
+            Record::new().with_dmarc_output(&dmarc).action_disposition(),
+            ActionDisposition::Reject
+        );
+    }
 }
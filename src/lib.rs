@@ -32,12 +32,22 @@
 //!   - SPF failure reporting using the Abuse Reporting Format.
 //! - **Domain-based Message Authentication, Reporting, and Conformance (DMARC)**:
 //!   - Policy evaluation.
+//!   - DMARCbis tree walk, `np=` and `psd=` support.
 //!   - DMARC aggregate report parsing and generation.
+//! - **Brand Indicators for Message Identification (BIMI)**:
+//!   - Record lookup and evaluation.
+//!   - BIMI-Location and BIMI-Indicator header generation.
 //! - **Abuse Reporting Format (ARF)**:
 //!   - Abuse and Authentication failure reporting.
 //!   - Feedback report parsing and generation.
 //! - **SMTP TLS Reporting**:
 //!   - Report parsing and generation.
+//! - **SMTP MTA Strict Transport Security (MTA-STS)**:
+//!   - Policy fetching, parsing and caching.
+//!   - MX host verification.
+//! - **DNS-Based Authentication of Named Entities (DANE)**:
+//!   - DNSSEC validated TLSA record lookup.
+//!   - Certificate chain matching.
 //!
 //! ## Usage examples
 //!
@@ -191,8 +201,47 @@
 //!     assert_eq!(dmarc_result.spf_result(), &DmarcResult::Pass);
 //! ```
 //!
+//! ### Full Message Authentication
+//!
+//! ```rust
+//!     // Create a resolver using Cloudflare DNS
+//!     let resolver = Resolver::new_cloudflare_tls().unwrap();
+//!
+//!     // Verify DKIM, SPF, ARC, iprev and DMARC in a single call
+//!     let authenticated_message = AuthenticatedMessage::parse(RFC5322_MESSAGE.as_bytes()).unwrap();
+//!     let output = resolver
+//!         .verify_message(
+//!             MessageAuthParams::new(&authenticated_message, "::1".parse().unwrap())
+//!                 .with_helo("mail.example.org")
+//!                 .with_mail_from("sender@example.org")
+//!                 .with_hostname("mx.mydomain.org"),
+//!         )
+//!         .await;
+//!     assert_eq!(output.dmarc().unwrap().dkim_result(), &DmarcResult::Pass);
+//!
+//!     // Prepend the Authentication-Results and Received-SPF headers to the message
+//!     println!("{}{}", output.to_header(), RFC5322_MESSAGE);
+//! ```
+//!
 //! More examples available under the [examples](examples) directory.
 //!
+//! ## Cryptographic backends
+//!
+//! The backend implementing `SigningKey` and `VerifyingKey` is selected at build time:
+//!
+//! - `ring` (default): RSA and Ed25519 keys backed by [ring](https://crates.io/crates/ring), recommended for high-volume verifiers as it is considerably faster for RSA verification.
+//! - `rust-crypto`: pure Rust implementation using the RustCrypto `rsa`, `ed25519-dalek`, `sha1` and `sha2` crates. Takes precedence over `ring` when both features are enabled.
+//!
+//! To build with RustCrypto only:
+//!
+//! ```toml
+//! mail-auth = { version = "0.4", default-features = false, features = ["rust-crypto"] }
+//! ```
+//!
+//! ## Sharing signers and sealers
+//!
+//! Signing and sealing only borrow `DkimSigner` and `ArcSealer`, so a single instance can be wrapped in an `Arc` and shared across threads and tasks. Both are `Send + Sync` whenever their key is, which holds for all keys provided by this crate.
+//!
 //! ## Testing & Fuzzing
 //!
 //! To run the testsuite:
@@ -233,6 +282,9 @@
 //! - [RFC 8616 - Email Authentication for Internationalized Mail](https://datatracker.ietf.org/doc/html/rfc8616)
 //! - [RFC 7960 - Interoperability Issues between Domain-based Message Authentication, Reporting, and Conformance (DMARC) and Indirect Email Flows](https://datatracker.ietf.org/doc/html/rfc7960)
 //!
+//! ### BIMI
+//! - [draft-brand-indicators-for-message-identification - Brand Indicators for Message Identification (BIMI)](https://datatracker.ietf.org/doc/html/draft-brand-indicators-for-message-identification)
+//!
 //! ### ARF
 //! - [RFC 5965 - An Extensible Format for Email Feedback Reports](https://datatracker.ietf.org/doc/html/rfc5965)
 //! - [RFC 6430 - Email Feedback Report Type Value: not-spam](https://datatracker.ietf.org/doc/html/rfc6430)
@@ -243,6 +295,13 @@
 //! ### SMTP TLS Reporting
 //! - [RFC 8460 - SMTP TLS Reporting](https://datatracker.ietf.org/doc/html/rfc8460)
 //!
+//! ### MTA-STS
+//! - [RFC 8461 - SMTP MTA Strict Transport Security (MTA-STS)](https://datatracker.ietf.org/doc/html/rfc8461)
+//!
+//! ### DANE
+//! - [RFC 6698 - The DNS-Based Authentication of Named Entities (DANE) Transport Layer Security (TLS) Protocol: TLSA](https://datatracker.ietf.org/doc/html/rfc6698)
+//! - [RFC 7672 - SMTP Security via Opportunistic DNS-Based Authentication of Named Entities (DANE) Transport Layer Security (TLS)](https://datatracker.ietf.org/doc/html/rfc7672)
+//!
 //! ## License
 //!
 //! Licensed under either of
@@ -267,6 +326,8 @@ use std::{
 };
 
 use arc::Set;
+use arc_swap::ArcSwap;
+use bimi::Bimi;
 use common::{crypto::HashAlgorithm, headers::Header, lru::LruCache, verify::DomainKey};
 use dkim::{Atps, Canonicalization, DomainKeyReport};
 use dmarc::Dmarc;
@@ -277,26 +338,54 @@ use hickory_resolver::{
 use mta_sts::{MtaSts, TlsRpt};
 use parking_lot::Mutex;
 use spf::{Macro, Spf};
+use vbr::Vouch;
 
 pub mod arc;
+#[cfg(all(feature = "bench", any(feature = "ring", feature = "rust-crypto")))]
+pub mod bench;
+pub mod bimi;
 pub mod common;
+#[cfg(feature = "corpus")]
+pub mod corpus;
+pub mod dane;
 pub mod dkim;
 pub mod dmarc;
 pub mod mta_sts;
 pub mod report;
+#[cfg(any(feature = "ring", feature = "rust-crypto"))]
+pub mod self_test;
 pub mod spf;
+pub mod vbr;
 
+pub use common::domain::{Domain, Selector};
 pub use flate2;
 pub use hickory_resolver;
+#[cfg(any(feature = "ring", feature = "rust-crypto"))]
+pub use self_test::self_test;
 pub use zip;
 
 pub struct Resolver {
     pub(crate) resolver: TokioAsyncResolver,
+    pub(crate) dns_config: Arc<common::resolver::DnsConfig>,
     pub(crate) cache_txt: LruCache<String, Txt>,
     pub(crate) cache_mx: LruCache<String, Arc<Vec<MX>>>,
     pub(crate) cache_ipv4: LruCache<String, Arc<Vec<Ipv4Addr>>>,
     pub(crate) cache_ipv6: LruCache<String, Arc<Vec<Ipv6Addr>>>,
     pub(crate) cache_ptr: LruCache<IpAddr, Arc<Vec<String>>>,
+    pub(crate) cache_mta_sts: LruCache<String, Arc<mta_sts::Policy>>,
+    pub(crate) cache_tlsa: LruCache<String, Arc<dane::Tlsa>>,
+    pub(crate) cache_spf: Option<spf::cache::SpfCache>,
+    pub(crate) cache_iprev: LruCache<(IpAddr, common::config::IprevPolicy), IprevOutput>,
+    pub(crate) config: Arc<ArcSwap<common::config::Config>>,
+    pub(crate) propagation_resolvers: Vec<Resolver>,
+    pub(crate) quorum: Option<common::quorum::Quorum>,
+    pub(crate) fairness: Option<Arc<common::fairness::Fairness>>,
+    pub(crate) tenant: Option<Arc<common::tenant::Tenant>>,
+    pub(crate) arc_scorer: Option<Arc<dyn arc::score::ArcScorer>>,
+    pub(crate) upstreams: Option<Arc<common::health::Upstreams>>,
+    pub(crate) offline: bool,
+    #[cfg(any(test, feature = "test"))]
+    pub(crate) mock: Arc<common::mock::MockDns>,
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -324,6 +413,8 @@ pub enum Txt {
     Atps(Arc<Atps>),
     MtaSts(Arc<MtaSts>),
     TlsRpt(Arc<TlsRpt>),
+    Bimi(Arc<Bimi>),
+    Vouch(Arc<Vouch>),
     Error(Error),
 }
 
@@ -339,6 +430,7 @@ pub struct AuthenticatedMessage<'x> {
     pub from: Vec<String>,
     pub raw_message: &'x [u8],
     pub body_offset: usize,
+    pub body_len: usize,
     pub body_hashes: Vec<(Canonicalization, HashAlgorithm, u64, Vec<u8>)>,
     pub dkim_headers: Vec<Header<'x, crate::Result<dkim::Signature>>>,
     pub ams_headers: Vec<Header<'x, crate::Result<arc::Signature>>>,
@@ -347,6 +439,8 @@ pub struct AuthenticatedMessage<'x> {
     pub received_headers_count: usize,
     pub date_header_present: bool,
     pub message_id_header_present: bool,
+    pub(crate) tag_warnings: Vec<Header<'x, common::parse::TagWarning>>,
+    pub storage_artifacts: Vec<common::message::StorageArtifact>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -363,6 +457,7 @@ pub struct ReceivedSpf {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DkimResult {
     Pass,
     Neutral(crate::Error),
@@ -373,20 +468,30 @@ pub enum DkimResult {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DkimOutput<'x> {
     result: DkimResult,
+    #[cfg_attr(feature = "serde", serde(skip_deserializing))]
     signature: Option<&'x dkim::Signature>,
     report: Option<String>,
     is_atps: bool,
+    compat_shims: Vec<dkim::CompatShim>,
+    body_normalization: Option<dkim::BodyNormalization>,
+    body_bytes_hashed: Option<u64>,
+    resent_unsigned: bool,
+    policy: Option<dkim::PolicyDecision>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ArcOutput<'x> {
     result: DkimResult,
     set: Vec<Set<'x>>,
+    violations: Vec<arc::ArcViolation>,
+    score: Option<u8>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SpfResult {
     Pass,
     Fail,
@@ -398,23 +503,59 @@ pub enum SpfResult {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SpfOutput {
     result: SpfResult,
     domain: String,
     report: Option<String>,
     explanation: Option<String>,
+    code: Option<common::codes::ResultCode>,
+    trace: Option<spf::SpfTrace>,
+    helo: Option<spf::HeloIdentity>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpfIdentitiesOutput {
+    helo: SpfOutput,
+    mail_from: SpfOutput,
+    is_null_sender: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MessageAuthOutput<'x> {
+    hostname: String,
+    remote_ip: IpAddr,
+    helo: String,
+    mail_from: String,
+    header_from: String,
+    dkim: Vec<DkimOutput<'x>>,
+    spf: Option<SpfIdentitiesOutput>,
+    arc: Option<ArcOutput<'x>>,
+    iprev: Option<IprevOutput>,
+    dmarc: Option<DmarcOutput>,
+    arc_set: Option<String>,
+    first_seen: Vec<(String, Option<u64>)>,
+    lookalike: Vec<common::lookalike::LookalikeWarning>,
+    local_overrides: Vec<common::hooks::LocalOverride>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DmarcOutput {
     spf_result: DmarcResult,
     dkim_result: DmarcResult,
     domain: String,
     policy: dmarc::Policy,
     record: Option<Arc<Dmarc>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    arc_override: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    local_override: Option<common::hooks::LocalOverride>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DmarcResult {
     Pass,
     Fail(crate::Error),
@@ -424,12 +565,72 @@ pub enum DmarcResult {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BimiOutput {
+    result: BimiResult,
+    domain: String,
+    selector: String,
+    record: Option<Arc<Bimi>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BimiResult {
+    Pass,
+    Declined,
+    Skipped,
+    Fail(crate::Error),
+    TempError(crate::Error),
+    None,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VbrOutput {
+    result: VbrResult,
+    domain: String,
+    content_type: String,
+    service: Option<String>,
+    record: Option<Arc<Vouch>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum VbrResult {
+    Pass,
+    Fail(crate::Error),
+    TempError(crate::Error),
+    PermError(crate::Error),
+    None,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MtaStsOutput {
+    mode: mta_sts::Mode,
+    policy: Option<Arc<mta_sts::Policy>>,
+    mx_hosts: Vec<(String, bool)>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DaneOutput {
+    result: DaneResult,
+    entry: Option<dane::TlsaEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DaneResult {
+    Pass,
+    Fail,
+    NoUsableRecords,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IprevOutput {
     pub result: IprevResult,
     pub ptr: Option<Arc<Vec<String>>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub confirmed: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IprevResult {
     Pass,
     Fail(crate::Error),
@@ -439,11 +640,13 @@ pub enum IprevResult {
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Version {
     V1,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Error {
     ParseError,
     MissingParameters,
@@ -451,6 +654,7 @@ pub enum Error {
     CryptoError(String),
     Io(String),
     Base64,
+    Base64TooLong,
     UnsupportedVersion,
     UnsupportedAlgorithm,
     UnsupportedCanonicalization,
@@ -463,7 +667,10 @@ pub enum Error {
     SignatureExpired,
     SignatureLength,
     DnsError(String),
-    DnsRecordNotFound(ResponseCode),
+    DnsRecordNotFound(
+        #[cfg_attr(feature = "serde", serde(with = "common::serde::response_code"))] ResponseCode,
+    ),
+    TooManySignatures,
     ArcChainTooLong,
     ArcInvalidInstance(u32),
     ArcInvalidCV,
@@ -471,6 +678,10 @@ pub enum Error {
     ArcBrokenChain,
     NotAligned,
     InvalidRecordType,
+    RecordTooLong,
+    MultipleRecords,
+    DeprecatedRecordType,
+    LocalPolicy,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -484,6 +695,7 @@ impl Display for Error {
             Error::CryptoError(err) => write!(f, "Cryptography layer error: {err}"),
             Error::Io(e) => write!(f, "I/O error: {e}"),
             Error::Base64 => write!(f, "Base64 encode or decode error."),
+            Error::Base64TooLong => write!(f, "Base64 value exceeds the maximum length."),
             Error::UnsupportedVersion => write!(f, "Unsupported version in DKIM Signature"),
             Error::UnsupportedAlgorithm => write!(f, "Unsupported algorithm in DKIM Signature"),
             Error::UnsupportedCanonicalization => {
@@ -510,11 +722,16 @@ impl Display for Error {
             Error::ArcInvalidCV => write!(f, "Invalid 'cv=' value found in ARC header"),
             Error::ArcHasHeaderTag => write!(f, "Invalid 'h=' tag present in ARC-Seal"),
             Error::ArcBrokenChain => write!(f, "Broken or missing ARC chain"),
+            Error::TooManySignatures => write!(f, "Too many DKIM signatures"),
             Error::ArcChainTooLong => write!(f, "Too many ARC headers"),
             Error::InvalidRecordType => write!(f, "Invalid record"),
+            Error::RecordTooLong => write!(f, "Record exceeds the maximum length"),
+            Error::MultipleRecords => write!(f, "Multiple records published"),
+            Error::DeprecatedRecordType => write!(f, "Record published with a deprecated type"),
             Error::DnsError(err) => write!(f, "DNS resolution error: {err}"),
             Error::DnsRecordNotFound(code) => write!(f, "DNS record not found: {code}"),
             Error::NotAligned => write!(f, "Policy not aligned"),
+            Error::LocalPolicy => write!(f, "Overridden by local policy"),
         }
     }
 }
@@ -603,6 +820,9 @@ impl Default for SpfOutput {
             domain: Default::default(),
             report: Default::default(),
             explanation: Default::default(),
+            code: Default::default(),
+            trace: Default::default(),
+            helo: Default::default(),
         }
     }
 }
@@ -629,11 +849,26 @@ impl Clone for Resolver {
     fn clone(&self) -> Self {
         Self {
             resolver: self.resolver.clone(),
+            dns_config: self.dns_config.clone(),
             cache_txt: Mutex::new(self.cache_txt.lock().clone()),
             cache_mx: Mutex::new(self.cache_mx.lock().clone()),
             cache_ipv4: Mutex::new(self.cache_ipv4.lock().clone()),
             cache_ipv6: Mutex::new(self.cache_ipv6.lock().clone()),
             cache_ptr: Mutex::new(self.cache_ptr.lock().clone()),
+            cache_mta_sts: Mutex::new(self.cache_mta_sts.lock().clone()),
+            cache_tlsa: Mutex::new(self.cache_tlsa.lock().clone()),
+            cache_spf: self.cache_spf.clone(),
+            cache_iprev: Mutex::new(self.cache_iprev.lock().clone()),
+            config: Arc::new(ArcSwap::new(self.config.load_full())),
+            propagation_resolvers: self.propagation_resolvers.clone(),
+            quorum: self.quorum.clone(),
+            fairness: self.fairness.clone(),
+            tenant: self.tenant.clone(),
+            arc_scorer: self.arc_scorer.clone(),
+            upstreams: self.upstreams.clone(),
+            offline: self.offline,
+            #[cfg(any(test, feature = "test"))]
+            mock: self.mock.clone(),
         }
     }
 }
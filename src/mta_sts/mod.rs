@@ -11,6 +11,7 @@
 use serde::{Deserialize, Serialize};
 
 pub mod parse;
+pub mod verify;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct MtaSts {
@@ -27,3 +28,40 @@ pub enum ReportUri {
     Mail(String),
     Http(String),
 }
+
+/// MTA-STS policy served at `https://mta-sts.<domain>/.well-known/mta-sts.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Policy {
+    pub id: String,
+    pub mode: Mode,
+    pub mx: Vec<MxPattern>,
+    pub max_age: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Enforce,
+    Testing,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MxPattern {
+    Equals(String),
+    StartsWith(String),
+}
+
+impl Policy {
+    /// Returns `true` if the MX host matches any of the policy's patterns.
+    pub fn verify(&self, mx_host: &str) -> bool {
+        let mx_host = mx_host.trim_end_matches('.');
+        self.mx.iter().any(|pattern| match pattern {
+            MxPattern::Equals(host) => host.eq_ignore_ascii_case(mx_host),
+            MxPattern::StartsWith(domain) => {
+                mx_host.split_once('.').is_some_and(|(label, parent)| {
+                    !label.is_empty() && parent.eq_ignore_ascii_case(domain)
+                })
+            }
+        })
+    }
+}
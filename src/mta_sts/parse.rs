@@ -10,7 +10,7 @@
 
 use crate::common::parse::{TagParser, TxtRecordParser, V};
 
-use super::{MtaSts, ReportUri, TlsRpt};
+use super::{Mode, MtaSts, MxPattern, Policy, ReportUri, TlsRpt};
 
 const ID: u64 = (b'i' as u64) | ((b'd' as u64) << 8);
 const RUA: u64 = (b'r' as u64) | (b'u' as u64) << 8 | (b'a' as u64) << 16;
@@ -112,11 +112,78 @@ impl TxtRecordParser for TlsRpt {
     }
 }
 
+impl Policy {
+    /// Parses an MTA-STS policy file, as served by the policy host.
+    pub fn parse(data: &str, id: String) -> crate::Result<Self> {
+        let mut mode = None;
+        let mut max_age = None;
+        let mut mx = Vec::new();
+        let mut has_version = false;
+
+        for line in data.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None if line.trim().is_empty() => continue,
+                None => return Err(crate::Error::ParseError),
+            };
+
+            match key {
+                "version" => {
+                    if value != "STSv1" {
+                        return Err(crate::Error::InvalidRecordType);
+                    }
+                    has_version = true;
+                }
+                "mode" => {
+                    mode = match value {
+                        "enforce" => Mode::Enforce,
+                        "testing" => Mode::Testing,
+                        "none" => Mode::None,
+                        _ => return Err(crate::Error::ParseError),
+                    }
+                    .into();
+                }
+                "max_age" => {
+                    max_age = std::cmp::min(
+                        value.parse::<u64>().map_err(|_| crate::Error::ParseError)?,
+                        MAX_AGE,
+                    )
+                    .into();
+                }
+                "mx" => {
+                    let value = value.trim_end_matches('.').to_lowercase();
+                    mx.push(if let Some(domain) = value.strip_prefix("*.") {
+                        MxPattern::StartsWith(domain.to_string())
+                    } else {
+                        MxPattern::Equals(value)
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        match (has_version, mode, max_age) {
+            (true, Some(mode), Some(max_age)) if mode == Mode::None || !mx.is_empty() => {
+                Ok(Policy {
+                    id,
+                    mode,
+                    mx,
+                    max_age,
+                })
+            }
+            _ => Err(crate::Error::ParseError),
+        }
+    }
+}
+
+const MAX_AGE: u64 = 31557600;
+
 #[cfg(test)]
 mod tests {
     use crate::{
         common::parse::TxtRecordParser,
-        mta_sts::{MtaSts, ReportUri, TlsRpt},
+        mta_sts::{Mode, MtaSts, MxPattern, Policy, ReportUri, TlsRpt},
+        Error,
     };
 
     #[test]
@@ -169,4 +236,76 @@ mod tests {
             assert_eq!(TlsRpt::parse(tls_rpt.as_bytes()).unwrap(), expected_tls_rpt);
         }
     }
+
+    #[test]
+    fn mta_sts_policy_parse() {
+        for (policy, expected_policy) in [
+            (
+                concat!(
+                    "version: STSv1\r\n",
+                    "mode: enforce\r\n",
+                    "mx: mail.example.com\r\n",
+                    "mx: *.example.net\r\n",
+                    "mx: backupmx.example.com.\r\n",
+                    "max_age: 604800\r\n"
+                ),
+                Ok(Policy {
+                    id: "20160831085700Z".to_string(),
+                    mode: Mode::Enforce,
+                    mx: vec![
+                        MxPattern::Equals("mail.example.com".to_string()),
+                        MxPattern::StartsWith("example.net".to_string()),
+                        MxPattern::Equals("backupmx.example.com".to_string()),
+                    ],
+                    max_age: 604800,
+                }),
+            ),
+            (
+                "version: STSv1\nmode: none\nmax_age: 99999999999\n",
+                Ok(Policy {
+                    id: "20160831085700Z".to_string(),
+                    mode: Mode::None,
+                    mx: vec![],
+                    max_age: 31557600,
+                }),
+            ),
+            (
+                "version: STSv1\nmode: enforce\nmax_age: 86400\n",
+                Err(Error::ParseError),
+            ),
+            (
+                "version: STSv2\nmode: none\nmax_age: 86400\n",
+                Err(Error::InvalidRecordType),
+            ),
+            (
+                "<html><body>Not found</body></html>",
+                Err(Error::ParseError),
+            ),
+        ] {
+            assert_eq!(
+                Policy::parse(policy, "20160831085700Z".to_string()),
+                expected_policy
+            );
+        }
+
+        let policy = Policy {
+            id: "1".to_string(),
+            mode: Mode::Enforce,
+            mx: vec![
+                MxPattern::Equals("mail.example.com".to_string()),
+                MxPattern::StartsWith("example.net".to_string()),
+            ],
+            max_age: 86400,
+        };
+        for (mx_host, expected_result) in [
+            ("mail.example.com", true),
+            ("MAIL.example.com.", true),
+            ("mx1.example.net", true),
+            ("example.net", false),
+            ("a.mx1.example.net", false),
+            ("other.example.com", false),
+        ] {
+            assert_eq!(policy.verify(mx_host), expected_result, "{mx_host}");
+        }
+    }
 }
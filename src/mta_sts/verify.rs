@@ -0,0 +1,338 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{common::lru::DnsCache, Error, MtaStsOutput, Resolver};
+
+use super::{Mode, MtaSts, Policy};
+
+impl Resolver {
+    /// Obtains the MTA-STS policy of a domain over HTTPS and verifies its MX hosts.
+    #[cfg(feature = "mta-sts-fetch")]
+    pub async fn verify_mta_sts(
+        &self,
+        domain: &str,
+        mx_hosts: &[impl AsRef<str>],
+    ) -> crate::Result<MtaStsOutput> {
+        self.verify_mta_sts_with(domain, mx_hosts, |domain| async move {
+            fetch_policy(&domain).await
+        })
+        .await
+    }
+
+    /// Obtains the MTA-STS policy of a domain using the provided fetch function and
+    /// verifies its MX hosts. Cached policies are reused until their `id` changes.
+    pub async fn verify_mta_sts_with<F, Fut>(
+        &self,
+        domain: &str,
+        mx_hosts: &[impl AsRef<str>],
+        fetch: F,
+    ) -> crate::Result<MtaStsOutput>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = crate::Result<String>>,
+    {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        let cached = self.cache_mta_sts.get(&domain);
+
+        let policy = match self
+            .txt_lookup::<MtaSts>(format!("_mta-sts.{domain}."))
+            .await
+        {
+            Ok(record) => match cached {
+                Some(policy) if policy.id == record.id => policy,
+                cached => match fetch(domain.clone())
+                    .await
+                    .and_then(|policy| Policy::parse(&policy, record.id.clone()))
+                {
+                    Ok(policy) => {
+                        let valid_until = Instant::now() + Duration::from_secs(policy.max_age);
+                        self.cache_mta_sts
+                            .insert(domain, Arc::new(policy), valid_until)
+                    }
+                    Err(err) => cached.ok_or(err)?,
+                },
+            },
+            Err(Error::DnsRecordNotFound(_) | Error::InvalidRecordType) => match cached {
+                Some(policy) => policy,
+                None => return Ok(MtaStsOutput::default()),
+            },
+            Err(err) => cached.ok_or(err)?,
+        };
+
+        Ok(MtaStsOutput {
+            mode: policy.mode,
+            mx_hosts: mx_hosts
+                .iter()
+                .map(|host| {
+                    let host = host.as_ref();
+                    (host.to_string(), policy.verify(host))
+                })
+                .collect(),
+            policy: policy.into(),
+        })
+    }
+
+    #[cfg(any(test, feature = "test"))]
+    pub fn mta_sts_add(&self, domain: &str, value: Policy, valid_until: Instant) {
+        self.cache_mta_sts
+            .insert(domain.to_lowercase(), Arc::new(value), valid_until);
+    }
+}
+
+/// Fetches the MTA-STS policy file of a domain.
+#[cfg(feature = "mta-sts-fetch")]
+pub async fn fetch_policy(domain: &str) -> crate::Result<String> {
+    static CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
+
+    let client = match CLIENT.get() {
+        Some(client) => client,
+        None => {
+            let client = reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .timeout(Duration::from_secs(60))
+                .build()
+                .map_err(|err| Error::Io(err.to_string()))?;
+            CLIENT.get_or_init(|| client)
+        }
+    };
+    let mut response = client
+        .get(format!("https://mta-sts.{domain}/.well-known/mta-sts.txt"))
+        .send()
+        .await
+        .map_err(|err| Error::Io(err.to_string()))?;
+
+    if !response.status().is_success() {
+        return Err(Error::Io(format!(
+            "MTA-STS policy fetch failed with status {}",
+            response.status()
+        )));
+    }
+
+    // Policies are served as text/plain (RFC 8461 3.3)
+    if !is_text_plain(
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok()),
+    ) {
+        return Err(Error::Io(
+            "MTA-STS policy is not served as text/plain".to_string(),
+        ));
+    }
+    if response.content_length().unwrap_or(0) > MAX_POLICY_SIZE as u64 {
+        return Err(Error::Io("MTA-STS policy is too large".to_string()));
+    }
+
+    // Read the body in chunks, the declared length may be missing or wrong
+    let mut policy = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| Error::Io(err.to_string()))?
+    {
+        if policy.len() + chunk.len() > MAX_POLICY_SIZE {
+            return Err(Error::Io("MTA-STS policy is too large".to_string()));
+        }
+        policy.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(policy).map_err(|_| Error::ParseError)
+}
+
+#[cfg(feature = "mta-sts-fetch")]
+fn is_text_plain(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|content_type| {
+        content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .eq_ignore_ascii_case("text/plain")
+    })
+}
+
+#[cfg(feature = "mta-sts-fetch")]
+const MAX_POLICY_SIZE: usize = 64 * 1024;
+
+impl MtaStsOutput {
+    /// Returns the policy mode, `Mode::None` if the domain has no policy.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn policy(&self) -> Option<&Policy> {
+        self.policy.as_deref()
+    }
+
+    /// Returns `true` if the MX host is permitted by the policy.
+    pub fn is_valid_mx(&self, mx_host: &str) -> bool {
+        !self
+            .policy
+            .as_ref()
+            .is_some_and(|policy| policy.mode != Mode::None && !policy.verify(mx_host))
+    }
+
+    /// Returns the MX hosts that match the policy.
+    pub fn valid_mx_hosts(&self) -> impl Iterator<Item = &str> {
+        self.mx_hosts
+            .iter()
+            .filter(|(_, valid)| *valid)
+            .map(|(host, _)| host.as_str())
+    }
+
+    /// Returns the MX hosts that do not match the policy.
+    pub fn invalid_mx_hosts(&self) -> impl Iterator<Item = &str> {
+        self.mx_hosts
+            .iter()
+            .filter(|(_, valid)| !*valid)
+            .map(|(host, _)| host.as_str())
+    }
+}
+
+impl Default for MtaStsOutput {
+    fn default() -> Self {
+        Self {
+            mode: Mode::None,
+            policy: None,
+            mx_hosts: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        mta_sts::{Mode, MtaSts},
+        Error, Resolver,
+    };
+
+    #[tokio::test]
+    async fn mta_sts_verify() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        let fetches = AtomicUsize::new(0);
+        let fetch = |domain: String| {
+            fetches.fetch_add(1, Ordering::Relaxed);
+            async move {
+                if domain == "example.com" {
+                    Ok(concat!(
+                        "version: STSv1\r\n",
+                        "mode: enforce\r\n",
+                        "mx: mail.example.com\r\n",
+                        "mx: *.mx.example.com\r\n",
+                        "max_age: 86400\r\n"
+                    )
+                    .to_string())
+                } else {
+                    Err(Error::Io("connection refused".to_string()))
+                }
+            }
+        };
+        let mx_hosts = ["mail.example.com", "a.mx.example.com", "mail.example.org"];
+
+        // Fetch policy
+        resolver.txt_add(
+            "_mta-sts.example.com.",
+            MtaSts {
+                id: "1".to_string(),
+            },
+            valid_until,
+        );
+        let output = resolver
+            .verify_mta_sts_with("example.com", &mx_hosts, fetch)
+            .await
+            .unwrap();
+        assert_eq!(output.mode(), Mode::Enforce);
+        assert_eq!(
+            output.valid_mx_hosts().collect::<Vec<_>>(),
+            vec!["mail.example.com", "a.mx.example.com"]
+        );
+        assert_eq!(
+            output.invalid_mx_hosts().collect::<Vec<_>>(),
+            vec!["mail.example.org"]
+        );
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // Cached policy is reused while the id is unchanged
+        resolver
+            .verify_mta_sts_with("example.com", &mx_hosts, fetch)
+            .await
+            .unwrap();
+        assert_eq!(fetches.load(Ordering::Relaxed), 1);
+
+        // A new id invalidates the cached policy
+        resolver.txt_add(
+            "_mta-sts.example.com.",
+            MtaSts {
+                id: "2".to_string(),
+            },
+            valid_until,
+        );
+        let output = resolver
+            .verify_mta_sts_with("example.com", &mx_hosts, fetch)
+            .await
+            .unwrap();
+        assert_eq!(output.policy().unwrap().id, "2");
+        assert_eq!(fetches.load(Ordering::Relaxed), 2);
+
+        // Failed fetch without a cached policy
+        resolver.txt_add(
+            "_mta-sts.example.org.",
+            MtaSts {
+                id: "1".to_string(),
+            },
+            valid_until,
+        );
+        assert_eq!(
+            resolver
+                .verify_mta_sts_with("example.org", &mx_hosts, fetch)
+                .await
+                .unwrap_err(),
+            Error::Io("connection refused".to_string())
+        );
+
+        // No MTA-STS record
+        let output = resolver
+            .verify_mta_sts_with("example.net", &mx_hosts, fetch)
+            .await
+            .unwrap();
+        assert_eq!(output.mode(), Mode::None);
+        assert!(output.is_valid_mx("mail.example.org"));
+    }
+
+    #[cfg(feature = "mta-sts-fetch")]
+    #[test]
+    fn mta_sts_content_type() {
+        for (content_type, expected) in [
+            (Some("text/plain"), true),
+            (Some("Text/Plain; charset=utf-8"), true),
+            (Some("text/html"), false),
+            (Some("text/plainish"), false),
+            (None, false),
+        ] {
+            assert_eq!(
+                super::is_text_plain(content_type),
+                expected,
+                "{content_type:?}"
+            );
+        }
+    }
+}
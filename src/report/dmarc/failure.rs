@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, io, net::IpAddr, time::SystemTime};
+
+use mail_builder::headers::address::Address;
+
+use crate::{
+    dmarc::Policy,
+    report::{
+        AuthFailureType, DeliveryResult, DmarcFailureReport, Feedback, FeedbackType,
+        IdentityAlignment,
+    },
+    DmarcOutput, DmarcResult,
+};
+
+const REDACTED: &str = "redacted";
+const ADDRESS_HEADERS: [&str; 10] = [
+    "from",
+    "sender",
+    "reply-to",
+    "to",
+    "cc",
+    "bcc",
+    "return-path",
+    "delivered-to",
+    "resent-from",
+    "resent-to",
+];
+
+impl<'x> DmarcFailureReport<'x> {
+    /// Creates a failure report for a DMARC evaluation and the headers of the
+    /// message it was performed on.
+    pub fn new(output: &'x DmarcOutput, headers: &'x [u8]) -> Self {
+        let identity_alignment = match (
+            output.spf_result() == &DmarcResult::Pass,
+            output.dkim_result() == &DmarcResult::Pass,
+        ) {
+            (true, true) => IdentityAlignment::DkimSpf,
+            (true, false) => IdentityAlignment::Spf,
+            (false, true) => IdentityAlignment::Dkim,
+            (false, false) => IdentityAlignment::None,
+        };
+
+        DmarcFailureReport {
+            output,
+            headers: String::from_utf8_lossy(headers),
+            feedback: Feedback::new(FeedbackType::AuthFailure)
+                .with_auth_failure(AuthFailureType::Dmarc)
+                .with_identity_alignment(identity_alignment)
+                .with_delivery_result(match output.policy() {
+                    Policy::Reject => DeliveryResult::Reject,
+                    Policy::Quarantine => DeliveryResult::Spam,
+                    Policy::None | Policy::Unspecified => DeliveryResult::Delivered,
+                })
+                .with_reported_domain(output.domain()),
+            redact: false,
+            last_report: None,
+        }
+    }
+
+    pub fn with_reporting_mta(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.feedback = self.feedback.with_reporting_mta(value);
+        self
+    }
+
+    pub fn with_source_ip(mut self, value: IpAddr) -> Self {
+        self.feedback = self.feedback.with_source_ip(value);
+        self
+    }
+
+    pub fn with_original_mail_from(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.feedback = self.feedback.with_original_mail_from(value);
+        self
+    }
+
+    pub fn with_original_rcpt_to(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.feedback = self.feedback.with_original_rcpt_to(value);
+        self
+    }
+
+    pub fn with_authentication_results(mut self, value: impl Into<Cow<'x, str>>) -> Self {
+        self.feedback = self.feedback.with_authentication_results(value);
+        self
+    }
+
+    pub fn with_arrival_date(mut self, value: i64) -> Self {
+        self.feedback = self.feedback.with_arrival_date(value);
+        self
+    }
+
+    /// Sets the number of failures this report stands for, when failures
+    /// are aggregated over the report interval.
+    pub fn with_incidents(mut self, value: u32) -> Self {
+        self.feedback = self.feedback.with_incidents(value);
+        self
+    }
+
+    /// Redacts the local-parts of the e-mail addresses in the report (RFC 6590).
+    pub fn with_redaction(mut self, redact: bool) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    /// Sets the UNIX timestamp of the last failure report sent to the domain,
+    /// used to honor the record's `ri=` report interval.
+    pub fn with_last_report(mut self, timestamp: u64) -> Self {
+        self.last_report = timestamp.into();
+        self
+    }
+
+    /// Returns `true` if the DMARC record requests a failure report for this
+    /// evaluation (`ruf=` and `fo=`) and the report interval has elapsed.
+    pub fn is_due(&self) -> bool {
+        self.is_due_at(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        )
+    }
+
+    /// Same as `is_due`, evaluating the report interval as of a UNIX timestamp.
+    pub fn is_due_at(&self, now: u64) -> bool {
+        match (self.output.failure_report(), self.output.dmarc_record()) {
+            (Some(_), Some(record)) => self
+                .last_report
+                .is_none_or(|last_report| now >= last_report.saturating_add(record.ri as u64)),
+            _ => false,
+        }
+    }
+
+    /// Generates one report message for each `ruf=` address, returning the
+    /// addresses along with their messages. Reports exceeding the size limit
+    /// of an address are sent without the original headers or skipped if still
+    /// too large. Returns no messages if no report is due.
+    pub fn to_rfc5322<'y>(
+        &self,
+        from: impl Into<Address<'y>>,
+    ) -> io::Result<Vec<(&'x str, String)>> {
+        let record = match self.output.dmarc_record() {
+            Some(record) if self.is_due() => record,
+            _ => return Ok(Vec::new()),
+        };
+
+        let from = from.into();
+        let subject = format!("DMARC Failure Report for {}", self.output.domain());
+        let mut feedback = self.feedback.clone();
+        let headers = if self.redact {
+            if let Some(value) = &feedback.original_mail_from {
+                feedback.original_mail_from = Some(redact_local_parts(value).into());
+            }
+            if let Some(value) = &feedback.original_rcpt_to {
+                feedback.original_rcpt_to = Some(redact_local_parts(value).into());
+            }
+            redact_headers(&self.headers).into()
+        } else {
+            self.headers.clone()
+        };
+        let feedback_with_headers = feedback.clone().with_headers(headers);
+
+        let mut reports = Vec::with_capacity(record.ruf.len());
+        for uri in &record.ruf {
+            let mut report =
+                feedback_with_headers.to_rfc5322(from.clone(), uri.uri.as_str(), &subject)?;
+            if uri.max_size != 0 && report.len() > uri.max_size {
+                report = feedback.to_rfc5322(from.clone(), uri.uri.as_str(), &subject)?;
+                if report.len() > uri.max_size {
+                    continue;
+                }
+            }
+            reports.push((uri.uri.as_str(), report));
+        }
+        Ok(reports)
+    }
+}
+
+fn redact_headers(headers: &str) -> String {
+    let mut redacted = String::with_capacity(headers.len());
+    let mut is_address = false;
+    for line in headers.split_inclusive('\n') {
+        if !line.starts_with([' ', '\t']) {
+            is_address = line.split_once(':').is_some_and(|(name, _)| {
+                ADDRESS_HEADERS
+                    .iter()
+                    .any(|header| name.trim().eq_ignore_ascii_case(header))
+            });
+        }
+        if is_address {
+            redacted.push_str(&redact_local_parts(line));
+        } else {
+            redacted.push_str(line);
+        }
+    }
+    redacted
+}
+
+fn redact_local_parts(value: &str) -> String {
+    let mut redacted = String::with_capacity(value.len());
+    let mut last_pos = 0;
+    for (pos, _) in value.match_indices('@') {
+        let start = value[last_pos..pos]
+            .rfind(|ch: char| ch.is_ascii_whitespace() || "<,:;(\"".contains(ch))
+            .map_or(last_pos, |start| last_pos + start + 1);
+        redacted.push_str(&value[last_pos..start]);
+        if start < pos {
+            redacted.push_str(REDACTED);
+        }
+        last_pos = pos;
+    }
+    redacted.push_str(&value[last_pos..]);
+    redacted
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        dmarc::{Dmarc, Policy},
+        report::DmarcFailureReport,
+        DmarcOutput, DmarcResult, Error,
+    };
+
+    const HEADERS: &[u8] = concat!(
+        "From: \"John Doe\" <john.doe@example.org>\r\n",
+        "To: jane@example.com,\r\n",
+        " bill@example.com\r\n",
+        "Subject: john@example.org\r\n",
+        "\r\n"
+    )
+    .as_bytes();
+
+    fn output(record: &str, dkim_pass: bool) -> DmarcOutput {
+        let mut output = DmarcOutput::default()
+            .with_domain("example.org")
+            .with_spf_result(DmarcResult::Fail(Error::NotAligned))
+            .with_dkim_result(if dkim_pass {
+                DmarcResult::Pass
+            } else {
+                DmarcResult::Fail(Error::NotAligned)
+            })
+            .with_record(Arc::new(Dmarc::parse(record.as_bytes()).unwrap()));
+        output.policy = Policy::Reject;
+        output
+    }
+
+    #[test]
+    fn dmarc_failure_report() {
+        // Reports honor fo=
+        let output_fo = output("v=DMARC1; p=reject; ruf=mailto:ruf@example.org; fo=0", true);
+        let report = DmarcFailureReport::new(&output_fo, HEADERS);
+        assert!(!report.is_due());
+        assert_eq!(report.to_rfc5322("dmarc@example.net").unwrap(), vec![]);
+
+        // Reports honor ri=
+        let output = output(
+            concat!(
+                "v=DMARC1; p=reject; fo=1; ri=3600; ",
+                "ruf=mailto:ruf@example.org,mailto:small@example.org!2k,mailto:tiny@example.org!100"
+            ),
+            false,
+        );
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(!DmarcFailureReport::new(&output, HEADERS)
+            .with_last_report(now - 60)
+            .is_due());
+        assert!(DmarcFailureReport::new(&output, HEADERS)
+            .with_last_report(now - 7200)
+            .is_due());
+        let report = DmarcFailureReport::new(&output, HEADERS).with_last_report(1_000_000);
+        assert!(!report.is_due_at(1_003_599));
+        assert!(report.is_due_at(1_003_600));
+
+        // Reports are generated for each URI within its size limit
+        let headers = [
+            HEADERS,
+            "X-Padding: 0123456789abcdef\r\n".repeat(64).as_bytes(),
+        ]
+        .concat();
+        let reports = DmarcFailureReport::new(&output, &headers)
+            .with_original_mail_from("john.doe@example.org")
+            .with_redaction(true)
+            .to_rfc5322("dmarc@example.net")
+            .unwrap();
+        assert_eq!(reports.len(), 2);
+        let (uri, report) = &reports[0];
+        assert_eq!(*uri, "ruf@example.org");
+        assert!(report.contains("Auth-Failure: dmarc"), "{report}");
+        assert!(report.contains("text/rfc822-headers"));
+        assert!(report.contains("Delivery-Result: reject"), "{report}");
+        assert!(report.contains("Original-Mail-From: redacted@example.org"));
+        assert!(report.contains("From: \"John Doe\" <redacted@example.org>"));
+        assert!(report.contains("To: redacted@example.com,\r\n redacted@example.com"));
+        assert!(report.contains("Subject: john@example.org"));
+        assert!(!report.contains("john.doe"));
+        let (uri, report) = &reports[1];
+        assert_eq!(*uri, "small@example.org");
+        assert!(!report.contains("text/rfc822-headers"));
+        assert!(report.len() <= 2048);
+
+        // Headers are left intact without redaction
+        let reports = DmarcFailureReport::new(&output, HEADERS)
+            .to_rfc5322("dmarc@example.net")
+            .unwrap();
+        assert!(reports[0].1.contains("<john.doe@example.org>"));
+    }
+}
@@ -34,6 +34,23 @@ impl Report {
         from: impl Into<Address<'x>>,
         to: impl Iterator<Item = &'x str>,
         writer: impl io::Write,
+    ) -> io::Result<()> {
+        self.write_message(
+            submitter,
+            from,
+            Address::List(to.map(|to| (*to).into()).collect()),
+            None,
+            writer,
+        )
+    }
+
+    pub(crate) fn write_message<'x>(
+        &self,
+        submitter: &'x str,
+        from: impl Into<Address<'x>>,
+        to: Address<'x>,
+        unique_id: Option<usize>,
+        writer: impl io::Write,
     ) -> io::Result<()> {
         // Compress XML report
         let xml = self.to_xml();
@@ -43,18 +60,10 @@ impl Report {
 
         MessageBuilder::new()
             .from(from)
-            .header(
-                "To",
-                HeaderType::Address(Address::List(to.map(|to| (*to).into()).collect())),
-            )
+            .header("To", HeaderType::Address(to))
             .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
             .message_id(format!("{}@{}", make_boundary("."), submitter))
-            .subject(format!(
-                "Report Domain: {} Submitter: {} Report-ID: <{}>",
-                self.domain(),
-                submitter,
-                self.report_id()
-            ))
+            .subject(self.subject(submitter))
             .text_body(format!(
                 concat!(
                     "DMARC aggregate report from {}\r\n\r\n",
@@ -69,18 +78,38 @@ impl Report {
             ))
             .attachment(
                 "application/gzip",
-                format!(
-                    "{}!{}!{}!{}.xml.gz",
-                    submitter,
-                    self.domain(),
-                    self.date_range_begin(),
-                    self.date_range_end()
-                ),
+                self.filename(submitter, unique_id),
                 compressed_bytes,
             )
             .write_to(writer)
     }
 
+    /// Returns the subject of a report message (RFC 7489 Section 7.2.1.1).
+    pub fn subject(&self, submitter: &str) -> String {
+        format!(
+            "Report Domain: {} Submitter: {} Report-ID: <{}>",
+            self.domain(),
+            submitter,
+            self.report_id()
+        )
+    }
+
+    /// Returns the name of the compressed report attachment (RFC 7489 Section 7.2.1.1).
+    pub fn filename(&self, submitter: &str, unique_id: Option<usize>) -> String {
+        let mut filename = format!(
+            "{}!{}!{}!{}",
+            submitter,
+            self.domain(),
+            self.date_range_begin(),
+            self.date_range_end()
+        );
+        if let Some(unique_id) = unique_id {
+            write!(filename, "!{unique_id}").ok();
+        }
+        filename.push_str(".xml.gz");
+        filename
+    }
+
     pub fn to_rfc5322<'x>(
         &self,
         submitter: &'x str,
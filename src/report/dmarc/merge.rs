@@ -0,0 +1,341 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{
+    report::{Record, Report},
+    Domain,
+};
+
+/// Merges aggregate reports by policy domain, published policy and period.
+#[derive(Debug, Clone, Default)]
+pub struct ReportAggregator {
+    period: u64,
+    anonymization: Option<IpAnonymization>,
+    reports: HashMap<(String, u64), Vec<Report>>,
+}
+
+/// Truncates source IP addresses to a network prefix, for operators that may
+/// not disclose full addresses in aggregate reports. By default IPv4 addresses
+/// are truncated to /24 and IPv6 addresses to /48, zeroing the last octet and
+/// the last 80 bits respectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpAnonymization {
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+}
+
+impl Report {
+    /// Merges another report into this one, summing the counts of identical rows
+    /// and extending the date range to cover both reports. If the published
+    /// policies differ, the policy of the most recent report is kept.
+    pub fn merge(&mut self, other: Report) {
+        if other.report_metadata.date_range.end > self.report_metadata.date_range.end {
+            self.policy_published = other.policy_published;
+        }
+        let date_range = &mut self.report_metadata.date_range;
+        date_range.begin = date_range.begin.min(other.report_metadata.date_range.begin);
+        date_range.end = date_range.end.max(other.report_metadata.date_range.end);
+
+        for error in other.report_metadata.error {
+            if !self.report_metadata.error.contains(&error) {
+                self.report_metadata.error.push(error);
+            }
+        }
+        for extension in other.extensions {
+            if !self.extensions.contains(&extension) {
+                self.extensions.push(extension);
+            }
+        }
+
+        let mut records = std::mem::take(&mut self.record);
+        records.extend(other.record);
+        self.record = merge_rows(records);
+    }
+
+    /// Truncates the source IP address of every row, summing the counts of rows
+    /// that become identical, and records the setting as a report metadata
+    /// error so that receivers know the addresses are not exact.
+    pub fn anonymize_ips(&mut self, anonymization: IpAnonymization) {
+        for record in &mut self.record {
+            if let Some(ip) = &mut record.row.source_ip {
+                *ip = anonymization.anonymize(*ip);
+            }
+        }
+        self.record = merge_rows(std::mem::take(&mut self.record));
+
+        let comment = anonymization.to_string();
+        if !self.report_metadata.error.contains(&comment) {
+            self.report_metadata.error.push(comment);
+        }
+    }
+}
+
+fn merge_rows(records: Vec<Record>) -> Vec<Record> {
+    let mut rows = HashMap::with_capacity(records.len());
+    let mut merged_records = Vec::with_capacity(records.len());
+    for record in records {
+        let count = record.row.count;
+        let key = record.clone().with_count(0);
+        if let Some(&idx) = rows.get(&key) {
+            let merged: &mut Record = &mut merged_records[idx];
+            merged.row.count = merged.row.count.saturating_add(count);
+        } else {
+            rows.insert(key, merged_records.len());
+            merged_records.push(record);
+        }
+    }
+    merged_records
+}
+
+impl IpAnonymization {
+    /// Creates a setting that keeps the given number of leading bits, capped
+    /// at 32 for IPv4 and 128 for IPv6.
+    pub fn new(ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        IpAnonymization {
+            ipv4_prefix: ipv4_prefix.min(32),
+            ipv6_prefix: ipv6_prefix.min(128),
+        }
+    }
+
+    pub fn ipv4_prefix(&self) -> u8 {
+        self.ipv4_prefix
+    }
+
+    pub fn ipv6_prefix(&self) -> u8 {
+        self.ipv6_prefix
+    }
+
+    /// Zeroes the host bits of an address.
+    pub fn anonymize(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.ipv4_prefix))
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.ipv6_prefix))
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+}
+
+impl Default for IpAnonymization {
+    fn default() -> Self {
+        IpAnonymization::new(24, 48)
+    }
+}
+
+impl std::fmt::Display for IpAnonymization {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Source IP addresses anonymized to /{} (IPv4) and /{} (IPv6)",
+            self.ipv4_prefix, self.ipv6_prefix
+        )
+    }
+}
+
+impl ReportAggregator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only merges reports whose date ranges begin in the same period of
+    /// `seconds` length. Reports of any date range are merged by default.
+    pub fn with_period(mut self, seconds: u64) -> Self {
+        self.period = seconds;
+        self
+    }
+
+    /// Anonymizes the source IP addresses of every report added.
+    pub fn with_ip_anonymization(mut self, anonymization: IpAnonymization) -> Self {
+        self.anonymization = Some(anonymization);
+        self
+    }
+
+    /// Adds a report, merging it with a previous report for the same domain,
+    /// published policy and period. The policy domain is normalized and, if
+    /// configured, source IP addresses are anonymized before merging.
+    pub fn add(&mut self, mut report: Report) {
+        report.policy_published.domain = Domain::normalize(&report.policy_published.domain);
+        if let Some(anonymization) = self.anonymization {
+            report.anonymize_ips(anonymization);
+        }
+        let period = report
+            .report_metadata
+            .date_range
+            .begin
+            .checked_div(self.period)
+            .unwrap_or(0);
+        let reports = self
+            .reports
+            .entry((report.policy_published.domain.clone(), period))
+            .or_default();
+        if let Some(merged) = reports
+            .iter_mut()
+            .find(|merged| merged.policy_published == report.policy_published)
+        {
+            merged.merge(report);
+        } else {
+            reports.push(report);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// Returns the merged reports, sorted by domain and date range.
+    pub fn into_reports(self) -> Vec<Report> {
+        let mut reports = self.reports.into_values().flatten().collect::<Vec<_>>();
+        reports.sort_by(|a, b| {
+            a.policy_published
+                .domain
+                .cmp(&b.policy_published.domain)
+                .then(a.date_range_begin().cmp(&b.date_range_begin()))
+        });
+        reports
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::report::{ActionDisposition, Disposition, Record, Report};
+
+    use super::{IpAnonymization, ReportAggregator};
+
+    fn report(domain: &str, p: Disposition, begin: u64, rows: &[(u8, u32)]) -> Report {
+        let mut report = Report::new()
+            .with_org_name("example.net")
+            .with_domain(domain)
+            .with_p(p)
+            .with_date_range_begin(begin)
+            .with_date_range_end(begin + 86400);
+        for (ip, count) in rows {
+            report.add_record(
+                Record::new()
+                    .with_source_ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, *ip)))
+                    .with_action_disposition(ActionDisposition::None)
+                    .with_header_from(domain.to_lowercase())
+                    .with_count(*count),
+            );
+        }
+        report
+    }
+
+    fn counts(report: &Report) -> Vec<(Option<IpAddr>, u32)> {
+        report
+            .records()
+            .iter()
+            .map(|record| (record.source_ip(), record.count()))
+            .collect()
+    }
+
+    #[test]
+    fn merge_reports() {
+        let ip = |octet| Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, octet)));
+
+        // Overlapping ranges are combined and identical rows summed
+        let mut merged = report("example.org", Disposition::None, 0, &[(1, 2), (2, 1)]);
+        merged.merge(report(
+            "example.org",
+            Disposition::Reject,
+            3600,
+            &[(2, 4), (3, 1)],
+        ));
+        assert_eq!(merged.date_range_begin(), 0);
+        assert_eq!(merged.date_range_end(), 86400 + 3600);
+        assert_eq!(merged.p(), Disposition::Reject);
+        assert_eq!(counts(&merged), vec![(ip(1), 2), (ip(2), 5), (ip(3), 1)]);
+
+        // Aggregation keys reports by domain, policy and period
+        let mut aggregator = ReportAggregator::new().with_period(86400);
+        for report in [
+            report("example.org", Disposition::None, 0, &[(1, 1)]),
+            report("Example.org", Disposition::None, 3600, &[(1, 2)]),
+            report("example.org", Disposition::Reject, 7200, &[(1, 4)]),
+            report("example.org", Disposition::None, 86400, &[(1, 8)]),
+            report("example.com", Disposition::None, 0, &[(1, 16)]),
+        ] {
+            aggregator.add(report);
+        }
+        let reports = aggregator.into_reports();
+        assert_eq!(
+            reports
+                .iter()
+                .map(|report| (
+                    report.domain(),
+                    report.p(),
+                    report.date_range_begin(),
+                    counts(report)
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("example.com", Disposition::None, 0, vec![(ip(1), 16)]),
+                ("example.org", Disposition::None, 0, vec![(ip(1), 3)]),
+                ("example.org", Disposition::Reject, 7200, vec![(ip(1), 4)]),
+                ("example.org", Disposition::None, 86400, vec![(ip(1), 8)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn anonymize_report_ips() {
+        let anonymization = IpAnonymization::default();
+        for (ip, expected) in [
+            ("192.0.2.201", "192.0.2.0"),
+            ("2001:db8:1234:5678::1", "2001:db8:1234::"),
+        ] {
+            assert_eq!(
+                anonymization.anonymize(ip.parse().unwrap()),
+                expected.parse::<IpAddr>().unwrap()
+            );
+        }
+        assert_eq!(
+            IpAnonymization::new(0, 200).anonymize("192.0.2.1".parse().unwrap()),
+            "0.0.0.0".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(IpAnonymization::new(0, 200).ipv6_prefix(), 128);
+
+        let mut aggregator = ReportAggregator::new().with_ip_anonymization(anonymization);
+        aggregator.add(report(
+            "example.org",
+            Disposition::None,
+            0,
+            &[(1, 1), (2, 2)],
+        ));
+        aggregator.add(report("example.org", Disposition::None, 3600, &[(3, 4)]));
+        let reports = aggregator.into_reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            counts(&reports[0]),
+            vec![(Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 0))), 7)]
+        );
+        assert_eq!(
+            reports[0].error(),
+            ["Source IP addresses anonymized to /24 (IPv4) and /48 (IPv6)"]
+        );
+        assert!(reports[0]
+            .to_xml()
+            .contains("<source_ip>192.0.2.0</source_ip>"));
+    }
+}
@@ -0,0 +1,175 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::io;
+
+use mail_builder::headers::address::Address;
+
+use crate::{
+    dmarc::URI,
+    report::{Record, Report, ReportMessage},
+};
+
+impl<'x> ReportMessage<'x> {
+    /// Creates a message for an aggregate report sent by `submitter`, the domain
+    /// of the reporting organization.
+    pub fn new(report: &'x Report, submitter: &'x str, from: impl Into<Address<'x>>) -> Self {
+        ReportMessage {
+            report,
+            submitter,
+            from: from.into(),
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        self.report.subject(self.submitter)
+    }
+
+    pub fn filename(&self) -> String {
+        self.report.filename(self.submitter, None)
+    }
+
+    /// Generates a message with the compressed report for each `rua=` address,
+    /// returning the addresses along with their messages. Reports exceeding the
+    /// size limit of an address are split into several messages, each with a
+    /// part of the records and a numbered Report-ID and filename. Addresses are
+    /// skipped if a single record exceeds their size limit.
+    pub fn to_rfc5322(&self, rua: &'x [URI]) -> io::Result<Vec<(&'x str, String)>> {
+        let mut messages = Vec::with_capacity(rua.len());
+        for uri in rua {
+            let message = self.write(self.report, uri.uri(), None)?;
+            if uri.max_size() == 0 || message.len() <= uri.max_size() {
+                messages.push((uri.uri(), message));
+                continue;
+            }
+
+            let records = self.report.records();
+            let mut parts = 1;
+            while parts < records.len() {
+                parts = (parts * 2).min(records.len());
+                if let Some(split) = self.split(records, parts, uri)? {
+                    messages.extend(split);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    fn split(
+        &self,
+        records: &[Record],
+        parts: usize,
+        uri: &'x URI,
+    ) -> io::Result<Option<Vec<(&'x str, String)>>> {
+        let mut messages = Vec::with_capacity(parts);
+        for (num, records) in records.chunks(records.len().div_ceil(parts)).enumerate() {
+            let num = num + 1;
+            let report = Report {
+                version: self.report.version,
+                report_metadata: self.report.report_metadata.clone(),
+                policy_published: self.report.policy_published.clone(),
+                record: records.to_vec(),
+                extensions: self.report.extensions.clone(),
+            };
+            let report = report.with_report_id(format!("{}.{num}", self.report.report_id()));
+            let message = self.write(&report, uri.uri(), Some(num))?;
+            if message.len() > uri.max_size() {
+                return Ok(None);
+            }
+            messages.push((uri.uri(), message));
+        }
+        Ok(Some(messages))
+    }
+
+    fn write(&self, report: &Report, to: &'x str, unique_id: Option<usize>) -> io::Result<String> {
+        let mut buf = Vec::new();
+        report.write_message(
+            self.submitter,
+            self.from.clone(),
+            to.into(),
+            unique_id,
+            &mut buf,
+        )?;
+        String::from_utf8(buf).map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use mail_parser::{MessageParser, MimeHeaders};
+
+    use crate::{
+        dmarc::URI,
+        report::{ActionDisposition, Record, Report, ReportMessage},
+    };
+
+    #[test]
+    fn dmarc_report_message() {
+        let mut report = Report::new()
+            .with_org_name("example.net")
+            .with_report_id("abc123")
+            .with_domain("example.org")
+            .with_date_range_begin(1000)
+            .with_date_range_end(2000);
+        for octet in 0..64 {
+            report.add_record(
+                Record::new()
+                    .with_source_ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, octet)))
+                    .with_action_disposition(ActionDisposition::None)
+                    .with_header_from("example.org")
+                    .with_count(1),
+            );
+        }
+
+        let message = ReportMessage::new(&report, "example.net", "dmarc@example.net");
+        assert_eq!(
+            message.subject(),
+            "Report Domain: example.org Submitter: example.net Report-ID: <abc123>"
+        );
+        assert_eq!(
+            message.filename(),
+            "example.net!example.org!1000!2000.xml.gz"
+        );
+
+        let full_size = message
+            .to_rfc5322(&[URI::new("rua@example.org", 0)])
+            .unwrap()[0]
+            .1
+            .len();
+        let rua = [
+            URI::new("rua@example.org", 0),
+            URI::new("small@example.org", full_size - 64),
+            URI::new("tiny@example.org", 100),
+        ];
+        let messages = message.to_rfc5322(&rua).unwrap();
+        assert_eq!(messages[0].0, "rua@example.org");
+        assert!(messages.len() > 2);
+
+        // Each part holds a share of the records
+        let mut records = 0;
+        for (num, (uri, message)) in messages.iter().enumerate().skip(1) {
+            assert_eq!(*uri, "small@example.org");
+            assert!(message.len() <= full_size - 64);
+            let message = MessageParser::new().parse(message.as_bytes()).unwrap();
+            let attachment = message.attachment(0).unwrap();
+            assert_eq!(
+                attachment.attachment_name().unwrap(),
+                format!("example.net!example.org!1000!2000!{num}.xml.gz")
+            );
+            let part = Report::parse_rfc5322(message.raw_message()).unwrap();
+            assert_eq!(part.report_id(), format!("abc123.{num}"));
+            records += part.records().len();
+        }
+        assert_eq!(records, 64);
+    }
+}
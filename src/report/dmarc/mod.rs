@@ -8,8 +8,12 @@
  * except according to those terms.
  */
 
+pub mod failure;
 pub mod generate;
+pub mod merge;
+pub mod message;
 pub mod parse;
+pub mod simulate;
 
 use std::fmt::Write;
 use std::net::IpAddr;
@@ -260,6 +264,16 @@ impl Record {
                 crate::dmarc::Policy::Unspecified => ActionDisposition::None,
             }
         };
+        if let (Some(sealer), ActionDisposition::Quarantine | ActionDisposition::Reject) = (
+            &dmarc_output.arc_override,
+            self.row.policy_evaluated.disposition,
+        ) {
+            self.row.policy_evaluated.disposition = ActionDisposition::None;
+            self.row.policy_evaluated.reason.push(
+                PolicyOverrideReason::new(PolicyOverride::LocalPolicy)
+                    .with_comment(format!("arc-override as.d={sealer}")),
+            );
+        }
         self.row.policy_evaluated.dkim = (&dmarc_output.dkim_result).into();
         self.row.policy_evaluated.spf = (&dmarc_output.spf_result).into();
         self
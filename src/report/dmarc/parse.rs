@@ -8,7 +8,7 @@
  * except according to those terms.
  */
 
-use std::io::{BufRead, Cursor, Read};
+use std::io::{BufRead, BufReader, Cursor, Read};
 use std::net::IpAddr;
 use std::str::FromStr;
 
@@ -20,12 +20,27 @@ use quick_xml::reader::Reader;
 use crate::report::{
     ActionDisposition, Alignment, AuthResult, DKIMAuthResult, DateRange, Disposition, DkimResult,
     DmarcResult, Error, Extension, Identifier, PolicyEvaluated, PolicyOverride,
-    PolicyOverrideReason, PolicyPublished, Record, Report, ReportMetadata, Row, SPFAuthResult,
-    SPFDomainScope, SpfResult,
+    PolicyOverrideReason, PolicyPublished, Record, Report, ReportLimit, ReportLimits,
+    ReportMetadata, ReportReader, Row, SPFAuthResult, SPFDomainScope, SpfResult,
 };
 
+/// XML reader that enforces `ReportLimits` on the events it returns.
+pub(crate) struct XmlReader<R> {
+    reader: Reader<R>,
+    limits: ReportLimits,
+    depth: usize,
+    elements: usize,
+    exceeded: Option<ReportLimit>,
+}
+
 impl Report {
     pub fn parse_rfc5322(report: &[u8]) -> Result<Self, Error> {
+        Self::parse_rfc5322_with_limits(report, &ReportLimits::default())
+    }
+
+    /// Parses a report message, failing with `Error::LimitExceeded` if its
+    /// report exceeds the limits.
+    pub fn parse_rfc5322_with_limits(report: &[u8], limits: &ReportLimits) -> Result<Self, Error> {
         let message = MessageParser::new()
             .parse(report)
             .ok_or(Error::MailParseError)?;
@@ -43,10 +58,10 @@ impl Report {
                             .and_then(|n| n.rsplit_once('.'))
                             .map_or(false, |(_, e)| e.eq_ignore_ascii_case("xml")) =>
                 {
-                    match Report::parse_xml(report.as_bytes()) {
+                    match Report::parse_xml_with_limits(report.as_bytes(), limits) {
                         Ok(feedback) => return Ok(feedback),
                         Err(err) => {
-                            error = err.into();
+                            error = err;
                         }
                     }
                 }
@@ -84,15 +99,12 @@ impl Report {
 
                     match rt {
                         ReportType::Gzip => {
-                            let mut file = GzDecoder::new(report.as_ref());
-                            let mut buf = Vec::new();
-                            file.read_to_end(&mut buf)
-                                .map_err(|err| Error::UncompressError(err.to_string()))?;
+                            let buf = limits.read(GzDecoder::new(report.as_ref()), 0)?;
 
-                            match Report::parse_xml(&buf) {
+                            match Report::parse_xml_with_limits(&buf, limits) {
                                 Ok(feedback) => return Ok(feedback),
                                 Err(err) => {
-                                    error = err.into();
+                                    error = err;
                                 }
                             }
                         }
@@ -101,16 +113,13 @@ impl Report {
                                 .map_err(|err| Error::UncompressError(err.to_string()))?;
                             for i in 0..archive.len() {
                                 match archive.by_index(i) {
-                                    Ok(mut file) => {
-                                        let mut buf =
-                                            Vec::with_capacity(file.compressed_size() as usize);
-                                        file.read_to_end(&mut buf).map_err(|err| {
-                                            Error::UncompressError(err.to_string())
-                                        })?;
-                                        match Report::parse_xml(&buf) {
+                                    Ok(file) => {
+                                        let capacity = file.compressed_size() as usize;
+                                        let buf = limits.read(file, capacity)?;
+                                        match Report::parse_xml_with_limits(&buf, limits) {
                                             Ok(feedback) => return Ok(feedback),
                                             Err(err) => {
-                                                error = err.into();
+                                                error = err;
                                             }
                                         }
                                     }
@@ -120,10 +129,10 @@ impl Report {
                                 }
                             }
                         }
-                        ReportType::Xml => match Report::parse_xml(report) {
+                        ReportType::Xml => match Report::parse_xml_with_limits(report, limits) {
                             Ok(feedback) => return Ok(feedback),
                             Err(err) => {
-                                error = err.into();
+                                error = err;
                             }
                         },
                     }
@@ -136,63 +145,167 @@ impl Report {
     }
 
     pub fn parse_xml(report: &[u8]) -> Result<Self, String> {
-        let mut version: f32 = 0.0;
-        let mut report_metadata = None;
-        let mut policy_published = None;
-        let mut record = Vec::new();
-        let mut extensions = Vec::new();
+        let mut reader = ReportReader::new(report);
+        let record = (&mut reader).collect::<Result<Vec<_>, _>>()?;
+        reader
+            .into_report()
+            .map(|report| Report { record, ..report })
+    }
 
-        let mut reader = Reader::from_reader(report);
+    /// Parses an XML report, failing with `Error::LimitExceeded` if it exceeds
+    /// the limits.
+    pub fn parse_xml_with_limits(report: &[u8], limits: &ReportLimits) -> Result<Self, Error> {
+        if report.len() > limits.max_size() {
+            return Err(Error::LimitExceeded(ReportLimit::Size));
+        }
+        let mut reader = ReportReader::new(report).with_limits(*limits);
+        match (&mut reader).collect::<Result<Vec<_>, _>>() {
+            Ok(record) => reader
+                .into_report()
+                .map(|report| Report { record, ..report })
+                .map_err(Error::ReportParseError),
+            Err(err) => Err(match reader.limit_exceeded() {
+                Some(limit) => Error::LimitExceeded(limit),
+                None => Error::ReportParseError(err),
+            }),
+        }
+    }
+}
+
+impl<R: BufRead> ReportReader<R> {
+    pub fn new(reader: R) -> Self {
+        let mut reader = Reader::from_reader(reader);
         reader.config_mut().trim_text(true);
 
-        let mut buf = Vec::with_capacity(128);
-        let mut found_feedback = false;
+        ReportReader {
+            reader: XmlReader {
+                reader,
+                limits: ReportLimits::default(),
+                depth: 0,
+                elements: 0,
+                exceeded: None,
+            },
+            buf: Vec::with_capacity(128),
+            report: Report::default(),
+            has_metadata: false,
+            has_policy: false,
+            found_feedback: false,
+            done: false,
+        }
+    }
+
+    /// Sets the limits enforced while reading, `ReportLimits::default()` unless set.
+    pub fn with_limits(mut self, limits: ReportLimits) -> Self {
+        self.reader.limits = limits;
+        self
+    }
+
+    /// Returns the limit exceeded by the report, if reading failed because of it.
+    pub fn limit_exceeded(&self) -> Option<ReportLimit> {
+        self.reader.exceeded
+    }
+
+    /// Returns the report parsed so far, without its records. The metadata and
+    /// published policy precede the records, so they are available once the
+    /// first record has been read.
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    /// Returns the report without its records, reading any remaining records.
+    pub fn into_report(mut self) -> Result<Report, String> {
+        for record in &mut self {
+            record?;
+        }
+
+        if !self.has_metadata {
+            Err("Missing feedback/report_metadata tag.".to_string())
+        } else if !self.has_policy {
+            Err("Missing feedback/policy_published tag.".to_string())
+        } else {
+            Ok(self.report)
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Record>, String> {
+        loop {
+            self.buf.clear();
+            let tag = match self.reader.next_tag(&mut self.buf)? {
+                Some(tag) => tag,
+                None => return Ok(None),
+            };
 
-        while let Some(tag) = reader.next_tag(&mut buf)? {
             match tag.name().as_ref() {
-                b"feedback" if !found_feedback => {
-                    found_feedback = true;
+                b"feedback" if !self.found_feedback => {
+                    self.found_feedback = true;
                 }
-                b"version" if found_feedback => {
-                    version = reader.next_value(&mut buf)?.unwrap_or(0.0);
+                b"version" if self.found_feedback => {
+                    self.report.version = self.reader.next_value(&mut self.buf)?.unwrap_or(0.0);
                 }
-                b"report_metadata" if found_feedback => {
-                    report_metadata = ReportMetadata::parse(&mut reader, &mut buf)?.into();
+                b"report_metadata" if self.found_feedback => {
+                    self.report.report_metadata =
+                        ReportMetadata::parse(&mut self.reader, &mut self.buf)?;
+                    self.has_metadata = true;
                 }
-                b"policy_published" if found_feedback => {
-                    policy_published = PolicyPublished::parse(&mut reader, &mut buf)?.into();
+                b"policy_published" if self.found_feedback => {
+                    self.report.policy_published =
+                        PolicyPublished::parse(&mut self.reader, &mut self.buf)?;
+                    self.has_policy = true;
                 }
-                b"record" if found_feedback => {
-                    record.push(Record::parse(&mut reader, &mut buf)?);
+                b"record" if self.found_feedback => {
+                    return Record::parse(&mut self.reader, &mut self.buf).map(Some);
                 }
-                b"extensions" if found_feedback => {
-                    Extension::parse(&mut reader, &mut buf, &mut extensions)?;
+                b"extensions" if self.found_feedback => {
+                    Extension::parse(&mut self.reader, &mut self.buf, &mut self.report.extensions)?;
                 }
                 b"" => {}
-                other if !found_feedback => {
+                other if !self.found_feedback => {
                     return Err(format!(
                         "Unexpected tag {} at position {}.",
                         String::from_utf8_lossy(other),
-                        reader.buffer_position()
+                        self.reader.buffer_position()
                     ));
                 }
                 _ => (),
             }
         }
+    }
+}
 
-        Ok(Report {
-            version,
-            report_metadata: report_metadata.ok_or("Missing feedback/report_metadata tag.")?,
-            policy_published: policy_published.ok_or("Missing feedback/policy_published tag.")?,
-            record,
-            extensions,
-        })
+impl<R: Read> ReportReader<BufReader<R>> {
+    /// Reads an uncompressed XML report. Reports in a ZIP archive can be read
+    /// by passing one of the archive's files.
+    pub fn from_read(reader: R) -> Self {
+        ReportReader::new(BufReader::new(reader))
+    }
+}
+
+impl<R: Read> ReportReader<BufReader<GzDecoder<R>>> {
+    /// Reads a gzip compressed XML report.
+    pub fn from_gzip(reader: R) -> Self {
+        ReportReader::new(BufReader::new(GzDecoder::new(reader)))
+    }
+}
+
+impl<R: BufRead> Iterator for ReportReader<R> {
+    type Item = Result<Record, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = self.next_record();
+        if !matches!(result, Ok(Some(_))) {
+            self.done = true;
+        }
+        result.transpose()
     }
 }
 
 impl ReportMetadata {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut rm = ReportMetadata::default();
@@ -232,7 +345,7 @@ impl ReportMetadata {
 
 impl DateRange {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut dr = DateRange::default();
@@ -258,7 +371,7 @@ impl DateRange {
 
 impl PolicyPublished {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut p = PolicyPublished::default();
@@ -304,7 +417,7 @@ impl PolicyPublished {
 
 impl Extension {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
         extensions: &mut Vec<Extension>,
     ) -> Result<(), String> {
@@ -338,7 +451,7 @@ impl Extension {
 
 impl Record {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut r = Record::default();
@@ -370,7 +483,7 @@ impl Record {
 
 impl Row {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut r = Row::default();
@@ -401,7 +514,7 @@ impl Row {
 
 impl PolicyEvaluated {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut pe = PolicyEvaluated::default();
@@ -433,7 +546,7 @@ impl PolicyEvaluated {
 
 impl PolicyOverrideReason {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut por = PolicyOverrideReason::default();
@@ -459,7 +572,7 @@ impl PolicyOverrideReason {
 
 impl Identifier {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut i = Identifier::default();
@@ -488,7 +601,7 @@ impl Identifier {
 
 impl AuthResult {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut ar = AuthResult::default();
@@ -514,7 +627,7 @@ impl AuthResult {
 
 impl DKIMAuthResult {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut dar = DKIMAuthResult::default();
@@ -546,7 +659,7 @@ impl DKIMAuthResult {
 
 impl SPFAuthResult {
     pub(crate) fn parse<R: BufRead>(
-        reader: &mut Reader<R>,
+        reader: &mut XmlReader<R>,
         buf: &mut Vec<u8>,
     ) -> Result<Self, String> {
         let mut sar = SPFAuthResult::default();
@@ -695,16 +808,55 @@ trait ReaderHelper {
     fn skip_tag(&mut self, buf: &mut Vec<u8>) -> Result<(), String>;
 }
 
-impl<R: BufRead> ReaderHelper for Reader<R> {
-    fn next_tag<'x>(&mut self, buf: &'x mut Vec<u8>) -> Result<Option<BytesStart<'x>>, String> {
-        match self.read_event_into(buf) {
-            Ok(Event::Start(e)) => Ok(Some(e)),
-            Ok(Event::End(_)) | Ok(Event::Eof) => Ok(None),
-            Err(e) => Err(format!(
+impl<R: BufRead> XmlReader<R> {
+    fn read_event_into<'x>(&mut self, buf: &'x mut Vec<u8>) -> Result<Event<'x>, String> {
+        if let Some(limit) = self.exceeded {
+            return Err(limit.to_string());
+        }
+
+        let event = self.reader.read_event_into(buf).map_err(|err| {
+            format!(
                 "Error at position {}: {:?}",
-                self.buffer_position(),
-                e
-            )),
+                self.reader.buffer_position(),
+                err
+            )
+        })?;
+        match &event {
+            Event::Start(_) => {
+                self.depth += 1;
+                self.elements += 1;
+            }
+            Event::Empty(_) => {
+                self.elements += 1;
+            }
+            Event::End(_) => {
+                self.depth = self.depth.saturating_sub(1);
+            }
+            _ => (),
+        }
+
+        self.exceeded = if self.reader.buffer_position() > self.limits.max_size() {
+            Some(ReportLimit::Size)
+        } else if self.depth > self.limits.max_depth() {
+            Some(ReportLimit::Depth)
+        } else if self.elements > self.limits.max_elements() {
+            Some(ReportLimit::Elements)
+        } else {
+            return Ok(event);
+        };
+        Err(self.exceeded.unwrap().to_string())
+    }
+
+    fn buffer_position(&self) -> usize {
+        self.reader.buffer_position()
+    }
+}
+
+impl<R: BufRead> ReaderHelper for XmlReader<R> {
+    fn next_tag<'x>(&mut self, buf: &'x mut Vec<u8>) -> Result<Option<BytesStart<'x>>, String> {
+        match self.read_event_into(buf)? {
+            Event::Start(e) => Ok(Some(e)),
+            Event::End(_) | Event::Eof => Ok(None),
             _ => Ok(Some(BytesStart::new(""))),
         }
     }
@@ -712,21 +864,21 @@ impl<R: BufRead> ReaderHelper for Reader<R> {
     fn next_value<T: FromStr>(&mut self, buf: &mut Vec<u8>) -> Result<Option<T>, String> {
         let mut value = None;
         loop {
-            match self.read_event_into(buf) {
-                Ok(Event::Text(e)) => {
+            match self.read_event_into(buf)? {
+                Event::Text(e) => {
                     value = e.unescape().ok().and_then(|v| T::from_str(v.as_ref()).ok());
                 }
-                Ok(Event::End(_)) => {
+                Event::End(_) => {
                     break;
                 }
-                Ok(Event::Start(e)) => {
+                Event::Start(e) => {
                     return Err(format!(
                         "Expected value, found unexpected tag {} at position {}.",
                         String::from_utf8_lossy(e.name().as_ref()),
                         self.buffer_position()
                     ));
                 }
-                Ok(Event::Eof) => {
+                Event::Eof => {
                     return Err(format!(
                         "Expected value, found unexpected EOF at position {}.",
                         self.buffer_position()
@@ -742,18 +894,18 @@ impl<R: BufRead> ReaderHelper for Reader<R> {
     fn skip_tag(&mut self, buf: &mut Vec<u8>) -> Result<(), String> {
         let mut tag_count = 0;
         loop {
-            match self.read_event_into(buf) {
-                Ok(Event::End(_)) => {
+            match self.read_event_into(buf)? {
+                Event::End(_) => {
                     if tag_count == 0 {
                         break;
                     } else {
                         tag_count -= 1;
                     }
                 }
-                Ok(Event::Start(_)) => {
+                Event::Start(_) => {
                     tag_count += 1;
                 }
-                Ok(Event::Eof) => {
+                Event::Eof => {
                     return Err(format!(
                         "Expected value, found unexpected EOF at position {}.",
                         self.buffer_position()
@@ -768,9 +920,11 @@ impl<R: BufRead> ReaderHelper for Reader<R> {
 
 #[cfg(test)]
 mod test {
-    use std::{fs, path::PathBuf};
+    use std::{fs, io::Write, path::PathBuf};
 
-    use crate::report::Report;
+    use flate2::{write::GzEncoder, Compression};
+
+    use crate::report::{Report, ReportReader};
 
     #[test]
     fn dmarc_report_parse() {
@@ -831,4 +985,42 @@ mod test {
             .unwrap();*/
         }
     }
+
+    #[test]
+    fn dmarc_report_stream() {
+        let mut test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        test_dir.push("resources");
+        test_dir.push("dmarc-feedback");
+
+        for file_name in fs::read_dir(&test_dir).unwrap() {
+            let file_name = file_name.unwrap().path();
+            if !file_name.extension().unwrap().to_str().unwrap().eq("xml") {
+                continue;
+            }
+            let xml = fs::read(&file_name).unwrap();
+            let expected = Report::parse_xml(&xml).unwrap();
+
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&xml).unwrap();
+            let gzip = encoder.finish().unwrap();
+
+            let mut reader = ReportReader::from_read(xml.as_slice());
+            let records = (&mut reader).collect::<Result<Vec<_>, _>>().unwrap();
+            assert_eq!(records, expected.records());
+            assert_eq!(reader.report().domain(), expected.domain());
+
+            let mut reader = ReportReader::from_gzip(gzip.as_slice());
+            let first = reader.next().unwrap().unwrap();
+            assert_eq!(&first, &expected.records()[0]);
+            assert_eq!(reader.report().report_id(), expected.report_id());
+            let report = reader.into_report().unwrap();
+            assert!(report.records().is_empty());
+            assert_eq!(report.extensions, expected.extensions);
+        }
+
+        // Errors end the iteration
+        let mut reader = ReportReader::new(&b"<feedback><record><row></count>"[..]);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
 }
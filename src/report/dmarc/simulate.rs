@@ -0,0 +1,310 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{
+    report::{Disposition, DmarcResult, PolicyOverride, Record, Report},
+    Domain,
+};
+
+/// A DMARC policy to evaluate against recorded authentication outcomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedPolicy {
+    p: Disposition,
+    sp: Disposition,
+    pct: u8,
+}
+
+/// Messages that would have received each disposition under a simulated policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SimulationSummary {
+    passed: u64,
+    none: u64,
+    quarantine: u64,
+    reject: u64,
+    overridden: u64,
+}
+
+/// Replays the rows of aggregate reports against alternative DMARC policies,
+/// to estimate the impact of tightening a policy before publishing it.
+#[derive(Debug, Clone)]
+pub struct PolicySimulator {
+    policies: Vec<SimulatedPolicy>,
+    honor_overrides: bool,
+    passed: u64,
+    overridden: u64,
+    // Failed messages from the policy domain and from its subdomains
+    failed: [u64; 2],
+}
+
+impl SimulatedPolicy {
+    /// Creates a policy applied to all failing messages, and to subdomains.
+    pub fn new(p: Disposition) -> Self {
+        SimulatedPolicy {
+            p,
+            sp: Disposition::Unspecified,
+            pct: 100,
+        }
+    }
+
+    /// Sets a different policy for messages from subdomains.
+    pub fn with_sp(mut self, sp: Disposition) -> Self {
+        self.sp = sp;
+        self
+    }
+
+    /// Applies the policy to `pct` percent of the failing messages, the rest
+    /// receiving the next less strict disposition (RFC 7489 section 6.6.4).
+    pub fn with_pct(mut self, pct: u8) -> Self {
+        self.pct = pct.min(100);
+        self
+    }
+
+    pub fn p(&self) -> Disposition {
+        self.p
+    }
+
+    pub fn sp(&self) -> Disposition {
+        self.sp
+    }
+
+    pub fn pct(&self) -> u8 {
+        self.pct
+    }
+
+    fn summary(&self, passed: u64, overridden: u64, failed: [u64; 2]) -> SimulationSummary {
+        let mut summary = SimulationSummary {
+            passed,
+            none: overridden,
+            overridden,
+            ..Default::default()
+        };
+        for (disposition, count) in [self.p, self.sp]
+            .into_iter()
+            .map(|d| match d {
+                Disposition::Unspecified => self.p,
+                d => d,
+            })
+            .zip(failed)
+        {
+            let applied = (count * u64::from(self.pct) + 50) / 100;
+            let sampled_out = count - applied;
+            match disposition {
+                Disposition::Reject => {
+                    summary.reject += applied;
+                    summary.quarantine += sampled_out;
+                }
+                Disposition::Quarantine => {
+                    summary.quarantine += applied;
+                    summary.none += sampled_out;
+                }
+                Disposition::None | Disposition::Unspecified => {
+                    summary.none += count;
+                }
+            }
+        }
+        summary
+    }
+}
+
+impl SimulationSummary {
+    /// Total number of messages evaluated.
+    pub fn total(&self) -> u64 {
+        self.passed + self.failed()
+    }
+
+    /// Messages that passed DMARC.
+    pub fn passed(&self) -> u64 {
+        self.passed
+    }
+
+    /// Messages that failed DMARC, whatever their disposition.
+    pub fn failed(&self) -> u64 {
+        self.none + self.quarantine + self.reject
+    }
+
+    /// Failed messages that would have been delivered normally.
+    pub fn none(&self) -> u64 {
+        self.none
+    }
+
+    /// Failed messages that would have been quarantined.
+    pub fn quarantine(&self) -> u64 {
+        self.quarantine
+    }
+
+    /// Failed messages that would have been rejected.
+    pub fn reject(&self) -> u64 {
+        self.reject
+    }
+
+    /// Failed messages delivered because of a recorded policy override,
+    /// included in `none`.
+    pub fn overridden(&self) -> u64 {
+        self.overridden
+    }
+}
+
+impl Default for PolicySimulator {
+    fn default() -> Self {
+        PolicySimulator {
+            policies: Vec::new(),
+            honor_overrides: true,
+            passed: 0,
+            overridden: 0,
+            failed: [0; 2],
+        }
+    }
+}
+
+impl PolicySimulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a policy to simulate.
+    pub fn with_policy(mut self, policy: SimulatedPolicy) -> Self {
+        self.policies.push(policy);
+        self
+    }
+
+    /// Adds a policy for each percentage of a gradual rollout, such as
+    /// `Disposition::Quarantine` at 10, 25, 50 and 100 percent.
+    pub fn with_ramp(mut self, p: Disposition, pcts: impl IntoIterator<Item = u8>) -> Self {
+        for pct in pcts {
+            self = self.with_policy(SimulatedPolicy::new(p).with_pct(pct));
+        }
+        self
+    }
+
+    /// Delivers failing messages that the receiver overrode for a reason other
+    /// than sampling, such as forwarding or mailing lists, as they were
+    /// recorded. Enabled by default.
+    pub fn with_honor_overrides(mut self, honor_overrides: bool) -> Self {
+        self.honor_overrides = honor_overrides;
+        self
+    }
+
+    /// Adds the rows of an aggregate report.
+    pub fn add(&mut self, report: &Report) {
+        for record in report.records() {
+            self.add_record(report.domain(), record);
+        }
+    }
+
+    /// Adds a row recorded for messages of a policy domain.
+    pub fn add_record(&mut self, domain: &str, record: &Record) {
+        let count = u64::from(record.count());
+        if record.dmarc_dkim_result() == DmarcResult::Pass
+            || record.dmarc_spf_result() == DmarcResult::Pass
+        {
+            self.passed += count;
+        } else if self.honor_overrides
+            && record
+                .policy_override_reason()
+                .iter()
+                .any(|reason| reason.policy_override() != PolicyOverride::SampledOut)
+        {
+            self.overridden += count;
+        } else {
+            let header_from = Domain::normalize(record.header_from());
+            let is_subdomain = !header_from.is_empty() && header_from != Domain::normalize(domain);
+            self.failed[usize::from(is_subdomain)] += count;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.passed == 0 && self.overridden == 0 && self.failed == [0; 2]
+    }
+
+    /// Returns the outcome of each simulated policy, in the order they were added.
+    pub fn summaries(&self) -> Vec<(SimulatedPolicy, SimulationSummary)> {
+        self.policies
+            .iter()
+            .map(|policy| {
+                (
+                    *policy,
+                    policy.summary(self.passed, self.overridden, self.failed),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::report::{
+        ActionDisposition, Disposition, DmarcResult, PolicyOverride, PolicyOverrideReason, Record,
+        Report,
+    };
+
+    use super::{PolicySimulator, SimulatedPolicy};
+
+    #[test]
+    fn simulate_policies() {
+        let row = |header_from: &str, dmarc: DmarcResult, count: u32| {
+            Record::new()
+                .with_action_disposition(ActionDisposition::None)
+                .with_header_from(header_from)
+                .with_dmarc_dkim_result(dmarc)
+                .with_dmarc_spf_result(DmarcResult::Fail)
+                .with_count(count)
+        };
+        let report = Report::new()
+            .with_domain("example.org")
+            .with_p(Disposition::None)
+            .with_record(row("example.org", DmarcResult::Pass, 900))
+            .with_record(row("Example.org", DmarcResult::Fail, 60))
+            .with_record(row("news.example.org", DmarcResult::Fail, 30))
+            .with_record(
+                row("example.org", DmarcResult::Fail, 10).with_policy_override_reason(
+                    PolicyOverrideReason::new(PolicyOverride::MailingList),
+                ),
+            );
+
+        let mut simulator = PolicySimulator::new()
+            .with_policy(SimulatedPolicy::new(Disposition::None))
+            .with_ramp(Disposition::Quarantine, [25, 100])
+            .with_policy(SimulatedPolicy::new(Disposition::Reject).with_pct(50))
+            .with_policy(
+                SimulatedPolicy::new(Disposition::Reject).with_sp(Disposition::Quarantine),
+            );
+        assert!(simulator.is_empty());
+        simulator.add(&report);
+        assert!(!simulator.is_empty());
+
+        let summaries = simulator.summaries();
+        assert_eq!(summaries.len(), 5);
+        assert_eq!(summaries[1].0.pct(), 25);
+        assert!(summaries
+            .iter()
+            .all(|(_, s)| s.total() == 1000 && s.passed() == 900 && s.overridden() == 10));
+        assert_eq!(
+            summaries
+                .iter()
+                .map(|(_, s)| (s.none(), s.quarantine(), s.reject()))
+                .collect::<Vec<_>>(),
+            [
+                (100, 0, 0),
+                (77, 23, 0),
+                (10, 90, 0),
+                (10, 45, 45),
+                (10, 30, 60)
+            ]
+        );
+
+        // Overrides can be ignored
+        let mut simulator = PolicySimulator::new()
+            .with_honor_overrides(false)
+            .with_policy(SimulatedPolicy::new(Disposition::Reject));
+        simulator.add(&report);
+        let (_, summary) = simulator.summaries()[0];
+        assert_eq!((summary.reject(), summary.overridden()), (100, 0));
+    }
+}
@@ -0,0 +1,115 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use mail_parser::{MessageParser, MimeHeaders};
+
+use crate::report::{tlsrpt::TlsReport, Error, Feedback, IncomingReport, Report, ReportLimits};
+
+impl<'x> IncomingReport<'x> {
+    /// Parses a report message, detecting whether it contains an ARF feedback
+    /// report, a TLS-RPT report or a DMARC aggregate report from its MIME
+    /// structure, content types and attachment names.
+    pub fn parse_rfc5322(message: &'x [u8]) -> Result<Self, Error> {
+        Self::parse_rfc5322_with_limits(message, &ReportLimits::default())
+    }
+
+    /// Parses a report message, failing with `Error::LimitExceeded` if its
+    /// DMARC or TLS-RPT report exceeds the limits.
+    pub fn parse_rfc5322_with_limits(
+        message: &'x [u8],
+        limits: &ReportLimits,
+    ) -> Result<Self, Error> {
+        let parsed = MessageParser::new()
+            .parse(message)
+            .ok_or(Error::MailParseError)?;
+        let report_type = parsed
+            .content_type()
+            .filter(|ct| {
+                ct.ctype().eq_ignore_ascii_case("multipart")
+                    && ct
+                        .subtype()
+                        .is_some_and(|st| st.eq_ignore_ascii_case("report"))
+            })
+            .and_then(|ct| ct.attribute("report-type"))
+            .unwrap_or_default();
+
+        if report_type.eq_ignore_ascii_case("feedback-report")
+            || parsed
+                .parts
+                .iter()
+                .any(|part| part.is_content_type("message", "feedback-report"))
+        {
+            Feedback::parse_rfc5322(message)
+                .map(|feedback| IncomingReport::Feedback(feedback.into()))
+        } else if report_type.eq_ignore_ascii_case("tlsrpt")
+            || parsed.header_raw("TLS-Report-Domain").is_some()
+            || parsed.parts.iter().any(|part| {
+                part.content_type().is_some_and(|ct| {
+                    ct.subtype()
+                        .is_some_and(|st| st.to_ascii_lowercase().starts_with("tlsrpt"))
+                }) || part
+                    .attachment_name()
+                    .is_some_and(|name| name.to_ascii_lowercase().contains(".json"))
+            })
+        {
+            TlsReport::parse_rfc5322_with_limits(message, limits).map(IncomingReport::Tls)
+        } else {
+            Report::parse_rfc5322_with_limits(message, limits).map(IncomingReport::Dmarc)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::report::{extract, tlsrpt::TlsReport, Error, Feedback, IncomingReport, Report};
+
+    #[test]
+    fn extract_reports() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources");
+
+        for (dir, expected) in [
+            ("dmarc-feedback", "dmarc"),
+            ("tlsrpt", "tls"),
+            ("arf", "arf"),
+        ] {
+            for file in fs::read_dir(path.join(dir)).unwrap() {
+                let file = file.unwrap().path();
+                if file.extension().is_none_or(|e| e != "eml") {
+                    continue;
+                }
+                let message = fs::read(&file).unwrap();
+                match extract(&message)
+                    .unwrap_or_else(|err| panic!("Failed to parse {}: {:?}", file.display(), err))
+                {
+                    IncomingReport::Dmarc(report) => {
+                        assert_eq!(expected, "dmarc", "{}", file.display());
+                        assert_eq!(report, Report::parse_rfc5322(&message).unwrap());
+                    }
+                    IncomingReport::Tls(report) => {
+                        assert_eq!(expected, "tls", "{}", file.display());
+                        assert_eq!(report, TlsReport::parse_rfc5322(&message).unwrap());
+                    }
+                    IncomingReport::Feedback(feedback) => {
+                        assert_eq!(expected, "arf", "{}", file.display());
+                        assert_eq!(*feedback, Feedback::parse_rfc5322(&message).unwrap());
+                    }
+                }
+            }
+        }
+
+        assert_eq!(
+            extract(b"From: a@example.org\r\nSubject: Hi\r\n\r\nHello\r\n"),
+            Err(Error::NoReportsFound)
+        );
+    }
+}
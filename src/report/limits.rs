@@ -0,0 +1,237 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{fmt::Display, io::Read};
+
+use super::{Error, ReportLimit, ReportLimits};
+
+impl ReportLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum size of a report in bytes, applied after decompression.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the maximum nesting depth of XML elements or JSON objects and arrays.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the maximum number of XML elements or JSON values in a report.
+    pub fn with_max_elements(mut self, max_elements: usize) -> Self {
+        self.max_elements = max_elements;
+        self
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn max_elements(&self) -> usize {
+        self.max_elements
+    }
+
+    /// Reads a possibly compressed report, failing once it exceeds the maximum size.
+    pub(crate) fn read(&self, reader: impl Read, capacity: usize) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::with_capacity(capacity.min(self.max_size));
+        reader
+            .take(self.max_size as u64 + 1)
+            .read_to_end(&mut buf)
+            .map_err(|err| Error::UncompressError(err.to_string()))?;
+        if buf.len() <= self.max_size {
+            Ok(buf)
+        } else {
+            Err(Error::LimitExceeded(ReportLimit::Size))
+        }
+    }
+
+    /// Checks the size, nesting depth and number of values of a JSON document
+    /// before it is deserialized.
+    pub(crate) fn check_json(&self, json: &[u8]) -> Result<(), ReportLimit> {
+        if json.len() > self.max_size {
+            return Err(ReportLimit::Size);
+        }
+
+        let mut depth = 0;
+        let mut values = 1;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut opened = false;
+
+        for &ch in json {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if ch == b'\\' {
+                    escaped = true;
+                } else if ch == b'"' {
+                    in_string = false;
+                }
+                continue;
+            } else if ch.is_ascii_whitespace() {
+                continue;
+            }
+
+            // The first value of a non-empty object or array
+            if std::mem::take(&mut opened) && ch != b'}' && ch != b']' {
+                values += 1;
+            }
+
+            match ch {
+                b'"' => {
+                    in_string = true;
+                }
+                b'{' | b'[' => {
+                    depth += 1;
+                    opened = true;
+                    if depth > self.max_depth {
+                        return Err(ReportLimit::Depth);
+                    }
+                }
+                b'}' | b']' => {
+                    depth -= usize::from(depth > 0);
+                }
+                b',' => {
+                    values += 1;
+                }
+                _ => continue,
+            }
+
+            if values > self.max_elements {
+                return Err(ReportLimit::Elements);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ReportLimits {
+    fn default() -> Self {
+        ReportLimits {
+            max_size: 100 * 1024 * 1024,
+            max_depth: 32,
+            max_elements: 10_000_000,
+        }
+    }
+}
+
+impl Display for ReportLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportLimit::Size => write!(f, "Report exceeds the maximum size."),
+            ReportLimit::Depth => write!(f, "Report exceeds the maximum nesting depth."),
+            ReportLimit::Elements => write!(f, "Report exceeds the maximum number of elements."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+    use crate::report::{tlsrpt::TlsReport, Error, Report, ReportLimit, ReportLimits};
+
+    #[test]
+    fn report_limits() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources");
+        let xml = fs::read(path.join("dmarc-feedback").join("001.xml")).unwrap();
+        let json = fs::read(path.join("tlsrpt").join("rpt01.json")).unwrap();
+
+        // Reports within the limits
+        let limits = ReportLimits::new().with_max_depth(8);
+        assert_eq!(
+            Report::parse_xml_with_limits(&xml, &limits).unwrap(),
+            Report::parse_xml(&xml).unwrap()
+        );
+        assert_eq!(
+            TlsReport::parse_json_with_limits(&json, &limits).unwrap(),
+            TlsReport::parse_json(&json).unwrap()
+        );
+
+        // Reports exceeding the limits
+        for (limits, expected) in [
+            (
+                ReportLimits::new().with_max_size(xml.len() / 2),
+                ReportLimit::Size,
+            ),
+            (ReportLimits::new().with_max_depth(2), ReportLimit::Depth),
+            (
+                ReportLimits::new().with_max_elements(10),
+                ReportLimit::Elements,
+            ),
+        ] {
+            assert_eq!(
+                Report::parse_xml_with_limits(&xml, &limits),
+                Err(Error::LimitExceeded(expected))
+            );
+            assert_eq!(
+                TlsReport::parse_json_with_limits(&json, &limits),
+                Err(Error::LimitExceeded(expected))
+            );
+        }
+
+        // Deeply nested elements
+        let nested = format!(
+            "<feedback>{}{}</feedback>",
+            "<a>".repeat(1000),
+            "</a>".repeat(1000)
+        );
+        assert_eq!(
+            Report::parse_xml_with_limits(nested.as_bytes(), &ReportLimits::default()),
+            Err(Error::LimitExceeded(ReportLimit::Depth))
+        );
+        let nested = format!("{{\"a\":{}1{}}}", "[".repeat(1000), "]".repeat(1000));
+        assert_eq!(
+            TlsReport::parse_json(nested.as_bytes()),
+            Err(Error::LimitExceeded(ReportLimit::Depth))
+        );
+
+        // JSON values are counted outside strings only
+        let limits = ReportLimits::new().with_max_elements(5);
+        assert_eq!(
+            limits.check_json(br#"{"a": "[1, {2}]", "b": [], "c": [{}]}"#),
+            Ok(())
+        );
+        assert_eq!(
+            limits.check_json(br#"{"a": "\"[1]", "b": [1, 2, 3]}"#),
+            Err(ReportLimit::Elements)
+        );
+
+        // Compressed reports are bounded after decompression
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&vec![b' '; 1024 * 1024]).unwrap();
+        let bomb = encoder.finish().unwrap();
+        let limits = ReportLimits::new().with_max_size(64 * 1024);
+        assert_eq!(
+            limits.read(GzDecoder::new(bomb.as_slice()), bomb.len()),
+            Err(Error::LimitExceeded(ReportLimit::Size))
+        );
+        assert_eq!(
+            ReportLimits::default()
+                .read(GzDecoder::new(bomb.as_slice()), 0)
+                .unwrap()
+                .len(),
+            1024 * 1024
+        );
+    }
+}
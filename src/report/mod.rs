@@ -10,6 +10,8 @@
 
 pub mod arf;
 pub mod dmarc;
+pub mod incoming;
+pub mod limits;
 pub mod tlsrpt;
 
 use std::{borrow::Cow, net::IpAddr};
@@ -199,12 +201,58 @@ pub struct Report {
 
 impl Eq for Report {}
 
+/// Streaming parser of DMARC aggregate reports that yields one record at a time.
+pub struct ReportReader<R: std::io::BufRead> {
+    reader: dmarc::parse::XmlReader<R>,
+    buf: Vec<u8>,
+    report: Report,
+    has_metadata: bool,
+    has_policy: bool,
+    found_feedback: bool,
+    done: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     MailParseError,
     ReportParseError(String),
     UncompressError(String),
     NoReportsFound,
+    LimitExceeded(ReportLimit),
+}
+
+/// Bounds on the size and complexity of parsed reports, protecting report
+/// collectors from crafted reports that exhaust memory or CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportLimits {
+    max_size: usize,
+    max_depth: usize,
+    max_elements: usize,
+}
+
+/// A limit of `ReportLimits` exceeded by a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportLimit {
+    /// Size of the report in bytes, after decompression.
+    Size,
+    /// Nesting depth of XML elements or JSON objects and arrays.
+    Depth,
+    /// Number of XML elements or JSON values.
+    Elements,
+}
+
+/// A report received by e-mail, as returned by [`extract`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncomingReport<'x> {
+    Dmarc(Report),
+    Tls(tlsrpt::TlsReport),
+    Feedback(Box<Feedback<'x>>),
+}
+
+/// Extracts a DMARC aggregate report, TLS-RPT report or ARF feedback report
+/// from a raw message, decompressing its attachments as needed.
+pub fn extract(message: &[u8]) -> Result<IncomingReport<'_>, Error> {
+    IncomingReport::parse_rfc5322(message)
 }
 
 impl From<String> for Error {
@@ -247,6 +295,24 @@ pub struct Feedback<'x> {
     headers: Option<Cow<'x, str>>,
 }
 
+/// DMARC failure report (RFC 7489 Section 7.3) for the `ruf=` addresses of a DMARC record.
+#[derive(Debug, Clone)]
+pub struct DmarcFailureReport<'x> {
+    output: &'x crate::DmarcOutput,
+    headers: Cow<'x, str>,
+    feedback: Feedback<'x>,
+    redact: bool,
+    last_report: Option<u64>,
+}
+
+/// DMARC aggregate report message (RFC 7489 Section 7.2.1.1) for the `rua=` addresses of a DMARC record.
+#[derive(Debug, Clone)]
+pub struct ReportMessage<'x> {
+    report: &'x Report,
+    submitter: &'x str,
+    from: mail_builder::headers::address::Address<'x>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize, Deserialize, Default)]
 pub enum AuthFailureType {
     Adsp,
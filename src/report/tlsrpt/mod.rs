@@ -15,6 +15,8 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub mod generate;
 pub mod parse;
+pub mod recorder;
+pub mod summary;
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
 pub struct TlsReport {
@@ -51,7 +53,7 @@ pub struct Policy {
     pub failure_details: Vec<FailureDetails>,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone)]
 pub struct PolicyDetails {
     #[serde(rename = "policy-type")]
     pub policy_type: PolicyType,
@@ -80,7 +82,7 @@ pub struct Summary {
     pub total_failure: u32,
 }
 
-#[derive(Debug, Default, Hash, PartialEq, Eq, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone)]
 pub struct FailureDetails {
     #[serde(rename = "result-type")]
     pub result_type: ResultType,
@@ -120,7 +122,9 @@ pub struct DateRange {
     pub end_datetime: DateTime,
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
+#[derive(
+    Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Copy,
+)]
 pub enum PolicyType {
     #[serde(rename = "tlsa")]
     Tlsa,
@@ -133,7 +137,9 @@ pub enum PolicyType {
     Other,
 }
 
-#[derive(Debug, Default, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(
+    Debug, Default, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
 pub enum ResultType {
     #[serde(rename = "starttls-not-supported")]
     StartTlsNotSupported,
@@ -212,4 +218,19 @@ impl FailureDetails {
         self.receiving_ip = Some(value);
         self
     }
+
+    pub fn with_receiving_mx_helo(mut self, value: impl Into<String>) -> Self {
+        self.receiving_mx_helo = Some(value.into());
+        self
+    }
+
+    pub fn with_sending_mta_ip(mut self, value: IpAddr) -> Self {
+        self.sending_mta_ip = Some(value);
+        self
+    }
+
+    pub fn with_additional_information(mut self, value: impl Into<String>) -> Self {
+        self.additional_information = Some(value.into());
+        self
+    }
 }
@@ -8,22 +8,35 @@
  * except according to those terms.
  */
 
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 
 use flate2::read::GzDecoder;
 use mail_parser::{MessageParser, MimeHeaders, PartType};
 use zip::ZipArchive;
 
-use crate::report::Error;
+use crate::report::{Error, ReportLimits};
 
 use super::TlsReport;
 
 impl TlsReport {
     pub fn parse_json(report: &[u8]) -> Result<Self, Error> {
+        Self::parse_json_with_limits(report, &ReportLimits::default())
+    }
+
+    /// Parses a JSON report, failing with `Error::LimitExceeded` if it exceeds
+    /// the limits.
+    pub fn parse_json_with_limits(report: &[u8], limits: &ReportLimits) -> Result<Self, Error> {
+        limits.check_json(report).map_err(Error::LimitExceeded)?;
         serde_json::from_slice(report).map_err(|err| Error::ReportParseError(err.to_string()))
     }
 
     pub fn parse_rfc5322(report: &[u8]) -> Result<Self, Error> {
+        Self::parse_rfc5322_with_limits(report, &ReportLimits::default())
+    }
+
+    /// Parses a report message, failing with `Error::LimitExceeded` if its
+    /// report exceeds the limits.
+    pub fn parse_rfc5322_with_limits(report: &[u8], limits: &ReportLimits) -> Result<Self, Error> {
         let message = MessageParser::new()
             .parse(report)
             .ok_or(Error::MailParseError)?;
@@ -65,12 +78,9 @@ impl TlsReport {
 
                     match rt {
                         ReportType::Gzip => {
-                            let mut file = GzDecoder::new(report.as_ref());
-                            let mut buf = Vec::new();
-                            file.read_to_end(&mut buf)
-                                .map_err(|err| Error::UncompressError(err.to_string()))?;
+                            let buf = limits.read(GzDecoder::new(report.as_ref()), 0)?;
 
-                            match Self::parse_json(&buf) {
+                            match Self::parse_json_with_limits(&buf, limits) {
                                 Ok(report) => return Ok(report),
                                 Err(err) => {
                                     error = err;
@@ -82,13 +92,10 @@ impl TlsReport {
                                 .map_err(|err| Error::UncompressError(err.to_string()))?;
                             for i in 0..archive.len() {
                                 match archive.by_index(i) {
-                                    Ok(mut file) => {
-                                        let mut buf =
-                                            Vec::with_capacity(file.compressed_size() as usize);
-                                        file.read_to_end(&mut buf).map_err(|err| {
-                                            Error::UncompressError(err.to_string())
-                                        })?;
-                                        match Self::parse_json(&buf) {
+                                    Ok(file) => {
+                                        let capacity = file.compressed_size() as usize;
+                                        let buf = limits.read(file, capacity)?;
+                                        match Self::parse_json_with_limits(&buf, limits) {
                                             Ok(report) => return Ok(report),
                                             Err(err) => {
                                                 error = err;
@@ -101,7 +108,7 @@ impl TlsReport {
                                 }
                             }
                         }
-                        ReportType::Json => match Self::parse_json(report) {
+                        ReportType::Json => match Self::parse_json_with_limits(report, limits) {
                             Ok(report) => return Ok(report),
                             Err(err) => {
                                 error = err;
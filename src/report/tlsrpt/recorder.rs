@@ -0,0 +1,254 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{borrow::Cow, collections::HashMap};
+
+use parking_lot::Mutex;
+
+use crate::Domain;
+
+use super::{FailureDetails, Policy, PolicyDetails, Summary};
+
+type Shard = HashMap<PolicyDetails, Sessions, ahash::RandomState>;
+
+/// Collects the outcome of SMTP sessions for TLS reporting (RFC 8460).
+///
+/// Sessions are counted per policy in shards selected by policy domain, so
+/// concurrent SMTP sessions rarely contend on the same lock. Failures are
+/// grouped by their details, with `failed_session_count` ignored.
+#[derive(Debug)]
+pub struct TlsSessionRecorder {
+    shards: Box<[Mutex<Shard>]>,
+    hasher: ahash::RandomState,
+}
+
+#[derive(Debug, Default)]
+struct Sessions {
+    success: u32,
+    failures: HashMap<FailureDetails, u32, ahash::RandomState>,
+}
+
+impl Default for TlsSessionRecorder {
+    fn default() -> Self {
+        Self::with_shards(
+            std::thread::available_parallelism()
+                .map(|n| n.get() * 4)
+                .unwrap_or(16),
+        )
+    }
+}
+
+impl TlsSessionRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a recorder with a fixed number of shards.
+    pub fn with_shards(shards: usize) -> Self {
+        TlsSessionRecorder {
+            shards: (0..shards.max(1))
+                .map(|_| Mutex::new(Shard::default()))
+                .collect(),
+            hasher: ahash::RandomState::new(),
+        }
+    }
+
+    /// Records a session that negotiated TLS as required by the policy.
+    pub fn record_success(&self, policy: &PolicyDetails) {
+        self.record(policy, None);
+    }
+
+    /// Records a session that failed to negotiate TLS as required by the policy.
+    pub fn record_failure(&self, policy: &PolicyDetails, failure: FailureDetails) {
+        self.record(policy, Some(failure));
+    }
+
+    /// Records the outcome of a session, `None` meaning success.
+    pub fn record(&self, policy: &PolicyDetails, failure: Option<FailureDetails>) {
+        let domain = Domain::normalize(&policy.policy_domain);
+        let policy = if policy.policy_domain == domain {
+            Cow::Borrowed(policy)
+        } else {
+            Cow::Owned(PolicyDetails {
+                policy_domain: domain,
+                ..policy.clone()
+            })
+        };
+        let mut shard = self.shard(&policy.policy_domain).lock();
+        if !shard.contains_key(policy.as_ref()) {
+            shard.insert(policy.as_ref().clone(), Sessions::default());
+        }
+        let sessions = shard.get_mut(policy.as_ref()).unwrap();
+
+        match failure {
+            Some(mut failure) => {
+                failure.failed_session_count = 0;
+                let count = sessions.failures.entry(failure).or_default();
+                *count = count.saturating_add(1);
+            }
+            None => {
+                sessions.success = sessions.success.saturating_add(1);
+            }
+        }
+    }
+
+    /// Returns `true` if no sessions were recorded since the last drain.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+
+    /// Returns the policies recorded for a domain, resetting their counters.
+    pub fn drain_domain(&self, domain: &str) -> Vec<Policy> {
+        let domain = Domain::normalize(domain);
+        let mut shard = self.shard(&domain).lock();
+        let policies = shard
+            .keys()
+            .filter(|policy| policy.policy_domain == domain)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut policies = policies
+            .into_iter()
+            .filter_map(|policy| {
+                let sessions = shard.remove(&policy)?;
+                Some(sessions.into_policy(policy))
+            })
+            .collect::<Vec<_>>();
+        drop(shard);
+        sort_policies(&mut policies);
+        policies
+    }
+
+    /// Returns all recorded policies sorted by domain, resetting the recorder.
+    pub fn drain(&self) -> Vec<Policy> {
+        let mut policies = Vec::new();
+        for shard in self.shards.iter() {
+            let sessions = std::mem::take(&mut *shard.lock());
+            policies.extend(
+                sessions
+                    .into_iter()
+                    .map(|(policy, sessions)| sessions.into_policy(policy)),
+            );
+        }
+        sort_policies(&mut policies);
+        policies
+    }
+
+    fn shard(&self, domain: &str) -> &Mutex<Shard> {
+        &self.shards[self.hasher.hash_one(domain) as usize % self.shards.len()]
+    }
+}
+
+impl Sessions {
+    fn into_policy(self, policy: PolicyDetails) -> Policy {
+        let mut failure_details = self
+            .failures
+            .into_iter()
+            .map(|(mut failure, count)| {
+                failure.failed_session_count = count;
+                failure
+            })
+            .collect::<Vec<_>>();
+        failure_details.sort_unstable_by(|a, b| {
+            b.failed_session_count
+                .cmp(&a.failed_session_count)
+                .then_with(|| a.cmp(b))
+        });
+
+        Policy {
+            policy,
+            summary: Summary {
+                total_success: self.success,
+                total_failure: failure_details.iter().fold(0u32, |total, f| {
+                    total.saturating_add(f.failed_session_count)
+                }),
+            },
+            failure_details,
+        }
+    }
+}
+
+fn sort_policies(policies: &mut [Policy]) {
+    policies.sort_unstable_by(|a, b| {
+        a.policy
+            .policy_domain
+            .cmp(&b.policy.policy_domain)
+            .then_with(|| a.policy.cmp(&b.policy))
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use crate::report::tlsrpt::{FailureDetails, PolicyDetails, PolicyType, ResultType};
+
+    use super::TlsSessionRecorder;
+
+    #[test]
+    fn tls_session_recorder() {
+        let recorder = TlsSessionRecorder::with_shards(4);
+        let sts = PolicyDetails::new(PolicyType::Sts, "Example.org");
+        let tlsa = PolicyDetails::new(PolicyType::Tlsa, "example.org");
+        let other = PolicyDetails::new(PolicyType::NoPolicyFound, "example.com");
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..25 {
+                        recorder.record_success(&sts);
+                        recorder.record_failure(
+                            &tlsa,
+                            FailureDetails::new(ResultType::CertificateExpired)
+                                .with_receiving_mx_hostname("mx.example.org")
+                                .with_receiving_ip(ip),
+                        );
+                    }
+                    recorder.record_failure(
+                        &sts,
+                        FailureDetails::new(ResultType::StartTlsNotSupported)
+                            .with_sending_mta_ip(ip),
+                    );
+                    recorder.record_success(&other);
+                });
+            }
+        });
+
+        let policies = recorder.drain_domain("EXAMPLE.ORG.");
+        assert_eq!(policies.len(), 2);
+        let sts = policies
+            .iter()
+            .find(|p| p.policy.policy_type == PolicyType::Sts)
+            .unwrap();
+        assert_eq!(sts.policy.policy_domain, "example.org");
+        assert_eq!(
+            (sts.summary.total_success, sts.summary.total_failure),
+            (100, 4)
+        );
+        assert_eq!(sts.failure_details.len(), 1);
+        assert_eq!(sts.failure_details[0].failed_session_count, 4);
+        assert_eq!(sts.failure_details[0].sending_mta_ip, Some(ip));
+        let tlsa = policies
+            .iter()
+            .find(|p| p.policy.policy_type == PolicyType::Tlsa)
+            .unwrap();
+        assert_eq!(
+            (tlsa.summary.total_success, tlsa.summary.total_failure),
+            (0, 100)
+        );
+        assert_eq!(tlsa.failure_details[0].failed_session_count, 100);
+
+        let policies = recorder.drain();
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].policy.policy_domain, "example.com");
+        assert_eq!(policies[0].summary.total_success, 4);
+        assert!(recorder.is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Domain;
+
+use super::{FailureDetails, Policy, PolicyType, ResultType, TlsReport};
+
+/// Session statistics aggregated from one or many TLS reports.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TlsReportSummary {
+    reports: u64,
+    sessions: SessionCounts,
+    policy_types: BTreeMap<PolicyType, SessionCounts>,
+    result_types: BTreeMap<ResultType, u64>,
+    mx_hosts: BTreeMap<String, u64>,
+}
+
+/// Successful and failed session counts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SessionCounts {
+    success: u64,
+    failure: u64,
+}
+
+impl TlsReport {
+    /// Returns the session statistics of this report.
+    pub fn summary(&self) -> TlsReportSummary {
+        let mut summary = TlsReportSummary::default();
+        summary.add(self);
+        summary
+    }
+
+    /// Merges another report into this one, summing the session counts of
+    /// identical policies and failure details, and extending the date range to
+    /// cover both reports.
+    pub fn merge(&mut self, other: TlsReport) {
+        if other.date_range.start_datetime.to_timestamp()
+            < self.date_range.start_datetime.to_timestamp()
+        {
+            self.date_range.start_datetime = other.date_range.start_datetime;
+        }
+        if other.date_range.end_datetime.to_timestamp()
+            > self.date_range.end_datetime.to_timestamp()
+        {
+            self.date_range.end_datetime = other.date_range.end_datetime;
+        }
+
+        for policy in other.policies {
+            match self.policies.iter_mut().find(|p| p.policy == policy.policy) {
+                Some(merged) => merged.merge(policy),
+                None => self.policies.push(policy),
+            }
+        }
+    }
+}
+
+impl Policy {
+    fn merge(&mut self, other: Policy) {
+        self.summary.total_success = self
+            .summary
+            .total_success
+            .saturating_add(other.summary.total_success);
+        self.summary.total_failure = self
+            .summary
+            .total_failure
+            .saturating_add(other.summary.total_failure);
+
+        let mut details: HashMap<FailureDetails, usize> = HashMap::new();
+        let mut failure_details: Vec<FailureDetails> = Vec::new();
+        for failure in std::mem::take(&mut self.failure_details)
+            .into_iter()
+            .chain(other.failure_details)
+        {
+            let key = FailureDetails {
+                failed_session_count: 0,
+                ..failure.clone()
+            };
+            if let Some(&idx) = details.get(&key) {
+                let merged = &mut failure_details[idx].failed_session_count;
+                *merged = merged.saturating_add(failure.failed_session_count);
+            } else {
+                details.insert(key, failure_details.len());
+                failure_details.push(failure);
+            }
+        }
+        self.failure_details = failure_details;
+    }
+}
+
+impl TlsReportSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the sessions of a report to the summary.
+    pub fn add(&mut self, report: &TlsReport) {
+        self.reports += 1;
+        for policy in &report.policies {
+            let counts = SessionCounts {
+                success: policy.summary.total_success.into(),
+                failure: policy.summary.total_failure.into(),
+            };
+            self.sessions += counts;
+            *self
+                .policy_types
+                .entry(policy.policy.policy_type)
+                .or_default() += counts;
+
+            for failure in &policy.failure_details {
+                let count = u64::from(failure.failed_session_count);
+                *self.result_types.entry(failure.result_type).or_default() += count;
+                if let Some(host) = &failure.receiving_mx_hostname {
+                    *self.mx_hosts.entry(Domain::normalize(host)).or_default() += count;
+                }
+            }
+        }
+    }
+
+    /// Combines the statistics of another summary into this one.
+    pub fn merge(&mut self, other: TlsReportSummary) {
+        self.reports += other.reports;
+        self.sessions += other.sessions;
+        for (policy_type, counts) in other.policy_types {
+            *self.policy_types.entry(policy_type).or_default() += counts;
+        }
+        for (result_type, count) in other.result_types {
+            *self.result_types.entry(result_type).or_default() += count;
+        }
+        for (host, count) in other.mx_hosts {
+            *self.mx_hosts.entry(host).or_default() += count;
+        }
+    }
+
+    /// Number of reports summarized.
+    pub fn reports(&self) -> u64 {
+        self.reports
+    }
+
+    /// Sessions across all policies.
+    pub fn sessions(&self) -> SessionCounts {
+        self.sessions
+    }
+
+    /// Sessions of the policies of a type.
+    pub fn policy_type(&self, policy_type: PolicyType) -> SessionCounts {
+        self.policy_types
+            .get(&policy_type)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sessions by policy type.
+    pub fn policy_types(&self) -> impl Iterator<Item = (PolicyType, SessionCounts)> + '_ {
+        self.policy_types
+            .iter()
+            .map(|(policy_type, counts)| (*policy_type, *counts))
+    }
+
+    /// Returns up to `limit` result types with the most failed sessions, in
+    /// descending order.
+    pub fn top_failures(&self, limit: usize) -> Vec<(ResultType, u64)> {
+        let mut failures = self
+            .result_types
+            .iter()
+            .map(|(result_type, count)| (*result_type, *count))
+            .collect::<Vec<_>>();
+        failures.sort_by_key(|&(_, count)| Reverse(count));
+        failures.truncate(limit);
+        failures
+    }
+
+    /// Returns the MX hosts with failed sessions, from the host with the most
+    /// failures to the one with the least.
+    pub fn affected_mx_hosts(&self) -> Vec<(&str, u64)> {
+        let mut hosts = self
+            .mx_hosts
+            .iter()
+            .map(|(host, count)| (host.as_str(), *count))
+            .collect::<Vec<_>>();
+        hosts.sort_by_key(|&(_, count)| Reverse(count));
+        hosts
+    }
+}
+
+impl SessionCounts {
+    pub fn success(&self) -> u64 {
+        self.success
+    }
+
+    pub fn failure(&self) -> u64 {
+        self.failure
+    }
+
+    pub fn total(&self) -> u64 {
+        self.success + self.failure
+    }
+
+    /// Fraction of successful sessions, `1.0` if no sessions were reported.
+    pub fn success_rate(&self) -> f64 {
+        match self.total() {
+            0 => 1.0,
+            total => self.success as f64 / total as f64,
+        }
+    }
+}
+
+impl std::ops::AddAssign for SessionCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.success += other.success;
+        self.failure += other.failure;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::report::tlsrpt::{PolicyType, ResultType, TlsReport};
+
+    use super::TlsReportSummary;
+
+    #[test]
+    fn tlsrpt_summary() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("resources");
+        path.push("tlsrpt");
+        path.push("rpt01.json");
+        let report = TlsReport::parse_json(&fs::read(&path).unwrap()).unwrap();
+
+        let summary = report.summary();
+        assert_eq!(summary.reports(), 1);
+        assert_eq!(summary.sessions().success(), 5326);
+        assert_eq!(summary.sessions().failure(), 303);
+        assert_eq!(summary.policy_type(PolicyType::Sts).total(), 5629);
+        assert_eq!(summary.policy_type(PolicyType::Tlsa).total(), 0);
+        assert_eq!(
+            summary.top_failures(2),
+            vec![
+                (ResultType::StartTlsNotSupported, 200),
+                (ResultType::CertificateExpired, 100)
+            ]
+        );
+        assert_eq!(
+            summary.affected_mx_hosts(),
+            vec![
+                ("mx2.mail.company-y.example", 200),
+                ("mx1.mail.company-y.example", 100),
+                ("mx-backup.mail.company-y.example", 3)
+            ]
+        );
+
+        // Merging a report sums identical policies and failure details
+        let mut merged = report.clone();
+        merged.merge(report.clone());
+        assert_eq!(merged.policies.len(), 1);
+        assert_eq!(merged.policies[0].summary.total_success, 5326 * 2);
+        assert_eq!(merged.policies[0].failure_details.len(), 3);
+        assert_eq!(
+            merged.policies[0].failure_details[0].failed_session_count,
+            200
+        );
+        assert_eq!(merged.date_range, report.date_range);
+
+        let mut summary = summary;
+        summary.add(&report);
+        let mut merged_summary = merged.summary();
+        assert_eq!(merged_summary.sessions(), summary.sessions());
+        assert_eq!(merged_summary.top_failures(10), summary.top_failures(10));
+        merged_summary.merge(TlsReportSummary::new());
+        assert_eq!(
+            merged_summary.affected_mx_hosts()[0],
+            ("mx2.mail.company-y.example", 200 * 2)
+        );
+        assert!((summary.sessions().success_rate() - 5326.0 / 5629.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,389 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+//! Conformance self-test that runs bundled RFC test vectors at runtime, so that
+//! deployments can check that their build, features and crypto backend behave
+//! correctly on the target platform.
+//!
+//! DNS answers are served from the resolver cache. The resolver used by the
+//! self-test has no name servers configured, so no network queries are made.
+
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use hickory_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+
+use crate::{
+    common::{
+        headers::HeaderWriter,
+        lru::DnsCache,
+        parse::TxtRecordParser,
+        test_key::{ed25519_key, ED25519_PUBLIC_KEY},
+        verify::DomainKey,
+    },
+    dkim::DkimSigner,
+    spf::Spf,
+    AuthenticatedMessage, DkimResult, Resolver, SpfResult,
+};
+
+const DKIM_VECTORS: [(&str, &str, &[&str]); 2] = [
+    (
+        "rfc8463",
+        include_str!("../resources/dkim/001.txt"),
+        &["ed25519-sha256", "rsa-sha256"],
+    ),
+    (
+        "rfc6376",
+        include_str!("../resources/dkim/002.txt"),
+        &["rsa-sha256"],
+    ),
+];
+
+const ARC_VECTORS: [(&str, &str); 2] = [
+    ("single-set", include_str!("../resources/arc/001.txt")),
+    ("two-sets", include_str!("../resources/arc/002.txt")),
+];
+
+const SPF_RECORDS: [(&str, &str); 5] = [
+    (
+        "example.org.",
+        "v=spf1 ip4:198.51.100.0/24 include:_spf.example.org include:_spf.example.net -all",
+    ),
+    (
+        "_spf.example.org.",
+        "v=spf1 ip4:203.0.113.0/24 ip6:2001:db8::/32 ~all",
+    ),
+    ("_spf.example.net.", "v=spf1 include:_ip4.example.net ?all"),
+    ("_ip4.example.net.", "v=spf1 ip4:192.0.2.0/24 -all"),
+    ("example.com.", "v=spf1 a/24 ~all"),
+];
+
+const SPF_VECTORS: [(&str, &str, IpAddr, SpfResult); 7] = [
+    (
+        "ip4",
+        "example.org",
+        IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7)),
+        SpfResult::Pass,
+    ),
+    (
+        "ip6",
+        "example.org",
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)),
+        SpfResult::Pass,
+    ),
+    (
+        "include",
+        "example.org",
+        IpAddr::V4(Ipv4Addr::new(203, 0, 113, 5)),
+        SpfResult::Pass,
+    ),
+    (
+        "nested-include",
+        "example.org",
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+        SpfResult::Pass,
+    ),
+    (
+        "all",
+        "example.org",
+        IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+        SpfResult::Fail,
+    ),
+    (
+        "a",
+        "example.com",
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, 200)),
+        SpfResult::Pass,
+    ),
+    (
+        "a-softfail",
+        "example.com",
+        IpAddr::V4(Ipv4Addr::new(192, 0, 3, 1)),
+        SpfResult::SoftFail,
+    ),
+];
+
+/// Group of test vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SelfTestSuite {
+    Dkim,
+    DkimSign,
+    Arc,
+    Spf,
+}
+
+/// Outcome of a single test vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestCase {
+    pub suite: SelfTestSuite,
+    pub name: String,
+    pub passed: bool,
+    pub result: String,
+}
+
+/// Outcome of all the test vectors run by [`self_test`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfTestReport {
+    cases: Vec<SelfTestCase>,
+}
+
+impl SelfTestSuite {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SelfTestSuite::Dkim => "dkim",
+            SelfTestSuite::DkimSign => "dkim-sign",
+            SelfTestSuite::Arc => "arc",
+            SelfTestSuite::Spf => "spf",
+        }
+    }
+}
+
+impl SelfTestReport {
+    pub fn cases(&self) -> &[SelfTestCase] {
+        &self.cases
+    }
+
+    /// Returns the test vectors that did not produce the expected result.
+    pub fn failures(&self) -> impl Iterator<Item = &SelfTestCase> {
+        self.cases.iter().filter(|case| !case.passed)
+    }
+
+    /// Returns `true` if every test vector produced the expected result.
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|case| case.passed)
+    }
+
+    fn add(
+        &mut self,
+        suite: SelfTestSuite,
+        name: impl Into<String>,
+        passed: bool,
+        result: impl Display,
+    ) {
+        self.cases.push(SelfTestCase {
+            suite,
+            name: name.into(),
+            passed,
+            result: result.to_string(),
+        });
+    }
+}
+
+impl Display for SelfTestCase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<6} {}/{}: {}",
+            if self.passed { "ok" } else { "FAILED" },
+            self.suite.name(),
+            self.name,
+            self.result
+        )
+    }
+}
+
+impl Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for case in &self.cases {
+            writeln!(f, "{case}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the bundled DKIM (RFC 6376, RFC 8463), ARC and SPF test vectors and
+/// reports the outcome of each one.
+pub async fn self_test() -> crate::Result<SelfTestReport> {
+    let resolver = Resolver::with_capacity(
+        ResolverConfig::from_parts(None, Vec::new(), NameServerConfigGroup::new()),
+        ResolverOpts::default(),
+        32,
+    )?;
+    let valid_until = Instant::now() + Duration::from_secs(3600);
+    let mut report = SelfTestReport::default();
+
+    // DKIM verification
+    for (name, vector, algorithms) in DKIM_VECTORS {
+        let raw_message = load_vector(&resolver, vector, valid_until);
+        let Some(message) = AuthenticatedMessage::parse(raw_message.as_bytes()) else {
+            report.add(SelfTestSuite::Dkim, name, false, "failed to parse message");
+            continue;
+        };
+        let outputs = resolver.verify_dkim(&message).await;
+        if outputs.len() != algorithms.len() {
+            report.add(
+                SelfTestSuite::Dkim,
+                name,
+                false,
+                format!("found {} signatures", outputs.len()),
+            );
+            continue;
+        }
+        for (output, algorithm) in outputs.iter().zip(algorithms.iter()) {
+            report.add(
+                SelfTestSuite::Dkim,
+                format!("{name}/{algorithm}"),
+                output.result() == &DkimResult::Pass,
+                output.result(),
+            );
+        }
+
+        // Altering a signed header has to break every signature
+        let tampered = raw_message.replacen("Subject: ", "Subject: Re: ", 1);
+        if let Some(message) = AuthenticatedMessage::parse(tampered.as_bytes()) {
+            for (output, algorithm) in resolver
+                .verify_dkim(&message)
+                .await
+                .iter()
+                .zip(algorithms.iter())
+            {
+                report.add(
+                    SelfTestSuite::Dkim,
+                    format!("{name}/{algorithm}/tampered"),
+                    matches!(output.result(), DkimResult::Fail(_)),
+                    output.result(),
+                );
+            }
+        }
+    }
+
+    // DKIM signing
+    resolver.cache_txt.insert(
+        "ed._domainkey.example.com.".to_string(),
+        DomainKey::parse(format!("v=DKIM1; k=ed25519; p={ED25519_PUBLIC_KEY}").as_bytes()).into(),
+        valid_until,
+    );
+    let signed = sign_ed25519().map(|signature| signature.to_header() + SIGNED_MESSAGE);
+    let result = match &signed {
+        Ok(signed) => match AuthenticatedMessage::parse(signed.as_bytes()) {
+            Some(message) => match resolver.verify_dkim(&message).await.first() {
+                Some(output) => output.result().clone(),
+                None => DkimResult::None,
+            },
+            None => DkimResult::PermError(crate::Error::ParseError),
+        },
+        Err(err) => DkimResult::PermError(err.clone()),
+    };
+    report.add(
+        SelfTestSuite::DkimSign,
+        "ed25519-sha256",
+        result == DkimResult::Pass,
+        result,
+    );
+
+    // ARC verification
+    for (name, vector) in ARC_VECTORS {
+        let message = load_vector(&resolver, vector, valid_until);
+        let Some(message) = AuthenticatedMessage::parse(message.as_bytes()) else {
+            report.add(SelfTestSuite::Arc, name, false, "failed to parse message");
+            continue;
+        };
+        let output = resolver.verify_arc(&message).await;
+        report.add(
+            SelfTestSuite::Arc,
+            name,
+            output.result() == &DkimResult::Pass,
+            output.result(),
+        );
+    }
+
+    // SPF evaluation
+    for (name, record) in SPF_RECORDS {
+        resolver.cache_txt.insert(
+            name.to_string(),
+            Spf::parse(record.as_bytes()).into(),
+            valid_until,
+        );
+    }
+    resolver.cache_ipv4.insert(
+        "example.com.".to_string(),
+        Arc::new(vec![Ipv4Addr::new(192, 0, 2, 10)]),
+        valid_until,
+    );
+    for (name, domain, ip, expected) in SPF_VECTORS {
+        let output = resolver
+            .verify_spf_sender(
+                ip,
+                "mx.example.net",
+                "mx.example.net",
+                &format!("sender@{domain}"),
+            )
+            .await;
+        report.add(
+            SelfTestSuite::Spf,
+            name,
+            output.result() == expected,
+            output.result(),
+        );
+    }
+
+    Ok(report)
+}
+
+const SIGNED_MESSAGE: &str = concat!(
+    "From: bill@example.com\r\n",
+    "To: jdoe@example.com\r\n",
+    "Subject: TPS Report\r\n",
+    "\r\n",
+    "I'm going to need those TPS reports ASAP. ",
+    "So, if you could do that, that'd be great.\r\n"
+);
+
+fn sign_ed25519() -> crate::Result<crate::dkim::Signature> {
+    DkimSigner::from_key(ed25519_key()?)
+        .domain("example.com")
+        .selector("ed")
+        .headers(["From", "To", "Subject"])
+        .sign(SIGNED_MESSAGE.as_bytes())
+}
+
+/// Adds the DNS records of a test vector to the resolver cache and returns
+/// the message.
+fn load_vector(resolver: &Resolver, vector: &str, valid_until: Instant) -> String {
+    let (records, message) = vector.split_once("\n\n").unwrap_or_default();
+    for (name, record) in records
+        .split('\n')
+        .filter_map(|record| record.split_once(' '))
+    {
+        resolver.cache_txt.insert(
+            format!("{name}."),
+            DomainKey::parse(record.as_bytes()).into(),
+            valid_until,
+        );
+    }
+    message.replace('\n', "\r\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{self_test, SelfTestSuite};
+
+    #[tokio::test]
+    async fn run_self_test() {
+        let report = self_test().await.unwrap();
+        for suite in [
+            SelfTestSuite::Dkim,
+            SelfTestSuite::DkimSign,
+            SelfTestSuite::Arc,
+            SelfTestSuite::Spf,
+        ] {
+            assert!(
+                report.cases().iter().any(|case| case.suite == suite),
+                "{report}"
+            );
+        }
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.failures().count(), 0);
+    }
+}
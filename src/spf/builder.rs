@@ -0,0 +1,500 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    fmt::{Display, Formatter},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use crate::{Error, Version};
+
+use super::{
+    parse::SPFParser, Directive, Macro, Mechanism, Qualifier, Spf, SpfRecord, SpfRecordBuilder,
+    Variable, RR_FAIL, RR_NEUTRAL_NONE, RR_SOFTFAIL, RR_TEMP_PERM_ERROR,
+};
+
+// RFC 7208 Section 3.4 recommends keeping SPF answers within 512 bytes.
+const DEFAULT_MAX_LENGTH: usize = 450;
+const MAX_TXT_STRING_LENGTH: usize = 255;
+
+impl SpfRecord {
+    /// Creates a builder for an SPF record.
+    pub fn builder() -> SpfRecordBuilder {
+        SpfRecordBuilder {
+            spf: Spf {
+                version: Version::V1,
+                directives: Vec::new(),
+                exp: None,
+                redirect: None,
+                ra: None,
+                rp: 100,
+                rr: u8::MAX,
+            },
+            all: None,
+            max_length: DEFAULT_MAX_LENGTH,
+            invalid: false,
+        }
+    }
+
+    /// Returns the record as a single string.
+    pub fn as_str(&self) -> &str {
+        &self.record
+    }
+
+    /// Returns the record split into character-strings of at most 255 bytes,
+    /// ready to be published as a single TXT record.
+    pub fn txt_strings(&self) -> Vec<&str> {
+        self.record
+            .as_bytes()
+            .chunks(MAX_TXT_STRING_LENGTH)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap_or_default())
+            .collect()
+    }
+}
+
+impl SpfRecordBuilder {
+    /// Adds an `include:` mechanism.
+    pub fn include(self, domain: impl AsRef<str>) -> Self {
+        self.domain_mechanism(domain, |macro_string| Mechanism::Include { macro_string })
+    }
+
+    /// Adds an `a` mechanism matching the addresses of the checked domain.
+    pub fn a(self) -> Self {
+        self.directive(
+            Qualifier::Pass,
+            Mechanism::A {
+                macro_string: Macro::None,
+                ip4_mask: u32::MAX,
+                ip6_mask: u128::MAX,
+            },
+        )
+    }
+
+    /// Adds an `a:` mechanism matching the addresses of `domain`.
+    pub fn a_domain(self, domain: impl AsRef<str>) -> Self {
+        self.domain_mechanism(domain, |macro_string| Mechanism::A {
+            macro_string,
+            ip4_mask: u32::MAX,
+            ip6_mask: u128::MAX,
+        })
+    }
+
+    /// Adds an `mx` mechanism matching the mail exchangers of the checked domain.
+    pub fn mx(self) -> Self {
+        self.directive(
+            Qualifier::Pass,
+            Mechanism::Mx {
+                macro_string: Macro::None,
+                ip4_mask: u32::MAX,
+                ip6_mask: u128::MAX,
+            },
+        )
+    }
+
+    /// Adds an `mx:` mechanism matching the mail exchangers of `domain`.
+    pub fn mx_domain(self, domain: impl AsRef<str>) -> Self {
+        self.domain_mechanism(domain, |macro_string| Mechanism::Mx {
+            macro_string,
+            ip4_mask: u32::MAX,
+            ip6_mask: u128::MAX,
+        })
+    }
+
+    /// Adds an `ip4:` mechanism for the network `addr/prefix`.
+    pub fn ip4(mut self, addr: Ipv4Addr, prefix: u8) -> Self {
+        self.invalid |= prefix > 32;
+        self.directive(
+            Qualifier::Pass,
+            Mechanism::Ip4 {
+                addr,
+                mask: u32::MAX
+                    .checked_shl(32 - prefix.min(32) as u32)
+                    .unwrap_or(0),
+            },
+        )
+    }
+
+    /// Adds an `ip6:` mechanism for the network `addr/prefix`.
+    pub fn ip6(mut self, addr: Ipv6Addr, prefix: u8) -> Self {
+        self.invalid |= prefix > 128;
+        self.directive(
+            Qualifier::Pass,
+            Mechanism::Ip6 {
+                addr,
+                mask: u128::MAX
+                    .checked_shl(128 - prefix.min(128) as u32)
+                    .unwrap_or(0),
+            },
+        )
+    }
+
+    /// Adds an `exists:` mechanism.
+    pub fn exists(self, domain: impl AsRef<str>) -> Self {
+        self.domain_mechanism(domain, |macro_string| Mechanism::Exists { macro_string })
+    }
+
+    /// Adds a directive with an explicit qualifier.
+    pub fn directive(mut self, qualifier: Qualifier, mechanism: Mechanism) -> Self {
+        self.spf
+            .directives
+            .push(Directive::new(qualifier, mechanism));
+        self
+    }
+
+    /// Sets the qualifier of the `all` mechanism, which is always serialized last.
+    pub fn all(mut self, qualifier: Qualifier) -> Self {
+        self.all = qualifier.into();
+        self
+    }
+
+    /// Sets the `redirect=` modifier.
+    pub fn redirect(mut self, domain: impl AsRef<str>) -> Self {
+        self.spf.redirect = self.domain_spec(domain.as_ref()).into();
+        self
+    }
+
+    /// Sets the `exp=` modifier.
+    pub fn exp(mut self, domain: impl AsRef<str>) -> Self {
+        self.spf.exp = self.domain_spec(domain.as_ref()).into();
+        self
+    }
+
+    /// Sets the maximum length of the serialized record, defaults to 450 bytes.
+    pub fn max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    /// Serializes the record, failing with `Error::ParseError` if any of the
+    /// terms is invalid or `Error::RecordTooLong` if it exceeds the maximum length.
+    pub fn build(&self) -> crate::Result<SpfRecord> {
+        if self.invalid {
+            return Err(Error::ParseError);
+        }
+
+        let mut spf = self.spf.clone();
+        if let Some(qualifier) = &self.all {
+            spf.directives
+                .push(Directive::new(qualifier.clone(), Mechanism::All));
+        }
+        let record = spf.to_string();
+        if record.len() <= self.max_length {
+            Ok(SpfRecord { record })
+        } else {
+            Err(Error::RecordTooLong)
+        }
+    }
+
+    fn domain_mechanism(
+        mut self,
+        domain: impl AsRef<str>,
+        mechanism: impl FnOnce(Macro) -> Mechanism,
+    ) -> Self {
+        let macro_string = self.domain_spec(domain.as_ref());
+        self.directive(Qualifier::Pass, mechanism(macro_string))
+    }
+
+    fn domain_spec(&mut self, domain: &str) -> Macro {
+        let mut domain_spec = domain.as_bytes().iter();
+        match domain_spec.macro_string(false) {
+            Ok((macro_string, b' ')) if domain.is_ascii() && domain_spec.as_slice().is_empty() => {
+                macro_string
+            }
+            _ => {
+                self.invalid = true;
+                Macro::None
+            }
+        }
+    }
+}
+
+impl From<Spf> for SpfRecordBuilder {
+    fn from(spf: Spf) -> Self {
+        SpfRecordBuilder {
+            spf,
+            all: None,
+            max_length: DEFAULT_MAX_LENGTH,
+            invalid: false,
+        }
+    }
+}
+
+impl Display for SpfRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.record)
+    }
+}
+
+impl Display for Spf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("v=spf1")?;
+        for directive in &self.directives {
+            write!(f, " {directive}")?;
+        }
+        if let Some(redirect) = &self.redirect {
+            write!(f, " redirect={redirect}")?;
+        }
+        if let Some(exp) = &self.exp {
+            write!(f, " exp={exp}")?;
+        }
+        if let Some(ra) = &self.ra {
+            write!(f, " ra={}", String::from_utf8_lossy(ra))?;
+        }
+        if self.rp != 100 {
+            write!(f, " rp={}", self.rp)?;
+        }
+        if self.rr != u8::MAX {
+            f.write_str(" rr=")?;
+            let mut flags = [
+                (RR_TEMP_PERM_ERROR, "e"),
+                (RR_FAIL, "f"),
+                (RR_SOFTFAIL, "s"),
+                (RR_NEUTRAL_NONE, "n"),
+            ]
+            .into_iter()
+            .filter(|(flag, _)| self.rr & flag != 0);
+            if let Some((_, flag)) = flags.next() {
+                f.write_str(flag)?;
+                for (_, flag) in flags {
+                    write!(f, ":{flag}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self.qualifier {
+            Qualifier::Pass => "",
+            Qualifier::Fail => "-",
+            Qualifier::SoftFail => "~",
+            Qualifier::Neutral => "?",
+        })?;
+        self.mechanism.fmt(f)
+    }
+}
+
+impl Display for Mechanism {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mechanism::All => f.write_str("all"),
+            Mechanism::Include { macro_string } => write!(f, "include:{macro_string}"),
+            Mechanism::A {
+                macro_string,
+                ip4_mask,
+                ip6_mask,
+            } => {
+                f.write_str("a")?;
+                write_domain_spec(f, macro_string, *ip4_mask, *ip6_mask)
+            }
+            Mechanism::Mx {
+                macro_string,
+                ip4_mask,
+                ip6_mask,
+            } => {
+                f.write_str("mx")?;
+                write_domain_spec(f, macro_string, *ip4_mask, *ip6_mask)
+            }
+            Mechanism::Ptr { macro_string } => {
+                f.write_str("ptr")?;
+                write_domain_spec(f, macro_string, u32::MAX, u128::MAX)
+            }
+            Mechanism::Ip4 { addr, mask } => {
+                write!(f, "ip4:{addr}")?;
+                if *mask != u32::MAX {
+                    write!(f, "/{}", mask.leading_ones())?;
+                }
+                Ok(())
+            }
+            Mechanism::Ip6 { addr, mask } => {
+                write!(f, "ip6:{addr}")?;
+                if *mask != u128::MAX {
+                    write!(f, "/{}", mask.leading_ones())?;
+                }
+                Ok(())
+            }
+            Mechanism::Exists { macro_string } => write!(f, "exists:{macro_string}"),
+        }
+    }
+}
+
+fn write_domain_spec(
+    f: &mut Formatter<'_>,
+    macro_string: &Macro,
+    ip4_mask: u32,
+    ip6_mask: u128,
+) -> std::fmt::Result {
+    if macro_string != &Macro::None {
+        write!(f, ":{macro_string}")?;
+    }
+    if ip4_mask != u32::MAX {
+        write!(f, "/{}", ip4_mask.leading_ones())?;
+    }
+    if ip6_mask != u128::MAX {
+        write!(f, "//{}", ip6_mask.leading_ones())?;
+    }
+    Ok(())
+}
+
+impl Display for Macro {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Macro::Literal(literal) => {
+                for &ch in literal {
+                    match ch {
+                        b'%' => f.write_str("%%")?,
+                        b' ' => f.write_str("%_")?,
+                        _ => write!(f, "{}", char::from(ch))?,
+                    }
+                }
+                Ok(())
+            }
+            Macro::Variable {
+                letter,
+                num_parts,
+                reverse,
+                escape,
+                delimiters,
+            } => {
+                let letter = letter.as_char();
+                write!(
+                    f,
+                    "%{{{}",
+                    if *escape {
+                        letter.to_ascii_uppercase()
+                    } else {
+                        letter
+                    }
+                )?;
+                if *num_parts > 0 {
+                    write!(f, "{num_parts}")?;
+                }
+                if *reverse {
+                    f.write_str("r")?;
+                }
+                if *delimiters != 1u64 << (b'.' - b'+') {
+                    for ch in [b'.', b'-', b'+', b',', b'/', b'_', b'='] {
+                        if delimiters & (1u64 << (ch - b'+')) != 0 {
+                            write!(f, "{}", char::from(ch))?;
+                        }
+                    }
+                }
+                f.write_str("}")
+            }
+            Macro::List(list) => {
+                for item in list {
+                    item.fmt(f)?;
+                }
+                Ok(())
+            }
+            Macro::None => Ok(()),
+        }
+    }
+}
+
+impl Variable {
+    fn as_char(&self) -> char {
+        match self {
+            Variable::Sender => 's',
+            Variable::SenderLocalPart => 'l',
+            Variable::SenderDomainPart => 'o',
+            Variable::Domain => 'd',
+            Variable::Ip => 'i',
+            Variable::ValidatedDomain => 'p',
+            Variable::IpVersion => 'v',
+            Variable::HeloDomain => 'h',
+            Variable::SmtpIp => 'c',
+            Variable::HostDomain => 'r',
+            Variable::CurrentTime => 't',
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Qualifier, Spf, SpfRecord},
+        Error,
+    };
+
+    #[test]
+    fn spf_record_builder() {
+        let record = SpfRecord::builder()
+            .include("_spf.google.com")
+            .ip4(Ipv4Addr::new(192, 0, 2, 0), 24)
+            .ip6("2001:db8::".parse::<Ipv6Addr>().unwrap(), 32)
+            .mx()
+            .exists("%{ir}.%{l1r+-}._spf.%{d}")
+            .all(Qualifier::SoftFail)
+            .build()
+            .unwrap();
+        assert_eq!(
+            record.as_str(),
+            concat!(
+                "v=spf1 include:_spf.google.com ip4:192.0.2.0/24 ip6:2001:db8::/32 ",
+                "mx exists:%{ir}.%{l1r-+}._spf.%{d} ~all"
+            )
+        );
+        assert_eq!(record.txt_strings(), [record.as_str()]);
+        Spf::parse(record.as_str().as_bytes()).unwrap();
+
+        // Records longer than 255 bytes are split across character-strings
+        let mut builder = SpfRecord::builder().max_length(1024);
+        for octet in 0..=40 {
+            builder = builder.ip4(Ipv4Addr::new(198, 51, 100, octet), 32);
+        }
+        let builder = builder.redirect("_spf.example.org");
+        let record = builder.build().unwrap();
+        let txt_strings = record.txt_strings();
+        assert_eq!(txt_strings.len(), 3);
+        assert!(txt_strings.iter().all(|txt| txt.len() <= 255));
+        assert_eq!(txt_strings.concat(), record.as_str());
+        assert_eq!(
+            builder.max_length(450).build().unwrap_err(),
+            Error::RecordTooLong
+        );
+
+        // Invalid terms
+        for builder in [
+            SpfRecord::builder().include("example .org"),
+            SpfRecord::builder().include(""),
+            SpfRecord::builder().a_domain("%{x}.example.org"),
+            SpfRecord::builder().mx_domain("exämple.org"),
+            SpfRecord::builder().ip4(Ipv4Addr::LOCALHOST, 33),
+        ] {
+            assert_eq!(builder.build().unwrap_err(), Error::ParseError);
+        }
+    }
+
+    #[test]
+    fn spf_serialize() {
+        for record in [
+            "v=spf1 +mx a:colo.example.com/28 -all",
+            "v=spf1 a/24//64 mx:example.org//96 ptr ptr:example.org ?all",
+            "v=spf1 ip4:10.0.0.0/8 ip6:::ffff:192.0.2.1 ip6:2001:db8::/48 ~all",
+            "v=spf1 exists:%{ir}.%{l1r+-}._spf.%{d} redirect=_spf.example.org exp=explain._spf.%{d}",
+            "v=spf1 include:%%literal%_space.example.org -all ra=postmaster rp=25 rr=e:f",
+            "v=spf1 -all rr=",
+        ] {
+            let spf = Spf::parse(record.as_bytes()).unwrap();
+            let serialized = spf.to_string();
+            assert_eq!(
+                Spf::parse(serialized.as_bytes()).unwrap(),
+                spf,
+                "{record} -> {serialized}"
+            );
+        }
+    }
+}
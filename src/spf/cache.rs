@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    common::lru::{DnsCache, LruCache},
+    Domain, Resolver, SpfOutput, SpfResult,
+};
+
+use super::{Macro, Mechanism, Spf, Variable};
+
+/// Cache of SPF results keyed by domain and source network prefix.
+///
+/// All addresses within a prefix share the same result, which is only correct when
+/// the policy does not distinguish between them. Only the result and its code are
+/// cached, and evaluations of records with an `exp=` modifier, a `ptr` mechanism or
+/// macros depending on the client or sender (`%{s}`, `%{l}`, `%{i}`, `%{p}`, ...) are
+/// not cached. Policies listing networks narrower than the configured prefix may still
+/// evaluate differently for other members of the prefix, keep the TTL short and the
+/// prefixes narrow where this matters.
+#[derive(Debug)]
+pub struct SpfCache {
+    cache: LruCache<(String, IpAddr), SpfOutput>,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    ttl: Duration,
+}
+
+impl SpfCache {
+    /// Creates a cache using /24 IPv4 and /56 IPv6 prefixes and a five minute TTL.
+    pub fn new(capacity: usize) -> Self {
+        SpfCache {
+            cache: LruCache::with_capacity(capacity),
+            ipv4_prefix: 24,
+            ipv6_prefix: 56,
+            ttl: Duration::from_secs(300),
+        }
+    }
+
+    /// Sets the IPv4 prefix length, at most 32.
+    pub fn with_ipv4_prefix(mut self, prefix: u8) -> Self {
+        self.ipv4_prefix = std::cmp::min(prefix, 32);
+        self
+    }
+
+    /// Sets the IPv6 prefix length, at most 128.
+    pub fn with_ipv6_prefix(mut self, prefix: u8) -> Self {
+        self.ipv6_prefix = std::cmp::min(prefix, 128);
+        self
+    }
+
+    /// Sets how long results are cached.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Removes all cached results.
+    pub fn clear(&self) {
+        self.cache.lock().clear();
+    }
+
+    /// Returns an empty cache with the same capacity and settings.
+    pub(crate) fn empty(&self) -> Self {
+        SpfCache {
+            cache: LruCache::with_capacity(self.cache.lock().capacity()),
+            ipv4_prefix: self.ipv4_prefix,
+            ipv6_prefix: self.ipv6_prefix,
+            ttl: self.ttl,
+        }
+    }
+
+    pub(crate) fn get(&self, domain: &str, ip: IpAddr) -> Option<SpfOutput> {
+        self.cache.get(&self.key(domain, ip))
+    }
+
+    pub(crate) fn insert(&self, domain: &str, ip: IpAddr, output: &SpfOutput) {
+        // Temporary errors are not cached
+        if output.result != SpfResult::TempError {
+            let mut cached = SpfOutput::new(output.domain.clone()).with_result(output.result);
+            cached.code = output.code;
+            self.cache
+                .insert(self.key(domain, ip), cached, Instant::now() + self.ttl);
+        }
+    }
+
+    fn key(&self, domain: &str, ip: IpAddr) -> (String, IpAddr) {
+        let ip = match ip {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(
+                u32::from(ip)
+                    & u32::MAX
+                        .checked_shl(32 - self.ipv4_prefix as u32)
+                        .unwrap_or(0),
+            )),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(
+                u128::from(ip)
+                    & u128::MAX
+                        .checked_shl(128 - self.ipv6_prefix as u32)
+                        .unwrap_or(0),
+            )),
+        };
+        (Domain::normalize(domain), ip)
+    }
+}
+
+impl Clone for SpfCache {
+    fn clone(&self) -> Self {
+        Self {
+            cache: Mutex::new(self.cache.lock().clone()),
+            ipv4_prefix: self.ipv4_prefix,
+            ipv6_prefix: self.ipv6_prefix,
+            ttl: self.ttl,
+        }
+    }
+}
+
+impl Spf {
+    /// Returns `false` if evaluating the record may differ between clients sharing
+    /// a cached prefix, or produces an explanation specific to the sender.
+    pub(crate) fn is_cacheable(&self) -> bool {
+        self.exp.is_none()
+            && self
+                .redirect
+                .as_ref()
+                .is_none_or(|redirect| !redirect.depends_on_client())
+            && self
+                .directives
+                .iter()
+                .all(|directive| match &directive.mechanism {
+                    Mechanism::Ptr { .. } => false,
+                    Mechanism::Include { macro_string }
+                    | Mechanism::A { macro_string, .. }
+                    | Mechanism::Mx { macro_string, .. }
+                    | Mechanism::Exists { macro_string } => !macro_string.depends_on_client(),
+                    Mechanism::All | Mechanism::Ip4 { .. } | Mechanism::Ip6 { .. } => true,
+                })
+    }
+}
+
+impl Macro {
+    fn depends_on_client(&self) -> bool {
+        match self {
+            Macro::Variable { letter, .. } => !matches!(
+                letter,
+                Variable::Domain | Variable::IpVersion | Variable::HostDomain
+            ),
+            Macro::List(list) => list.iter().any(Macro::depends_on_client),
+            Macro::Literal(_) | Macro::None => false,
+        }
+    }
+}
+
+impl Resolver {
+    /// Enables caching of SPF results.
+    pub fn with_spf_cache(mut self, cache: SpfCache) -> Self {
+        self.cache_spf = cache.into();
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::IpAddr,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Macro, Spf},
+        Resolver, SpfResult,
+    };
+
+    use super::SpfCache;
+
+    #[tokio::test]
+    async fn spf_cache() {
+        let resolver = Resolver::new_system_conf()
+            .unwrap()
+            .with_spf_cache(SpfCache::new(128).with_ipv4_prefix(24));
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let helo = "mx.example.org";
+        let sender = "user@example.org";
+
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.0/24 -all"),
+            valid_until,
+        );
+        for (ip, result) in [
+            ("192.0.2.1", SpfResult::Pass),
+            ("198.51.100.1", SpfResult::Fail),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_spf_sender(ip.parse::<IpAddr>().unwrap(), helo, helo, sender)
+                    .await
+                    .result(),
+                result
+            );
+        }
+
+        // Results are served from the cache for the whole prefix
+        resolver.txt_add("example.org.", Spf::parse(b"v=spf1 -all"), valid_until);
+        for (ip, result) in [
+            ("192.0.2.200", SpfResult::Pass),
+            ("198.51.100.2", SpfResult::Fail),
+            ("203.0.113.1", SpfResult::Fail),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_spf_sender(ip.parse::<IpAddr>().unwrap(), helo, helo, sender)
+                    .await
+                    .result(),
+                result
+            );
+        }
+
+        resolver.cache_spf.as_ref().unwrap().clear();
+        assert_eq!(
+            resolver
+                .verify_spf_sender("192.0.2.1".parse().unwrap(), helo, helo, sender)
+                .await
+                .result(),
+            SpfResult::Fail
+        );
+
+        // Explanations and sender dependent policies are not cached
+        resolver.txt_add(
+            "example.net.",
+            Spf::parse(b"v=spf1 -all exp=explain.example.net"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "explain.example.net.",
+            Macro::parse(b"%{s} is not allowed"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "example.com.",
+            Spf::parse(b"v=spf1 exists:%{l}.users.example.com -all"),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "allowed.users.example.com.",
+            vec!["127.0.0.2".parse().unwrap()],
+            valid_until,
+        );
+        for (sender, explanation) in [
+            ("jane@example.net", "jane@example.net is not allowed"),
+            ("john@example.net", "john@example.net is not allowed"),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_spf_sender("192.0.2.1".parse().unwrap(), helo, helo, sender)
+                    .await
+                    .explanation(),
+                Some(explanation)
+            );
+        }
+        for (sender, result) in [
+            ("allowed@example.com", SpfResult::Pass),
+            ("denied@example.com", SpfResult::Fail),
+        ] {
+            assert_eq!(
+                resolver
+                    .verify_spf_sender("192.0.2.1".parse().unwrap(), helo, helo, sender)
+                    .await
+                    .result(),
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,584 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use futures_util::future::BoxFuture;
+
+use crate::{Error, Resolver, Version};
+
+use super::{
+    Directive, Macro, Mechanism, Qualifier, Spf, SpfAnalysis, SpfTraceEvent, SpfTraceStep,
+    Variable, Variables,
+};
+
+// RFC 7208 Section 4.6.4
+const MAX_LOOKUPS: u32 = 10;
+const MAX_VOID_LOOKUPS: u32 = 2;
+
+#[derive(Default)]
+struct Flattener {
+    lookups: u32,
+    void_lookups: u32,
+    steps: Vec<SpfTraceStep>,
+    directives: Vec<Directive>,
+    is_static: bool,
+    path: Vec<String>,
+}
+
+// Where the directives of a record end up in the flattened record
+#[derive(Clone)]
+enum Target {
+    Record,
+    Include(Qualifier),
+    Ignore,
+}
+
+impl Resolver {
+    /// Resolves the SPF record of `domain` and all of its `include:` and `redirect=`
+    /// targets, counting the DNS lookups an evaluation would perform and flattening
+    /// the `a`, `mx`, `include:` and `redirect=` terms into `ip4:` and `ip6:` mechanisms.
+    pub async fn analyze_spf(&self, domain: &str) -> crate::Result<SpfAnalysis> {
+        let spf = self.txt_lookup::<Spf>(domain).await?;
+        let mut flattener = Flattener {
+            is_static: true,
+            ..Default::default()
+        };
+        self.flatten_record(
+            &mut flattener,
+            domain.trim_end_matches('.').to_lowercase(),
+            spf.clone(),
+            Target::Record,
+            0,
+        )
+        .await?;
+
+        Ok(SpfAnalysis {
+            lookups: flattener.lookups,
+            void_lookups: flattener.void_lookups,
+            steps: flattener.steps,
+            flattened: Spf {
+                version: Version::V1,
+                directives: flattener.directives,
+                exp: spf.exp.clone(),
+                redirect: None,
+                ra: spf.ra.clone(),
+                rp: spf.rp,
+                rr: spf.rr,
+            },
+            is_static: flattener.is_static,
+        })
+    }
+
+    fn flatten_record<'x>(
+        &'x self,
+        flattener: &'x mut Flattener,
+        domain: String,
+        spf: Arc<Spf>,
+        target: Target,
+        depth: usize,
+    ) -> BoxFuture<'x, crate::Result<()>> {
+        Box::pin(async move {
+            flattener.push_step(
+                depth,
+                SpfTraceEvent::Record {
+                    domain: domain.clone(),
+                },
+            );
+            flattener.path.push(domain.clone());
+            let target = match target {
+                // Addresses excluded by the included record cannot be subtracted
+                // from the ranges authorized after them, so the include is kept
+                Target::Include(qualifier) if shadows_pass(&spf) => {
+                    flattener.is_static = false;
+                    flattener.push(
+                        qualifier,
+                        Mechanism::Include {
+                            macro_string: Macro::Literal(domain.as_bytes().to_vec()),
+                        },
+                    );
+                    Target::Ignore
+                }
+                target => target,
+            };
+            let mut vars = Variables::new();
+            vars.set_domain(domain.as_bytes());
+
+            for directive in &spf.directives {
+                let qualifier = target.qualifier(&directive.qualifier);
+                match &directive.mechanism {
+                    Mechanism::All => {
+                        if let Some(qualifier) = qualifier {
+                            flattener.push(qualifier, Mechanism::All);
+                        }
+                        break;
+                    }
+                    Mechanism::Ip4 { addr, mask } => {
+                        if let Some(qualifier) = qualifier {
+                            flattener.push_ip4(qualifier, *addr, *mask);
+                        }
+                    }
+                    Mechanism::Ip6 { addr, mask } => {
+                        if let Some(qualifier) = qualifier {
+                            flattener.push_ip6(qualifier, *addr, *mask);
+                        }
+                    }
+                    Mechanism::A {
+                        macro_string,
+                        ip4_mask,
+                        ip6_mask,
+                    } => {
+                        let Some(name) = expand_static(macro_string, &vars, &domain) else {
+                            flattener.push_dynamic(depth, qualifier, directive);
+                            continue;
+                        };
+                        flattener.push_lookup(depth, &name);
+                        let (ip4, ip6) = self.host_addresses(&name).await?;
+                        if ip4.is_empty() && ip6.is_empty() {
+                            flattener.void_lookups += 1;
+                        }
+                        if let Some(qualifier) = qualifier {
+                            for addr in ip4 {
+                                flattener.push_ip4(qualifier.clone(), addr, *ip4_mask);
+                            }
+                            for addr in ip6 {
+                                flattener.push_ip6(qualifier.clone(), addr, *ip6_mask);
+                            }
+                        }
+                    }
+                    Mechanism::Mx {
+                        macro_string,
+                        ip4_mask,
+                        ip6_mask,
+                    } => {
+                        let Some(name) = expand_static(macro_string, &vars, &domain) else {
+                            flattener.push_dynamic(depth, qualifier, directive);
+                            continue;
+                        };
+                        flattener.push_lookup(depth, &name);
+                        let records = match self.mx_lookup(name.as_str()).await {
+                            Ok(records) => records,
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                flattener.void_lookups += 1;
+                                continue;
+                            }
+                            Err(err) => return Err(err),
+                        };
+                        // Evaluating more exchanges than allowed fails, so the
+                        // mechanism is kept for the evaluator to reach the limit
+                        if records.iter().map(|mx| mx.exchanges.len()).sum::<usize>()
+                            > self.config().limits().spf_mx_lookups()
+                        {
+                            flattener.is_static = false;
+                            if let Some(qualifier) = qualifier {
+                                flattener.push(
+                                    qualifier,
+                                    Mechanism::Mx {
+                                        macro_string: Macro::Literal(name.into_bytes()),
+                                        ip4_mask: *ip4_mask,
+                                        ip6_mask: *ip6_mask,
+                                    },
+                                );
+                            }
+                            continue;
+                        }
+                        for exchange in records.iter().flat_map(|mx| mx.exchanges.iter()) {
+                            let (ip4, ip6) = self.host_addresses(exchange).await?;
+                            if let Some(qualifier) = &qualifier {
+                                for addr in ip4 {
+                                    flattener.push_ip4(qualifier.clone(), addr, *ip4_mask);
+                                }
+                                for addr in ip6 {
+                                    flattener.push_ip6(qualifier.clone(), addr, *ip6_mask);
+                                }
+                            }
+                        }
+                    }
+                    Mechanism::Include { macro_string } => {
+                        let Some(name) = expand_static(macro_string, &vars, &domain) else {
+                            flattener.push_dynamic(depth, qualifier, directive);
+                            continue;
+                        };
+                        flattener.push_lookup(depth, &name);
+                        let target = match qualifier {
+                            Some(qualifier) => Target::Include(qualifier),
+                            None => Target::Ignore,
+                        };
+                        self.flatten_target(flattener, name, target, depth + 1)
+                            .await?;
+                    }
+                    Mechanism::Ptr { .. } | Mechanism::Exists { .. } => {
+                        flattener.push_dynamic(depth, qualifier, directive);
+                    }
+                }
+            }
+
+            // Redirects are only followed when no mechanism matches
+            if let Some(redirect) = spf
+                .redirect
+                .as_ref()
+                .filter(|_| !spf.directives.iter().any(|d| d.mechanism == Mechanism::All))
+            {
+                if let Some(name) = expand_static(redirect, &vars, &domain) {
+                    flattener.push_lookup(depth, &name);
+                    self.flatten_target(flattener, name, target, depth + 1)
+                        .await?;
+                } else {
+                    flattener.lookups += 1;
+                    flattener.is_static = false;
+                }
+            }
+
+            flattener.path.pop();
+            Ok(())
+        })
+    }
+
+    async fn flatten_target(
+        &self,
+        flattener: &mut Flattener,
+        name: String,
+        target: Target,
+        depth: usize,
+    ) -> crate::Result<()> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        if flattener.path.contains(&name) {
+            return Err(Error::DnsError(format!("SPF include loop at {name}")));
+        }
+        let spf = self.txt_lookup::<Spf>(name.as_str()).await?;
+        self.flatten_record(flattener, name, spf, target, depth)
+            .await
+    }
+
+    async fn host_addresses(&self, name: &str) -> crate::Result<(Vec<Ipv4Addr>, Vec<Ipv6Addr>)> {
+        let ip4 = match self.ipv4_lookup(name).await {
+            Ok(addrs) => addrs.to_vec(),
+            Err(Error::DnsRecordNotFound(_)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let ip6 = match self.ipv6_lookup(name).await {
+            Ok(addrs) => addrs.to_vec(),
+            Err(Error::DnsRecordNotFound(_)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        Ok((ip4, ip6))
+    }
+}
+
+impl Flattener {
+    fn push(&mut self, qualifier: Qualifier, mechanism: Mechanism) {
+        let directive = Directive::new(qualifier, mechanism);
+        if !self.directives.contains(&directive) {
+            self.directives.push(directive);
+        }
+    }
+
+    fn push_ip4(&mut self, qualifier: Qualifier, addr: Ipv4Addr, mask: u32) {
+        self.push(
+            qualifier,
+            Mechanism::Ip4 {
+                addr: Ipv4Addr::from(u32::from(addr) & mask),
+                mask,
+            },
+        );
+    }
+
+    fn push_ip6(&mut self, qualifier: Qualifier, addr: Ipv6Addr, mask: u128) {
+        self.push(
+            qualifier,
+            Mechanism::Ip6 {
+                addr: Ipv6Addr::from(u128::from(addr) & mask),
+                mask,
+            },
+        );
+    }
+
+    // Mechanisms that depend on the message being evaluated are copied as is
+    fn push_dynamic(&mut self, depth: usize, qualifier: Option<Qualifier>, directive: &Directive) {
+        self.lookups += 1;
+        self.is_static = false;
+        self.push_step(
+            depth,
+            SpfTraceEvent::Directive {
+                directive: directive.clone(),
+                matched: false,
+            },
+        );
+        if let Some(qualifier) = qualifier {
+            self.push(qualifier, directive.mechanism.clone());
+        }
+    }
+
+    fn push_lookup(&mut self, depth: usize, name: &str) {
+        self.lookups += 1;
+        self.push_step(
+            depth,
+            SpfTraceEvent::Lookup {
+                name: name.to_string(),
+                count: self.lookups,
+            },
+        );
+    }
+
+    fn push_step(&mut self, depth: usize, event: SpfTraceEvent) {
+        self.steps.push(SpfTraceStep {
+            depth: depth as u32,
+            event,
+        });
+    }
+}
+
+// Returns `true` if a directive not matching as `pass` precedes a directive or
+// redirect that may, which makes the included record impossible to flatten
+fn shadows_pass(spf: &Spf) -> bool {
+    let mut has_non_pass = false;
+    for directive in &spf.directives {
+        if directive.qualifier == Qualifier::Pass {
+            if has_non_pass {
+                return true;
+            }
+        } else if directive.mechanism != Mechanism::All {
+            has_non_pass = true;
+        }
+        if directive.mechanism == Mechanism::All {
+            return false;
+        }
+    }
+    has_non_pass && spf.redirect.is_some()
+}
+
+impl Target {
+    fn qualifier(&self, qualifier: &Qualifier) -> Option<Qualifier> {
+        match self {
+            Target::Record => Some(qualifier.clone()),
+            Target::Include(include) if qualifier == &Qualifier::Pass => Some(include.clone()),
+            Target::Include(_) | Target::Ignore => None,
+        }
+    }
+}
+
+fn expand_static(macro_string: &Macro, vars: &Variables<'_>, domain: &str) -> Option<String> {
+    fn is_static(macro_string: &Macro) -> bool {
+        match macro_string {
+            Macro::Literal(_) | Macro::None => true,
+            Macro::Variable { letter, .. } => *letter == Variable::Domain,
+            Macro::List(list) => list.iter().all(is_static),
+        }
+    }
+
+    is_static(macro_string).then(|| macro_string.eval(vars, domain, true).into_owned())
+}
+
+impl SpfAnalysis {
+    /// Returns the number of DNS lookups counted against the limit of RFC 7208 Section 4.6.4.
+    pub fn lookups(&self) -> u32 {
+        self.lookups
+    }
+
+    /// Returns the number of lookups that returned no records.
+    pub fn void_lookups(&self) -> u32 {
+        self.void_lookups
+    }
+
+    /// Returns `true` if evaluating the record would fail with a PermError
+    /// due to the RFC 7208 lookup limits.
+    pub fn exceeds_limits(&self) -> bool {
+        self.lookups > MAX_LOOKUPS || self.void_lookups > MAX_VOID_LOOKUPS
+    }
+
+    /// Returns the records fetched and the lookups performed, in evaluation order.
+    pub fn steps(&self) -> &[SpfTraceStep] {
+        &self.steps
+    }
+
+    /// Returns the flattened record. Mechanisms depending on the message being
+    /// evaluated, such as `ptr` and `exists`, are copied unchanged, as are
+    /// `mx` mechanisms exceeding the MX lookup limit and includes excluding
+    /// addresses before authorizing others.
+    pub fn flattened(&self) -> &Spf {
+        &self.flattened
+    }
+
+    /// Returns `true` if the flattened record consists only of `ip4`, `ip6` and `all`
+    /// mechanisms and therefore requires no further DNS lookups.
+    pub fn is_static(&self) -> bool {
+        self.is_static
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::parse::TxtRecordParser,
+        spf::{Spf, SpfRecordBuilder, SpfTraceEvent},
+        Error, Resolver, MX,
+    };
+
+    #[tokio::test]
+    async fn spf_analyze() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        for (name, record) in [
+            (
+                "example.org.",
+                "v=spf1 a mx include:_spf.example.net -include:_bad.example.net ~all",
+            ),
+            (
+                "_spf.example.net.",
+                "v=spf1 ip4:192.0.2.0/24 include:_ip6.example.net -ip4:198.51.100.1 -all",
+            ),
+            ("_ip6.example.net.", "v=spf1 redirect=_redirect.example.net"),
+            (
+                "_redirect.example.net.",
+                "v=spf1 ip6:2001:db8::/32 a:missing.example.net ?all",
+            ),
+            (
+                "_bad.example.net.",
+                "v=spf1 ip4:203.0.113.7 exists:%{i}.bl.example.net",
+            ),
+            ("loop.example.org.", "v=spf1 include:_loop.example.org -all"),
+            ("_loop.example.org.", "v=spf1 include:loop.example.org"),
+        ] {
+            resolver.txt_add(name, Spf::parse(record.as_bytes()).unwrap(), valid_until);
+        }
+        resolver.ipv4_add(
+            "example.org.",
+            vec!["192.0.2.10".parse().unwrap()],
+            valid_until,
+        );
+        resolver.mx_add(
+            "example.org.",
+            vec![MX {
+                exchanges: vec!["mx.example.org.".to_string()],
+                preference: 10,
+            }],
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "mx.example.org.",
+            vec!["198.51.100.25".parse().unwrap()],
+            valid_until,
+        );
+        resolver.ipv6_add(
+            "mx.example.org.",
+            vec!["2001:db8:1::25".parse().unwrap()],
+            valid_until,
+        );
+
+        let analysis = resolver.analyze_spf("example.org").await.unwrap();
+        assert_eq!(analysis.lookups(), 8);
+        assert_eq!(analysis.void_lookups(), 1);
+        assert!(!analysis.exceeds_limits());
+        assert!(!analysis.is_static());
+        assert_eq!(
+            analysis.flattened().to_string(),
+            concat!(
+                "v=spf1 ip4:192.0.2.10 ip4:198.51.100.25 ip6:2001:db8:1::25 ",
+                "ip4:192.0.2.0/24 ip6:2001:db8::/32 -ip4:203.0.113.7 ",
+                "-exists:%{i}.bl.example.net ~all"
+            )
+        );
+        assert_eq!(
+            analysis
+                .steps()
+                .iter()
+                .filter_map(|step| match step.event() {
+                    SpfTraceEvent::Record { domain } => Some((step.depth(), domain.as_str())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+            [
+                (0, "example.org"),
+                (1, "_spf.example.net"),
+                (2, "_ip6.example.net"),
+                (3, "_redirect.example.net"),
+                (1, "_bad.example.net"),
+            ]
+        );
+        assert_eq!(
+            SpfRecordBuilder::from(analysis.flattened().clone())
+                .build()
+                .unwrap()
+                .as_str(),
+            analysis.flattened().to_string()
+        );
+
+        assert!(matches!(
+            resolver.analyze_spf("loop.example.org").await,
+            Err(Error::DnsError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn spf_analyze_excluded_ranges() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        for (name, record) in [
+            ("example.org.", "v=spf1 include:_spf.example.net -all"),
+            (
+                "_spf.example.net.",
+                "v=spf1 -ip4:192.0.2.5 ip4:192.0.2.0/24 -all",
+            ),
+            (
+                "example.com.",
+                "v=spf1 include:_tail.example.net ip4:198.51.100.0/24 -all",
+            ),
+            (
+                "_tail.example.net.",
+                "v=spf1 ip4:192.0.2.0/24 -ip4:203.0.113.5 -all",
+            ),
+        ] {
+            resolver.txt_add(name, Spf::parse(record.as_bytes()).unwrap(), valid_until);
+        }
+
+        // 192.0.2.5 is rejected by the included record and cannot be flattened
+        let analysis = resolver.analyze_spf("example.org").await.unwrap();
+        assert!(!analysis.is_static());
+        assert_eq!(analysis.lookups(), 1);
+        assert_eq!(
+            analysis.flattened().to_string(),
+            "v=spf1 include:_spf.example.net -all"
+        );
+
+        // Exclusions after the last authorized range have no effect
+        let analysis = resolver.analyze_spf("example.com").await.unwrap();
+        assert!(analysis.is_static());
+        assert_eq!(
+            analysis.flattened().to_string(),
+            "v=spf1 ip4:192.0.2.0/24 ip4:198.51.100.0/24 -all"
+        );
+
+        // MX mechanisms exceeding the MX lookup limit are not flattened
+        resolver.txt_add(
+            "example.net.",
+            Spf::parse(b"v=spf1 mx -all").unwrap(),
+            valid_until,
+        );
+        resolver.mx_add(
+            "example.net.",
+            vec![MX {
+                exchanges: (0..11).map(|i| format!("mx{i}.example.net.")).collect(),
+                preference: 10,
+            }],
+            valid_until,
+        );
+        let analysis = resolver.analyze_spf("example.net").await.unwrap();
+        assert!(!analysis.is_static());
+        assert_eq!(
+            analysis.flattened().to_string(),
+            "v=spf1 mx:example.net -all"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::Domain;
+
+use super::HeloIdentity;
+
+impl HeloIdentity {
+    /// Parses and normalizes the argument of a HELO or EHLO command.
+    /// Bare IP addresses are accepted as address literals.
+    pub fn parse(helo: &str) -> Self {
+        let helo = helo.trim();
+        if let Some(literal) = helo.strip_prefix('[').and_then(|h| h.strip_suffix(']')) {
+            match literal.get(..5) {
+                Some(prefix) if prefix.eq_ignore_ascii_case("IPv6:") => {
+                    literal[5..].parse::<Ipv6Addr>().ok().map(IpAddr::V6)
+                }
+                _ => literal.parse::<Ipv4Addr>().ok().map(IpAddr::V4),
+            }
+            .map_or_else(
+                || HeloIdentity::Invalid(helo.to_string()),
+                Self::AddressLiteral,
+            )
+        } else if let Ok(ip) = helo.parse::<IpAddr>() {
+            HeloIdentity::AddressLiteral(ip)
+        } else {
+            match Domain::new(helo) {
+                Ok(domain) if domain.as_str().contains('.') => HeloIdentity::Domain(domain.into()),
+                _ => HeloIdentity::Invalid(helo.to_string()),
+            }
+        }
+    }
+
+    /// Returns the domain name, if the identity is a fully qualified domain name.
+    pub fn domain(&self) -> Option<&str> {
+        match self {
+            HeloIdentity::Domain(domain) => Some(domain),
+            _ => None,
+        }
+    }
+
+    pub fn is_address_literal(&self) -> bool {
+        matches!(self, HeloIdentity::AddressLiteral(_))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::{Duration, Instant},
+    };
+
+    use crate::{common::parse::TxtRecordParser, spf::Spf, Resolver, SpfResult};
+
+    use super::HeloIdentity;
+
+    #[tokio::test]
+    async fn spf_helo_identity() {
+        for (helo, expected) in [
+            (
+                "Mail.Example.ORG.",
+                HeloIdentity::Domain("mail.example.org".to_string()),
+            ),
+            (
+                "[192.0.2.1]",
+                HeloIdentity::AddressLiteral(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            ),
+            (
+                "[ipv6:2001:db8::1]",
+                HeloIdentity::AddressLiteral(IpAddr::V6(Ipv6Addr::new(
+                    0x2001, 0xdb8, 0, 0, 0, 0, 0, 1,
+                ))),
+            ),
+            (
+                "192.0.2.1",
+                HeloIdentity::AddressLiteral(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            ),
+            (
+                "[192.0.2.300]",
+                HeloIdentity::Invalid("[192.0.2.300]".to_string()),
+            ),
+            ("localhost", HeloIdentity::Invalid("localhost".to_string())),
+            (
+                "mail..example.org",
+                HeloIdentity::Invalid("mail..example.org".to_string()),
+            ),
+            ("", HeloIdentity::Invalid("".to_string())),
+        ] {
+            assert_eq!(HeloIdentity::parse(helo), expected, "{helo}");
+        }
+
+        let resolver = Resolver::new_system_conf().unwrap();
+        resolver.txt_add(
+            "mail.example.org.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.1 -all").unwrap(),
+            Instant::now() + Duration::new(3200, 0),
+        );
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        // Names are evaluated in their normalized form
+        let output = resolver
+            .verify_spf_helo(ip, "Mail.Example.ORG.", "mx.example.net")
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert_eq!(output.domain(), "mail.example.org");
+        assert_eq!(
+            output.helo_identity().and_then(|helo| helo.domain()),
+            Some("mail.example.org")
+        );
+
+        // Address literals and malformed names are not evaluated
+        for helo in ["[192.0.2.1]", "localhost"] {
+            let output = resolver.verify_spf_helo(ip, helo, "mx.example.net").await;
+            assert_eq!(output.result(), SpfResult::None);
+            assert_eq!(output.domain(), helo);
+            assert_eq!(
+                output.helo_identity().unwrap().is_address_literal(),
+                helo.starts_with('[')
+            );
+        }
+
+        // Null senders with an address literal HELO have no MAIL FROM identity
+        let output = resolver
+            .verify_spf_sender(ip, "[192.0.2.1]", "mx.example.net", "")
+            .await;
+        assert_eq!(output.result(), SpfResult::None);
+    }
+}
@@ -8,16 +8,23 @@
  * except according to those terms.
  */
 
+pub mod builder;
+pub mod cache;
+pub mod flatten;
+pub mod helo;
 pub mod macros;
 pub mod parse;
 pub mod verify;
 
 use std::{
     borrow::Cow,
-    net::{Ipv4Addr, Ipv6Addr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
 
-use crate::{is_within_pct, SpfOutput, SpfResult, Version};
+use crate::{
+    common::{codes::ResultCode, domain::to_unicode},
+    is_within_pct, SpfIdentitiesOutput, SpfOutput, SpfResult, Version,
+};
 
 /*
       "+" pass
@@ -27,6 +34,7 @@ use crate::{is_within_pct, SpfOutput, SpfResult, Version};
 */
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Qualifier {
     Pass,
     Fail,
@@ -39,6 +47,7 @@ pub enum Qualifier {
                       / a / mx / ptr / ip4 / ip6 / exists )
 */
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mechanism {
     All,
     Include {
@@ -74,6 +83,7 @@ pub enum Mechanism {
     directive        = [ qualifier ] mechanism
 */
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Directive {
     pub qualifier: Qualifier,
     pub mechanism: Mechanism,
@@ -96,6 +106,7 @@ pub struct Directive {
 */
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(u8)]
 pub enum Variable {
     Sender = 0,
@@ -111,12 +122,60 @@ pub enum Variable {
     CurrentTime = 10,
 }
 
+/// HELO/EHLO identity of an SMTP client.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeloIdentity {
+    /// Fully qualified domain name, lowercased and without a trailing dot.
+    Domain(String),
+    /// Address literal such as `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+    AddressLiteral(IpAddr),
+    /// Malformed or unqualified name.
+    Invalid(String),
+}
+
+/// Step-by-step record of an SPF evaluation.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpfTrace {
+    steps: Vec<SpfTraceStep>,
+    lookups: u32,
+    max_lookups: u32,
+    matched: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpfTraceStep {
+    depth: u32,
+    event: SpfTraceEvent,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SpfTraceEvent {
+    /// The SPF record of a domain was fetched and its evaluation started.
+    Record { domain: String },
+    /// A macro string was expanded.
+    Macro {
+        macro_string: Macro,
+        expanded: String,
+    },
+    /// A DNS lookup counted against the lookup limit was issued.
+    Lookup { name: String, count: u32 },
+    /// A directive was evaluated.
+    Directive { directive: Directive, matched: bool },
+    /// The target of an `mx` mechanism publishes a Null MX record (RFC 7505).
+    NullMx { name: String },
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
 pub struct Variables<'x> {
     vars: [Cow<'x, [u8]>; 11],
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Macro {
     Literal(Vec<u8>),
     Variable {
@@ -131,6 +190,7 @@ pub enum Macro {
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Spf {
     pub version: Version,
     pub directives: Vec<Directive>,
@@ -141,6 +201,31 @@ pub struct Spf {
     pub rr: u8,
 }
 
+/// DNS lookups performed by an SPF record and its flattened equivalent.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpfAnalysis {
+    lookups: u32,
+    void_lookups: u32,
+    steps: Vec<SpfTraceStep>,
+    flattened: Spf,
+    is_static: bool,
+}
+
+/// Serialized SPF TXT record.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SpfRecord {
+    record: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpfRecordBuilder {
+    spf: Spf,
+    all: Option<Qualifier>,
+    max_length: usize,
+    invalid: bool,
+}
+
 pub(crate) const RR_TEMP_PERM_ERROR: u8 = 0x01;
 pub(crate) const RR_FAIL: u8 = 0x02;
 pub(crate) const RR_SOFTFAIL: u8 = 0x04;
@@ -208,10 +293,18 @@ impl SpfOutput {
             result: SpfResult::None,
             report: None,
             explanation: None,
+            code: None,
+            trace: None,
+            helo: None,
             domain,
         }
     }
 
+    pub(crate) fn with_code(mut self, code: ResultCode) -> Self {
+        self.code = code.into();
+        self
+    }
+
     pub(crate) fn with_result(mut self, result: SpfResult) -> Self {
         self.result = result;
         self
@@ -242,6 +335,16 @@ impl SpfOutput {
         self
     }
 
+    pub(crate) fn with_trace(mut self, trace: SpfTrace) -> Self {
+        self.trace = trace.into();
+        self
+    }
+
+    pub(crate) fn with_helo_identity(mut self, helo: HeloIdentity) -> Self {
+        self.helo = helo.into();
+        self
+    }
+
     pub fn result(&self) -> SpfResult {
         self.result
     }
@@ -250,11 +353,115 @@ impl SpfOutput {
         &self.domain
     }
 
+    /// Returns the domain with its A-labels converted to U-labels, for display.
+    pub fn domain_unicode(&self) -> Cow<'_, str> {
+        to_unicode(&self.domain)
+    }
+
     pub fn explanation(&self) -> Option<&str> {
         self.explanation.as_deref()
     }
 
+    /// Returns the HELO/EHLO identity evaluated by `verify_spf_helo`.
+    pub fn helo_identity(&self) -> Option<&HeloIdentity> {
+        self.helo.as_ref()
+    }
+
     pub fn report_address(&self) -> Option<&str> {
         self.report.as_deref()
     }
+
+    /// Returns the evaluation trace, if SPF tracing is enabled.
+    pub fn trace(&self) -> Option<&SpfTrace> {
+        self.trace.as_ref()
+    }
+}
+
+impl SpfIdentitiesOutput {
+    pub fn helo(&self) -> &SpfOutput {
+        &self.helo
+    }
+
+    pub fn mail_from(&self) -> &SpfOutput {
+        &self.mail_from
+    }
+
+    /// Returns `true` if the message has a null reverse-path.
+    pub fn is_null_sender(&self) -> bool {
+        self.is_null_sender
+    }
+
+    /// Returns the output of the identity used for policy decisions, the MAIL FROM
+    /// identity or, for null reverse-paths, the HELO identity (RFC 7208 Section 2.4).
+    pub fn combined(&self) -> &SpfOutput {
+        if self.is_null_sender {
+            &self.helo
+        } else {
+            &self.mail_from
+        }
+    }
+
+    pub fn into_combined(self) -> SpfOutput {
+        if self.is_null_sender {
+            self.helo
+        } else {
+            self.mail_from
+        }
+    }
+}
+
+impl SpfTrace {
+    pub(crate) fn new(max_lookups: u32) -> Self {
+        SpfTrace {
+            max_lookups,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, depth: usize, event: SpfTraceEvent) {
+        match &event {
+            SpfTraceEvent::Lookup { count, .. } => self.lookups = *count,
+            SpfTraceEvent::Directive { matched: true, .. } => self.matched = Some(self.steps.len()),
+            _ => (),
+        }
+        self.steps.push(SpfTraceStep {
+            depth: depth as u32,
+            event,
+        });
+    }
+
+    pub(crate) fn clear_match(&mut self) {
+        self.matched = None;
+    }
+
+    /// Returns the evaluation steps in the order they were performed.
+    pub fn steps(&self) -> &[SpfTraceStep] {
+        &self.steps
+    }
+
+    /// Returns the number of DNS lookups counted against the lookup limit.
+    pub fn lookups(&self) -> u32 {
+        self.lookups
+    }
+
+    /// Returns the DNS lookup limit in effect during the evaluation.
+    pub fn max_lookups(&self) -> u32 {
+        self.max_lookups
+    }
+
+    /// Returns the step of the top-level directive that determined the result, if any.
+    pub fn matched(&self) -> Option<&SpfTraceStep> {
+        self.matched.and_then(|pos| self.steps.get(pos))
+    }
+}
+
+impl SpfTraceStep {
+    /// Returns the `include` nesting level of this step.
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+
+    pub fn event(&self) -> &SpfTraceEvent {
+        &self.event
+    }
 }
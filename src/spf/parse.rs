@@ -24,6 +24,9 @@ use super::{
 };
 
 impl TxtRecordParser for Spf {
+    const UNIQUE: bool = true;
+    const LEGACY_RECORD_TYPE: Option<u16> = Some(99);
+
     fn parse(bytes: &[u8]) -> crate::Result<Spf> {
         let mut record = bytes.iter();
         if !matches!(record.key(), Some(k) if k == V)
@@ -9,35 +9,47 @@
  */
 
 use std::{
+    borrow::Cow,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
-use crate::{Error, Resolver, SpfOutput, SpfResult};
+use crate::{
+    common::{
+        codes::ResultCode,
+        config::Limits,
+        domain::{address_to_ascii, to_ascii},
+        mx::{mx_records_policy, MxPolicy},
+    },
+    Error, Resolver, SpfIdentitiesOutput, SpfOutput, SpfResult,
+};
 
-use super::{Macro, Mechanism, Qualifier, Spf, Variables};
+use super::{HeloIdentity, Macro, Mechanism, Qualifier, Spf, SpfTrace, SpfTraceEvent, Variables};
 
 #[allow(clippy::iter_skip_zero)]
 impl Resolver {
-    /// Verifies the SPF EHLO identity
+    /// Verifies the SPF EHLO identity. Address literals and malformed names
+    /// are not evaluated and return `SpfResult::None` (RFC 7208 Section 2.3).
     pub async fn verify_spf_helo(
         &self,
         ip: IpAddr,
         helo_domain: &str,
         host_domain: &str,
     ) -> SpfOutput {
-        if helo_domain.has_valid_labels() {
+        let helo = HeloIdentity::parse(helo_domain);
+        if let Some(domain) = helo.domain() {
             self.check_host(
                 ip,
-                helo_domain,
+                domain,
                 helo_domain,
                 host_domain,
-                &format!("postmaster@{helo_domain}"),
+                &format!("postmaster@{domain}"),
             )
             .await
         } else {
             SpfOutput::new(helo_domain.to_string()).with_result(SpfResult::None)
         }
+        .with_helo_identity(helo)
     }
 
     /// Verifies the SPF MAIL FROM identity
@@ -48,14 +60,23 @@ impl Resolver {
         host_domain: &str,
         sender: &str,
     ) -> SpfOutput {
-        self.check_host(
-            ip,
-            sender.rsplit_once('@').map_or(helo_domain, |(_, d)| d),
-            helo_domain,
-            host_domain,
-            sender,
-        )
-        .await
+        match sender.rsplit_once('@') {
+            Some((_, domain)) => {
+                self.check_host(ip, domain, helo_domain, host_domain, sender)
+                    .await
+            }
+            None => {
+                // Null reverse-path, evaluate postmaster@<helo> unless the HELO
+                // identity is an address literal or malformed.
+                let helo = HeloIdentity::parse(helo_domain);
+                if let Some(domain) = helo.domain() {
+                    self.check_host(ip, domain, helo_domain, host_domain, sender)
+                        .await
+                } else {
+                    SpfOutput::new(helo_domain.to_string()).with_result(SpfResult::None)
+                }
+            }
+        }
     }
 
     /// Verifies both the SPF EHLO and MAIL FROM identities
@@ -77,19 +98,148 @@ impl Resolver {
         }
     }
 
+    /// Verifies the SPF EHLO and MAIL FROM identities concurrently
+    pub async fn verify_spf_identities(
+        &self,
+        ip: IpAddr,
+        helo_domain: &str,
+        host_domain: &str,
+        mail_from: &str,
+    ) -> SpfIdentitiesOutput {
+        let (helo, mail_from, is_null_sender) = match mail_from.rsplit_once('@') {
+            Some((_, domain)) if !domain.eq_ignore_ascii_case(helo_domain) => {
+                let (helo, mail_from) = futures_util::future::join(
+                    self.verify_spf_helo(ip, helo_domain, host_domain),
+                    self.verify_spf_sender(ip, helo_domain, host_domain, mail_from),
+                )
+                .await;
+                (helo, mail_from, false)
+            }
+            Some(_) => {
+                // Both identities share the same domain, evaluate them in sequence
+                // so that the second check is answered from the DNS cache.
+                let mail_from = self
+                    .verify_spf_sender(ip, helo_domain, host_domain, mail_from)
+                    .await;
+                let helo = self.verify_spf_helo(ip, helo_domain, host_domain).await;
+                (helo, mail_from, false)
+            }
+            None => {
+                // Null reverse-path, the MAIL FROM identity is postmaster@<helo>
+                let helo = self.verify_spf_helo(ip, helo_domain, host_domain).await;
+                (helo.clone(), helo, true)
+            }
+        };
+
+        SpfIdentitiesOutput {
+            helo,
+            mail_from,
+            is_null_sender,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, helo_domain, host_domain, sender))
+    )]
+    pub async fn check_host(
+        &self,
+        ip: IpAddr,
+        domain: &str,
+        helo_domain: &str,
+        host_domain: &str,
+        sender: &str,
+    ) -> SpfOutput {
+        // Internationalized domains are evaluated using A-labels (RFC 8616)
+        let domain = to_ascii(domain);
+        let helo_domain = to_ascii(helo_domain);
+        let sender = address_to_ascii(sender);
+        let (domain, helo_domain, sender) =
+            (domain.as_ref(), helo_domain.as_ref(), sender.as_ref());
+
+        match &self.cache_spf {
+            Some(cache) if !self.config().spf_trace() => {
+                if let Some(output) = cache.get(domain, ip) {
+                    return output;
+                }
+                let mut cacheable = true;
+                let output = self
+                    .check_host_uncached(
+                        ip,
+                        domain,
+                        helo_domain,
+                        host_domain,
+                        sender,
+                        &mut cacheable,
+                    )
+                    .await;
+                if cacheable {
+                    cache.insert(domain, ip, &output);
+                }
+                output
+            }
+            _ => {
+                self.check_host_uncached(ip, domain, helo_domain, host_domain, sender, &mut true)
+                    .await
+            }
+        }
+    }
+
+    async fn check_host_uncached(
+        &self,
+        ip: IpAddr,
+        domain: &str,
+        helo_domain: &str,
+        host_domain: &str,
+        sender: &str,
+        cacheable: &mut bool,
+    ) -> SpfOutput {
+        let _permit = self.fairness_permit(domain).await;
+        let config = self.config();
+        let mut trace = config
+            .spf_trace()
+            .then(|| SpfTrace::new(config.limits().spf_lookups()));
+        let output = self
+            .evaluate_spf(
+                ip,
+                domain,
+                helo_domain,
+                host_domain,
+                sender,
+                &mut trace,
+                cacheable,
+            )
+            .await;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            result = %output.result(),
+            code = output.result_code().map(|code| code.as_str()),
+            "spf evaluated"
+        );
+        match trace {
+            Some(trace) => output.with_trace(trace),
+            None => output,
+        }
+    }
+
     #[allow(clippy::while_let_on_iterator)]
     #[allow(clippy::iter_skip_zero)]
-    pub async fn check_host(
+    #[allow(clippy::too_many_arguments)]
+    async fn evaluate_spf(
         &self,
         ip: IpAddr,
         domain: &str,
         helo_domain: &str,
         host_domain: &str,
         sender: &str,
+        trace: &mut Option<SpfTrace>,
+        cacheable: &mut bool,
     ) -> SpfOutput {
         let output = SpfOutput::new(domain.to_string());
         if domain.is_empty() || domain.len() > 255 || !domain.has_valid_labels() {
-            return output.with_result(SpfResult::None);
+            return output
+                .with_result(SpfResult::None)
+                .with_code(ResultCode::SpfInvalidDomain);
         }
         let mut vars = Variables::new();
         let mut has_p_var = false;
@@ -103,11 +253,33 @@ impl Resolver {
         vars.set_host_domain(host_domain.as_bytes());
         vars.set_helo_domain(helo_domain.as_bytes());
 
-        let mut lookup_limit = LookupLimit::new();
+        let config = self.config();
+        let mut lookup_limit = LookupLimit::new(config.limits());
+        let limit_result = config.limits().spf_limit_result();
+        trace_step(trace, 0, || SpfTraceEvent::Lookup {
+            name: domain.to_string(),
+            count: lookup_limit.num_lookups,
+        });
         let mut spf_record = match self.txt_lookup::<Spf>(domain).await {
             Ok(spf_record) => spf_record,
+            // Multiple records are a permanent error (RFC 7208 Section 4.5)
+            Err(Error::MultipleRecords) => {
+                return output
+                    .with_result(SpfResult::PermError)
+                    .with_code(ResultCode::SpfMultipleRecords)
+            }
+            // Records of type SPF are not evaluated (RFC 7208 Section 3.1)
+            Err(Error::DeprecatedRecordType) => {
+                return output
+                    .with_result(SpfResult::None)
+                    .with_code(ResultCode::SpfDeprecatedRecordType)
+            }
             Err(err) => return output.with_result(err.into()),
         };
+        trace_step(trace, 0, || SpfTraceEvent::Record {
+            domain: domain.to_string(),
+        });
+        *cacheable &= spf_record.is_cacheable();
 
         let mut domain = domain.to_string();
         let mut include_stack = Vec::new();
@@ -117,12 +289,18 @@ impl Resolver {
 
         loop {
             while let Some((pos, directive)) = directives.next() {
+                let depth = include_stack.len();
                 if !has_p_var && directive.mechanism.needs_ptr() {
                     if !lookup_limit.can_lookup() {
                         return output
-                            .with_result(SpfResult::PermError)
+                            .with_result(limit_result)
+                            .with_code(ResultCode::SpfLookupLimit)
                             .with_report(&spf_record);
                     }
+                    trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                        name: ip.to_string(),
+                        count: lookup_limit.num_lookups,
+                    });
                     if let Some(ptr) = self
                         .ptr_lookup(ip)
                         .await
@@ -145,20 +323,30 @@ impl Resolver {
                     } => {
                         if !lookup_limit.can_lookup() {
                             return output
-                                .with_result(SpfResult::PermError)
+                                .with_result(limit_result)
+                                .with_code(ResultCode::SpfLookupLimit)
                                 .with_report(&spf_record);
                         }
+                        let target_name = expand_macro(macro_string, &vars, &domain, trace, depth);
+                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                            name: target_name.to_string(),
+                            count: lookup_limit.num_lookups,
+                        });
                         match self
-                            .ip_matches(
-                                macro_string.eval(&vars, &domain, true).as_ref(),
-                                ip,
-                                *ip4_mask,
-                                *ip6_mask,
-                            )
+                            .ip_matches(target_name.as_ref(), ip, *ip4_mask, *ip6_mask)
                             .await
                         {
                             Ok(true) => true,
-                            Ok(false) | Err(Error::DnsRecordNotFound(_)) => false,
+                            Ok(false) => false,
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(limit_result)
+                                        .with_code(ResultCode::SpfVoidLookupLimit)
+                                        .with_report(&spf_record);
+                                }
+                                false
+                            }
                             Err(_) => {
                                 return output
                                     .with_result(SpfResult::TempError)
@@ -173,24 +361,35 @@ impl Resolver {
                     } => {
                         if !lookup_limit.can_lookup() {
                             return output
-                                .with_result(SpfResult::PermError)
+                                .with_result(limit_result)
+                                .with_code(ResultCode::SpfLookupLimit)
                                 .with_report(&spf_record);
                         }
+                        let target_name = expand_macro(macro_string, &vars, &domain, trace, depth);
+                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                            name: target_name.to_string(),
+                            count: lookup_limit.num_lookups,
+                        });
 
                         let mut matches = false;
-                        match self
-                            .mx_lookup(macro_string.eval(&vars, &domain, true).as_ref())
-                            .await
-                        {
+                        match self.mx_lookup(target_name.as_ref()).await {
+                            Ok(records)
+                                if mx_records_policy(records.clone()) == MxPolicy::NullMx =>
+                            {
+                                trace_step(trace, depth, || SpfTraceEvent::NullMx {
+                                    name: target_name.to_string(),
+                                });
+                            }
                             Ok(records) => {
                                 for (mx_num, exchange) in records
                                     .iter()
                                     .flat_map(|mx| mx.exchanges.iter())
                                     .enumerate()
                                 {
-                                    if mx_num > 9 {
+                                    if mx_num >= config.limits().spf_mx_lookups() {
                                         return output
-                                            .with_result(SpfResult::PermError)
+                                            .with_result(limit_result)
+                                            .with_code(ResultCode::SpfMxLimit)
                                             .with_report(&spf_record);
                                     }
 
@@ -209,7 +408,14 @@ impl Resolver {
                                     }
                                 }
                             }
-                            Err(Error::DnsRecordNotFound(_)) => (),
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(limit_result)
+                                        .with_code(ResultCode::SpfVoidLookupLimit)
+                                        .with_report(&spf_record);
+                                }
+                            }
                             Err(_) => {
                                 return output
                                     .with_result(SpfResult::TempError)
@@ -221,13 +427,19 @@ impl Resolver {
                     Mechanism::Include { macro_string } => {
                         if !lookup_limit.can_lookup() {
                             return output
-                                .with_result(SpfResult::PermError)
+                                .with_result(limit_result)
+                                .with_code(ResultCode::SpfLookupLimit)
                                 .with_report(&spf_record);
                         }
 
-                        let target_name = macro_string.eval(&vars, &domain, true);
+                        let target_name = expand_macro(macro_string, &vars, &domain, trace, depth);
+                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                            name: target_name.to_string(),
+                            count: lookup_limit.num_lookups,
+                        });
                         match self.txt_lookup::<Spf>(target_name.as_ref()).await {
                             Ok(included_spf) => {
+                                *cacheable &= included_spf.is_cacheable();
                                 let new_domain = target_name.to_string();
                                 include_stack.push((
                                     std::mem::replace(&mut spf_record, included_spf),
@@ -237,15 +449,25 @@ impl Resolver {
                                 directives = spf_record.directives.iter().enumerate().skip(0);
                                 domain = new_domain;
                                 vars.set_domain(domain.as_bytes().to_vec());
+                                trace_step(trace, include_stack.len(), || SpfTraceEvent::Record {
+                                    domain: domain.clone(),
+                                });
                                 continue;
                             }
                             Err(
-                                Error::DnsRecordNotFound(_)
+                                err @ (Error::DnsRecordNotFound(_)
                                 | Error::InvalidRecordType
-                                | Error::ParseError,
+                                | Error::DeprecatedRecordType
+                                | Error::MultipleRecords
+                                | Error::ParseError),
                             ) => {
                                 return output
                                     .with_result(SpfResult::PermError)
+                                    .with_code(match err {
+                                        Error::ParseError => ResultCode::SpfRecordInvalid,
+                                        Error::MultipleRecords => ResultCode::SpfMultipleRecords,
+                                        _ => ResultCode::SpfIncludeNotFound,
+                                    })
                                     .with_report(&spf_record)
                             }
                             Err(_) => {
@@ -258,55 +480,96 @@ impl Resolver {
                     Mechanism::Ptr { macro_string } => {
                         if !lookup_limit.can_lookup() {
                             return output
-                                .with_result(SpfResult::PermError)
+                                .with_result(limit_result)
+                                .with_code(ResultCode::SpfLookupLimit)
                                 .with_report(&spf_record);
                         }
+                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                            name: ip.to_string(),
+                            count: lookup_limit.num_lookups,
+                        });
 
-                        let target_addr = macro_string.eval(&vars, &domain, true).to_lowercase();
+                        let target_addr =
+                            expand_macro(macro_string, &vars, &domain, trace, depth).to_lowercase();
                         let target_sub_addr = format!(".{target_addr}");
                         let mut matches = false;
 
-                        if let Ok(records) = self.ptr_lookup(ip).await {
-                            for record in records.iter() {
-                                if lookup_limit.can_lookup() {
-                                    if let Ok(true) =
-                                        self.ip_matches(record, ip, u32::MAX, u128::MAX).await
-                                    {
-                                        matches = record == &target_addr
-                                            || record
-                                                .strip_suffix('.')
-                                                .unwrap_or(record.as_str())
-                                                .ends_with(&target_sub_addr);
-                                        if matches {
-                                            break;
+                        match self.ptr_lookup(ip).await {
+                            Ok(records) => {
+                                for record in records.iter().take(config.limits().spf_ptr_lookups())
+                                {
+                                    if lookup_limit.can_lookup() {
+                                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                                            name: record.clone(),
+                                            count: lookup_limit.num_lookups,
+                                        });
+                                        if let Ok(true) =
+                                            self.ip_matches(record, ip, u32::MAX, u128::MAX).await
+                                        {
+                                            matches = record == &target_addr
+                                                || record
+                                                    .strip_suffix('.')
+                                                    .unwrap_or(record.as_str())
+                                                    .ends_with(&target_sub_addr);
+                                            if matches {
+                                                break;
+                                            }
                                         }
                                     }
                                 }
                             }
+                            Err(Error::DnsRecordNotFound(_)) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(limit_result)
+                                        .with_code(ResultCode::SpfVoidLookupLimit)
+                                        .with_report(&spf_record);
+                                }
+                            }
+                            Err(_) => (),
                         }
                         matches
                     }
                     Mechanism::Exists { macro_string } => {
                         if !lookup_limit.can_lookup() {
                             return output
-                                .with_result(SpfResult::PermError)
+                                .with_result(limit_result)
+                                .with_code(ResultCode::SpfLookupLimit)
                                 .with_report(&spf_record);
                         }
+                        let target_name = expand_macro(macro_string, &vars, &domain, trace, depth);
+                        trace_step(trace, depth, || SpfTraceEvent::Lookup {
+                            name: target_name.to_string(),
+                            count: lookup_limit.num_lookups,
+                        });
 
-                        if let Ok(result) = self
-                            .exists(macro_string.eval(&vars, &domain, true).as_ref())
-                            .await
-                        {
-                            result
-                        } else {
-                            return output
-                                .with_result(SpfResult::TempError)
-                                .with_report(&spf_record);
+                        match self.exists(target_name.as_ref()).await {
+                            Ok(true) => true,
+                            Ok(false) => {
+                                if !lookup_limit.can_void_lookup() {
+                                    return output
+                                        .with_result(limit_result)
+                                        .with_code(ResultCode::SpfVoidLookupLimit)
+                                        .with_report(&spf_record);
+                                }
+                                false
+                            }
+                            Err(_) => {
+                                return output
+                                    .with_result(SpfResult::TempError)
+                                    .with_report(&spf_record);
+                            }
                         }
                     }
                 };
 
+                trace_step(trace, depth, || SpfTraceEvent::Directive {
+                    directive: directive.clone(),
+                    matched: matches,
+                });
                 if matches {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(domain = %domain, directive = %directive, "spf directive matched");
                     result = Some((&directive.qualifier).into());
                     break;
                 }
@@ -316,41 +579,67 @@ impl Resolver {
                 spf_record = prev_record;
                 directives = spf_record.directives.iter().enumerate().skip(prev_pos);
                 let (_, directive) = directives.next().unwrap();
+                let matches = matches!(result, Some(SpfResult::Pass));
+                trace_step(trace, include_stack.len(), || SpfTraceEvent::Directive {
+                    directive: directive.clone(),
+                    matched: matches,
+                });
 
-                if matches!(result, Some(SpfResult::Pass)) {
+                if matches {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(domain = %prev_domain, directive = %directive, "spf directive matched");
                     result = Some((&directive.qualifier).into());
                     break;
                 } else {
                     vars.set_domain(prev_domain.as_bytes().to_vec());
                     domain = prev_domain;
                     result = None;
+                    if let Some(trace) = trace.as_mut() {
+                        trace.clear_match();
+                    }
                 }
             } else {
                 // Follow redirect
                 if let (Some(macro_string), None) = (&spf_record.redirect, &result) {
                     if !lookup_limit.can_lookup() {
                         return output
-                            .with_result(SpfResult::PermError)
+                            .with_result(limit_result)
+                            .with_code(ResultCode::SpfLookupLimit)
                             .with_report(&spf_record);
                     }
 
-                    let target_name = macro_string.eval(&vars, &domain, true);
+                    let target_name = expand_macro(macro_string, &vars, &domain, trace, 0);
+                    trace_step(trace, 0, || SpfTraceEvent::Lookup {
+                        name: target_name.to_string(),
+                        count: lookup_limit.num_lookups,
+                    });
                     match self.txt_lookup::<Spf>(target_name.as_ref()).await {
                         Ok(redirect_spf) => {
+                            *cacheable &= redirect_spf.is_cacheable();
                             let new_domain = target_name.to_string();
                             spf_record = redirect_spf;
                             directives = spf_record.directives.iter().enumerate().skip(0);
                             domain = new_domain;
                             vars.set_domain(domain.as_bytes().to_vec());
+                            trace_step(trace, 0, || SpfTraceEvent::Record {
+                                domain: domain.clone(),
+                            });
                             continue;
                         }
                         Err(
-                            Error::DnsRecordNotFound(_)
+                            err @ (Error::DnsRecordNotFound(_)
                             | Error::InvalidRecordType
-                            | Error::ParseError,
+                            | Error::DeprecatedRecordType
+                            | Error::MultipleRecords
+                            | Error::ParseError),
                         ) => {
                             return output
                                 .with_result(SpfResult::PermError)
+                                .with_code(match err {
+                                    Error::ParseError => ResultCode::SpfRecordInvalid,
+                                    Error::MultipleRecords => ResultCode::SpfMultipleRecords,
+                                    _ => ResultCode::SpfRedirectNotFound,
+                                })
                                 .with_report(&spf_record)
                         }
                         Err(_) => {
@@ -454,6 +743,29 @@ impl IpMask for Ipv4Addr {
     }
 }
 
+fn trace_step(trace: &mut Option<SpfTrace>, depth: usize, event: impl FnOnce() -> SpfTraceEvent) {
+    if let Some(trace) = trace {
+        trace.push(depth, event());
+    }
+}
+
+fn expand_macro<'z>(
+    macro_string: &'z Macro,
+    vars: &'z Variables<'z>,
+    domain: &'z str,
+    trace: &mut Option<SpfTrace>,
+    depth: usize,
+) -> Cow<'z, str> {
+    let expanded = macro_string.eval(vars, domain, true);
+    if !matches!(macro_string, Macro::Literal(_)) {
+        trace_step(trace, depth, || SpfTraceEvent::Macro {
+            macro_string: macro_string.clone(),
+            expanded: expanded.to_string(),
+        });
+    }
+    expanded
+}
+
 impl From<&Qualifier> for SpfResult {
     fn from(q: &Qualifier) -> Self {
         match q {
@@ -468,8 +780,10 @@ impl From<&Qualifier> for SpfResult {
 impl From<Error> for SpfResult {
     fn from(err: Error) -> Self {
         match err {
-            Error::DnsRecordNotFound(_) | Error::InvalidRecordType => SpfResult::None,
-            Error::ParseError => SpfResult::PermError,
+            Error::DnsRecordNotFound(_)
+            | Error::InvalidRecordType
+            | Error::DeprecatedRecordType => SpfResult::None,
+            Error::ParseError | Error::MultipleRecords => SpfResult::PermError,
             _ => SpfResult::TempError,
         }
     }
@@ -477,26 +791,40 @@ impl From<Error> for SpfResult {
 
 struct LookupLimit {
     num_lookups: u32,
+    max_lookups: u32,
+    num_void_lookups: u32,
+    max_void_lookups: u32,
+    timeout: Duration,
     timer: Instant,
 }
 
 impl LookupLimit {
-    pub fn new() -> Self {
+    pub fn new(limits: &Limits) -> Self {
         LookupLimit {
             num_lookups: 1,
+            max_lookups: limits.spf_lookups(),
+            num_void_lookups: 0,
+            max_void_lookups: limits.spf_void_lookups(),
+            timeout: limits.spf_timeout(),
             timer: Instant::now(),
         }
     }
 
     #[inline(always)]
     fn can_lookup(&mut self) -> bool {
-        if self.num_lookups < 10 && self.timer.elapsed().as_secs() < 20 {
+        if self.num_lookups < self.max_lookups && self.timer.elapsed() < self.timeout {
             self.num_lookups += 1;
             true
         } else {
             false
         }
     }
+
+    #[inline(always)]
+    fn can_void_lookup(&mut self) -> bool {
+        self.num_void_lookups += 1;
+        self.num_void_lookups <= self.max_void_lookups
+    }
 }
 
 pub trait HasValidLabels {
@@ -541,11 +869,260 @@ mod test {
     };
 
     use crate::{
-        common::parse::TxtRecordParser,
-        spf::{Macro, Spf},
-        Resolver, SpfResult, MX,
+        common::{
+            codes::ResultCode,
+            config::{Config, Limits},
+            parse::TxtRecordParser,
+        },
+        spf::{Macro, Mechanism, Spf, SpfTraceEvent},
+        Error, Resolver, SpfResult, MX,
     };
 
+    #[tokio::test]
+    async fn spf_verify_identities() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip = "192.0.2.1".parse::<IpAddr>().unwrap();
+        resolver.txt_add(
+            "mx.example.org.",
+            Spf::parse(b"v=spf1 ip4:192.0.2.1 -all"),
+            valid_until,
+        );
+        resolver.txt_add(
+            "example.com.",
+            Spf::parse(b"v=spf1 ip4:198.51.100.0/24 -all"),
+            valid_until,
+        );
+
+        // Different domains
+        let output = resolver
+            .verify_spf_identities(ip, "mx.example.org", "mx.example.net", "user@example.com")
+            .await;
+        assert_eq!(output.helo().result(), SpfResult::Pass);
+        assert_eq!(output.mail_from().result(), SpfResult::Fail);
+        assert_eq!(output.combined().domain(), "example.com");
+        assert!(!output.is_null_sender());
+
+        // Same domain
+        let output = resolver
+            .verify_spf_identities(
+                ip,
+                "mx.example.org",
+                "mx.example.net",
+                "bounces@mx.example.org",
+            )
+            .await;
+        assert_eq!(output.helo().result(), SpfResult::Pass);
+        assert_eq!(output.combined().result(), SpfResult::Pass);
+
+        // Null sender
+        let output = resolver
+            .verify_spf_identities(ip, "mx.example.org", "mx.example.net", "")
+            .await;
+        assert!(output.is_null_sender());
+        assert_eq!(output.combined().result(), SpfResult::Pass);
+        assert_eq!(output.combined().domain(), "mx.example.org");
+    }
+
+    #[tokio::test]
+    async fn spf_multiple_records() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip = "192.0.2.1".parse::<IpAddr>().unwrap();
+        resolver.txt_add(
+            "multiple.example.org.",
+            Err::<Spf, _>(Error::MultipleRecords),
+            valid_until,
+        );
+        resolver.txt_add(
+            "legacy.example.org.",
+            Err::<Spf, _>(Error::DeprecatedRecordType),
+            valid_until,
+        );
+        resolver.txt_add(
+            "include.example.org.",
+            Spf::parse(b"v=spf1 include:multiple.example.org -all"),
+            valid_until,
+        );
+
+        for (domain, result, code, key) in [
+            (
+                "multiple.example.org",
+                SpfResult::PermError,
+                ResultCode::SpfMultipleRecords,
+                "spf.multiple",
+            ),
+            (
+                "include.example.org",
+                SpfResult::PermError,
+                ResultCode::SpfMultipleRecords,
+                "spf.multiple",
+            ),
+            (
+                "legacy.example.org",
+                SpfResult::None,
+                ResultCode::SpfDeprecatedRecordType,
+                "spf.deprecated",
+            ),
+        ] {
+            let output = resolver
+                .verify_spf_sender(
+                    ip,
+                    "mx.example.org",
+                    "mx.example.net",
+                    &format!("user@{domain}"),
+                )
+                .await;
+            assert_eq!(output.result(), result, "{domain}");
+            assert_eq!(output.result_code(), Some(code), "{domain}");
+            assert_eq!(output.explanation_key(), key, "{domain}");
+        }
+
+        // Record analysis reports the misconfiguration
+        assert_eq!(
+            resolver
+                .analyze_spf("multiple.example.org")
+                .await
+                .unwrap_err(),
+            Error::MultipleRecords
+        );
+        assert_eq!(
+            resolver
+                .analyze_spf("legacy.example.org")
+                .await
+                .unwrap_err(),
+            Error::DeprecatedRecordType
+        );
+    }
+
+    #[tokio::test]
+    async fn spf_trace() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip = "192.0.2.1".parse::<IpAddr>().unwrap();
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(b"v=spf1 ip4:10.0.0.1 mx:null.example.org include:_spf.example.org -all"),
+            valid_until,
+        );
+        resolver.mx_add(
+            "null.example.org.",
+            vec![MX {
+                exchanges: vec![".".to_string()],
+                preference: 0,
+            }],
+            valid_until,
+        );
+        resolver.txt_add(
+            "_spf.example.org.",
+            Spf::parse(b"v=spf1 a:%{l}.out.example.org -all"),
+            valid_until,
+        );
+        resolver.ipv4_add(
+            "jdoe.out.example.org.",
+            vec!["192.0.2.1".parse().unwrap()],
+            valid_until,
+        );
+
+        // Tracing is disabled by default
+        let output = resolver
+            .verify_spf_sender(ip, "mx.example.org", "example.org", "jdoe@example.org")
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        assert!(output.trace().is_none());
+
+        resolver.set_config(Config::new().with_spf_trace(true));
+        let output = resolver
+            .verify_spf_sender(ip, "mx.example.org", "example.org", "jdoe@example.org")
+            .await;
+        assert_eq!(output.result(), SpfResult::Pass);
+        let trace = output.trace().unwrap();
+        assert_eq!(trace.lookups(), 4);
+        assert_eq!(trace.max_lookups(), 10);
+        assert_eq!(
+            trace
+                .steps()
+                .iter()
+                .map(|step| match step.event() {
+                    SpfTraceEvent::Record { domain } => format!("{} record {domain}", step.depth()),
+                    SpfTraceEvent::Macro { expanded, .. } => {
+                        format!("{} macro {expanded}", step.depth())
+                    }
+                    SpfTraceEvent::Lookup { name, count } => {
+                        format!("{} lookup {name} ({count})", step.depth())
+                    }
+                    SpfTraceEvent::Directive { matched, .. } => {
+                        format!("{} directive {matched}", step.depth())
+                    }
+                    SpfTraceEvent::NullMx { name } => format!("{} null mx {name}", step.depth()),
+                })
+                .collect::<Vec<_>>(),
+            [
+                "0 lookup example.org (1)",
+                "0 record example.org",
+                "0 directive false",
+                "0 lookup null.example.org (2)",
+                "0 null mx null.example.org",
+                "0 directive false",
+                "0 lookup _spf.example.org (3)",
+                "1 record _spf.example.org",
+                "1 macro jdoe.out.example.org.",
+                "1 lookup jdoe.out.example.org. (4)",
+                "1 directive true",
+                "0 directive true",
+            ]
+        );
+        assert!(matches!(
+            trace.matched().unwrap().event(),
+            SpfTraceEvent::Directive { directive, matched: true }
+                if matches!(directive.mechanism, Mechanism::Include { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn spf_limits() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::from_secs(30);
+        let ip = "192.0.2.1".parse::<IpAddr>().unwrap();
+        resolver.txt_add(
+            "example.org.",
+            Spf::parse(
+                b"v=spf1 a:n1.example.org a:n2.example.org a:n3.example.org ip4:192.0.2.1 -all",
+            ),
+            valid_until,
+        );
+
+        for (limits, result, code) in [
+            (Limits::default(), SpfResult::Pass, None),
+            (
+                Limits::default().with_spf_void_lookups(2),
+                SpfResult::PermError,
+                Some(ResultCode::SpfVoidLookupLimit),
+            ),
+            (
+                Limits::default()
+                    .with_spf_void_lookups(2)
+                    .with_spf_limit_result(SpfResult::Neutral),
+                SpfResult::Neutral,
+                Some(ResultCode::SpfVoidLookupLimit),
+            ),
+            (
+                Limits::default()
+                    .with_spf_lookups(3)
+                    .with_spf_limit_result(SpfResult::SoftFail),
+                SpfResult::SoftFail,
+                Some(ResultCode::SpfLookupLimit),
+            ),
+        ] {
+            resolver.set_config(Config::new().with_limits(limits));
+            let output = resolver
+                .verify_spf_sender(ip, "mx.example.org", "example.org", "a@example.org")
+                .await;
+            assert_eq!(output.result(), result);
+            assert_eq!(output.result_code(), code);
+        }
+    }
+
     #[tokio::test]
     async fn spf_verify() {
         let valid_until = Instant::now() + Duration::from_secs(30);
@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use std::sync::Arc;
+
+use crate::{Error, VbrOutput, VbrResult};
+
+pub mod parse;
+pub mod verify;
+
+/// Contents of a VBR-Info header (RFC 5518).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VbrInfo {
+    /// Domain the message is vouched for.
+    pub md: String,
+    /// Content type of the message, such as `list` or `transaction`.
+    pub mc: String,
+    /// Vouching services that may vouch for the domain.
+    pub mv: Vec<String>,
+}
+
+/// Content types a vouching service vouches for, published at
+/// `<domain>._vouch.<vouching-service>`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Vouch {
+    pub content_types: Vec<String>,
+}
+
+impl Vouch {
+    /// Returns `true` if the vouching service vouches for messages of this content type.
+    pub fn vouches_for(&self, content_type: &str) -> bool {
+        self.content_types
+            .iter()
+            .any(|ct| ct == "all" || ct.eq_ignore_ascii_case(content_type))
+    }
+}
+
+impl From<Error> for VbrResult {
+    fn from(err: Error) -> Self {
+        if matches!(&err, Error::DnsError(_) | Error::Io(_)) {
+            VbrResult::TempError(err)
+        } else {
+            VbrResult::Fail(err)
+        }
+    }
+}
+
+impl VbrOutput {
+    pub(crate) fn new(domain: &str, content_type: &str) -> Self {
+        VbrOutput {
+            result: VbrResult::None,
+            domain: domain.to_string(),
+            content_type: content_type.to_string(),
+            service: None,
+            record: None,
+        }
+    }
+
+    pub(crate) fn with_result(mut self, result: VbrResult) -> Self {
+        self.result = result;
+        self
+    }
+
+    pub(crate) fn with_service(mut self, service: &str, record: Arc<Vouch>) -> Self {
+        self.service = Some(service.to_string());
+        self.record = record.into();
+        self
+    }
+
+    pub fn result(&self) -> &VbrResult {
+        &self.result
+    }
+
+    /// Returns the vouched for domain (`md=`).
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the content type of the message (`mc=`).
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Returns the vouching service that answered the query.
+    pub fn vouching_service(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    pub fn vouch_record(&self) -> Option<&Vouch> {
+        self.record.as_deref()
+    }
+}
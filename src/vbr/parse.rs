@@ -0,0 +1,116 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{
+    common::parse::{TagParser, TxtRecordParser, MC, MD, MV},
+    Error,
+};
+
+use super::{VbrInfo, Vouch};
+
+impl VbrInfo {
+    /// Parses the value of a VBR-Info header.
+    pub fn parse(header: &[u8]) -> crate::Result<Self> {
+        let mut header = header.iter();
+        let mut info = VbrInfo {
+            md: String::new(),
+            mc: String::new(),
+            mv: Vec::new(),
+        };
+
+        while let Some(key) = header.key() {
+            match key {
+                MD => {
+                    info.md = header.text(true);
+                }
+                MC => {
+                    info.mc = header.text(true);
+                }
+                MV => {
+                    info.mv = header
+                        .text(true)
+                        .split(':')
+                        .filter(|service| !service.is_empty())
+                        .map(|service| service.to_string())
+                        .collect();
+                }
+                _ => {
+                    header.ignore();
+                }
+            }
+        }
+
+        if !info.md.is_empty() && !info.mc.is_empty() && !info.mv.is_empty() {
+            Ok(info)
+        } else {
+            Err(Error::MissingParameters)
+        }
+    }
+}
+
+impl TxtRecordParser for Vouch {
+    fn parse(record: &[u8]) -> crate::Result<Self> {
+        let content_types = std::str::from_utf8(record)
+            .map_err(|_| Error::ParseError)?
+            .split_ascii_whitespace()
+            .map(|ct| ct.to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        if !content_types.is_empty() {
+            Ok(Vouch { content_types })
+        } else {
+            Err(Error::InvalidRecordType)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::parse::TxtRecordParser,
+        vbr::{VbrInfo, Vouch},
+        Error,
+    };
+
+    #[test]
+    fn parse_vbr() {
+        for (header, expected_result) in [
+            (
+                "md=example.com; mc=list; mv=certifier.example:Other.example",
+                Ok(VbrInfo {
+                    md: "example.com".to_string(),
+                    mc: "list".to_string(),
+                    mv: vec!["certifier.example".to_string(), "other.example".to_string()],
+                }),
+            ),
+            (
+                " mc=transaction;\r\n\tmd=Example.COM;mv=certifier.example",
+                Ok(VbrInfo {
+                    md: "example.com".to_string(),
+                    mc: "transaction".to_string(),
+                    mv: vec!["certifier.example".to_string()],
+                }),
+            ),
+            ("md=example.com; mc=list", Err(Error::MissingParameters)),
+        ] {
+            assert_eq!(
+                VbrInfo::parse(header.as_bytes()),
+                expected_result,
+                "{header}"
+            );
+        }
+
+        let vouch = Vouch::parse(b"List Transaction").unwrap();
+        assert!(vouch.vouches_for("list"));
+        assert!(!vouch.vouches_for("all"));
+        assert!(Vouch::parse(b"all").unwrap().vouches_for("list"));
+        assert_eq!(Vouch::parse(b"  "), Err(Error::InvalidRecordType));
+    }
+}
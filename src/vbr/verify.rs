@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) 2020-2023, Stalwart Labs Ltd.
+ *
+ * Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+ * https://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+ * <LICENSE-MIT or https://opensource.org/licenses/MIT>, at your
+ * option. This file may not be copied, modified, or distributed
+ * except according to those terms.
+ */
+
+use crate::{
+    AuthenticatedMessage, DkimOutput, DkimResult, Domain, Error, Resolver, SpfOutput, SpfResult,
+    VbrOutput, VbrResult,
+};
+
+use super::{VbrInfo, Vouch};
+
+impl Resolver {
+    /// Verifies the VBR-Info headers of an RFC5322 message (RFC 5518), querying
+    /// the listed vouching services that are trusted. The vouched for domain has
+    /// to be validated by a passing DKIM signature or by SPF.
+    pub async fn verify_vbr(
+        &self,
+        message: &AuthenticatedMessage<'_>,
+        dkim_output: &[DkimOutput<'_>],
+        spf_output: &SpfOutput,
+        trusted_services: &[&str],
+    ) -> VbrOutput {
+        let mut infos = Vec::new();
+        let mut parse_error = None;
+        for (_, value) in message
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(b"VBR-Info"))
+        {
+            match VbrInfo::parse(value) {
+                Ok(info) => infos.push(info),
+                Err(err) => parse_error = Some(err),
+            }
+        }
+        if infos.is_empty() {
+            return match parse_error {
+                Some(err) => VbrOutput::new("", "").with_result(VbrResult::PermError(err)),
+                None => VbrOutput::new("", ""),
+            };
+        }
+
+        // Use the header of a domain validated by DKIM or SPF
+        let mut validated = dkim_output
+            .iter()
+            .filter(|o| o.result() == &DkimResult::Pass)
+            .filter_map(|o| o.signature().map(|s| Domain::normalize(&s.d)))
+            .collect::<Vec<_>>();
+        if spf_output.result() == SpfResult::Pass {
+            validated.push(Domain::normalize(spf_output.domain()));
+        }
+        let Some(info) = infos
+            .iter()
+            .find(|info| validated.contains(&Domain::normalize(&info.md)))
+        else {
+            return VbrOutput::new(&infos[0].md, &infos[0].mc)
+                .with_result(VbrResult::Fail(Error::NotAligned));
+        };
+
+        // Query the trusted vouching services
+        let domain = Domain::normalize(&info.md);
+        let mut output = VbrOutput::new(&domain, &info.mc);
+        for service in info.mv.iter().filter(|service| {
+            let service = Domain::normalize(service);
+            trusted_services
+                .iter()
+                .any(|trusted| Domain::normalize(trusted) == service)
+        }) {
+            output = match self
+                .txt_lookup::<Vouch>(format!("{domain}._vouch.{service}."))
+                .await
+            {
+                Ok(vouch) if vouch.vouches_for(&info.mc) => {
+                    return output
+                        .with_result(VbrResult::Pass)
+                        .with_service(service, vouch);
+                }
+                Ok(vouch) => output
+                    .with_result(VbrResult::Fail(Error::NotAligned))
+                    .with_service(service, vouch),
+                Err(err) => output.with_result(err.into()),
+            };
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        common::parse::TxtRecordParser, vbr::Vouch, AuthenticatedMessage, AuthenticationResults,
+        DkimOutput, Error, Resolver, SpfOutput, SpfResult, VbrResult,
+    };
+
+    #[tokio::test]
+    async fn vbr_verify() {
+        let resolver = Resolver::new_system_conf().unwrap();
+        let valid_until = Instant::now() + Duration::new(3200, 0);
+        resolver.txt_add(
+            "example.com._vouch.certifier.example.",
+            Vouch::parse(b"transaction list").unwrap(),
+            valid_until,
+        );
+        resolver.txt_add(
+            "example.com._vouch.other.example.",
+            Vouch::parse(b"transaction").unwrap(),
+            valid_until,
+        );
+        let spf = SpfOutput::new("example.com".to_string()).with_result(SpfResult::Pass);
+        let dkim: [DkimOutput; 0] = [];
+
+        for (headers, trusted, spf, expected_result, expected_service) in [
+            // Vouched for by a trusted service
+            (
+                "VBR-Info: md=example.com; mc=list; mv=untrusted.example:certifier.example\r\n",
+                &["certifier.example"][..],
+                &spf,
+                VbrResult::Pass,
+                Some("certifier.example"),
+            ),
+            // Content type not vouched for
+            (
+                "VBR-Info: md=example.com; mc=list; mv=other.example\r\n",
+                &["other.example", "certifier.example"][..],
+                &spf,
+                VbrResult::Fail(Error::NotAligned),
+                Some("other.example"),
+            ),
+            // No trusted vouching service listed
+            (
+                "VBR-Info: md=example.com; mc=list; mv=untrusted.example\r\n",
+                &["certifier.example"][..],
+                &spf,
+                VbrResult::None,
+                None,
+            ),
+            // Domain not validated
+            (
+                "VBR-Info: md=example.com; mc=list; mv=certifier.example\r\n",
+                &["certifier.example"][..],
+                &SpfOutput::new("example.com".to_string()).with_result(SpfResult::Fail),
+                VbrResult::Fail(Error::NotAligned),
+                None,
+            ),
+            // Vouching service does not know the domain
+            (
+                "VBR-Info: md=example.com; mc=list; mv=unknown.example\r\n",
+                &["unknown.example"][..],
+                &spf,
+                VbrResult::Fail(Error::DnsRecordNotFound(
+                    hickory_resolver::proto::op::ResponseCode::NXDomain,
+                )),
+                None,
+            ),
+            (
+                "VBR-Info: md=example.com\r\n",
+                &["certifier.example"][..],
+                &spf,
+                VbrResult::PermError(Error::MissingParameters),
+                None,
+            ),
+            ("", &["certifier.example"][..], &spf, VbrResult::None, None),
+        ] {
+            let message = format!("{headers}From: jdoe@example.com\r\n\r\nHi!\r\n");
+            let message = AuthenticatedMessage::parse(message.as_bytes()).unwrap();
+            let output = resolver.verify_vbr(&message, &dkim, spf, trusted).await;
+            assert_eq!(output.result(), &expected_result, "{headers}");
+            assert_eq!(output.vouching_service(), expected_service, "{headers}");
+        }
+
+        let message = AuthenticatedMessage::parse(
+            b"VBR-Info: md=example.com; mc=list; mv=certifier.example\r\n\r\nHi!\r\n",
+        )
+        .unwrap();
+        let output = resolver
+            .verify_vbr(&message, &dkim, &spf, &["certifier.example"])
+            .await;
+        assert_eq!(
+            AuthenticationResults::new("mx.example.org")
+                .with_vbr_result(&output)
+                .to_string(),
+            "mx.example.org;\r\n\tvbr=pass header.md=example.com header.mv=certifier.example"
+        );
+    }
+}